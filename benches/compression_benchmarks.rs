@@ -1,8 +1,12 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId, Throughput};
 use codec_cdn_rust::codecs::{
-    text::{TcfCodec},
-    bencode::{BencodeCodec, BencodeValue},
+    text::{ArithmeticCoder, ChunkStrategy, CostEstimator, FrequencyModel, JsonAwareTokenizer, TcfCodec, TcfEncodeOptions},
+    bencode::{BencodeCodec, BencodeValue, HashAlgo, PieceHasher},
+    image::dct_transform::{Dct8x8, DctTransform},
+    image::IcfCodec,
+    cli_common::RuntimeConfig,
 };
+use ndarray::Array2;
 use std::time::Duration;
 use std::collections::HashMap;
 
@@ -150,6 +154,250 @@ fn bench_bencode_operations(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_bencode_presizing(c: &mut Criterion) {
+    // Integer-heavy tree: sizing it must not allocate, and the exact size lets
+    // `encode` write into a buffer that never reallocates.
+    let integers = BencodeValue::list((0..100_000).map(|i| BencodeValue::integer(i * 7919 - 500_000)).collect());
+    
+    let mut group = c.benchmark_group("bencode_presizing");
+    group.throughput(Throughput::Bytes(integers.encoded_size() as u64));
+    
+    group.bench_function("encoded_size", |b| {
+        b.iter(|| black_box(&integers).encoded_size())
+    });
+    
+    group.bench_function("encode_presized", |b| {
+        b.iter(|| {
+            let _encoded = BencodeCodec::encode(black_box(&integers));
+        })
+    });
+    
+    group.bench_function("encode_unsized", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            let _ = BencodeCodec::encode_to_writer(black_box(&integers), &mut output);
+        })
+    });
+    
+    group.finish();
+}
+
+fn bench_bencode_file_format(c: &mut Criterion) {
+    // The wrapper is written around the content in place, so the 10 MB
+    // string is copied once, into the output, and never cloned
+    let content = BencodeValue::byte_string(vec![0x5A; 10 * 1024 * 1024]);
+    let metadata = BencodeValue::dictionary(HashMap::from([(b"source".to_vec(), BencodeValue::string("bench"))]));
+    let shared = content.clone().shared();
+    
+    let mut group = c.benchmark_group("bencode_file_format");
+    group.throughput(Throughput::Bytes(content.encoded_size() as u64));
+    
+    group.bench_function("create_10mb", |b| {
+        b.iter(|| BencodeCodec::create_file_format(black_box(&content), Some(&metadata)))
+    });
+    
+    group.bench_function("share_10mb", |b| {
+        b.iter(|| black_box(&shared).clone())
+    });
+    
+    group.finish();
+}
+
+fn bench_piece_hashing(c: &mut Criterion) {
+    // Torrent creation and chunked store puts are bound by this; feeding
+    // odd-sized writes exercises the piece boundary handling
+    let data: Vec<u8> = (0..8u32 << 20).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+    
+    let mut group = c.benchmark_group("piece_hashing");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    
+    for algo in [HashAlgo::Sha1, HashAlgo::Sha256] {
+        group.bench_with_input(BenchmarkId::new("reader", algo), &data, |b, data| {
+            b.iter(|| {
+                let mut hasher = PieceHasher::new(256 * 1024, algo);
+                hasher.hash_reader(black_box(&data[..])).unwrap();
+                hasher.finalize()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("unaligned_updates", algo), &data, |b, data| {
+            b.iter(|| {
+                let mut hasher = PieceHasher::new(256 * 1024, algo);
+                for part in black_box(data).chunks(10_007) {
+                    hasher.update(part);
+                }
+                hasher.finalize()
+            })
+        });
+    }
+    
+    group.finish();
+}
+
+fn bench_cost_estimation(c: &mut Criterion) {
+    // Auto method selection asks what the arithmetic coder would produce;
+    // the estimate should be at least 10x cheaper than finding out
+    let data = include_str!("../src/main.rs").repeat(10).into_bytes();
+    let mut model = FrequencyModel::new();
+    model.build_from_data(&data);
+    
+    let mut group = c.benchmark_group("arithmetic_cost");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    
+    group.bench_function("estimate", |b| {
+        b.iter(|| CostEstimator::new(&model).estimate_bits(black_box(&data).iter().copied()))
+    });
+    
+    group.bench_function("trial_encode", |b| {
+        b.iter(|| {
+            let mut encoder = ArithmeticCoder::new();
+            for &byte in black_box(&data) {
+                let (low, high) = model.get_symbol_range(byte).unwrap();
+                encoder.encode_symbol(low, high, model.total_frequency()).unwrap();
+            }
+            encoder.bits_written()
+        })
+    });
+    
+    group.finish();
+}
+
+/// Minified JSON: ~90 distinct bytes, all on one line
+fn minified_json(target: usize) -> String {
+    let words = ["alpha", "Bravo", "charlie_2", "DELTA-x", "echo.io", "fox~trot", "golf/7", "hotel#q"];
+    let mut text = String::from("[");
+    let mut id = 0usize;
+    while text.len() < target {
+        let word = |n: usize| words[n % words.len()];
+        text.push_str(&format!(
+            r#"{}{{"id":{},"user":"{}@{}.example","score":{}.{:03},"tags":["{}"],"ref":"{:08X}"}}"#,
+            if id > 0 { "," } else { "" }, id, word(id * 7), word(id / 3), id % 997, id * 31 % 1000, word(id * 13), id * 2_654_435_761 % (1 << 32),
+        ));
+        id += 1;
+    }
+    text.push(']');
+    text
+}
+
+fn bench_pathological_text(c: &mut Criterion) {
+    // One enormous line and a flat 64-symbol alphabet: no newline for record
+    // chunkers to cut at, no skew for the arithmetic coder to exploit
+    use base64::Engine;
+    let mut state = 0x2545_f491_u32;
+    let random: Vec<u8> = (0..3 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let corpora = [
+        ("minified JSON", minified_json(4 << 20)),
+        ("base64", base64::engine::general_purpose::STANDARD.encode(random)),
+    ];
+    let encodings = [
+        ("arithmetic", TcfEncodeOptions::default()),
+        ("arithmetic, json tokens", TcfEncodeOptions { tokenizer_id: JsonAwareTokenizer::ID.to_string(), ..Default::default() }),
+        ("auto, chunked on newlines", TcfEncodeOptions { method: None, chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 1 << 20)), ..Default::default() }),
+    ];
+
+    let mut group = c.benchmark_group("text_pathological");
+    group.sample_size(10);
+    for (name, text) in &corpora {
+        group.throughput(Throughput::Bytes(text.len() as u64));
+        for (label, options) in &encodings {
+            group.bench_with_input(BenchmarkId::new(*label, name), text, |b, text| {
+                b.iter(|| TcfCodec::encode_with_options(black_box(text), options).unwrap())
+            });
+        }
+        let encoded = TcfCodec::encode(text).unwrap();
+        group.bench_with_input(BenchmarkId::new("decode arithmetic", name), &encoded, |b, encoded| {
+            b.iter(|| TcfCodec::decode(black_box(encoded)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_dct(c: &mut Criterion) {
+    // The `_into` forms reuse one output buffer and a shared table instead
+    // of allocating per block
+    let input = Array2::from_shape_fn((8, 8), |(i, j)| ((i * 37 + j * 11) % 255) as f64 - 128.0);
+    let block: [[f64; 8]; 8] = std::array::from_fn(|i| std::array::from_fn(|j| input[[i, j]]));
+    
+    let mut group = c.benchmark_group("dct");
+    
+    group.bench_function("transform_new_forward", |b| {
+        b.iter(|| DctTransform::new(8).forward(black_box(&input)))
+    });
+    
+    let dct = DctTransform::shared(8);
+    let mut output = Array2::zeros((8, 8));
+    group.bench_function("transform_shared_forward_into", |b| {
+        b.iter(|| dct.forward_into(black_box(&input), &mut output))
+    });
+    
+    group.bench_function("8x8_new_forward", |b| {
+        b.iter(|| Dct8x8::new().forward_8x8(black_box(&block)))
+    });
+    
+    let mut out = [[0.0; 8]; 8];
+    group.bench_function("8x8_shared_forward_into", |b| {
+        b.iter(|| Dct8x8::shared().forward_8x8_into(black_box(&block), &mut out))
+    });
+    
+    group.finish();
+}
+
+fn bench_image_4k(c: &mut Criterion) {
+    // Encode reads 8x8 blocks straight out of the color planes and decode
+    // writes them straight back, with no per-block copy of either
+    let image = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(3840, 2160, |x, y| {
+        image::Rgb([(x / 15) as u8, (y / 9) as u8, ((x ^ y) % 256) as u8])
+    }));
+    let codec = IcfCodec::new();
+    let encoded = codec.encode_image(&image, 75).unwrap();
+
+    let mut group = c.benchmark_group("image_4k");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(3840 * 2160));
+
+    group.bench_function("icf_encode", |b| {
+        b.iter(|| codec.encode_image(black_box(&image), 75).unwrap())
+    });
+
+    group.bench_function("icf_decode", |b| {
+        b.iter(|| codec.decode(black_box(&encoded)).unwrap())
+    });
+
+    group.finish();
+}
+
+fn bench_image_1080p(c: &mut Criterion) {
+    // Same container as tests/icf_perf.rs, which holds the single-thread
+    // median to a budget; this gives the full distribution
+    let image = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(1920, 1080, |x, y| {
+        image::Rgb([(x / 8) as u8, (y / 5) as u8, ((x ^ y) % 256) as u8])
+    }));
+    let encoded = IcfCodec::new().encode_image(&image, 75).unwrap();
+    let runtime = RuntimeConfig::new(Some(1), None).unwrap();
+    let single = IcfCodec::new().with_runtime(runtime.clone());
+    let pooled = IcfCodec::new();
+
+    let mut group = c.benchmark_group("image_1080p");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(1920 * 1080));
+
+    group.bench_function("icf_decode_single_thread", |b| {
+        b.iter(|| runtime.install(|| single.decode(black_box(&encoded)).unwrap()))
+    });
+
+    group.bench_function("icf_decode", |b| {
+        b.iter(|| pooled.decode(black_box(&encoded)).unwrap())
+    });
+
+    group.finish();
+}
+
 fn bench_text_sizes(c: &mut Criterion) {
     let sizes = vec![100, 1000, 10000, 100000];
     let base_text = "The quick brown fox jumps over the lazy dog. This is a sample text for compression benchmarking. ";
@@ -318,6 +566,14 @@ criterion_group!(
     benches, 
     bench_text_compression, 
     bench_bencode_operations,
+    bench_bencode_presizing,
+    bench_bencode_file_format,
+    bench_piece_hashing,
+    bench_cost_estimation,
+    bench_pathological_text,
+    bench_dct,
+    bench_image_4k,
+    bench_image_1080p,
     bench_text_sizes,
     bench_memory_usage,
     bench_codec_comparison,
@@ -0,0 +1,218 @@
+//! Fixed-point ICF block decoding
+//!
+//! The decode math of `codec-cdn-rust`'s ICF codec (dequantization, inverse
+//! DCT, color conversion and clamping) in i32 arithmetic using only `core`,
+//! so it can run on targets without std or an FPU. The per-block functions
+//! never allocate; callers own every buffer. The `alloc` feature (on by
+//! default) adds whole-plane helpers that return owned buffers.
+//!
+//! Check the allocation-free build with
+//! `cargo check -p icf-core --no-default-features`.
+//!
+//! Samples carry `FRACTION_BITS` fractional bits and use the f64 decoder's
+//! scale: luma centered on 0, chroma centered on 0, both in 0-255 units.
+//! Results match the f64 decoder to within one pixel value.
+
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+/// Fractional bits of every sample and dequantized coefficient
+pub const FRACTION_BITS: u32 = 3;
+
+/// Fractional bits of `QuantTable` entries
+pub const TABLE_BITS: u32 = 8;
+
+/// Fractional bits of the inverse DCT basis
+const COS_BITS: u32 = 11;
+
+/// Fractional bits of the YCbCr conversion factors
+const COLOR_BITS: u32 = 12;
+
+/// Dequantized coefficients are clamped to this magnitude so the inverse
+/// DCT can't overflow i32; valid ICF data stays well inside it
+const MAX_COEFFICIENT: i32 = 4096 << FRACTION_BITS;
+
+/// 8x8 block of fixed-point samples or coefficients
+pub type Block = [[i32; 8]; 8];
+
+/// Quantization table with `TABLE_BITS` fractional bits
+pub type QuantTable = [[i32; 8]; 8];
+
+/// Zigzag scan order, matching the encoder
+pub const ZIGZAG_ORDER: [(usize, usize); 64] = [
+    (0, 0), (0, 1), (1, 0), (2, 0), (1, 1), (0, 2), (0, 3), (1, 2),
+    (2, 1), (3, 0), (4, 0), (3, 1), (2, 2), (1, 3), (0, 4), (0, 5),
+    (1, 4), (2, 3), (3, 2), (4, 1), (5, 0), (6, 0), (5, 1), (4, 2),
+    (3, 3), (2, 4), (1, 5), (0, 6), (0, 7), (1, 6), (2, 5), (3, 4),
+    (4, 3), (5, 2), (6, 1), (7, 0), (7, 1), (6, 2), (5, 3), (4, 4),
+    (3, 5), (2, 6), (1, 7), (2, 7), (3, 6), (4, 5), (5, 4), (6, 3),
+    (7, 2), (7, 3), (6, 4), (5, 5), (4, 6), (3, 7), (4, 7), (5, 6),
+    (6, 5), (7, 4), (7, 5), (6, 6), (5, 7), (6, 7), (7, 6), (7, 7),
+];
+
+/// `C(k) / 2 * cos((2j + 1) k pi / 16)` indexed `[j][k]`, with `COS_BITS` fractional bits
+const INVERSE_COS: [[i32; 8]; 8] = [
+    [724, 1004, 946, 851, 724, 569, 392, 200],
+    [724, 851, 392, -200, -724, -1004, -946, -569],
+    [724, 569, -392, -1004, -724, 200, 946, 851],
+    [724, 200, -946, -569, 724, 851, -392, -1004],
+    [724, -200, -946, 569, 724, -851, -392, 1004],
+    [724, -569, -392, 1004, -724, -200, 946, -851],
+    [724, -851, 392, 200, -724, 1004, -946, 569],
+    [724, -1004, 946, -851, 724, -569, 392, -200],
+];
+
+/// Expand a DC coefficient and run-length coded AC coefficients into zigzag order
+///
+/// Each run is `(zeros, value)`; `(0, 0)` ends the block early. Anything
+/// past 64 coefficients is dropped.
+pub fn expand_runs(dc: i16, runs: &[(u8, i16)], zigzag: &mut [i16; 64]) {
+    *zigzag = [0; 64];
+    zigzag[0] = dc;
+
+    let mut position = 1;
+    for &(zeros, value) in runs {
+        position += zeros as usize;
+        if zeros == 0 && value == 0 {
+            continue;
+        }
+        if position >= 64 {
+            break;
+        }
+        zigzag[position] = value;
+        position += 1;
+    }
+}
+
+/// Dequantize coefficients in zigzag order into a natural-order block
+pub fn dequantize(zigzag: &[i16; 64], table: &QuantTable, out: &mut Block) {
+    const SHIFT: u32 = TABLE_BITS - FRACTION_BITS;
+
+    for (&coefficient, &(i, j)) in zigzag.iter().zip(ZIGZAG_ORDER.iter()) {
+        let coefficient = (coefficient as i32).clamp(-4096, 4096);
+        let step = table[i][j].clamp(0, 1 << 18);
+        let value = (coefficient * step + (1 << (SHIFT - 1))) >> SHIFT;
+        out[i][j] = value.clamp(-MAX_COEFFICIENT, MAX_COEFFICIENT);
+    }
+}
+
+/// In-place 8x8 inverse DCT
+pub fn inverse_dct(block: &mut Block) {
+    const ROUND: i32 = 1 << (COS_BITS - 1);
+
+    // Rows
+    let mut temp = [[0i32; 8]; 8];
+    for (row, temp_row) in block.iter().zip(temp.iter_mut()) {
+        for (value, basis) in temp_row.iter_mut().zip(INVERSE_COS.iter()) {
+            let sum: i32 = row.iter().zip(basis).map(|(&x, &c)| x * c).sum();
+            *value = (sum + ROUND) >> COS_BITS;
+        }
+    }
+
+    // Columns
+    for (i, basis) in INVERSE_COS.iter().enumerate() {
+        for j in 0..8 {
+            let sum: i32 = (0..8).map(|k| temp[k][j] * basis[k]).sum();
+            block[i][j] = (sum + ROUND) >> COS_BITS;
+        }
+    }
+}
+
+/// Decode one block's zigzag coefficients into spatial samples
+pub fn decode_block(zigzag: &[i16; 64], table: &QuantTable, out: &mut Block) {
+    dequantize(zigzag, table, out);
+    inverse_dct(out);
+}
+
+/// Color space of the coded planes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorTransform {
+    YCoCg,
+    YCbCr,
+}
+
+/// Convert one pixel's luma and chroma samples to 8-bit RGB
+pub fn to_rgb(transform: ColorTransform, luma: i32, c1: i32, c2: i32) -> [u8; 3] {
+    const ROUND: i32 = 1 << (COLOR_BITS - 1);
+
+    let y = luma + (128 << FRACTION_BITS);
+    let (r, g, b) = match transform {
+        ColorTransform::YCoCg => {
+            let (co, cg) = (c1, c2);
+            let t = y - (cg >> 1);
+            let g = cg + t;
+            let b = t - (co >> 1);
+            (b + co, g, b)
+        }
+        ColorTransform::YCbCr => {
+            let (cb, cr) = (c1, c2);
+            let r = y + ((5743 * cr + ROUND) >> COLOR_BITS);
+            let g = y - ((1410 * cb + 2925 * cr + ROUND) >> COLOR_BITS);
+            let b = y + ((7258 * cb + ROUND) >> COLOR_BITS);
+            (r, g, b)
+        }
+    };
+
+    [clamp_sample(r), clamp_sample(g), clamp_sample(b)]
+}
+
+/// Round a fixed-point sample to the nearest 8-bit value
+pub fn clamp_sample(value: i32) -> u8 {
+    ((value + (1 << (FRACTION_BITS - 1))) >> FRACTION_BITS).clamp(0, 255) as u8
+}
+
+/// Convert full-resolution planes to interleaved 8-bit RGB
+///
+/// Stops at the shortest plane.
+#[cfg(feature = "alloc")]
+pub fn planes_to_rgb(transform: ColorTransform, planes: [&[i32]; 3]) -> alloc::vec::Vec<u8> {
+    let [luma, c1, c2] = planes;
+    luma.iter()
+        .zip(c1)
+        .zip(c2)
+        .flat_map(|((&luma, &c1), &c2)| to_rgb(transform, luma, c1, c2))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dc_only_block_is_flat() {
+        // A flat table of 1.0 and a DC of 80 decodes to samples of 80 / 8 = 10
+        let table = [[1 << TABLE_BITS; 8]; 8];
+        let mut zigzag = [0i16; 64];
+        expand_runs(80, &[(0, 0)], &mut zigzag);
+
+        let mut block = [[0; 8]; 8];
+        decode_block(&zigzag, &table, &mut block);
+        for row in block {
+            for sample in row {
+                assert!((sample - (10 << FRACTION_BITS)).abs() <= 1, "{}", sample);
+            }
+        }
+    }
+
+    #[test]
+    fn test_expand_runs_matches_zigzag_layout() {
+        let mut zigzag = [0i16; 64];
+        expand_runs(5, &[(2, 7), (0, -3), (0, 0)], &mut zigzag);
+        assert_eq!(&zigzag[..6], &[5, 0, 0, 7, -3, 0]);
+
+        // Runs past the end of the block are dropped
+        expand_runs(0, &[(255, 1), (70, 2)], &mut zigzag);
+        assert!(zigzag.iter().all(|&value| value == 0));
+    }
+
+    #[test]
+    fn test_grey_and_clamping() {
+        for transform in [ColorTransform::YCoCg, ColorTransform::YCbCr] {
+            assert_eq!(to_rgb(transform, 0, 0, 0), [128, 128, 128]);
+            assert_eq!(to_rgb(transform, 1 << 20, 0, 0), [255, 255, 255]);
+            assert_eq!(to_rgb(transform, -(1 << 20), 0, 0), [0, 0, 0]);
+        }
+    }
+}
@@ -1,16 +1,25 @@
 use clap::{Arg, ArgMatches, Command};
 use std::fs;
+use std::io::Read;
 use std::collections::HashMap;
 use std::time::Instant;
+use anyhow::Context;
 use base64::{Engine as _, engine::general_purpose};
 
-use codec_cdn_rust::codecs::bencode::{BencodeCodec, BencodeValue};
+use codec_cdn_rust::codecs::cli_common::{OutputOptions, RuntimeConfig};
+use codec_cdn_rust::codecs::bencode::{
+    codegen, create_torrent, extract_bytes, list_leaves, parse_magnet, render, schemas, to_magnet, BencodeCodec, BencodeStats,
+    BencodeValue, BencodeVisitor, Extracted, InfoHasher, Severity, TorrentOptions, WalkLimits,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::path::Path;
 
 fn main() -> anyhow::Result<()> {
-    let matches = Command::new("bencode-cli")
+    let command = Command::new("bencode-cli")
         .version("1.0.0")
         .author("vats98754")
         .about("Bencode encoder/decoder - BitTorrent serialization format")
+        .args(RuntimeConfig::args())
         .subcommand(
             Command::new("encode")
                 .about("Encode data to bencode format")
@@ -26,6 +35,7 @@ fn main() -> anyhow::Result<()> {
                         .required(true)
                         .index(2),
                 )
+                .args(OutputOptions::args())
                 .arg(
                     Arg::new("pretty")
                         .long("pretty")
@@ -48,6 +58,7 @@ fn main() -> anyhow::Result<()> {
                         .required(true)
                         .index(2),
                 )
+                .args(OutputOptions::args())
                 .arg(
                     Arg::new("format")
                         .long("format")
@@ -64,14 +75,36 @@ fn main() -> anyhow::Result<()> {
                         .help("Input file (.bencode)")
                         .required(true)
                         .index(1),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Print dates, sizes and hashes as stored, without readable forms")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("tree")
+                .about("Print every node of a bencode file, indented by depth")
+                .arg(
+                    Arg::new("input")
+                        .help("Input file (.bencode)")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Print dates, sizes and hashes as stored, without readable forms")
+                        .action(clap::ArgAction::SetTrue),
                 ),
         )
         .subcommand(
             Command::new("create-torrent")
-                .about("Create a torrent file structure")
+                .about("Create a torrent for a file or directory")
                 .arg(
-                    Arg::new("name")
-                        .help("Torrent name")
+                    Arg::new("path")
+                        .help("File or directory to share")
                         .required(true)
                         .index(1),
                 )
@@ -81,6 +114,12 @@ fn main() -> anyhow::Result<()> {
                         .required(true)
                         .index(2),
                 )
+                .args(OutputOptions::args())
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .help("Torrent name (default: the file or directory name)"),
+                )
                 .arg(
                     Arg::new("announce")
                         .long("announce")
@@ -91,17 +130,193 @@ fn main() -> anyhow::Result<()> {
                     Arg::new("piece-length")
                         .long("piece-length")
                         .help("Piece length in bytes")
-                        .value_parser(clap::value_parser!(u64))
+                        .value_parser(clap::value_parser!(u64).range(1..))
                         .default_value("32768"),
+                )
+                .arg(
+                    Arg::new("symlinks")
+                        .long("symlinks")
+                        .help("What to do with symbolic links inside a directory")
+                        .value_parser(["skip", "follow", "error"])
+                        .default_value("skip"),
+                )
+                .arg(
+                    Arg::new("include-hidden")
+                        .long("include-hidden")
+                        .help("Include files and directories whose names start with '.'")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("private")
+                        .long("private")
+                        .help("Mark the torrent private (trackers only, no DHT or PEX)")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("web-seed")
+                        .long("web-seed")
+                        .value_name("URL")
+                        .help("HTTP seed URL; may be repeated")
+                        .action(clap::ArgAction::Append),
                 ),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("validate")
+                .about("Check a bencode document against a known schema")
+                .arg(
+                    Arg::new("input")
+                        .help("Input file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("schema")
+                        .long("schema")
+                        .help("Schema to check against")
+                        .value_parser(["torrent", "announce"])
+                        .default_value("torrent"),
+                ),
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Write out the raw bytes stored at a path, e.g. the content of a bencode file")
+                .arg(
+                    Arg::new("input")
+                        .help("Input file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .value_name("PATH")
+                        .help("Node to extract: keys joined by '.', list items as [n] (e.g. info.files[0].path)")
+                        .required_unless_present("list"),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("Output file, or - for stdout")
+                        .default_value("-"),
+                )
+                .args(OutputOptions::args())
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .help("List every byte string with its path and size instead")
+                        .conflicts_with_all(["path", "out"])
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("codegen")
+                .about("Write serde structs for the dictionaries in sample documents")
+                .arg(
+                    Arg::new("inputs")
+                        .help("Sample files; keys missing from any of them become Option fields")
+                        .required(true)
+                        .num_args(1..)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_name("TYPE")
+                        .help("Name of the root struct")
+                        .default_value("Root"),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .help("Output Rust file, or - for stdout")
+                        .default_value("-"),
+                )
+                .args(OutputOptions::args()),
+        )
+        .subcommand(
+            Command::new("magnet")
+                .about("Print the magnet link of a torrent")
+                .arg(
+                    Arg::new("input")
+                        .help("Torrent file")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("from-magnet")
+                .about("Write the skeleton torrent a magnet link describes: trackers, name and info hash")
+                .arg(
+                    Arg::new("uri")
+                        .help("magnet:? link")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Output file (.bencode), or - for stdout")
+                        .required(true)
+                        .index(2),
+                )
+                .args(OutputOptions::args()),
+        );
+
+    #[cfg(feature = "interop")]
+    let command = command.subcommand(
+        Command::new("convert")
+            .about("Convert between bencode, CBOR and MessagePack")
+            .arg(
+                Arg::new("input")
+                    .help("Input file")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::new("output")
+                    .help("Output file")
+                    .required(true)
+                    .index(2),
+            )
+            .args(OutputOptions::args())
+            .arg(
+                Arg::new("to")
+                    .long("to")
+                    .help("Output format")
+                    .value_parser(["bencode", "cbor", "msgpack"])
+                    .required(true),
+            )
+            .arg(
+                Arg::new("from")
+                    .long("from")
+                    .help("Input format (default: from the file extension, else bencode)")
+                    .value_parser(["bencode", "cbor", "msgpack"]),
+            )
+            .arg(
+                Arg::new("text-keys")
+                    .long("text-keys")
+                    .help("Write dictionary keys as text instead of binary (keys must be UTF-8)")
+                    .action(clap::ArgAction::SetTrue),
+            ),
+    );
+
+    let matches = command.get_matches();
+    let runtime = RuntimeConfig::from_matches(&matches)?;
 
     match matches.subcommand() {
-        Some(("encode", sub_matches)) => encode_command(sub_matches),
-        Some(("decode", sub_matches)) => decode_command(sub_matches),
+        Some(("encode", sub_matches)) => encode_command(sub_matches, &runtime),
+        Some(("decode", sub_matches)) => decode_command(sub_matches, &runtime),
         Some(("info", sub_matches)) => info_command(sub_matches),
+        Some(("tree", sub_matches)) => tree_command(sub_matches, &runtime),
         Some(("create-torrent", sub_matches)) => create_torrent_command(sub_matches),
+        Some(("validate", sub_matches)) => validate_command(sub_matches, &runtime),
+        Some(("extract", sub_matches)) => extract_command(sub_matches, &runtime),
+        Some(("codegen", sub_matches)) => codegen_command(sub_matches, &runtime),
+        Some(("magnet", sub_matches)) => magnet_command(sub_matches, &runtime),
+        Some(("from-magnet", sub_matches)) => from_magnet_command(sub_matches),
+        #[cfg(feature = "interop")]
+        Some(("convert", sub_matches)) => convert_command(sub_matches, &runtime),
         _ => {
             eprintln!("No subcommand specified. Use --help for usage information.");
             Ok(())
@@ -109,15 +324,25 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
-fn encode_command(matches: &ArgMatches) -> anyhow::Result<()> {
+/// Read a whole input file, refusing it if it's over `--memory-limit`
+///
+/// Decoded values take at least as much memory as their encoding.
+fn read_input(path: &str, runtime: &RuntimeConfig) -> anyhow::Result<Vec<u8>> {
+    runtime.check_memory(&format!("Reading {}", path), fs::metadata(path)?.len())?;
+    Ok(fs::read(path)?)
+}
+
+fn encode_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
     let input_path = matches.get_one::<String>("input").unwrap();
     let output_path = matches.get_one::<String>("output").unwrap();
     let pretty = matches.get_flag("pretty");
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
 
     let start_time = Instant::now();
 
     // Read input JSON file
-    let input_data = fs::read_to_string(input_path)?;
+    let input_data = String::from_utf8(read_input(input_path, runtime)?)?;
     let json_value: serde_json::Value = serde_json::from_str(&input_data)?;
     
     // Convert JSON to BencodeValue
@@ -133,7 +358,9 @@ fn encode_command(matches: &ArgMatches) -> anyhow::Result<()> {
     let encoded_data = BencodeCodec::create_file_format(&bencode_value, Some(&metadata_value))?;
     
     // Write output
-    fs::write(output_path, &encoded_data)?;
+    if !output_options.write(input_path, output_path, &encoded_data)? {
+        return Ok(());
+    }
     
     let encode_time = start_time.elapsed();
     
@@ -156,31 +383,29 @@ fn encode_command(matches: &ArgMatches) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn decode_command(matches: &ArgMatches) -> anyhow::Result<()> {
+fn decode_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
     let input_path = matches.get_one::<String>("input").unwrap();
     let output_path = matches.get_one::<String>("output").unwrap();
     let format = matches.get_one::<String>("format").unwrap();
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
 
     let start_time = Instant::now();
 
     // Read bencode file
-    let encoded_data = fs::read(input_path)?;
+    let encoded_data = read_input(input_path, runtime)?;
     
     // Parse file format
     let (content, metadata) = BencodeCodec::parse_file_format(&encoded_data)?;
     
     // Convert to output format
-    match format.as_str() {
-        "json" => {
-            let json_value = bencode_to_json(&content)?;
-            let output_data = serde_json::to_string_pretty(&json_value)?;
-            fs::write(output_path, output_data)?;
-        }
-        "text" => {
-            let output_data = format!("{}", content);
-            fs::write(output_path, output_data)?;
-        }
+    let output_data = match format.as_str() {
+        "json" => serde_json::to_string_pretty(&bencode_to_json(&content)?)?,
+        "text" => format!("{}", content),
         _ => unreachable!(),
+    };
+    if !output_options.write(input_path, output_path, output_data.as_bytes())? {
+        return Ok(());
     }
     
     let decode_time = start_time.elapsed();
@@ -188,7 +413,7 @@ fn decode_command(matches: &ArgMatches) -> anyhow::Result<()> {
     println!("✅ Decoding complete!");
     println!("📦 Input: {}", input_path);
     println!("📄 Output: {}", output_path);
-    println!("📊 Decoded size: {} bytes", fs::metadata(output_path)?.len());
+    println!("📊 Decoded size: {} bytes", output_data.len());
     println!("⏱️  Decoding time: {:.3}ms", decode_time.as_secs_f64() * 1000.0);
     
     if let Some(meta) = metadata {
@@ -202,102 +427,280 @@ fn decode_command(matches: &ArgMatches) -> anyhow::Result<()> {
 fn info_command(matches: &ArgMatches) -> anyhow::Result<()> {
     let input_path = matches.get_one::<String>("input").unwrap();
 
-    let encoded_data = fs::read(input_path)?;
-    let (content, metadata) = BencodeCodec::parse_file_format(&encoded_data)?;
-    
+    let size = fs::metadata(input_path)?.len();
+    let mut summary = Summary { raw: matches.get_flag("raw"), ..Default::default() };
+    BencodeCodec::walk(fs::File::open(input_path)?, &mut summary, &WalkLimits::default())?;
+
     println!("📁 File: {}", input_path);
-    println!("📦 Size: {} bytes", encoded_data.len());
+    println!("📦 Size: {} bytes", size);
     println!("🏷️  Format: Bencode");
-    
-    // Analyze content structure
-    match &content {
-        BencodeValue::Integer(_) => println!("📊 Content type: Integer"),
-        BencodeValue::ByteString(s) => {
-            println!("📊 Content type: Byte string ({} bytes)", s.len());
-            if let Ok(text) = String::from_utf8(s.clone()) {
-                println!("📄 Text preview: {}", 
-                    if text.len() > 100 { 
-                        format!("{}...", &text[..100]) 
-                    } else { 
-                        text 
-                    }
-                );
-            }
-        }
-        BencodeValue::List(l) => {
-            println!("📊 Content type: List ({} items)", l.len());
-            for (i, item) in l.iter().enumerate().take(5) {
-                println!("  [{}]: {}", i, type_name(item));
-            }
-            if l.len() > 5 {
-                println!("  ... and {} more items", l.len() - 5);
-            }
+
+    let noun = match summary.root {
+        Some("dictionary") => "keys",
+        _ => "items",
+    };
+    match summary.root {
+        Some(kind @ ("dictionary" | "list")) => println!("📊 Root: {} ({} {})", kind, summary.children, noun),
+        Some("byte string") => println!("📊 Root: byte string ({} bytes)", summary.stats.string_bytes),
+        Some(kind) => println!("📊 Root: {}", kind),
+        None => {}
+    }
+    for entry in &summary.entries {
+        match &entry.preview {
+            Some(preview) => println!("  {}: {} = {}", entry.label, entry.kind, preview),
+            None => println!("  {}: {}", entry.label, entry.kind),
         }
-        BencodeValue::Dictionary(d) => {
-            println!("📊 Content type: Dictionary ({} keys)", d.keys().len());
-            let mut sorted_keys: Vec<_> = d.keys().collect();
-            sorted_keys.sort();
-            for key in sorted_keys.iter().take(10) {
-                if let Ok(key_str) = String::from_utf8((*key).clone()) {
-                    println!("  \"{}\": {}", key_str, type_name(d.get(*key).unwrap()));
+    }
+    if summary.children > summary.entries.len() {
+        println!("  ... and {} more {}", summary.children - summary.entries.len(), noun);
+    }
+
+    let stats = &summary.stats;
+    println!(
+        "🔢 Nodes: {} ({} integers, {} byte strings holding {} bytes, {} lists, {} dictionaries), {} deep",
+        stats.nodes(), stats.integers, stats.strings, stats.string_bytes, stats.lists, stats.dictionaries, stats.max_depth
+    );
+    if let Some(hash) = summary.hasher.finish() {
+        println!("🧲 Info hash: {}", hash.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+    }
+
+    Ok(())
+}
+
+fn tree_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
+    let input_path = matches.get_one::<String>("input").unwrap();
+    let value = BencodeCodec::decode(&read_input(input_path, runtime)?)?;
+    render::write_tree(std::io::stdout().lock(), &value, matches.get_flag("raw"))?;
+    Ok(())
+}
+
+/// One value directly under the root, as `info` lists it
+struct Entry {
+    label: String,
+    kind: &'static str,
+    /// Integers, and byte strings short enough to print as text
+    preview: Option<String>,
+}
+
+/// Visitor gathering what `info` prints in a single pass over the file
+#[derive(Default)]
+struct Summary {
+    stats: BencodeStats,
+    hasher: InfoHasher,
+    depth: usize,
+    root: Option<&'static str>,
+    /// Values directly under the root, of which the first few are kept
+    children: usize,
+    entries: Vec<Entry>,
+    key: String,
+    /// Leave out the readable forms of dates, sizes and hashes
+    raw: bool,
+}
+
+impl Summary {
+    const PREVIEW_LEN: u64 = 100;
+
+    /// Note a value starting, returning its entry if it is one to show
+    fn start(&mut self, kind: &'static str) -> Option<&mut Entry> {
+        match self.depth {
+            0 => self.root = Some(kind),
+            1 => {
+                let (shown, label) = match self.root {
+                    Some("list") => (5, format!("[{}]", self.children)),
+                    _ => (10, format!("\"{}\"", self.key)),
+                };
+                self.children += 1;
+                if self.entries.len() < shown {
+                    self.entries.push(Entry { label, kind, preview: None });
+                    return self.entries.last_mut();
                 }
             }
-            if d.len() > 10 {
-                println!("  ... and {} more keys", d.len() - 10);
-            }
+            _ => {}
         }
+        None
     }
-    
-    if let Some(meta) = metadata {
-        println!("\n📋 Metadata:");
-        if let Some(dict) = meta.as_dictionary() {
-            for (key, value) in dict.iter() {
-                if let Ok(key_str) = String::from_utf8(key.clone()) {
-                    println!("  {}: {}", key_str, value);
+
+    /// Readable form of a value directly under a root dictionary
+    fn annotation(&self, value: &BencodeValue) -> Option<String> {
+        if self.raw || self.depth != 1 || self.root != Some("dictionary") {
+            return None;
+        }
+        render::annotate(self.key.as_bytes(), value)
+    }
+}
+
+impl BencodeVisitor for Summary {
+    fn on_integer(&mut self, value: i64) -> anyhow::Result<()> {
+        self.stats.on_integer(value)?;
+        self.hasher.on_integer(value)?;
+        let annotation = self.annotation(&BencodeValue::integer(value));
+        if let Some(entry) = self.start("integer") {
+            entry.preview = Some(match annotation {
+                Some(annotation) => format!("{} ({})", annotation, value),
+                None => value.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn on_string(&mut self, len: u64, reader: &mut dyn Read) -> anyhow::Result<()> {
+        self.stats.on_string(len, reader)?;
+        // The hasher only reads strings inside info, which are never previewed
+        self.hasher.on_string(len, reader)?;
+        if self.depth == 1 && len <= Self::PREVIEW_LEN {
+            let mut bytes = Vec::new();
+            if reader.read_to_end(&mut bytes)? as u64 == len {
+                let value = BencodeValue::byte_string(bytes);
+                let preview = match self.annotation(&value) {
+                    Some(annotation) => Some(format!("{} (<{} bytes>)", annotation, len)),
+                    None => value.as_string().map(|text| format!("{:?}", text)),
+                };
+                if let Some(entry) = self.start("byte string") {
+                    entry.preview = preview;
                 }
+                return Ok(());
             }
         }
+        self.start("byte string");
+        Ok(())
+    }
+
+    fn on_list_start(&mut self) -> anyhow::Result<()> {
+        self.stats.on_list_start()?;
+        self.hasher.on_list_start()?;
+        self.start("list");
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn on_list_end(&mut self) -> anyhow::Result<()> {
+        self.stats.on_list_end()?;
+        self.hasher.on_list_end()?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn on_dict_start(&mut self) -> anyhow::Result<()> {
+        self.stats.on_dict_start()?;
+        self.hasher.on_dict_start()?;
+        self.start("dictionary");
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn on_dict_end(&mut self) -> anyhow::Result<()> {
+        self.stats.on_dict_end()?;
+        self.hasher.on_dict_end()?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn on_key(&mut self, key: &[u8]) -> anyhow::Result<()> {
+        self.stats.on_key(key)?;
+        self.hasher.on_key(key)?;
+        if self.depth == 1 {
+            self.key = String::from_utf8_lossy(key).into_owned();
+        }
+        Ok(())
     }
-    
-    Ok(())
 }
 
 fn create_torrent_command(matches: &ArgMatches) -> anyhow::Result<()> {
-    let name = matches.get_one::<String>("name").unwrap();
+    let path = matches.get_one::<String>("path").unwrap();
     let output_path = matches.get_one::<String>("output").unwrap();
-    let announce = matches.get_one::<String>("announce").unwrap();
-    let piece_length = *matches.get_one::<u64>("piece-length").unwrap();
-
-    // Create torrent info dictionary
-    let mut info = HashMap::new();
-    info.insert(b"name".to_vec(), BencodeValue::string(name));
-    info.insert(b"piece length".to_vec(), BencodeValue::integer(piece_length as i64));
-    info.insert(b"length".to_vec(), BencodeValue::integer(0)); // Placeholder
-    info.insert(b"pieces".to_vec(), BencodeValue::byte_string(Vec::new())); // Placeholder
-    
-    // Create main torrent dictionary
-    let mut torrent = HashMap::new();
-    torrent.insert(b"announce".to_vec(), BencodeValue::string(announce));
-    torrent.insert(b"info".to_vec(), BencodeValue::dictionary(info));
-    torrent.insert(b"creation date".to_vec(), BencodeValue::integer(
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64
-    ));
-    torrent.insert(b"created by".to_vec(), BencodeValue::string("bencode-cli 1.0.0"));
-    
-    let torrent_value = BencodeValue::dictionary(torrent);
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
+    let options = TorrentOptions {
+        announce: matches.get_one::<String>("announce").unwrap().clone(),
+        piece_length: *matches.get_one::<u64>("piece-length").unwrap(),
+        name: matches.get_one::<String>("name").cloned(),
+        include_hidden: matches.get_flag("include-hidden"),
+        symlinks: matches.get_one::<String>("symlinks").unwrap().parse()?,
+        private: matches.get_flag("private"),
+        web_seeds: matches.get_many::<String>("web-seed").into_iter().flatten().cloned().collect(),
+        creation_date: Some(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64
+        ),
+        created_by: Some("bencode-cli 1.0.0".to_string()),
+    };
+
+    let bar = ProgressBar::new(0).with_style(
+        ProgressStyle::with_template("{spinner} Hashing [{bar:40}] {bytes}/{total_bytes} ({eta})")?
+            .progress_chars("=> "),
+    );
+    let torrent_value = create_torrent(Path::new(path), &options, &mut |progress| {
+        bar.set_length(progress.total);
+        bar.set_position(progress.done);
+    })?;
+    bar.finish_and_clear();
+
     let encoded_data = BencodeCodec::encode(&torrent_value)?;
-    
-    fs::write(output_path, encoded_data)?;
-    
+    if !output_options.write(path, output_path, &encoded_data)? {
+        return Ok(());
+    }
+
+    let info = torrent_value.get_dict_value("info").unwrap();
+    let file_count = info.get_dict_value("files").and_then(BencodeValue::as_list).map_or(1, Vec::len);
+    let piece_count = info.get_dict_value("pieces").and_then(BencodeValue::as_byte_string).map_or(0, |pieces| pieces.len() / 20);
+
     println!("✅ Torrent file created!");
-    println!("📁 Name: {}", name);
+    println!("📁 Name: {}", info.get_dict_value("name").and_then(BencodeValue::as_string).unwrap_or_default());
     println!("📦 Output: {}", output_path);
-    println!("🌐 Announce: {}", announce);
-    println!("📊 Piece length: {} bytes", piece_length);
-    
+    println!("🌐 Announce: {}", options.announce);
+    println!("📄 Files: {}", file_count);
+    println!("📊 Piece length: {} bytes ({} pieces)", options.piece_length, piece_count);
+    if options.private {
+        println!("🔒 Private");
+    }
+    for url in &options.web_seeds {
+        println!("🌱 Web seed: {}", url);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "interop")]
+fn convert_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
+    use codec_cdn_rust::codecs::bencode::interop::{self, KeyEncoding};
+
+    let input_path = matches.get_one::<String>("input").unwrap();
+    let output_path = matches.get_one::<String>("output").unwrap();
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
+    let to = matches.get_one::<String>("to").unwrap();
+    let from = match matches.get_one::<String>("from") {
+        Some(format) => format.as_str(),
+        None => match std::path::Path::new(input_path).extension().and_then(|e| e.to_str()) {
+            Some("cbor") => "cbor",
+            Some("msgpack") | Some("mpk") => "msgpack",
+            _ => "bencode",
+        },
+    };
+    let keys = if matches.get_flag("text-keys") { KeyEncoding::Utf8 } else { KeyEncoding::Binary };
+
+    let input_data = read_input(input_path, runtime)?;
+    let value = match from {
+        "cbor" => interop::from_cbor(&input_data)?,
+        "msgpack" => interop::from_msgpack(&input_data)?,
+        _ => BencodeCodec::decode(&input_data)?,
+    };
+    let output_data = match to.as_str() {
+        "cbor" => interop::to_cbor_with_keys(&value, keys)?,
+        "msgpack" => interop::to_msgpack_with_keys(&value, keys)?,
+        _ => BencodeCodec::encode(&value)?,
+    };
+
+    if !output_options.write(input_path, output_path, &output_data)? {
+        return Ok(());
+    }
+
+    println!("✅ Conversion complete!");
+    println!("📄 Input: {} ({}, {} bytes)", input_path, from, input_data.len());
+    println!("📦 Output: {} ({}, {} bytes)", output_path, to, output_data.len());
+
     Ok(())
 }
 
@@ -334,8 +737,8 @@ fn bencode_to_json(bencode: &BencodeValue) -> anyhow::Result<serde_json::Value>
     match bencode {
         BencodeValue::Integer(i) => Ok(serde_json::Value::Number(serde_json::Number::from(*i))),
         BencodeValue::ByteString(s) => {
-            if let Ok(text) = String::from_utf8(s.clone()) {
-                Ok(serde_json::Value::String(text))
+            if let Ok(text) = std::str::from_utf8(s) {
+                Ok(serde_json::Value::String(text.to_string()))
             } else {
                 // For binary data, encode as base64
                 Ok(serde_json::Value::String(general_purpose::STANDARD.encode(s)))
@@ -360,11 +763,87 @@ fn bencode_to_json(bencode: &BencodeValue) -> anyhow::Result<serde_json::Value>
     }
 }
 
-fn type_name(value: &BencodeValue) -> &'static str {
-    match value {
-        BencodeValue::Integer(_) => "integer",
-        BencodeValue::ByteString(_) => "byte string",
-        BencodeValue::List(_) => "list",
-        BencodeValue::Dictionary(_) => "dictionary",
+fn validate_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
+    let input_path = matches.get_one::<String>("input").unwrap();
+    let schema = match matches.get_one::<String>("schema").unwrap().as_str() {
+        "announce" => schemas::announce_response(),
+        _ => schemas::torrent_v1(),
+    };
+
+    let value = BencodeCodec::decode(&read_input(input_path, runtime)?)?;
+    let violations = schema.validate(&value);
+    for violation in &violations {
+        println!("{}", violation);
     }
-}
\ No newline at end of file
+
+    let errors = violations.iter().filter(|violation| violation.severity == Severity::Error).count();
+    if errors > 0 {
+        anyhow::bail!("{} failed validation with {} error(s)", input_path, errors);
+    }
+    println!("✅ {} is valid ({} warning(s))", input_path, violations.len());
+    Ok(())
+}
+
+fn magnet_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
+    let input_path = matches.get_one::<String>("input").unwrap();
+    let torrent = BencodeCodec::decode(&read_input(input_path, runtime)?)?;
+    println!("{}", to_magnet(&torrent)?);
+    Ok(())
+}
+
+fn from_magnet_command(matches: &ArgMatches) -> anyhow::Result<()> {
+    let uri = matches.get_one::<String>("uri").unwrap();
+    let output_path = matches.get_one::<String>("output").unwrap();
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
+
+    let magnet = parse_magnet(uri)?;
+    let encoded = BencodeCodec::encode(&magnet.to_skeleton())?;
+    if output_options.write("magnet link", output_path, &encoded)? && output_path != "-" {
+        println!("🧲 Info hash: {}", magnet.info_hash.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+        println!("📦 Output: {} ({} bytes, {} tracker(s))", output_path, encoded.len(), magnet.trackers.len());
+    }
+    Ok(())
+}
+
+fn codegen_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
+    let inputs: Vec<&String> = matches.get_many::<String>("inputs").unwrap().collect();
+    let output_path = matches.get_one::<String>("out").unwrap();
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
+
+    let samples = inputs.iter()
+        .map(|input| BencodeCodec::decode(&read_input(input, runtime)?).with_context(|| format!("Failed to decode {}", input)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let code = codegen::infer(&samples)?.render(matches.get_one::<String>("name").unwrap());
+    if output_options.write(inputs[0], output_path, code.as_bytes())? && output_path != "-" {
+        println!("✅ Wrote types inferred from {} sample(s) to {}", samples.len(), output_path);
+    }
+    Ok(())
+}
+
+fn extract_command(matches: &ArgMatches, runtime: &RuntimeConfig) -> anyhow::Result<()> {
+    let input_path = matches.get_one::<String>("input").unwrap();
+    let value = BencodeCodec::decode(&read_input(input_path, runtime)?)?;
+
+    if matches.get_flag("list") {
+        for leaf in list_leaves(&value) {
+            println!("{}\t{}", leaf.size, leaf.path);
+        }
+        return Ok(());
+    }
+
+    let path = matches.get_one::<String>("path").unwrap();
+    let output_path = matches.get_one::<String>("out").unwrap();
+    let output_options = OutputOptions::from_matches(matches, false);
+    output_options.check(output_path)?;
+
+    let extracted = extract_bytes(&value, path)?;
+    if let Extracted::Encoded(_) = extracted {
+        eprintln!("⚠️  {} is not a byte string; writing its bencode encoding", path);
+    }
+    if output_options.write(input_path, output_path, extracted.as_bytes())? && output_path != "-" {
+        println!("✅ Extracted {} ({} bytes) to {}", path, extracted.as_bytes().len(), output_path);
+    }
+    Ok(())
+}
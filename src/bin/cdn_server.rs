@@ -0,0 +1,102 @@
+use anyhow::Context;
+use clap::{Arg, ArgAction, Command};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use codec_cdn_rust::cdn::{CachePolicy, ObjectStore, UploadPolicy};
+use codec_cdn_rust::codecs::cli_common::RuntimeConfig;
+use codec_cdn_rust::codecs::image::{IcfProfile, PROFILES};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let matches = Command::new("cdn-server")
+        .version("1.0.0")
+        .author("vats98754")
+        .about("Content-addressed CDN server with on-demand ICF variants")
+        .args(RuntimeConfig::args())
+        .arg(
+            Arg::new("root")
+                .long("root")
+                .help("Object store directory")
+                .default_value("cdn-store"),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Serve objects over HTTP")
+                .arg(
+                    Arg::new("addr")
+                        .long("addr")
+                        .help("Listen address")
+                        .default_value("127.0.0.1:8080"),
+                )
+                .arg(
+                    Arg::new("max-age")
+                        .long("max-age")
+                        .value_name("FORMAT=SECONDS")
+                        .help("Cache lifetime of base objects in a format (tcf, icf); repeatable")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("variant-max-age")
+                        .long("variant-max-age")
+                        .value_name("SECONDS")
+                        .help("Cache lifetime of transcoded variants")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("max-upload")
+                        .long("max-upload")
+                        .value_name("BYTES")
+                        .help("Largest body POST /upload accepts, for text and images alike")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("image-profile")
+                        .long("image-profile")
+                        .value_name("NAME")
+                        .help("ICF profile POST /upload encodes images with")
+                        .default_value("photo")
+                        .value_parser(clap::builder::PossibleValuesParser::new(PROFILES.iter().map(|profile| profile.name))),
+                ),
+        )
+        .subcommand(Command::new("stats").about("Show object counts and sizes"))
+        .get_matches();
+
+    let root = matches.get_one::<String>("root").unwrap();
+    let store = ObjectStore::open(root)?;
+
+    match matches.subcommand() {
+        Some(("serve", sub_matches)) => {
+            let addr: SocketAddr = sub_matches.get_one::<String>("addr").unwrap().parse()?;
+            let mut policy = CachePolicy::default();
+            for setting in sub_matches.get_many::<String>("max-age").into_iter().flatten() {
+                let (format, seconds) = setting.split_once('=')
+                    .with_context(|| format!("--max-age expects FORMAT=SECONDS, got '{}'", setting))?;
+                let seconds = seconds.parse().with_context(|| format!("Invalid max-age '{}'", seconds))?;
+                policy.format_max_age.insert(format.to_string(), seconds);
+            }
+            if let Some(&seconds) = sub_matches.get_one::<u32>("variant-max-age") {
+                policy.variant_max_age = seconds;
+            }
+            let mut upload = UploadPolicy {
+                image_profile: *IcfProfile::by_name(sub_matches.get_one::<String>("image-profile").unwrap()).unwrap(),
+                runtime: RuntimeConfig::from_matches(&matches)?,
+                ..Default::default()
+            };
+            if let Some(&bytes) = sub_matches.get_one::<u64>("max-upload") {
+                upload.max_text_size = bytes;
+                upload.max_image_size = bytes;
+            }
+            println!("🌐 Serving {} on http://{}", root, addr);
+            codec_cdn_rust::cdn::serve(Arc::new(store), policy, upload, addr).await;
+        }
+        Some(("stats", _)) => {
+            println!("📊 {}", store.stats()?);
+        }
+        _ => {
+            println!("Use --help for usage information");
+        }
+    }
+
+    Ok(())
+}
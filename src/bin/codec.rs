@@ -0,0 +1,43 @@
+use clap::{Arg, Command};
+
+use codec_cdn_rust::codecs::cli_common::RuntimeConfig;
+use codec_cdn_rust::codecs::doctor;
+
+fn main() {
+    let matches = Command::new("codec")
+        .version("1.0.0")
+        .author("vats98754")
+        .about("Tools that apply to every codec")
+        .args(RuntimeConfig::args())
+        .subcommand(
+            Command::new("doctor")
+                .about("Report build capabilities, run self-tests and check files")
+                .arg(
+                    Arg::new("files")
+                        .help("Files to check (TCF, ICF, VCF or bencode)")
+                        .value_name("FILE")
+                        .num_args(0..)
+                )
+        )
+        .get_matches();
+    let runtime = match RuntimeConfig::from_matches(&matches) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    match matches.subcommand() {
+        Some(("doctor", sub_matches)) => {
+            let files: Vec<&String> = sub_matches.get_many::<String>("files").unwrap_or_default().collect();
+            // Self-tests run on the configured pool, so its size is what's reported
+            let report = runtime.install(|| doctor::run(&files));
+            println!("{}", report);
+            std::process::exit(report.exit_code());
+        }
+        _ => {
+            println!("Use --help for usage information");
+        }
+    }
+}
@@ -1,12 +1,28 @@
 use clap::{Arg, Command};
-use codec_cdn_rust::codecs::image::{IcfCodec, ImageCompressionStats};
+use codec_cdn_rust::codecs::atomic::write_atomic;
+use codec_cdn_rust::codecs::cli_common::{init_tracing, OutputOptions, RuntimeConfig};
+use codec_cdn_rust::codecs::image::{decode_input, load_input, Anchor, CoefficientPlane, IcfCodec, IcfEncodeOptions, IcfProfile, ImageCompressionStats, Overlay, SubsamplingMode, fit_image, suggested_quality, ResampleOptions, DEFAULT_COMPONENTS, PROFILES};
+use codec_cdn_rust::codecs::text::CodecWarning;
+use codec_cdn_rust::codecs::layout::{hexdump, LayoutRegion};
+use codec_cdn_rust::codecs::npy::write_npy;
+use codec_cdn_rust::codecs::video::{simulate_cvd, CvdKind, WeightedPsnr};
 use std::fs;
+use image::ImageFormat;
+use std::io::{self, Cursor, Write};
 use std::path::Path;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("icf-cli")
         .version("1.0")
         .about("Image Codec Format (ICF) CLI tool with advanced DCT compression")
+        .arg(
+            Arg::new("verbose")
+                .help("Print codec phase spans and sizes to stderr (needs the 'tracing' feature)")
+                .long("verbose")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+        )
+        .args(RuntimeConfig::args())
         .subcommand(
             Command::new("encode")
                 .about("Encode image to ICF format")
@@ -22,14 +38,120 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .args(OutputOptions::args())
                 .arg(
                     Arg::new("quality")
-                        .help("Quality level (1-100, default: 85)")
+                        .help("Quality level (1-100, default: 85 or the profile's)")
                         .short('q')
                         .long("quality")
                         .value_name("NUM")
-                        .default_value("85")
                 )
+                .arg(
+                    Arg::new("profile")
+                        .help("Start from a named profile (see 'icf-cli profiles')")
+                        .long("profile")
+                        .value_name("NAME")
+                        .value_parser(clap::builder::PossibleValuesParser::new(PROFILES.iter().map(|profile| profile.name)))
+                )
+                .arg(
+                    Arg::new("subsampling")
+                        .help("Chroma subsampling; auto picks 444 or 420 from the image's chroma detail (default: auto, or the profile's)")
+                        .long("subsampling")
+                        .value_name("MODE")
+                        .value_parser(["auto", "444", "420"])
+                )
+                .arg(
+                    Arg::new("color-space")
+                        .help("Coding color space (overrides the profile)")
+                        .long("color-space")
+                        .value_name("SPACE")
+                        .value_parser(["ycocg", "ycbcr"])
+                )
+                .arg(
+                    Arg::new("tables")
+                        .help("Quantization table set (overrides the profile)")
+                        .long("tables")
+                        .value_name("SET")
+                        .value_parser(["standard", "perceptual", "optimized"])
+                )
+                .arg(
+                    Arg::new("tile-size")
+                        .help("Tile edge in pixels, a multiple of 16; 0 disables tiling (overrides the profile)")
+                        .long("tile-size")
+                        .value_name("PIXELS")
+                        .value_parser(clap::value_parser!(u32))
+                )
+                .arg(
+                    Arg::new("strip")
+                        .help("Omit the optional metadata section")
+                        .long("strip")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("fill-transparent")
+                        .help("Recolor fully transparent pixels so they cost next to nothing (alpha is not stored)")
+                        .long("fill-transparent")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("sign-contexts")
+                        .help("Context code coefficient signs (smaller; needs a decoder that supports it)")
+                        .long("sign-contexts")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("compress-sections")
+                        .help("Deflate the metadata and quantization tables (smaller; needs a decoder that supports it)")
+                        .long("compress-sections")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("no-reproducible")
+                        .help("Record the source file name and encode time (output varies between runs)")
+                        .long("no-reproducible")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("tag")
+                        .help("Metadata tag to record (repeatable)")
+                        .long("tag")
+                        .value_name("KEY=VALUE")
+                        .action(clap::ArgAction::Append)
+                )
+                .arg(
+                    Arg::new("watermark")
+                        .help("Image to blend onto the source before encoding, scaled down if larger")
+                        .long("watermark")
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("anchor")
+                        .help("Corner or center the watermark is placed at")
+                        .long("anchor")
+                        .value_name("POSITION")
+                        .value_parser(["top-left", "top-right", "bottom-left", "bottom-right", "center"])
+                        .default_value("bottom-right")
+                        .requires("watermark")
+                )
+                .arg(
+                    Arg::new("opacity")
+                        .help("Watermark opacity, 0 to 1")
+                        .long("opacity")
+                        .value_name("ALPHA")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("0.3")
+                        .requires("watermark")
+                )
+                .arg(
+                    Arg::new("explain-json")
+                        .help("Write the subsampling and quantization decisions the encode made to FILE as JSON")
+                        .long("explain-json")
+                        .value_name("FILE")
+                )
+        )
+        .subcommand(
+            Command::new("profiles")
+                .about("List the built-in encoding profiles")
         )
         .subcommand(
             Command::new("decode")
@@ -46,6 +168,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .args(OutputOptions::args())
+                .arg(
+                    Arg::new("partial")
+                        .help("Salvage what can be decoded from a truncated or damaged file")
+                        .long("partial")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("max-dim")
+                        .help("Scale the image down to fit a square this many pixels on a side")
+                        .long("max-dim")
+                        .value_name("PIXELS")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                )
+                .arg(
+                    Arg::new("linear-light")
+                        .help("Scale in linear light, keeping the brightness of fine detail")
+                        .long("linear-light")
+                        .requires("max-dim")
+                        .action(clap::ArgAction::SetTrue)
+                )
         )
         .subcommand(
             Command::new("info")
@@ -56,6 +199,92 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .arg(
+                    Arg::new("layout")
+                        .help("Show the byte range of every region of the file")
+                        .long("layout")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Print the layout as JSON")
+                        .long("json")
+                        .requires("layout")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("placeholder")
+                        .help("Show a blurhash-style placeholder string for the image")
+                        .long("placeholder")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Dump the bytes of one region of an ICF file")
+                .arg(
+                    Arg::new("input")
+                        .help("Input ICF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("section")
+                        .help("Region name as listed by 'info --layout' (e.g. header, blocks)")
+                        .long("section")
+                        .required(true)
+                        .value_name("NAME")
+                )
+                .arg(
+                    Arg::new("hex")
+                        .help("Print a hexdump instead of raw bytes")
+                        .long("hex")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("dump-coefficients")
+                .about("Export the quantized DCT coefficients of one channel")
+                .arg(
+                    Arg::new("input")
+                        .help("Input ICF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("channel")
+                        .help("Channel index (0 = luma)")
+                        .long("channel")
+                        .value_name("INDEX")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("0")
+                )
+                .arg(
+                    Arg::new("block")
+                        .help("Only export block X,Y of the channel's block grid")
+                        .long("block")
+                        .value_name("X,Y")
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Output format")
+                        .long("format")
+                        .value_parser(["json", "npy"])
+                        .default_value("json")
+                )
+                .arg(
+                    Arg::new("dequantize")
+                        .help("Scale coefficients back by the quantization table")
+                        .long("dequantize")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Write to this file instead of stdout")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                )
         )
         .subcommand(
             Command::new("compare")
@@ -72,45 +301,162 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .arg(
+                    Arg::new("weighted")
+                        .help("Weight luma errors above chroma errors in the combined PSNR")
+                        .long("weighted")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("weights")
+                        .help("Y:CO:CG weights of the combined PSNR")
+                        .long("weights")
+                        .value_name("Y:CO:CG")
+                        .requires("weighted")
+                        .default_value("6:1:1")
+                )
+                .arg(
+                    Arg::new("cvd")
+                        .help("Simulate a color vision deficiency on both images before measuring")
+                        .long("cvd")
+                        .value_name("KIND")
+                        .value_parser(["protan", "deutan", "tritan"])
+                )
         )
         .get_matches();
+    init_tracing(matches.get_flag("verbose"));
 
-    let codec = IcfCodec::new();
+    let codec = IcfCodec::new().with_runtime(RuntimeConfig::from_matches(&matches)?);
 
     match matches.subcommand() {
         Some(("encode", sub_matches)) => {
             let input = sub_matches.get_one::<String>("input").unwrap();
             let output = sub_matches.get_one::<String>("output").unwrap();
-            let quality = sub_matches.get_one::<String>("quality").unwrap()
-                .parse::<u8>()
-                .map_err(|_| "Quality must be a number between 1 and 100")?;
+            let output_options = OutputOptions::from_matches(sub_matches, false);
+            output_options.check(output)?;
             
-            if quality < 1 || quality > 100 {
+            let mut options = match sub_matches.get_one::<String>("profile") {
+                Some(name) => IcfEncodeOptions::profile(IcfProfile::by_name(name).unwrap()),
+                None => IcfEncodeOptions { chroma_subsampling: SubsamplingMode::Auto, ..Default::default() },
+            };
+            options.reproducible = !sub_matches.get_flag("no-reproducible");
+            options.strip_metadata = sub_matches.get_flag("strip");
+            options.sign_contexts = sub_matches.get_flag("sign-contexts");
+            options.fill_transparent = sub_matches.get_flag("fill-transparent");
+            options.compress_sections = sub_matches.get_flag("compress-sections");
+
+            // Individual flags win over the profile
+            if let Some(quality) = sub_matches.get_one::<String>("quality") {
+                options.quality = quality.parse::<u8>()
+                    .map_err(|_| "Quality must be a number between 1 and 100")?;
+            }
+            if let Some(subsampling) = sub_matches.get_one::<String>("subsampling") {
+                options.chroma_subsampling = subsampling.parse()?;
+            }
+            if let Some(color_space) = sub_matches.get_one::<String>("color-space") {
+                options.color_space = color_space.parse()?;
+            }
+            if let Some(tables) = sub_matches.get_one::<String>("tables") {
+                options.quantization = tables.parse()?;
+            }
+            if let Some(&tile_size) = sub_matches.get_one::<u32>("tile-size") {
+                options.tile_size = (tile_size != 0).then_some(tile_size);
+            }
+            let quality = options.quality;
+            
+            if !(1..=100).contains(&quality) {
                 return Err("Quality must be between 1 and 100".into());
             }
+
+            for tag in sub_matches.get_many::<String>("tag").unwrap_or_default() {
+                let (key, value) = tag.split_once('=')
+                    .ok_or_else(|| format!("Tag '{}' must be KEY=VALUE", tag))?;
+                options.metadata.tags.insert(key.to_string(), value.to_string());
+            }
+            if let Some(watermark) = sub_matches.get_one::<String>("watermark") {
+                let image = load_input(watermark.as_str())
+                    .map_err(|e| format!("Failed to read watermark {}: {:#}", watermark, e))?;
+                let anchor: Anchor = sub_matches.get_one::<String>("anchor").unwrap().parse()?;
+                let mut overlay = Overlay::new(image, anchor, *sub_matches.get_one::<f32>("opacity").unwrap());
+                overlay.name = Path::new(watermark).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                options.overlay = Some(overlay);
+            }
             
-            println!("Encoding image: {} (quality: {})", input, quality);
+            match &options.profile {
+                Some(profile) => println!("Encoding image: {} (profile: {}, quality: {})", input, profile, quality),
+                None => println!("Encoding image: {} (quality: {})", input, quality),
+            }
             
-            let compressed = codec.encode(input, quality)?;
-            fs::write(output, &compressed)?;
+            let (compressed, warnings, trace) = codec.encode_file_with_trace(input, &options)?;
+            for warning in &warnings {
+                eprintln!("note: {}", warning);
+                if let CodecWarning::LossySource { estimated_prior_quality: Some(estimate), .. } = warning {
+                    if let Some(suggested) = suggested_quality(quality, *estimate) {
+                        eprintln!("note: quality {} spends bits reproducing the source's artifacts; try -q {}", quality, suggested);
+                    }
+                }
+            }
+            if !output_options.write(input, output, &compressed)? {
+                return Ok(());
+            }
+            if let Some(path) = sub_matches.get_one::<String>("explain-json") {
+                write_atomic(path, format!("{}\n", trace.to_json()?).as_bytes())?;
+            }
             
             let stats = codec.get_stats(input, &compressed)?;
             println!("✓ Encoding complete!");
             println!("  Input: {} bytes", stats.original_size);
             println!("  Output: {} bytes", stats.compressed_size);
+            if options.chroma_subsampling == SubsamplingMode::Auto {
+                println!("  Chroma subsampling: {} (auto)", codec.parse_container(&compressed)?.0.chroma_subsampling);
+            }
             println!("  Compression ratio: {:.2}:1", stats.compression_ratio);
             println!("  Space savings: {:.2}%", stats.savings_percent);
         }
         
+        Some(("profiles", _)) => {
+            println!("{:<12} {:>7}  {:<11} {:<11} {:<10} {:>6}  Description",
+                "Profile", "Quality", "Subsampling", "Color space", "Tables", "Tiles");
+            for profile in PROFILES {
+                let tiles = profile.tile_size.map_or("-".to_string(), |size| size.to_string());
+                println!("{:<12} {:>7}  {:<11} {:<11} {:<10} {:>6}  {}",
+                    profile.name, profile.quality, profile.chroma_subsampling.to_string(),
+                    profile.color_space.to_string(), profile.quantization.to_string(), tiles,
+                    profile.description);
+            }
+        }
+
         Some(("decode", sub_matches)) => {
             let input = sub_matches.get_one::<String>("input").unwrap();
             let output = sub_matches.get_one::<String>("output").unwrap();
+            let output_options = OutputOptions::from_matches(sub_matches, false);
+            output_options.check(output)?;
             
             let compressed = fs::read(input)?;
             println!("Decoding ICF file: {} ({} bytes)", input, compressed.len());
             
-            let image = codec.decode(&compressed)?;
-            image.save(output)?;
+            let image = if sub_matches.get_flag("partial") {
+                let partial = codec.decode_partial(&compressed)?;
+                if let Some(reason) = &partial.stop_reason {
+                    println!("⚠ Stopped early: {}", reason);
+                }
+                println!("  Coverage: {:.1}% of blocks decoded", partial.coverage);
+                partial.image
+            } else {
+                codec.decode(&compressed)?
+            };
+            let image = match sub_matches.get_one::<u32>("max-dim") {
+                Some(&max_dim) => {
+                    let options = ResampleOptions { linear_light: sub_matches.get_flag("linear-light"), ..Default::default() };
+                    fit_image(&image, max_dim, &options)
+                }
+                None => image,
+            };
+            let mut encoded = Cursor::new(Vec::new());
+            image.write_to(&mut encoded, ImageFormat::from_path(output)?)?;
+            if !output_options.write(input, output, encoded.get_ref())? {
+                return Ok(());
+            }
             
             println!("✓ Decoding complete!");
             println!("  Output: {} ({}x{})", output, image.width(), image.height());
@@ -120,6 +466,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let input = sub_matches.get_one::<String>("input").unwrap();
             
             let compressed = fs::read(input)?;
+
+            if sub_matches.get_flag("json") {
+                let layout = codec.parse_layout(&compressed)?;
+                println!("{}", serde_json::to_string_pretty(&layout)?);
+                return Ok(());
+            }
             
             // Parse header to show information
             if let Ok((header, _)) = codec.parse_container(&compressed) {
@@ -129,23 +481,109 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("  Dimensions: {}x{}", header.width, header.height);
                 println!("  Channels: {}", header.channels);
                 println!("  Color space: {}", header.color_space);
+                println!("  Chroma subsampling: {}", header.chroma_subsampling);
+                if let Some(profile) = &header.profile {
+                    println!("  Profile: {}", profile);
+                }
                 println!("  Quality: {}", header.quality);
+                println!("  Quantization tables: {}", header.quantization);
+                if let Some(tile_size) = header.tile_size {
+                    println!("  Tile size: {}x{}", tile_size, tile_size);
+                }
                 println!("  Compression method: {}", header.compression_method);
+                if header.sign_contexts {
+                    println!("  Sign contexts: yes");
+                }
                 println!("  Block size: {}x{}", header.block_size, header.block_size);
                 println!("  Original size: {} bytes", header.original_size);
                 println!("  Compressed size: {} bytes", header.compressed_size);
                 println!("  File size: {} bytes", compressed.len());
                 println!("  Checksum: {}", header.checksum);
+                for section in &header.sections {
+                    println!("  Section {}: {} bytes stored, {} expanded",
+                        section.kind.as_str(), section.length, section.expanded_length);
+                }
+                if let Some(metadata) = &codec.metadata(&compressed)? {
+                    if let Some(source_name) = &metadata.source_name {
+                        println!("  Source: {}", source_name);
+                    }
+                    if let Some(created_at) = metadata.created_at {
+                        println!("  Created: {} (Unix time)", created_at);
+                    }
+                    for (key, value) in &metadata.tags {
+                        println!("  Tag {}: {}", key, value);
+                    }
+                    if let Some(overlay) = &metadata.overlay {
+                        println!("  Overlay: {} (sha256 {})", overlay.name, overlay.sha256);
+                    }
+                }
                 
                 let compression_ratio = header.original_size as f64 / compressed.len() as f64;
-                let savings = ((header.original_size - compressed.len() as u64) as f64 / header.original_size as f64) * 100.0;
+                let savings = ((header.original_size as f64 - compressed.len() as f64) / header.original_size as f64) * 100.0;
                 println!("  Compression ratio: {:.2}:1", compression_ratio);
                 println!("  Space savings: {:.2}%", savings);
+
+                if sub_matches.get_flag("placeholder") {
+                    let (components_x, components_y) = DEFAULT_COMPONENTS;
+                    println!("  Placeholder: {}", codec.placeholder(&compressed, components_x, components_y)?);
+                }
             } else {
                 return Err("Failed to parse ICF header".into());
             }
+
+            if sub_matches.get_flag("layout") {
+                let layout = codec.parse_layout(&compressed)?;
+                print_layout(&layout.regions);
+            }
+        }
+
+        Some(("dump", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let section = sub_matches.get_one::<String>("section").unwrap();
+
+            let compressed = fs::read(input)?;
+            let layout = codec.parse_layout(&compressed)?;
+            let region = layout.region(section)
+                .ok_or_else(|| format!("No '{}' section in {}", section, input))?;
+
+            dump_region(&compressed, region, sub_matches.get_flag("hex"))?;
         }
         
+        Some(("dump-coefficients", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let channel = *sub_matches.get_one::<usize>("channel").unwrap();
+            let dequantize = sub_matches.get_flag("dequantize");
+
+            let compressed = fs::read(input)?;
+            let planes = codec.coefficients(&compressed)?;
+            let plane = planes.channels.get(channel)
+                .ok_or_else(|| format!("No channel {} in {} ({} channels)", channel, input, planes.channels.len()))?;
+            let block = sub_matches.get_one::<String>("block").map(|block| parse_block(block)).transpose()?;
+
+            let bytes = match (sub_matches.get_one::<String>("format").unwrap().as_str(), block) {
+                ("npy", None) => plane.to_npy(dequantize)?,
+                ("npy", Some((x, y))) => {
+                    let quantized = plane.block(x, y).ok_or_else(|| block_error(x, y, plane))?;
+                    if dequantize {
+                        write_npy(&[8, 8], plane.dequantized_block(x, y).unwrap().as_flattened())?
+                    } else {
+                        write_npy(&[8, 8], quantized.as_flattened())?
+                    }
+                }
+                (_, block) => {
+                    let json = coefficients_json(plane, channel, block, dequantize)?;
+                    let mut text = serde_json::to_string_pretty(&json)?;
+                    text.push('\n');
+                    text.into_bytes()
+                }
+            };
+
+            match sub_matches.get_one::<String>("output") {
+                Some(output) => write_atomic(output, &bytes)?,
+                None => io::stdout().write_all(&bytes)?,
+            }
+        }
+
         Some(("compare", sub_matches)) => {
             let original = sub_matches.get_one::<String>("original").unwrap();
             let icf_file = sub_matches.get_one::<String>("icf").unwrap();
@@ -160,7 +598,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             
             // Decode and compare dimensions
             let decoded = codec.decode(&compressed)?;
-            let original_img = image::open(original)?;
+            let original_img = decode_input(original.as_str())?;
             
             println!("  Original dimensions: {}x{}", original_img.width(), original_img.height());
             println!("  Decoded dimensions: {}x{}", decoded.width(), decoded.height());
@@ -169,7 +607,22 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("  ✓ Dimensions match");
             } else {
                 println!("  ⚠ Dimension mismatch");
+                return Ok(());
             }
+
+            let weights = if sub_matches.get_flag("weighted") {
+                sub_matches.get_one::<String>("weights").unwrap().parse()?
+            } else {
+                WeightedPsnr::UNWEIGHTED
+            };
+            let (mut original_rgb, mut decoded_rgb) = (original_img.to_rgb8(), decoded.to_rgb8());
+            if let Some(kind) = sub_matches.get_one::<String>("cvd") {
+                let kind: CvdKind = kind.parse()?;
+                original_rgb = simulate_cvd(&original_rgb, kind);
+                decoded_rgb = simulate_cvd(&decoded_rgb, kind);
+                println!("  Simulated: {} color vision", kind);
+            }
+            println!("  {}", weights.compare(&original_rgb, &decoded_rgb)?);
         }
         
         _ => {
@@ -181,8 +634,80 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn print_layout(regions: &[LayoutRegion]) {
+    println!("  Layout:");
+    for region in regions {
+        println!("    {:<14} {:>10}..{:<10} {:>10} bytes  crc32 {:08x}",
+            region.name, region.offset, region.end(), region.length, region.crc32);
+    }
+}
+
+fn dump_region(data: &[u8], region: &LayoutRegion, hex: bool) -> io::Result<()> {
+    let bytes = &data[region.range()];
+    if hex {
+        print!("{}", hexdump(bytes, region.offset));
+        Ok(())
+    } else {
+        io::stdout().write_all(bytes)
+    }
+}
+
+/// Parse an "X,Y" block position
+fn parse_block(block: &str) -> Result<(usize, usize), String> {
+    let (x, y) = block.split_once(',').ok_or_else(|| format!("Block '{}' is not X,Y", block))?;
+    let parse = |value: &str| value.trim().parse::<usize>().map_err(|_| format!("Block '{}' is not X,Y", block));
+    Ok((parse(x)?, parse(y)?))
+}
+
+fn block_error(x: usize, y: usize, plane: &CoefficientPlane) -> String {
+    format!("Block {},{} lies outside the {}x{} block grid", x, y, plane.blocks_x, plane.blocks_y)
+}
+
+/// One block, or the whole plane as rows, of quantized or dequantized coefficients
+fn coefficients_json(
+    plane: &CoefficientPlane,
+    channel: usize,
+    block: Option<(usize, usize)>,
+    dequantize: bool,
+) -> Result<serde_json::Value, String> {
+    let Some((x, y)) = block else {
+        let rows: Vec<serde_json::Value> = if dequantize {
+            plane.dequantized().chunks(plane.width().max(1)).map(|row| row.into()).collect()
+        } else {
+            plane.quantized.chunks(plane.width().max(1)).map(|row| row.into()).collect()
+        };
+        return Ok(serde_json::json!({
+            "channel": channel,
+            "blocks_x": plane.blocks_x,
+            "blocks_y": plane.blocks_y,
+            "dequantized": dequantize,
+            "quantization_table": plane.quantization_table,
+            "coefficients": rows,
+        }));
+    };
+
+    let values = if dequantize {
+        plane.dequantized_block(x, y).map(|block| serde_json::json!(block))
+    } else {
+        plane.block(x, y).map(|block| serde_json::json!(block))
+    };
+    Ok(serde_json::json!({
+        "channel": channel,
+        "block": [x, y],
+        "dequantized": dequantize,
+        "coefficients": values.ok_or_else(|| block_error(x, y, plane))?,
+    }))
+}
+
 // Usage examples:
 // icf-cli encode input.jpg output.icf --quality 85
+// icf-cli encode input.jpg output.icf --strip
+// icf-cli encode input.png output.icf --profile screenshot --quality 80
+// icf-cli profiles
 // icf-cli decode output.icf decoded.png
 // icf-cli info output.icf
-// icf-cli compare input.jpg output.icf
\ No newline at end of file
+// icf-cli info output.icf --layout --json
+// icf-cli dump output.icf --section header --hex
+// icf-cli dump-coefficients output.icf --channel 0 --block 12,7 --format json
+// icf-cli dump-coefficients output.icf --format npy -o luma.npy
+// icf-cli compare input.jpg output.icf
@@ -1,7 +1,8 @@
 use clap::{Arg, Command};
+use codec_cdn_rust::codecs::atomic::write_atomic;
 use codec_cdn_rust::codecs::text::SimpleTcfCodec;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("simple-tcf")
@@ -57,7 +58,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Encoding {} characters...", text.len());
             
             let compressed = SimpleTcfCodec::encode(&text)?;
-            fs::write(output, &compressed)?;
+            write_atomic(output, &compressed)?;
             
             let stats = SimpleTcfCodec::get_stats(&text, &compressed);
             println!("✓ Encoding complete!");
@@ -68,19 +69,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let input = sub_matches.get_one::<String>("input").unwrap();
             let output = sub_matches.get_one::<String>("output").unwrap();
             
+            // Status goes to stderr when stdout carries the decoded text
+            let status = |line: String| if output == "-" { eprintln!("{}", line) } else { println!("{}", line) };
+
             let compressed = fs::read(input)?;
-            println!("Decoding {} bytes...", compressed.len());
+            status(format!("Decoding {} bytes...", compressed.len()));
             
             let text = SimpleTcfCodec::decode(&compressed)?;
             
+            // Raw bytes, so NUL and other control characters pass through untouched
             if output == "-" {
-                print!("{}", text);
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
             } else {
-                fs::write(output, &text)?;
+                write_atomic(output, text.as_bytes())?;
             }
             
-            println!("✓ Decoding complete!");
-            println!("  Decoded {} characters", text.len());
+            status("✓ Decoding complete!".to_string());
+            status(format!("  Decoded {} characters", text.len()));
         }
         
         _ => {
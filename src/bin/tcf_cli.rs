@@ -1,13 +1,29 @@
 use clap::{Arg, Command};
-use codec_cdn_rust::codecs::text::TcfCodec;
+use codec_cdn_rust::codecs::atomic::write_atomic;
+use codec_cdn_rust::codecs::cli_common::{init_tracing, OutputOptions, RuntimeConfig};
+use codec_cdn_rust::codecs::explain::Decision;
+use codec_cdn_rust::codecs::layout::{hexdump, LayoutRegion};
+use codec_cdn_rust::codecs::text::{
+    migrate, migrate_tree, ChunkStrategy, ChunkType, DictionaryDir, MigrationOutcome, SourceEncoding, TcfArchiveReader,
+    TcfArchiveWriter, TcfCodec, TcfEncodeOptions, TcfIndex, TcfMethod, TcfReader, MAX_MODEL_ORDER, TOKENIZER_IDS,
+};
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::Path;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = Command::new("tcf-cli")
         .version("1.0")
         .about("Text Codec Format (TCF) CLI tool with advanced arithmetic coding")
+        .arg(
+            Arg::new("verbose")
+                .help("Print codec phase spans and sizes to stderr (needs the 'tracing' feature)")
+                .long("verbose")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+        )
+        .args(RuntimeConfig::args())
         .subcommand(
             Command::new("encode")
                 .about("Encode text file to TCF format")
@@ -23,6 +39,88 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .arg(
+                    Arg::new("method")
+                        .help("Payload coding; 'auto' keeps the smallest of the available methods, trying front-coding when lines are sorted")
+                        .long("method")
+                        .value_name("METHOD")
+                        .value_parser(["arithmetic", "gzip", "zstd", "stored", "front-coding", "auto"])
+                        .default_value("arithmetic")
+                )
+                .arg(
+                    Arg::new("tokenizer")
+                        .help("How the arithmetic method splits text before modelling it")
+                        .long("tokenizer")
+                        .value_name("TOKENIZER")
+                        .value_parser(clap::builder::PossibleValuesParser::new(TOKENIZER_IDS))
+                        .default_value("byte")
+                )
+                .arg(
+                    Arg::new("max-order")
+                        .help("Code arithmetic payloads with an adaptive context model of up to N bytes instead of a stored order-0 model")
+                        .long("max-order")
+                        .value_name("N")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(u8).range(0..=MAX_MODEL_ORDER as i64))
+                )
+                .arg(
+                    Arg::new("force")
+                        .help("Replace an existing output, and code the input with --method even if it looks incompressible")
+                        .long("force")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .args(OutputOptions::args().into_iter().filter(|arg| arg.get_id() != "force"))
+                .arg(
+                    Arg::new("chunk-on")
+                        .help("Code the text as independent chunks ending at these record boundaries")
+                        .long("chunk-on")
+                        .value_name("BOUNDARY")
+                        .value_parser(["bytes", "newline", "blank-line"])
+                )
+                .arg(
+                    Arg::new("chunk-max")
+                        .help("Largest chunk, in bytes with an optional k or m suffix (default: 256k)")
+                        .long("chunk-max")
+                        .value_name("SIZE")
+                        .requires("chunk-on")
+                        .default_value("256k")
+                )
+                .arg(
+                    Arg::new("newlines")
+                        .help("'normalize' codes CRLFs as LFs and records where they were, so decoding restores them")
+                        .long("newlines")
+                        .value_name("POLICY")
+                        .value_parser(["preserve", "normalize"])
+                        .default_value("preserve")
+                        .conflicts_with("chunk-on")
+                )
+                .arg(
+                    Arg::new("source-encoding")
+                        .help("Encoding of the input; other than UTF-8, it's converted to UTF-8 and recorded so decode --restore-encoding gives back the original bytes. 'detect' guesses it")
+                        .long("source-encoding")
+                        .value_name("ENCODING")
+                        .value_parser(["utf8", "utf16le", "utf16be", "latin1", "detect"])
+                        .default_value("utf8")
+                )
+                .arg(
+                    Arg::new("pad")
+                        .help("Pad the file with zeros to hide its exact size: 'pow2', or comma-separated bucket sizes in bytes")
+                        .long("pad")
+                        .value_name("POLICY")
+                        .default_value("none")
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Print the result, warnings included, as JSON")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("explain-json")
+                        .help("Write the method and chunk decisions the encode made to FILE as JSON")
+                        .long("explain-json")
+                        .value_name("FILE")
+                )
         )
         .subcommand(
             Command::new("decode")
@@ -39,6 +137,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .arg(
+                    Arg::new("range")
+                        .help("Decode only bytes START..END of the text, using FILE.tcfx as the seek index when present")
+                        .long("range")
+                        .value_name("START..END")
+                )
+                .arg(
+                    Arg::new("dict-dir")
+                        .help("Directory of dictionaries named by their 16 hex digit hash, for files that don't carry theirs")
+                        .long("dict-dir")
+                        .value_name("DIR")
+                )
+                .arg(
+                    Arg::new("restore-encoding")
+                        .help("Write the text in the encoding it was read from, byte order mark included, rather than as UTF-8")
+                        .long("restore-encoding")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("range")
+                )
+                .args(OutputOptions::args())
+        )
+        .subcommand(
+            Command::new("index")
+                .about("Write a .tcfx seek index next to a chunked TCF file")
+                .arg(
+                    Arg::new("input")
+                        .help("Input TCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
         )
         .subcommand(
             Command::new("info")
@@ -49,31 +177,253 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(true)
                         .value_name("FILE")
                 )
+                .arg(
+                    Arg::new("layout")
+                        .help("Show the byte range of every region of the file")
+                        .long("layout")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Print the layout as JSON")
+                        .long("json")
+                        .requires("layout")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("chunks")
+                        .help("List every chunk with its method and compression ratio")
+                        .long("chunks")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Convert a legacy TCF1 file to the current format, or every one under a directory in place")
+                .arg(
+                    Arg::new("input")
+                        .help("TCF1 file, or a directory to migrate in place")
+                        .required(true)
+                        .value_name("PATH")
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Output TCF file; not taken with a directory")
+                        .value_name("FILE")
+                )
+                .args(OutputOptions::args())
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("Pack many small text files into one solid TCF archive, or extract one")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("Archive every file under a directory")
+                        .arg(
+                            Arg::new("output")
+                                .help("Output archive (.tcfa)")
+                                .required(true)
+                                .value_name("FILE")
+                        )
+                        .arg(
+                            Arg::new("dir")
+                                .help("Directory to archive; files are named by their path under it")
+                                .required(true)
+                                .value_name("DIR")
+                        )
+                        .arg(
+                            Arg::new("method")
+                                .help("Coding of each shared chunk; 'auto' keeps the smallest")
+                                .long("method")
+                                .value_name("METHOD")
+                                .value_parser(["arithmetic", "gzip", "zstd", "stored", "auto"])
+                                .default_value("auto")
+                        )
+                        .arg(
+                            Arg::new("chunk-max")
+                                .help("Size of the shared chunks, in bytes with an optional k or m suffix")
+                                .long("chunk-max")
+                                .value_name("SIZE")
+                                .default_value("256k")
+                        )
+                        .args(OutputOptions::args())
+                )
+                .subcommand(
+                    Command::new("extract")
+                        .about("Extract one file from an archive")
+                        .arg(
+                            Arg::new("input")
+                                .help("Input archive")
+                                .required(true)
+                                .value_name("FILE")
+                        )
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the file, as listed by 'info'")
+                                .required(true)
+                                .value_name("NAME")
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .help("Output file (default: stdout)")
+                                .long("output")
+                                .short('o')
+                                .value_name("FILE")
+                                .default_value("-")
+                        )
+                        .args(OutputOptions::args())
+                )
+        )
+        .subcommand(
+            Command::new("model-stats")
+                .about("Replay the text through a context model and report what each order learned")
+                .arg(
+                    Arg::new("input")
+                        .help("Input TCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("max-order")
+                        .help("Longest context to replay, in bytes")
+                        .long("max-order")
+                        .value_name("N")
+                        .default_value("3")
+                        .value_parser(clap::value_parser!(usize))
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Print the statistics as JSON")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Dump the bytes of one region of a TCF file")
+                .arg(
+                    Arg::new("input")
+                        .help("Input TCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("section")
+                        .help("Region name as listed by 'info --layout' (e.g. header, model, payload)")
+                        .long("section")
+                        .required(true)
+                        .value_name("NAME")
+                )
+                .arg(
+                    Arg::new("hex")
+                        .help("Print a hexdump instead of raw bytes")
+                        .long("hex")
+                        .action(clap::ArgAction::SetTrue)
+                )
         )
         .get_matches();
+    init_tracing(matches.get_flag("verbose"));
+    let runtime = RuntimeConfig::from_matches(&matches)?;
 
     match matches.subcommand() {
         Some(("encode", sub_matches)) => {
             let input = sub_matches.get_one::<String>("input").unwrap();
             let output = sub_matches.get_one::<String>("output").unwrap();
+            let output_options = OutputOptions::from_matches(sub_matches, false);
+            output_options.check(output)?;
             
-            let text = if input == "-" {
-                let mut buffer = String::new();
-                io::stdin().read_to_string(&mut buffer)?;
+            let data = if input == "-" {
+                let mut buffer = Vec::new();
+                io::stdin().read_to_end(&mut buffer)?;
                 buffer
             } else {
-                fs::read_to_string(input)?
+                fs::read(input)?
             };
-            
-            println!("Encoding {} characters...", text.len());
-            
-            let compressed = TcfCodec::encode(&text)?;
-            fs::write(output, &compressed)?;
+
+            // Catch inputs that would only grow before spending time on them
+            let hint = TcfCodec::estimate_compressibility(&data);
+            let source_encoding: SourceEncoding = sub_matches.get_one::<String>("source-encoding").unwrap().parse()?;
+            let (text, source) = source_encoding.decode(&data)
+                .map_err(|err| format!("{}: {:#} ({}); TCF only encodes text", input, err, hint))?;
+
+            let method = sub_matches.get_one::<String>("method").unwrap();
+            let mut method = if method == "auto" { None } else { Some(method.parse()?) };
+            let mut fallback = None;
+            if hint.is_incompressible() {
+                eprintln!("⚠ {} looks {}; coding it will not make it smaller", input, hint);
+                if !sub_matches.get_flag("force") {
+                    eprintln!("  Storing it uncompressed (use --force to code it anyway)");
+                    method = Some(TcfMethod::Stored);
+                    fallback = Some(format!("input looks {}; --force codes it anyway", hint));
+                }
+            }
+            let chunk_max = parse_size(sub_matches.get_one::<String>("chunk-max").unwrap())?;
+            let chunking = sub_matches.get_one::<String>("chunk-on").map(|boundary| match boundary.as_str() {
+                "newline" => ChunkStrategy::OnDelimiter(b'\n', chunk_max),
+                "blank-line" => ChunkStrategy::OnBlankLine(chunk_max),
+                _ => ChunkStrategy::FixedBytes(chunk_max),
+            });
+            let options = TcfEncodeOptions {
+                method,
+                tokenizer_id: sub_matches.get_one::<String>("tokenizer").unwrap().clone(),
+                chunking,
+                newline: sub_matches.get_one::<String>("newlines").unwrap().parse()?,
+                pad_to: sub_matches.get_one::<String>("pad").unwrap().parse()?,
+                max_order: *sub_matches.get_one::<u8>("max-order").unwrap(),
+                ..Default::default()
+            };
+
+            let json = sub_matches.get_flag("json");
+            if !json {
+                println!("Encoding {} characters...", text.len());
+            }
+
+            let (encoded, mut trace) = TcfCodec::encode_with_trace(&text, &options)?;
+            if !json {
+                for warning in &encoded.warnings {
+                    eprintln!("note: {}", warning);
+                }
+            }
+            let compressed = TcfCodec::record_source(&encoded.data, source, &options.pad_to)?;
+            if !output_options.write(input, output, &compressed)? {
+                return Ok(());
+            }
+            if let Some(path) = sub_matches.get_one::<String>("explain-json") {
+                if let Some(reason) = fallback {
+                    trace.decisions.insert(0, Decision::Chosen { step: "cli.fallback", option: TcfMethod::Stored.to_string(), reason });
+                }
+                write_atomic(path, format!("{}\n", trace.to_json()?).as_bytes())?;
+            }
             
             let stats = TcfCodec::get_stats(&text, &compressed);
+            let header = TcfCodec::parse_header(&compressed)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+                    "input_size": text.len(),
+                    "output_size": compressed.len(),
+                    "padding": stats.padding_size,
+                    "method": header.compression_method,
+                    "chunks": header.chunks.len(),
+                    "source_encoding": header.source_encoding,
+                    "compression_ratio": stats.compression_ratio,
+                    "warnings": encoded.warnings,
+                }))?);
+                return Ok(());
+            }
             println!("✓ Encoding complete!");
             println!("  Input: {} bytes", text.as_bytes().len());
             println!("  Output: {} bytes", compressed.len());
+            if stats.padding_size > 0 {
+                println!("  Padded from: {} bytes ({})", stats.logical_size(), options.pad_to);
+            }
+            println!("  Method: {}", header.compression_method);
+            if !source.is_plain_utf8() {
+                println!("  Source encoding: {}{}", source.encoding, if source.bom { " with BOM" } else { "" });
+            }
+            if chunking.is_some() {
+                println!("  Chunks: {}", header.chunks.len());
+            }
             println!("  Compression ratio: {:.2}:1", stats.compression_ratio);
             println!("  Space savings: {:.2}%", stats.savings_percent);
         }
@@ -82,19 +432,48 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let input = sub_matches.get_one::<String>("input").unwrap();
             let output = sub_matches.get_one::<String>("output").unwrap();
             
+            let output_options = OutputOptions::from_matches(sub_matches, false);
+            output_options.check(output)?;
+            // Status goes to stderr when stdout carries the decoded text
+            let status = |line: String| if output == "-" { eprintln!("{}", line) } else { println!("{}", line) };
+
             let compressed = fs::read(input)?;
-            println!("Decoding {} bytes...", compressed.len());
+            if let Some(range) = sub_matches.get_one::<String>("range") {
+                let range = parse_range(range)?;
+                let bytes = decode_range(input, &compressed, range.clone())?;
+                if output_options.write(input, output, &bytes)? {
+                    status(format!("✓ Decoded bytes {}..{}", range.start, range.end));
+                }
+                return Ok(());
+            }
+            status(format!("Decoding {} bytes...", compressed.len()));
             
-            let text = TcfCodec::decode(&compressed)?;
+            let text = match sub_matches.get_one::<String>("dict-dir") {
+                Some(dir) => {
+                    runtime.check_memory("TCF decode", TcfCodec::parse_header(&compressed)?.original_size)?;
+                    TcfCodec::decode_with_resolver(&compressed, &DictionaryDir::new(dir))?
+                }
+                None => TcfCodec::decode_with_runtime(&compressed, &runtime)?,
+            };
             
-            if output == "-" {
-                print!("{}", text);
-            } else {
-                fs::write(output, &text)?;
+            let source = TcfCodec::parse_header(&compressed)?.source_encoding;
+            let restore = sub_matches.get_flag("restore-encoding");
+            let decoded_len = text.len();
+            let bytes = match source {
+                Some(source) if restore => source.encode(&text)?,
+                _ => text.into_bytes(),
+            };
+            
+            // Raw bytes, so NUL and other control characters pass through untouched
+            if !output_options.write(input, output, &bytes)? {
+                return Ok(());
             }
             
-            println!("✓ Decoding complete!");
-            println!("  Decoded {} characters", text.len());
+            status("✓ Decoding complete!".to_string());
+            status(format!("  Decoded {} characters", decoded_len));
+            if let Some(source) = source.filter(|_| !restore) {
+                status(format!("  Written as UTF-8; the source was {} (use --restore-encoding for its bytes)", source.encoding));
+            }
         }
         
         Some(("info", sub_matches)) => {
@@ -102,6 +481,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             
             let compressed = fs::read(input)?;
             let header = TcfCodec::parse_header(&compressed)?;
+
+            if sub_matches.get_flag("json") {
+                let layout = TcfCodec::parse_layout(&compressed)?;
+                println!("{}", serde_json::to_string_pretty(&layout)?);
+                return Ok(());
+            }
             
             println!("TCF File Information:");
             println!("  Magic: {}", header.magic);
@@ -109,13 +494,189 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("  Original size: {} bytes", header.original_size);
             println!("  Compressed size: {} bytes", header.compressed_size);
             println!("  Compression method: {}", header.compression_method);
+            println!("  Tokenizer: {}", header.model_params.tokenizer_id);
+            if header.model_params.max_order > 0 {
+                println!("  Context model: orders 0..={}", header.model_params.max_order);
+            }
             println!("  Model size: {} bytes", header.model_size);
+            if let Some(strategy) = header.chunking {
+                let references = header.chunks.iter().filter(|chunk| matches!(chunk.chunk_type, ChunkType::Reference(_))).count();
+                println!("  Chunks: {} ({:?}, {} repeating an earlier chunk)", header.chunks.len(), strategy, references);
+                let mut methods: BTreeMap<&str, usize> = BTreeMap::new();
+                for chunk in header.chunks.iter().filter(|chunk| chunk.chunk_type == ChunkType::Data) {
+                    *methods.entry(chunk.compression_method.as_str()).or_default() += 1;
+                }
+                let methods: Vec<String> = methods.iter().map(|(method, count)| format!("{} {}", method, count)).collect();
+                println!("  Chunk methods: {}", methods.join(", "));
+            }
+            if let Some(record) = &header.dictionary {
+                let embedded = header.chunks.iter().any(|chunk| chunk.chunk_type == ChunkType::Dictionary);
+                println!("  Dictionary: {}, {}", record.hash, if embedded { "embedded" } else { "not embedded" });
+            }
+            if let Some(newlines) = &header.newlines {
+                println!("  Newlines: normalized, {} CRs restored from a {} byte record",
+                    header.original_size.saturating_sub(newlines.coded_size), newlines.record_size);
+            }
+            if header.padding > 0 {
+                println!("  Padding: {} bytes", header.padding);
+            }
+            if let Some(source) = &header.source_encoding {
+                println!("  Source encoding: {}{}", source.encoding, if source.bom { " with BOM" } else { "" });
+            }
             println!("  Checksum: {}", header.checksum);
+            if let Some(record) = &header.migrated_from {
+                println!("  Migrated from: {} v{} (checksum {})", record.format, record.version, record.checksum);
+            }
             
             let compression_ratio = header.original_size as f64 / compressed.len() as f64;
-            let savings = ((header.original_size - compressed.len() as u64) as f64 / header.original_size as f64) * 100.0;
+            let savings = ((header.original_size as f64 - compressed.len() as f64) / header.original_size as f64) * 100.0;
             println!("  Compression ratio: {:.2}:1", compression_ratio);
             println!("  Space savings: {:.2}%", savings);
+
+            if header.chunks.iter().any(|chunk| chunk.chunk_type == ChunkType::Metadata) {
+                let archive = TcfArchiveReader::new(&compressed)?;
+                println!();
+                println!("  Archive: {} files", archive.entries().len());
+                println!("{:>12} {:>10}  Name", "Offset", "Size");
+                for entry in archive.entries() {
+                    println!("{:>12} {:>10}  {}", entry.offset, entry.length, entry.name);
+                }
+            }
+
+            if sub_matches.get_flag("chunks") {
+                println!();
+                println!("{:>6} {:>12} {:>10} {:>10} {:<12} {:>7}", "Chunk", "Text offset", "Size", "Stored", "Method", "Ratio");
+                for (index, chunk) in header.chunks.iter().enumerate() {
+                    let (method, ratio) = match (chunk.chunk_type, chunk.compression_ratio()) {
+                        (ChunkType::Reference(original), _) => (format!("= chunk {}", original), "-".to_string()),
+                        (ChunkType::Metadata, _) => (format!("{} table", chunk.compression_method), "-".to_string()),
+                        (_, ratio) => (chunk.compression_method.clone(), ratio.map_or("-".to_string(), |ratio| format!("{:.2}:1", ratio))),
+                    };
+                    println!("{:>6} {:>12} {:>10} {:>10} {:<12} {:>7}", index, chunk.text_offset, chunk.original_size,
+                        chunk.model_size as u64 + chunk.compressed_size, method, ratio);
+                }
+            }
+
+            if sub_matches.get_flag("layout") {
+                let layout = TcfCodec::parse_layout(&compressed)?;
+                print_layout(&layout.regions);
+            }
+        }
+
+        Some(("index", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let index = TcfIndex::build(input)?;
+            let sidecar = TcfIndex::sidecar_path(input);
+            index.write(&sidecar)?;
+            println!("✓ Indexed {} chunks of {} into {} ({} bytes)",
+                index.chunks().len(), input, sidecar.display(), fs::metadata(&sidecar)?.len());
+        }
+
+        Some(("migrate", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            if Path::new(input).is_dir() {
+                if sub_matches.contains_id("output") {
+                    return Err("Directories are migrated in place; leave out the output".into());
+                }
+                let summary = migrate_tree(Path::new(input))?;
+                for path in &summary.migrated {
+                    println!("✓ Migrated {}", path.display());
+                }
+                for path in &summary.skipped {
+                    println!("  {} is already current, skipped", path.display());
+                }
+                for (path, error) in &summary.failed {
+                    eprintln!("✗ {}: {}", path.display(), error);
+                }
+                println!("{}", summary);
+                if !summary.failed.is_empty() {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+
+            let output = sub_matches.get_one::<String>("output")
+                .ok_or("Give an output file, or a directory to migrate in place")?;
+            let output_options = OutputOptions::from_matches(sub_matches, false);
+            output_options.check(output)?;
+            let mut migrated = Vec::new();
+            match migrate(fs::File::open(input)?, &mut migrated)? {
+                MigrationOutcome::AlreadyCanonical => println!("{} is already current; nothing to migrate", input),
+                MigrationOutcome::Migrated { legacy_size, migrated_size } => {
+                    if output_options.write(input, output, &migrated)? {
+                        println!("✓ Migrated {} ({} bytes) to {} ({} bytes)", input, legacy_size, output, migrated_size);
+                    }
+                }
+            }
+        }
+
+        Some(("archive", sub_matches)) => match sub_matches.subcommand() {
+            Some(("create", sub_matches)) => {
+                let output = sub_matches.get_one::<String>("output").unwrap();
+                let dir = sub_matches.get_one::<String>("dir").unwrap();
+                let output_options = OutputOptions::from_matches(sub_matches, false);
+                output_options.check(output)?;
+
+                let method = sub_matches.get_one::<String>("method").unwrap();
+                let chunk_max = parse_size(sub_matches.get_one::<String>("chunk-max").unwrap())?;
+                let mut writer = TcfArchiveWriter::new(TcfEncodeOptions {
+                    method: if method == "auto" { None } else { Some(method.parse()?) },
+                    chunking: Some(ChunkStrategy::FixedBytes(chunk_max)),
+                    ..Default::default()
+                });
+                let mut total = 0;
+                for (name, path) in archive_files(Path::new(dir))? {
+                    let data = fs::read(&path)?;
+                    let text = String::from_utf8(data)
+                        .map_err(|_| format!("{} is not UTF-8 text; TCF archives only hold text", path.display()))?;
+                    total += text.len();
+                    writer.add(name, &text)?;
+                }
+                let count = writer.len();
+                let encoded = writer.finish()?;
+                if output_options.write(dir, output, &encoded.data)? {
+                    println!("✓ Archived {} files ({} bytes) into {} ({} bytes)", count, total, output, encoded.data.len());
+                }
+            }
+            Some(("extract", sub_matches)) => {
+                let input = sub_matches.get_one::<String>("input").unwrap();
+                let name = sub_matches.get_one::<String>("name").unwrap();
+                let output = sub_matches.get_one::<String>("output").unwrap();
+                let output_options = OutputOptions::from_matches(sub_matches, false);
+                output_options.check(output)?;
+
+                let archive = fs::read(input)?;
+                let text = TcfArchiveReader::new(&archive)?.read(name)?;
+                if output_options.write(input, output, text.as_bytes())? && output != "-" {
+                    println!("✓ Extracted {} ({} bytes) to {}", name, text.len(), output);
+                }
+            }
+            _ => unreachable!("archive needs a subcommand"),
+        },
+
+        Some(("model-stats", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let max_order = *sub_matches.get_one::<usize>("max-order").unwrap();
+
+            let compressed = fs::read(input)?;
+            let stats = TcfCodec::model_stats(&compressed, max_order)?;
+            if sub_matches.get_flag("json") {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                print!("{}", stats);
+            }
+        }
+
+        Some(("dump", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let section = sub_matches.get_one::<String>("section").unwrap();
+
+            let compressed = fs::read(input)?;
+            let layout = TcfCodec::parse_layout(&compressed)?;
+            let region = layout.region(section)
+                .ok_or_else(|| format!("No '{}' section in {}", section, input))?;
+
+            dump_region(&compressed, region, sub_matches.get_flag("hex"))?;
         }
         
         _ => {
@@ -127,7 +688,90 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Every file under `root`, named by its `/`-separated path below it, in
+/// sorted order
+fn archive_files(root: &Path) -> io::Result<Vec<(String, std::path::PathBuf)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let name = path.strip_prefix(root).unwrap_or(&path).components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((name, path));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Parse a byte count such as `4096`, `256k` or `1m`
+fn parse_size(value: &str) -> Result<usize, String> {
+    let lower = value.trim().to_ascii_lowercase();
+    let (digits, multiplier) = match lower.strip_suffix('k') {
+        Some(digits) => (digits, 1024),
+        None => match lower.strip_suffix('m') {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (lower.as_str(), 1),
+        },
+    };
+    digits.parse::<usize>()
+        .ok()
+        .and_then(|count| count.checked_mul(multiplier))
+        .filter(|&size| size > 0)
+        .ok_or_else(|| format!("Invalid size: {}", value))
+}
+
+/// Parse a byte range such as `100..200`
+fn parse_range(value: &str) -> Result<std::ops::Range<u64>, String> {
+    value.split_once("..")
+        .and_then(|(start, end)| Some(start.trim().parse().ok()?..end.trim().parse().ok()?))
+        .ok_or_else(|| format!("Range '{}' must be START..END", value))
+}
+
+/// Decode `range` of `input`, through its .tcfx sidecar if there is a
+/// current one
+fn decode_range(input: &str, compressed: &[u8], range: std::ops::Range<u64>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let sidecar = TcfIndex::sidecar_path(input);
+    if sidecar.exists() {
+        match TcfIndex::read(&sidecar).and_then(|index| TcfReader::with_index(compressed, &index)) {
+            Ok(mut reader) => return Ok(reader.read_range(range)?),
+            Err(error) => eprintln!("⚠ Ignoring {}: {}", sidecar.display(), error),
+        }
+    }
+    Ok(TcfCodec::decode_range(compressed, range)?)
+}
+
+fn print_layout(regions: &[LayoutRegion]) {
+    println!("  Layout:");
+    for region in regions {
+        println!("    {:<14} {:>10}..{:<10} {:>10} bytes  crc32 {:08x}",
+            region.name, region.offset, region.end(), region.length, region.crc32);
+    }
+}
+
+fn dump_region(data: &[u8], region: &LayoutRegion, hex: bool) -> io::Result<()> {
+    let bytes = &data[region.range()];
+    if hex {
+        print!("{}", hexdump(bytes, region.offset));
+        Ok(())
+    } else {
+        io::stdout().write_all(bytes)
+    }
+}
+
 // Usage examples:
 // echo "Hello, World!" | tcf-cli encode - hello.tcf
+// tcf-cli encode notes.txt notes.tcf --method gzip
 // tcf-cli decode hello.tcf -
-// tcf-cli info hello.tcf
\ No newline at end of file
+// tcf-cli index big.tcf
+// tcf-cli decode big.tcf - --range 1000..2000
+// tcf-cli info hello.tcf
+// tcf-cli info hello.tcf --layout --json
+// tcf-cli dump hello.tcf --section model --hex
@@ -0,0 +1,364 @@
+use clap::{Arg, Command};
+use codec_cdn_rust::codecs::atomic::write_atomic;
+use codec_cdn_rust::codecs::cli_common::{init_tracing, RuntimeConfig};
+use codec_cdn_rust::codecs::video::{
+    write_quality_csv, FilterChain, FpsConverter, FrameType, QualitySummary, RateControl, Scale,
+    ScaleMethod, TemporalDenoise, VcfCodec, Y4mReader,
+};
+use std::fs;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("vcf-cli")
+        .version("1.0")
+        .about("Video Codec Format (VCF) CLI tool with motion-compensated compression")
+        .arg(
+            Arg::new("verbose")
+                .help("Print codec phase spans and sizes to stderr (needs the 'tracing' feature)")
+                .long("verbose")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+        )
+        .args(RuntimeConfig::args())
+        .subcommand(
+            Command::new("encode")
+                .about("Encode Y4M video to VCF format")
+                .arg(
+                    Arg::new("input")
+                        .help("Input video file (.y4m, 4:2:0)")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Output VCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("quality")
+                        .help("Quality level (1-100, default: 85)")
+                        .short('q')
+                        .long("quality")
+                        .value_name("NUM")
+                        .default_value("85")
+                )
+                .arg(
+                    Arg::new("qp")
+                        .help("Code every frame at this quantizer instead of a quality (0-63)")
+                        .long("qp")
+                        .value_name("NUM")
+                        .value_parser(clap::value_parser!(u8).range(0..=VcfCodec::MAX_QP as i64))
+                        .conflicts_with("quality")
+                )
+                .arg(
+                    Arg::new("gop")
+                        .help("Frames between I-frames (default: 30)")
+                        .long("gop")
+                        .value_name("NUM")
+                        .default_value("30")
+                )
+                .arg(
+                    Arg::new("refs")
+                        .help("Reference frames P-frames may predict from (1-4, default: 1)")
+                        .long("refs")
+                        .value_name("NUM")
+                        .value_parser(clap::value_parser!(u8).range(1..=VcfCodec::MAX_REFERENCES as i64))
+                        .default_value("1")
+                )
+                .arg(
+                    Arg::new("fps-out")
+                        .help("Convert to this frame rate by dropping or repeating frames")
+                        .long("fps-out")
+                        .value_name("FPS")
+                        .value_parser(clap::value_parser!(f64))
+                )
+                .arg(
+                    Arg::new("denoise")
+                        .help("Temporal denoise; optional strength 0-1 (default: 0.5)")
+                        .long("denoise")
+                        .value_name("STRENGTH")
+                        .num_args(0..=1)
+                        .default_missing_value("0.5")
+                        .value_parser(clap::value_parser!(f64))
+                )
+                .arg(
+                    Arg::new("scale")
+                        .help("Resize frames to WIDTHxHEIGHT")
+                        .long("scale")
+                        .value_name("WxH")
+                )
+                .arg(
+                    Arg::new("scale-method")
+                        .help("Resampling for --scale")
+                        .long("scale-method")
+                        .value_name("METHOD")
+                        .value_parser(["bilinear", "nearest"])
+                        .default_value("bilinear")
+                        .requires("scale")
+                )
+        )
+        .subcommand(
+            Command::new("decode")
+                .about("Decode VCF file to Y4M video")
+                .arg(
+                    Arg::new("input")
+                        .help("Input VCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Output video file (.y4m)")
+                        .required(true)
+                        .value_name("FILE")
+                )
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Show information about VCF file")
+                .arg(
+                    Arg::new("input")
+                        .help("Input VCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("frames")
+                        .help("List every frame's type, size, QP and timestamp")
+                        .long("frames")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Report per-frame PSNR of an encode against its source")
+                .arg(
+                    Arg::new("original")
+                        .help("Original video file (.y4m)")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("vcf")
+                        .help("VCF compressed file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("csv")
+                        .help("Write the per-frame report as CSV")
+                        .long("csv")
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("worst")
+                        .help("Number of worst frames to list (default: 5)")
+                        .long("worst")
+                        .value_name("NUM")
+                        .default_value("5")
+                )
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Copy one frame out as a standalone ICF image")
+                .arg(
+                    Arg::new("input")
+                        .help("Input VCF file")
+                        .required(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("frame")
+                        .help("Index of the frame to extract")
+                        .long("frame")
+                        .value_name("N")
+                        .required(true)
+                        .value_parser(clap::value_parser!(usize))
+                )
+                .arg(
+                    Arg::new("as-icf")
+                        .help("Output ICF file; I-frames are remuxed, other frames re-encoded")
+                        .long("as-icf")
+                        .value_name("FILE")
+                        .required(true)
+                )
+        )
+        .get_matches();
+    init_tracing(matches.get_flag("verbose"));
+    let runtime = RuntimeConfig::from_matches(&matches)?;
+
+    match matches.subcommand() {
+        Some(("encode", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let output = sub_matches.get_one::<String>("output").unwrap();
+            let quality = sub_matches.get_one::<String>("quality").unwrap()
+                .parse::<u8>()
+                .map_err(|_| "Quality must be a number between 1 and 100")?;
+            let gop_size = sub_matches.get_one::<String>("gop").unwrap()
+                .parse::<u32>()
+                .map_err(|_| "GOP size must be a positive number")?;
+            let references = *sub_matches.get_one::<u8>("refs").unwrap();
+
+            if !(1..=100).contains(&quality) {
+                return Err("Quality must be between 1 and 100".into());
+            }
+            let rate_control = match sub_matches.get_one::<u8>("qp") {
+                Some(&qp) => RateControl::ConstantQp(qp),
+                None => RateControl::ConstantQuality(quality),
+            };
+
+            let mut filters = FilterChain::new();
+            if let Some(&fps) = sub_matches.get_one::<f64>("fps-out") {
+                filters = filters.with(FpsConverter::new(fps));
+            }
+            if let Some(&strength) = sub_matches.get_one::<f64>("denoise") {
+                filters = filters.with(TemporalDenoise::new(strength));
+            }
+            if let Some(size) = sub_matches.get_one::<String>("scale") {
+                let (width, height) = size.split_once('x')
+                    .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                    .ok_or_else(|| format!("Scale '{}' must be WIDTHxHEIGHT", size))?;
+                let method = match sub_matches.get_one::<String>("scale-method").map(String::as_str) {
+                    Some("nearest") => ScaleMethod::Nearest,
+                    _ => ScaleMethod::Bilinear,
+                };
+                filters = filters.with(Scale::new(width, height, method));
+            }
+
+            println!("Encoding video: {} ({}, GOP: {}, refs: {})", input, rate_control, gop_size, references);
+            for filter in filters.describe() {
+                println!("  Filter: {}", filter);
+            }
+
+            let codec = VcfCodec::new().with_runtime(runtime.clone()).with_gop_size(gop_size).with_reference_count(references);
+            codec.encode_filtered(input, output, rate_control, filters)?;
+
+            let compressed = fs::read(output)?;
+            let (header, _) = codec.parse_container(&compressed)?;
+            println!("✓ Encoding complete!");
+            println!("  Frames: {} ({:.2}s)", header.frame_count, header.duration);
+            println!("  Input: {} bytes", header.original_size);
+            println!("  Output: {} bytes", compressed.len());
+            println!("  Compression ratio: {:.2}:1", header.original_size as f64 / compressed.len() as f64);
+        }
+
+        Some(("decode", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let output = sub_matches.get_one::<String>("output").unwrap();
+
+            let compressed = fs::read(input)?;
+            println!("Decoding VCF file: {} ({} bytes)", input, compressed.len());
+
+            let y4m = VcfCodec::new().with_runtime(runtime.clone()).decode(&compressed)?;
+            write_atomic(output, &y4m)?;
+
+            println!("✓ Decoding complete!");
+            println!("  Output: {} ({} bytes)", output, y4m.len());
+        }
+
+        Some(("info", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+
+            let compressed = fs::read(input)?;
+            let (header, _) = VcfCodec::new().parse_container(&compressed)?;
+            let i_frames = header.frames.iter()
+                .filter(|entry| entry.frame_type == FrameType::I)
+                .count();
+
+            println!("VCF File Information:");
+            println!("  Magic: {}", header.magic);
+            println!("  Version: {}", header.version);
+            println!("  Dimensions: {}x{}", header.width, header.height);
+            println!("  Frame rate: {:.3} fps", header.fps);
+            println!("  Frames: {} ({} I, {} P)", header.frame_count, i_frames, header.frame_count as usize - i_frames);
+            println!("  Duration: {:.2}s", header.duration);
+            let timebase = header.timebase();
+            println!("  Timebase: {}/{} s", timebase.num, timebase.den);
+            println!("  Rate control: {}", header.rate_control());
+            if !header.qp_histogram.is_empty() {
+                let counts: Vec<String> = header.qp_histogram.iter()
+                    .map(|(qp, frames)| format!("{} ({} frames)", qp, frames))
+                    .collect();
+                println!("  QP histogram: {}", counts.join(", "));
+            }
+            println!("  GOP size: {}", header.gop_size);
+            println!("  Reference frames: {}", header.reference_count);
+            for filter in &header.filters {
+                println!("  Filter: {}", filter);
+            }
+            println!("  Original size: {} bytes", header.original_size);
+            println!("  File size: {} bytes", compressed.len());
+            println!("  Checksum: {}", header.checksum);
+            if sub_matches.get_flag("frames") {
+                println!();
+                header.write_frame_table(std::io::stdout().lock())?;
+            }
+        }
+
+        Some(("analyze", sub_matches)) => {
+            let original = sub_matches.get_one::<String>("original").unwrap();
+            let vcf_file = sub_matches.get_one::<String>("vcf").unwrap();
+            let worst = sub_matches.get_one::<String>("worst").unwrap()
+                .parse::<usize>()
+                .map_err(|_| "Worst frame count must be a number")?;
+
+            let compressed = fs::read(vcf_file)?;
+            let report = VcfCodec::new().with_runtime(runtime.clone()).quality_report(Y4mReader::open(original)?, &compressed)?;
+
+            if let Some(csv_path) = sub_matches.get_one::<String>("csv") {
+                write_quality_csv(fs::File::create(csv_path)?, &report)?;
+                println!("✓ Wrote per-frame report to {}", csv_path);
+            }
+
+            match QualitySummary::from_frames(&report, worst) {
+                Some(summary) => {
+                    println!("Video Quality Analysis:");
+                    println!("  {}", summary);
+                    for index in &summary.worst_frames {
+                        let frame = &report[*index];
+                        println!("  Frame {:>5} ({:?}): {:.2} dB (Y {:.2}, U {:.2}, V {:.2})",
+                            index, frame.frame_type, frame.psnr, frame.psnr_y, frame.psnr_u, frame.psnr_v);
+                    }
+                }
+                None => println!("No frames to analyze"),
+            }
+        }
+
+        Some(("extract", sub_matches)) => {
+            let input = sub_matches.get_one::<String>("input").unwrap();
+            let frame = *sub_matches.get_one::<usize>("frame").unwrap();
+            let output = sub_matches.get_one::<String>("as-icf").unwrap();
+
+            let compressed = fs::read(input)?;
+            let codec = VcfCodec::new().with_runtime(runtime.clone());
+            let (header, _) = codec.parse_container(&compressed)?;
+            let entry = header.frames.get(frame)
+                .ok_or_else(|| format!("{} has {} frames, no frame {}", input, header.frames.len(), frame))?;
+            if entry.frame_type != FrameType::I || entry.qp_deltas {
+                eprintln!("Warning: frame {} is not a keyframe coded at one quantizer; re-encoding it", frame);
+            }
+
+            let icf = codec.extract_icf(&compressed, frame)?;
+            write_atomic(output, &icf)?;
+
+            println!("✓ Extracted frame {} ({:?}) to {} ({} bytes)", frame, entry.frame_type, output, icf.len());
+        }
+
+        _ => {
+            eprintln!("No subcommand provided. Use --help for usage information.");
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+// Usage examples:
+// vcf-cli encode input.y4m output.vcf --quality 85 --gop 30
+// vcf-cli encode input.y4m output.vcf --refs 2
+// vcf-cli encode input.y4m output.vcf --qp 30
+// vcf-cli encode input.y4m output.vcf --fps-out 24 --denoise --scale 640x360
+// vcf-cli decode output.vcf decoded.y4m
+// vcf-cli info output.vcf --frames
+// vcf-cli analyze input.y4m output.vcf --csv report.csv
+// vcf-cli extract output.vcf --frame 30 --as-icf still.icf
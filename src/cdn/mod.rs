@@ -0,0 +1,5 @@
+pub mod object_store;
+pub mod server;
+
+pub use object_store::*;
+pub use server::*;
@@ -0,0 +1,840 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use thiserror::Error;
+
+use crate::codecs::bencode::{HashAlgo, PieceDigests, PieceHasher};
+use crate::codecs::text::static_dictionary::{Dictionary, DictionaryResolver};
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Invalid content id: {0}")]
+    InvalidContentId(String),
+    #[error("Invalid variant parameters: {0}")]
+    InvalidVariant(String),
+    #[error("Base object not found: {0}")]
+    MissingBase(ContentId),
+    #[error("Piece length must be positive")]
+    InvalidPieceLength,
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+pub type StoreResult<T> = std::result::Result<T, StoreError>;
+
+/// Content address of a stored object: the lowercase hex SHA-256 of its bytes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ContentId(String);
+
+impl ContentId {
+    /// Compute the id of the given content
+    pub fn for_content(data: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        ContentId(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Parse an id from its hex form, rejecting anything that isn't a SHA-256 digest
+    ///
+    /// Ids become directory names, so this is also what keeps request paths
+    /// from escaping the store root.
+    pub fn parse(id: &str) -> StoreResult<Self> {
+        let valid = id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if !valid {
+            return Err(StoreError::InvalidContentId(id.to_string()));
+        }
+        Ok(ContentId(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ContentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Crop rectangle in source pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Transcoding parameters that distinguish a variant from its base object
+///
+/// Crop is applied before scaling. Unset fields inherit from the base.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VariantParams {
+    pub quality: Option<u8>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub crop: Option<CropRect>,
+}
+
+impl VariantParams {
+    /// Canonical serialization: fields in a fixed order, unset fields omitted
+    ///
+    /// e.g. `q85_w320_h240_c0.0.640.480`, or `orig` when nothing is set. The
+    /// form is filename-safe and doubles as the variant's on-disk name.
+    pub fn canonical(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(quality) = self.quality {
+            parts.push(format!("q{}", quality));
+        }
+        if let Some(width) = self.width {
+            parts.push(format!("w{}", width));
+        }
+        if let Some(height) = self.height {
+            parts.push(format!("h{}", height));
+        }
+        if let Some(crop) = self.crop {
+            parts.push(format!("c{}.{}.{}.{}", crop.x, crop.y, crop.width, crop.height));
+        }
+
+        if parts.is_empty() {
+            "orig".to_string()
+        } else {
+            parts.join("_")
+        }
+    }
+
+    /// Parse the canonical serialization back into parameters
+    ///
+    /// Only the exact canonical form is accepted, so every parameter set has
+    /// a single spelling.
+    pub fn from_canonical(canonical: &str) -> StoreResult<Self> {
+        let invalid = || StoreError::InvalidVariant(canonical.to_string());
+        let mut params = VariantParams::default();
+
+        if canonical != "orig" {
+            for part in canonical.split('_') {
+                let (tag, value) = part.split_at(part.len().min(1));
+                match tag {
+                    "q" => params.quality = Some(value.parse().map_err(|_| invalid())?),
+                    "w" => params.width = Some(value.parse().map_err(|_| invalid())?),
+                    "h" => params.height = Some(value.parse().map_err(|_| invalid())?),
+                    "c" => {
+                        let fields: Vec<u32> = value.split('.')
+                            .map(|field| field.parse().map_err(|_| invalid()))
+                            .collect::<StoreResult<_>>()?;
+                        if fields.len() != 4 {
+                            return Err(invalid());
+                        }
+                        params.crop = Some(CropRect {
+                            x: fields[0],
+                            y: fields[1],
+                            width: fields[2],
+                            height: fields[3],
+                        });
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+
+        if params.canonical() != canonical {
+            return Err(invalid());
+        }
+        Ok(params)
+    }
+}
+
+/// Identifies a derived object: the base it was transcoded from plus the parameters
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VariantKey {
+    pub base: ContentId,
+    pub params: VariantParams,
+}
+
+impl VariantKey {
+    pub fn new(base: ContentId, params: VariantParams) -> Self {
+        Self { base, params }
+    }
+
+    /// Canonical serialization of the whole key: `<base>/<params>`
+    pub fn canonical(&self) -> String {
+        format!("{}/{}", self.base, self.params.canonical())
+    }
+
+    /// Stable hex SHA-256 of the canonical key, usable as a cache key or ETag
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.canonical().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Object count and total size for one kind of stored object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct KindStats {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// Store-wide usage, split into base objects and derived variants
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StoreStats {
+    pub bases: KindStats,
+    pub variants: KindStats,
+}
+
+impl std::fmt::Display for StoreStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,
+            "Bases: {} objects ({} bytes), Variants: {} objects ({} bytes)",
+            self.bases.objects,
+            self.bases.bytes,
+            self.variants.objects,
+            self.variants.bytes
+        )
+    }
+}
+
+/// What the filesystem knows about a stored object, without reading its bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub modified: SystemTime,
+    /// Codec format recorded when the object was stored, if any
+    pub format: Option<String>,
+}
+
+/// Content-addressed object store on the local filesystem
+///
+/// Layout uses one directory per base object so that a base and everything
+/// derived from it can be removed with a single rename:
+///
+/// ```text
+/// <root>/objects/<id>/data                 base object bytes
+/// <root>/objects/<id>/format               codec format recorded at upload
+/// <root>/objects/<id>/pieces               piece hashes of a chunked upload
+/// <root>/objects/<id>/variants/<params>    derived variants
+/// <root>/trash/<id>.<n>                    purges in progress
+/// ```
+///
+/// All writes go to a temporary file that is renamed into place, so readers
+/// never observe partial objects.
+pub struct ObjectStore {
+    root: PathBuf,
+    payload_reads: AtomicU64,
+}
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl ObjectStore {
+    const OBJECTS_DIR: &'static str = "objects";
+    const TRASH_DIR: &'static str = "trash";
+    const DATA_FILE: &'static str = "data";
+    const FORMAT_FILE: &'static str = "format";
+    const PIECES_FILE: &'static str = "pieces";
+    const VARIANTS_DIR: &'static str = "variants";
+    const DICTIONARIES_DIR: &'static str = "dictionaries";
+    /// Format recorded for objects stored by `put_dictionary`
+    pub const DICTIONARY_FORMAT: &'static str = "tcf-dictionary";
+
+    /// Open (creating if needed) a store rooted at `root`
+    ///
+    /// Purges interrupted by a crash are completed here, which makes `purge`
+    /// idempotent across restarts.
+    pub fn open<P: AsRef<Path>>(root: P) -> StoreResult<Self> {
+        let store = Self { root: root.as_ref().to_path_buf(), payload_reads: AtomicU64::new(0) };
+        fs::create_dir_all(store.root.join(Self::OBJECTS_DIR))?;
+        fs::create_dir_all(store.root.join(Self::TRASH_DIR))?;
+
+        for entry in fs::read_dir(store.root.join(Self::TRASH_DIR))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Store a base object, returning its content id
+    pub fn put(&self, data: &[u8]) -> StoreResult<ContentId> {
+        self.put_with_format(data, None)
+    }
+
+    /// Store a base object along with the codec format it is in
+    ///
+    /// The format is written before the data, so it is there by the time
+    /// the object can be read. Objects stored without one keep none.
+    pub fn put_with_format(&self, data: &[u8], format: Option<&str>) -> StoreResult<ContentId> {
+        let id = ContentId::for_content(data);
+        let format_path = self.base_dir(&id).join(Self::FORMAT_FILE);
+        if let Some(format) = format.filter(|_| !format_path.exists()) {
+            fs::create_dir_all(self.base_dir(&id))?;
+            Self::write_file_atomic(&format_path, format.as_bytes())?;
+        }
+        let data_path = self.data_path(&id);
+        if !data_path.exists() {
+            fs::create_dir_all(self.base_dir(&id))?;
+            Self::write_file_atomic(&data_path, data)?;
+        }
+        Ok(id)
+    }
+
+    /// Store a base object streamed from `reader`, recording the SHA-256
+    /// of each `piece_length` piece so parts of it can be verified alone
+    ///
+    /// The bytes are spooled to a temporary file, never held in memory.
+    pub fn put_chunked<R: Read>(&self, reader: R, piece_length: u64) -> StoreResult<ContentId> {
+        if piece_length == 0 {
+            return Err(StoreError::InvalidPieceLength);
+        }
+        let temp_path = self.root.join(Self::OBJECTS_DIR).join(format!(
+            ".upload.tmp-{}-{}",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = (|| {
+            let mut file = fs::File::create(&temp_path)?;
+            let mut whole = Sha256::new();
+            let mut pieces = PieceHasher::new(piece_length, HashAlgo::Sha256);
+            pieces.hash_reader(Spool { inner: reader, file: &mut file, whole: &mut whole })?;
+            file.sync_all()?;
+            let id = ContentId(format!("{:x}", whole.finalize()));
+
+            fs::create_dir_all(self.base_dir(&id))?;
+            let pieces_path = self.base_dir(&id).join(Self::PIECES_FILE);
+            if !pieces_path.exists() {
+                Self::write_file_atomic(&pieces_path, &Self::encode_pieces(&pieces.finalize()))?;
+            }
+            let data_path = self.data_path(&id);
+            if data_path.exists() {
+                fs::remove_file(&temp_path)?;
+            } else {
+                fs::rename(&temp_path, &data_path)?;
+            }
+            Ok(id)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Piece hashes recorded by `put_chunked`; `None` for objects stored whole
+    pub fn pieces(&self, id: &ContentId) -> StoreResult<Option<PieceDigests>> {
+        let Some(data) = Self::read_optional(&self.base_dir(id).join(Self::PIECES_FILE))? else {
+            return Ok(None);
+        };
+        let invalid = || StoreError::IoError(io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt piece hashes for {}", id)));
+        let field = |range: std::ops::Range<usize>| data.get(range).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+        let (piece_length, total_length) = field(0..8).zip(field(8..16)).ok_or_else(invalid)?;
+        let digests = PieceDigests { algo: HashAlgo::Sha256, piece_length, total_length, bytes: data[16..].to_vec() };
+        let expected = if piece_length == 0 { None } else { Some(total_length.div_ceil(piece_length)) };
+        if expected != Some(digests.len() as u64) || !digests.bytes.len().is_multiple_of(HashAlgo::Sha256.digest_len()) {
+            return Err(invalid());
+        }
+        Ok(Some(digests))
+    }
+
+    /// Piece length and total length as u64 LE, then the digests
+    fn encode_pieces(digests: &PieceDigests) -> Vec<u8> {
+        let mut data = Vec::with_capacity(16 + digests.bytes.len());
+        data.extend_from_slice(&digests.piece_length.to_le_bytes());
+        data.extend_from_slice(&digests.total_length.to_le_bytes());
+        data.extend_from_slice(&digests.bytes);
+        data
+    }
+
+    /// Read a base object
+    pub fn get(&self, id: &ContentId) -> StoreResult<Option<Vec<u8>>> {
+        self.read_payload(&self.data_path(id))
+    }
+
+    /// Store a TCF dictionary for files coded without theirs to share
+    ///
+    /// Its content id is its `Dictionary::content_hash`. The files record
+    /// only the first 64 bits of that, its `Dictionary::short_hash`, so
+    /// that is indexed under `dictionaries/`. Should two dictionaries ever
+    /// share one, the second is refused while the first is stored.
+    pub fn put_dictionary(&self, dictionary: &Dictionary) -> StoreResult<ContentId> {
+        let (bytes, hash) = (dictionary.to_bytes(), dictionary.short_hash());
+        if let Some(existing) = self.dictionary_content_id(&hash)? {
+            if existing != ContentId::for_content(&bytes) && self.data_path(&existing).exists() {
+                return Err(StoreError::IoError(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Dictionary hash {} is taken by object {}", hash, existing),
+                )));
+            }
+        }
+        let id = self.put_with_format(&bytes, Some(Self::DICTIONARY_FORMAT))?;
+        fs::create_dir_all(self.root.join(Self::DICTIONARIES_DIR))?;
+        Self::write_file_atomic(&self.dictionary_index_path(&hash), id.as_str().as_bytes())?;
+        Ok(id)
+    }
+
+    /// Read a dictionary stored by `put_dictionary`
+    pub fn get_dictionary(&self, id: &ContentId) -> StoreResult<Option<Dictionary>> {
+        let Some(data) = self.get(id)? else {
+            return Ok(None);
+        };
+        let dictionary = Dictionary::from_bytes(&data).map_err(|e| {
+            StoreError::IoError(io::Error::new(io::ErrorKind::InvalidData, format!("Object {} is not a TCF dictionary: {:#}", id, e)))
+        })?;
+        Ok(Some(dictionary))
+    }
+
+    /// Size, modification time and format of a base object
+    pub fn info(&self, id: &ContentId) -> StoreResult<Option<ObjectInfo>> {
+        let Some((size, modified)) = Self::stat(&self.data_path(id))? else {
+            return Ok(None);
+        };
+        let format = Self::read_optional(&self.base_dir(id).join(Self::FORMAT_FILE))?
+            .map(|format| String::from_utf8_lossy(&format).into_owned());
+        Ok(Some(ObjectInfo { size, modified, format }))
+    }
+
+    /// Size and modification time of a stored variant
+    pub fn variant_info(&self, key: &VariantKey) -> StoreResult<Option<ObjectInfo>> {
+        Ok(Self::stat(&self.variant_path(key))?.map(|(size, modified)| ObjectInfo { size, modified, format: None }))
+    }
+
+    /// How many times object or variant bytes have been read since `open`
+    pub fn payload_reads(&self) -> u64 {
+        self.payload_reads.load(Ordering::Relaxed)
+    }
+
+    /// Read at most `limit` bytes from the start of a base object, with its full size
+    pub fn get_prefix(&self, id: &ContentId, limit: usize) -> StoreResult<Option<(Vec<u8>, u64)>> {
+        self.payload_reads.fetch_add(1, Ordering::Relaxed);
+        let file = match fs::File::open(self.data_path(id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata()?.len();
+        let mut prefix = Vec::with_capacity(limit.min(size as usize));
+        file.take(limit as u64).read_to_end(&mut prefix)?;
+        Ok(Some((prefix, size)))
+    }
+
+    /// Whether a base object exists
+    pub fn contains(&self, id: &ContentId) -> bool {
+        self.data_path(id).is_file()
+    }
+
+    /// Store a variant derived from an existing base object
+    pub fn put_variant(&self, key: &VariantKey, data: &[u8]) -> StoreResult<()> {
+        if !self.contains(&key.base) {
+            return Err(StoreError::MissingBase(key.base.clone()));
+        }
+
+        let variants_dir = self.base_dir(&key.base).join(Self::VARIANTS_DIR);
+        fs::create_dir_all(&variants_dir)?;
+        Self::write_file_atomic(&variants_dir.join(key.params.canonical()), data)
+    }
+
+    /// Read a variant
+    pub fn get_variant(&self, key: &VariantKey) -> StoreResult<Option<Vec<u8>>> {
+        self.read_payload(&self.variant_path(key))
+    }
+
+    /// List all variants of a base, ordered by their canonical parameters
+    pub fn list_variants(&self, base: &ContentId) -> StoreResult<Vec<VariantKey>> {
+        let variants_dir = self.base_dir(base).join(Self::VARIANTS_DIR);
+        let mut keys = Vec::new();
+
+        for name in Self::list_names(&variants_dir)? {
+            // Skip anything that isn't a canonical variant name (e.g. temp files)
+            if let Ok(params) = VariantParams::from_canonical(&name) {
+                keys.push(VariantKey::new(base.clone(), params));
+            }
+        }
+
+        keys.sort_by_key(|key| key.params.canonical());
+        Ok(keys)
+    }
+
+    /// Remove a base object and all of its variants
+    ///
+    /// The base directory is first renamed into the trash, which atomically
+    /// hides the base and every variant; the trash entry is then deleted. If
+    /// the process dies in between, `open` finishes the deletion. Returns
+    /// whether anything was removed.
+    pub fn purge(&self, base: &ContentId) -> StoreResult<bool> {
+        match self.move_to_trash(base)? {
+            Some(trash_path) => {
+                fs::remove_dir_all(trash_path)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Count objects and bytes by kind
+    pub fn stats(&self) -> StoreResult<StoreStats> {
+        let mut stats = StoreStats::default();
+
+        for name in Self::list_names(&self.root.join(Self::OBJECTS_DIR))? {
+            let base_dir = self.root.join(Self::OBJECTS_DIR).join(&name);
+            if let Ok(metadata) = fs::metadata(base_dir.join(Self::DATA_FILE)) {
+                stats.bases.objects += 1;
+                stats.bases.bytes += metadata.len();
+            }
+
+            let variants_dir = base_dir.join(Self::VARIANTS_DIR);
+            for variant in Self::list_names(&variants_dir)? {
+                if VariantParams::from_canonical(&variant).is_ok() {
+                    stats.variants.objects += 1;
+                    stats.variants.bytes += fs::metadata(variants_dir.join(variant))?.len();
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// First phase of a purge: rename the base directory into the trash
+    fn move_to_trash(&self, base: &ContentId) -> StoreResult<Option<PathBuf>> {
+        let trash_path = self.root.join(Self::TRASH_DIR).join(format!(
+            "{}.{}.{}",
+            base,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        match fs::rename(self.base_dir(base), &trash_path) {
+            Ok(()) => Ok(Some(trash_path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn base_dir(&self, id: &ContentId) -> PathBuf {
+        self.root.join(Self::OBJECTS_DIR).join(id.as_str())
+    }
+
+    fn data_path(&self, id: &ContentId) -> PathBuf {
+        self.base_dir(id).join(Self::DATA_FILE)
+    }
+
+    fn dictionary_index_path(&self, hash: &str) -> PathBuf {
+        self.root.join(Self::DICTIONARIES_DIR).join(hash)
+    }
+
+    /// Content id `put_dictionary` indexed under `Dictionary::short_hash`
+    /// `hash`; the object may since have been purged
+    fn dictionary_content_id(&self, hash: &str) -> StoreResult<Option<ContentId>> {
+        // Hashes come from file headers, so they mustn't name other paths
+        if !Dictionary::is_short_hash(hash) {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.dictionary_index_path(hash)) {
+            Ok(content_id) => Ok(Some(ContentId::parse(content_id.trim())?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn variant_path(&self, key: &VariantKey) -> PathBuf {
+        self.base_dir(&key.base).join(Self::VARIANTS_DIR).join(key.params.canonical())
+    }
+
+    fn read_payload(&self, path: &Path) -> StoreResult<Option<Vec<u8>>> {
+        self.payload_reads.fetch_add(1, Ordering::Relaxed);
+        Self::read_optional(path)
+    }
+
+    fn stat(path: &Path) -> StoreResult<Option<(u64, SystemTime)>> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some((metadata.len(), metadata.modified()?))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read_optional(path: &Path) -> StoreResult<Option<Vec<u8>>> {
+        match fs::read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Names of the visible entries in a directory; missing directories are empty
+    fn list_names(dir: &Path) -> StoreResult<Vec<String>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Write to a hidden temp file in the same directory, then rename over `path`
+    fn write_file_atomic(path: &Path, data: &[u8]) -> StoreResult<()> {
+        let file_name = path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temp_path = path.with_file_name(format!(
+            ".{}.tmp-{}-{}",
+            file_name,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        if let Err(e) = fs::write(&temp_path, data).and_then(|_| fs::rename(&temp_path, path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+impl DictionaryResolver for ObjectStore {
+    fn resolve(&self, hash: &str) -> anyhow::Result<Option<Dictionary>> {
+        let Some(content_id) = self.dictionary_content_id(hash)? else {
+            return Ok(None);
+        };
+        Ok(self.get_dictionary(&content_id)?.filter(|dictionary| dictionary.short_hash() == hash))
+    }
+}
+
+/// Reader that copies what passes through it to a file and a whole-object hash
+struct Spool<'a, R> {
+    inner: R,
+    file: &'a mut fs::File,
+    whole: &'a mut Sha256,
+}
+
+impl<R: Read> Read for Spool<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.file.write_all(&buf[..read])?;
+        self.whole.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn variant(quality: u8, width: u32) -> VariantParams {
+        VariantParams {
+            quality: Some(quality),
+            width: Some(width),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_canonical_variant_params() {
+        let params = VariantParams {
+            quality: Some(85),
+            width: Some(320),
+            height: None,
+            crop: Some(CropRect { x: 0, y: 10, width: 640, height: 480 }),
+        };
+        assert_eq!(params.canonical(), "q85_w320_c0.10.640.480");
+        assert_eq!(VariantParams::from_canonical(&params.canonical()).unwrap(), params);
+        assert_eq!(VariantParams::default().canonical(), "orig");
+
+        // Non-canonical spellings are rejected so each parameter set has one name
+        assert!(VariantParams::from_canonical("w320_q85").is_err());
+        assert!(VariantParams::from_canonical("q085").is_err());
+        assert!(VariantParams::from_canonical("x1").is_err());
+
+        let base = ContentId::for_content(b"base");
+        let a = VariantKey::new(base.clone(), params.clone());
+        let b = VariantKey::new(base, params);
+        assert_eq!(a.hash(), b.hash());
+        assert_ne!(a.hash(), VariantKey::new(a.base.clone(), variant(85, 320)).hash());
+    }
+
+    #[test]
+    fn test_content_id_validation() {
+        let id = ContentId::for_content(b"hello");
+        assert_eq!(ContentId::parse(id.as_str()).unwrap(), id);
+        assert!(ContentId::parse("../../etc/passwd").is_err());
+        assert!(ContentId::parse(&id.as_str().to_uppercase()).is_err());
+    }
+
+    #[test]
+    fn test_put_variants_list_and_purge() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = ObjectStore::open(temp_dir.path()).unwrap();
+
+        let base = store.put(b"base object").unwrap();
+        let other = store.put(b"unrelated object").unwrap();
+        assert_eq!(store.get(&base).unwrap().unwrap(), b"base object");
+        assert_eq!(store.get_prefix(&base, 4).unwrap().unwrap(), (b"base".to_vec(), 11));
+
+        let keys: Vec<VariantKey> = [variant(85, 640), variant(50, 320), variant(30, 64)]
+            .into_iter()
+            .map(|params| VariantKey::new(base.clone(), params))
+            .collect();
+        for (index, key) in keys.iter().enumerate() {
+            store.put_variant(key, &vec![index as u8; 10 * (index + 1)]).unwrap();
+        }
+        store.put_variant(&VariantKey::new(other.clone(), variant(85, 640)), b"keep").unwrap();
+
+        let listed = store.list_variants(&base).unwrap();
+        assert_eq!(listed.len(), 3);
+        for key in &keys {
+            assert!(listed.contains(key));
+        }
+        assert_eq!(store.get_variant(&keys[1]).unwrap().unwrap(), vec![1u8; 20]);
+
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.bases, KindStats { objects: 2, bytes: 27 });
+        assert_eq!(stats.variants, KindStats { objects: 4, bytes: 64 });
+
+        assert!(store.purge(&base).unwrap());
+        assert!(!store.contains(&base));
+        assert!(store.list_variants(&base).unwrap().is_empty());
+        assert!(store.get_variant(&keys[0]).unwrap().is_none());
+
+        // Purging again is a no-op, and unrelated objects survive
+        assert!(!store.purge(&base).unwrap());
+        assert!(store.contains(&other));
+        assert_eq!(store.stats().unwrap().variants, KindStats { objects: 1, bytes: 4 });
+    }
+
+    #[test]
+    fn test_chunked_put_records_piece_hashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = ObjectStore::open(temp_dir.path()).unwrap();
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+
+        let id = store.put_chunked(&data[..], 65536).unwrap();
+        assert_eq!(id, ContentId::for_content(&data));
+        assert_eq!(store.get(&id).unwrap().unwrap(), data);
+        let pieces = store.pieces(&id).unwrap().unwrap();
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(pieces.get(3), Some(&Sha256::digest(&data[3 * 65536..])[..]));
+        assert_eq!(store.put(&data).unwrap(), id);
+        assert!(store.pieces(&store.put(b"whole").unwrap()).unwrap().is_none());
+
+        let empty = store.put_chunked(io::empty(), 16).unwrap();
+        assert!(store.pieces(&empty).unwrap().unwrap().is_empty());
+        assert!(matches!(store.put_chunked(&data[..], 0), Err(StoreError::InvalidPieceLength)));
+        // No spooled uploads are left behind
+        assert_eq!(ObjectStore::list_names(&temp_dir.path().join("objects")).unwrap().len(), 3);
+        assert!(fs::read_dir(temp_dir.path().join("objects")).unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with('.')));
+    }
+
+    #[test]
+    fn test_info_reads_metadata_only() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = ObjectStore::open(temp_dir.path()).unwrap();
+
+        let tcf = store.put_with_format(b"pretend tcf", Some("tcf")).unwrap();
+        let plain = store.put(b"plain bytes").unwrap();
+        let key = VariantKey::new(plain.clone(), variant(85, 64));
+        store.put_variant(&key, b"small").unwrap();
+
+        assert_eq!(store.info(&tcf).unwrap().unwrap().format.as_deref(), Some("tcf"));
+        let info = store.info(&plain).unwrap().unwrap();
+        assert_eq!((info.size, info.format), (11, None));
+        assert_eq!(store.variant_info(&key).unwrap().unwrap().size, 5);
+        assert!(store.info(&ContentId::for_content(b"missing")).unwrap().is_none());
+        assert_eq!(store.payload_reads(), 0);
+
+        store.get(&plain).unwrap();
+        store.get_variant(&key).unwrap();
+        assert_eq!(store.payload_reads(), 2);
+        assert_eq!(store.stats().unwrap().bases, KindStats { objects: 2, bytes: 22 });
+    }
+
+    #[test]
+    fn test_variant_requires_base() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = ObjectStore::open(temp_dir.path()).unwrap();
+
+        let missing = ContentId::for_content(b"never stored");
+        let result = store.put_variant(&VariantKey::new(missing, variant(85, 64)), b"data");
+        assert!(matches!(result, Err(StoreError::MissingBase(_))));
+    }
+
+    #[test]
+    fn test_interrupted_purge_completes_on_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = {
+            let store = ObjectStore::open(temp_dir.path()).unwrap();
+            let base = store.put(b"base object").unwrap();
+            store.put_variant(&VariantKey::new(base.clone(), variant(85, 64)), b"small").unwrap();
+
+            // Simulate a crash after the rename but before the deletion
+            let trash_path = store.move_to_trash(&base).unwrap().unwrap();
+            assert!(trash_path.exists());
+            assert!(!store.contains(&base));
+            base
+        };
+
+        let store = ObjectStore::open(temp_dir.path()).unwrap();
+        assert!(!store.contains(&base));
+        assert!(store.list_variants(&base).unwrap().is_empty());
+        assert_eq!(fs::read_dir(temp_dir.path().join("trash")).unwrap().count(), 0);
+        assert_eq!(store.stats().unwrap(), StoreStats::default());
+        assert!(!store.purge(&base).unwrap());
+    }
+
+    #[test]
+    fn test_tcf_objects_share_a_stored_dictionary() {
+        use crate::codecs::text::errors::TcfError;
+        use crate::codecs::text::tcf_codec::{TcfCodec, TcfEncodeOptions};
+
+        let temp_dir = TempDir::new().unwrap();
+        let store = ObjectStore::open(temp_dir.path()).unwrap();
+        let dictionary = Dictionary::new(vec!["{\"status\":\"".to_string(), "\",\"owner\":\"".to_string()]).unwrap();
+        let dictionary_id = store.put_dictionary(&dictionary).unwrap();
+        assert_eq!(dictionary_id.as_str(), dictionary.content_hash());
+        assert_eq!(store.info(&dictionary_id).unwrap().unwrap().format.as_deref(), Some(ObjectStore::DICTIONARY_FORMAT));
+        assert_eq!(store.get_dictionary(&dictionary_id).unwrap(), Some(dictionary.clone()));
+        let hash = dictionary.short_hash();
+        assert_eq!(store.resolve(&hash).unwrap(), Some(dictionary.clone()));
+        assert_eq!(store.put_dictionary(&dictionary).unwrap(), dictionary_id);
+        assert_eq!(store.resolve("../objects").unwrap(), None);
+
+        // Headers name dictionaries by a 64-bit hash, so should two ever
+        // share one, the second is refused rather than shadowing the first
+        let other = Dictionary::new(vec!["\"owner\":\"".to_string()]).unwrap();
+        let other_id = store.put_dictionary(&other).unwrap();
+        fs::write(store.dictionary_index_path(&hash), other_id.as_str()).unwrap();
+        assert!(store.put_dictionary(&dictionary).is_err());
+        assert_eq!(store.resolve(&hash).unwrap(), None);
+        fs::write(store.dictionary_index_path(&hash), dictionary_id.as_str()).unwrap();
+
+        let options = TcfEncodeOptions { dictionary: Some(dictionary), embed_dictionary: false, ..Default::default() };
+        let texts = ["{\"status\":\"active\",\"owner\":\"ana\"}", "{\"status\":\"archived\",\"owner\":\"bo\"}"];
+        let ids: Vec<ContentId> = texts.iter()
+            .map(|text| store.put_with_format(&TcfCodec::encode_with_options(text, &options).unwrap().data, Some("tcf")).unwrap())
+            .collect();
+        for (text, id) in texts.iter().zip(&ids) {
+            assert_eq!(TcfCodec::decode_with_resolver(&store.get(id).unwrap().unwrap(), &store).unwrap(), *text);
+        }
+        assert!(store.get_dictionary(&ids[0]).is_err());
+
+        assert!(store.purge(&dictionary_id).unwrap());
+        assert_eq!(store.get_dictionary(&dictionary_id).unwrap(), None);
+        let error = TcfCodec::decode_with_resolver(&store.get(&ids[0]).unwrap().unwrap(), &store).unwrap_err();
+        assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::MissingDictionary(missing)) if *missing == hash), "{:#}", error);
+    }
+}
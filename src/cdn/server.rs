@@ -0,0 +1,1163 @@
+use anyhow::{bail, Context, Result};
+use image::DynamicImage;
+use httpdate::HttpDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::cdn::object_store::{ContentId, CropRect, ObjectInfo, ObjectStore, StoreError, VariantKey, VariantParams};
+use crate::codecs::cli_common::{MemoryLimitError, RuntimeConfig};
+use crate::codecs::formats::{self, FormatId};
+use crate::codecs::image::icf_codec::{IcfCodec, IcfEncodeOptions};
+use crate::codecs::image::input::decode_input;
+use crate::codecs::image::profile::IcfProfile;
+use crate::codecs::image::resample::{resize_image, ResampleOptions};
+use crate::codecs::peek::{PeekResult, PEEK_LIMIT};
+use crate::codecs::text::static_dictionary::DictionaryResolver;
+use crate::codecs::text::tcf_codec::{TcfCodec, TcfEncodeOptions, VerifyMode};
+use crate::codecs::text::tcf_stream::transcode_gzip_to_tcf;
+
+/// Quality used for variants of non-ICF sources when the request doesn't set one
+const DEFAULT_VARIANT_QUALITY: u8 = 85;
+
+/// Largest width or height a variant may be scaled to
+///
+/// Resampling holds several full-size planes at once, so an unbounded size
+/// from a query string would let one request exhaust memory.
+pub const MAX_VARIANT_DIMENSION: u32 = 8192;
+
+/// `Content-Encoding` token for a body sent as a TCF file
+pub const TCF_ENCODING: &str = "tcf";
+
+/// How long clients and shared caches may keep responses
+///
+/// Base objects are content-addressed, so the bytes under an id never
+/// change and they are marked `immutable`. Variants are only named by their
+/// parameters and are rebuilt if transcoding changes, so they get less.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// `max-age` in seconds for base objects with no per-format setting
+    pub immutable_max_age: u32,
+    /// `max-age` in seconds for base objects by format (`tcf`, `icf`)
+    pub format_max_age: BTreeMap<String, u32>,
+    /// `max-age` in seconds for transcoded variants
+    pub variant_max_age: u32,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            immutable_max_age: 365 * 24 * 60 * 60,
+            format_max_age: BTreeMap::new(),
+            variant_max_age: 24 * 60 * 60,
+        }
+    }
+}
+
+impl CachePolicy {
+    /// `Cache-Control` for a base object stored in `format`
+    pub fn base(&self, format: Option<&str>) -> String {
+        let max_age = format.and_then(|format| self.format_max_age.get(format)).copied().unwrap_or(self.immutable_max_age);
+        format!("public, max-age={}, immutable", max_age)
+    }
+
+    /// `Cache-Control` for a transcoded variant
+    pub fn variant(&self) -> String {
+        format!("public, max-age={}", self.variant_max_age)
+    }
+}
+
+/// Limits and encoder settings for `POST /upload`
+#[derive(Debug, Clone)]
+pub struct UploadPolicy {
+    /// Largest `text/*` body accepted, in bytes
+    pub max_text_size: u64,
+    /// Largest `image/*` body accepted, in bytes
+    pub max_image_size: u64,
+    /// How text is encoded to TCF
+    pub text: TcfEncodeOptions,
+    /// Profile images are encoded to ICF with
+    pub image_profile: IcfProfile,
+    /// Threads and memory image encodes may use
+    pub runtime: RuntimeConfig,
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self {
+            max_text_size: 16 * 1024 * 1024,
+            max_image_size: 32 * 1024 * 1024,
+            text: TcfEncodeOptions::default(),
+            image_profile: IcfProfile::PHOTO,
+            runtime: RuntimeConfig::default(),
+        }
+    }
+}
+
+/// Why `POST /upload` turned a payload away
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Payload of {size} bytes is over the {limit} byte limit")]
+    TooLarge { size: u64, limit: u64 },
+    #[error("Unsupported content type '{0}'; expected text/* or image/*")]
+    UnsupportedType(String),
+    #[error("Payload isn't decodable as {content_type}: {reason}")]
+    Undecodable { content_type: String, reason: String },
+}
+
+impl UploadError {
+    /// Stable identifier sent as `code` in the error body
+    pub fn code(&self) -> &'static str {
+        match self {
+            UploadError::TooLarge { .. } => "payload_too_large",
+            UploadError::UnsupportedType(_) => "unsupported_media_type",
+            UploadError::Undecodable { .. } => "undecodable_payload",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UploadError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            UploadError::UnsupportedType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            UploadError::Undecodable { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+/// An object stored by `POST /upload`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredObject {
+    pub id: ContentId,
+    pub size: u64,
+}
+
+/// Response body of `POST /upload`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadReceipt {
+    pub content_type: String,
+    /// `tcf` or `icf`
+    pub format: String,
+    pub original: StoredObject,
+    pub encoded: StoredObject,
+    /// Original size over encoded size
+    pub ratio: f64,
+}
+
+/// Encode an upload by its `Content-Type`: `text/*` (UTF-8) to TCF and
+/// `image/*` to ICF; returns the format name and the encoded bytes
+pub fn encode_upload(content_type: &str, body: &[u8], policy: &UploadPolicy) -> std::result::Result<(&'static str, Vec<u8>), UploadError> {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    let (format, limit) = if media_type.starts_with("text/") {
+        ("tcf", policy.max_text_size)
+    } else if media_type.starts_with("image/") {
+        ("icf", policy.max_image_size)
+    } else {
+        return Err(UploadError::UnsupportedType(content_type.to_string()));
+    };
+    if body.len() as u64 > limit {
+        return Err(UploadError::TooLarge { size: body.len() as u64, limit });
+    }
+
+    let undecodable = |reason: String| UploadError::Undecodable { content_type: media_type.clone(), reason };
+    let encoded = if format == "tcf" {
+        let text = std::str::from_utf8(body).map_err(|e| undecodable(e.to_string()))?;
+        TcfCodec::encode_with_options(text, &policy.text).map(|encoded| encoded.data)
+    } else {
+        let img = decode_input(body).map_err(|e| undecodable(format!("{:#}", e)))?;
+        IcfCodec::new().with_runtime(policy.runtime.clone())
+            .encode_with_options(&img, &IcfEncodeOptions::profile(&policy.image_profile))
+    };
+    let encoded = encoded.map_err(|e| match e.downcast_ref::<MemoryLimitError>() {
+        Some(refused) => UploadError::TooLarge { size: refused.needed, limit: refused.limit },
+        None => undecodable(format!("{:#}", e)),
+    })?;
+    Ok((format, encoded))
+}
+
+/// `If-None-Match` and `If-Modified-Since` from a request
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<String>,
+}
+
+impl Conditions {
+    /// Whether a representation with these validators is what the client has
+    ///
+    /// `If-None-Match` uses weak comparison and, when present, overrides
+    /// `If-Modified-Since` (RFC 9110 13.2.2). Unparseable dates are ignored.
+    pub fn not_modified(&self, etag: &str, last_modified: Option<SystemTime>) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+            return if_none_match.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag));
+        }
+        match (&self.if_modified_since, last_modified) {
+            (Some(since), Some(modified)) => since.parse::<HttpDate>().is_ok_and(|since| HttpDate::from(modified) <= since),
+            _ => false,
+        }
+    }
+}
+
+/// Caching headers for one representation
+struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+    cache_control: String,
+    /// Whether the response depends on `Accept-Encoding`
+    negotiated: bool,
+}
+
+impl Validators {
+    /// Validators for a base object; negotiated TCF bodies get a weak ETag,
+    /// since the same id may go out encoded or decoded
+    fn base(id: &ContentId, info: &ObjectInfo, policy: &CachePolicy) -> Self {
+        let negotiated = info.format.as_deref() == Some("tcf");
+        Self {
+            etag: format!("{}\"{}\"", if negotiated { "W/" } else { "" }, id),
+            last_modified: Some(info.modified),
+            cache_control: policy.base(info.format.as_deref()),
+            negotiated,
+        }
+    }
+
+    /// Validators for a variant, named by its base and parameters
+    fn variant(key: &VariantKey, info: Option<&ObjectInfo>, policy: &CachePolicy) -> Self {
+        Self {
+            etag: format!("\"{}\"", key.hash()),
+            last_modified: info.map(|info| info.modified),
+            cache_control: policy.variant(),
+            negotiated: false,
+        }
+    }
+
+    fn apply(&self, mut response: Response) -> Response {
+        let headers = response.headers_mut();
+        for (name, value) in [
+            (warp::http::header::ETAG, Some(self.etag.clone())),
+            (warp::http::header::LAST_MODIFIED, self.last_modified.map(httpdate::fmt_http_date)),
+            (warp::http::header::CACHE_CONTROL, Some(self.cache_control.clone())),
+            (warp::http::header::VARY, self.negotiated.then(|| "accept-encoding".to_string())),
+        ] {
+            if let Some(value) = value.and_then(|value| value.parse().ok()) {
+                headers.insert(name, value);
+            }
+        }
+        response
+    }
+
+    fn not_modified(&self) -> Response {
+        self.apply(StatusCode::NOT_MODIFIED.into_response())
+    }
+}
+
+/// Query string accepted by the variant route, e.g. `?q=50&w=320&crop=0,0,640,480`
+#[derive(Debug, Default, Deserialize)]
+pub struct VariantQuery {
+    pub q: Option<u8>,
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub crop: Option<String>,
+}
+
+impl VariantQuery {
+    /// Validate the query and turn it into store parameters
+    pub fn to_params(&self) -> Result<VariantParams> {
+        if matches!(self.q, Some(q) if q == 0 || q > 100) {
+            bail!("Quality must be between 1 and 100");
+        }
+        if self.w == Some(0) || self.h == Some(0) {
+            bail!("Width and height must be non-zero");
+        }
+        if self.w.max(self.h).is_some_and(|side| side > MAX_VARIANT_DIMENSION) {
+            bail!("Width and height must be at most {}", MAX_VARIANT_DIMENSION);
+        }
+
+        let crop = match &self.crop {
+            Some(crop) => {
+                let fields: Vec<u32> = crop.split(',')
+                    .map(|field| field.trim().parse::<u32>())
+                    .collect::<std::result::Result<_, _>>()
+                    .context("Crop must be x,y,width,height")?;
+                if fields.len() != 4 {
+                    bail!("Crop must be x,y,width,height");
+                }
+                if fields[2] == 0 || fields[3] == 0 {
+                    bail!("Crop width and height must be non-zero");
+                }
+                Some(CropRect { x: fields[0], y: fields[1], width: fields[2], height: fields[3] })
+            }
+            None => None,
+        };
+
+        Ok(VariantParams { quality: self.q, width: self.w, height: self.h, crop })
+    }
+}
+
+/// Produce an ICF-encoded variant of `source` (ICF or any format `image` reads)
+///
+/// Crop is applied first, then scaling; a single requested dimension keeps the
+/// aspect ratio. Crops that fall outside the source are rejected, as are
+/// sizes over `MAX_VARIANT_DIMENSION`, including a derived one.
+pub fn transcode(source: &[u8], params: &VariantParams) -> Result<Vec<u8>> {
+    let codec = IcfCodec::new();
+
+    let (mut img, source_quality) = if formats::sniff(source) == Some(FormatId::Icf) {
+        let (header, _) = codec.parse_container(source)?;
+        (codec.decode(source)?, Some(header.quality))
+    } else {
+        (decode_input(source).context("Base object is not a decodable image")?, None)
+    };
+
+    if let Some(crop) = params.crop {
+        let fits_x = crop.x.checked_add(crop.width).is_some_and(|end| end <= img.width());
+        let fits_y = crop.y.checked_add(crop.height).is_some_and(|end| end <= img.height());
+        if !fits_x || !fits_y {
+            bail!("Crop {}x{}+{}+{} exceeds image bounds {}x{}",
+                crop.width, crop.height, crop.x, crop.y, img.width(), img.height());
+        }
+        img = img.crop_imm(crop.x, crop.y, crop.width, crop.height);
+    }
+
+    img = scale(img, params.width, params.height)?;
+
+    let quality = params.quality.or(source_quality).unwrap_or(DEFAULT_VARIANT_QUALITY);
+    codec.encode_image(&img, quality)
+}
+
+fn scale(img: DynamicImage, width: Option<u32>, height: Option<u32>) -> Result<DynamicImage> {
+    let (src_w, src_h) = (img.width() as u64, img.height() as u64);
+    let derive = |side: u32, along: u64, across: u64| -> Result<u32> {
+        u32::try_from((side as u64 * along / across.max(1)).max(1))
+            .ok()
+            .filter(|&derived| derived <= MAX_VARIANT_DIMENSION)
+            .with_context(|| format!("Scaling {}x{} to {} keeps the aspect ratio beyond {} pixels a side",
+                src_w, src_h, side, MAX_VARIANT_DIMENSION))
+    };
+    let (w, h) = match (width, height) {
+        (None, None) => return Ok(img),
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, derive(w, src_h, src_w)?),
+        (None, Some(h)) => (derive(h, src_w, src_h)?, h),
+    };
+    if w > MAX_VARIANT_DIMENSION || h > MAX_VARIANT_DIMENSION {
+        bail!("Variant {}x{} exceeds {} pixels a side", w, h, MAX_VARIANT_DIMENSION);
+    }
+
+    if (w, h) == (img.width(), img.height()) {
+        Ok(img)
+    } else {
+        Ok(resize_image(&img, w, h, &ResampleOptions::default()))
+    }
+}
+
+/// All CDN routes:
+///
+/// - `POST /o` stores the request body and returns its id; a gzipped body
+///   of UTF-8 text is stored as TCF, transcoded a chunk at a time
+/// - `POST /upload` takes a `text/*` or `image/*` body, encodes it to TCF or
+///   ICF under `upload`, stores both, and returns an `UploadReceipt`;
+///   refusals are 413, 415 or 422 with a `code` from `UploadError`
+/// - `GET /o/{id}` returns a base object; TCF objects go out as-is with
+///   `Content-Encoding: tcf` to clients that accept it, decoded otherwise
+/// - `HEAD /o/{id}` returns its size and, for TCF and ICF objects, `x-codec-*`
+///   headers peeked from the first 4 KB
+/// - `GET /o/{id}/variant?q=&w=&h=&crop=x,y,w,h` returns (and caches) a transcoded variant
+/// - `DELETE /o/{id}` purges a base object and all of its variants
+///
+/// Reads carry `ETag`, `Last-Modified` and `Cache-Control` from `policy`,
+/// and answer a matching `If-None-Match` or `If-Modified-Since` with 304
+/// from store metadata alone, without reading or decoding the object.
+pub fn routes(store: Arc<ObjectStore>, policy: CachePolicy, upload: UploadPolicy) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone {
+    let dictionaries: Arc<dyn DictionaryResolver + Send + Sync> = store.clone();
+    let with_store = warp::any().map(move || store.clone());
+    let policy = Arc::new(policy);
+    let with_policy = warp::any().map(move || policy.clone());
+    let max_upload = upload.max_text_size.max(upload.max_image_size);
+    let upload = Arc::new(upload);
+    let with_upload = warp::any().map(move || upload.clone());
+
+    let upload = warp::post()
+        .and(warp::path!("o"))
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and(with_store.clone())
+        .and_then(handle_upload);
+
+    let encode_upload = warp::post()
+        .and(warp::path!("upload"))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(max_upload))
+        .and(warp::body::bytes())
+        .and(with_store.clone())
+        .and(with_upload)
+        .and_then(handle_encode_upload)
+        // Bodies over every limit are turned away before they're read
+        .recover(move |rejection: warp::Rejection| async move {
+            match rejection.find::<warp::reject::PayloadTooLarge>() {
+                Some(_) => Ok(upload_error_reply(&UploadError::TooLarge { size: max_upload + 1, limit: max_upload })),
+                None => Err(rejection),
+            }
+        })
+        .unify();
+
+    let get = decode_tcf_unless_accepted(warp::get()
+        .and(warp::path!("o" / String))
+        .and(conditions())
+        .and(with_store.clone())
+        .and(with_policy.clone())
+        .and_then(handle_get), dictionaries);
+
+    let head = warp::head()
+        .and(warp::path!("o" / String))
+        .and(with_store.clone())
+        .and(with_policy.clone())
+        .and_then(handle_head);
+
+    let variant = warp::get()
+        .and(warp::path!("o" / String / "variant"))
+        .and(warp::query::<VariantQuery>())
+        .and(conditions())
+        .and(with_store.clone())
+        .and(with_policy)
+        .and_then(handle_variant);
+
+    let purge = warp::delete()
+        .and(warp::path!("o" / String))
+        .and(with_store)
+        .and_then(handle_purge);
+
+    upload.or(encode_upload).unify().or(get).unify().or(head).unify().or(variant).unify().or(purge).unify()
+}
+
+/// Serve the CDN routes until the process exits
+pub async fn serve(store: Arc<ObjectStore>, policy: CachePolicy, upload: UploadPolicy, addr: SocketAddr) {
+    warp::serve(routes(store, policy, upload)).run(addr).await;
+}
+
+fn conditions() -> impl Filter<Extract = (Conditions,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .map(|if_none_match, if_modified_since| Conditions { if_none_match, if_modified_since })
+}
+
+async fn handle_upload(content_encoding: Option<String>, body: Bytes, store: Arc<ObjectStore>) -> std::result::Result<Response, Infallible> {
+    let gzip = content_encoding.is_some_and(|coding| {
+        let coding = coding.trim();
+        coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip")
+    });
+    let result = run_blocking(move || {
+        // Anything that isn't gzipped text is stored as sent
+        let mut tcf = Vec::new();
+        if gzip && transcode_gzip_to_tcf(body.as_ref(), &mut tcf).is_ok() {
+            return store.put_with_format(&tcf, Some("tcf"));
+        }
+        store.put_with_format(&body, codec_format(&body))
+    }).await;
+    Ok(match result {
+        Ok(id) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "id": id })),
+            StatusCode::CREATED,
+        ).into_response(),
+        Err(e) => store_error_reply(e),
+    })
+}
+
+async fn handle_encode_upload(
+    content_type: Option<String>,
+    body: Bytes,
+    store: Arc<ObjectStore>,
+    policy: Arc<UploadPolicy>,
+) -> std::result::Result<Response, Infallible> {
+    let content_type = content_type.unwrap_or_default();
+    let result = run_blocking(move || {
+        let (format, encoded) = match encode_upload(&content_type, &body, &policy) {
+            Ok(encoded) => encoded,
+            Err(e) => return Ok(Err(e)),
+        };
+        let original = StoredObject { id: store.put(&body)?, size: body.len() as u64 };
+        let encoded = StoredObject { id: store.put_with_format(&encoded, Some(format))?, size: encoded.len() as u64 };
+        let ratio = original.size as f64 / encoded.size.max(1) as f64;
+        Ok(Ok(UploadReceipt { content_type, format: format.to_string(), original, encoded, ratio }))
+    })
+    .await;
+
+    Ok(match result {
+        Ok(Ok(receipt)) => warp::reply::with_status(warp::reply::json(&receipt), StatusCode::CREATED).into_response(),
+        Ok(Err(e)) => upload_error_reply(&e),
+        Err(e) => store_error_reply(e),
+    })
+}
+
+async fn handle_get(
+    id: String,
+    conditions: Conditions,
+    store: Arc<ObjectStore>,
+    policy: Arc<CachePolicy>,
+) -> std::result::Result<Response, Infallible> {
+    let id = match ContentId::parse(&id) {
+        Ok(id) => id,
+        Err(e) => return Ok(store_error_reply(e)),
+    };
+
+    let result = run_blocking(move || {
+        let Some(info) = store.info(&id)? else {
+            return Ok(None);
+        };
+        let validators = Validators::base(&id, &info, &policy);
+        if conditions.not_modified(&validators.etag, validators.last_modified) {
+            return Ok(Some((validators, None)));
+        }
+        Ok(store.get(&id)?.map(|data| (validators, Some(data))))
+    })
+    .await;
+
+    Ok(match result {
+        Ok(Some((validators, None))) => validators.not_modified(),
+        Ok(Some((validators, Some(data)))) if matches!(TcfCodec::peek(&data), Ok(PeekResult::Ready(_))) => {
+            validators.apply(tcf_response(data))
+        }
+        Ok(Some((validators, Some(data)))) => validators.apply(data.into_response()),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => store_error_reply(e),
+    })
+}
+
+/// Content codings a response can be sent with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Tcf,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Tcf => TCF_ENCODING,
+            Encoding::Gzip => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+}
+
+/// Pick the coding to answer an `Accept-Encoding` header with
+///
+/// The acceptable coding with the highest q-value wins, ties going to tcf,
+/// then gzip, then identity. `*` covers gzip and identity but never tcf,
+/// which only clients naming it can decode. Identity is acceptable unless
+/// excluded, and is also the answer when nothing acceptable is known.
+pub fn negotiate_encoding(accept_encoding: Option<&str>) -> Encoding {
+    let mut weights: Vec<(String, f32)> = Vec::new();
+    for item in accept_encoding.unwrap_or_default().split(',') {
+        let mut parts = item.split(';');
+        let token = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        if token.is_empty() {
+            continue;
+        }
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|value| value.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        weights.push((token, q));
+    }
+    let weight = |token: &str| weights.iter().find(|(name, _)| name == token).map(|&(_, q)| q);
+    let wildcard = weight("*");
+
+    let candidates = [
+        (Encoding::Tcf, weight(TCF_ENCODING)),
+        (Encoding::Gzip, weight("gzip").or(weight("x-gzip")).or(wildcard)),
+        // Implicitly acceptable, but below any coding the client asked for
+        (Encoding::Identity, weight("identity").or(wildcard).or(Some(f32::MIN_POSITIVE))),
+    ];
+    let mut best = (Encoding::Identity, 0.0);
+    for (encoding, q) in candidates {
+        if let Some(q) = q.filter(|&q| q > best.1) {
+            best = (encoding, q);
+        }
+    }
+    best.0
+}
+
+/// A TCF file sent as-is with `Content-Encoding: tcf`
+///
+/// Carries the `x-codec-*` headers `HEAD` reports, so clients see the size
+/// of the decoded text in `x-codec-original-size`.
+pub fn tcf_response(tcf: Vec<u8>) -> Response {
+    let codec_headers = codec_headers(&tcf[..tcf.len().min(PEEK_LIMIT)]);
+    let length = tcf.len();
+    let mut response = Response::new(tcf.into());
+    let headers = response.headers_mut();
+    headers.insert(warp::http::header::CONTENT_ENCODING, TCF_ENCODING.parse().unwrap());
+    headers.insert(warp::http::header::CONTENT_LENGTH, length.into());
+    headers.insert(warp::http::header::VARY, "accept-encoding".parse().unwrap());
+    for (name, value) in codec_headers {
+        if let Ok(value) = value.parse() {
+            headers.insert(name, value);
+        }
+    }
+    response
+}
+
+/// Wrap `filter` so `Content-Encoding: tcf` responses are decoded to
+/// identity unless the request's `Accept-Encoding` names tcf
+///
+/// Other responses pass through untouched. The text is decoded one chunk at
+/// a time on a blocking thread and streamed out as it's produced, so large
+/// chunked files are never held whole as text.
+///
+/// Chunks are checked with `VerifyMode::PerChunk`: each one must match its
+/// CRC-32 before any of it is sent, and the first that doesn't cuts the
+/// body short, so a corrupt tail never follows good data out. The
+/// whole-file SHA-256 isn't checked, since by the time it could fail the
+/// client already has everything but the status.
+///
+/// Files coded without their dictionary get it from `dictionaries` before
+/// anything is sent; one it can't supply is a 500 naming its hash.
+pub fn decode_tcf_unless_accepted<F>(filter: F, dictionaries: Arc<dyn DictionaryResolver + Send + Sync>) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone
+where
+    F: Filter<Extract = (Response,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+{
+    filter
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(move |response: Response, accept_encoding: Option<String>| {
+            let dictionaries = dictionaries.clone();
+            async move {
+                Ok::<_, Infallible>(match negotiate_encoding(accept_encoding.as_deref()) {
+                    Encoding::Tcf => response,
+                    _ => decode_tcf_response(response, dictionaries).await,
+                })
+            }
+        })
+}
+
+async fn decode_tcf_response(response: Response, dictionaries: Arc<dyn DictionaryResolver + Send + Sync>) -> Response {
+    let is_tcf = response.headers()
+        .get(warp::http::header::CONTENT_ENCODING)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(TCF_ENCODING.as_bytes()));
+    if !is_tcf {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let tcf = match warp::hyper::body::to_bytes(body).await {
+        Ok(tcf) => tcf,
+        Err(e) => return error_reply(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let header = match TcfCodec::parse_header(&tcf) {
+        Ok(header) => header,
+        Err(e) => return error_reply(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e)),
+    };
+    let original_size = header.original_size;
+    let dictionary = tokio::task::spawn_blocking(move || TcfCodec::resolve_dictionary(&header, dictionaries.as_ref()))
+        .await
+        .unwrap_or_else(|e| Err(e.into()));
+    let dictionary = match dictionary {
+        Ok(dictionary) => dictionary,
+        Err(e) => return error_reply(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e)),
+    };
+    parts.headers.remove(warp::http::header::CONTENT_ENCODING);
+    parts.headers.insert(warp::http::header::CONTENT_LENGTH, original_size.into());
+
+    let (mut sender, body) = warp::hyper::Body::channel();
+    tokio::task::spawn_blocking(move || {
+        let runtime = tokio::runtime::Handle::current();
+        let pieces = match &dictionary {
+            Some(dictionary) => TcfCodec::decode_stream_with_dictionary(&tcf, dictionary),
+            None => TcfCodec::decode_stream(&tcf),
+        };
+        let pieces = match pieces {
+            Ok(pieces) => pieces.verify(VerifyMode::PerChunk),
+            Err(_) => return sender.abort(),
+        };
+        for piece in pieces {
+            let Ok(piece) = piece else {
+                // Cuts the body short so the client sees a failed transfer
+                return sender.abort();
+            };
+            if runtime.block_on(sender.send_data(piece.into())).is_err() {
+                return;
+            }
+        }
+    });
+    Response::from_parts(parts, body)
+}
+
+async fn handle_head(id: String, store: Arc<ObjectStore>, policy: Arc<CachePolicy>) -> std::result::Result<Response, Infallible> {
+    let id = match ContentId::parse(&id) {
+        Ok(id) => id,
+        Err(e) => return Ok(store_error_reply(e)),
+    };
+
+    let result = run_blocking(move || {
+        let Some(info) = store.info(&id)? else {
+            return Ok(None);
+        };
+        let validators = Validators::base(&id, &info, &policy);
+        Ok(store.get_prefix(&id, PEEK_LIMIT)?.map(|(prefix, size)| (validators, prefix, size)))
+    })
+    .await;
+
+    Ok(match result {
+        Ok(Some((validators, prefix, size))) => {
+            let mut response = validators.apply(Response::default());
+            let headers = response.headers_mut();
+            headers.insert(warp::http::header::CONTENT_LENGTH, size.into());
+            for (name, value) in codec_headers(&prefix) {
+                if let Ok(value) = value.parse() {
+                    headers.insert(name, value);
+                }
+            }
+            response
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => store_error_reply(e),
+    })
+}
+
+/// Format name of a TCF or ICF object, from its first bytes
+pub fn codec_format(prefix: &[u8]) -> Option<&'static str> {
+    if matches!(TcfCodec::peek(prefix), Ok(PeekResult::Ready(_))) {
+        Some("tcf")
+    } else if matches!(IcfCodec::peek(prefix), Ok(PeekResult::Ready(_))) {
+        Some("icf")
+    } else {
+        None
+    }
+}
+
+/// Metadata headers for a TCF or ICF object, from its first bytes; none
+/// for other content or a header that doesn't fit in the prefix
+pub fn codec_headers(prefix: &[u8]) -> Vec<(&'static str, String)> {
+    if let Ok(PeekResult::Ready(peek)) = TcfCodec::peek(prefix) {
+        return vec![
+            ("x-codec-format", "tcf".to_string()),
+            ("x-codec-original-size", peek.original_size.to_string()),
+            ("x-codec-method", peek.compression_method.clone()),
+            ("x-codec-ratio", format!("{:.2}", peek.compression_ratio())),
+        ];
+    }
+    if let Ok(PeekResult::Ready(peek)) = IcfCodec::peek(prefix) {
+        return vec![
+            ("x-codec-format", "icf".to_string()),
+            ("x-codec-original-size", peek.original_size.to_string()),
+            ("x-image-width", peek.width.to_string()),
+            ("x-image-height", peek.height.to_string()),
+            ("x-image-channels", peek.channels.to_string()),
+            ("x-image-quality", peek.quality.to_string()),
+        ];
+    }
+    Vec::new()
+}
+
+async fn handle_variant(
+    id: String,
+    query: VariantQuery,
+    conditions: Conditions,
+    store: Arc<ObjectStore>,
+    policy: Arc<CachePolicy>,
+) -> std::result::Result<Response, Infallible> {
+    let id = match ContentId::parse(&id) {
+        Ok(id) => id,
+        Err(e) => return Ok(store_error_reply(e)),
+    };
+    let params = match query.to_params() {
+        Ok(params) => params,
+        Err(e) => return Ok(error_reply(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    let key = VariantKey::new(id, params);
+
+    type Variant = Option<(Validators, Option<Vec<u8>>)>;
+    let result = tokio::task::spawn_blocking(move || -> std::result::Result<Variant, VariantError> {
+        // The ETag names the parameters, so it holds even if the cached copy is gone
+        let info = store.variant_info(&key)?;
+        if info.is_none() && !store.contains(&key.base) {
+            return Ok(None);
+        }
+        let validators = Validators::variant(&key, info.as_ref(), &policy);
+        if conditions.not_modified(&validators.etag, validators.last_modified) {
+            return Ok(Some((validators, None)));
+        }
+
+        if let Some(cached) = store.get_variant(&key)? {
+            return Ok(Some((validators, Some(cached))));
+        }
+        let source = match store.get(&key.base)? {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+
+        let data = transcode(&source, &key.params).map_err(VariantError::Transcode)?;
+        match store.put_variant(&key, &data) {
+            // The base was purged while we were transcoding; serve the result uncached
+            Ok(()) => Ok(Some((Validators::variant(&key, store.variant_info(&key)?.as_ref(), &policy), Some(data)))),
+            Err(StoreError::MissingBase(_)) => Ok(Some((validators, Some(data)))),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await;
+
+    Ok(match result {
+        Ok(Ok(Some((validators, None)))) => validators.not_modified(),
+        Ok(Ok(Some((validators, Some(data))))) => validators.apply(data.into_response()),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(VariantError::Store(e))) => store_error_reply(e),
+        Ok(Err(VariantError::Transcode(e))) => {
+            error_reply(StatusCode::UNPROCESSABLE_ENTITY, &format!("{:#}", e))
+        }
+        Err(e) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    })
+}
+
+async fn handle_purge(id: String, store: Arc<ObjectStore>) -> std::result::Result<Response, Infallible> {
+    let id = match ContentId::parse(&id) {
+        Ok(id) => id,
+        Err(e) => return Ok(store_error_reply(e)),
+    };
+
+    Ok(match run_blocking(move || store.purge(&id)).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => store_error_reply(e),
+    })
+}
+
+enum VariantError {
+    Store(StoreError),
+    Transcode(anyhow::Error),
+}
+
+impl From<StoreError> for VariantError {
+    fn from(e: StoreError) -> Self {
+        VariantError::Store(e)
+    }
+}
+
+/// Run filesystem work off the async executor
+async fn run_blocking<T, F>(f: F) -> std::result::Result<T, StoreError>
+where
+    F: FnOnce() -> std::result::Result<T, StoreError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(StoreError::IoError(std::io::Error::other(e))))
+}
+
+fn store_error_reply(error: StoreError) -> Response {
+    let status = match error {
+        StoreError::InvalidContentId(_) | StoreError::InvalidVariant(_) | StoreError::InvalidPieceLength => StatusCode::BAD_REQUEST,
+        StoreError::MissingBase(_) => StatusCode::NOT_FOUND,
+        StoreError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_reply(status, &error.to_string())
+}
+
+fn upload_error_reply(error: &UploadError) -> Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": error.to_string(), "code": error.code() })),
+        error.status(),
+    ).into_response()
+}
+
+fn error_reply(status: StatusCode, message: &str) -> Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": message })),
+        status,
+    ).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+    use tempfile::TempDir;
+
+    fn test_png(width: u32, height: u32) -> Vec<u8> {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        });
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_variant_route_caches_and_purge_removes_everything() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let base = store.put(&test_png(64, 48)).unwrap();
+        let api = routes(store.clone(), CachePolicy::default(), UploadPolicy::default());
+
+        for query in ["q=50&w=32", "crop=8,8,16,16", "h=24"] {
+            let response = warp::test::request()
+                .path(&format!("/o/{}/variant?{}", base, query))
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK, "query {}", query);
+
+            let img = IcfCodec::new().decode(response.body()).unwrap();
+            match query {
+                "q=50&w=32" => assert_eq!((img.width(), img.height()), (32, 24)),
+                "crop=8,8,16,16" => assert_eq!((img.width(), img.height()), (16, 16)),
+                _ => assert_eq!((img.width(), img.height()), (32, 24)),
+            }
+        }
+        assert_eq!(store.list_variants(&base).unwrap().len(), 3);
+
+        let response = warp::test::request()
+            .method("DELETE")
+            .path(&format!("/o/{}", base))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!store.contains(&base));
+        assert_eq!(store.stats().unwrap().variants.objects, 0);
+
+        let response = warp::test::request()
+            .method("DELETE")
+            .path(&format!("/o/{}", base))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_head_reports_codec_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let text = "cache me if you can ".repeat(50);
+        let tcf = TcfCodec::encode(&text).unwrap();
+        let tcf_id = store.put(&tcf).unwrap();
+        let icf_id = store.put(&transcode(&test_png(40, 24), &VariantParams::default()).unwrap()).unwrap();
+        let png_id = store.put(&test_png(8, 8)).unwrap();
+        let api = routes(store, CachePolicy::default(), UploadPolicy::default());
+
+        let response = warp::test::request().method("HEAD").path(&format!("/o/{}", tcf_id)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.body().is_empty());
+        assert_eq!(response.headers()["content-length"], tcf.len().to_string().as_str());
+        assert_eq!(response.headers()["x-codec-format"], "tcf");
+        assert_eq!(response.headers()["x-codec-original-size"], text.len().to_string().as_str());
+
+        let response = warp::test::request().method("HEAD").path(&format!("/o/{}", icf_id)).reply(&api).await;
+        assert_eq!(response.headers()["x-image-width"], "40");
+        assert_eq!(response.headers()["x-image-height"], "24");
+
+        let response = warp::test::request().method("HEAD").path(&format!("/o/{}", png_id)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("x-codec-format"));
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        assert_eq!(negotiate_encoding(None), Encoding::Identity);
+        assert_eq!(negotiate_encoding(Some("gzip, deflate, br")), Encoding::Gzip);
+        assert_eq!(negotiate_encoding(Some("gzip;q=0.5, TCF")), Encoding::Tcf);
+        assert_eq!(negotiate_encoding(Some("tcf;q=0.2, gzip;q=0.8")), Encoding::Gzip);
+        assert_eq!(negotiate_encoding(Some("tcf;q=0, identity")), Encoding::Identity);
+        // A wildcard never selects tcf
+        assert_eq!(negotiate_encoding(Some("*")), Encoding::Gzip);
+        assert_eq!(negotiate_encoding(Some("br")), Encoding::Identity);
+    }
+
+    #[tokio::test]
+    async fn test_get_serves_tcf_only_to_clients_that_accept_it() {
+        use crate::codecs::text::{ChunkStrategy, TcfEncodeOptions};
+
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let text: String = (0..400).map(|i| format!("line {} of the streamed body\n", i)).collect();
+        let options = TcfEncodeOptions { chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 1024)), ..Default::default() };
+        let tcf = TcfCodec::encode_with_options(&text, &options).unwrap().data;
+        let id = store.put(&tcf).unwrap();
+        let api = routes(store, CachePolicy::default(), UploadPolicy::default());
+
+        let response = warp::test::request()
+            .path(&format!("/o/{}", id))
+            .header("accept-encoding", "gzip, tcf")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), tcf.as_slice());
+        assert_eq!(response.headers()["content-encoding"], "tcf");
+        assert_eq!(response.headers()["content-length"], tcf.len().to_string().as_str());
+        assert_eq!(response.headers()["x-codec-original-size"], text.len().to_string().as_str());
+
+        for accept_encoding in [None, Some("gzip")] {
+            let mut request = warp::test::request().path(&format!("/o/{}", id));
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header("accept-encoding", accept_encoding);
+            }
+            let response = request.reply(&api).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.body().as_ref(), text.as_bytes());
+            assert!(!response.headers().contains_key("content-encoding"));
+            assert_eq!(response.headers()["content-length"], text.len().to_string().as_str());
+            assert_eq!(response.headers()["vary"], "accept-encoding");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_decodes_tcf_with_a_stored_dictionary() {
+        use crate::codecs::text::static_dictionary::Dictionary;
+
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let dictionary = Dictionary::new(vec!["\"level\":\"info\",\"msg\":\"".to_string()]).unwrap();
+        let dictionary_id = store.put_dictionary(&dictionary).unwrap();
+        let hash = dictionary.short_hash();
+        let options = TcfEncodeOptions { dictionary: Some(dictionary), embed_dictionary: false, ..Default::default() };
+        let texts = ["{\"level\":\"info\",\"msg\":\"started\"}\n", "{\"level\":\"info\",\"msg\":\"stopped\"}\n"];
+        let ids: Vec<ContentId> = texts.iter()
+            .map(|text| store.put(&TcfCodec::encode_with_options(text, &options).unwrap().data).unwrap())
+            .collect();
+        let api = routes(store.clone(), CachePolicy::default(), UploadPolicy::default());
+
+        for (text, id) in texts.iter().zip(&ids) {
+            let response = warp::test::request().path(&format!("/o/{}", id)).reply(&api).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.body().as_ref(), text.as_bytes());
+        }
+
+        assert!(store.purge(&dictionary_id).unwrap());
+        let response = warp::test::request().path(&format!("/o/{}", ids[0])).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(String::from_utf8_lossy(response.body()).contains(&hash));
+        // Clients taking tcf get the file as stored, to resolve themselves
+        let response = warp::test::request().path(&format!("/o/{}", ids[0])).header("accept-encoding", "tcf").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_uploads_are_stored_as_tcf() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let api = routes(store.clone(), CachePolicy::default(), UploadPolicy::default());
+        let text: String = (0..2000).map(|i| format!("{{\"event\":\"view\",\"seq\":{}}}\n", i)).collect();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let upload = |body: Vec<u8>| warp::test::request().method("POST").path("/o").header("content-encoding", "gzip").body(body).reply(&api);
+        let response = upload(gzip).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let id = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()["id"].as_str().unwrap().to_string();
+        let stored = store.get(&ContentId::parse(&id).unwrap()).unwrap().unwrap();
+        assert_eq!(TcfCodec::decode(&stored).unwrap(), text);
+        assert_eq!(store.info(&ContentId::parse(&id).unwrap()).unwrap().unwrap().format.as_deref(), Some("tcf"));
+
+        let response = warp::test::request().path(&format!("/o/{}", id)).reply(&api).await;
+        assert_eq!(response.body().as_ref(), text.as_bytes());
+
+        // Gzipped bytes that aren't text are kept as they came
+        let mut binary = GzEncoder::new(Vec::new(), Compression::default());
+        binary.write_all(&[0xFF, 0xFE, 0x00, 0x80]).unwrap();
+        let binary = binary.finish().unwrap();
+        let response = upload(binary.clone()).await;
+        let id = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()["id"].as_str().unwrap().to_string();
+        assert_eq!(store.get(&ContentId::parse(&id).unwrap()).unwrap().unwrap(), binary);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_requests_skip_the_payload() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let policy = CachePolicy { format_max_age: [("tcf".to_string(), 600)].into(), ..Default::default() };
+        let api = routes(store.clone(), policy, UploadPolicy::default());
+
+        let upload = |body: Vec<u8>| warp::test::request().method("POST").path("/o").body(body).reply(&api);
+        let png_id = serde_json::from_slice::<serde_json::Value>(upload(test_png(64, 48)).await.body()).unwrap()["id"]
+            .as_str().unwrap().to_string();
+        let tcf_id = serde_json::from_slice::<serde_json::Value>(upload(TcfCodec::encode(&"etag ".repeat(100)).unwrap()).await.body()).unwrap()["id"]
+            .as_str().unwrap().to_string();
+
+        let response = warp::test::request().path(&format!("/o/{}", png_id)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+        assert_eq!(etag, format!("\"{}\"", png_id));
+        assert_eq!(response.headers()["cache-control"], "public, max-age=31536000, immutable");
+
+        let response = warp::test::request().path(&format!("/o/{}", tcf_id)).reply(&api).await;
+        assert_eq!(response.headers()["etag"], format!("W/\"{}\"", tcf_id).as_str());
+        assert_eq!(response.headers()["cache-control"], "public, max-age=600, immutable");
+
+        let variant_path = format!("/o/{}/variant?w=32", png_id);
+        let response = warp::test::request().path(&variant_path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let variant_key = VariantKey::new(ContentId::parse(&png_id).unwrap(), VariantParams { width: Some(32), ..Default::default() });
+        assert_eq!(response.headers()["etag"], format!("\"{}\"", variant_key.hash()).as_str());
+        assert_eq!(response.headers()["cache-control"], "public, max-age=86400");
+        assert!(response.headers().contains_key("last-modified"));
+
+        let reads = store.payload_reads();
+        let not_modified = [
+            (format!("/o/{}", png_id), "if-none-match", etag.clone()),
+            (format!("/o/{}", png_id), "if-none-match", format!("\"other\", W/{}", etag)),
+            (format!("/o/{}", png_id), "if-modified-since", last_modified.clone()),
+            (format!("/o/{}", tcf_id), "if-none-match", format!("W/\"{}\"", tcf_id)),
+            (variant_path.clone(), "if-none-match", format!("\"{}\"", variant_key.hash())),
+        ];
+        for (path, header, value) in &not_modified {
+            let response = warp::test::request().path(path).header(*header, value).reply(&api).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{} {}: {}", path, header, value);
+            assert!(response.body().is_empty());
+            assert!(response.headers().contains_key("etag"));
+            assert!(response.headers().contains_key("cache-control"));
+        }
+        assert_eq!(store.payload_reads(), reads);
+
+        let modified = [
+            (format!("/o/{}", png_id), "if-none-match", "\"other\"".to_string()),
+            (format!("/o/{}", png_id), "if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT".to_string()),
+            (format!("/o/{}/variant?w=16", png_id), "if-none-match", format!("\"{}\"", variant_key.hash())),
+        ];
+        for (path, header, value) in &modified {
+            let response = warp::test::request().path(path).header(*header, value).reply(&api).await;
+            assert_eq!(response.status(), StatusCode::OK, "{} {}: {}", path, header, value);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_variant_route_rejects_bad_requests() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+        let base = store.put(&test_png(16, 16)).unwrap();
+        let api = routes(store, CachePolicy::default(), UploadPolicy::default());
+
+        let cases = [
+            (format!("/o/{}/variant?q=0", base), StatusCode::BAD_REQUEST),
+            (format!("/o/{}/variant?crop=1,2,3", base), StatusCode::BAD_REQUEST),
+            (format!("/o/{}/variant?w=100000&h=100000", base), StatusCode::BAD_REQUEST),
+            (format!("/o/{}/variant?w={}", base, MAX_VARIANT_DIMENSION + 1), StatusCode::BAD_REQUEST),
+            (format!("/o/{}/variant?crop=8,8,16,16", base), StatusCode::UNPROCESSABLE_ENTITY),
+            ("/o/not-an-id/variant?w=8".to_string(), StatusCode::BAD_REQUEST),
+            (format!("/o/{}/variant?w=8", ContentId::for_content(b"missing")), StatusCode::NOT_FOUND),
+        ];
+        for (path, status) in cases {
+            let response = warp::test::request().path(&path).reply(&api).await;
+            assert_eq!(response.status(), status, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_derived_variant_side_is_capped() {
+        let tall = test_png(2, 64);
+        let params = |width| VariantParams { quality: None, width: Some(width), height: None, crop: None };
+        assert!(transcode(&tall, &params(MAX_VARIANT_DIMENSION)).unwrap_err().to_string().contains("aspect ratio"));
+        let img = IcfCodec::new().decode(&transcode(&tall, &params(4)).unwrap()).unwrap();
+        assert_eq!((img.width(), img.height()), (4, 128));
+    }
+}
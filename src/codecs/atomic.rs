@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Write `data` to `path` so readers see either the old file or all of the new one
+pub fn write_atomic<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<()> {
+    let mut writer = AtomicFileWriter::create(path)?;
+    writer.write_all(data)?;
+    writer.commit()
+}
+
+/// A file written under a temporary name beside `path`, and renamed over
+/// it only by `commit`
+///
+/// Dropping the writer without committing, as an error return does,
+/// removes the temporary file and leaves `path` untouched. On Unix the
+/// file and its directory are synced, so a crash after `commit` can't
+/// leave an empty or partial file under the final name either.
+pub struct AtomicFileWriter {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl AtomicFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut temp_name = path.file_name()
+            .with_context(|| format!("Not a file path: {}", path.display()))?
+            .to_os_string();
+        temp_name.push(format!(".tmp-{}-{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let temp_path = path.with_file_name(temp_name);
+        let file = File::create(&temp_path).with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        Ok(Self { path, temp_path, file: Some(BufWriter::new(file)) })
+    }
+
+    /// Flush, sync and rename the file into place
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().expect("writer is only emptied by commit");
+        let file = file.into_inner().map_err(|error| error.into_error())
+            .and_then(|file| file.sync_all().map(|_| file))
+            .with_context(|| format!("Failed to write {}", self.temp_path.display()))?;
+        drop(file);
+        // std's rename replaces an existing destination on Windows as well
+        if let Err(error) = fs::rename(&self.temp_path, &self.path) {
+            let _ = fs::remove_file(&self.temp_path);
+            return Err(error).with_context(|| format!("Failed to write {}", self.path.display()));
+        }
+        Self::sync_parent(&self.path).with_context(|| format!("Failed to sync the directory of {}", self.path.display()))
+    }
+
+    #[cfg(unix)]
+    fn sync_parent(path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+            _ => File::open(".")?.sync_all(),
+        }
+    }
+
+    /// Directories can't be opened for syncing; the rename is as durable as it gets
+    #[cfg(not(unix))]
+    fn sync_parent(_path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for AtomicFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("writer is only emptied by commit").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("writer is only emptied by commit").flush()
+    }
+}
+
+impl Drop for AtomicFileWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// Yields `good` bytes, then fails
+    struct FailingReader {
+        good: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.good == 0 {
+                return Err(io::Error::other("source went away"));
+            }
+            let n = buf.len().min(self.good);
+            buf[..n].fill(b'x');
+            self.good -= n;
+            Ok(n)
+        }
+    }
+
+    fn copy_atomic(path: &Path, mut source: impl Read) -> Result<()> {
+        let mut writer = AtomicFileWriter::create(path)?;
+        io::copy(&mut source, &mut writer)?;
+        writer.commit()
+    }
+
+    #[test]
+    fn test_failed_write_leaves_destination_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let fresh = dir.path().join("fresh.tcf");
+        let existing = dir.path().join("existing.tcf");
+        fs::write(&existing, b"previous").unwrap();
+
+        for path in [&fresh, &existing] {
+            assert!(copy_atomic(path, FailingReader { good: 100_000 }).is_err());
+        }
+        assert!(!fresh.exists());
+        assert_eq!(fs::read(&existing).unwrap(), b"previous");
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        copy_atomic(&existing, FailingReader { good: 10 }.take(10)).unwrap();
+        assert_eq!(fs::read(&existing).unwrap(), b"xxxxxxxxxx");
+        write_atomic(&fresh, b"new").unwrap();
+        assert_eq!(fs::read(&fresh).unwrap(), b"new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
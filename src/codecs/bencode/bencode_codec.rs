@@ -1,4 +1,6 @@
 use super::bencode_value::BencodeValue;
+use super::dictionary::BencodeDict;
+use super::writer::BencodeWriter;
 use std::collections::HashMap;
 use thiserror::Error;
 use anyhow::{Result, Context};
@@ -37,20 +39,73 @@ pub struct BencodeCodec;
 
 impl BencodeCodec {
     /// Encode a BencodeValue to bencode format
+    ///
+    /// The output buffer is sized exactly from `encoded_size`, so encoding
+    /// never reallocates.
     pub fn encode(value: &BencodeValue) -> Result<Vec<u8>> {
-        let mut result = Vec::with_capacity(value.encoded_size());
+        let size = value.encoded_size();
+        let mut result = Vec::with_capacity(size);
         Self::encode_to_writer(value, &mut result)?;
+        debug_assert_eq!(result.len(), size, "encoded_size disagrees with encoder output");
+        Ok(result)
+    }
+
+    /// Encode like `encode`, checking every nested value's output against
+    /// its `encoded_size`
+    ///
+    /// A mismatch is reported as `BencodeError::InvalidFormat` rather than
+    /// returned as a buffer that decodes differently. Sizing each level again
+    /// makes this slower than `encode` on deeply nested values.
+    pub fn encode_verified(value: &BencodeValue) -> Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(value.encoded_size());
+        Self::encode_checked(value, &mut result)?;
         Ok(result)
     }
 
+    fn encode_checked(value: &BencodeValue, out: &mut Vec<u8>) -> Result<()> {
+        let start = out.len();
+        match value {
+            BencodeValue::List(l) => {
+                out.push(b'l');
+                for item in l {
+                    Self::encode_checked(item, out)?;
+                }
+                out.push(b'e');
+            }
+            BencodeValue::Dictionary(d) => {
+                out.push(b'd');
+                for (key, value) in d {
+                    Self::write_number(out, None, false, key.len() as u64, b':')?;
+                    out.extend_from_slice(key);
+                    Self::encode_checked(value, out)?;
+                }
+                out.push(b'e');
+            }
+            _ => Self::encode_to_writer(value, out)?,
+        }
+
+        let (written, declared) = (out.len() - start, value.encoded_size());
+        if written != declared {
+            return Err(BencodeError::InvalidFormat(format!(
+                "value at offset {} wrote {} bytes, encoded_size declared {}", start, written, declared
+            )).into());
+        }
+        Ok(())
+    }
+
     /// Encode a BencodeValue to a writer (for streaming)
+    ///
+    /// Nested lists and dictionaries reserve nothing of their own: a writer
+    /// has no buffer to reserve in, and `encode`'s buffer is sized for the
+    /// whole value up front, every container included. Sizing each
+    /// container again would cost a pass over its subtree per level.
     pub fn encode_to_writer<W: std::io::Write>(value: &BencodeValue, writer: &mut W) -> Result<()> {
         match value {
             BencodeValue::Integer(i) => {
-                write!(writer, "i{}e", i)?;
+                Self::write_number(writer, Some(b'i'), *i < 0, i.unsigned_abs(), b'e')?;
             }
             BencodeValue::ByteString(s) => {
-                write!(writer, "{}:", s.len())?;
+                Self::write_number(writer, None, false, s.len() as u64, b':')?;
                 writer.write_all(s)?;
             }
             BencodeValue::List(l) => {
@@ -62,17 +117,11 @@ impl BencodeCodec {
             }
             BencodeValue::Dictionary(d) => {
                 writer.write_all(b"d")?;
-                
-                // Sort keys for deterministic output (Bencode requirement)
-                let mut sorted_keys: Vec<_> = d.keys().collect();
-                sorted_keys.sort();
-                
-                for key in sorted_keys {
-                    // Encode key as byte string
-                    write!(writer, "{}:", key.len())?;
+                // Entries are kept in key order, as bencode requires
+                for (key, value) in d {
+                    Self::write_number(writer, None, false, key.len() as u64, b':')?;
                     writer.write_all(key)?;
-                    // Encode value
-                    Self::encode_to_writer(d.get(key).unwrap(), writer)?;
+                    Self::encode_to_writer(value, writer)?;
                 }
                 writer.write_all(b"e")?;
             }
@@ -80,6 +129,43 @@ impl BencodeCodec {
         Ok(())
     }
 
+    /// Write an optional prefix, a decimal number and a terminator in one call
+    ///
+    /// Formats into a stack buffer instead of going through `write!`, which
+    /// keeps integer-heavy documents (piece lists, scrape responses) cheap.
+    pub(super) fn write_number<W: std::io::Write>(
+        writer: &mut W,
+        prefix: Option<u8>,
+        negative: bool,
+        magnitude: u64,
+        terminator: u8,
+    ) -> std::io::Result<()> {
+        // Prefix + sign + 20 digits of u64::MAX + terminator
+        let mut buffer = [0u8; 23];
+        let mut start = buffer.len() - 1;
+        buffer[start] = terminator;
+
+        let mut remaining = magnitude;
+        loop {
+            start -= 1;
+            buffer[start] = b'0' + (remaining % 10) as u8;
+            remaining /= 10;
+            if remaining == 0 {
+                break;
+            }
+        }
+        if negative {
+            start -= 1;
+            buffer[start] = b'-';
+        }
+        if let Some(prefix) = prefix {
+            start -= 1;
+            buffer[start] = prefix;
+        }
+
+        writer.write_all(&buffer[start..])
+    }
+
     /// Decode bencode data to a BencodeValue
     pub fn decode(data: &[u8]) -> Result<BencodeValue> {
         let mut position = 0;
@@ -191,7 +277,7 @@ impl BencodeCodec {
         }
         *position += 1; // Skip 'd'
 
-        let mut dict = HashMap::new();
+        let mut dict = BencodeDict::new();
         while *position < data.len() && data[*position] != b'e' {
             // Decode key (must be a byte string)
             let key_value = Self::decode_value(data, position)?;
@@ -216,46 +302,52 @@ impl BencodeCodec {
     }
 
     /// Create a bencode file format with metadata
+    ///
+    /// `content` and `metadata` are written in place rather than copied
+    /// into a wrapper dictionary, so large values are never cloned.
     pub fn create_file_format(
         content: &BencodeValue,
         metadata: Option<&BencodeValue>
     ) -> Result<Vec<u8>> {
-        let mut dict = HashMap::new();
-        
-        // Add content
-        dict.insert(b"content".to_vec(), content.clone());
-        
-        // Add metadata if provided
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        // Keys in sorted order, as the wrapper dictionary would hold them
+        let created = BencodeValue::integer(created);
+        let format = BencodeValue::string("bencode");
+        let version = BencodeValue::integer(1);
+        let mut fields = vec![("content", content), ("created", &created), ("format", &format)];
         if let Some(meta) = metadata {
-            dict.insert(b"metadata".to_vec(), meta.clone());
+            fields.push(("metadata", meta));
         }
-        
-        // Add file format info
-        dict.insert(b"format".to_vec(), BencodeValue::string("bencode"));
-        dict.insert(b"version".to_vec(), BencodeValue::integer(1));
-        dict.insert(b"created".to_vec(), BencodeValue::integer(
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64
-        ));
-        
-        let file_value = BencodeValue::Dictionary(dict);
-        Self::encode(&file_value)
+        fields.push(("version", &version));
+        let fields: Vec<(BencodeValue, &BencodeValue)> = fields.into_iter()
+            .map(|(key, value)| (BencodeValue::string(key), value))
+            .collect();
+
+        let size = 2 + fields.iter().map(|(key, value)| key.encoded_size() + value.encoded_size()).sum::<usize>();
+        let mut writer = BencodeWriter::new(Vec::with_capacity(size));
+        writer.begin_dictionary()?;
+        for (key, value) in &fields {
+            writer.write_value(key)?;
+            writer.write_value(value)?;
+        }
+        writer.end()?;
+        writer.finish()
     }
 
     /// Parse a bencode file format and extract content
     pub fn parse_file_format(data: &[u8]) -> Result<(BencodeValue, Option<BencodeValue>)> {
-        let file_value = Self::decode(data)?;
-        
-        let dict = file_value.as_dictionary()
-            .ok_or_else(|| BencodeError::InvalidFormat("File must be a dictionary".to_string()))?;
+        let BencodeValue::Dictionary(mut dict) = Self::decode(data)? else {
+            return Err(BencodeError::InvalidFormat("File must be a dictionary".to_string()).into());
+        };
         
-        let content = dict.get(b"content".as_slice())
-            .ok_or_else(|| BencodeError::InvalidFormat("Missing 'content' field".to_string()))?
-            .clone();
+        let content = dict.remove(b"content".as_slice())
+            .ok_or_else(|| BencodeError::InvalidFormat("Missing 'content' field".to_string()))?;
         
-        let metadata = dict.get(b"metadata".as_slice()).cloned();
+        let metadata = dict.remove(b"metadata".as_slice());
         
         Ok((content, metadata))
     }
@@ -373,6 +465,76 @@ mod tests {
         
         assert_eq!(parsed_content, content);
         assert!(parsed_metadata.is_some());
+        // Written in canonical order, as encoding a wrapper dictionary would
+        assert_eq!(BencodeCodec::encode(&BencodeCodec::decode(&file_data).unwrap()).unwrap(), file_data);
+    }
+
+    /// Deterministic generator for nested values exercising digit-count boundaries
+    fn generate_corpus_value(seed: &mut u64, depth: usize) -> BencodeValue {
+        const INTEGERS: [i64; 10] = [0, 9, 10, -1, -9, -10, 99, i64::MAX, i64::MIN, i64::MIN + 1];
+
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let choice = (*seed >> 33) as usize;
+        let kind = if depth == 0 { choice % 2 } else { choice % 4 };
+
+        match kind {
+            0 => {
+                if choice.is_multiple_of(3) {
+                    BencodeValue::integer(INTEGERS[choice % INTEGERS.len()])
+                } else {
+                    BencodeValue::integer((*seed as i64).wrapping_shr((choice % 64) as u32))
+                }
+            }
+            1 => {
+                let len = [0, 1, 9, 10, 99, 100, 1000][choice % 7];
+                BencodeValue::byte_string((0..len).map(|b| (b * 31 + choice) as u8).collect())
+            }
+            2 => {
+                let len = choice % 6;
+                BencodeValue::list((0..len).map(|_| generate_corpus_value(seed, depth - 1)).collect())
+            }
+            _ => {
+                let len = choice % 6;
+                let mut dict = HashMap::new();
+                for index in 0..len {
+                    let key = format!("key{}", index * 7 + choice % 13).into_bytes();
+                    dict.insert(key, generate_corpus_value(seed, depth - 1));
+                }
+                BencodeValue::dictionary(dict)
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoded_size_matches_encode() {
+        let mut seed = 0x5eed;
+        for _ in 0..500 {
+            let value = generate_corpus_value(&mut seed, 4);
+            let encoded = BencodeCodec::encode(&value).unwrap();
+            assert_eq!(value.encoded_size(), encoded.len());
+            // Nested containers fit in the one reservation, never growing it
+            assert_eq!(encoded.capacity(), encoded.len());
+
+            let decoded = BencodeCodec::decode(&encoded).unwrap();
+            assert_eq!(decoded, value);
+        }
+    }
+
+    #[test]
+    fn test_encode_verified_matches_encode() {
+        let mut seed = 0xfeed;
+        for _ in 0..100 {
+            let value = generate_corpus_value(&mut seed, 4);
+            assert_eq!(BencodeCodec::encode_verified(&value).unwrap(), BencodeCodec::encode(&value).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_integer_extremes() {
+        for value in [i64::MIN, i64::MAX, 0, -1] {
+            let encoded = BencodeCodec::encode(&BencodeValue::integer(value)).unwrap();
+            assert_eq!(encoded, format!("i{}e", value).into_bytes());
+        }
     }
 
     #[test]
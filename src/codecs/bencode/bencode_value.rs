@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use super::dictionary::BencodeDict;
 use std::fmt;
 
 /// Represents a Bencode value
@@ -8,7 +8,7 @@ pub enum BencodeValue {
     Integer(i64),
     ByteString(Vec<u8>),
     List(Vec<BencodeValue>),
-    Dictionary(HashMap<Vec<u8>, BencodeValue>),
+    Dictionary(BencodeDict),
 }
 
 impl BencodeValue {
@@ -32,9 +32,9 @@ impl BencodeValue {
         BencodeValue::List(value)
     }
 
-    /// Create a new dictionary value
-    pub fn dictionary(value: HashMap<Vec<u8>, BencodeValue>) -> Self {
-        BencodeValue::Dictionary(value)
+    /// Create a new dictionary value from a `HashMap`, `BTreeMap` or `BencodeDict`
+    pub fn dictionary(value: impl Into<BencodeDict>) -> Self {
+        BencodeValue::Dictionary(value.into())
     }
 
     /// Get the value as an integer if possible
@@ -70,7 +70,7 @@ impl BencodeValue {
     }
 
     /// Get the value as a dictionary if possible
-    pub fn as_dictionary(&self) -> Option<&HashMap<Vec<u8>, BencodeValue>> {
+    pub fn as_dictionary(&self) -> Option<&BencodeDict> {
         match self {
             BencodeValue::Dictionary(d) => Some(d),
             _ => None,
@@ -85,25 +85,44 @@ impl BencodeValue {
         }
     }
 
-    /// Get the estimated encoded size in bytes
+    /// Get the exact encoded size in bytes
+    ///
+    /// Digit counts are computed arithmetically, so sizing a large tree
+    /// performs no allocations and always equals `BencodeCodec::encode(v).len()`.
     pub fn encoded_size(&self) -> usize {
         match self {
             BencodeValue::Integer(i) => {
-                format!("i{}e", i).len()
+                2 + Self::integer_len(*i) // "i" + digits + "e"
             }
             BencodeValue::ByteString(s) => {
-                format!("{}:", s.len()).len() + s.len()
+                Self::length_prefix_len(s.len()) + s.len()
             }
             BencodeValue::List(l) => {
                 2 + l.iter().map(|v| v.encoded_size()).sum::<usize>() // "l" + content + "e"
             }
             BencodeValue::Dictionary(d) => {
                 2 + d.iter().map(|(k, v)| {
-                    format!("{}:", k.len()).len() + k.len() + v.encoded_size()
+                    Self::length_prefix_len(k.len()) + k.len() + v.encoded_size()
                 }).sum::<usize>() // "d" + content + "e"
             }
         }
     }
+
+    /// Length of the decimal representation of an integer, including the minus sign
+    fn integer_len(value: i64) -> usize {
+        let sign = usize::from(value < 0);
+        // unsigned_abs handles i64::MIN without overflowing
+        sign + Self::decimal_len(value.unsigned_abs())
+    }
+
+    /// Length of a byte string's "<length>:" prefix
+    fn length_prefix_len(len: usize) -> usize {
+        Self::decimal_len(len as u64) + 1
+    }
+
+    fn decimal_len(value: u64) -> usize {
+        value.checked_ilog10().map_or(1, |digits| digits as usize + 1)
+    }
 }
 
 impl fmt::Display for BencodeValue {
@@ -111,7 +130,7 @@ impl fmt::Display for BencodeValue {
         match self {
             BencodeValue::Integer(i) => write!(f, "{}", i),
             BencodeValue::ByteString(s) => {
-                if let Ok(string) = String::from_utf8(s.clone()) {
+                if let Ok(string) = std::str::from_utf8(s) {
                     write!(f, "\"{}\"", string)
                 } else {
                     write!(f, "<{} bytes>", s.len())
@@ -151,6 +170,7 @@ impl fmt::Display for BencodeValue {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn test_integer_value() {
@@ -184,6 +204,26 @@ mod tests {
         assert_eq!(value.get_dict_value("key").unwrap().as_string().unwrap(), "value");
     }
 
+    #[test]
+    fn test_encoded_size_digit_counts() {
+        let cases: [(i64, usize); 8] = [
+            (0, 3),                 // "i0e"
+            (9, 3),                 // "i9e"
+            (10, 4),                // "i10e"
+            (-1, 4),                // "i-1e"
+            (-10, 5),               // "i-10e"
+            (i64::MAX, 21),         // "i9223372036854775807e"
+            (i64::MIN, 22),         // "i-9223372036854775808e"
+            (i64::MIN + 1, 22),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(BencodeValue::integer(value).encoded_size(), expected, "value {}", value);
+        }
+
+        assert_eq!(BencodeValue::byte_string(Vec::new()).encoded_size(), 2); // "0:"
+        assert_eq!(BencodeValue::byte_string(vec![0; 10]).encoded_size(), 13); // "10:" + 10
+    }
+
     #[test]
     fn test_display() {
         let value = BencodeValue::integer(42);
@@ -0,0 +1,299 @@
+//! Rust type definitions inferred from sample bencode documents
+//!
+//! `infer` unifies the structure of one or more samples into a `Schema`,
+//! which `Schema::render` writes out as serde-derived structs: dictionary
+//! keys become snake_case fields renamed to the original key, integers
+//! `i64`, byte strings `String` when every sample was UTF-8 and
+//! `serde_bytes::ByteBuf` otherwise, and lists `Vec`s of their unified
+//! element type. A key missing from any sample makes its field an `Option`.
+use super::bencode_value::BencodeValue;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+/// Unified structure of every value seen at one position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Integer,
+    /// Byte strings; `utf8` if all of them were valid UTF-8
+    Bytes { utf8: bool },
+    /// Lists of the shape of their items; `None` while every list was empty
+    List(Option<Box<Shape>>),
+    /// Dictionaries, fields in key order
+    Dictionary(Vec<Field>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub key: String,
+    pub shape: Shape,
+    /// Missing from at least one dictionary
+    pub optional: bool,
+}
+
+/// Structure shared by a set of samples, each a dictionary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    pub root: Vec<Field>,
+    pub samples: usize,
+}
+
+/// Unify the structure of `samples`
+///
+/// Fails on an empty set, on a sample that isn't a dictionary, on keys
+/// that aren't UTF-8, and where samples disagree on a value's type.
+pub fn infer(samples: &[BencodeValue]) -> Result<Schema> {
+    let mut root: Option<Shape> = None;
+    for (index, sample) in samples.iter().enumerate() {
+        if !matches!(sample, BencodeValue::Dictionary(_)) {
+            bail!("Sample {} is not a dictionary", index);
+        }
+        let shape = Shape::of(sample, "")?;
+        root = Some(match root {
+            None => shape,
+            Some(root) => root.merge(shape, "")?,
+        });
+    }
+    match root.context("No samples to infer types from")? {
+        Shape::Dictionary(root) => Ok(Schema { root, samples: samples.len() }),
+        _ => unreachable!("samples are dictionaries"),
+    }
+}
+
+impl Shape {
+    fn of(value: &BencodeValue, path: &str) -> Result<Self> {
+        Ok(match value {
+            BencodeValue::Integer(_) => Shape::Integer,
+            BencodeValue::ByteString(bytes) => Shape::Bytes { utf8: std::str::from_utf8(bytes).is_ok() },
+            BencodeValue::List(items) => {
+                let item_path = format!("{}[]", path);
+                let mut item_shape: Option<Shape> = None;
+                for item in items {
+                    let shape = Shape::of(item, &item_path)?;
+                    item_shape = Some(match item_shape {
+                        None => shape,
+                        Some(previous) => previous.merge(shape, &item_path)?,
+                    });
+                }
+                Shape::List(item_shape.map(Box::new))
+            }
+            BencodeValue::Dictionary(dict) => {
+                let mut fields = Vec::with_capacity(dict.len());
+                for (key, value) in dict.iter() {
+                    let key = String::from_utf8(key.clone())
+                        .map_err(|_| anyhow::anyhow!("Key {:?} at {} is not UTF-8", String::from_utf8_lossy(key), display_path(path)))?;
+                    let shape = Shape::of(value, &join(path, &key))?;
+                    fields.push(Field { key, shape, optional: false });
+                }
+                fields.sort_by(|a, b| a.key.cmp(&b.key));
+                Shape::Dictionary(fields)
+            }
+        })
+    }
+
+    /// The shape covering both `self` and `other`
+    fn merge(self, other: Shape, path: &str) -> Result<Self> {
+        Ok(match (self, other) {
+            (Shape::Integer, Shape::Integer) => Shape::Integer,
+            (Shape::Bytes { utf8: a }, Shape::Bytes { utf8: b }) => Shape::Bytes { utf8: a && b },
+            (Shape::List(a), Shape::List(b)) => Shape::List(match (a, b) {
+                (Some(a), Some(b)) => Some(Box::new(a.merge(*b, &format!("{}[]", path))?)),
+                (a, b) => a.or(b),
+            }),
+            (Shape::Dictionary(a), Shape::Dictionary(b)) => {
+                let mut only_a: BTreeMap<String, Field> = a.into_iter().map(|field| (field.key.clone(), field)).collect();
+                let mut fields = BTreeMap::new();
+                for field in b {
+                    let field = match only_a.remove(&field.key) {
+                        Some(existing) => Field {
+                            shape: existing.shape.merge(field.shape, &join(path, &field.key))?,
+                            optional: existing.optional || field.optional,
+                            key: field.key,
+                        },
+                        None => Field { optional: true, ..field },
+                    };
+                    fields.insert(field.key.clone(), field);
+                }
+                fields.extend(only_a.into_iter().map(|(key, field)| (key, Field { optional: true, ..field })));
+                Shape::Dictionary(fields.into_values().collect())
+            }
+            (a, b) => bail!("Samples disagree at {}: {} and {}", display_path(path), a.kind(), b.kind()),
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Shape::Integer => "integer",
+            Shape::Bytes { .. } => "byte string",
+            Shape::List(_) => "list",
+            Shape::Dictionary(_) => "dictionary",
+        }
+    }
+}
+
+impl Schema {
+    /// A Rust module defining `root_name` and the structs nested in it
+    pub fn render(&self, root_name: &str) -> String {
+        let mut names = HashSet::new();
+        let mut pending = VecDeque::new();
+        pending.push_back((unique_name(&type_name(root_name), &mut names), &self.root));
+
+        let mut out = String::new();
+        let _ = writeln!(out, "// Generated by `bencode-cli codegen` from {} sample(s)", self.samples);
+        out.push('\n');
+        out.push_str("use serde::{Deserialize, Serialize};\n");
+        while let Some((name, fields)) = pending.pop_front() {
+            out.push('\n');
+            out.push_str("#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]\n");
+            let _ = writeln!(out, "pub struct {} {{", name);
+            let mut field_names = HashSet::new();
+            for field in fields {
+                let ident = unique_name(&field_name(&field.key), &mut field_names);
+                let mut rust_type = rust_type(&field.shape, &field.key, &mut names, &mut pending);
+                let mut attributes = Vec::new();
+                if ident.trim_start_matches("r#") != field.key {
+                    attributes.push(format!("rename = {:?}", field.key));
+                }
+                if field.optional {
+                    attributes.push("default, skip_serializing_if = \"Option::is_none\"".to_string());
+                    rust_type = format!("Option<{}>", rust_type);
+                }
+                if !attributes.is_empty() {
+                    let _ = writeln!(out, "    #[serde({})]", attributes.join(", "));
+                }
+                let _ = writeln!(out, "    pub {}: {},", ident, rust_type);
+            }
+            out.push_str("}\n");
+        }
+        out
+    }
+}
+
+/// Type of a field named `key`, queueing the structs it needs
+fn rust_type<'a>(
+    shape: &'a Shape,
+    key: &str,
+    names: &mut HashSet<String>,
+    pending: &mut VecDeque<(String, &'a Vec<Field>)>,
+) -> String {
+    match shape {
+        Shape::Integer => "i64".to_string(),
+        Shape::Bytes { utf8: true } => "String".to_string(),
+        Shape::Bytes { utf8: false } => "serde_bytes::ByteBuf".to_string(),
+        // Empty lists say nothing of their items; bytes are the likeliest guess
+        Shape::List(None) => "Vec<serde_bytes::ByteBuf>".to_string(),
+        Shape::List(Some(item)) => {
+            let singular = key.strip_suffix('s').filter(|stem| !stem.is_empty()).unwrap_or(key);
+            format!("Vec<{}>", rust_type(item, singular, names, pending))
+        }
+        Shape::Dictionary(fields) => {
+            let name = unique_name(&type_name(key), names);
+            pending.push_back((name.clone(), fields));
+            name
+        }
+    }
+}
+
+/// Lowercase words of `key`, split at punctuation and lower-to-upper case changes
+fn words(key: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous_lower = false;
+    for c in key.chars() {
+        if !c.is_alphanumeric() {
+            words.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+            previous_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && previous_lower {
+            words.push(std::mem::take(&mut word));
+        }
+        previous_lower = c.is_lowercase() || c.is_numeric();
+        word.extend(c.to_lowercase());
+    }
+    words.extend((!word.is_empty()).then_some(word));
+    words
+}
+
+fn field_name(key: &str) -> String {
+    let name = words(key).join("_");
+    match name.as_str() {
+        "" => "field".to_string(),
+        name if name.starts_with(|c: char| c.is_numeric()) => format!("field_{}", name),
+        "self" | "super" | "crate" => format!("{}_", name),
+        name if KEYWORDS.contains(&name) => format!("r#{}", name),
+        name => name.to_string(),
+    }
+}
+
+fn type_name(key: &str) -> String {
+    let name: String = words(key).iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect();
+    match name {
+        name if name.is_empty() || name.starts_with(|c: char| c.is_numeric()) => format!("Item{}", name),
+        name if name == "Self" => "SelfItem".to_string(),
+        name => name,
+    }
+}
+
+/// `name`, or `name` with the first free numeric suffix from 2
+fn unique_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let name = (1..)
+        .map(|n| if n == 1 { name.to_string() } else { format!("{}{}", name, n) })
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_default();
+    taken.insert(name.clone());
+    name
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) }
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() { "<root>" } else { path }
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen",
+    "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::BencodeCodec;
+
+    #[test]
+    fn test_fields_are_renamed_and_optional_across_samples() {
+        let samples = [
+            BencodeCodec::decode(b"d10:piece sizei5e4:type1:a9:userAgent2:x1e").unwrap(),
+            BencodeCodec::decode(b"d5:peersld4:porti1eee10:piece sizei6e4:type1:be").unwrap(),
+        ];
+        let schema = infer(&samples).unwrap();
+        let code = schema.render("Message");
+        assert!(code.contains("    #[serde(rename = \"piece size\")]\n    pub piece_size: i64,"), "{}", code);
+        assert!(code.contains("    pub r#type: String,"), "{}", code);
+        assert!(code.contains("    #[serde(rename = \"userAgent\", default, skip_serializing_if = \"Option::is_none\")]\n    pub user_agent: Option<String>,"), "{}", code);
+        assert!(code.contains("    pub peers: Option<Vec<Peer>>,"), "{}", code);
+        assert!(code.contains("pub struct Peer {\n    pub port: i64,\n}"), "{}", code);
+    }
+
+    #[test]
+    fn test_conflicting_samples_are_rejected() {
+        let samples = [
+            BencodeCodec::decode(b"d4:infod4:sizei1eee").unwrap(),
+            BencodeCodec::decode(b"d4:infod4:size2:10ee").unwrap(),
+        ];
+        let error = infer(&samples).unwrap_err().to_string();
+        assert_eq!(error, "Samples disagree at info.size: integer and byte string");
+        assert!(infer(&[]).is_err());
+        assert!(infer(&[BencodeValue::integer(1)]).is_err());
+    }
+}
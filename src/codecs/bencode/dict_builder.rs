@@ -0,0 +1,269 @@
+use super::bencode_codec::{BencodeCodec, BencodeError};
+use super::bencode_value::BencodeValue;
+use anyhow::{Context, Result};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Runs merged at once; more are first merged into larger runs, which
+/// keeps the number of open files bounded
+pub const MERGE_FAN_IN: usize = 64;
+
+/// Bookkeeping counted per buffered entry on top of its bytes
+const ENTRY_OVERHEAD: usize = std::mem::size_of::<(Vec<u8>, Vec<u8>)>();
+
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Assembles one dictionary from entries arriving in any order, in bounded memory
+///
+/// Values are encoded as they are inserted. Once the buffered keys and
+/// encoded values exceed `mem_budget` bytes they are sorted and spilled to a
+/// run file in `spill_dir`; `finish_to_writer` merges the runs into a single
+/// canonical dictionary written straight to its output. Apart from the
+/// budget, the merge holds a read buffer per run, at most `MERGE_FAN_IN`
+/// at a time. Duplicate keys are rejected. Run files are removed when the
+/// builder is finished or dropped.
+pub struct DictBuilder {
+    spill_dir: PathBuf,
+    mem_budget: usize,
+    /// Entries since the last spill, as (key, encoded value)
+    pending: Vec<(Vec<u8>, Vec<u8>)>,
+    pending_bytes: usize,
+    runs: Vec<PathBuf>,
+    len: u64,
+}
+
+impl DictBuilder {
+    pub fn new(spill_dir: impl Into<PathBuf>, mem_budget: usize) -> Self {
+        Self {
+            spill_dir: spill_dir.into(),
+            mem_budget,
+            pending: Vec::new(),
+            pending_bytes: 0,
+            runs: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Entries inserted so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Run files currently on disk
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: BencodeValue) -> Result<()> {
+        let encoded = BencodeCodec::encode(&value)?;
+        self.pending_bytes += key.len() + encoded.len() + ENTRY_OVERHEAD;
+        self.pending.push((key, encoded));
+        self.len += 1;
+        if self.pending_bytes > self.mem_budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Merge everything inserted into one dictionary written to `writer`,
+    /// returning the number of entries
+    pub fn finish_to_writer<W: Write>(mut self, writer: W) -> Result<u64> {
+        // Narrow the runs until one merge can take them all alongside the
+        // entries still in memory
+        while self.runs.len() >= MERGE_FAN_IN {
+            let group: Vec<PathBuf> = self.runs.drain(..MERGE_FAN_IN).collect();
+            let merged = group.iter()
+                .map(Self::open_run)
+                .collect::<Result<Vec<_>>>()
+                .and_then(|readers| self.create_run(|out| {
+                    merge(readers, |key, value| write_entry(out, key, value)).map(|_| ())
+                }));
+            for path in group {
+                let _ = fs::remove_file(path);
+            }
+            self.runs.push(merged?);
+        }
+
+        let mut readers = self.runs.iter().map(Self::open_run).collect::<Result<Vec<_>>>()?;
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        readers.push(RunReader::Memory(pending.into_iter()));
+
+        let mut out = BufWriter::new(writer);
+        out.write_all(b"d")?;
+        let count = merge(readers, |key, value| {
+            write!(out, "{}:", key.len())?;
+            out.write_all(key)?;
+            out.write_all(value)
+        })?;
+        out.write_all(b"e")?;
+        out.flush()?;
+        Ok(count)
+    }
+
+    /// Sort the buffered entries into a new run file
+    fn spill(&mut self) -> Result<()> {
+        let mut pending = std::mem::take(&mut self.pending);
+        self.pending_bytes = 0;
+        pending.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let path = self.create_run(|out| {
+            for (key, value) in &pending {
+                write_entry(out, key, value)?;
+            }
+            Ok(())
+        })?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Create a run file in the spill directory and fill it with `fill`
+    fn create_run(&self, fill: impl FnOnce(&mut BufWriter<File>) -> Result<()>) -> Result<PathBuf> {
+        let path = self.spill_dir.join(format!(
+            "bencode-run-{}-{}", std::process::id(), RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        let written = fill(&mut out).and_then(|_| Ok(out.flush()?));
+        if let Err(err) = written {
+            drop(out);
+            let _ = fs::remove_file(&path);
+            return Err(err.context(format!("Failed to write {}", path.display())));
+        }
+        Ok(path)
+    }
+
+    fn open_run(path: &PathBuf) -> Result<RunReader> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(RunReader::File(BufReader::new(file)))
+    }
+}
+
+impl Drop for DictBuilder {
+    fn drop(&mut self) {
+        for path in self.runs.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Sorted entries of one run
+enum RunReader {
+    File(BufReader<File>),
+    Memory(std::vec::IntoIter<(Vec<u8>, Vec<u8>)>),
+}
+
+impl RunReader {
+    fn next_entry(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            RunReader::Memory(entries) => Ok(entries.next()),
+            RunReader::File(reader) => {
+                let mut length = [0u8; 4];
+                match reader.read_exact(&mut length) {
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    result => result?,
+                }
+                let key = read_bytes(reader, u32::from_le_bytes(length))?;
+                reader.read_exact(&mut length)?;
+                let value = read_bytes(reader, u32::from_le_bytes(length))?;
+                Ok(Some((key, value)))
+            }
+        }
+    }
+}
+
+fn read_bytes(reader: &mut impl Read, len: u32) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes).context("Truncated bencode run file")?;
+    Ok(bytes)
+}
+
+/// One run entry: key and encoded value, each behind a u32 LE length
+fn write_entry(out: &mut impl Write, key: &[u8], value: &[u8]) -> io::Result<()> {
+    for bytes in [key, value] {
+        let len = u32::try_from(bytes.len()).map_err(|_| io::Error::other("run entry over 4 GiB"))?;
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(bytes)?;
+    }
+    Ok(())
+}
+
+/// Feed the entries of all `runs` to `emit` in key order, failing on the
+/// first key seen twice; returns the number of entries
+fn merge(mut runs: Vec<RunReader>, mut emit: impl FnMut(&[u8], &[u8]) -> io::Result<()>) -> Result<u64> {
+    let mut heads = BinaryHeap::new();
+    for (index, run) in runs.iter_mut().enumerate() {
+        if let Some((key, value)) = run.next_entry()? {
+            heads.push(Reverse((key, index, value)));
+        }
+    }
+
+    let mut previous: Option<Vec<u8>> = None;
+    let mut count = 0;
+    while let Some(Reverse((key, index, value))) = heads.pop() {
+        if previous.as_ref() == Some(&key) {
+            return Err(BencodeError::InvalidFormat(format!(
+                "duplicate dictionary key {:?}", String::from_utf8_lossy(&key)
+            )).into());
+        }
+        emit(&key, &value)?;
+        count += 1;
+        if let Some((key, value)) = runs[index].next_entry()? {
+            heads.push(Reverse((key, index, value)));
+        }
+        previous = Some(key);
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spilled_runs_merge_into_one_canonical_dictionary() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = DictBuilder::new(dir.path(), 512 << 10);
+        let count = 1_000_000u64;
+        // Odd multiplier: a permutation of 0..2^20, so keys arrive out of order
+        for i in 0..count {
+            let scrambled = i.wrapping_mul(0x9e37_79b9) & 0xf_ffff;
+            builder.insert(format!("hash{:08x}", scrambled).into_bytes(), BencodeValue::integer(scrambled as i64)).unwrap();
+        }
+        assert!(builder.spilled_runs() > MERGE_FAN_IN, "{} runs", builder.spilled_runs());
+
+        let mut output = Vec::new();
+        assert_eq!(builder.finish_to_writer(&mut output).unwrap(), count);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let decoded = BencodeCodec::decode(&output).unwrap();
+        let dict = decoded.as_dictionary().unwrap();
+        assert_eq!(dict.len() as u64, count);
+        assert_eq!(BencodeCodec::encode(&decoded).unwrap(), output);
+        let (key, value) = dict.iter().next().unwrap();
+        assert_eq!((key.as_slice(), value.as_integer()), (&b"hash00000000"[..], Some(0)));
+    }
+
+    #[test]
+    fn test_duplicate_keys_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        // One duplicate spilled in separate runs, one within the last batch
+        for budget in [0, 1 << 20] {
+            let mut builder = DictBuilder::new(dir.path(), budget);
+            builder.insert(b"b".to_vec(), BencodeValue::integer(1)).unwrap();
+            builder.insert(b"a".to_vec(), BencodeValue::integer(2)).unwrap();
+            builder.insert(b"b".to_vec(), BencodeValue::string("again")).unwrap();
+            let error = builder.finish_to_writer(io::sink()).unwrap_err();
+            assert!(matches!(error.downcast_ref::<BencodeError>(), Some(BencodeError::InvalidFormat(_))), "{}", error);
+            assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use super::bencode_value::BencodeValue;
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::fmt;
+
+/// Entries a dictionary keeps in a sorted `Vec` before moving to a `BTreeMap`
+///
+/// Almost every real dictionary (torrent roots, info dicts, file entries,
+/// announce replies) is well under this, and a binary search over a short
+/// contiguous slice beats hashing the key.
+pub const SMALL_DICT_LIMIT: usize = 16;
+
+/// A bencode dictionary, always iterated in raw byte order of its keys
+///
+/// That is the order bencode requires on the wire, so encoding writes the
+/// entries as they come without sorting or looking keys up.
+#[derive(Clone, Default)]
+pub struct BencodeDict {
+    entries: Entries,
+}
+
+#[derive(Clone)]
+enum Entries {
+    /// Sorted by key, at most `SMALL_DICT_LIMIT` entries
+    Small(Vec<(Vec<u8>, BencodeValue)>),
+    Large(BTreeMap<Vec<u8>, BencodeValue>),
+}
+
+impl Default for Entries {
+    fn default() -> Self {
+        Entries::Small(Vec::new())
+    }
+}
+
+impl BencodeDict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        if capacity > SMALL_DICT_LIMIT {
+            return Self { entries: Entries::Large(BTreeMap::new()) };
+        }
+        Self { entries: Entries::Small(Vec::with_capacity(capacity)) }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Small(entries) => entries.len(),
+            Entries::Large(entries) => entries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&BencodeValue> {
+        let key = key.as_ref();
+        match &self.entries {
+            Entries::Small(entries) => Self::search(entries, key).ok().map(|index| &entries[index].1),
+            Entries::Large(entries) => entries.get(key),
+        }
+    }
+
+    pub fn get_mut<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<&mut BencodeValue> {
+        let key = key.as_ref();
+        match &mut self.entries {
+            Entries::Small(entries) => Self::search(entries, key).ok().map(|index| &mut entries[index].1),
+            Entries::Large(entries) => entries.get_mut(key),
+        }
+    }
+
+    pub fn contains_key<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert an entry, returning the value it replaced if the key was present
+    pub fn insert(&mut self, key: Vec<u8>, value: BencodeValue) -> Option<BencodeValue> {
+        match &mut self.entries {
+            // Decoding canonical input appends every key
+            Entries::Small(entries) if entries.len() < SMALL_DICT_LIMIT
+                && entries.last().is_none_or(|(last, _)| *last < key) =>
+            {
+                entries.push((key, value));
+                None
+            }
+            Entries::Small(entries) => match Self::search(entries, &key) {
+                Ok(index) => Some(std::mem::replace(&mut entries[index].1, value)),
+                Err(_) if entries.len() == SMALL_DICT_LIMIT => {
+                    let mut spilled: BTreeMap<_, _> = std::mem::take(entries).into_iter().collect();
+                    spilled.insert(key, value);
+                    self.entries = Entries::Large(spilled);
+                    None
+                }
+                Err(index) => {
+                    entries.insert(index, (key, value));
+                    None
+                }
+            },
+            Entries::Large(entries) => entries.insert(key, value),
+        }
+    }
+
+    pub fn remove<K: AsRef<[u8]> + ?Sized>(&mut self, key: &K) -> Option<BencodeValue> {
+        let key = key.as_ref();
+        match &mut self.entries {
+            Entries::Small(entries) => Self::search(entries, key).ok().map(|index| entries.remove(index).1),
+            Entries::Large(entries) => entries.remove(key),
+        }
+    }
+
+    /// Entries in key order
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.entries {
+            Entries::Small(entries) => Iter::Small(entries.iter()),
+            Entries::Large(entries) => Iter::Large(entries.iter()),
+        }
+    }
+
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &Vec<u8>> + DoubleEndedIterator + '_ {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl ExactSizeIterator<Item = &BencodeValue> + DoubleEndedIterator + '_ {
+        self.iter().map(|(_, value)| value)
+    }
+
+    fn search(entries: &[(Vec<u8>, BencodeValue)], key: &[u8]) -> Result<usize, usize> {
+        entries.binary_search_by(|(probe, _)| probe.as_slice().cmp(key))
+    }
+}
+
+/// Equal when the entries are, whichever representation each side uses
+impl PartialEq for BencodeDict {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for BencodeDict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Extend<(Vec<u8>, BencodeValue)> for BencodeDict {
+    fn extend<I: IntoIterator<Item = (Vec<u8>, BencodeValue)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// Later entries win over earlier ones with the same key
+impl FromIterator<(Vec<u8>, BencodeValue)> for BencodeDict {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, BencodeValue)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut dict = Self::with_capacity(iter.size_hint().0);
+        dict.extend(iter);
+        dict
+    }
+}
+
+impl From<HashMap<Vec<u8>, BencodeValue>> for BencodeDict {
+    fn from(map: HashMap<Vec<u8>, BencodeValue>) -> Self {
+        if map.len() > SMALL_DICT_LIMIT {
+            return Self { entries: Entries::Large(map.into_iter().collect()) };
+        }
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Self { entries: Entries::Small(entries) }
+    }
+}
+
+impl From<BTreeMap<Vec<u8>, BencodeValue>> for BencodeDict {
+    fn from(map: BTreeMap<Vec<u8>, BencodeValue>) -> Self {
+        if map.len() > SMALL_DICT_LIMIT {
+            return Self { entries: Entries::Large(map) };
+        }
+        Self { entries: Entries::Small(map.into_iter().collect()) }
+    }
+}
+
+/// Borrowing iterator over a `BencodeDict`, in key order
+pub enum Iter<'a> {
+    Small(std::slice::Iter<'a, (Vec<u8>, BencodeValue)>),
+    Large(btree_map::Iter<'a, Vec<u8>, BencodeValue>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Vec<u8>, &'a BencodeValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Small(entries) => entries.next().map(|(key, value)| (key, value)),
+            Iter::Large(entries) => entries.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Small(entries) => entries.size_hint(),
+            Iter::Large(entries) => entries.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Small(entries) => entries.next_back().map(|(key, value)| (key, value)),
+            Iter::Large(entries) => entries.next_back(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+/// Owning iterator over a `BencodeDict`, in key order
+pub enum IntoIter {
+    Small(std::vec::IntoIter<(Vec<u8>, BencodeValue)>),
+    Large(btree_map::IntoIter<Vec<u8>, BencodeValue>),
+}
+
+impl Iterator for IntoIter {
+    type Item = (Vec<u8>, BencodeValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            IntoIter::Small(entries) => entries.next(),
+            IntoIter::Large(entries) => entries.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            IntoIter::Small(entries) => entries.size_hint(),
+            IntoIter::Large(entries) => entries.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+impl<'a> IntoIterator for &'a BencodeDict {
+    type Item = (&'a Vec<u8>, &'a BencodeValue);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for BencodeDict {
+    type Item = (Vec<u8>, BencodeValue);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        match self.entries {
+            Entries::Small(entries) => IntoIter::Small(entries.into_iter()),
+            Entries::Large(entries) => IntoIter::Large(entries.into_iter()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::BencodeCodec;
+
+    fn key(index: usize) -> Vec<u8> {
+        format!("key{:03}", index).into_bytes()
+    }
+
+    #[test]
+    fn test_insertion_order_does_not_matter() {
+        for size in [0, 1, SMALL_DICT_LIMIT - 1, SMALL_DICT_LIMIT, SMALL_DICT_LIMIT + 1, 3 * SMALL_DICT_LIMIT] {
+            let forward: BencodeDict = (0..size).map(|i| (key(i), BencodeValue::integer(i as i64))).collect();
+            let backward: BencodeDict = (0..size).rev().map(|i| (key(i), BencodeValue::integer(i as i64))).collect();
+            // 97 is a prime larger than any size, so stepping by it visits every key once
+            let mut shuffled = BencodeDict::new();
+            for i in 0..size {
+                let index = i * 97 % size;
+                shuffled.insert(key(index), BencodeValue::integer(index as i64));
+            }
+
+            assert_eq!(forward, backward, "size {}", size);
+            assert_eq!(forward, shuffled, "size {}", size);
+            let keys: Vec<_> = shuffled.keys().cloned().collect();
+            assert_eq!(keys, (0..size).map(key).collect::<Vec<_>>());
+
+            let encoded = BencodeCodec::encode(&BencodeValue::Dictionary(shuffled)).unwrap();
+            assert_eq!(encoded, BencodeCodec::encode(&BencodeValue::Dictionary(forward)).unwrap());
+            assert_eq!(BencodeCodec::decode(&encoded).unwrap(), BencodeValue::Dictionary(backward));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys_at_spill_boundary() {
+        let mut dict: BencodeDict = (0..SMALL_DICT_LIMIT).map(|i| (key(i), BencodeValue::integer(0))).collect();
+        assert!(matches!(dict.entries, Entries::Small(_)));
+
+        // Replacing an entry of a full small dictionary must not spill
+        assert_eq!(dict.insert(key(3), BencodeValue::integer(1)), Some(BencodeValue::integer(0)));
+        assert!(matches!(dict.entries, Entries::Small(_)));
+        assert_eq!(dict.len(), SMALL_DICT_LIMIT);
+
+        // The entry that spills keeps everything, and replacing still works after
+        assert_eq!(dict.insert(key(SMALL_DICT_LIMIT), BencodeValue::integer(2)), None);
+        assert!(matches!(dict.entries, Entries::Large(_)));
+        assert_eq!(dict.insert(key(3), BencodeValue::integer(3)), Some(BencodeValue::integer(1)));
+        assert_eq!(dict.len(), SMALL_DICT_LIMIT + 1);
+        assert_eq!(dict.get(&key(3)), Some(&BencodeValue::integer(3)));
+
+        // A spilled dictionary equals a small one holding the same entries
+        dict.remove(&key(SMALL_DICT_LIMIT));
+        let small: BencodeDict = dict.clone().into_iter().collect();
+        assert!(matches!(small.entries, Entries::Small(_)));
+        assert_eq!(small, dict);
+
+        // Duplicate keys in the input keep the last value
+        let collected: BencodeDict = (0..=SMALL_DICT_LIMIT)
+            .map(|i| (key(i % SMALL_DICT_LIMIT), BencodeValue::integer(i as i64)))
+            .collect();
+        assert_eq!(collected.len(), SMALL_DICT_LIMIT);
+        assert_eq!(collected.get(&key(0)), Some(&BencodeValue::integer(SMALL_DICT_LIMIT as i64)));
+    }
+}
@@ -0,0 +1,341 @@
+//! Bencode carried inside text: magnet links, `data:` URIs and the
+//! base64, percent and hex payloads tracker APIs hand out
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use sha1::{Digest, Sha1};
+use std::fmt::Write as _;
+use thiserror::Error;
+
+use crate::codecs::bencode::{BencodeCodec, BencodeDict, BencodeValue};
+
+/// A malformed envelope or magnet link, at a byte offset of the input
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("{message} at byte {position}")]
+pub struct EnvelopeError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl EnvelopeError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self { position, message: message.into() }
+    }
+
+    /// The same error, for a slice starting `offset` bytes into the input
+    fn offset(self, offset: usize) -> Self {
+        Self { position: self.position + offset, ..self }
+    }
+}
+
+/// How bencode bytes are written as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Envelope {
+    /// Standard or URL-safe alphabet, padding optional
+    Base64,
+    /// `%XX` escapes, as in URL query strings
+    Percent,
+    Hex,
+}
+
+impl Envelope {
+    /// The bytes `input` carries
+    pub fn unwrap(self, input: &str) -> Result<Vec<u8>, EnvelopeError> {
+        match self {
+            Envelope::Base64 => decode_base64(input),
+            Envelope::Percent => percent_decode(input),
+            Envelope::Hex => decode_hex(input),
+        }
+    }
+
+    /// `bytes` as text of this envelope
+    pub fn wrap(self, bytes: &[u8]) -> String {
+        match self {
+            Envelope::Base64 => general_purpose::STANDARD.encode(bytes),
+            Envelope::Percent => percent_encode(bytes),
+            Envelope::Hex => to_hex(bytes),
+        }
+    }
+}
+
+/// Decode the bencode value `input` carries in `envelope`
+pub fn decode_enveloped(input: &str, envelope: Envelope) -> Result<BencodeValue> {
+    BencodeCodec::decode(&envelope.unwrap(input.trim())?)
+}
+
+/// Canonical bencode of `value` in `envelope`
+pub fn encode_enveloped(value: &BencodeValue, envelope: Envelope) -> Result<String> {
+    Ok(envelope.wrap(&BencodeCodec::encode(value)?))
+}
+
+/// Decode the bencode value in a `data:` URI, base64 or percent encoded
+pub fn decode_data_uri(uri: &str) -> Result<BencodeValue> {
+    let rest = uri.strip_prefix("data:").ok_or_else(|| EnvelopeError::new(0, "Expected a data: URI"))?;
+    let comma = rest.find(',').ok_or_else(|| EnvelopeError::new(uri.len(), "data: URI has no ','"))?;
+    let offset = "data:".len() + comma + 1;
+    let envelope = if rest[..comma].split(';').any(|param| param == "base64") { Envelope::Base64 } else { Envelope::Percent };
+    let bytes = envelope.unwrap(&rest[comma + 1..]).map_err(|e| e.offset(offset))?;
+    BencodeCodec::decode(&bytes)
+}
+
+/// `value` as a base64 `data:application/x-bittorrent` URI
+pub fn to_data_uri(value: &BencodeValue) -> Result<String> {
+    Ok(format!("data:application/x-bittorrent;base64,{}", encode_enveloped(value, Envelope::Base64)?))
+}
+
+/// What a BitTorrent magnet link names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagnetInfo {
+    /// SHA-1 of the torrent's `info` dictionary
+    pub info_hash: [u8; 20],
+    /// `tr` parameters, in order
+    pub trackers: Vec<String>,
+    /// The `dn` parameter
+    pub display_name: Option<String>,
+}
+
+impl MagnetInfo {
+    /// The magnet link, with a hex info hash
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("magnet:?xt=urn:btih:{}", to_hex(&self.info_hash));
+        if let Some(name) = &self.display_name {
+            let _ = write!(uri, "&dn={}", percent_encode(name.as_bytes()));
+        }
+        for tracker in &self.trackers {
+            let _ = write!(uri, "&tr={}", percent_encode(tracker.as_bytes()));
+        }
+        uri
+    }
+
+    /// The torrent skeleton a magnet link describes: `announce` and
+    /// `announce-list` from its trackers, `name`, and the 20-byte
+    /// `info hash`
+    ///
+    /// There is no `info` dictionary; clients fetch it from peers by hash
+    /// (BEP 9).
+    pub fn to_skeleton(&self) -> BencodeValue {
+        let mut entries = vec![(b"info hash".to_vec(), BencodeValue::byte_string(self.info_hash.to_vec()))];
+        if let Some(first) = self.trackers.first() {
+            entries.push((b"announce".to_vec(), BencodeValue::string(first)));
+        }
+        if self.trackers.len() > 1 {
+            let tiers = self.trackers.iter().map(|tracker| BencodeValue::list(vec![BencodeValue::string(tracker)])).collect();
+            entries.push((b"announce-list".to_vec(), BencodeValue::list(tiers)));
+        }
+        if let Some(name) = &self.display_name {
+            entries.push((b"name".to_vec(), BencodeValue::string(name)));
+        }
+        BencodeValue::dictionary(entries.into_iter().collect::<BencodeDict>())
+    }
+}
+
+/// Parse a `magnet:?` link with a BitTorrent `xt=urn:btih:` hash, hex or
+/// base32
+///
+/// Parameters other than `xt`, `tr` and `dn` are ignored. Exactly one
+/// BitTorrent `xt` must be present.
+pub fn parse_magnet(uri: &str) -> Result<MagnetInfo, EnvelopeError> {
+    const PREFIX: &str = "magnet:?";
+    if !uri.get(..PREFIX.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(PREFIX)) {
+        return Err(EnvelopeError::new(0, "Expected a magnet:? URI"));
+    }
+    let mut info_hash = None;
+    let mut trackers = Vec::new();
+    let mut display_name = None;
+    let mut start = PREFIX.len();
+    for param in uri[PREFIX.len()..].split('&') {
+        let position = start;
+        start += param.len() + 1;
+        if param.is_empty() {
+            continue;
+        }
+        let (key, value) = param.split_once('=').ok_or_else(|| EnvelopeError::new(position, "Magnet parameter has no '='"))?;
+        let value_at = position + key.len() + 1;
+        let text = || -> Result<String, EnvelopeError> {
+            let bytes = percent_decode(&value.replace('+', " ")).map_err(|e| e.offset(value_at))?;
+            String::from_utf8(bytes).map_err(|_| EnvelopeError::new(value_at, "Magnet parameter isn't UTF-8"))
+        };
+        match key {
+            "xt" => {
+                let Some(hash) = value.strip_prefix("urn:btih:") else { continue };
+                if info_hash.is_some() {
+                    return Err(EnvelopeError::new(position, "Magnet link has more than one BitTorrent info hash"));
+                }
+                let hash_at = value_at + "urn:btih:".len();
+                info_hash = Some(match hash.len() {
+                    40 => decode_hex(hash),
+                    32 => decode_base32(hash),
+                    len => Err(EnvelopeError::new(0, format!("Info hash is {} characters; expected 40 hex or 32 base32", len))),
+                }.map_err(|e| e.offset(hash_at))?);
+            }
+            "tr" => trackers.push(text()?),
+            "dn" => display_name = Some(text()?),
+            _ => {}
+        }
+    }
+    let info_hash = info_hash.ok_or_else(|| EnvelopeError::new(uri.len(), "Magnet link has no xt=urn:btih: info hash"))?;
+    Ok(MagnetInfo { info_hash: info_hash.try_into().unwrap(), trackers, display_name })
+}
+
+/// The magnet link of a decoded torrent: the SHA-1 of its canonically
+/// encoded `info`, its name and every tracker it announces to
+pub fn to_magnet(torrent: &BencodeValue) -> Result<String> {
+    let info = torrent.get_dict_value("info").context("Torrent has no info dictionary")?;
+    let info_hash = Sha1::digest(BencodeCodec::encode(info)?).into();
+    let mut trackers: Vec<String> = torrent.get_dict_value("announce").and_then(BencodeValue::as_string).into_iter().collect();
+    let tiers = torrent.get_dict_value("announce-list").and_then(BencodeValue::as_list);
+    for tracker in tiers.into_iter().flatten().filter_map(BencodeValue::as_list).flatten().filter_map(BencodeValue::as_string) {
+        if !trackers.contains(&tracker) {
+            trackers.push(tracker);
+        }
+    }
+    let display_name = info.get_dict_value("name").and_then(BencodeValue::as_string);
+    Ok(MagnetInfo { info_hash, trackers, display_name }.to_uri())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_digit(byte: u8, position: usize) -> Result<u8, EnvelopeError> {
+    (byte as char).to_digit(16)
+        .map(|digit| digit as u8)
+        .ok_or_else(|| EnvelopeError::new(position, format!("Invalid hex digit {:?}", byte as char)))
+}
+
+fn decode_hex(input: &str) -> Result<Vec<u8>, EnvelopeError> {
+    let bytes = input.as_bytes();
+    if !bytes.len().is_multiple_of(2) {
+        return Err(EnvelopeError::new(bytes.len(), "Hex has an odd number of digits"));
+    }
+    (0..bytes.len()).step_by(2)
+        .map(|i| Ok(hex_digit(bytes[i], i)? << 4 | hex_digit(bytes[i + 1], i + 1)?))
+        .collect()
+}
+
+/// RFC 4648 base32, case-insensitive, as magnet links write info hashes
+fn decode_base32(input: &str) -> Result<Vec<u8>, EnvelopeError> {
+    let mut bytes = Vec::with_capacity(input.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for (position, byte) in input.bytes().enumerate() {
+        let value = match byte.to_ascii_uppercase() {
+            upper @ b'A'..=b'Z' => upper - b'A',
+            digit @ b'2'..=b'7' => digit - b'2' + 26,
+            _ => return Err(EnvelopeError::new(position, format!("Invalid base32 digit {:?}", byte as char))),
+        };
+        buffer = buffer << 5 | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+fn decode_base64(input: &str) -> Result<Vec<u8>, EnvelopeError> {
+    let trimmed = input.trim_end_matches('=');
+    let engine = if trimmed.contains(['-', '_']) { general_purpose::URL_SAFE_NO_PAD } else { general_purpose::STANDARD_NO_PAD };
+    engine.decode(trimmed).map_err(|e| match e {
+        base64::DecodeError::InvalidByte(position, byte) => EnvelopeError::new(position, format!("Invalid base64 byte {:?}", byte as char)),
+        base64::DecodeError::InvalidLastSymbol(position, byte) => EnvelopeError::new(position, format!("Invalid last base64 symbol {:?}", byte as char)),
+        other => EnvelopeError::new(trimmed.len(), other.to_string()),
+    })
+}
+
+fn percent_decode(input: &str) -> Result<Vec<u8>, EnvelopeError> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let digits = bytes.get(i + 1..i + 3).ok_or_else(|| EnvelopeError::new(i, "Truncated % escape"))?;
+            let digit = |offset: usize| hex_digit(digits[offset], i + 1 + offset).map_err(|_| EnvelopeError::new(i, "Invalid % escape"));
+            out.push(digit(0)? << 4 | digit(1)?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Escape everything but RFC 3986 unreserved characters
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{:02X}", byte);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::info_hash;
+
+    const FIXTURE: &[u8] = include_bytes!("../../../tests/fixtures/multi_file.torrent");
+
+    #[test]
+    fn test_magnet_roundtrip_matches_info_hash() {
+        let torrent = BencodeCodec::decode(FIXTURE).unwrap();
+        let uri = to_magnet(&torrent).unwrap();
+        let magnet = parse_magnet(&uri).unwrap();
+        assert_eq!(magnet.info_hash, info_hash(FIXTURE).unwrap());
+        assert_eq!(magnet.display_name, torrent.get_dict_value("info").unwrap().get_dict_value("name").unwrap().as_string());
+        assert_eq!(magnet.to_uri(), uri);
+
+        // Several trackers, a base32 hash, and parameters we don't know
+        let hash: [u8; 20] = std::array::from_fn(|i| i as u8 * 13);
+        let base32 = "AAGRUJZUIFHFW2DVQKHZZKNWYPIN32XX";
+        let uri = format!("magnet:?xt=urn:btih:{}&dn=Two+words%21&tr=udp%3A%2F%2Fa.example%3A80&x.pe=1.2.3.4:5&tr=http://b.example/announce", base32);
+        let magnet = parse_magnet(&uri).unwrap();
+        assert_eq!(magnet.info_hash, hash);
+        assert_eq!(magnet.trackers, ["udp://a.example:80", "http://b.example/announce"]);
+        assert_eq!(magnet.display_name.as_deref(), Some("Two words!"));
+        assert_eq!(parse_magnet(&magnet.to_uri()).unwrap(), magnet);
+
+        let skeleton = magnet.to_skeleton();
+        assert_eq!(skeleton.get_dict_value("info hash").unwrap().as_byte_string().unwrap(), &hash.to_vec());
+        assert_eq!(skeleton.get_dict_value("announce").unwrap().as_string().as_deref(), Some("udp://a.example:80"));
+        assert_eq!(skeleton.get_dict_value("announce-list").unwrap().as_list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_malformed_magnets_report_positions() {
+        let hex = "0".repeat(40);
+        let cases = [
+            ("http://example.com".to_string(), 0),
+            ("magnet:?dn=x".to_string(), 12),
+            (format!("magnet:?xt=urn:btih:{}g", &hex[..39]), 59),
+            ("magnet:?xt=urn:btih:abc".to_string(), 20),
+            (format!("magnet:?xt=urn:btih:{}&tr=%2", hex), 64),
+            (format!("magnet:?xt=urn:btih:{}&bogus", hex), 61),
+            (format!("magnet:?xt=urn:btih:{0}&xt=urn:btih:{0}", hex), 61),
+        ];
+        for (uri, position) in cases {
+            let error = parse_magnet(&uri).unwrap_err();
+            assert_eq!(error.position, position, "{}: {}", uri, error);
+        }
+    }
+
+    #[test]
+    fn test_envelopes_roundtrip() {
+        let value = BencodeCodec::decode(b"d3:cow3:moo4:spaml1:a1:bee").unwrap();
+        for envelope in [Envelope::Base64, Envelope::Percent, Envelope::Hex] {
+            let text = encode_enveloped(&value, envelope).unwrap();
+            assert_eq!(decode_enveloped(&text, envelope).unwrap(), value, "{:?}", envelope);
+        }
+        assert_eq!(decode_data_uri(&to_data_uri(&value).unwrap()).unwrap(), value);
+        assert_eq!(decode_data_uri("data:,d3:cow3:mooe").unwrap(), BencodeCodec::decode(b"d3:cow3:mooe").unwrap());
+
+        let error = Envelope::Hex.unwrap("64 3a0").unwrap_err();
+        assert_eq!(error.position, 2);
+        let error = decode_data_uri("data:;base64,ZDM6Y2*3").unwrap_err().downcast::<EnvelopeError>().unwrap();
+        assert_eq!(error.position, 19);
+    }
+}
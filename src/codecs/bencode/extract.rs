@@ -0,0 +1,140 @@
+use super::bencode_codec::{BencodeCodec, BencodeError};
+use super::bencode_value::BencodeValue;
+use super::schema::child_path;
+use anyhow::Result;
+
+/// Bytes found at a path by `extract_bytes`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extracted<'a> {
+    /// The raw contents of a byte string
+    Bytes(&'a [u8]),
+    /// Canonical bencode of a node that is not a byte string
+    Encoded(Vec<u8>),
+}
+
+impl Extracted<'_> {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Extracted::Bytes(bytes) => bytes,
+            Extracted::Encoded(encoded) => encoded,
+        }
+    }
+}
+
+/// A byte string somewhere inside a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leaf {
+    pub path: String,
+    pub size: usize,
+}
+
+/// Find the node at `path`, written as validation reports paths:
+/// dictionary keys joined by `.`, list indices as `[n]`
+///
+/// The empty path is the whole document. Keys containing `.` or `[`
+/// can't be addressed.
+pub fn resolve<'a>(value: &'a BencodeValue, path: &str) -> Result<&'a BencodeValue> {
+    let mut node = value;
+    let mut rest = path;
+    let mut seen = 0;
+    while !rest.is_empty() {
+        let here = &path[..seen];
+        if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')
+                .ok_or_else(|| BencodeError::InvalidFormat(format!("unclosed '[' after '{}'", here)))?;
+            let index: usize = after[..end].parse()
+                .map_err(|_| BencodeError::InvalidFormat(format!("bad list index '{}' after '{}'", &after[..end], here)))?;
+            let list = node.as_list()
+                .ok_or_else(|| BencodeError::InvalidFormat(format!("'{}' is not a list", here)))?;
+            node = list.get(index)
+                .ok_or_else(|| BencodeError::InvalidFormat(format!("'{}' has {} items, no [{}]", here, list.len(), index)))?;
+            seen += end + 2;
+        } else {
+            let key_start = usize::from(seen > 0 && rest.starts_with('.'));
+            let key_end = rest[key_start..].find(['.', '[']).map_or(rest.len(), |end| end + key_start);
+            let key = &rest[key_start..key_end];
+            let dict = node.as_dictionary()
+                .ok_or_else(|| BencodeError::InvalidFormat(format!("'{}' is not a dictionary", here)))?;
+            node = dict.get(key.as_bytes())
+                .ok_or_else(|| BencodeError::InvalidFormat(format!("no key '{}' in '{}'", key, here)))?;
+            seen += key_end;
+        }
+        rest = &path[seen..];
+    }
+    Ok(node)
+}
+
+/// The bytes at `path`: a byte string's raw contents, or the canonical
+/// encoding of any other node
+pub fn extract_bytes<'a>(value: &'a BencodeValue, path: &str) -> Result<Extracted<'a>> {
+    Ok(match resolve(value, path)? {
+        BencodeValue::ByteString(bytes) => Extracted::Bytes(bytes),
+        node => Extracted::Encoded(BencodeCodec::encode(node)?),
+    })
+}
+
+/// Every byte string in `value` with its path, in document order
+pub fn list_leaves(value: &BencodeValue) -> Vec<Leaf> {
+    fn walk(value: &BencodeValue, path: String, leaves: &mut Vec<Leaf>) {
+        match value {
+            BencodeValue::ByteString(bytes) => leaves.push(Leaf { path, size: bytes.len() }),
+            BencodeValue::List(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    walk(item, format!("{}[{}]", path, index), leaves);
+                }
+            }
+            BencodeValue::Dictionary(dict) => {
+                for (key, item) in dict.iter() {
+                    walk(item, child_path(&path, key), leaves);
+                }
+            }
+            BencodeValue::Integer(_) => {}
+        }
+    }
+
+    let mut leaves = Vec::new();
+    walk(value, String::new(), &mut leaves);
+    leaves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_binary_payload_from_file_format() {
+        let payload: Vec<u8> = (0..=255u8).chain([0, 0xFF, b'e', b':']).collect();
+        let metadata = BencodeValue::dictionary([(b"name".to_vec(), BencodeValue::string("blob.bin"))]
+            .into_iter().collect::<std::collections::BTreeMap<_, _>>());
+        let container = BencodeCodec::create_file_format(&BencodeValue::byte_string(payload.clone()), Some(&metadata)).unwrap();
+        let document = BencodeCodec::decode(&container).unwrap();
+
+        assert_eq!(extract_bytes(&document, "content").unwrap(), Extracted::Bytes(&payload));
+        assert_eq!(extract_bytes(&document, "metadata").unwrap().as_bytes(), b"d4:name8:blob.bine");
+        assert_eq!(extract_bytes(&document, "version").unwrap().as_bytes(), b"i1e");
+        assert!(extract_bytes(&document, "missing").is_err());
+        assert!(extract_bytes(&document, "content.name").is_err());
+    }
+
+    #[test]
+    fn test_list_leaves_of_nested_document() {
+        let document = BencodeCodec::decode(
+            b"d4:infod5:filesld6:lengthi3e4:pathl1:a5:b.txteee4:name3:dir6:pieces4:\x00\x01\x02\x03e4:note0:e"
+        ).unwrap();
+        let leaves: Vec<(String, usize)> = list_leaves(&document).into_iter().map(|leaf| (leaf.path, leaf.size)).collect();
+        assert_eq!(leaves, [
+            ("info.files[0].path[0]".to_string(), 1),
+            ("info.files[0].path[1]".to_string(), 5),
+            ("info.name".to_string(), 3),
+            ("info.pieces".to_string(), 4),
+            ("note".to_string(), 0),
+        ]);
+        for (path, size) in &leaves {
+            assert_eq!(extract_bytes(&document, path).unwrap().as_bytes().len(), *size);
+        }
+        assert_eq!(extract_bytes(&document, "info.files[0].length").unwrap().as_bytes(), b"i3e");
+        assert!(resolve(&document, "info.files[1]").is_err());
+        assert!(resolve(&document, "info.files[x]").is_err());
+        assert_eq!(resolve(&document, "").unwrap(), &document);
+    }
+}
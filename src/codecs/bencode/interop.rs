@@ -0,0 +1,284 @@
+//! Lossless conversion between bencode and CBOR / MessagePack
+//!
+//! Byte strings map to the native binary types of both formats, integers to
+//! integers, lists to arrays and dictionaries to maps. Map keys are written
+//! as binary by default; `KeyEncoding::Utf8` writes text keys instead for
+//! consumers that only accept string keys.
+use super::bencode_value::BencodeValue;
+use super::dictionary::BencodeDict;
+use anyhow::{bail, Context, Result};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How dictionary keys are written to the target format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyEncoding {
+    /// Keys as byte strings (lossless for any key)
+    #[default]
+    Binary,
+    /// Keys as text; fails on keys that aren't valid UTF-8
+    Utf8,
+}
+
+/// Encode a bencode value as CBOR with binary map keys
+pub fn to_cbor(value: &BencodeValue) -> Result<Vec<u8>> {
+    to_cbor_with_keys(value, KeyEncoding::Binary)
+}
+
+pub fn to_cbor_with_keys(value: &BencodeValue, keys: KeyEncoding) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(value.encoded_size());
+    ciborium::ser::into_writer(&Canonical { value, keys }, &mut output)
+        .context("Failed to encode CBOR")?;
+    Ok(output)
+}
+
+/// Decode CBOR into a bencode value; floats, booleans, null and tags are rejected
+pub fn from_cbor(data: &[u8]) -> Result<BencodeValue> {
+    let value: Converted = ciborium::de::from_reader(data).context("Failed to decode CBOR")?;
+    Ok(value.0)
+}
+
+/// Encode a bencode value as MessagePack with binary map keys
+pub fn to_msgpack(value: &BencodeValue) -> Result<Vec<u8>> {
+    to_msgpack_with_keys(value, KeyEncoding::Binary)
+}
+
+pub fn to_msgpack_with_keys(value: &BencodeValue, keys: KeyEncoding) -> Result<Vec<u8>> {
+    rmp_serde::to_vec(&Canonical { value, keys }).context("Failed to encode MessagePack")
+}
+
+/// Decode MessagePack into a bencode value; floats, booleans and nil are rejected
+pub fn from_msgpack(data: &[u8]) -> Result<BencodeValue> {
+    let mut deserializer = rmp_serde::Deserializer::new(data);
+    let value = Converted::deserialize(&mut deserializer).context("Failed to decode MessagePack")?;
+    let consumed = deserializer.get_ref().len();
+    if consumed != 0 {
+        bail!("{} trailing bytes after MessagePack value", consumed);
+    }
+    Ok(value.0)
+}
+
+/// Serializes a value with dictionary keys in sorted (bencode) order
+struct Canonical<'a> {
+    value: &'a BencodeValue,
+    keys: KeyEncoding,
+}
+
+/// Byte string that serializes as binary rather than as a sequence of integers
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Serialize for Canonical<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.value {
+            BencodeValue::Integer(i) => serializer.serialize_i64(*i),
+            BencodeValue::ByteString(s) => serializer.serialize_bytes(s),
+            BencodeValue::List(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&Canonical { value: item, keys: self.keys })?;
+                }
+                seq.end()
+            }
+            BencodeValue::Dictionary(dict) => {
+                let mut map = serializer.serialize_map(Some(dict.len()))?;
+                for (key, value) in dict {
+                    let value = Canonical { value, keys: self.keys };
+                    match self.keys {
+                        KeyEncoding::Binary => map.serialize_entry(&Bytes(key), &value)?,
+                        KeyEncoding::Utf8 => {
+                            let key = std::str::from_utf8(key).map_err(|_| {
+                                ser::Error::custom(format!(
+                                    "dictionary key {:?} is not valid UTF-8; use binary keys",
+                                    String::from_utf8_lossy(key)
+                                ))
+                            })?;
+                            map.serialize_entry(key, &value)?
+                        }
+                    }
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// Bencode value reconstructed from a self-describing format
+struct Converted(BencodeValue);
+
+impl<'de> Deserialize<'de> for Converted {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor).map(Converted)
+    }
+}
+
+/// Map key given either as text or as bytes
+struct Key(Vec<u8>);
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        match deserializer.deserialize_any(ValueVisitor)? {
+            BencodeValue::ByteString(key) => Ok(Key(key)),
+            _ => Err(de::Error::custom("map keys must be byte strings or text")),
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = BencodeValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an integer, byte string, text, array or map")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> std::result::Result<BencodeValue, E> {
+        Ok(BencodeValue::Integer(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> std::result::Result<BencodeValue, E> {
+        i64::try_from(value)
+            .map(BencodeValue::Integer)
+            .map_err(|_| E::custom(format!("integer {} does not fit in a bencode integer", value)))
+    }
+
+    fn visit_i128<E: de::Error>(self, value: i128) -> std::result::Result<BencodeValue, E> {
+        i64::try_from(value)
+            .map(BencodeValue::Integer)
+            .map_err(|_| E::custom(format!("integer {} does not fit in a bencode integer", value)))
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> std::result::Result<BencodeValue, E> {
+        i64::try_from(value)
+            .map(BencodeValue::Integer)
+            .map_err(|_| E::custom(format!("integer {} does not fit in a bencode integer", value)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> std::result::Result<BencodeValue, E> {
+        Ok(BencodeValue::ByteString(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> std::result::Result<BencodeValue, E> {
+        Ok(BencodeValue::ByteString(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> std::result::Result<BencodeValue, E> {
+        Ok(BencodeValue::ByteString(value.as_bytes().to_vec()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> std::result::Result<BencodeValue, E> {
+        Ok(BencodeValue::ByteString(value.into_bytes()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<BencodeValue, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(Converted(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(BencodeValue::List(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<BencodeValue, A::Error> {
+        let mut dict = BencodeDict::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((Key(key), Converted(value))) = map.next_entry()? {
+            // Text and binary spellings of the same key collapse to one bencode key
+            if dict.contains_key(&key) {
+                return Err(de::Error::custom(format!(
+                    "duplicate map key {:?}",
+                    String::from_utf8_lossy(&key)
+                )));
+            }
+            dict.insert(key, value);
+        }
+        Ok(BencodeValue::Dictionary(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::BencodeCodec;
+    use std::collections::HashMap;
+
+    fn sample_torrent() -> Vec<u8> {
+        let mut info = HashMap::new();
+        info.insert(b"name".to_vec(), BencodeValue::string("ubuntu.iso"));
+        info.insert(b"piece length".to_vec(), BencodeValue::integer(262144));
+        info.insert(b"length".to_vec(), BencodeValue::integer(i64::MAX));
+        info.insert(b"pieces".to_vec(), BencodeValue::byte_string((0..=255u8).cycle().take(60).collect()));
+        info.insert(b"private".to_vec(), BencodeValue::integer(-1));
+
+        let mut torrent = HashMap::new();
+        torrent.insert(b"announce".to_vec(), BencodeValue::string("http://tracker.example/announce"));
+        torrent.insert(b"announce-list".to_vec(), BencodeValue::list(vec![
+            BencodeValue::list(vec![BencodeValue::string("udp://a.example:80")]),
+            BencodeValue::list(vec![]),
+        ]));
+        torrent.insert(b"info".to_vec(), BencodeValue::dictionary(info));
+        torrent.insert(vec![0xff, 0x00, b'k'], BencodeValue::integer(i64::MIN));
+
+        BencodeCodec::encode(&BencodeValue::dictionary(torrent)).unwrap()
+    }
+
+    #[test]
+    fn test_cbor_roundtrip_is_byte_exact() {
+        let original = sample_torrent();
+        let value = BencodeCodec::decode(&original).unwrap();
+
+        let cbor = to_cbor(&value).unwrap();
+        assert_eq!(BencodeCodec::encode(&from_cbor(&cbor).unwrap()).unwrap(), original);
+        // Binary data stays binary: the 60 piece bytes appear verbatim
+        let pieces: Vec<u8> = (0..=255u8).cycle().take(60).collect();
+        assert!(cbor.windows(pieces.len()).any(|window| window == pieces.as_slice()));
+    }
+
+    #[test]
+    fn test_msgpack_roundtrip_is_byte_exact() {
+        let original = sample_torrent();
+        let value = BencodeCodec::decode(&original).unwrap();
+
+        let msgpack = to_msgpack(&value).unwrap();
+        assert_eq!(BencodeCodec::encode(&from_msgpack(&msgpack).unwrap()).unwrap(), original);
+        assert_eq!(to_msgpack(&value).unwrap(), msgpack, "output must be deterministic");
+    }
+
+    #[test]
+    fn test_utf8_keys() {
+        let original = sample_torrent();
+        let value = BencodeCodec::decode(&original).unwrap();
+
+        // The torrent has a non-UTF-8 key, which text keys can't represent
+        assert!(to_cbor_with_keys(&value, KeyEncoding::Utf8).is_err());
+        assert!(to_msgpack_with_keys(&value, KeyEncoding::Utf8).is_err());
+
+        let info = value.get_dict_value("info").unwrap().clone();
+        let cbor = to_cbor_with_keys(&info, KeyEncoding::Utf8).unwrap();
+        assert_eq!(from_cbor(&cbor).unwrap(), info);
+        let msgpack = to_msgpack_with_keys(&info, KeyEncoding::Utf8).unwrap();
+        assert_eq!(from_msgpack(&msgpack).unwrap(), info);
+    }
+
+    #[test]
+    fn test_rejects_unrepresentable_values() {
+        // Map with the key "a" both as text and as bytes collides in bencode
+        let colliding = [0xa2, 0x61, b'a', 0x01, 0x41, b'a', 0x02];
+        assert!(from_cbor(&colliding).is_err());
+
+        // CBOR float, boolean and a u64 above i64::MAX
+        assert!(from_cbor(&[0xf9, 0x3c, 0x00]).is_err());
+        assert!(from_cbor(&[0xf5]).is_err());
+        assert!(from_cbor(&[0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+
+        // MessagePack nil and trailing garbage
+        assert!(from_msgpack(&[0xc0]).is_err());
+        assert!(from_msgpack(&[0x01, 0x02]).is_err());
+    }
+}
@@ -1,5 +1,29 @@
 pub mod bencode_codec;
 pub mod bencode_value;
+pub mod codegen;
+pub mod dict_builder;
+pub mod dictionary;
+pub mod envelope;
+pub mod extract;
+#[cfg(feature = "interop")]
+pub mod interop;
+pub mod pieces;
+pub mod render;
+pub mod schema;
+pub mod shared;
+pub mod torrent;
+pub mod visitor;
+pub mod writer;
 
 pub use bencode_codec::BencodeCodec;
-pub use bencode_value::BencodeValue;
\ No newline at end of file
+pub use bencode_value::BencodeValue;
+pub use dict_builder::DictBuilder;
+pub use dictionary::BencodeDict;
+pub use envelope::{decode_data_uri, decode_enveloped, encode_enveloped, parse_magnet, to_data_uri, to_magnet, Envelope, EnvelopeError, MagnetInfo};
+pub use extract::{extract_bytes, list_leaves, resolve, Extracted, Leaf};
+pub use pieces::{HashAlgo, PieceDigests, PieceHasher};
+pub use schema::{schemas, Schema, SchemaBuilder, Severity, ValueType, Violation};
+pub use shared::SharedBencode;
+pub use torrent::{create_torrent, info_hash, InfoHasher, SymlinkPolicy, TorrentFile, TorrentOptions};
+pub use visitor::{BencodeStats, BencodeVisitor, WalkLimits};
+pub use writer::BencodeWriter;
\ No newline at end of file
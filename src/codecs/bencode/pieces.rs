@@ -0,0 +1,237 @@
+//! Hashing a byte stream in fixed-size pieces
+//!
+//! Torrents list a SHA-1 per piece of their content, and the object store
+//! keeps a SHA-256 per piece of chunked uploads; both feed their bytes
+//! through a `PieceHasher`.
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// Hash applied to each piece
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgo {
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgo {
+    /// Bytes in one digest
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgo::Sha1 => 20,
+            HashAlgo::Sha256 => 32,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgo::Sha1 => "sha1",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "sha1" => Ok(HashAlgo::Sha1),
+            "sha256" => Ok(HashAlgo::Sha256),
+            _ => anyhow::bail!("Unknown piece hash {:?}, expected sha1 or sha256", s),
+        }
+    }
+}
+
+enum PieceState {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl PieceState {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            PieceState::Sha1(hasher) => hasher.update(data),
+            PieceState::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finish_into(&mut self, out: &mut Vec<u8>) {
+        match self {
+            PieceState::Sha1(hasher) => out.extend_from_slice(&hasher.finalize_reset()),
+            PieceState::Sha256(hasher) => out.extend_from_slice(&hasher.finalize_reset()),
+        }
+    }
+}
+
+/// Digests of consecutive pieces of a stream, the last possibly short
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceDigests {
+    pub algo: HashAlgo,
+    pub piece_length: u64,
+    /// Bytes hashed in total
+    pub total_length: u64,
+    /// The digests back to back, `algo.digest_len()` bytes each
+    pub bytes: Vec<u8>,
+}
+
+impl PieceDigests {
+    pub fn len(&self) -> usize {
+        self.bytes.len() / self.algo.digest_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.bytes.chunks_exact(self.algo.digest_len()).nth(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.bytes.chunks_exact(self.algo.digest_len())
+    }
+}
+
+/// Streaming hasher that splits its input into `piece_length` pieces,
+/// however the input is split into `update` calls
+pub struct PieceHasher<'a> {
+    algo: HashAlgo,
+    piece_length: u64,
+    state: PieceState,
+    /// Bytes of the current piece hashed so far
+    filled: u64,
+    total: u64,
+    digests: Vec<u8>,
+    on_piece: Option<&'a mut dyn FnMut(usize, u64)>,
+}
+
+impl<'a> PieceHasher<'a> {
+    /// Bytes read at a time by `hash_reader`
+    const READ_BUFFER: usize = 64 * 1024;
+
+    /// A hasher for pieces of `piece_length` bytes, which must be positive
+    pub fn new(piece_length: u64, algo: HashAlgo) -> Self {
+        assert!(piece_length > 0, "piece length must be positive");
+        let state = match algo {
+            HashAlgo::Sha1 => PieceState::Sha1(Sha1::new()),
+            HashAlgo::Sha256 => PieceState::Sha256(Sha256::new()),
+        };
+        Self { algo, piece_length, state, filled: 0, total: 0, digests: Vec::new(), on_piece: None }
+    }
+
+    /// Call `callback` with the piece index and the bytes hashed so far
+    /// after each piece, the final short one included
+    pub fn with_callback(mut self, callback: &'a mut dyn FnMut(usize, u64)) -> Self {
+        self.on_piece = Some(callback);
+        self
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let room = (self.piece_length - self.filled).min(data.len() as u64) as usize;
+            self.state.update(&data[..room]);
+            self.filled += room as u64;
+            self.total += room as u64;
+            data = &data[room..];
+            if self.filled == self.piece_length {
+                self.finish_piece();
+            }
+        }
+    }
+
+    /// Hash everything `reader` yields, returning the number of bytes read
+    pub fn hash_reader<R: Read>(&mut self, mut reader: R) -> io::Result<u64> {
+        let mut buffer = vec![0u8; Self::READ_BUFFER];
+        let mut read_total = 0;
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(read_total),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.update(&buffer[..read]);
+            read_total += read as u64;
+        }
+    }
+
+    /// Bytes hashed so far
+    pub fn bytes_hashed(&self) -> u64 {
+        self.total
+    }
+
+    /// Finish the last piece if it's partial; empty input has no pieces
+    pub fn finalize(mut self) -> PieceDigests {
+        if self.filled > 0 {
+            self.finish_piece();
+        }
+        PieceDigests { algo: self.algo, piece_length: self.piece_length, total_length: self.total, bytes: self.digests }
+    }
+
+    fn finish_piece(&mut self) {
+        self.state.finish_into(&mut self.digests);
+        self.filled = 0;
+        let index = self.digests.len() / self.algo.digest_len() - 1;
+        if let Some(callback) = self.on_piece.as_mut() {
+            callback(index, self.total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected(data: &[u8], piece_length: usize) -> Vec<u8> {
+        data.chunks(piece_length).flat_map(|piece| Sha256::digest(piece).to_vec()).collect()
+    }
+
+    #[test]
+    fn test_pieces_ignore_how_input_is_split() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+        // Update boundaries on, one before and one after the piece boundaries
+        for piece_length in [64usize, 100, 1000, 1001] {
+            for split in [piece_length - 1, piece_length, piece_length + 1] {
+                let mut hasher = PieceHasher::new(piece_length as u64, HashAlgo::Sha256);
+                for part in data.chunks(split) {
+                    hasher.update(part);
+                }
+                let digests = hasher.finalize();
+                assert_eq!(digests.bytes, expected(&data, piece_length), "pieces of {} fed {} at a time", piece_length, split);
+                assert_eq!(digests.len(), data.len().div_ceil(piece_length));
+                assert_eq!(digests.total_length, 1000);
+            }
+        }
+
+        let mut reported = Vec::new();
+        let mut callback = |index, done| reported.push((index, done));
+        let mut hasher = PieceHasher::new(400, HashAlgo::Sha1).with_callback(&mut callback);
+        assert_eq!(hasher.hash_reader(&data[..]).unwrap(), 1000);
+        let digests = hasher.finalize();
+        assert_eq!(digests.get(2), Some(&Sha1::digest(&data[800..])[..]));
+        assert_eq!(reported, [(0, 400), (1, 800), (2, 1000)]);
+    }
+
+    #[test]
+    fn test_empty_input_has_no_pieces() {
+        let mut calls = 0;
+        let mut callback = |_, _| calls += 1;
+        let mut hasher = PieceHasher::new(16, HashAlgo::Sha1).with_callback(&mut callback);
+        hasher.update(&[]);
+        assert_eq!(hasher.hash_reader(io::empty()).unwrap(), 0);
+        let digests = hasher.finalize();
+        assert!(digests.is_empty());
+        assert_eq!(digests.len(), 0);
+        assert_eq!(digests.iter().count(), 0);
+        assert_eq!(calls, 0);
+    }
+}
@@ -0,0 +1,213 @@
+//! Human-readable rendering of well-known values, for display only
+//!
+//! Torrents store dates as epoch seconds and sizes as plain byte counts.
+//! `annotate` turns the values of keys known to hold these into something
+//! readable; callers show it next to the raw value, never instead of it
+//! in anything that is parsed back.
+
+use super::bencode_value::BencodeValue;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// How the value of a well-known key is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Annotation {
+    /// Epoch seconds as an ISO-8601 UTC timestamp
+    Date,
+    /// A byte count in IEC units
+    Size,
+    /// A 20 or 32 byte digest in hex
+    Hash,
+}
+
+/// Keys `annotate` recognizes, matched exactly
+pub const ANNOTATED_KEYS: &[(&str, Annotation)] = &[
+    ("creation date", Annotation::Date),
+    ("date", Annotation::Date),
+    ("mtime", Annotation::Date),
+    ("length", Annotation::Size),
+    ("piece length", Annotation::Size),
+    ("size", Annotation::Size),
+    ("total size", Annotation::Size),
+    ("info hash", Annotation::Hash),
+    ("info_hash", Annotation::Hash),
+    ("pieces root", Annotation::Hash),
+    ("root hash", Annotation::Hash),
+    ("sha1", Annotation::Hash),
+    ("sha256", Annotation::Hash),
+];
+
+/// Epoch seconds treated as dates: 1990-01-01 up to 2100-01-01
+///
+/// Outside it a "date" is more likely a counter or a corrupt field.
+pub const DATE_RANGE: Range<i64> = 631_152_000..4_102_444_800;
+
+/// Readable form of `value` stored under `key`, if the key is well known
+/// and the value looks like what it should hold
+pub fn annotate(key: &[u8], value: &BencodeValue) -> Option<String> {
+    let (_, annotation) = ANNOTATED_KEYS.iter().find(|(name, _)| name.as_bytes() == key)?;
+    match (annotation, value) {
+        (Annotation::Date, BencodeValue::Integer(seconds)) if DATE_RANGE.contains(seconds) => Some(iso8601(*seconds)),
+        (Annotation::Size, BencodeValue::Integer(bytes)) => Some(iec_size(u64::try_from(*bytes).ok()?)),
+        (Annotation::Hash, BencodeValue::ByteString(digest)) if matches!(digest.len(), 20 | 32) => {
+            Some(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+        }
+        _ => None,
+    }
+}
+
+/// `seconds` since the epoch as `YYYY-MM-DDTHH:MM:SSZ`
+pub fn iso8601(seconds: i64) -> String {
+    // Howard Hinnant's civil_from_days
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// `bytes` in the largest IEC unit it reaches, e.g. `256 KiB` or `1.50 MiB`
+pub fn iec_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let exponent = (bytes.ilog2() / 10).min(UNITS.len() as u32);
+    let unit = 1u64 << (10 * exponent);
+    let unit_name = UNITS[exponent as usize - 1];
+    if bytes.is_multiple_of(unit) {
+        format!("{} {}", bytes / unit, unit_name)
+    } else {
+        format!("{:.2} {}", bytes as f64 / unit as f64, unit_name)
+    }
+}
+
+/// Byte strings up to this long are shown as text when they are UTF-8
+const TEXT_PREVIEW_LEN: usize = 100;
+
+/// Write `value` as an indented tree, one node per line
+///
+/// Values of well-known keys show their annotation with the raw value in
+/// parentheses after it, unless `raw` is set.
+pub fn write_tree<W: Write>(mut out: W, value: &BencodeValue, raw: bool) -> io::Result<()> {
+    fn node<W: Write>(out: &mut W, label: Option<&str>, key: &[u8], value: &BencodeValue, depth: usize, raw: bool) -> io::Result<()> {
+        let indent = "  ".repeat(depth);
+        let label = label.map_or_else(String::new, |label| format!("{}: ", label));
+        let shown = match value {
+            BencodeValue::Integer(number) => number.to_string(),
+            BencodeValue::ByteString(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) if bytes.len() <= TEXT_PREVIEW_LEN => format!("{:?}", text),
+                _ => format!("<{} bytes>", bytes.len()),
+            },
+            BencodeValue::List(items) => format!("list ({} items)", items.len()),
+            BencodeValue::Dictionary(dict) => format!("dictionary ({} keys)", dict.len()),
+        };
+        match annotate(key, value).filter(|_| !raw) {
+            Some(annotation) => writeln!(out, "{}{}{} ({})", indent, label, annotation, shown)?,
+            None => writeln!(out, "{}{}{}", indent, label, shown)?,
+        }
+
+        match value {
+            BencodeValue::List(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    node(out, Some(&format!("[{}]", index)), &[], item, depth + 1, raw)?;
+                }
+            }
+            BencodeValue::Dictionary(dict) => {
+                for (key, item) in dict.iter() {
+                    node(out, Some(&String::from_utf8_lossy(key)), key, item, depth + 1, raw)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    node(&mut out, None, &[], value, 0, raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::BencodeDict;
+
+    fn dictionary<const N: usize>(entries: [(&[u8], BencodeValue); N]) -> BencodeValue {
+        BencodeValue::dictionary(entries.into_iter().map(|(key, value)| (key.to_vec(), value)).collect::<BencodeDict>())
+    }
+
+    fn fixture_torrent() -> BencodeValue {
+        let file = |length: i64, name: &str| {
+            dictionary([
+                (b"length", BencodeValue::integer(length)),
+                (b"path", BencodeValue::list(vec![BencodeValue::string(name)])),
+            ])
+        };
+        let info = dictionary([
+            (b"files", BencodeValue::list(vec![file(1_572_864, "video.mkv"), file(812, "notes.txt")])),
+            (b"name", BencodeValue::string("release")),
+            (b"piece length", BencodeValue::integer(262_144)),
+            (b"pieces", BencodeValue::byte_string(vec![0xAB; 140])),
+            (b"pieces root", BencodeValue::byte_string((0xE0..=0xFF).collect())),
+        ]);
+        dictionary([
+            (b"announce", BencodeValue::string("http://tracker.example/announce")),
+            (b"creation date", BencodeValue::integer(1_700_000_000)),
+            (b"info", info),
+        ])
+    }
+
+    fn tree(value: &BencodeValue, raw: bool) -> String {
+        let mut out = Vec::new();
+        write_tree(&mut out, value, raw).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_annotated_tree_snapshot() {
+        let torrent = fixture_torrent();
+        assert_eq!(tree(&torrent, false), r#"dictionary (3 keys)
+  announce: "http://tracker.example/announce"
+  creation date: 2023-11-14T22:13:20Z (1700000000)
+  info: dictionary (5 keys)
+    files: list (2 items)
+      [0]: dictionary (2 keys)
+        length: 1.50 MiB (1572864)
+        path: list (1 items)
+          [0]: "video.mkv"
+      [1]: dictionary (2 keys)
+        length: 812 B (812)
+        path: list (1 items)
+          [0]: "notes.txt"
+    name: "release"
+    piece length: 256 KiB (262144)
+    pieces: <140 bytes>
+    pieces root: e0e1e2e3e4e5e6e7e8e9eaebecedeeeff0f1f2f3f4f5f6f7f8f9fafbfcfdfeff (<32 bytes>)
+"#);
+
+        // Raw output carries the values alone, as before annotations existed
+        let raw = tree(&torrent, true);
+        assert!(raw.contains("  creation date: 1700000000\n"));
+        assert!(raw.contains("    piece length: 262144\n"));
+        assert!(raw.contains("    pieces root: <32 bytes>\n"));
+        assert_eq!(raw.lines().count(), tree(&torrent, false).lines().count());
+    }
+
+    #[test]
+    fn test_annotate_rejects_implausible_values() {
+        assert_eq!(annotate(b"creation date", &BencodeValue::integer(0)), None);
+        assert_eq!(annotate(b"creation date", &BencodeValue::integer(5_000_000_000)), None);
+        assert_eq!(annotate(b"date", &BencodeValue::integer(951_782_400)).as_deref(), Some("2000-02-29T00:00:00Z"));
+        assert_eq!(annotate(b"length", &BencodeValue::integer(-1)), None);
+        assert_eq!(annotate(b"length", &BencodeValue::string("12")), None);
+        assert_eq!(annotate(b"sha1", &BencodeValue::byte_string(vec![0; 19])), None);
+        assert_eq!(annotate(b"comment", &BencodeValue::integer(1_700_000_000)), None);
+        assert_eq!(iec_size(1023), "1023 B");
+        assert_eq!(iec_size(u64::MAX), "16.00 EiB");
+    }
+}
@@ -0,0 +1,449 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::codecs::bencode::BencodeValue;
+
+/// The four bencode value types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Integer,
+    ByteString,
+    List,
+    Dictionary,
+}
+
+impl ValueType {
+    pub fn of(value: &BencodeValue) -> Self {
+        match value {
+            BencodeValue::Integer(_) => ValueType::Integer,
+            BencodeValue::ByteString(_) => ValueType::ByteString,
+            BencodeValue::List(_) => ValueType::List,
+            BencodeValue::Dictionary(_) => ValueType::Dictionary,
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::Integer => "integer",
+            ValueType::ByteString => "byte string",
+            ValueType::List => "list",
+            ValueType::Dictionary => "dictionary",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The document is usable but unusual, e.g. a trackerless torrent
+    Warning,
+    /// The document breaks the format
+    Error,
+}
+
+/// One way a value fails its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Where the value is, e.g. `info.files[2].length`; empty for the root
+    pub path: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let path = if self.path.is_empty() { "<root>" } else { &self.path };
+        write!(f, "{}: {}: {}", severity, path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Presence {
+    Required,
+    /// Missing is only a warning
+    Recommended,
+    Optional,
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    key: Vec<u8>,
+    presence: Presence,
+    schema: Schema,
+}
+
+/// Constraints on a value of one type
+#[derive(Debug, Clone)]
+struct Rules {
+    value_type: ValueType,
+    fields: Vec<Field>,
+    /// Key groups of which exactly one key must be present
+    exclusive: Vec<Vec<Vec<u8>>>,
+    /// A key whose presence lifts the required and exclusive constraints
+    waived_by: Option<Vec<u8>>,
+    elements: Option<Schema>,
+    length: Option<RangeInclusive<usize>>,
+    length_multiple: Option<usize>,
+    range: Option<RangeInclusive<i64>>,
+}
+
+/// The expected shape of a bencode value
+///
+/// A schema is a set of alternatives told apart by value type; most have
+/// just one. Dictionary keys not named in the schema are allowed.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    alternatives: Vec<Rules>,
+}
+
+/// Builds a single-type `Schema`
+#[derive(Debug, Clone)]
+pub struct SchemaBuilder {
+    rules: Rules,
+}
+
+impl Schema {
+    pub fn integer() -> SchemaBuilder {
+        SchemaBuilder::new(ValueType::Integer)
+    }
+
+    pub fn byte_string() -> SchemaBuilder {
+        SchemaBuilder::new(ValueType::ByteString)
+    }
+
+    /// A list whose elements all match `elements`
+    pub fn list(elements: impl Into<Schema>) -> SchemaBuilder {
+        let mut builder = SchemaBuilder::new(ValueType::List);
+        builder.rules.elements = Some(elements.into());
+        builder
+    }
+
+    pub fn dictionary() -> SchemaBuilder {
+        SchemaBuilder::new(ValueType::Dictionary)
+    }
+
+    /// A value matching whichever of `schemas` has its type
+    pub fn one_of(schemas: impl IntoIterator<Item = Schema>) -> Schema {
+        Schema { alternatives: schemas.into_iter().flat_map(|schema| schema.alternatives).collect() }
+    }
+
+    /// Every violation in `value`, in schema order
+    pub fn validate(&self, value: &BencodeValue) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.check(value, "", &mut violations);
+        violations
+    }
+
+    fn check(&self, value: &BencodeValue, path: &str, violations: &mut Vec<Violation>) {
+        let found = ValueType::of(value);
+        let Some(rules) = self.alternatives.iter().find(|rules| rules.value_type == found) else {
+            let expected: Vec<String> = self.alternatives.iter().map(|rules| rules.value_type.to_string()).collect();
+            violations.push(error(path, format!("expected {}, found {}", expected.join(" or "), found)));
+            return;
+        };
+
+        match value {
+            BencodeValue::Integer(number) => {
+                if let Some(range) = &rules.range {
+                    if !range.contains(number) {
+                        violations.push(error(path, format!("value {} {}", number, describe_range(range))));
+                    }
+                }
+            }
+            BencodeValue::ByteString(bytes) => rules.check_length(bytes.len(), path, violations),
+            BencodeValue::List(items) => {
+                rules.check_length(items.len(), path, violations);
+                if let Some(elements) = &rules.elements {
+                    for (index, item) in items.iter().enumerate() {
+                        elements.check(item, &format!("{}[{}]", path, index), violations);
+                    }
+                }
+            }
+            BencodeValue::Dictionary(entries) => {
+                let waived = rules.waived_by.as_ref().is_some_and(|key| entries.contains_key(key));
+                for field in &rules.fields {
+                    let field_path = child_path(path, &field.key);
+                    match (entries.get(&field.key), field.presence) {
+                        (Some(item), _) => field.schema.check(item, &field_path, violations),
+                        (None, Presence::Required) if !waived => {
+                            violations.push(error(&field_path, "missing required key".to_string()));
+                        }
+                        (None, Presence::Recommended) if !waived => violations.push(Violation {
+                            path: field_path,
+                            severity: Severity::Warning,
+                            message: "missing recommended key".to_string(),
+                        }),
+                        (None, _) => {}
+                    }
+                }
+                for group in rules.exclusive.iter().filter(|_| !waived) {
+                    let present = group.iter().filter(|key| entries.contains_key(*key)).count();
+                    if present != 1 {
+                        let keys: Vec<String> = group.iter().map(|key| String::from_utf8_lossy(key).into_owned()).collect();
+                        violations.push(error(path, format!("expected exactly one of {}, found {}", keys.join(", "), present)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl From<SchemaBuilder> for Schema {
+    fn from(builder: SchemaBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl SchemaBuilder {
+    fn new(value_type: ValueType) -> Self {
+        Self {
+            rules: Rules {
+                value_type,
+                fields: Vec::new(),
+                exclusive: Vec::new(),
+                waived_by: None,
+                elements: None,
+                length: None,
+                length_multiple: None,
+                range: None,
+            },
+        }
+    }
+
+    /// Dictionary key that must be present
+    pub fn required(self, key: &str, schema: impl Into<Schema>) -> Self {
+        self.field(key, Presence::Required, schema.into())
+    }
+
+    /// Dictionary key whose absence is a warning
+    pub fn recommended(self, key: &str, schema: impl Into<Schema>) -> Self {
+        self.field(key, Presence::Recommended, schema.into())
+    }
+
+    /// Dictionary key that is only checked when present
+    pub fn optional(self, key: &str, schema: impl Into<Schema>) -> Self {
+        self.field(key, Presence::Optional, schema.into())
+    }
+
+    /// Exactly one of these dictionary keys must be present
+    pub fn exactly_one_of(mut self, keys: &[&str]) -> Self {
+        self.rules.exclusive.push(keys.iter().map(|key| key.as_bytes().to_vec()).collect());
+        self
+    }
+
+    /// Skip required keys and `exactly_one_of` groups when `key` is present,
+    /// as in an error reply that carries nothing else
+    pub fn waived_by(mut self, key: &str) -> Self {
+        self.rules.waived_by = Some(key.as_bytes().to_vec());
+        self
+    }
+
+    /// Allowed byte string length or list size
+    pub fn length(mut self, length: RangeInclusive<usize>) -> Self {
+        self.rules.length = Some(length);
+        self
+    }
+
+    /// Byte string length or list size must be a multiple of `multiple`
+    pub fn length_multiple_of(mut self, multiple: usize) -> Self {
+        self.rules.length_multiple = Some(multiple);
+        self
+    }
+
+    /// Allowed integer values
+    pub fn range(mut self, range: RangeInclusive<i64>) -> Self {
+        self.rules.range = Some(range);
+        self
+    }
+
+    pub fn build(self) -> Schema {
+        Schema { alternatives: vec![self.rules] }
+    }
+
+    fn field(mut self, key: &str, presence: Presence, schema: Schema) -> Self {
+        self.rules.fields.push(Field { key: key.as_bytes().to_vec(), presence, schema });
+        self
+    }
+}
+
+impl Rules {
+    fn check_length(&self, length: usize, path: &str, violations: &mut Vec<Violation>) {
+        if let Some(range) = &self.length {
+            if !range.contains(&length) {
+                violations.push(error(path, format!("length {} {}", length, describe_range(range))));
+            }
+        }
+        if let Some(multiple) = self.length_multiple {
+            if !length.is_multiple_of(multiple) {
+                violations.push(error(path, format!("length {} is not a multiple of {}", length, multiple)));
+            }
+        }
+    }
+}
+
+fn error(path: &str, message: String) -> Violation {
+    Violation { path: path.to_string(), severity: Severity::Error, message }
+}
+
+/// `path` extended by a dictionary key, as violation and leaf paths are written
+pub(super) fn child_path(path: &str, key: &[u8]) -> String {
+    let key = String::from_utf8_lossy(key);
+    if path.is_empty() {
+        key.into_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// "should be at least 1", "should be between 0 and 65535", ...
+fn describe_range<T: fmt::Display + PartialEq + Bounded>(range: &RangeInclusive<T>) -> String {
+    match (*range.start() == T::MIN, *range.end() == T::MAX) {
+        (_, true) => format!("should be at least {}", range.start()),
+        (true, false) => format!("should be at most {}", range.end()),
+        (false, false) if range.start() == range.end() => format!("should be {}", range.start()),
+        (false, false) => format!("should be between {} and {}", range.start(), range.end()),
+    }
+}
+
+trait Bounded {
+    const MIN: Self;
+    const MAX: Self;
+}
+
+impl Bounded for usize {
+    const MIN: Self = usize::MIN;
+    const MAX: Self = usize::MAX;
+}
+
+impl Bounded for i64 {
+    const MIN: Self = i64::MIN;
+    const MAX: Self = i64::MAX;
+}
+
+/// Schemas for common BitTorrent documents
+pub mod schemas {
+    use super::Schema;
+
+    /// A BEP 3 metainfo file, single- or multi-file
+    pub fn torrent_v1() -> Schema {
+        let non_negative = || Schema::integer().range(0..=i64::MAX);
+        let file = Schema::dictionary()
+            .required("length", non_negative())
+            .required("path", Schema::list(Schema::byte_string()).length(1..=usize::MAX))
+            .optional("md5sum", Schema::byte_string().length(32..=32));
+        let info = Schema::dictionary()
+            .required("name", Schema::byte_string())
+            .required("piece length", Schema::integer().range(1..=i64::MAX))
+            .required("pieces", Schema::byte_string().length_multiple_of(20))
+            .optional("length", non_negative())
+            .optional("files", Schema::list(file).length(1..=usize::MAX))
+            .optional("md5sum", Schema::byte_string().length(32..=32))
+            .optional("private", Schema::integer().range(0..=1))
+            .exactly_one_of(&["length", "files"]);
+
+        Schema::dictionary()
+            // Trackerless torrents rely on DHT instead
+            .recommended("announce", Schema::byte_string())
+            .optional("announce-list", Schema::list(Schema::list(Schema::byte_string())))
+            .optional("url-list", Schema::one_of([Schema::byte_string().build(), Schema::list(Schema::byte_string()).build()]))
+            .optional("creation date", Schema::integer())
+            .optional("comment", Schema::byte_string())
+            .optional("created by", Schema::byte_string())
+            .optional("encoding", Schema::byte_string())
+            .required("info", info)
+            .build()
+    }
+
+    /// A tracker's reply to an announce, with compact (BEP 23) or
+    /// dictionary peer lists; a `failure reason` reply needs nothing else
+    pub fn announce_response() -> Schema {
+        let non_negative = || Schema::integer().range(0..=i64::MAX);
+        let peer = Schema::dictionary()
+            .optional("peer id", Schema::byte_string().length(20..=20))
+            .required("ip", Schema::byte_string())
+            .required("port", Schema::integer().range(0..=65535));
+
+        Schema::dictionary()
+            .waived_by("failure reason")
+            .optional("failure reason", Schema::byte_string())
+            .optional("warning message", Schema::byte_string())
+            .required("interval", non_negative())
+            .optional("min interval", non_negative())
+            .optional("tracker id", Schema::byte_string())
+            .optional("complete", non_negative())
+            .optional("incomplete", non_negative())
+            .required("peers", Schema::one_of([
+                Schema::byte_string().length_multiple_of(6).build(),
+                Schema::list(peer).build(),
+            ]))
+            .optional("peers6", Schema::byte_string().length_multiple_of(18))
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::{create_torrent, BencodeDict, TorrentOptions};
+
+    fn fixture() -> BencodeValue {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, vec![7u8; 100]).unwrap();
+        let options = TorrentOptions { piece_length: 32, ..Default::default() };
+        create_torrent(&path, &options, &mut |_| {}).unwrap()
+    }
+
+    fn info_mut(torrent: &mut BencodeValue) -> &mut BencodeDict {
+        let BencodeValue::Dictionary(root) = torrent else { unreachable!() };
+        match root.get_mut(b"info".as_slice()) {
+            Some(BencodeValue::Dictionary(info)) => info,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_created_torrent_is_valid() {
+        let torrent = fixture();
+        assert_eq!(schemas::torrent_v1().validate(&torrent), []);
+
+        // Dropping the tracker only warns
+        let mut trackerless = torrent.clone();
+        let BencodeValue::Dictionary(root) = &mut trackerless else { unreachable!() };
+        root.remove(b"announce".as_slice());
+        let violations = schemas::torrent_v1().validate(&trackerless);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].severity, Severity::Warning);
+        assert_eq!(violations[0].path, "announce");
+    }
+
+    #[test]
+    fn test_missing_pieces() {
+        let mut torrent = fixture();
+        info_mut(&mut torrent).remove(b"pieces".as_slice());
+
+        let violations = schemas::torrent_v1().validate(&torrent);
+        assert_eq!(violations, [Violation {
+            path: "info.pieces".to_string(),
+            severity: Severity::Error,
+            message: "missing required key".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_pieces_length_not_multiple_of_20() {
+        let mut torrent = fixture();
+        info_mut(&mut torrent).insert(b"pieces".to_vec(), BencodeValue::byte_string(vec![0; 30]));
+
+        let violations = schemas::torrent_v1().validate(&torrent);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, "info.pieces");
+        assert_eq!(violations[0].severity, Severity::Error);
+        assert!(violations[0].message.contains("multiple of 20"), "{}", violations[0]);
+    }
+}
@@ -0,0 +1,114 @@
+use super::bencode_codec::BencodeError;
+use super::bencode_value::BencodeValue;
+use anyhow::Result;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// An immutable bencode value that can be cloned and sent between threads
+/// without copying it
+///
+/// Edits copy the value first if anything else still holds it, so other
+/// holders never see them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedBencode(Arc<BencodeValue>);
+
+impl BencodeValue {
+    /// Wrap the value for cheap sharing
+    pub fn shared(self) -> SharedBencode {
+        SharedBencode(Arc::new(self))
+    }
+}
+
+impl SharedBencode {
+    /// Mutable access, copying the value first if it is shared
+    pub fn make_mut(&mut self) -> &mut BencodeValue {
+        Arc::make_mut(&mut self.0)
+    }
+
+    /// Set a dictionary entry, returning the value it replaced
+    pub fn set(&mut self, key: impl Into<Vec<u8>>, value: BencodeValue) -> Result<Option<BencodeValue>> {
+        match self.make_mut() {
+            BencodeValue::Dictionary(dict) => Ok(dict.insert(key.into(), value)),
+            _ => Err(BencodeError::InvalidFormat("Can only set entries of a dictionary".to_string()).into()),
+        }
+    }
+
+    /// Remove a dictionary entry; copies only if the key is present
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<BencodeValue>> {
+        match &*self.0 {
+            BencodeValue::Dictionary(dict) if !dict.contains_key(key) => Ok(None),
+            BencodeValue::Dictionary(_) => match self.make_mut() {
+                BencodeValue::Dictionary(dict) => Ok(dict.remove(key)),
+                _ => unreachable!(),
+            },
+            _ => Err(BencodeError::InvalidFormat("Can only remove entries of a dictionary".to_string()).into()),
+        }
+    }
+
+    /// Whether both handles point at the same value
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// The value, copied only if it is still shared
+    pub fn into_inner(self) -> BencodeValue {
+        Arc::unwrap_or_clone(self.0)
+    }
+}
+
+impl Deref for SharedBencode {
+    type Target = BencodeValue;
+
+    fn deref(&self) -> &BencodeValue {
+        &self.0
+    }
+}
+
+impl From<BencodeValue> for SharedBencode {
+    fn from(value: BencodeValue) -> Self {
+        value.shared()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::bencode::BencodeCodec;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_edits_do_not_reach_other_holders() {
+        let pieces = BencodeValue::byte_string(vec![7u8; 10 * 1024 * 1024]);
+        let original = BencodeValue::dictionary(BTreeMap::from([(b"pieces".to_vec(), pieces)])).shared();
+        let mut edited = original.clone();
+        assert!(edited.ptr_eq(&original));
+
+        // Removing a missing key doesn't copy
+        assert_eq!(edited.remove(b"missing").unwrap(), None);
+        assert!(edited.ptr_eq(&original));
+
+        assert_eq!(edited.set("name", BencodeValue::string("edited")).unwrap(), None);
+        assert!(!edited.ptr_eq(&original));
+        assert_eq!(original.get_dict_value("name"), None);
+        assert_eq!(edited.get_dict_value("name"), Some(&BencodeValue::string("edited")));
+        assert!(edited.remove(b"pieces").unwrap().is_some());
+        assert_eq!(original.get_dict_value("pieces").and_then(|p| p.as_byte_string()).map(Vec::len), Some(10 * 1024 * 1024));
+
+        let mut list = BencodeValue::list(Vec::new()).shared();
+        assert!(list.set("key", BencodeValue::integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_shared_across_threads() {
+        let shared = BencodeValue::list((0..100).map(BencodeValue::integer).collect()).shared();
+        let encoded = BencodeCodec::encode(&shared).unwrap();
+        let handles: Vec<_> = (0..4).map(|_| {
+            let shared = shared.clone();
+            std::thread::spawn(move || BencodeCodec::encode(&shared).unwrap())
+        }).collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), encoded);
+        }
+        assert_eq!(shared.into_inner().as_list().map(Vec::len), Some(100));
+    }
+}
@@ -0,0 +1,478 @@
+use anyhow::{Context, Result};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::codecs::bencode::bencode_codec::BencodeError;
+use crate::codecs::bencode::pieces::{HashAlgo, PieceHasher};
+use crate::codecs::bencode::{BencodeCodec, BencodeValue, BencodeVisitor, BencodeWriter, WalkLimits};
+use crate::codecs::progress::{Progress, ProgressCallback};
+
+/// What to do with symbolic links found while walking a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Leave links out of the torrent
+    #[default]
+    Skip,
+    /// Include the link target as if it were a regular file or directory
+    Follow,
+    /// Refuse to create the torrent
+    Error,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(SymlinkPolicy::Skip),
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "error" => Ok(SymlinkPolicy::Error),
+            _ => anyhow::bail!("Unknown symlink policy '{}' (expected skip, follow or error)", s),
+        }
+    }
+}
+
+/// Options for `create_torrent`
+#[derive(Debug, Clone)]
+pub struct TorrentOptions {
+    pub announce: String,
+    pub piece_length: u64,
+    /// Torrent name; defaults to the file or directory name
+    pub name: Option<String>,
+    /// Include files and directories whose names start with '.'
+    pub include_hidden: bool,
+    pub symlinks: SymlinkPolicy,
+    /// Set `private` in the info dictionary (BEP 27)
+    pub private: bool,
+    /// HTTP seeds written to `url-list` (BEP 19)
+    pub web_seeds: Vec<String>,
+    /// Unix timestamp for `creation date`; omitted when `None` so output is reproducible
+    pub creation_date: Option<i64>,
+    pub created_by: Option<String>,
+}
+
+impl Default for TorrentOptions {
+    fn default() -> Self {
+        Self {
+            announce: "http://tracker.example.com/announce".to_string(),
+            piece_length: 32768,
+            name: None,
+            include_hidden: false,
+            symlinks: SymlinkPolicy::default(),
+            private: false,
+            web_seeds: Vec::new(),
+            creation_date: None,
+            created_by: None,
+        }
+    }
+}
+
+/// One file of a torrent, in the order its bytes are hashed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentFile {
+    /// Path components relative to the torrent root
+    pub path: Vec<String>,
+    pub length: u64,
+    /// Where the bytes are read from
+    pub source: PathBuf,
+}
+
+/// Build a torrent for a file or directory
+///
+/// Directories are walked in sorted order so the same tree always yields
+/// the same torrent. Pieces span file boundaries; `progress` is told the
+/// number of bytes hashed after every piece.
+pub fn create_torrent(root: &Path, options: &TorrentOptions, progress: ProgressCallback<'_>) -> Result<BencodeValue> {
+    if options.piece_length == 0 {
+        anyhow::bail!("Piece length must be positive");
+    }
+
+    let metadata = fs::metadata(root)
+        .with_context(|| format!("Failed to read {}", root.display()))?;
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => component_name(root.file_name().unwrap_or(root.as_os_str()), root)?,
+    };
+
+    let files = if metadata.is_dir() {
+        collect_files(root, options)?
+    } else {
+        vec![TorrentFile { path: vec![name.clone()], length: metadata.len(), source: root.to_path_buf() }]
+    };
+    if files.is_empty() {
+        anyhow::bail!("No files to include in torrent under {}", root.display());
+    }
+
+    let mut info = HashMap::new();
+    info.insert(b"name".to_vec(), BencodeValue::string(&name));
+    info.insert(b"piece length".to_vec(), BencodeValue::integer(options.piece_length as i64));
+    info.insert(b"pieces".to_vec(), BencodeValue::byte_string(hash_pieces(&files, options.piece_length, progress)?));
+    if metadata.is_dir() {
+        let entries = files.iter()
+            .map(|file| {
+                let mut entry = HashMap::new();
+                entry.insert(b"length".to_vec(), BencodeValue::integer(file.length as i64));
+                entry.insert(b"path".to_vec(), BencodeValue::list(
+                    file.path.iter().map(|component| BencodeValue::string(component)).collect(),
+                ));
+                BencodeValue::dictionary(entry)
+            })
+            .collect();
+        info.insert(b"files".to_vec(), BencodeValue::list(entries));
+    } else {
+        info.insert(b"length".to_vec(), BencodeValue::integer(metadata.len() as i64));
+    }
+    if options.private {
+        info.insert(b"private".to_vec(), BencodeValue::integer(1));
+    }
+
+    let mut torrent = HashMap::new();
+    torrent.insert(b"announce".to_vec(), BencodeValue::string(&options.announce));
+    torrent.insert(b"info".to_vec(), BencodeValue::dictionary(info));
+    if !options.web_seeds.is_empty() {
+        torrent.insert(b"url-list".to_vec(), BencodeValue::list(
+            options.web_seeds.iter().map(|url| BencodeValue::string(url)).collect(),
+        ));
+    }
+    if let Some(date) = options.creation_date {
+        torrent.insert(b"creation date".to_vec(), BencodeValue::integer(date));
+    }
+    if let Some(created_by) = &options.created_by {
+        torrent.insert(b"created by".to_vec(), BencodeValue::string(created_by));
+    }
+
+    Ok(BencodeValue::dictionary(torrent))
+}
+
+/// Every file under `root` that the options admit, in sorted path order
+pub fn collect_files(root: &Path, options: &TorrentOptions) -> Result<Vec<TorrentFile>> {
+    let mut files = Vec::new();
+    let root_canonical = fs::canonicalize(root)
+        .with_context(|| format!("Failed to resolve {}", root.display()))?;
+    walk(root, &mut Vec::new(), &mut vec![root_canonical], options, &mut files)?;
+    Ok(files)
+}
+
+fn walk(
+    dir: &Path,
+    prefix: &mut Vec<String>,
+    ancestors: &mut Vec<PathBuf>,
+    options: &TorrentOptions,
+    files: &mut Vec<TorrentFile>,
+) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = component_name(&entry.file_name(), &path)?;
+        if !options.include_hidden && name.starts_with('.') {
+            continue;
+        }
+
+        let mut metadata = fs::symlink_metadata(&path)?;
+        if metadata.file_type().is_symlink() {
+            match options.symlinks {
+                SymlinkPolicy::Skip => continue,
+                SymlinkPolicy::Error => anyhow::bail!("Refusing to include symlink {}", path.display()),
+                SymlinkPolicy::Follow => {
+                    metadata = fs::metadata(&path)
+                        .with_context(|| format!("Broken symlink {}", path.display()))?;
+                }
+            }
+        }
+
+        prefix.push(name);
+        if metadata.is_dir() {
+            let canonical = fs::canonicalize(&path)?;
+            if ancestors.contains(&canonical) {
+                anyhow::bail!("Symlink cycle at {}", path.display());
+            }
+            ancestors.push(canonical);
+            walk(&path, prefix, ancestors, options, files)?;
+            ancestors.pop();
+        } else {
+            files.push(TorrentFile { path: prefix.clone(), length: metadata.len(), source: path });
+        }
+        prefix.pop();
+    }
+
+    Ok(())
+}
+
+fn component_name(name: &std::ffi::OsStr, path: &Path) -> Result<String> {
+    name.to_str()
+        .map(str::to_string)
+        .with_context(|| format!("File name is not valid UTF-8: {}", path.display()))
+}
+
+/// Concatenated SHA-1 digests of consecutive `piece_length` pieces of all files
+fn hash_pieces(files: &[TorrentFile], piece_length: u64, progress: ProgressCallback<'_>) -> Result<Vec<u8>> {
+    let total: u64 = files.iter().map(|file| file.length).sum();
+    let mut report = |_, done| progress(Progress { done, total });
+    let mut hasher = PieceHasher::new(piece_length, HashAlgo::Sha1).with_callback(&mut report);
+
+    for file in files {
+        let reader = File::open(&file.source)
+            .with_context(|| format!("Failed to open {}", file.source.display()))?
+            .take(file.length);
+        hasher.hash_reader(reader)?;
+    }
+    let done = hasher.bytes_hashed();
+    if done != total {
+        anyhow::bail!("Files changed while hashing: read {} bytes, expected {}", done, total);
+    }
+
+    Ok(hasher.finalize().bytes)
+}
+
+/// SHA-1 of a torrent's `info` dictionary, read from `reader` without
+/// decoding the rest of the file
+pub fn info_hash<R: Read>(reader: R) -> Result<[u8; 20]> {
+    let mut hasher = InfoHasher::default();
+    BencodeCodec::walk(reader, &mut hasher, &WalkLimits::default())?;
+    hasher.finish().context("Torrent has no info dictionary")
+}
+
+/// Visitor that re-encodes the top-level `info` value into SHA-1 as it
+/// streams past
+///
+/// Integers are hashed in canonical form and keys must arrive sorted,
+/// so the hash is that of the encoded `info` value, which is the hash
+/// of the file's own bytes for any canonically encoded torrent.
+#[derive(Default)]
+pub struct InfoHasher {
+    /// Lists and dictionaries the walk is inside
+    depth: usize,
+    /// The last top-level key was `info`
+    info_next: bool,
+    writer: Option<BencodeWriter<Sha1>>,
+    /// Last key seen in each dictionary open inside `info`
+    keys: Vec<Option<Vec<u8>>>,
+    digest: Option<[u8; 20]>,
+}
+
+impl InfoHasher {
+    pub fn finish(self) -> Option<[u8; 20]> {
+        self.digest
+    }
+
+    /// The writer for the value starting now, if it is or is inside `info`
+    fn start_value(&mut self) -> Option<&mut BencodeWriter<Sha1>> {
+        if std::mem::take(&mut self.info_next) {
+            self.writer = Some(BencodeWriter::new(Sha1::new()));
+        }
+        self.writer.as_mut()
+    }
+
+    /// Finish the hash once the `info` value has ended
+    fn end_value(&mut self) -> Result<()> {
+        if self.depth == 1 {
+            if let Some(writer) = self.writer.take() {
+                self.digest = Some(writer.finish()?.finalize().into());
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, dictionary: bool) -> Result<()> {
+        if let Some(writer) = self.start_value() {
+            if dictionary {
+                writer.begin_dictionary()?;
+            } else {
+                writer.begin_list()?;
+            }
+            self.keys.push(None);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.depth -= 1;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.end()?;
+            self.keys.pop();
+        }
+        self.end_value()
+    }
+}
+
+impl BencodeVisitor for InfoHasher {
+    fn on_integer(&mut self, value: i64) -> Result<()> {
+        if let Some(writer) = self.start_value() {
+            writer.write_value(&BencodeValue::integer(value))?;
+        }
+        self.end_value()
+    }
+
+    fn on_string(&mut self, len: u64, reader: &mut dyn Read) -> Result<()> {
+        if let Some(writer) = self.start_value() {
+            writer.write_bytes_from(len, reader)?;
+        }
+        self.end_value()
+    }
+
+    fn on_list_start(&mut self) -> Result<()> {
+        self.open(false)
+    }
+
+    fn on_list_end(&mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn on_dict_start(&mut self) -> Result<()> {
+        self.open(true)
+    }
+
+    fn on_dict_end(&mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn on_key(&mut self, key: &[u8]) -> Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            self.info_next = self.depth == 1 && self.digest.is_none() && key == b"info";
+            return Ok(());
+        };
+        let last = self.keys.last_mut().expect("keys inside info belong to a dictionary");
+        if last.as_deref().is_some_and(|last| last >= key) {
+            return Err(BencodeError::InvalidFormat(format!(
+                "info dictionary key '{}' is out of order", String::from_utf8_lossy(key)
+            )).into());
+        }
+        *last = Some(key.to_vec());
+        writer.write_bytes_from(key.len() as u64, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("b/nested")).unwrap();
+        fs::write(root.join("a.txt"), vec![b'a'; 20]).unwrap();
+        // 44 bytes so far, then this one straddles the 64-byte piece boundary
+        fs::write(root.join("b/nested/c.bin"), (0..70u8).collect::<Vec<_>>()).unwrap();
+        fs::write(root.join("b/d.txt"), b"dddd").unwrap();
+        fs::write(root.join(".hidden"), b"secret").unwrap();
+        dir
+    }
+
+    fn options() -> TorrentOptions {
+        TorrentOptions { piece_length: 64, name: Some("tree".to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn test_directory_torrent() {
+        let dir = sample_tree();
+        let mut reports = Vec::new();
+        let torrent = create_torrent(dir.path(), &options(), &mut |progress| reports.push(progress)).unwrap();
+
+        let info = torrent.get_dict_value("info").unwrap();
+        let files = info.get_dict_value("files").unwrap().as_list().unwrap();
+        let listed: Vec<(Vec<String>, i64)> = files.iter()
+            .map(|file| {
+                let path = file.get_dict_value("path").unwrap().as_list().unwrap()
+                    .iter().map(|component| component.as_string().unwrap()).collect();
+                (path, file.get_dict_value("length").unwrap().as_integer().unwrap())
+            })
+            .collect();
+        let expected_paths: [(&[&str], i64); 3] = [(&["a.txt"], 20), (&["b", "d.txt"], 4), (&["b", "nested", "c.bin"], 70)];
+        assert_eq!(listed.len(), expected_paths.len());
+        for ((path, length), (expected, expected_length)) in listed.iter().zip(expected_paths) {
+            assert_eq!(path, expected);
+            assert_eq!(*length, expected_length);
+        }
+
+        // Hash the concatenation independently
+        let mut data = vec![b'a'; 20];
+        data.extend_from_slice(b"dddd");
+        data.extend(0..70u8);
+        let expected: Vec<u8> = data.chunks(64).flat_map(|piece| Sha1::digest(piece).to_vec()).collect();
+        assert_eq!(info.get_dict_value("pieces").unwrap().as_byte_string().unwrap(), &expected);
+
+        assert_eq!(reports, [Progress { done: 64, total: 94 }, Progress { done: 94, total: 94 }]);
+        assert!(torrent.get_dict_value("creation date").is_none());
+    }
+
+    #[test]
+    fn test_output_is_deterministic() {
+        let dir = sample_tree();
+        let encode = || BencodeCodec::encode(&create_torrent(dir.path(), &options(), &mut |_| {}).unwrap()).unwrap();
+        assert_eq!(encode(), encode());
+    }
+
+    #[test]
+    fn test_hidden_private_and_web_seeds() {
+        let dir = sample_tree();
+        let options = TorrentOptions {
+            include_hidden: true,
+            private: true,
+            web_seeds: vec!["https://seed.example/files/".to_string()],
+            ..options()
+        };
+        let torrent = create_torrent(dir.path(), &options, &mut |_| {}).unwrap();
+        let info = torrent.get_dict_value("info").unwrap();
+
+        let first = &info.get_dict_value("files").unwrap().as_list().unwrap()[0];
+        assert_eq!(first.get_dict_value("path").unwrap().as_list().unwrap()[0].as_string().unwrap(), ".hidden");
+        assert_eq!(info.get_dict_value("private").unwrap().as_integer(), Some(1));
+        assert_eq!(torrent.get_dict_value("url-list").unwrap().as_list().unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policies() {
+        let dir = sample_tree();
+        std::os::unix::fs::symlink(dir.path().join("b"), dir.path().join("link")).unwrap();
+
+        let count = |symlinks| {
+            collect_files(dir.path(), &TorrentOptions { symlinks, ..options() }).map(|files| files.len())
+        };
+        assert_eq!(count(SymlinkPolicy::Skip).unwrap(), 3);
+        assert_eq!(count(SymlinkPolicy::Follow).unwrap(), 5);
+        assert!(count(SymlinkPolicy::Error).is_err());
+
+        // A link back to an ancestor would recurse forever
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("b/loop")).unwrap();
+        assert!(format!("{:#}", count(SymlinkPolicy::Follow).unwrap_err()).contains("cycle"));
+    }
+
+    #[test]
+    fn test_single_file_torrent() {
+        let dir = sample_tree();
+        let torrent = create_torrent(&dir.path().join("a.txt"), &TorrentOptions::default(), &mut |_| {}).unwrap();
+        let info = torrent.get_dict_value("info").unwrap();
+        assert_eq!(info.get_dict_value("name").unwrap().as_string().unwrap(), "a.txt");
+        assert_eq!(info.get_dict_value("length").unwrap().as_integer(), Some(20));
+        assert!(info.get_dict_value("files").is_none());
+    }
+
+    #[test]
+    fn test_info_hash_streams_like_tree_hash() {
+        let dir = sample_tree();
+        let torrent = create_torrent(dir.path(), &options(), &mut |_| {}).unwrap();
+        let encoded = BencodeCodec::encode(&torrent).unwrap();
+        let tree_hash: [u8; 20] = Sha1::digest(BencodeCodec::encode(torrent.get_dict_value("info").unwrap()).unwrap()).into();
+        assert_eq!(info_hash(&encoded[..]).unwrap(), tree_hash);
+
+        // Only the top-level info counts
+        let nested = BencodeCodec::decode(b"d1:ad4:infoi1ee4:infod1:xli1e2:abee1:zi0ee").unwrap();
+        let encoded = BencodeCodec::encode(&nested).unwrap();
+        let tree_hash: [u8; 20] = Sha1::digest(b"d1:xli1e2:abee").into();
+        assert_eq!(info_hash(&encoded[..]).unwrap(), tree_hash);
+
+        assert!(info_hash(&b"d4:namei1ee"[..]).is_err());
+        assert!(info_hash(&b"d4:infod1:bi1e1:ai2eee"[..]).is_err());
+    }
+}
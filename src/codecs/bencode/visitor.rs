@@ -0,0 +1,346 @@
+use super::bencode_codec::{BencodeCodec, BencodeError};
+use anyhow::Result;
+use std::io::{BufReader, Read};
+use std::str::FromStr;
+
+/// Receives the events of `BencodeCodec::walk` in document order
+///
+/// Every method defaults to doing nothing, so a visitor only implements
+/// the events it cares about. An error from any method stops the walk.
+pub trait BencodeVisitor {
+    fn on_integer(&mut self, _value: i64) -> Result<()> {
+        Ok(())
+    }
+
+    /// A byte string of `len` bytes, readable from `reader`
+    ///
+    /// Whatever the visitor leaves unread is skipped.
+    fn on_string(&mut self, _len: u64, _reader: &mut dyn Read) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_list_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_list_end(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_dict_start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_dict_end(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// A dictionary key; the events of its value follow
+    fn on_key(&mut self, _key: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Bounds on what `BencodeCodec::walk` holds in memory
+///
+/// Byte strings are streamed to the visitor, so only keys, which are
+/// handed over whole, and nesting, which the walk tracks, need limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkLimits {
+    /// Deepest nesting of lists and dictionaries
+    pub max_depth: usize,
+    /// Longest dictionary key, in bytes
+    pub max_key_len: usize,
+}
+
+impl Default for WalkLimits {
+    fn default() -> Self {
+        Self { max_depth: 256, max_key_len: 64 * 1024 }
+    }
+}
+
+/// Node counts gathered by walking a document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BencodeStats {
+    pub integers: u64,
+    pub strings: u64,
+    /// Total bytes in byte strings, keys excluded
+    pub string_bytes: u64,
+    pub lists: u64,
+    pub dictionaries: u64,
+    pub keys: u64,
+    pub max_depth: usize,
+    depth: usize,
+}
+
+impl BencodeStats {
+    pub fn nodes(&self) -> u64 {
+        self.integers + self.strings + self.lists + self.dictionaries
+    }
+
+    fn open(&mut self) {
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+}
+
+impl BencodeVisitor for BencodeStats {
+    fn on_integer(&mut self, _value: i64) -> Result<()> {
+        self.integers += 1;
+        Ok(())
+    }
+
+    fn on_string(&mut self, len: u64, _reader: &mut dyn Read) -> Result<()> {
+        self.strings += 1;
+        self.string_bytes += len;
+        Ok(())
+    }
+
+    fn on_list_start(&mut self) -> Result<()> {
+        self.lists += 1;
+        self.open();
+        Ok(())
+    }
+
+    fn on_list_end(&mut self) -> Result<()> {
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn on_dict_start(&mut self) -> Result<()> {
+        self.dictionaries += 1;
+        self.open();
+        Ok(())
+    }
+
+    fn on_dict_end(&mut self) -> Result<()> {
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn on_key(&mut self, _key: &[u8]) -> Result<()> {
+        self.keys += 1;
+        Ok(())
+    }
+}
+
+/// A list or dictionary the walk is inside
+enum Open {
+    List,
+    /// `true` while the next item is a key
+    Dictionary(bool),
+}
+
+impl BencodeCodec {
+    /// Stream one value from `reader` to `visitor` without building it
+    ///
+    /// Accepts what `decode` accepts and, like it, ignores anything after
+    /// the value. Memory use is bounded by `limits`, not by the size of
+    /// the document.
+    pub fn walk<R: Read, V: BencodeVisitor>(reader: R, visitor: &mut V, limits: &WalkLimits) -> Result<()> {
+        let mut reader = BufReader::new(reader);
+        let mut stack = Vec::new();
+        let mut key = Vec::new();
+        loop {
+            let byte = next_byte(&mut reader)?;
+            match (stack.last_mut(), byte) {
+                (Some(Open::Dictionary(true)), b'e') | (Some(Open::List), b'e') => {
+                    match stack.pop() {
+                        Some(Open::List) => visitor.on_list_end()?,
+                        _ => visitor.on_dict_end()?,
+                    }
+                }
+                (Some(Open::Dictionary(expect_key @ true)), _) => {
+                    if !byte.is_ascii_digit() {
+                        return Err(BencodeError::InvalidFormat("Dictionary keys must be byte strings".to_string()).into());
+                    }
+                    let len: usize = read_number(&mut reader, byte, b':', BencodeError::InvalidStringLength)?;
+                    if len > limits.max_key_len {
+                        return Err(BencodeError::InvalidStringLength(format!(
+                            "{} byte key is longer than the {} byte limit", len, limits.max_key_len
+                        )).into());
+                    }
+                    key.resize(len, 0);
+                    reader.read_exact(&mut key).map_err(eof)?;
+                    *expect_key = false;
+                    visitor.on_key(&key)?;
+                    continue;
+                }
+                (Some(Open::Dictionary(false)), b'e') => {
+                    return Err(BencodeError::InvalidFormat("Dictionary key has no value".to_string()).into());
+                }
+                (_, b'l' | b'd') => {
+                    if stack.len() == limits.max_depth {
+                        return Err(BencodeError::InvalidFormat(format!(
+                            "Nesting deeper than {} lists and dictionaries", limits.max_depth
+                        )).into());
+                    }
+                    if byte == b'l' {
+                        stack.push(Open::List);
+                        visitor.on_list_start()?;
+                    } else {
+                        stack.push(Open::Dictionary(true));
+                        visitor.on_dict_start()?;
+                    }
+                    continue;
+                }
+                (_, b'i') => {
+                    let value = read_number(&mut reader, b'i', b'e', BencodeError::InvalidInteger)?;
+                    visitor.on_integer(value)?;
+                }
+                (_, b'0'..=b'9') => {
+                    let len = read_number(&mut reader, byte, b':', BencodeError::InvalidStringLength)?;
+                    let mut string = (&mut reader).take(len);
+                    visitor.on_string(len, &mut string)?;
+                    std::io::copy(&mut string, &mut std::io::sink())?;
+                    if string.limit() > 0 {
+                        return Err(BencodeError::UnexpectedEof.into());
+                    }
+                }
+                _ => {
+                    return Err(BencodeError::InvalidFormat(format!("Unexpected character '{}'", byte as char)).into());
+                }
+            }
+
+            // A value just ended: the enclosing dictionary wants a key next
+            match stack.last_mut() {
+                Some(Open::Dictionary(expect_key)) => *expect_key = true,
+                Some(Open::List) => {}
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+fn next_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte).map_err(eof)?;
+    Ok(byte[0])
+}
+
+fn eof(error: std::io::Error) -> BencodeError {
+    if error.kind() == std::io::ErrorKind::UnexpectedEof {
+        BencodeError::UnexpectedEof
+    } else {
+        BencodeError::IoError(error)
+    }
+}
+
+/// Parse the number that runs up to `terminator`, as `decode` would
+///
+/// `first` is the byte already read: an integer's `i`, which is skipped,
+/// or a length's first digit.
+fn read_number<T: FromStr>(
+    reader: &mut impl Read,
+    first: u8,
+    terminator: u8,
+    error: fn(String) -> BencodeError,
+) -> Result<T> {
+    let mut digits = [0u8; 24];
+    let mut len = 0;
+    if first != b'i' {
+        digits[0] = first;
+        len = 1;
+    }
+    loop {
+        let byte = next_byte(reader)?;
+        if byte == terminator {
+            break;
+        }
+        if terminator == b':' && !byte.is_ascii_digit() {
+            return Err(BencodeError::InvalidFormat("Invalid character in string length".to_string()).into());
+        }
+        if len == digits.len() {
+            return Err(error(format!("{}...", String::from_utf8_lossy(&digits))).into());
+        }
+        digits[len] = byte;
+        len += 1;
+    }
+    let text = std::str::from_utf8(&digits[..len]).map_err(|_| error(String::from_utf8_lossy(&digits[..len]).into_owned()))?;
+    Ok(text.parse().map_err(|_| error(text.to_string()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records events as text, reading every string
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl BencodeVisitor for Recorder {
+        fn on_integer(&mut self, value: i64) -> Result<()> {
+            self.0.push(value.to_string());
+            Ok(())
+        }
+
+        fn on_string(&mut self, _len: u64, reader: &mut dyn Read) -> Result<()> {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            self.0.push(format!("{:?}", String::from_utf8_lossy(&bytes)));
+            Ok(())
+        }
+
+        fn on_list_start(&mut self) -> Result<()> {
+            self.0.push("[".to_string());
+            Ok(())
+        }
+
+        fn on_list_end(&mut self) -> Result<()> {
+            self.0.push("]".to_string());
+            Ok(())
+        }
+
+        fn on_dict_start(&mut self) -> Result<()> {
+            self.0.push("{".to_string());
+            Ok(())
+        }
+
+        fn on_dict_end(&mut self) -> Result<()> {
+            self.0.push("}".to_string());
+            Ok(())
+        }
+
+        fn on_key(&mut self, key: &[u8]) -> Result<()> {
+            self.0.push(format!("{}:", String::from_utf8_lossy(key)));
+            Ok(())
+        }
+    }
+
+    fn events(data: &[u8], limits: &WalkLimits) -> Result<String> {
+        let mut recorder = Recorder::default();
+        BencodeCodec::walk(data, &mut recorder, limits)?;
+        Ok(recorder.0.join(" "))
+    }
+
+    #[test]
+    fn test_walk_events_and_limits() {
+        let limits = WalkLimits::default();
+        assert_eq!(
+            events(b"d4:listli-3e0:le3:abce1:nd0:i0eee", &limits).unwrap(),
+            r#"{ list: [ -3 "" [ ] "abc" ] n: { : 0 } }"#
+        );
+        assert_eq!(events(b"i42etrailing", &limits).unwrap(), "42");
+        assert_eq!(events(b"5:hello", &limits).unwrap(), r#""hello""#);
+
+        for bad in [&b""[..], b"l", b"5:abc", b"di1ei2ee", b"d1:ae", b"i1x", b"x", b"3a:abc", b"i99999999999999999999e"] {
+            assert!(events(bad, &limits).is_err(), "{:?}", String::from_utf8_lossy(bad));
+        }
+
+        let nested = [&b"l".repeat(3)[..], &b"e".repeat(3)].concat();
+        assert!(events(&nested, &WalkLimits { max_depth: 3, ..limits }).is_ok());
+        assert!(events(&nested, &WalkLimits { max_depth: 2, ..limits }).is_err());
+        assert!(events(b"d5:helloi1ee", &WalkLimits { max_key_len: 4, ..limits }).is_err());
+    }
+
+    #[test]
+    fn test_stats_match_decoded_document() {
+        let data = b"d4:infod5:filesld6:lengthi3e4:pathl1:a5:b.txteee4:name3:dire4:note0:e";
+        let mut stats = BencodeStats::default();
+        BencodeCodec::walk(&data[..], &mut stats, &WalkLimits::default()).unwrap();
+        assert_eq!((stats.integers, stats.strings, stats.string_bytes), (1, 4, 9));
+        assert_eq!((stats.lists, stats.dictionaries, stats.keys), (2, 3, 6));
+        assert_eq!(stats.max_depth, 5);
+        assert_eq!(stats.nodes(), 10);
+    }
+}
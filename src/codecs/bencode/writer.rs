@@ -0,0 +1,135 @@
+use super::bencode_codec::{BencodeCodec, BencodeError};
+use super::bencode_value::BencodeValue;
+use anyhow::Result;
+use std::io::{Read, Write};
+
+/// Incremental bencode writer for documents too large to build in memory
+///
+/// Byte strings can be copied straight from a reader, with their length
+/// declared up front as the format requires. If a reader then supplies a
+/// different number of bytes, the output can no longer be valid: the writer
+/// reports `BencodeError::InvalidFormat` and refuses every later call,
+/// including `finish`, so a truncated document is never passed off as
+/// complete. Dictionary keys must be written in sorted order.
+pub struct BencodeWriter<W: Write> {
+    writer: W,
+    /// Lists and dictionaries opened but not yet ended
+    depth: usize,
+    failed: bool,
+}
+
+impl<W: Write> BencodeWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, depth: 0, failed: false }
+    }
+
+    /// Write a complete value
+    pub fn write_value(&mut self, value: &BencodeValue) -> Result<()> {
+        self.guard(|writer| BencodeCodec::encode_to_writer(value, writer))
+    }
+
+    /// Write a byte string of `len` bytes read from `reader`
+    ///
+    /// Fails if `reader` ends early or has bytes left over.
+    pub fn write_bytes_from<R: Read>(&mut self, len: u64, mut reader: R) -> Result<()> {
+        self.guard(|writer| {
+            BencodeCodec::write_number(writer, None, false, len, b':')?;
+            let copied = std::io::copy(&mut (&mut reader).take(len), writer)?;
+            if copied < len {
+                return Err(BencodeError::InvalidFormat(format!(
+                    "byte string declared {} bytes, reader supplied {}", len, copied
+                )).into());
+            }
+            if reader.read(&mut [0u8])? != 0 {
+                return Err(BencodeError::InvalidFormat(format!(
+                    "byte string declared {} bytes, reader supplied more", len
+                )).into());
+            }
+            Ok(())
+        })
+    }
+
+    pub fn begin_list(&mut self) -> Result<()> {
+        self.guard(|writer| Ok(writer.write_all(b"l")?))?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn begin_dictionary(&mut self) -> Result<()> {
+        self.guard(|writer| Ok(writer.write_all(b"d")?))?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Close the innermost open list or dictionary
+    pub fn end(&mut self) -> Result<()> {
+        if self.depth == 0 {
+            return Err(BencodeError::InvalidFormat("end without an open list or dictionary".to_string()).into());
+        }
+        self.guard(|writer| Ok(writer.write_all(b"e")?))?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /// Flush and return the underlying writer once the document is complete
+    pub fn finish(mut self) -> Result<W> {
+        if self.failed {
+            return Err(BencodeError::InvalidFormat("document is incomplete after an earlier error".to_string()).into());
+        }
+        if self.depth != 0 {
+            return Err(BencodeError::InvalidFormat(format!("{} lists or dictionaries left open", self.depth)).into());
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Run one write, poisoning the writer if it fails partway
+    fn guard(&mut self, write: impl FnOnce(&mut W) -> Result<()>) -> Result<()> {
+        if self.failed {
+            return Err(BencodeError::InvalidFormat("writer failed earlier".to_string()).into());
+        }
+        let result = write(&mut self.writer);
+        self.failed = result.is_err();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streamed_document_matches_encode() {
+        let mut writer = BencodeWriter::new(Vec::new());
+        writer.begin_dictionary().unwrap();
+        writer.write_value(&BencodeValue::string("data")).unwrap();
+        writer.write_bytes_from(5, &b"hello"[..]).unwrap();
+        writer.write_value(&BencodeValue::string("size")).unwrap();
+        writer.write_value(&BencodeValue::integer(5)).unwrap();
+        writer.end().unwrap();
+        let streamed = writer.finish().unwrap();
+
+        let expected = BencodeValue::dictionary([
+            (b"data".to_vec(), BencodeValue::string("hello")),
+            (b"size".to_vec(), BencodeValue::integer(5)),
+        ].into_iter().collect::<std::collections::BTreeMap<_, _>>());
+        assert_eq!(streamed, BencodeCodec::encode(&expected).unwrap());
+    }
+
+    #[test]
+    fn test_short_reader_poisons_writer() {
+        let mut writer = BencodeWriter::new(Vec::new());
+        writer.begin_list().unwrap();
+        let error = writer.write_bytes_from(10, &b"short"[..]).unwrap_err();
+        assert!(matches!(error.downcast_ref::<BencodeError>(), Some(BencodeError::InvalidFormat(_))), "{}", error);
+
+        // Later writes and finish fail instead of producing a document
+        assert!(writer.write_value(&BencodeValue::integer(1)).is_err());
+        assert!(writer.end().is_err());
+        assert!(writer.finish().is_err());
+
+        let mut long = BencodeWriter::new(Vec::new());
+        assert!(long.write_bytes_from(2, &b"abc"[..]).is_err());
+        assert!(long.finish().is_err());
+    }
+}
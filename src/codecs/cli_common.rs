@@ -0,0 +1,360 @@
+use crate::codecs::atomic::write_atomic;
+use anyhow::{bail, Context, Result};
+use clap::{Arg, ArgAction, ArgMatches};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Environment equivalent of `--threads`
+pub const THREADS_ENV: &str = "CODEC_THREADS";
+/// Environment equivalent of `--memory-limit`
+pub const MEMORY_LIMIT_ENV: &str = "CODEC_MEMORY_LIMIT";
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    Replace,
+    Refuse,
+}
+
+/// How the command line tools write their output files
+///
+/// `-` as an output means stdout, which always exists and is never refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Report each planned write and its size instead of writing
+    pub dry_run: bool,
+    pub overwrite: Overwrite,
+}
+
+impl OutputOptions {
+    /// `--dry-run`, `--no-clobber` and `--force`, for subcommands that write files
+    pub fn args() -> Vec<Arg> {
+        vec![
+            Arg::new("dry-run")
+                .help("Show what would be written, and how big, without writing anything")
+                .long("dry-run")
+                .action(ArgAction::SetTrue),
+            Arg::new("no-clobber")
+                .help("Fail instead of replacing an existing output file")
+                .long("no-clobber")
+                .action(ArgAction::SetTrue),
+            Arg::new("force")
+                .help("Replace existing output files (the default unless processing a batch)")
+                .long("force")
+                .conflicts_with("no-clobber")
+                .action(ArgAction::SetTrue),
+        ]
+    }
+
+    /// Read the flags from `args()`
+    ///
+    /// Without either flag, single-file invocations replace existing files
+    /// as they always have, while batch runs refuse to. `--no-clobber` wins
+    /// over a `--force` a subcommand defines with a wider meaning.
+    pub fn from_matches(matches: &ArgMatches, batch: bool) -> Self {
+        let overwrite = if matches.get_flag("no-clobber") || (batch && !matches.get_flag("force")) {
+            Overwrite::Refuse
+        } else {
+            Overwrite::Replace
+        };
+        Self { dry_run: matches.get_flag("dry-run"), overwrite }
+    }
+
+    /// Fail if `output` exists and may not be replaced, before any work is done
+    pub fn check(&self, output: &str) -> Result<()> {
+        if output != "-" && self.overwrite == Overwrite::Refuse && Path::new(output).exists() {
+            bail!("{} already exists (use --force to replace it)", output);
+        }
+        Ok(())
+    }
+
+    /// Write `data`, produced from `input`, to `output`
+    ///
+    /// Files are replaced atomically, so a failed write never leaves a partial one.
+    /// Returns false if this is a dry run and nothing was written.
+    pub fn write(&self, input: &str, output: &str, data: &[u8]) -> Result<bool> {
+        self.check(output)?;
+        if self.dry_run {
+            let replaces = if output != "-" && Path::new(output).exists() { ", replacing it" } else { "" };
+            let plan = format!("[dry run] {} -> {} ({} bytes{})", input, output, data.len(), replaces);
+            // Keep stdout clean for the data it would have carried
+            if output == "-" { eprintln!("{}", plan) } else { println!("{}", plan) }
+            return Ok(false);
+        }
+        if output == "-" {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(data)?;
+            stdout.flush()?;
+        } else {
+            write_atomic(output, data)?;
+        }
+        Ok(true)
+    }
+}
+
+/// A decode or encode whose estimated working set is over the memory limit
+#[derive(Debug, thiserror::Error)]
+#[error("{what} needs about {needed} bytes, over the {limit} byte memory limit")]
+pub struct MemoryLimitError {
+    pub what: String,
+    pub needed: u64,
+    pub limit: u64,
+}
+
+/// Threads and memory the codecs may use
+///
+/// With a thread count, parallel work runs on a pool of its own rather
+/// than rayon's global pool, which takes every core. Clones share the pool.
+#[derive(Clone, Default)]
+pub struct RuntimeConfig {
+    pool: Option<Arc<rayon::ThreadPool>>,
+    memory_limit: Option<u64>,
+}
+
+impl RuntimeConfig {
+    pub fn new(threads: Option<usize>, memory_limit: Option<u64>) -> Result<Self> {
+        let pool = match threads {
+            Some(0) => bail!("Thread count must be at least 1"),
+            Some(threads) => Some(Arc::new(rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|index| format!("codec-worker-{}", index))
+                .build()
+                .context("Failed to start the codec thread pool")?)),
+            None => None,
+        };
+        Ok(Self { pool, memory_limit })
+    }
+
+    /// `--threads` and `--memory-limit`, global to every subcommand
+    pub fn args() -> Vec<Arg> {
+        vec![
+            Arg::new("threads")
+                .help(format!("Worker threads for parallel coding [env: {}] (default: one per core)", THREADS_ENV))
+                .long("threads")
+                .value_name("N")
+                .global(true)
+                .value_parser(clap::value_parser!(usize)),
+            Arg::new("memory-limit")
+                .help(format!("Refuse inputs whose decode or encode needs more memory, e.g. 512m [env: {}]", MEMORY_LIMIT_ENV))
+                .long("memory-limit")
+                .value_name("BYTES")
+                .global(true)
+                .value_parser(parse_bytes),
+        ]
+    }
+
+    /// Read the flags from `args()`, falling back to the environment
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        let threads = match matches.get_one::<usize>("threads") {
+            Some(&threads) => Some(threads),
+            None => env_value(THREADS_ENV, |value| value.parse::<usize>().map_err(|e| e.to_string()))?,
+        };
+        let memory_limit = match matches.get_one::<u64>("memory-limit") {
+            Some(&limit) => Some(limit),
+            None => env_value(MEMORY_LIMIT_ENV, parse_bytes)?,
+        };
+        Self::new(threads, memory_limit)
+    }
+
+    /// Threads parallel work runs on
+    pub fn threads(&self) -> usize {
+        self.pool.as_ref().map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads())
+    }
+
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.memory_limit
+    }
+
+    /// Run `op` on this configuration's pool, or the current one if it has none
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Fail if `what`, needing about `needed` bytes, is over the memory limit
+    pub fn check_memory(&self, what: &str, needed: u64) -> Result<(), MemoryLimitError> {
+        match self.memory_limit {
+            Some(limit) if needed > limit => Err(MemoryLimitError { what: what.to_string(), needed, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for RuntimeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimeConfig")
+            .field("threads", &self.pool.as_ref().map(|pool| pool.current_num_threads()))
+            .field("memory_limit", &self.memory_limit)
+            .finish()
+    }
+}
+
+/// Send the codec's debug spans and events to stderr, with each span's
+/// timing, for a CLI's `--verbose`
+///
+/// Builds without the `tracing` feature have no subscriber to install, so
+/// they only warn that the flag does nothing.
+pub fn init_tracing(verbose: bool) {
+    if !verbose {
+        return;
+    }
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+    #[cfg(not(feature = "tracing"))]
+    eprintln!("Warning: built without the 'tracing' feature, --verbose has no effect");
+}
+
+fn env_value<T>(name: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) if !value.trim().is_empty() => Ok(Some(parse(value.trim()).map_err(|e| anyhow::anyhow!("{}: {}", name, e))?)),
+        _ => Ok(None),
+    }
+}
+
+/// Parse a byte count such as `4096`, `256k`, `512m` or `2g`
+pub fn parse_bytes(value: &str) -> Result<u64, String> {
+    let lower = value.trim().to_ascii_lowercase();
+    let (digits, shift) = match lower.as_bytes().last() {
+        Some(b'k') => (&lower[..lower.len() - 1], 10),
+        Some(b'm') => (&lower[..lower.len() - 1], 20),
+        Some(b'g') => (&lower[..lower.len() - 1], 30),
+        _ => (lower.as_str(), 0),
+    };
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(1 << shift))
+        .filter(|&bytes| bytes > 0)
+        .ok_or_else(|| format!("Invalid byte count: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Command;
+
+    fn parse(flags: &[&str], batch: bool) -> Result<OutputOptions, clap::Error> {
+        let command = Command::new("cli").args(OutputOptions::args());
+        let matches = command.try_get_matches_from(std::iter::once("cli").chain(flags.iter().copied()))?;
+        Ok(OutputOptions::from_matches(&matches, batch))
+    }
+
+    #[test]
+    fn test_flag_combinations() {
+        use Overwrite::*;
+        let cases: [(&[&str], bool, Overwrite); 6] = [
+            (&[], false, Replace),
+            (&[], true, Refuse),
+            (&["--force"], false, Replace),
+            (&["--force"], true, Replace),
+            (&["--no-clobber"], false, Refuse),
+            (&["--no-clobber"], true, Refuse),
+        ];
+        for (flags, batch, overwrite) in cases {
+            let options = parse(flags, batch).unwrap();
+            assert_eq!(options.overwrite, overwrite, "{:?} batch={}", flags, batch);
+            assert!(!options.dry_run);
+        }
+        assert!(parse(&["--dry-run", "--no-clobber"], true).unwrap().dry_run);
+        assert!(parse(&["--force", "--no-clobber"], false).is_err());
+    }
+
+    #[test]
+    fn test_refuse_and_dry_run_leave_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("existing.tcf");
+        std::fs::write(&existing, b"old").unwrap();
+        let existing = existing.to_str().unwrap();
+        let fresh = dir.path().join("fresh.tcf");
+        let fresh = fresh.to_str().unwrap();
+
+        let refuse = parse(&["--no-clobber"], false).unwrap();
+        assert!(refuse.check(existing).is_err());
+        assert!(refuse.write("in.txt", existing, b"new").is_err());
+        assert!(refuse.check("-").is_ok());
+        assert_eq!(std::fs::read(existing).unwrap(), b"old");
+
+        // A dry run still reports a refusal, but never creates or replaces a file
+        for flags in [&["--dry-run"][..], &["--dry-run", "--force"]] {
+            for batch in [false, true] {
+                let options = parse(flags, batch).unwrap();
+                assert!(!options.write("in.txt", fresh, b"new").unwrap());
+                assert_eq!(options.write("in.txt", existing, b"new").ok(), (!batch || flags.len() == 2).then_some(false));
+            }
+        }
+        assert!(!Path::new(fresh).exists());
+        assert_eq!(std::fs::read(existing).unwrap(), b"old");
+
+        assert!(parse(&[], true).unwrap().write("in.txt", fresh, b"new").unwrap());
+        assert_eq!(std::fs::read(fresh).unwrap(), b"new");
+    }
+
+    fn parse_runtime(flags: &[&str]) -> Result<RuntimeConfig> {
+        let command = Command::new("cli").args(RuntimeConfig::args());
+        RuntimeConfig::from_matches(&command.try_get_matches_from(std::iter::once("cli").chain(flags.iter().copied()))?)
+    }
+
+    #[test]
+    fn test_one_thread_runs_everything_on_one_worker() {
+        use rayon::prelude::*;
+        use std::sync::Mutex;
+
+        let runtime = parse_runtime(&["--threads", "1"]).unwrap();
+        assert_eq!(runtime.threads(), 1);
+        let seen = Mutex::new(Vec::new());
+        let order = runtime.install(|| (0..64).into_par_iter().map(|i| {
+            let thread = std::thread::current();
+            seen.lock().unwrap().push((thread.id(), thread.name().map(str::to_string)));
+            i
+        }).collect::<Vec<_>>());
+        assert_eq!(order, (0..64).collect::<Vec<_>>());
+        let mut seen = seen.into_inner().unwrap();
+        seen.dedup();
+        assert_eq!(seen.len(), 1, "{:?}", seen);
+        assert_eq!(seen[0].1.as_deref(), Some("codec-worker-0"));
+        assert_ne!(seen[0].0, std::thread::current().id());
+
+        // The codecs run their parallel work on the same pool
+        let codec = crate::codecs::image::IcfCodec::new().with_runtime(runtime.clone());
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([x as u8 * 4, y as u8 * 4, 128])));
+        let options = crate::codecs::image::IcfEncodeOptions::with_quality(80);
+        assert_eq!(codec.encode_with_options(&img, &options).unwrap(), codec.encode_with_options(&img, &options).unwrap());
+
+        assert!(parse_runtime(&["--threads", "0"]).is_err());
+        assert!(parse_runtime(&[]).unwrap().memory_limit().is_none());
+    }
+
+    #[test]
+    fn test_memory_limit_refuses_oversized_decodes() {
+        use crate::codecs::image::IcfCodec;
+        use crate::codecs::text::TcfCodec;
+
+        assert_eq!(parse_bytes("4096"), Ok(4096));
+        assert_eq!(parse_bytes("256k"), Ok(256 << 10));
+        assert_eq!(parse_bytes("2G"), Ok(2 << 30));
+        assert!(parse_bytes("0").is_err() && parse_bytes("12x").is_err());
+
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(128, 128, image::Rgb([90, 120, 150])));
+        let encoded = IcfCodec::new().encode_with_options(&img, &crate::codecs::image::IcfEncodeOptions::with_quality(80)).unwrap();
+        let limited = IcfCodec::new().with_runtime(parse_runtime(&["--memory-limit", "256k"]).unwrap());
+        let refused = limited.decode(&encoded).unwrap_err();
+        let refused = refused.downcast_ref::<MemoryLimitError>().unwrap();
+        assert_eq!((refused.needed, refused.limit), (128 * 128 * 27, 256 << 10));
+        assert!(limited.encode_with_options(&img, &Default::default()).is_err());
+        let roomy = IcfCodec::new().with_runtime(parse_runtime(&["--memory-limit", "1m"]).unwrap());
+        assert_eq!(roomy.decode(&encoded).unwrap().width(), 128);
+
+        let text = "a line of text to decode\n".repeat(100);
+        let encoded = TcfCodec::encode(&text).unwrap();
+        let small = parse_runtime(&["--memory-limit", "1k"]).unwrap();
+        assert!(TcfCodec::decode_with_runtime(&encoded, &small).unwrap_err().downcast_ref::<MemoryLimitError>().is_some());
+        assert_eq!(TcfCodec::decode_with_runtime(&encoded, &parse_runtime(&["--memory-limit", "4k"]).unwrap()).unwrap(), text);
+    }
+}
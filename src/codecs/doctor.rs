@@ -0,0 +1,394 @@
+use anyhow::{bail, Result};
+use image::{DynamicImage, RgbImage};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::codecs::bencode::BencodeCodec;
+use crate::codecs::formats::{self, FormatId};
+use crate::codecs::image::{DecodeOptions, IcfCodec};
+use crate::codecs::text::{TcfCodec, TcfEncodeOptions, TcfErrorCode, TcfIndex, TcfMethod};
+use crate::codecs::video::{VcfCodec, VideoFrame};
+
+/// Outcome of one self-test
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    /// What was verified, or what went wrong
+    pub detail: String,
+}
+
+/// Health of one file given to the doctor
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Sniffed format, or `None` if it matched none
+    pub format: Option<FormatId>,
+    pub summary: String,
+    /// Failures, each saying where and what
+    pub problems: Vec<String>,
+    /// Oddities that don't stop the file from decoding
+    pub notes: Vec<String>,
+}
+
+impl FileReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Everything `run` found, for printing or inspection
+#[derive(Debug, Clone)]
+pub struct DoctorReport {
+    /// Name and value of each build capability
+    pub capabilities: Vec<(&'static str, String)>,
+    pub self_tests: Vec<Check>,
+    pub files: Vec<FileReport>,
+}
+
+impl DoctorReport {
+    pub fn passed(&self) -> bool {
+        self.self_tests.iter().all(|check| check.passed) && self.files.iter().all(FileReport::passed)
+    }
+
+    /// 0 if every self-test and file passed, 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.passed() { 0 } else { 1 }
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = |passed| if passed { "ok  " } else { "FAIL" };
+        writeln!(f, "Build")?;
+        for (name, value) in &self.capabilities {
+            writeln!(f, "  {:<9} {}", format!("{}:", name), value)?;
+        }
+        writeln!(f, "Self-tests")?;
+        for check in &self.self_tests {
+            writeln!(f, "  {} {}: {}", status(check.passed), check.name, check.detail)?;
+        }
+        if !self.files.is_empty() {
+            writeln!(f, "Files")?;
+        }
+        for file in &self.files {
+            writeln!(f, "  {} {}: {}", status(file.passed()), file.path.display(), file.summary)?;
+            for problem in &file.problems {
+                writeln!(f, "       error: {}", problem)?;
+            }
+            for note in &file.notes {
+                writeln!(f, "       note: {}", note)?;
+            }
+        }
+        write!(f, "{}", if self.passed() { "All checks passed" } else { "Some checks failed" })
+    }
+}
+
+/// Report the build's capabilities, run the self-tests and check each of `files`
+pub fn run<P: AsRef<Path>>(files: &[P]) -> DoctorReport {
+    DoctorReport {
+        capabilities: capabilities(),
+        self_tests: self_tests(),
+        files: files.iter().map(|path| check_file(path.as_ref())).collect(),
+    }
+}
+
+fn capabilities() -> Vec<(&'static str, String)> {
+    let features: Vec<&str> = [
+        ("zstd", cfg!(feature = "zstd")),
+        ("interop", cfg!(feature = "interop")),
+        ("mmap", cfg!(feature = "mmap")),
+        ("tracing", cfg!(feature = "tracing")),
+    ].into_iter().filter(|&(_, enabled)| enabled).map(|(name, _)| name).collect();
+    let methods: Vec<&str> = TcfMethod::available().into_iter().map(TcfMethod::as_str).collect();
+
+    vec![
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ("features", if features.is_empty() { "none".to_string() } else { features.join(", ") }),
+        ("tcf", methods.join(", ")),
+        ("simd", format!("{} ({})", std::env::consts::ARCH, simd_features())),
+        ("threads", rayon::current_num_threads().to_string()),
+    ]
+}
+
+/// Vector extensions the CPU reports, as the compiler's feature names
+fn simd_features() -> String {
+    #[allow(unused_mut)]
+    let mut detected: Vec<&str> = Vec::new();
+    #[cfg(target_arch = "x86_64")]
+    {
+        for (name, present) in [
+            ("sse4.2", std::arch::is_x86_feature_detected!("sse4.2")),
+            ("avx2", std::arch::is_x86_feature_detected!("avx2")),
+            ("avx512f", std::arch::is_x86_feature_detected!("avx512f")),
+        ] {
+            if present {
+                detected.push(name);
+            }
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        detected.push("neon");
+    }
+    if detected.is_empty() { "none detected".to_string() } else { detected.join(", ") }
+}
+
+fn self_tests() -> Vec<Check> {
+    let mut checks: Vec<(String, Result<String>)> = TcfMethod::available().into_iter()
+        .map(|method| (format!("tcf {}", method), tcf_round_trip(method)))
+        .collect();
+    checks.push(("icf".to_string(), icf_round_trip()));
+    checks.push(("vcf".to_string(), vcf_round_trip()));
+    checks.push(("bencode".to_string(), bencode_round_trip()));
+
+    checks.into_iter()
+        .map(|(name, outcome)| match outcome {
+            Ok(detail) => Check { name, passed: true, detail },
+            Err(error) => Check { name, passed: false, detail: format!("{:#}", error) },
+        })
+        .collect()
+}
+
+fn tcf_round_trip(method: TcfMethod) -> Result<String> {
+    let text = "The quick brown fox jumps over the lazy dog.\r\n".repeat(50);
+    let options = TcfEncodeOptions { method: Some(method), ..Default::default() };
+    let encoded = TcfCodec::encode_with_options(&text, &options)?.data;
+    if TcfCodec::decode(&encoded)? != text {
+        bail!("decoded text differs from the sample");
+    }
+    Ok(format!("{} -> {} bytes", text.len(), encoded.len()))
+}
+
+/// Smallest PSNR an ICF or VCF sample may decode at
+const MIN_SAMPLE_PSNR: f64 = 30.0;
+
+fn icf_round_trip() -> Result<String> {
+    let sample = RgbImage::from_fn(32, 32, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8]));
+    let codec = IcfCodec::new();
+    let encoded = codec.encode_image(&DynamicImage::ImageRgb8(sample.clone()), 90)?;
+    let decoded = codec.decode(&encoded)?.to_rgb8();
+    if decoded.dimensions() != sample.dimensions() {
+        bail!("decoded to {:?}, expected {:?}", decoded.dimensions(), sample.dimensions());
+    }
+    let mse = sample.as_raw().iter().zip(decoded.as_raw())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum::<f64>() / sample.as_raw().len() as f64;
+    let psnr = crate::codecs::video::psnr_from_mse(mse);
+    if psnr < MIN_SAMPLE_PSNR {
+        bail!("decoded at {:.1} dB PSNR, expected at least {}", psnr, MIN_SAMPLE_PSNR);
+    }
+    Ok(format!("{} bytes, {:.1} dB", encoded.len(), psnr))
+}
+
+fn vcf_round_trip() -> Result<String> {
+    let frames: Vec<VideoFrame> = (0..3u32)
+        .map(|index| {
+            let mut frame = VideoFrame::new(32, 32);
+            for y in 0..32 {
+                for x in 0..32 {
+                    frame.planes[0].set(x, y, ((x + y) * 4 + index as usize * 2) as u8);
+                }
+            }
+            frame
+        })
+        .collect();
+    let codec = VcfCodec::new();
+    let encoded = codec.encode_frames(frames.iter().cloned().map(Ok), 25.0, 90)?;
+    let report = codec.quality_report(frames.into_iter().map(Ok), &encoded)?;
+    let worst = report.iter().map(|frame| frame.psnr).fold(f64::INFINITY, f64::min);
+    if worst < MIN_SAMPLE_PSNR {
+        bail!("worst frame decoded at {:.1} dB PSNR, expected at least {}", worst, MIN_SAMPLE_PSNR);
+    }
+    Ok(format!("{} frames, {} bytes, worst {:.1} dB", report.len(), encoded.len(), worst))
+}
+
+fn bencode_round_trip() -> Result<String> {
+    let sample: &[u8] = b"d4:infod6:lengthi1024e4:name8:doctor.x12:piece lengthi262144ee4:listl1:ai-7eee";
+    let encoded = BencodeCodec::encode(&BencodeCodec::decode(sample)?)?;
+    if encoded != sample {
+        bail!("re-encoding the sample changed it");
+    }
+    Ok(format!("{} bytes", sample.len()))
+}
+
+fn check_file(path: &Path) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        format: None,
+        summary: String::new(),
+        problems: Vec::new(),
+        notes: Vec::new(),
+    };
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) => {
+            report.summary = "unreadable".to_string();
+            report.problems.push(format!("can't read the file: {}", error));
+            return report;
+        }
+    };
+
+    report.format = formats::sniff(&data);
+    let checked = match report.format {
+        Some(FormatId::Tcf) => check_tcf(&data, &mut report),
+        Some(FormatId::TcfIndex) => TcfIndex::from_bytes(&data).map(|_| ()),
+        Some(FormatId::Icf) => check_icf(&data, &mut report),
+        Some(FormatId::Vcf) => check_vcf(&data, &mut report),
+        Some(FormatId::Bencode) => check_bencode(&data, &mut report),
+        None => {
+            let start = &data[..data.len().min(8)];
+            report.summary = format!("{} bytes", data.len());
+            report.problems.push(format!(
+                "not a TCF, ICF, VCF or bencode file (starts with {:02x?})", start
+            ));
+            return report;
+        }
+    };
+    if let Err(error) = checked {
+        report.problems.push(format!("{:#}", error));
+    }
+    if report.summary.is_empty() {
+        report.summary = format!("{}, {} bytes", report.format.map_or("?", FormatId::name), data.len());
+    }
+    report
+}
+
+fn check_tcf(data: &[u8], report: &mut FileReport) -> Result<()> {
+    let layout = TcfCodec::parse_layout(data)?;
+    let header = &layout.header;
+    report.summary = format!(
+        "TCF {}, {} bytes of text in {} bytes{}",
+        header.compression_method, header.original_size, data.len(),
+        if header.chunks.is_empty() { String::new() } else { format!(", {} chunks", header.chunks.len()) },
+    );
+    if layout.trailing_size > 0 {
+        report.notes.push(format!("{} unexpected bytes after the payload", layout.trailing_size));
+    }
+
+    // Chunked files decode one chunk per piece, so the failing piece is the damaged chunk
+    let mut pieces = TcfCodec::decode_stream(data)?;
+    let mut decoded = 0;
+    let error = loop {
+        match pieces.next() {
+            Some(Ok(_)) => decoded += 1,
+            Some(Err(error)) => break error,
+            None => return Ok(()),
+        }
+    };
+    let code = TcfErrorCode::of(&error);
+    let data_start = layout.region("model").map_or(0, |region| region.offset);
+    let message = match header.chunks.get(decoded) {
+        Some(chunk) if code == TcfErrorCode::ChecksumMismatch => format!(
+            "chunk {} checksum mismatch at offset {:#X}; the file is damaged, fetch it again",
+            decoded, data_start + chunk.data_offset
+        ),
+        _ => match code {
+            TcfErrorCode::ChecksumMismatch => format!("{:#}; the file is damaged, fetch it again", error),
+            TcfErrorCode::Truncated => format!("{:#}; the file was cut short, fetch it again", error),
+            TcfErrorCode::UnsupportedMethod => format!("{:#}; this build can't decode it", error),
+            TcfErrorCode::UnsupportedVersion => format!("{:#}; written by a newer encoder", error),
+            _ => format!("{:#}", error),
+        },
+    };
+    report.problems.push(message);
+    Ok(())
+}
+
+fn check_icf(data: &[u8], report: &mut FileReport) -> Result<()> {
+    let codec = IcfCodec::new();
+    codec.check_complete(data)?;
+    let layout = codec.parse_layout(data)?;
+    let header = &layout.header;
+    report.summary = format!(
+        "ICF {}x{}, quality {}, {} bytes", header.width, header.height, header.quality, data.len()
+    );
+    if layout.trailing_size > 0 {
+        report.notes.push(format!("{} unexpected bytes after the blocks", layout.trailing_size));
+    }
+
+    let outcome = codec.decode_checked(data, &DecodeOptions::default())?;
+    if !outcome.checksum_matched && header.is_lossless() {
+        report.problems.push("lossless file doesn't reproduce its checksum; the file is damaged".to_string());
+    }
+    codec.metadata(data)?;
+    Ok(())
+}
+
+fn check_vcf(data: &[u8], report: &mut FileReport) -> Result<()> {
+    let codec = VcfCodec::new();
+    let frames = codec.frames(data)?;
+    let header = frames.header().clone();
+    report.summary = format!(
+        "VCF {}x{}, {} frames at {} fps, {} bytes", header.width, header.height, header.frame_count, header.fps, data.len()
+    );
+    for (index, frame) in frames.enumerate() {
+        if let Err(error) = frame {
+            report.problems.push(format!("frame {} doesn't decode: {:#}", index, error));
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn check_bencode(data: &[u8], report: &mut FileReport) -> Result<()> {
+    let value = BencodeCodec::decode(data)?;
+    if BencodeCodec::encode(&value)? != data {
+        report.notes.push("not canonically encoded; re-encoding changes the bytes".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::ChunkStrategy;
+
+    fn chunked_fixture() -> Vec<u8> {
+        let text = (0..400).map(|i| format!("line {} of the doctor fixture\n", i)).collect::<String>();
+        let options = TcfEncodeOptions { chunking: Some(ChunkStrategy::FixedBytes(2048)), ..Default::default() };
+        TcfCodec::encode_with_options(&text, &options).unwrap().data
+    }
+
+    #[test]
+    fn test_good_files_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let tcf = dir.path().join("good.tcf");
+        fs::write(&tcf, chunked_fixture()).unwrap();
+        let torrent = dir.path().join("good.torrent");
+        fs::write(&torrent, b"d4:name4:teste").unwrap();
+
+        let report = run(&[&tcf, &torrent]);
+        assert!(report.self_tests.iter().all(|check| check.passed), "{}", report);
+        assert_eq!(report.exit_code(), 0, "{}", report);
+        assert_eq!(report.files[0].format, Some(FormatId::Tcf));
+        assert!(report.files[0].summary.contains("chunks"), "{}", report.files[0].summary);
+        assert_eq!(report.files[1].format, Some(FormatId::Bencode));
+        assert!(report.to_string().ends_with("All checks passed"));
+    }
+
+    #[test]
+    fn test_damaged_chunk_is_located() {
+        let mut data = chunked_fixture();
+        let layout = TcfCodec::parse_layout(&data).unwrap();
+        let chunk = &layout.header.chunks[3];
+        let offset = layout.region("model").unwrap().offset + chunk.data_offset;
+        let stored = offset + chunk.model_size as u64 + chunk.compressed_size / 2;
+        data[stored as usize] ^= 0x55;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad.tcf");
+        fs::write(&path, &data).unwrap();
+        let unknown = dir.path().join("notes.txt");
+        fs::write(&unknown, b"#!/bin/sh\n").unwrap();
+
+        let report = run(&[&path, &unknown]);
+        assert_eq!(report.exit_code(), 1);
+        let problems = &report.files[0].problems;
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with(&format!("chunk 3 checksum mismatch at offset {:#X}", offset)), "{}", problems[0]);
+        assert_eq!(report.files[1].format, None);
+        assert!(report.to_string().contains("not a TCF, ICF, VCF or bencode file"));
+    }
+}
@@ -0,0 +1,74 @@
+//! Why an encode came out the way it did
+//!
+//! Encoders record each choice they make automatically (which method,
+//! which subsampling) as `Decision`s in an `ExplainTrace`. Recording never
+//! changes what is encoded.
+use anyhow::Result;
+use serde::Serialize;
+
+/// One step of an encoder's reasoning
+///
+/// `step` names the choice being made, e.g. `tcf.method`; a step's records
+/// appear in the order they were made, ending with its `Chosen` record.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Decision {
+    /// An option weighed by `metric`, lower being better
+    Candidate { step: &'static str, option: String, metric: &'static str, value: f64 },
+    /// A measurement compared against a fixed cutoff
+    Threshold { step: &'static str, metric: &'static str, value: f64, threshold: f64, exceeded: bool },
+    /// The option taken, and why
+    Chosen { step: &'static str, option: String, reason: String },
+}
+
+impl Decision {
+    pub fn step(&self) -> &'static str {
+        match self {
+            Decision::Candidate { step, .. } | Decision::Threshold { step, .. } | Decision::Chosen { step, .. } => step,
+        }
+    }
+}
+
+/// The decisions of one encode, in order
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExplainTrace {
+    pub decisions: Vec<Decision>,
+}
+
+impl ExplainTrace {
+    /// Bumped when records are renamed or change meaning; new steps don't bump it
+    pub const VERSION: u32 = 1;
+
+    pub fn candidate(&mut self, step: &'static str, option: impl ToString, metric: &'static str, value: f64) {
+        self.decisions.push(Decision::Candidate { step, option: option.to_string(), metric, value });
+    }
+
+    /// Record `value` against `threshold`, returning whether it exceeded it
+    pub fn threshold(&mut self, step: &'static str, metric: &'static str, value: f64, threshold: f64) -> bool {
+        let exceeded = value > threshold;
+        self.decisions.push(Decision::Threshold { step, metric, value, threshold, exceeded });
+        exceeded
+    }
+
+    pub fn chosen(&mut self, step: &'static str, option: impl ToString, reason: impl Into<String>) {
+        self.decisions.push(Decision::Chosen { step, option: option.to_string(), reason: reason.into() });
+    }
+
+    /// Options taken at `step`, in order
+    pub fn choices(&self, step: &str) -> Vec<&str> {
+        self.decisions.iter()
+            .filter_map(|decision| match decision {
+                Decision::Chosen { step: chosen_step, option, .. } if *chosen_step == step => Some(option.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `{"version": 1, "decisions": [...]}`, pretty-printed
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&serde_json::json!({
+            "version": Self::VERSION,
+            "decisions": self.decisions,
+        }))?)
+    }
+}
@@ -0,0 +1,177 @@
+//! Magic bytes and versions of every container format
+//!
+//! Codecs read their magic and version from here, so writers, readers and
+//! sniffers can't drift apart.
+use std::fmt;
+
+/// Formats `sniff` can tell apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormatId {
+    Tcf,
+    /// Seek index written alongside a chunked TCF file
+    TcfIndex,
+    Icf,
+    Vcf,
+    /// No magic: recognized by the first byte of a dictionary, list,
+    /// integer or byte string
+    Bencode,
+}
+
+impl FormatId {
+    pub fn name(self) -> &'static str {
+        match self {
+            FormatId::Tcf => "TCF",
+            FormatId::TcfIndex => "TCF index",
+            FormatId::Icf => "ICF",
+            FormatId::Vcf => "VCF",
+            FormatId::Bencode => "bencode",
+        }
+    }
+}
+
+impl fmt::Display for FormatId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A container format's signature and the versions of it this build handles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub id: FormatId,
+    /// First bytes of every file
+    pub magic: &'static str,
+    /// Version new files are written as
+    pub version: u16,
+    pub min_readable: u16,
+    pub max_readable: u16,
+}
+
+impl Format {
+    /// Whether files of `version` can be read
+    pub fn reads(&self, version: u16) -> bool {
+        (self.min_readable..=self.max_readable).contains(&version)
+    }
+}
+
+pub const TCF: Format = Format { id: FormatId::Tcf, magic: "TCF2", version: 2, min_readable: 2, max_readable: 2 };
+pub const TCF_INDEX: Format = Format { id: FormatId::TcfIndex, magic: "TCFX", version: 1, min_readable: 1, max_readable: 1 };
+pub const ICF: Format = Format { id: FormatId::Icf, magic: "ICF2", version: 2, min_readable: 2, max_readable: 2 };
+/// Version 2 added the per-frame QP, version 3 the frame timestamps
+pub const VCF: Format = Format { id: FormatId::Vcf, magic: "VCF1", version: 3, min_readable: 1, max_readable: 3 };
+
+/// Container of the TypeScript codec, read only to migrate it; not sniffed
+pub const TCF1: Format = Format { id: FormatId::Tcf, magic: "TCF1", version: 1, min_readable: 1, max_readable: 1 };
+
+/// Every format with a magic
+pub const FORMATS: [Format; 4] = [TCF, TCF_INDEX, ICF, VCF];
+
+const _: () = assert!(registry_is_consistent(&FORMATS), "format magics must be distinct and versions readable");
+
+/// No magic is a prefix of another, and each format reads its own version
+const fn registry_is_consistent(formats: &[Format]) -> bool {
+    let mut i = 0;
+    while i < formats.len() {
+        let format = &formats[i];
+        if format.magic.is_empty() || format.min_readable > format.version || format.version > format.max_readable {
+            return false;
+        }
+        let mut j = i + 1;
+        while j < formats.len() {
+            if is_prefix(format.magic.as_bytes(), formats[j].magic.as_bytes())
+                || is_prefix(formats[j].magic.as_bytes(), format.magic.as_bytes())
+            {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn is_prefix(prefix: &[u8], bytes: &[u8]) -> bool {
+    if prefix.len() > bytes.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < prefix.len() {
+        if prefix[i] != bytes[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Format of a file from its first bytes; `None` if unknown or too short to tell
+pub fn sniff(data: &[u8]) -> Option<FormatId> {
+    if let Some(format) = FORMATS.iter().find(|format| data.starts_with(format.magic.as_bytes())) {
+        return Some(format.id);
+    }
+    match data.first()? {
+        b'd' | b'l' | b'i' | b'0'..=b'9' => Some(FormatId::Bencode),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::{TcfCodec, TcfIndex};
+    use crate::codecs::image::IcfCodec;
+    use crate::codecs::video::{VcfCodec, VideoFrame};
+    use std::path::Path;
+
+    #[test]
+    fn test_sniff_recognizes_each_format() {
+        let tcf = TcfCodec::encode("sniff me").unwrap();
+        let icf = IcfCodec::new().encode_image(&image::DynamicImage::new_rgb8(8, 8), 80).unwrap();
+        let vcf = VcfCodec::new().encode_frames(vec![Ok(VideoFrame::new(16, 16))], 25.0, 80).unwrap();
+        let options = crate::codecs::text::TcfEncodeOptions {
+            chunking: Some(crate::codecs::text::ChunkStrategy::FixedBytes(64)),
+            ..Default::default()
+        };
+        let chunked = TcfCodec::encode_with_options(&"x".repeat(200), &options).unwrap().data;
+        let index = TcfIndex::from_tcf(&chunked).unwrap().to_bytes().unwrap();
+
+        for (data, expected) in [(&tcf, FormatId::Tcf), (&icf, FormatId::Icf), (&vcf, FormatId::Vcf), (&index, FormatId::TcfIndex)] {
+            assert_eq!(sniff(&data[..4]), Some(expected));
+            assert_eq!(sniff(data), Some(expected));
+        }
+        assert_eq!(sniff(b"d4:spami1ee"), Some(FormatId::Bencode));
+
+        // Cut-off magics and unknown data
+        for data in [&b""[..], b"TCF", b"TC", b"\x89PNG\r\n", b"VCF2xxxx"] {
+            assert_eq!(sniff(data), None, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn test_magics_are_declared_only_here() {
+        // Code outside this module must use the registry; tests may spell magics out
+        fn scan(dir: &Path, found: &mut Vec<String>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    scan(&path, found);
+                } else if path.extension().is_some_and(|ext| ext == "rs") && !path.ends_with("codecs/formats.rs") {
+                    let source = std::fs::read_to_string(&path).unwrap();
+                    let code = source.split("#[cfg(test)]").next().unwrap_or_default();
+                    for format in FORMATS.iter().chain([&TCF1]) {
+                        if code.contains(&format!("\"{}\"", format.magic)) {
+                            found.push(format!("{} declares {}", path.display(), format.magic));
+                        }
+                    }
+                }
+            }
+        }
+        let mut found = Vec::new();
+        scan(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut found);
+        assert!(found.is_empty(), "{:#?}", found);
+
+        assert!(registry_is_consistent(&FORMATS));
+        assert!(!registry_is_consistent(&[TCF, Format { magic: "TCF", ..ICF }]));
+        assert!(VCF.reads(1) && !VCF.reads(4));
+    }
+}
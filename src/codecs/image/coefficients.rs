@@ -0,0 +1,74 @@
+use anyhow::Result;
+
+use crate::codecs::image::quantization::Quantization;
+use crate::codecs::npy;
+
+/// Quantized DCT coefficients of one channel, laid out like its pixels
+///
+/// Block `(x, y)` occupies rows `y * 8..y * 8 + 8` and columns
+/// `x * 8..x * 8 + 8` with its DC coefficient top-left, so the plane spans
+/// the whole block grid, padding included. DC values are absolute, with
+/// the differential prediction of the bitstream already undone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoefficientPlane {
+    pub blocks_x: usize,
+    pub blocks_y: usize,
+    /// `height()` rows of `width()` coefficients
+    pub quantized: Vec<i16>,
+    pub quantization_table: [[f64; 8]; 8],
+}
+
+impl CoefficientPlane {
+    pub fn width(&self) -> usize {
+        self.blocks_x * 8
+    }
+
+    pub fn height(&self) -> usize {
+        self.blocks_y * 8
+    }
+
+    /// Quantized coefficients of block `(x, y)`, indexed `[row][col]`
+    pub fn block(&self, x: usize, y: usize) -> Option<[[i16; 8]; 8]> {
+        if x >= self.blocks_x || y >= self.blocks_y {
+            return None;
+        }
+        let width = self.width();
+        Some(std::array::from_fn(|row| {
+            let start = (y * 8 + row) * width + x * 8;
+            std::array::from_fn(|col| self.quantized[start + col])
+        }))
+    }
+
+    /// Coefficients of block `(x, y)` scaled back by the quantization table
+    pub fn dequantized_block(&self, x: usize, y: usize) -> Option<[[f64; 8]; 8]> {
+        self.block(x, y).map(|block| Quantization::dequantize_block(&block, &self.quantization_table))
+    }
+
+    /// The whole plane scaled back by the quantization table, in the same layout
+    pub fn dequantized(&self) -> Vec<f64> {
+        let width = self.width();
+        self.quantized.iter()
+            .enumerate()
+            .map(|(i, &value)| value as f64 * self.quantization_table[(i / width) % 8][(i % width) % 8])
+            .collect()
+    }
+
+    /// The plane as a `height() x width()` NumPy array, `int16` or, when
+    /// `dequantized`, `float64`
+    pub fn to_npy(&self, dequantized: bool) -> Result<Vec<u8>> {
+        let shape = [self.height(), self.width()];
+        if dequantized {
+            npy::write_npy(&shape, &self.dequantized())
+        } else {
+            npy::write_npy(&shape, &self.quantized)
+        }
+    }
+}
+
+/// Coefficient planes of every channel of an ICF file, in channel order
+///
+/// Subsampled chroma planes cover the smaller chroma block grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoefficientPlanes {
+    pub channels: Vec<CoefficientPlane>,
+}
@@ -1,5 +1,9 @@
 use ndarray::Array2;
+use std::collections::HashMap;
 use std::f64::consts::PI;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::codecs::precision::round_table_value;
 
 /// High-performance 2D DCT implementation using separable transforms
 pub struct DctTransform {
@@ -21,15 +25,27 @@ impl DctTransform {
         Self {
             size,
             cosine_table: cosine_table.into_iter().map(|row| {
-                row.into_iter().map(|x| x.cos()).collect()
+                row.into_iter().map(|x| round_table_value(x.cos())).collect()
             }).collect(),
         }
     }
 
+    /// Shared transform for `size`, building its cosine table on first use
+    pub fn shared(size: usize) -> Arc<DctTransform> {
+        static CACHE: OnceLock<Mutex<HashMap<usize, Arc<DctTransform>>>> = OnceLock::new();
+        let mut cache = CACHE.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        cache.entry(size).or_insert_with(|| Arc::new(DctTransform::new(size))).clone()
+    }
+
     /// Apply forward 2D DCT transform
     pub fn forward(&self, input: &Array2<f64>) -> Array2<f64> {
         let mut output = Array2::zeros((self.size, self.size));
-        
+        self.forward_into(input, &mut output);
+        output
+    }
+
+    /// `forward` into an existing `size` x `size` buffer
+    pub fn forward_into(&self, input: &Array2<f64>, output: &mut Array2<f64>) {
         for u in 0..self.size {
             for v in 0..self.size {
                 let mut sum = 0.0;
@@ -48,14 +64,17 @@ impl DctTransform {
                 output[[u, v]] = (2.0 / self.size as f64) * cu * cv * sum;
             }
         }
-        
-        output
     }
 
     /// Apply inverse 2D DCT transform
     pub fn inverse(&self, input: &Array2<f64>) -> Array2<f64> {
         let mut output = Array2::zeros((self.size, self.size));
-        
+        self.inverse_into(input, &mut output);
+        output
+    }
+
+    /// `inverse` into an existing `size` x `size` buffer
+    pub fn inverse_into(&self, input: &Array2<f64>, output: &mut Array2<f64>) {
         for x in 0..self.size {
             for y in 0..self.size {
                 let mut sum = 0.0;
@@ -74,8 +93,6 @@ impl DctTransform {
                 output[[x, y]] = (2.0 / self.size as f64) * sum;
             }
         }
-        
-        output
     }
 
     /// Apply forward DCT using separable approach (more efficient)
@@ -177,10 +194,7 @@ impl Dct8x8 {
         // Precompute DCT coefficients for 8x8 blocks
         for i in 0..8 {
             for j in 0..8 {
-                let ci = if i == 0 { 1.0 / (2.0_f64).sqrt() } else { 1.0 };
-                let angle = PI * (2.0 * j as f64 + 1.0) * i as f64 / 16.0;
-                
-                forward_table[i][j] = ci * 0.5 * angle.cos();
+                forward_table[i][j] = round_table_value(Self::basis(i, j));
                 inverse_table[j][i] = forward_table[i][j]; // Transpose for inverse
             }
         }
@@ -191,10 +205,28 @@ impl Dct8x8 {
         }
     }
 
+    /// Sample `j` of DCT basis function `i`, before rounding
+    fn basis(i: usize, j: usize) -> f64 {
+        let ci = if i == 0 { 1.0 / (2.0_f64).sqrt() } else { 1.0 };
+        let angle = PI * (2.0 * j as f64 + 1.0) * i as f64 / 16.0;
+        ci * 0.5 * angle.cos()
+    }
+
+    /// Process-wide instance, so codecs don't rebuild the tables
+    pub fn shared() -> &'static Dct8x8 {
+        static SHARED: OnceLock<Dct8x8> = OnceLock::new();
+        SHARED.get_or_init(Dct8x8::new)
+    }
+
     /// Fast 8x8 forward DCT
     pub fn forward_8x8(&self, input: &[[f64; 8]; 8]) -> [[f64; 8]; 8] {
         let mut output = [[0.0; 8]; 8];
-        
+        self.forward_8x8_into(input, &mut output);
+        output
+    }
+
+    /// `forward_8x8` into an existing block
+    pub fn forward_8x8_into(&self, input: &[[f64; 8]; 8], output: &mut [[f64; 8]; 8]) {
         // Apply 1D DCT to rows
         let mut temp = [[0.0; 8]; 8];
         for i in 0..8 {
@@ -217,14 +249,17 @@ impl Dct8x8 {
                 output[i][j] = sum;
             }
         }
-        
-        output
     }
 
     /// Fast 8x8 inverse DCT
     pub fn inverse_8x8(&self, input: &[[f64; 8]; 8]) -> [[f64; 8]; 8] {
         let mut output = [[0.0; 8]; 8];
-        
+        self.inverse_8x8_into(input, &mut output);
+        output
+    }
+
+    /// `inverse_8x8` into an existing block
+    pub fn inverse_8x8_into(&self, input: &[[f64; 8]; 8], output: &mut [[f64; 8]; 8]) {
         // Apply 1D IDCT to rows
         let mut temp = [[0.0; 8]; 8];
         for i in 0..8 {
@@ -247,8 +282,6 @@ impl Dct8x8 {
                 output[i][j] = sum;
             }
         }
-        
-        output
     }
 }
 
@@ -274,6 +307,22 @@ impl ColorSpace {
         (r, g, b)
     }
 
+    /// Convert RGB to JFIF YCbCr (full-range BT.601), chroma centered on 0
+    pub fn rgb_to_ycbcr(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = -0.168736 * r - 0.331264 * g + 0.5 * b;
+        let cr = 0.5 * r - 0.418688 * g - 0.081312 * b;
+        (y, cb, cr)
+    }
+
+    /// Convert JFIF YCbCr back to RGB
+    pub fn ycbcr_to_rgb(y: f64, cb: f64, cr: f64) -> (f64, f64, f64) {
+        let r = y + 1.402 * cr;
+        let g = y - 0.344136 * cb - 0.714136 * cr;
+        let b = y + 1.772 * cb;
+        (r, g, b)
+    }
+
     /// Convert RGB to YUV color space
     pub fn rgb_to_yuv(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
         let y = 0.299 * r + 0.587 * g + 0.114 * b;
@@ -320,6 +369,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dct_table_rounding_is_stable() {
+        // Every entry must sit far from a rounding boundary, in units of
+        // 2^-TABLE_FRACTION_BITS, so a libm a few ulps off (an ulp is at
+        // most 1/8192 of a unit here) still produces the same table
+        let scale = (1u64 << crate::codecs::precision::TABLE_FRACTION_BITS) as f64;
+        for i in 0..8 {
+            for j in 0..8 {
+                let scaled = Dct8x8::basis(i, j) * scale;
+                let margin = (scaled - scaled.floor() - 0.5).abs();
+                assert!(margin > 1.0 / 256.0, "entry ({}, {}) is {} from a rounding boundary", i, j, margin);
+            }
+        }
+    }
+
     #[test]
     fn test_8x8_dct_roundtrip() {
         let dct = Dct8x8::new();
@@ -344,6 +408,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_into_matches_allocating_api() {
+        let dct = DctTransform::shared(8);
+        assert!(Arc::ptr_eq(&dct, &DctTransform::shared(8)));
+        let input = Array2::from_shape_fn((8, 8), |(i, j)| ((i * 37 + j * 11) % 255) as f64 - 128.0);
+
+        // Stale contents in the reused buffer must not leak into the result
+        let mut output = Array2::from_elem((8, 8), f64::NAN);
+        dct.forward_into(&input, &mut output);
+        assert_eq!(output, dct.forward(&input));
+        let coefficients = output.clone();
+        dct.inverse_into(&coefficients, &mut output);
+        assert_eq!(output, dct.inverse(&coefficients));
+
+        let dct8 = Dct8x8::shared();
+        let block: [[f64; 8]; 8] = std::array::from_fn(|i| std::array::from_fn(|j| input[[i, j]]));
+        let mut out = [[f64::NAN; 8]; 8];
+        dct8.forward_8x8_into(&block, &mut out);
+        assert_eq!(out, Dct8x8::new().forward_8x8(&block));
+        let coefficients = out;
+        dct8.inverse_8x8_into(&coefficients, &mut out);
+        assert_eq!(out, Dct8x8::new().inverse_8x8(&coefficients));
+    }
+
     #[test]
     fn test_color_space_conversions() {
         let rgb = (0.5, 0.7, 0.3);
@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::DeflateDecoder;
+use flate2::{Compress, Compression, FlushCompress, Status};
+use std::io::Read;
+
+/// Flag byte of a section stored as it is
+pub const SECTION_STORED: u8 = 0;
+/// Flag byte of a section deflated against `DICTIONARY`
+pub const SECTION_DEFLATE: u8 = 1;
+
+/// Strings common in metadata and quantization table JSON, which deflated
+/// sections may refer back to
+///
+/// Part of the format: changing it breaks every file with a deflated section.
+const DICTIONARY: &[u8] = b"{\"source_name\":\"\",\"created_at\":17,\"tags\":{\"camera\":\"\",\"origin\":\"\",\"exif\":\"\"}}.jpg.jpeg.png.webp\
+[[[16.0,11.0,10.0,16.0,24.0,40.0,51.0,61.0],[12.0,12.0,14.0,19.0,26.0,58.0,60.0,55.0],\
+[14.0,13.0,16.0,24.0,40.0,57.0,69.0,56.0],[14.0,17.0,22.0,29.0,51.0,87.0,80.0,62.0],\
+[18.0,22.0,37.0,56.0,68.0,109.0,103.0,77.0],[24.0,35.0,55.0,64.0,81.0,104.0,113.0,92.0],\
+[49.0,64.0,78.0,87.0,103.0,121.0,120.0,101.0],[72.0,92.0,95.0,98.0,112.0,100.0,103.0,99.0]],\
+[[17.0,18.0,24.0,47.0,99.0,99.0,99.0,99.0],[18.0,21.0,26.0,66.0,99.0,99.0,99.0,99.0],\
+[24.0,26.0,56.0,99.0,99.0,99.0,99.0,99.0],[47.0,66.0,99.0,99.0,99.0,99.0,99.0,99.0],\
+[99.0,99.0,99.0,99.0,99.0,99.0,99.0,99.0],[99.0,99.0,99.0,99.0,99.0,99.0,99.0,99.0]]]";
+
+/// `json` behind a flag byte, deflated if that makes it smaller
+pub fn pack(json: &[u8]) -> Result<Vec<u8>> {
+    // Run the dictionary through the compressor and flush to a byte
+    // boundary, so what follows can match against it; only the output
+    // after the flush is kept
+    let mut compress = Compress::new(Compression::best(), false);
+    let mut primed = Vec::with_capacity(DICTIONARY.len() * 2 + 64);
+    compress.compress_vec(DICTIONARY, &mut primed, FlushCompress::Sync)
+        .context("Failed to prime section compressor")?;
+    if compress.total_in() != DICTIONARY.len() as u64 {
+        bail!("Section compressor didn't take the whole dictionary");
+    }
+
+    let mut deflated = Vec::with_capacity(json.len() + 64);
+    loop {
+        let consumed = (compress.total_in() - DICTIONARY.len() as u64) as usize;
+        let status = compress.compress_vec(&json[consumed..], &mut deflated, FlushCompress::Finish)
+            .context("Failed to deflate section")?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        deflated.reserve(deflated.capacity().max(64));
+    }
+
+    let mut packed = Vec::with_capacity(1 + json.len().min(deflated.len()));
+    if deflated.len() < json.len() {
+        packed.push(SECTION_DEFLATE);
+        packed.extend_from_slice(&deflated);
+    } else {
+        packed.push(SECTION_STORED);
+        packed.extend_from_slice(json);
+    }
+    Ok(packed)
+}
+
+/// The JSON of a section written by `pack`, which must be `expanded_length` bytes
+pub fn unpack(stored: &[u8], expanded_length: u64) -> Result<Vec<u8>> {
+    let (&flag, body) = stored.split_first().context("Empty ICF section")?;
+    let json = match flag {
+        SECTION_STORED => body.to_vec(),
+        SECTION_DEFLATE => {
+            // A stored deflate block holding the dictionary stands in for
+            // however the encoder coded it: matches only see the output
+            let length = DICTIONARY.len() as u16;
+            let mut prefix = vec![0];
+            prefix.extend_from_slice(&length.to_le_bytes());
+            prefix.extend_from_slice(&(!length).to_le_bytes());
+            prefix.extend_from_slice(DICTIONARY);
+
+            let limit = (DICTIONARY.len() as u64).saturating_add(expanded_length).saturating_add(1);
+            let mut expanded = Vec::new();
+            DeflateDecoder::new(prefix.as_slice().chain(body))
+                .take(limit)
+                .read_to_end(&mut expanded)
+                .context("Failed to inflate ICF section")?;
+            expanded.split_off(DICTIONARY.len().min(expanded.len()))
+        }
+        other => bail!("Unknown ICF section flag {}", other),
+    };
+    if json.len() as u64 != expanded_length {
+        bail!("ICF section expands to {} bytes, header says {}", json.len(), expanded_length);
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dictionary_shrinks_small_sections() {
+        let json = br#"{"source_name":"holiday.jpg","tags":{"camera":"phone","origin":"upload"}}"#;
+        let packed = pack(json).unwrap();
+        assert_eq!(packed[0], SECTION_DEFLATE);
+        assert!(packed.len() < json.len() * 2 / 3, "{} bytes", packed.len());
+        assert_eq!(unpack(&packed, json.len() as u64).unwrap(), json);
+        assert!(unpack(&packed, json.len() as u64 + 1).is_err());
+
+        // Nothing to gain: stored as it is
+        let packed = pack(b"{}").unwrap();
+        assert_eq!(packed, b"\0{}");
+        assert_eq!(unpack(&packed, 2).unwrap(), b"{}");
+    }
+}
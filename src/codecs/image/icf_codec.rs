@@ -1,13 +1,39 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use image::{DynamicImage, GrayImage, RgbImage};
 use anyhow::{Result, Context};
 use rayon::prelude::*;
 
 use crate::codecs::image::{
-    dct_transform::{Dct8x8, ColorSpace},
+    coefficients::{CoefficientPlane, CoefficientPlanes},
+    dct_transform::Dct8x8,
+    header_sections,
+    input,
+    jpeg,
+    overlay::{Overlay, OverlayRecord},
+    phash,
+    placeholder,
+    profile::{ChromaSubsampling, IcfColorSpace, IcfProfile, QuantTableKind, SubsamplingMode},
     quantization::Quantization,
+    sign_context::{self, SignDecoder},
+    source_analysis,
+    transparency::fill_transparent,
 };
+use crate::codecs::cli_common::RuntimeConfig;
+use crate::codecs::explain::ExplainTrace;
+use crate::codecs::formats;
+use crate::codecs::layout::{self, LayoutRegion};
+use crate::codecs::text::CodecWarning;
+use crate::codecs::plane::{Frame, Plane};
+use crate::codecs::peek::{self, ChecksumKind, PeekResult};
+use crate::codecs::residual::{self, ZERO_PREDICTION};
+use crate::codecs::video::VcfCodec;
+use crate::codecs::trace::{diagnostic, phase, trace_event};
 
 /// ICF (Image Codec Format) header structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,14 +43,446 @@ pub struct IcfHeader {
     pub width: u32,
     pub height: u32,
     pub channels: u8,
-    pub color_space: String,
+    pub color_space: IcfColorSpace,
+    #[serde(default)]
+    pub chroma_subsampling: ChromaSubsampling,
     pub quality: u8,
     pub compression_method: String,
     pub block_size: u8,
     pub quantization_tables: Vec<Vec<Vec<f64>>>, // [channel][row][col]
+    /// How `quantization_tables` were derived (informational)
+    #[serde(default)]
+    pub quantization: QuantTableKind,
+    /// Tile edge in luma pixels; DC prediction restarts in every tile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tile_size: Option<u32>,
+    /// Name of the profile the encoder started from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
     pub original_size: u64,
     pub compressed_size: u64,
     pub checksum: String,
+    /// Optional descriptive metadata; absent in stripped files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<IcfMetadata>,
+    /// One entry per channel when the blocks are stored as one array per
+    /// channel, in channel order; empty for a single interleaved array
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channel_sections: Vec<IcfChannelSection>,
+    /// AC coefficient signs are context coded ahead of each channel's
+    /// blocks, which then carry magnitudes only
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sign_contexts: bool,
+    /// Sections stored between the header and the blocks, in order; files
+    /// without any keep their metadata and quantization tables in the header
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<IcfSection>,
+}
+
+/// Where one channel's blocks sit in a planar block payload
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcfChannelSection {
+    pub blocks: u64,
+    /// Byte length of the channel's block array
+    pub length: u64,
+    /// Byte length of the sign stream before the block array, when the
+    /// header has `sign_contexts`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub signs: u64,
+}
+
+/// Part of the header moved out of the header JSON
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IcfSectionKind {
+    Metadata,
+    QuantizationTables,
+    /// A kind added by a newer encoder; its bytes are skipped
+    #[serde(other)]
+    Unknown,
+}
+
+impl IcfSectionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IcfSectionKind::Metadata => "metadata",
+            IcfSectionKind::QuantizationTables => "quantization_tables",
+            IcfSectionKind::Unknown => "unknown",
+        }
+    }
+}
+
+/// Where one header section sits after the header
+///
+/// The stored bytes are a flag byte followed by the section's JSON, either
+/// as it is or deflated against a small fixed dictionary.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcfSection {
+    pub kind: IcfSectionKind,
+    /// Stored bytes, flag byte included
+    pub length: u64,
+    /// Bytes of JSON the section expands to
+    pub expanded_length: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Optional descriptive metadata stored in the ICF header
+///
+/// Nothing here affects decoding. Tags use a `BTreeMap` so they always
+/// serialize in the same order.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct IcfMetadata {
+    /// File name of the source image (never the full path)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_name: Option<String>,
+    /// Encode time in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Overlay baked into the pixels before encoding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlay: Option<OverlayRecord>,
+}
+
+impl IcfMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.source_name.is_none() && self.created_at.is_none() && self.tags.is_empty() && self.overlay.is_none()
+    }
+}
+
+/// Options for `IcfCodec::encode_with_options`
+#[derive(Debug, Clone)]
+pub struct IcfEncodeOptions {
+    pub quality: u8,
+    pub chroma_subsampling: SubsamplingMode,
+    pub color_space: IcfColorSpace,
+    pub quantization: QuantTableKind,
+    /// Tile edge in luma pixels (a multiple of 16), or `None` for no tiling
+    pub tile_size: Option<u32>,
+    /// Profile name recorded in the header
+    pub profile: Option<String>,
+    /// Produce byte-identical output for identical pixels and options: blocks
+    /// are written in canonical order and no source name or timestamp is
+    /// recorded
+    pub reproducible: bool,
+    /// Omit the metadata section entirely
+    pub strip_metadata: bool,
+    /// Context code AC signs instead of storing them with each coefficient;
+    /// decoders that predate this can't read the result
+    pub sign_contexts: bool,
+    /// Move the metadata and quantization tables into deflated sections;
+    /// decoders that predate this can't read the result
+    pub compress_sections: bool,
+    pub metadata: IcfMetadata,
+    /// Composited onto the source before encoding, and recorded in the
+    /// metadata unless it is stripped
+    pub overlay: Option<Overlay>,
+    /// Recolor pixels the source's alpha hides so they cost next to
+    /// nothing; see `fill_transparent`
+    pub fill_transparent: bool,
+}
+
+impl Default for IcfEncodeOptions {
+    fn default() -> Self {
+        Self {
+            quality: 85,
+            chroma_subsampling: ChromaSubsampling::S444.into(),
+            color_space: IcfColorSpace::YCoCg,
+            quantization: QuantTableKind::Standard,
+            tile_size: None,
+            profile: None,
+            reproducible: true,
+            strip_metadata: false,
+            sign_contexts: false,
+            compress_sections: false,
+            metadata: IcfMetadata::default(),
+            overlay: None,
+            fill_transparent: false,
+        }
+    }
+}
+
+impl IcfEncodeOptions {
+    pub fn with_quality(quality: u8) -> Self {
+        Self { quality, ..Default::default() }
+    }
+
+    /// Start from a profile's parameters; individual fields can still be overridden
+    pub fn profile(profile: &IcfProfile) -> Self {
+        Self {
+            quality: profile.quality,
+            chroma_subsampling: profile.chroma_subsampling.into(),
+            color_space: profile.color_space,
+            quantization: profile.quantization,
+            tile_size: profile.tile_size,
+            profile: Some(profile.name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Metadata to record under these options, if any
+    fn effective_metadata(&self) -> Option<IcfMetadata> {
+        if self.strip_metadata {
+            return None;
+        }
+
+        let mut metadata = self.metadata.clone();
+        if let Some(overlay) = &self.overlay {
+            metadata.overlay = Some(overlay.record());
+        }
+        if self.reproducible {
+            metadata.source_name = None;
+            metadata.created_at = None;
+        } else if metadata.created_at.is_none() {
+            metadata.created_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|elapsed| elapsed.as_secs());
+        }
+
+        (!metadata.is_empty()).then_some(metadata)
+    }
+}
+
+impl IcfHeader {
+    /// Whether the file claims a lossless encoding, in which case the decoded
+    /// pixels must reproduce the stored checksum exactly
+    pub fn is_lossless(&self) -> bool {
+        self.compression_method == IcfCodec::LOSSLESS_METHOD
+    }
+
+    /// Whether the metadata is in a section, in which case `metadata` stays
+    /// `None` until read with `IcfCodec::metadata`
+    pub fn metadata_compressed(&self) -> bool {
+        self.section(IcfSectionKind::Metadata).is_some()
+    }
+
+    pub fn section(&self, kind: IcfSectionKind) -> Option<&IcfSection> {
+        self.sections.iter().find(|section| section.kind == kind)
+    }
+
+    /// Total stored bytes of the sections
+    pub fn sections_size(&self) -> Result<u64> {
+        self.sections.iter()
+            .try_fold(0u64, |size, section| size.checked_add(section.length))
+            .context("ICF section sizes overflow")
+    }
+}
+
+/// ICF failures callers may want to tell apart from other errors
+#[derive(Debug, thiserror::Error)]
+pub enum IcfError {
+    /// The file is shorter than its header says
+    #[error("ICF file is truncated: needs {needed} bytes, has {available}")]
+    Truncated { needed: u64, available: u64 },
+    /// A strict decode met an anomaly the other levels decode past
+    #[error("{}: {}", .0.code(), .0)]
+    Anomaly(DecodeAnomaly),
+}
+
+/// Something a decode can recover from but a conforming file never has
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeAnomaly {
+    /// The recorded checksum isn't a SHA-256 digest, so nothing was verified
+    #[error("ICF checksum {0:?} is not a SHA-256 digest")]
+    UnverifiableChecksum(String),
+    /// A lossless file decoded to other pixels than its checksum; only
+    /// `Tolerant` decodes past this
+    #[error("ICF checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    /// Blocks of a channel never arrived and were left mid-gray, or
+    /// concealed when tolerant
+    #[error("ICF channel {channel} is missing {count} blocks")]
+    MissingBlocks { channel: usize, count: usize },
+    /// A header section of a kind this decoder doesn't know was skipped
+    #[error("ICF section {index} has an unknown kind")]
+    UnknownSection { index: usize },
+    /// AC coefficients past ±4096, which the fixed-point pipeline clamps, or
+    /// runs past the 64th coefficient, which are dropped
+    #[error("{blocks} ICF blocks have out-of-range coefficients, first ({x}, {y}) of channel {channel}")]
+    CoefficientsOutOfRange { channel: u8, x: u16, y: u16, blocks: usize },
+    /// The payload ended early or failed to parse; only `Tolerant` decodes past this
+    #[error("ICF payload is incomplete: {0}")]
+    IncompletePayload(String),
+}
+
+impl DecodeAnomaly {
+    /// Stable name of the anomaly class
+    pub fn code(&self) -> &'static str {
+        match self {
+            DecodeAnomaly::UnverifiableChecksum(_) => "unverifiable_checksum",
+            DecodeAnomaly::ChecksumMismatch { .. } => "checksum_mismatch",
+            DecodeAnomaly::MissingBlocks { .. } => "missing_blocks",
+            DecodeAnomaly::UnknownSection { .. } => "unknown_section",
+            DecodeAnomaly::CoefficientsOutOfRange { .. } => "coefficients_out_of_range",
+            DecodeAnomaly::IncompletePayload(_) => "incomplete_payload",
+        }
+    }
+}
+
+/// How `decode_checked` treats anomalies it can recover from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeStrictness {
+    /// Fail with `IcfError::Anomaly` on the first one; for conformance tests
+    Strict,
+    /// Decode as always, listing them in `DecodeOutcome::warnings`
+    #[default]
+    Normal,
+    /// Like `Normal`, also decoding a truncated payload as far as it goes,
+    /// concealing missing blocks as `decode_partial` does (f64 pipeline
+    /// only) and accepting a lossless file whose checksum doesn't match
+    Tolerant,
+}
+
+impl DecodeStrictness {
+    /// Fail with `anomaly` when strict, otherwise add it to `warnings`
+    fn report(self, anomaly: DecodeAnomaly, warnings: &mut Vec<DecodeAnomaly>) -> Result<()> {
+        if self == DecodeStrictness::Strict {
+            return Err(IcfError::Anomaly(anomaly).into());
+        }
+        diagnostic!(debug, "{}", anomaly);
+        warnings.push(anomaly);
+        Ok(())
+    }
+}
+
+/// Options controlling how `decode_checked` treats integrity anomalies
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Fail on a checksum mismatch even when the file is lossy
+    pub strict_checksum: bool,
+    /// Decode with `icf_core`'s integer pipeline instead of f64; pixels may
+    /// differ by one, so checksums of lossy files usually won't match
+    pub fixed_point: bool,
+    pub strictness: DecodeStrictness,
+}
+
+/// Set of channels for `IcfCodec::decode_channels`, by coded channel index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask(u8);
+
+impl ChannelMask {
+    pub const LUMA: ChannelMask = ChannelMask(0b001);
+    pub const CHROMA: ChannelMask = ChannelMask(0b110);
+    pub const ALL: ChannelMask = ChannelMask(0b111);
+
+    /// Mask holding a single channel; indices past 2 select nothing
+    pub fn channel(channel: usize) -> Self {
+        ChannelMask(1u8.checked_shl(channel as u32).unwrap_or(0) & Self::ALL.0)
+    }
+
+    pub fn contains(self, channel: usize) -> bool {
+        channel < 3 && self.0 & (1 << channel) != 0
+    }
+}
+
+impl std::ops::BitOr for ChannelMask {
+    type Output = ChannelMask;
+
+    fn bitor(self, rhs: ChannelMask) -> ChannelMask {
+        ChannelMask(self.0 | rhs.0)
+    }
+}
+
+/// Which three planes a `PlanarF32` holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanarLayout {
+    /// Red, green and blue
+    Rgb,
+    /// Luma and two chroma planes in this color space, e.g. Y/Co/Cg
+    LumaChroma(IcfColorSpace),
+}
+
+/// Three full-resolution planes of unit-range f32 samples, row by row with
+/// no padding, ready to upload as textures
+///
+/// All samples are in 0..=1; chroma planes are offset by 0.5. See
+/// `IcfCodec::encode_planar_f32` for how other values are clamped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanarF32 {
+    pub planes: [Vec<f32>; 3],
+    pub width: u32,
+    pub height: u32,
+    pub layout: PlanarLayout,
+}
+
+/// `value` clamped to 0..=1, with NaN as 0
+fn unit_sample(value: f64) -> f32 {
+    if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) as f32 }
+}
+
+/// Decoded image together with the outcome of checksum verification
+#[derive(Debug, Clone)]
+pub struct DecodeOutcome {
+    pub image: DynamicImage,
+    pub checksum_matched: bool,
+    /// Checksum recorded by the encoder (of the source pixels)
+    pub expected: String,
+    /// Checksum of the decoded pixels
+    pub actual: String,
+    /// Anomalies decoded past, in the order met; always empty when strict
+    pub warnings: Vec<DecodeAnomaly>,
+}
+
+/// Image salvaged from a truncated or damaged ICF file
+#[derive(Debug, Clone)]
+pub struct PartialDecode {
+    pub image: DynamicImage,
+    /// Share of each pixel's channels that were decoded, scaled to 0-255:
+    /// 255 where all three were, 0 where all were filled in
+    pub coverage_mask: GrayImage,
+    /// Percentage of blocks, over all channels, that were decoded
+    pub coverage: f64,
+    /// Why block parsing stopped early; `None` for an intact file
+    pub stop_reason: Option<String>,
+}
+
+impl PartialDecode {
+    pub fn is_complete(&self) -> bool {
+        self.stop_reason.is_none() && self.coverage >= 100.0
+    }
+}
+
+/// Header fields `IcfCodec::peek` reads from the start of a file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IcfPeek {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub quality: u8,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub checksum: String,
+}
+
+impl IcfPeek {
+    pub fn checksum_kind(&self) -> ChecksumKind {
+        ChecksumKind::of(&self.checksum)
+    }
+}
+
+/// Byte-level map of an ICF file, for tooling and debugging
+#[derive(Serialize, Debug, Clone)]
+pub struct IcfLayout {
+    pub file_size: u64,
+    pub header: IcfHeader,
+    /// Consecutive regions covering the whole file: `magic`, `header_length`,
+    /// `header`, one per section (`metadata`, `quantization_tables`),
+    /// `blocks` and, if present, `trailing`
+    pub regions: Vec<LayoutRegion>,
+    pub blocks_size: u64,
+    /// Bytes after the block data that the decoder doesn't account for
+    pub trailing_size: u64,
+}
+
+impl IcfLayout {
+    pub fn region(&self, name: &str) -> Option<&LayoutRegion> {
+        self.regions.iter().find(|region| region.name == name)
+    }
 }
 
 /// Compressed block data
@@ -39,56 +497,268 @@ pub struct CompressedBlock {
 
 /// High-performance Image Codec implementation
 pub struct IcfCodec {
-    dct: Dct8x8,
+    dct: &'static Dct8x8,
+    runtime: RuntimeConfig,
 }
 
 impl IcfCodec {
-    const MAGIC: &'static str = "ICF2"; // Version 2 with proper DCT
-    const VERSION: u16 = 2;
+    const MAGIC: &'static str = formats::ICF.magic;
+    const VERSION: u16 = formats::ICF.version;
     const BLOCK_SIZE: usize = 8;
+    /// Compression method recorded by lossless encodes
+    pub const LOSSLESS_METHOD: &'static str = "LOSSLESS";
+    /// Mean square chroma detail, in 8-bit steps, that makes a block chroma-active
+    const CHROMA_ACTIVE_ENERGY: f64 = 64.0;
+    /// Share of chroma-active blocks above which `SubsamplingMode::Auto` keeps 4:4:4
+    const CHROMA_ACTIVE_FRACTION: f64 = 0.02;
+    /// Bytes an encode or decode holds per pixel: three f64 planes and the
+    /// 8-bit RGB image
+    const WORKING_BYTES_PER_PIXEL: u64 = 3 * 8 + 3;
 
     pub fn new() -> Self {
         Self {
-            dct: Dct8x8::new(),
+            dct: Dct8x8::shared(),
+            runtime: RuntimeConfig::default(),
         }
     }
 
+    /// Code channels on `runtime`'s pool and refuse images whose working
+    /// set is over its memory limit
+    pub fn with_runtime(mut self, runtime: RuntimeConfig) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    pub fn runtime(&self) -> &RuntimeConfig {
+        &self.runtime
+    }
+
+    /// Fail before allocating anything if a `width` x `height` image won't
+    /// fit the memory limit
+    fn check_memory(&self, what: &str, width: u32, height: u32) -> Result<()> {
+        let needed = (width as u64 * height as u64).saturating_mul(Self::WORKING_BYTES_PER_PIXEL);
+        Ok(self.runtime.check_memory(&format!("{} of {}x{}", what, width, height), needed)?)
+    }
+
     /// Encode image to ICF format with advanced compression
     pub fn encode(&self, image_path: &str, quality: u8) -> Result<Vec<u8>> {
+        self.encode_file_with_options(image_path, &IcfEncodeOptions::with_quality(quality))
+    }
+
+    /// Encode an image file, recording its file name unless the options are reproducible
+    pub fn encode_file_with_options(&self, image_path: &str, options: &IcfEncodeOptions) -> Result<Vec<u8>> {
         // Load image
-        let img = image::open(image_path)
-            .context("Failed to load image")?;
-        
-        let rgb_img = img.to_rgb8();
-        let (width, height) = rgb_img.dimensions();
-        
-        // Convert to YCoCg color space for better compression
-        let ycocg_data = self.rgb_to_ycocg_blocks(&rgb_img);
-        
-        // Create quantization tables for each channel
-        let quantization_tables = vec![
-            Quantization::create_quantization_table(quality, true),  // Y channel
-            Quantization::create_quantization_table(quality, false), // Co channel
-            Quantization::create_quantization_table(quality, false), // Cg channel
-        ];
+        let img = {
+            phase!("icf.read", path = image_path);
+            input::decode_input(image_path)?
+        };
+        self.encode_with_options(&img, &Self::file_options(image_path, options))
+    }
 
-        // Compress each channel in parallel
-        let compressed_blocks: Vec<CompressedBlock> = (0..3)
-            .into_par_iter()
-            .flat_map(|channel| {
-                self.compress_channel_blocks(
-                    &ycocg_data[channel],
-                    width,
-                    height,
-                    channel as u8,
-                    &quantization_tables[channel],
-                )
-            })
-            .collect();
+    /// Like `encode_file_with_options`, also warning when the file was
+    /// already lossily compressed
+    pub fn encode_file_with_warnings(&self, image_path: &str, options: &IcfEncodeOptions) -> Result<(Vec<u8>, Vec<CodecWarning>)> {
+        self.encode_file_with_trace(image_path, options).map(|(encoded, warnings, _)| (encoded, warnings))
+    }
+
+    /// Like `encode_file_with_warnings`, also returning the decisions the
+    /// encode made
+    pub fn encode_file_with_trace(&self, image_path: &str, options: &IcfEncodeOptions) -> Result<(Vec<u8>, Vec<CodecWarning>, ExplainTrace)> {
+        let (data, img) = {
+            phase!("icf.read", path = image_path);
+            let data = std::fs::read(image_path).with_context(|| format!("Failed to read {}", image_path))?;
+            let img = input::decode_input(&data[..]).with_context(|| format!("Failed to load {}", image_path))?;
+            (data, img)
+        };
+        let warnings = source_analysis::lossy_source_warning(&data, &img).into_iter().collect();
+        let (encoded, trace) = self.encode_with_trace(&img, &Self::file_options(image_path, options))?;
+        Ok((encoded, warnings, trace))
+    }
+
+    fn file_options(image_path: &str, options: &IcfEncodeOptions) -> IcfEncodeOptions {
+        let mut options = options.clone();
+        if !options.reproducible && options.metadata.source_name.is_none() {
+            options.metadata.source_name = std::path::Path::new(image_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+        }
+        options
+    }
+
+    /// Encode an already-decoded image to ICF format
+    pub fn encode_image(&self, img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+        self.encode_with_options(img, &IcfEncodeOptions::with_quality(quality))
+    }
+
+    /// Encode an already-decoded image with explicit options
+    pub fn encode_with_options(&self, img: &DynamicImage, options: &IcfEncodeOptions) -> Result<Vec<u8>> {
+        self.encode_with_trace(img, options).map(|(encoded, _)| encoded)
+    }
+
+    /// Like `encode_with_options`, also returning the profile, overlay,
+    /// subsampling and quantization decisions the encode made
+    pub fn encode_with_trace(&self, img: &DynamicImage, options: &IcfEncodeOptions) -> Result<(Vec<u8>, ExplainTrace)> {
+        Self::check_encode_options(options)?;
+        self.check_memory("ICF encode", img.width(), img.height())?;
+        phase!("icf.encode", width = img.width(), height = img.height(), quality = options.quality);
+
+        let mut trace = ExplainTrace::default();
+        let mut rgb_img = if options.fill_transparent && img.color().has_alpha() {
+            fill_transparent(&img.to_rgba8())
+        } else {
+            img.to_rgb8()
+        };
+        if let Some(overlay) = &options.overlay {
+            phase!("icf.overlay", name = %overlay.name);
+            trace.chosen("icf.overlay", &overlay.name, format!("requested, {} at opacity {}", overlay.position, overlay.opacity));
+            overlay.apply(&mut rgb_img)?;
+        }
+        let frame = {
+            phase!("icf.color_convert", color_space = ?options.color_space);
+            self.rgb_to_frame(&rgb_img, options.color_space)
+        };
+        let container = self.encode_frame(frame, rgb_img.width(), rgb_img.height(), rgb_img.as_raw(), options, &mut trace)?;
+        trace_event!(bytes_in = rgb_img.as_raw().len(), bytes_out = container.len(), "encoded ICF");
+        Ok((container, trace))
+    }
+
+    /// Encode planes of unit-range samples laid out as `input.layout`
+    ///
+    /// Every sample is clamped to 0..=1 before use, and NaN is taken as 0,
+    /// so the output never depends on what a non-finite input propagates
+    /// to. Chroma planes are offset by 0.5, so 0.5 is neutral. Input in
+    /// another color space than `options.color_space` is converted through
+    /// RGB. The checksum covers the clamped RGB rounded to 8 bits, so
+    /// decoders compare against what the u8 path would have produced.
+    pub fn encode_planar_f32(&self, input: &PlanarF32, options: &IcfEncodeOptions) -> Result<Vec<u8>> {
+        Self::check_encode_options(options)?;
+        if options.overlay.is_some() {
+            anyhow::bail!("Overlays can only be baked into 8-bit sources");
+        }
+        phase!("icf.encode_planar_f32", width = input.width, height = input.height, quality = options.quality);
+        let (width, height) = (input.width as usize, input.height as usize);
+        let pixels = width.checked_mul(height).context("Planar input is too large")?;
+        if let Some(plane) = input.planes.iter().find(|plane| plane.len() != pixels) {
+            anyhow::bail!("Planar input of {}x{} needs {} samples per plane, got {}", width, height, pixels, plane.len());
+        }
+
+        let color_space = options.color_space;
+        let mut rgb = Vec::with_capacity(pixels * 3);
+        let mut planes = [Vec::with_capacity(pixels), Vec::with_capacity(pixels), Vec::with_capacity(pixels)];
+        for i in 0..pixels {
+            let [a, b, c] = [0, 1, 2].map(|channel| unit_sample(input.planes[channel][i] as f64) as f64);
+            let (pixel, (luma, c1, c2)) = match input.layout {
+                PlanarLayout::Rgb => ((a, b, c), color_space.from_rgb(a, b, c)),
+                PlanarLayout::LumaChroma(given) => {
+                    let pixel = given.to_rgb(a, b - 0.5, c - 0.5);
+                    let coded = if given == color_space { (a, b - 0.5, c - 0.5) } else { color_space.from_rgb(pixel.0, pixel.1, pixel.2) };
+                    (pixel, coded)
+                }
+            };
+            rgb.extend([pixel.0, pixel.1, pixel.2].map(|value| (unit_sample(value) * 255.0).round() as u8));
+            planes[0].push(luma * 255.0 - 128.0);
+            planes[1].push(c1 * 255.0);
+            planes[2].push(c2 * 255.0);
+        }
+
+        let frame = Frame {
+            planes: planes.into_iter().map(|data| Plane::from_vec(width, height, data)).collect(),
+            color_space,
+        };
+        self.encode_frame(frame, input.width, input.height, &rgb, options, &mut ExplainTrace::default())
+    }
+
+    fn check_encode_options(options: &IcfEncodeOptions) -> Result<()> {
+        let quality = options.quality;
+        if !(1..=100).contains(&quality) {
+            anyhow::bail!("ICF quality must be between 1 and 100, got {}", quality);
+        }
+        Self::check_tile_size(options.tile_size)?;
+        if options.quantization == QuantTableKind::Jpeg {
+            anyhow::bail!("JPEG quantization tables come from transcode_from_jpeg, not from encoding pixels");
+        }
+        if let Some(overlay) = &options.overlay {
+            if !(0.0..=1.0).contains(&overlay.opacity) {
+                anyhow::bail!("Overlay opacity must be between 0 and 1, got {}", overlay.opacity);
+            }
+        }
+        Ok(())
+    }
+
+    /// Code a full-resolution frame; `rgb` is the source the checksum and
+    /// original size describe
+    fn encode_frame(
+        &self,
+        mut frame: Frame<f64>,
+        width: u32,
+        height: u32,
+        rgb: &[u8],
+        options: &IcfEncodeOptions,
+        trace: &mut ExplainTrace,
+    ) -> Result<Vec<u8>> {
+        let quality = options.quality;
+        if let Some(profile) = &options.profile {
+            trace.chosen("icf.profile", profile, "requested");
+        }
+
+        // Halve chroma for 4:2:0
+        let (frame, subsampling) = {
+            phase!("icf.subsample", subsampling = %options.chroma_subsampling);
+            let subsampling = match options.chroma_subsampling {
+                SubsamplingMode::Fixed(subsampling) => {
+                    trace.chosen("icf.subsampling", subsampling, "requested");
+                    subsampling
+                }
+                SubsamplingMode::Auto => Self::choose_subsampling(&frame, trace),
+            };
+            if subsampling == ChromaSubsampling::S420 {
+                for plane in &mut frame.planes[1..] {
+                    *plane = Self::downsample_plane(plane);
+                }
+            }
+            (frame, subsampling)
+        };
+
+        // DCT, quantize and run-length code each channel
+        let (quantization_tables, mut compressed_blocks) = {
+            phase!("icf.transform", quantization = ?options.quantization);
+
+            trace.chosen("icf.quantization", options.quantization, format!("requested, at quality {}", quality));
+            // Create quantization tables for each channel
+            let quantization_tables: Vec<[[f64; 8]; 8]> = (0..3)
+                .map(|channel| self.quantization_table(options.quantization, quality, channel, &frame.planes[channel]))
+                .collect();
+
+            // Compress each channel in parallel
+            let compressed_blocks: Vec<CompressedBlock> = self.runtime.install(|| (0..3)
+                .into_par_iter()
+                .flat_map(|channel| {
+                    self.compress_channel_blocks(
+                        &frame.planes[channel],
+                        channel as u8,
+                        &quantization_tables[channel],
+                        Self::tile_blocks(options.tile_size, channel, subsampling),
+                    )
+                })
+                .collect());
+            trace_event!(blocks = compressed_blocks.len(), "coded blocks");
+            (quantization_tables, compressed_blocks)
+        };
+
+        // Canonical order (channel, then coding order) regardless of how the work was scheduled
+        if options.reproducible {
+            compressed_blocks.sort_by_key(|block| {
+                let tile_blocks = Self::tile_blocks(options.tile_size, block.channel as usize, subsampling);
+                (block.channel, Self::scan_key(block.x as usize, block.y as usize, tile_blocks))
+            });
+        } else {
+            // Channel sections need each channel's blocks together
+            compressed_blocks.sort_by_key(|block| block.channel);
+        }
 
         // Calculate checksum of original image data
         let mut hasher = Sha256::new();
-        hasher.update(rgb_img.as_raw());
+        hasher.update(rgb);
         let checksum = format!("{:x}", hasher.finalize());
 
         // Create header
@@ -98,414 +768,2834 @@ impl IcfCodec {
             width,
             height,
             channels: 3,
-            color_space: "YCoCg".to_string(),
+            color_space: options.color_space,
+            chroma_subsampling: subsampling,
             quality,
             compression_method: "DCT+RLE".to_string(),
             block_size: Self::BLOCK_SIZE as u8,
             quantization_tables: quantization_tables.into_iter()
                 .map(|table| table.iter().map(|row| row.to_vec()).collect())
                 .collect(),
-            original_size: rgb_img.as_raw().len() as u64,
+            quantization: options.quantization,
+            tile_size: options.tile_size,
+            profile: options.profile.clone(),
+            original_size: rgb.len() as u64,
             compressed_size: 0, // Will be updated
             checksum,
+            metadata: options.effective_metadata(),
+            channel_sections: Vec::new(),
+            sign_contexts: options.sign_contexts,
+            sections: Vec::new(),
         };
 
-        // Serialize compressed blocks
-        let compressed_data = self.serialize_blocks(&compressed_blocks)?;
-        
-        // Create final container
-        self.create_container(header, compressed_data)
+        self.write_blocks(header, compressed_blocks, options.compress_sections)
     }
 
-    /// Decode ICF format to image
-    pub fn decode(&self, icf_data: &[u8]) -> Result<DynamicImage> {
-        let (header, compressed_data) = self.parse_container(icf_data)?;
-        
-        // Validate header
-        if header.magic != Self::MAGIC {
-            anyhow::bail!("Invalid ICF magic number: expected {}, got {}", 
-                Self::MAGIC, header.magic);
-        }
-
-        if header.version != Self::VERSION {
-            anyhow::bail!("Unsupported ICF version: {}", header.version);
-        }
+    /// Transcode a baseline JPEG without going through pixels
+    ///
+    /// The JPEG's quantized DCT coefficients and quantization tables are
+    /// stored as they are, so nothing is requantized and no generation loss
+    /// is added. The result is YCbCr with the JPEG's 4:4:4 or 4:2:0 chroma;
+    /// grayscale JPEGs get empty chroma. Progressive, arithmetic coded and
+    /// otherwise subsampled JPEGs are rejected. The checksum covers the
+    /// pixels the result decodes to.
+    pub fn transcode_from_jpeg(&self, jpeg_bytes: &[u8]) -> Result<Vec<u8>> {
+        let jpeg = {
+            phase!("icf.jpeg_read", bytes = jpeg_bytes.len());
+            jpeg::read_coefficients(jpeg_bytes)?
+        };
+        phase!("icf.transcode", width = jpeg.width, height = jpeg.height);
 
-        // Deserialize compressed blocks
-        let compressed_blocks = self.deserialize_blocks(&compressed_data)?;
+        let sampling: Vec<(u8, u8)> = jpeg.components.iter().map(|component| (component.h, component.v)).collect();
+        let chroma_subsampling = match sampling.as_slice() {
+            [_] => ChromaSubsampling::S444,
+            [_, _, _] if jpeg.adobe_transform == Some(0) => anyhow::bail!("JPEG stores RGB components, not YCbCr"),
+            [luma, cb, cr] if luma == cb && cb == cr => ChromaSubsampling::S444,
+            [(2, 2), (1, 1), (1, 1)] => ChromaSubsampling::S420,
+            [_, _, _] => anyhow::bail!("JPEG sampling factors {:?} have no ICF equivalent; only 4:4:4 and 4:2:0 can be transcoded", sampling),
+            _ => anyhow::bail!("JPEG has {} components; only grayscale and YCbCr JPEGs can be transcoded", sampling.len()),
+        };
 
-        // Reconstruct quantization tables
-        let quantization_tables: Vec<[[f64; 8]; 8]> = header.quantization_tables
-            .into_iter()
-            .map(|table| {
-                let mut array = [[0.0; 8]; 8];
-                for (i, row) in table.into_iter().enumerate() {
-                    for (j, val) in row.into_iter().enumerate() {
-                        if i < 8 && j < 8 {
-                            array[i][j] = val;
-                        }
-                    }
+        // Tables go from the JPEG's zigzag order to rows and columns
+        let mut quantization_tables: Vec<Vec<Vec<f64>>> = jpeg.components.iter()
+            .map(|component| {
+                let mut table = vec![vec![0.0; 8]; 8];
+                for (&(row, column), &step) in Quantization::ZIGZAG_ORDER.iter().zip(&component.quantization_table) {
+                    table[row][column] = step as f64;
                 }
-                array
+                table
             })
             .collect();
-
-        // Decompress blocks back to YCoCg data
-        let ycocg_blocks = self.decompress_blocks(
-            &compressed_blocks,
-            header.width,
-            header.height,
-            &quantization_tables,
-        )?;
-
-        // Convert YCoCg back to RGB
-        let rgb_img = self.ycocg_blocks_to_rgb(&ycocg_blocks, header.width, header.height);
-
-        // Verify checksum
-        let mut hasher = Sha256::new();
-        hasher.update(rgb_img.as_raw());
-        let actual_checksum = format!("{:x}", hasher.finalize());
-        
-        if actual_checksum != header.checksum {
-            println!("Warning: ICF checksum mismatch (lossy compression expected)");
+        while quantization_tables.len() < 3 {
+            quantization_tables.push(quantization_tables[0].clone());
         }
+        let luma_table: [[f64; 8]; 8] = std::array::from_fn(|row| std::array::from_fn(|column| quantization_tables[0][row][column]));
 
-        Ok(DynamicImage::ImageRgb8(rgb_img))
-    }
-
-    /// Convert RGB image to YCoCg blocks
-    fn rgb_to_ycocg_blocks(&self, rgb_img: &RgbImage) -> Vec<Vec<Vec<[[f64; 8]; 8]>>> {
-        let (width, height) = rgb_img.dimensions();
-        let blocks_x = ((width + 7) / 8) as usize;
-        let blocks_y = ((height + 7) / 8) as usize;
-
-        let mut channels = vec![vec![vec![[[0.0; 8]; 8]; blocks_x]; blocks_y]; 3];
-
-        for block_y in 0..blocks_y {
-            for block_x in 0..blocks_x {
-                // Extract 8x8 block from each channel
-                let mut rgb_block = [[[0.0; 3]; 8]; 8];
-                
-                for y in 0..8 {
-                    for x in 0..8 {
-                        let img_x = (block_x * 8 + x).min(width as usize - 1);
-                        let img_y = (block_y * 8 + y).min(height as usize - 1);
-                        
-                        let pixel = rgb_img.get_pixel(img_x as u32, img_y as u32);
-                        rgb_block[y][x][0] = pixel[0] as f64;
-                        rgb_block[y][x][1] = pixel[1] as f64;
-                        rgb_block[y][x][2] = pixel[2] as f64;
-                    }
-                }
+        let header = IcfHeader {
+            magic: Self::MAGIC.to_string(),
+            version: Self::VERSION,
+            width: jpeg.width,
+            height: jpeg.height,
+            channels: 3,
+            color_space: IcfColorSpace::YCbCr,
+            chroma_subsampling,
+            quality: Self::estimate_quality(&luma_table),
+            compression_method: "DCT+RLE".to_string(),
+            block_size: Self::BLOCK_SIZE as u8,
+            quantization_tables,
+            quantization: QuantTableKind::Jpeg,
+            tile_size: None,
+            profile: None,
+            original_size: jpeg.width as u64 * jpeg.height as u64 * 3,
+            compressed_size: 0,
+            checksum: String::new(),
+            metadata: None,
+            channel_sections: Vec::new(),
+            sign_contexts: false,
+            sections: Vec::new(),
+        };
 
-                // Convert RGB to YCoCg for this block
-                for y in 0..8 {
-                    for x in 0..8 {
-                        let (r, g, b) = (
-                            rgb_block[y][x][0] / 255.0,
-                            rgb_block[y][x][1] / 255.0,
-                            rgb_block[y][x][2] / 255.0,
-                        );
-                        
-                        let (yval, co, cg) = ColorSpace::rgb_to_ycocg(r, g, b);
-                        
-                        // Center around 0 for DCT
-                        channels[0][block_y][block_x][y][x] = (yval * 255.0) - 128.0;
-                        channels[1][block_y][block_x][y][x] = co * 255.0;
-                        channels[2][block_y][block_x][y][x] = cg * 255.0;
-                    }
+        // The JPEG's blocks past the plane edges only pad out its MCUs
+        let mut compressed_blocks = Vec::new();
+        for channel in 0..3 {
+            let (blocks_x, blocks_y) = Self::block_grid(&header, channel);
+            let component = jpeg.components.get(channel);
+            let mut prev_dc = 0i16;
+            for block_y in 0..blocks_y {
+                for block_x in 0..blocks_x {
+                    let (dc, ac_coefficients) = match component {
+                        Some(component) => {
+                            let zigzag = &component.blocks[block_y * component.blocks_x + block_x];
+                            (zigzag[0], Quantization::run_length_encode(&zigzag[1..]))
+                        }
+                        None => (0, Vec::new()),
+                    };
+                    compressed_blocks.push(CompressedBlock {
+                        x: block_x as u16,
+                        y: block_y as u16,
+                        channel: channel as u8,
+                        dc_coefficient: dc.wrapping_sub(prev_dc),
+                        ac_coefficients,
+                    });
+                    prev_dc = dc;
                 }
             }
         }
+        trace_event!(blocks = compressed_blocks.len(), subsampling = ?chroma_subsampling, "transcoded JPEG blocks");
 
-        channels
+        self.write_transcoded(header, compressed_blocks)
     }
 
-    /// Compress blocks for a single channel
-    fn compress_channel_blocks(
-        &self,
-        channel_blocks: &[Vec<[[f64; 8]; 8]>],
-        width: u32,
-        height: u32,
-        channel: u8,
-        quantization_table: &[[f64; 8]; 8],
-    ) -> Vec<CompressedBlock> {
-        let blocks_x = ((width + 7) / 8) as usize;
-        let blocks_y = ((height + 7) / 8) as usize;
-        let mut compressed_blocks = Vec::new();
-        let mut prev_dc = 0i16; // For DC coefficient differential encoding
-
-        for block_y in 0..blocks_y {
-            for block_x in 0..blocks_x {
-                let block = &channel_blocks[block_y][block_x];
-                
-                // Apply DCT transform
-                let dct_block = self.dct.forward_8x8(block);
-                
-                // Quantize coefficients
-                let quantized_block = Quantization::quantize_block(&dct_block, quantization_table);
-                
-                // Extract DC coefficient (differential encoding)
-                let dc_coefficient = quantized_block[0][0] - prev_dc;
-                prev_dc = quantized_block[0][0];
-                
-                // Convert to zigzag order and skip DC coefficient
-                let mut zigzag = Quantization::block_to_zigzag(&quantized_block);
-                zigzag.remove(0); // Remove DC coefficient (already stored separately)
-                
-                // Run-length encode AC coefficients
-                let ac_coefficients = Quantization::run_length_encode(&zigzag);
-                
-                compressed_blocks.push(CompressedBlock {
-                    x: block_x as u16,
-                    y: block_y as u16,
-                    channel,
-                    dc_coefficient,
-                    ac_coefficients,
-                });
-            }
-        }
-
-        compressed_blocks
+    /// Copy frames `indices` of a VCF out as standalone ICF images
+    ///
+    /// I-frames are remuxed coefficient for coefficient, so
+    /// `VcfCodec::from_icf_frames` can put them back as they were; see
+    /// `VcfCodec::extract_icf`.
+    pub fn from_vcf_frames(&self, vcf_data: &[u8], indices: &[usize]) -> Result<Vec<Vec<u8>>> {
+        let vcf = VcfCodec::new();
+        indices.iter().map(|&index| vcf.extract_icf(vcf_data, index)).collect()
     }
 
-    /// Decompress blocks back to spatial domain
-    fn decompress_blocks(
+    /// Wrap already quantized YCbCr coefficients in an ICF container
+    ///
+    /// Like `transcode_from_jpeg`, coefficients and tables are stored as
+    /// they are. `coefficients` holds luma then the two chroma planes, each
+    /// covering at least the block grid of a `width` x `height` image under
+    /// `chroma_subsampling`; blocks past that grid are padding and dropped.
+    /// Samples are taken as full-range, like JFIF.
+    pub fn transcode_from_coefficients(
         &self,
-        compressed_blocks: &[CompressedBlock],
         width: u32,
         height: u32,
-        quantization_tables: &[[[f64; 8]; 8]],
-    ) -> Result<Vec<Vec<Vec<[[f64; 8]; 8]>>>> {
-        let blocks_x = ((width + 7) / 8) as usize;
-        let blocks_y = ((height + 7) / 8) as usize;
-        
-        let mut channels = vec![vec![vec![[[0.0; 8]; 8]; blocks_x]; blocks_y]; 3];
-        let mut prev_dc = [0i16; 3]; // DC prediction for each channel
-
-        // Group blocks by channel for sequential DC decoding
-        let mut blocks_by_channel: Vec<Vec<&CompressedBlock>> = vec![Vec::new(); 3];
-        for block in compressed_blocks {
-            blocks_by_channel[block.channel as usize].push(block);
-        }
-
-        // Sort blocks by position for correct DC prediction
-        for channel_blocks in &mut blocks_by_channel {
-            channel_blocks.sort_by_key(|b| (b.y, b.x));
-        }
-
-        // Decompress each channel
-        for (channel_idx, channel_blocks) in blocks_by_channel.iter().enumerate() {
-            for block in channel_blocks {
-                // Reconstruct DC coefficient
-                let dc_coefficient = block.dc_coefficient + prev_dc[channel_idx];
-                prev_dc[channel_idx] = dc_coefficient;
-
-                // Reconstruct AC coefficients
-                let ac_coeffs = Quantization::run_length_decode(&block.ac_coefficients);
-                
-                // Combine DC and AC coefficients in zigzag order
-                let mut zigzag = vec![dc_coefficient];
-                zigzag.extend(ac_coeffs);
-                zigzag.truncate(64);
-
-                // Convert back to 8x8 block
-                let quantized_block = Quantization::zigzag_to_block(&zigzag);
-
-                // Dequantize
-                let dequantized_block = Quantization::dequantize_block(
-                    &quantized_block,
-                    &quantization_tables[channel_idx],
-                );
-
-                // Apply inverse DCT
-                let spatial_block = self.dct.inverse_8x8(&dequantized_block);
+        chroma_subsampling: ChromaSubsampling,
+        coefficients: &CoefficientPlanes,
+    ) -> Result<Vec<u8>> {
+        phase!("icf.transcode", width, height);
+        if coefficients.channels.len() != 3 {
+            anyhow::bail!("Expected 3 coefficient planes, got {}", coefficients.channels.len());
+        }
+        let luma_table = coefficients.channels[0].quantization_table;
+        let header = IcfHeader {
+            magic: Self::MAGIC.to_string(),
+            version: Self::VERSION,
+            width,
+            height,
+            channels: 3,
+            color_space: IcfColorSpace::YCbCr,
+            chroma_subsampling,
+            quality: Self::estimate_quality(&luma_table),
+            compression_method: "DCT+RLE".to_string(),
+            block_size: Self::BLOCK_SIZE as u8,
+            quantization_tables: coefficients.channels.iter()
+                .map(|plane| plane.quantization_table.iter().map(|row| row.to_vec()).collect())
+                .collect(),
+            quantization: QuantTableKind::Standard,
+            tile_size: None,
+            profile: None,
+            original_size: width as u64 * height as u64 * 3,
+            compressed_size: 0,
+            checksum: String::new(),
+            metadata: None,
+            channel_sections: Vec::new(),
+            sign_contexts: false,
+            sections: Vec::new(),
+        };
 
-                // Store in channel array
-                if (block.y as usize) < blocks_y && (block.x as usize) < blocks_x {
-                    channels[channel_idx][block.y as usize][block.x as usize] = spatial_block;
+        let mut compressed_blocks = Vec::new();
+        for (channel, plane) in coefficients.channels.iter().enumerate() {
+            let (blocks_x, blocks_y) = Self::block_grid(&header, channel);
+            if plane.blocks_x < blocks_x || plane.blocks_y < blocks_y {
+                anyhow::bail!("Coefficient plane {} has {}x{} blocks, a {}x{} image needs {}x{}",
+                    channel, plane.blocks_x, plane.blocks_y, width, height, blocks_x, blocks_y);
+            }
+            let mut prev_dc = 0i16;
+            for block_y in 0..blocks_y {
+                for block_x in 0..blocks_x {
+                    let block = plane.block(block_x, block_y).context("Coefficient block out of range")?;
+                    let zigzag = Quantization::block_to_zigzag(&block);
+                    compressed_blocks.push(CompressedBlock {
+                        x: block_x as u16,
+                        y: block_y as u16,
+                        channel: channel as u8,
+                        dc_coefficient: zigzag[0].wrapping_sub(prev_dc),
+                        ac_coefficients: Quantization::run_length_encode(&zigzag[1..]),
+                    });
+                    prev_dc = zigzag[0];
                 }
             }
         }
+        trace_event!(blocks = compressed_blocks.len(), subsampling = ?chroma_subsampling, "transcoded coefficient blocks");
 
-        Ok(channels)
+        self.write_transcoded(header, compressed_blocks)
     }
 
-    /// Convert YCoCg blocks back to RGB image
-    fn ycocg_blocks_to_rgb(
-        &self,
-        ycocg_blocks: &[Vec<Vec<[[f64; 8]; 8]>>],
-        width: u32,
-        height: u32,
-    ) -> RgbImage {
-        let mut rgb_img = ImageBuffer::new(width, height);
-        let blocks_x = ((width + 7) / 8) as usize;
-        let blocks_y = ((height + 7) / 8) as usize;
+    /// Checksum the pixels `compressed_blocks` decode to and write the container
+    fn write_transcoded(&self, mut header: IcfHeader, compressed_blocks: Vec<CompressedBlock>) -> Result<Vec<u8>> {
+        let row_bytes = header.width as usize * 3;
+        let mut pixels = vec![0u8; row_bytes * header.height as usize];
+        let (_, checksum, _) = self.decode_streamed_into(&header, &DecodeOptions::default(), |sink| {
+            compressed_blocks.iter().cloned().try_for_each(sink)
+        }, &mut pixels, row_bytes)?;
+        header.checksum = checksum;
+
+        self.write_blocks(header, compressed_blocks, false)
+    }
+
+    /// Quality whose standard luma table comes closest to `table`
+    pub(crate) fn estimate_quality(table: &[[f64; 8]; 8]) -> u8 {
+        let distance = |quality| {
+            let standard = Quantization::create_quantization_table(quality, true);
+            standard.iter().flatten().zip(table.iter().flatten()).map(|(s, t)| (s - t).abs()).sum::<f64>()
+        };
+        (1..=100u8)
+            .map(|quality| (distance(quality), quality))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or(100, |(_, quality)| quality)
+    }
+
+    /// Serialize blocks sorted by channel into a container for `header`,
+    /// one array per channel so readers can skip channels
+    fn write_blocks(&self, mut header: IcfHeader, mut compressed_blocks: Vec<CompressedBlock>, compress_sections: bool) -> Result<Vec<u8>> {
+        let compressed_data = {
+            phase!("icf.entropy", blocks = compressed_blocks.len());
+            let mut compressed_data = Vec::new();
+            let mut rest = compressed_blocks.as_mut_slice();
+            for channel in 0..3u8 {
+                let (blocks, remaining) = rest.split_at_mut(rest.partition_point(|block| block.channel == channel));
+                let signs = if header.sign_contexts {
+                    let (blocks_x, blocks_y) = Self::block_grid(&header, channel as usize);
+                    sign_context::encode_signs(blocks, blocks_x, blocks_y)?
+                } else {
+                    Vec::new()
+                };
+                let section = self.serialize_blocks(blocks)?;
+                header.channel_sections.push(IcfChannelSection {
+                    blocks: blocks.len() as u64,
+                    length: section.len() as u64,
+                    signs: signs.len() as u64,
+                });
+                compressed_data.extend_from_slice(&signs);
+                compressed_data.extend_from_slice(&section);
+                rest = remaining;
+            }
+            compressed_data
+        };
+
+        phase!("icf.container");
+        self.create_container(header, compressed_data, compress_sections)
+    }
+
+    /// Decode ICF format to image
+    ///
+    /// Lossy files never reproduce the source checksum, so the mismatch is
+    /// only logged; use `decode_checked` to inspect or enforce it.
+    pub fn decode(&self, icf_data: &[u8]) -> Result<DynamicImage> {
+        Ok(self.decode_checked(icf_data, &DecodeOptions::default())?.image)
+    }
+
+    /// Decode the file at `path` through a read-only memory map
+    ///
+    /// Blocks are parsed straight out of the mapping, so the file is never
+    /// read into memory. Its lengths are checked against the mapping first,
+    /// so a short file is `IcfError::Truncated` rather than a read past the
+    /// end. A file that shrinks while mapped can still fault; map files
+    /// that are replaced by rename, as `ObjectStore` does, not rewritten.
+    #[cfg(feature = "mmap")]
+    pub fn decode_mmap<P: AsRef<Path>>(&self, path: P) -> Result<DynamicImage> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: the mapping is read-only and only ever viewed as bytes.
+        // Truncation by another process is the caller's to rule out, above.
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .with_context(|| format!("Failed to map {}", path.display()))?;
+        self.check_complete(&map)?;
+        self.decode(&map)
+    }
+
+    /// Fail with `IcfError::Truncated` unless `icf_data` holds all of the
+    /// header and payload its prefix and header describe
+    pub fn check_complete(&self, icf_data: &[u8]) -> Result<()> {
+        let available = icf_data.len() as u64;
+        let truncated = |needed| IcfError::Truncated { needed, available };
+        let Some(prefix) = icf_data.get(..8) else {
+            return Err(truncated(8).into());
+        };
+        let header_end = 8 + u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as u64;
+        if available < header_end {
+            return Err(truncated(header_end).into());
+        }
+        let (header, _) = Self::split_header(icf_data)?;
+        let needed = header_end.checked_add(header.sections_size()?)
+            .and_then(|end| end.checked_add(header.compressed_size))
+            .context("ICF payload size overflows")?;
+        if available < needed {
+            return Err(truncated(needed).into());
+        }
+        self.parse_container(icf_data)?;
+        Ok(())
+    }
+
+    /// Decode into a caller-provided RGB8 buffer, returning the image size
+    ///
+    /// Row `y` is written to `out[y * out_stride..]`; bytes between rows are
+    /// left alone. The buffer is checked before any decoding happens.
+    pub fn decode_into(&self, icf_data: &[u8], out: &mut [u8], out_stride: usize) -> Result<(u32, u32)> {
+        let (header, compressed_data) = self.parse_container(icf_data)?;
+        self.check_memory("ICF decode", header.width, header.height)?;
+        self.decode_streamed_into(&header, &DecodeOptions::default(), |sink| {
+            Self::stream_payload(&header, compressed_data, ChannelMask::ALL, sink)
+        }, out, out_stride)?;
+        Ok((header.width, header.height))
+    }
+
+    /// Decode to planes of unit-range samples in `layout`, skipping the
+    /// rounding to 8 bits
+    ///
+    /// Samples are clamped to 0..=1; chroma planes are offset by 0.5, so 0.5
+    /// is neutral. A `LumaChroma` layout in another color space than the
+    /// file's is converted through RGB. No checksum is verified: the
+    /// checksum describes 8-bit pixels.
+    pub fn decode_planar_f32(&self, icf_data: &[u8], layout: PlanarLayout) -> Result<PlanarF32> {
+        let (header, compressed_data) = self.parse_container(icf_data)?;
+        Self::validate_header(&header)?;
+        self.check_memory("ICF decode", header.width, header.height)?;
+        phase!("icf.decode_planar_f32", width = header.width, height = header.height);
+        let planes = self.float_planes(&header, |sink| {
+            Self::stream_payload(&header, compressed_data, ChannelMask::ALL, sink)
+        }, |_, _| Ok(false))?;
+
+        phase!("icf.color_convert");
+        let color_space = header.color_space;
+        let pixels = header.width as usize * header.height as usize;
+        let mut output = [Vec::with_capacity(pixels), Vec::with_capacity(pixels), Vec::with_capacity(pixels)];
+        for i in 0..pixels {
+            let coded = ((planes[0].data[i] + 128.0) / 255.0, planes[1].data[i] / 255.0, planes[2].data[i] / 255.0);
+            let samples = match layout {
+                PlanarLayout::Rgb => color_space.to_rgb(coded.0, coded.1, coded.2),
+                PlanarLayout::LumaChroma(wanted) => {
+                    let (luma, c1, c2) = if wanted == color_space {
+                        coded
+                    } else {
+                        let (r, g, b) = color_space.to_rgb(coded.0, coded.1, coded.2);
+                        wanted.from_rgb(r, g, b)
+                    };
+                    (luma, c1 + 0.5, c2 + 0.5)
+                }
+            };
+            output[0].push(unit_sample(samples.0));
+            output[1].push(unit_sample(samples.1));
+            output[2].push(unit_sample(samples.2));
+        }
+
+        Ok(PlanarF32 { planes: output, width: header.width, height: header.height, layout })
+    }
+
+    /// Decode only the luma plane as a grayscale image
+    ///
+    /// Files with channel sections never parse the chroma blocks; older
+    /// files are scanned and their chroma blocks dropped unread.
+    pub fn decode_luma(&self, icf_data: &[u8]) -> Result<GrayImage> {
+        let [luma, _, _] = self.decode_channels(icf_data, ChannelMask::LUMA)?;
+        luma.context("ICF luma plane was not decoded")
+    }
+
+    /// Decode the channels in `mask` as full-size 8-bit planes
+    ///
+    /// Luma is shifted back to 0-255; chroma, which is centered on 0, is
+    /// offset by 128. Subsampled chroma is upsampled to the image size.
+    /// Channels outside the mask are `None`. No checksum is verified, since
+    /// the source checksum covers RGB pixels.
+    pub fn decode_channels(&self, icf_data: &[u8], mask: ChannelMask) -> Result<[Option<GrayImage>; 3]> {
+        let (header, compressed_data) = self.parse_container(icf_data)?;
+        self.decode_channels_streamed(&header, mask, |sink| {
+            Self::stream_payload(&header, compressed_data, mask, sink)
+        })
+    }
+
+    /// `decode_channels` over blocks delivered by `stream`, which only needs
+    /// to deliver the channels in `mask`
+    fn decode_channels_streamed<F>(&self, header: &IcfHeader, mask: ChannelMask, stream: F) -> Result<[Option<GrayImage>; 3]>
+    where
+        F: FnOnce(&mut dyn FnMut(CompressedBlock) -> Result<()>) -> Result<()>,
+    {
+        Self::validate_header(header)?;
+        phase!("icf.decode_channels", width = header.width, height = header.height, mask = ?mask);
+
+        let quantization_tables = Self::quantization_arrays(header);
+        let planes = self.assemble_planes(header, mask, stream, |channel, zigzag| {
+            residual::reconstruct_residual(self.dct, &ZERO_PREDICTION, &Quantization::zigzag_to_block(zigzag), &quantization_tables[channel])
+        }, |_, _, _| Ok(()))?;
+
+        let mut images = [None, None, None];
+        for (channel, plane) in planes.into_iter().enumerate() {
+            if !mask.contains(channel) {
+                continue;
+            }
+            let pixels = plane.data.iter()
+                .map(|&value| (value + 128.0).round().clamp(0.0, 255.0) as u8)
+                .collect();
+            images[channel] = Some(GrayImage::from_raw(header.width, header.height, pixels)
+                .context("Decoded plane has the wrong number of pixels")?);
+        }
+        Ok(images)
+    }
+
+    /// Decode ICF format to image and report checksum verification details
+    ///
+    /// A mismatch is an error when the file claims to be lossless or when
+    /// `options.strict_checksum` is set; otherwise it is flagged in the outcome.
+    /// `options.strictness` decides what happens to the other anomalies.
+    pub fn decode_checked(&self, icf_data: &[u8], options: &DecodeOptions) -> Result<DecodeOutcome> {
+        let (header, compressed_data) = self.parse_container(icf_data)?;
+        let layout = header.clone();
+        let mut incomplete = None;
+        let mut outcome = self.decode_streamed(header, options, |sink| {
+            if options.strictness != DecodeStrictness::Tolerant {
+                return Self::stream_payload(&layout, compressed_data, ChannelMask::ALL, sink);
+            }
+            if let Err(error) = Self::stream_available(&layout, compressed_data, sink) {
+                incomplete = Some(format!("{:#}", error));
+            }
+            Ok(())
+        })?;
+        if let Some(reason) = incomplete {
+            outcome.warnings.insert(0, DecodeAnomaly::IncompletePayload(reason));
+        }
+        Ok(outcome)
+    }
+
+    /// Decode as much of a truncated or damaged file as possible
+    ///
+    /// Blocks are parsed until the data runs out or the first parse error.
+    /// Missing blocks are filled with the mean of their decoded neighbors,
+    /// spreading outward into larger holes, or mid-gray when a channel has
+    /// no blocks at all. The header itself must be intact. No checksum is
+    /// verified.
+    pub fn decode_partial(&self, icf_data: &[u8]) -> Result<PartialDecode> {
+        let (header, compressed_data) = self.parse_container(icf_data)?;
+        Self::validate_header(&header)?;
+        phase!("icf.decode_partial", width = header.width, height = header.height);
+
+        let quantization_tables = Self::quantization_arrays(&header);
+        let subsampling = header.chroma_subsampling;
+        let dimensions = Self::plane_dimensions(header.width, header.height, subsampling);
+        let mut assembler = BlockAssembler::new(|channel, zigzag: &[i16; 64]| {
+            residual::reconstruct_residual(self.dct, &ZERO_PREDICTION, &Quantization::zigzag_to_block(zigzag), &quantization_tables[channel])
+        }, &dimensions, header.tile_size, subsampling, ChannelMask::ALL);
+        let stop_reason = Self::stream_available(&header, compressed_data, &mut |block| assembler.push(block))
+            .err()
+            .map(|error| format!("{:#}", error));
+
+        let (mut padded_planes, coverage): (Vec<_>, Vec<_>) = assembler.finish_with_coverage().into_iter().unzip();
+        for (plane, filled) in padded_planes.iter_mut().zip(&coverage) {
+            Self::fill_missing_blocks(plane, filled);
+        }
+        let total_blocks: usize = coverage.iter().flatten().map(Vec::len).sum();
+        let decoded_blocks = coverage.iter().flatten().flatten().filter(|&&filled| filled).count();
+        trace_event!(decoded_blocks, total_blocks, stopped = stop_reason.is_some(), "salvaged blocks");
+
+        let planes = Self::crop_planes(&header, ChannelMask::ALL, &padded_planes);
+        let row_bytes = header.width as usize * 3;
+        let mut raw = vec![0u8; row_bytes * header.height as usize];
+        Self::write_planes_rgb(&header, &planes, &mut raw, row_bytes);
+        let image = RgbImage::from_raw(header.width, header.height, raw)
+            .context("Decoded image has the wrong number of pixels")?;
+
+        let factor = subsampling.factor();
+        let coverage_mask = GrayImage::from_fn(header.width, header.height, |x, y| {
+            let decoded = coverage.iter().enumerate().filter(|(channel, filled)| {
+                let scale = if *channel == 0 { 1 } else { factor };
+                filled[(y / scale) as usize / Self::BLOCK_SIZE][(x / scale) as usize / Self::BLOCK_SIZE]
+            }).count();
+            image::Luma([(decoded * 255 / coverage.len().max(1)) as u8])
+        });
+
+        Ok(PartialDecode {
+            image: DynamicImage::ImageRgb8(image),
+            coverage_mask,
+            coverage: match total_blocks {
+                0 => 100.0,
+                _ => decoded_blocks as f64 * 100.0 / total_blocks as f64,
+            },
+            stop_reason,
+        })
+    }
+
+    /// Quantized DCT coefficients of every channel, with DC prediction undone
+    ///
+    /// Nothing is dequantized or inverse transformed; `CoefficientPlane`
+    /// scales coefficients back with the file's tables on request.
+    pub fn coefficients(&self, icf_data: &[u8]) -> Result<CoefficientPlanes> {
+        self.coefficient_planes(icf_data, ChannelMask::ALL)
+    }
+
+    /// 64-bit perceptual hash of the image, computed from the luma plane's
+    /// DCT coefficients without an inverse transform or any chroma parsing
+    ///
+    /// Each block contributes the means of its four 4x4 quadrants, taken
+    /// from its DC and three lowest AC coefficients, so the hash follows
+    /// `perceptual_hash_image` of the pixels closely. Compare hashes with
+    /// `hamming_distance` or `similar`.
+    pub fn perceptual_hash(&self, icf_data: &[u8]) -> Result<u64> {
+        let planes = self.coefficient_planes(icf_data, ChannelMask::LUMA)?;
+        let luma = &planes.channels[0];
+        let (columns, rows) = (luma.blocks_x * 2, luma.blocks_y * 2);
+        let mut grid = vec![0.0; columns * rows];
+        for y in 0..luma.blocks_y {
+            for x in 0..luma.blocks_x {
+                let means = phash::quadrant_means(&luma.dequantized_block(x, y).unwrap());
+                for (quadrant_y, row) in means.iter().enumerate() {
+                    let start = (y * 2 + quadrant_y) * columns + x * 2;
+                    grid[start..start + 2].copy_from_slice(row);
+                }
+            }
+        }
+        Ok(phash::hash_grid(&grid, columns, rows))
+    }
+
+    /// Blurhash-style placeholder of the image, from coefficients alone
+    ///
+    /// Each 16x16 cell's mean color comes from the DCs of the blocks it
+    /// covers; blocks on the right and bottom edges are averaged over the
+    /// part inside the image, which takes their lowest AC coefficients
+    /// too. Nothing is inverse transformed. Matches `placeholder_image` of
+    /// the decoded pixels.
+    pub fn placeholder(&self, icf_data: &[u8], components_x: u32, components_y: u32) -> Result<String> {
+        let (header, _) = self.parse_container(icf_data)?;
+        let planes = self.coefficients(icf_data)?;
+        let (width, height) = (header.width as usize, header.height as usize);
+        let columns = width.div_ceil(placeholder::CELL_SIZE);
+        let rows = height.div_ceil(placeholder::CELL_SIZE);
+
+        // Mean of a channel over a cell: its blocks weighted by the samples
+        // each has inside the image
+        let cell_mean = |channel: usize, column: usize, row: usize| {
+            let plane = &planes.channels[channel];
+            if plane.blocks_x == 0 {
+                // Grayscale JPEG transcodes carry no chroma
+                return 0.0;
+            }
+            let scale = if channel > 0 && header.chroma_subsampling == ChromaSubsampling::S420 { 2 } else { 1 };
+            let (plane_width, plane_height) = (width.div_ceil(scale), height.div_ceil(scale));
+            let blocks_per_cell = placeholder::CELL_SIZE / 8 / scale;
+            let (mut sum, mut area) = (0.0, 0.0);
+            for y in (row * blocks_per_cell..(row + 1) * blocks_per_cell).filter(|y| y * 8 < plane_height) {
+                for x in (column * blocks_per_cell..(column + 1) * blocks_per_cell).filter(|x| x * 8 < plane_width) {
+                    let (inside_x, inside_y) = ((plane_width - x * 8).min(8), (plane_height - y * 8).min(8));
+                    let mean = placeholder::region_mean(&plane.dequantized_block(x, y).unwrap(), inside_x, inside_y);
+                    sum += mean * (inside_x * inside_y) as f64;
+                    area += (inside_x * inside_y) as f64;
+                }
+            }
+            sum / area
+        };
+
+        let cells: Vec<[f64; 3]> = (0..columns * rows)
+            .map(|index| {
+                let [luma, c1, c2] = [0, 1, 2].map(|channel| cell_mean(channel, index % columns, index / columns));
+                let (r, g, b) = header.color_space.to_rgb((luma + 128.0) / 255.0, c1 / 255.0, c2 / 255.0);
+                [r, g, b]
+            })
+            .collect();
+        placeholder::encode_cells(&cells, width, height, components_x, components_y)
+    }
+
+    /// `coefficients` for the channels in `mask`; the others come back as
+    /// empty planes
+    fn coefficient_planes(&self, icf_data: &[u8], mask: ChannelMask) -> Result<CoefficientPlanes> {
+        let (header, compressed_data) = self.parse_container(icf_data)?;
+        Self::validate_header(&header)?;
+        phase!("icf.coefficients", width = header.width, height = header.height, mask = ?mask);
+
+        let subsampling = header.chroma_subsampling;
+        let dimensions = Self::plane_dimensions(header.width, header.height, subsampling);
+        let mut assembler = BlockAssembler::new(
+            |_, zigzag: &[i16; 64]| Quantization::zigzag_to_block(zigzag),
+            &dimensions, header.tile_size, subsampling, mask,
+        );
+        Self::stream_payload(&header, compressed_data, mask, &mut |block| assembler.push(block))?;
+
+        let channels = assembler.finish()?
+            .into_iter()
+            .zip(Self::quantization_arrays(&header))
+            .map(|(plane, quantization_table)| {
+                let (blocks_x, blocks_y) = plane.block_grid();
+                CoefficientPlane { blocks_x, blocks_y, quantized: plane.data, quantization_table }
+            })
+            .collect();
+        Ok(CoefficientPlanes { channels })
+    }
+
+    /// Reject headers this decoder can't handle
+    fn validate_header(header: &IcfHeader) -> Result<()> {
+        if header.magic != Self::MAGIC {
+            anyhow::bail!("Invalid ICF magic number: expected {}, got {}", 
+                Self::MAGIC, header.magic);
+        }
+
+        if !formats::ICF.reads(header.version) {
+            anyhow::bail!("Unsupported ICF version: {}", header.version);
+        }
+
+        if header.quantization_tables.len() != 3 {
+            anyhow::bail!("ICF header has {} quantization tables, expected 3",
+                header.quantization_tables.len());
+        }
+
+        if !matches!(header.channel_sections.len(), 0 | 3) {
+            anyhow::bail!("ICF header has {} channel sections, expected 3",
+                header.channel_sections.len());
+        }
+        if header.sign_contexts && header.channel_sections.is_empty() {
+            anyhow::bail!("ICF sign contexts need channel sections");
+        }
+        Self::check_tile_size(header.tile_size)
+    }
+
+    /// Decode an image whose blocks are delivered one at a time by `stream`
+    ///
+    /// Blocks are reconstructed as they arrive, so the compressed payload
+    /// never has to be held in memory.
+    fn decode_streamed<F>(&self, header: IcfHeader, options: &DecodeOptions, stream: F) -> Result<DecodeOutcome>
+    where
+        F: FnOnce(&mut dyn FnMut(CompressedBlock) -> Result<()>) -> Result<()>,
+    {
+        self.check_memory("ICF decode", header.width, header.height)?;
+        let row_bytes = header.width as usize * 3;
+        let mut raw = vec![0u8; row_bytes * header.height as usize];
+        let mut warnings = Vec::new();
+        for (index, section) in header.sections.iter().enumerate() {
+            if section.kind == IcfSectionKind::Unknown {
+                options.strictness.report(DecodeAnomaly::UnknownSection { index }, &mut warnings)?;
+            }
+        }
+        let (checksum_matched, actual, decode_warnings) = self.decode_streamed_into(&header, options, stream, &mut raw, row_bytes)?;
+        warnings.extend(decode_warnings);
+        let rgb_img = RgbImage::from_raw(header.width, header.height, raw)
+            .context("Decoded image has the wrong number of pixels")?;
+
+        Ok(DecodeOutcome {
+            image: DynamicImage::ImageRgb8(rgb_img),
+            checksum_matched,
+            expected: header.checksum,
+            actual,
+            warnings,
+        })
+    }
+
+    /// Decode straight into RGB8 rows of `out`, returning whether the checksum
+    /// matched, the checksum of the decoded pixels and the anomalies decoded past
+    fn decode_streamed_into<F>(
+        &self,
+        header: &IcfHeader,
+        options: &DecodeOptions,
+        stream: F,
+        out: &mut [u8],
+        out_stride: usize,
+    ) -> Result<(bool, String, Vec<DecodeAnomaly>)>
+    where
+        F: FnOnce(&mut dyn FnMut(CompressedBlock) -> Result<()>) -> Result<()>,
+    {
+        Self::validate_header(header)?;
+        Self::check_output_buffer(header.width, header.height, out.len(), out_stride)?;
+        phase!("icf.decode", width = header.width, height = header.height, fixed_point = options.fixed_point);
+
+        let strictness = options.strictness;
+        let mut warnings = Vec::new();
+        let mut out_of_range = None;
+        let stream = |sink: &mut dyn FnMut(CompressedBlock) -> Result<()>| stream(&mut |block: CompressedBlock| {
+            if !Self::coefficients_in_range(&block) {
+                match &mut out_of_range {
+                    Some(DecodeAnomaly::CoefficientsOutOfRange { blocks, .. }) => *blocks += 1,
+                    _ => {
+                        let anomaly = DecodeAnomaly::CoefficientsOutOfRange { channel: block.channel, x: block.x, y: block.y, blocks: 1 };
+                        if strictness == DecodeStrictness::Strict {
+                            return Err(IcfError::Anomaly(anomaly).into());
+                        }
+                        out_of_range = Some(anomaly);
+                    }
+                }
+            }
+            sink(block)
+        });
+        let mut gaps = Vec::new();
+        let mut on_gaps = |channel: usize, filled: &[Vec<bool>]| -> Result<bool> {
+            let count = filled.iter().flatten().filter(|&&decoded| !decoded).count();
+            if count == 0 {
+                return Ok(false);
+            }
+            strictness.report(DecodeAnomaly::MissingBlocks { channel, count }, &mut gaps)?;
+            Ok(strictness == DecodeStrictness::Tolerant)
+        };
+
+        let quantization_tables = Self::quantization_arrays(header);
+
+        // Decompress blocks back to luma/chroma planes and convert to RGB
+        if options.fixed_point {
+            let tables: Vec<icf_core::QuantTable> = quantization_tables.iter()
+                .map(Self::fixed_point_table)
+                .collect();
+            let planes = self.assemble_planes(header, ChannelMask::ALL, stream, |channel, zigzag| {
+                let mut block = [[0; 8]; 8];
+                icf_core::decode_block(zigzag, &tables[channel], &mut block);
+                block
+            }, |channel, _, filled| on_gaps(channel, filled).map(drop))?;
+            let transform = match header.color_space {
+                IcfColorSpace::YCoCg => icf_core::ColorTransform::YCoCg,
+                IcfColorSpace::YCbCr => icf_core::ColorTransform::YCbCr,
+            };
+            phase!("icf.color_convert");
+            Self::write_rgb(header.width, header.height, out, out_stride, |i| {
+                icf_core::to_rgb(transform, planes[0].data[i], planes[1].data[i], planes[2].data[i])
+            });
+        } else {
+            let planes = self.float_planes(header, stream, on_gaps)?;
+            Self::write_planes_rgb(header, &planes, out, out_stride);
+        }
+        warnings.extend(out_of_range);
+        warnings.extend(gaps);
+
+        // Verify checksum
+        phase!("icf.verify");
+        let row_bytes = header.width as usize * 3;
+        let mut hasher = Sha256::new();
+        for row in out.chunks(out_stride.max(1)).take(header.height as usize) {
+            hasher.update(&row[..row_bytes]);
+        }
+        let actual_checksum = format!("{:x}", hasher.finalize());
+        let checksum_matched = actual_checksum == header.checksum;
+
+        if !checksum_matched {
+            if ChecksumKind::of(&header.checksum) == ChecksumKind::Unknown {
+                strictness.report(DecodeAnomaly::UnverifiableChecksum(header.checksum.clone()), &mut warnings)?;
+            }
+            let mismatch = DecodeAnomaly::ChecksumMismatch { expected: header.checksum.clone(), actual: actual_checksum.clone() };
+            let fatal = options.strict_checksum || (header.is_lossless() && strictness != DecodeStrictness::Tolerant);
+            if fatal && strictness != DecodeStrictness::Strict {
+                anyhow::bail!("{}", mismatch);
+            }
+            if fatal || header.is_lossless() {
+                strictness.report(mismatch, &mut warnings)?;
+            } else {
+                diagnostic!(debug, "ICF checksum mismatch (expected for lossy compression)");
+            }
+        }
+
+        Ok((checksum_matched, actual_checksum, warnings))
+    }
+
+    /// Reconstruct full-resolution planes with the f64 pipeline
+    ///
+    /// `on_gaps` gets each channel's decoded-block grid and says whether to
+    /// conceal its missing blocks.
+    fn float_planes<F, G>(&self, header: &IcfHeader, stream: F, mut on_gaps: G) -> Result<Vec<Plane<f64>>>
+    where
+        F: FnOnce(&mut dyn FnMut(CompressedBlock) -> Result<()>) -> Result<()>,
+        G: FnMut(usize, &[Vec<bool>]) -> Result<bool>,
+    {
+        let quantization_tables = Self::quantization_arrays(header);
+        self.assemble_planes(header, ChannelMask::ALL, stream, |channel, zigzag| {
+            residual::reconstruct_residual(self.dct, &ZERO_PREDICTION, &Quantization::zigzag_to_block(zigzag), &quantization_tables[channel])
+        }, |channel, plane, filled| {
+            if on_gaps(channel, filled)? {
+                Self::fill_missing_blocks(plane, filled);
+            }
+            Ok(())
+        })
+    }
+
+    /// Whether every AC coefficient of `block` is within ±4096 and lands
+    /// inside the block
+    fn coefficients_in_range(block: &CompressedBlock) -> bool {
+        let mut position = 1;
+        block.ac_coefficients.iter().all(|&(zeros, value)| {
+            position += zeros as usize;
+            if zeros == 0 && value == 0 {
+                return true;
+            }
+            position += 1;
+            position <= 64 && value.unsigned_abs() <= 4096
+        })
+    }
+
+    /// Reconstruct the header's quantization tables as 8x8 arrays
+    fn quantization_arrays(header: &IcfHeader) -> Vec<[[f64; 8]; 8]> {
+        header.quantization_tables
+            .iter()
+            .map(|table| {
+                let mut array = [[0.0; 8]; 8];
+                for (i, row) in table.iter().enumerate() {
+                    for (j, &val) in row.iter().enumerate() {
+                        if i < 8 && j < 8 {
+                            array[i][j] = val;
+                        }
+                    }
+                }
+                array
+            })
+            .collect()
+    }
+
+    /// Reject an output buffer that can't hold `width` x `height` RGB8 rows `stride` bytes apart
+    fn check_output_buffer(width: u32, height: u32, len: usize, stride: usize) -> Result<()> {
+        let row_bytes = width as usize * 3;
+        if stride < row_bytes {
+            anyhow::bail!("Output stride {} is shorter than a {}-pixel RGB row ({} bytes)",
+                stride, width, row_bytes);
+        }
+
+        let needed = match height {
+            0 => 0,
+            _ => stride * (height as usize - 1) + row_bytes,
+        };
+        if len < needed {
+            anyhow::bail!("Output buffer too small for {}x{} RGB at stride {}: need {} bytes, got {}",
+                width, height, stride, needed, len);
+        }
+        Ok(())
+    }
+
+    /// Convert centered f64 luma/chroma planes to RGB8 rows of `out`
+    fn write_planes_rgb(header: &IcfHeader, planes: &[Plane<f64>], out: &mut [u8], out_stride: usize) {
+        let color_space = header.color_space;
+        let [luma, c1, c2] = [&planes[0].data, &planes[1].data, &planes[2].data];
+        phase!("icf.color_convert");
+        Self::write_rgb(header.width, header.height, out, out_stride, |i| {
+            let luma = (luma[i] + 128.0) / 255.0;
+            let (r, g, b) = color_space.to_rgb(luma, c1[i] / 255.0, c2[i] / 255.0);
+
+            // Clamp to valid range
+            let to_u8 = |value: f64| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+            [to_u8(r), to_u8(g), to_u8(b)]
+        });
+    }
+
+    /// Fill RGB8 rows of `out` with `pixel(i)` for each pixel index `i`
+    ///
+    /// The buffer must have passed `check_output_buffer`.
+    fn write_rgb(width: u32, height: u32, out: &mut [u8], stride: usize, pixel: impl Fn(usize) -> [u8; 3]) {
+        let width = width as usize;
+        for y in 0..height as usize {
+            let row = &mut out[y * stride..y * stride + width * 3];
+            for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+                rgb.copy_from_slice(&pixel(y * width + x));
+            }
+        }
+    }
+
+    /// Reconstruct full-resolution luma/chroma planes from a block stream
+    ///
+    /// `decode_block` turns one channel's zigzag coefficients into spatial samples.
+    /// `on_gaps` sees each reconstructed channel's block-padded plane and which
+    /// of its blocks were decoded before cropping.
+    /// Only channels in `mask` are reconstructed; the others come back empty.
+    fn assemble_planes<S, F, D, G>(&self, header: &IcfHeader, mask: ChannelMask, stream: F, decode_block: D, mut on_gaps: G) -> Result<Vec<Plane<S>>>
+    where
+        S: Copy + Default,
+        F: FnOnce(&mut dyn FnMut(CompressedBlock) -> Result<()>) -> Result<()>,
+        D: FnMut(usize, &[i16; 64]) -> [[S; 8]; 8],
+        G: FnMut(usize, &mut Plane<S>, &[Vec<bool>]) -> Result<()>,
+    {
+        phase!("icf.reconstruct");
+        let subsampling = header.chroma_subsampling;
+        let dimensions = Self::plane_dimensions(header.width, header.height, subsampling);
+        let mut assembler = BlockAssembler::new(decode_block, &dimensions, header.tile_size, subsampling, mask);
+        stream(&mut |block| assembler.push(block))?;
+        let mut padded_planes = Vec::new();
+        for (channel, (mut plane, filled)) in assembler.finish_with_coverage().into_iter().enumerate() {
+            if mask.contains(channel) {
+                on_gaps(channel, &mut plane, &filled)?;
+            }
+            padded_planes.push(plane);
+        }
+
+        Ok(Self::crop_planes(header, mask, &padded_planes))
+    }
+
+    /// Crop block-padded planes to full-resolution planes, upsampling
+    /// subsampled chroma; channels outside `mask` come back empty
+    fn crop_planes<S: Copy>(header: &IcfHeader, mask: ChannelMask, padded_planes: &[Plane<S>]) -> Vec<Plane<S>> {
+        let subsampling = header.chroma_subsampling;
+        let dimensions = Self::plane_dimensions(header.width, header.height, subsampling);
+        padded_planes.iter()
+            .zip(dimensions)
+            .enumerate()
+            .map(|(channel, (padded, (plane_width, plane_height)))| {
+                if !mask.contains(channel) {
+                    return Plane::from_vec(0, 0, Vec::new());
+                }
+                let plane = padded.cropped(plane_width as usize, plane_height as usize);
+                if channel > 0 && subsampling == ChromaSubsampling::S420 {
+                    Self::upsample_plane(&plane, header.width, header.height)
+                } else {
+                    plane
+                }
+            })
+            .collect()
+    }
+
+    /// Convert a quantization table to `icf_core`'s fixed-point form
+    fn fixed_point_table(table: &[[f64; 8]; 8]) -> icf_core::QuantTable {
+        let scale = (1 << icf_core::TABLE_BITS) as f64;
+        table.map(|row| row.map(|step| (step * scale).round() as i32))
+    }
+
+    /// Feed the blocks of a whole payload to `sink`
+    ///
+    /// With channel sections, only the arrays of channels in `mask` are
+    /// parsed; a single interleaved array is parsed whole.
+    fn stream_payload(
+        header: &IcfHeader,
+        compressed_data: &[u8],
+        mask: ChannelMask,
+        sink: &mut dyn FnMut(CompressedBlock) -> Result<()>,
+    ) -> Result<()> {
+        if header.channel_sections.is_empty() {
+            return Self::stream_blocks(&mut serde_json::Deserializer::from_slice(compressed_data), sink);
+        }
+
+        let mut offset = 0usize;
+        for (channel, section) in header.channel_sections.iter().enumerate() {
+            let span = |length: u64, start: usize| usize::try_from(length).ok()
+                .and_then(|length| start.checked_add(length))
+                .filter(|&end| end <= compressed_data.len())
+                .with_context(|| format!("ICF channel {} section extends past end of file", channel));
+            let blocks_start = span(section.signs, offset)?;
+            let end = span(section.length, blocks_start)?;
+            if mask.contains(channel) {
+                let mut sink = Self::section_sink(header, channel, &compressed_data[offset..blocks_start], sink);
+                Self::stream_blocks(&mut serde_json::Deserializer::from_slice(&compressed_data[blocks_start..end]), &mut sink)?;
+            }
+            offset = end;
+        }
+        Ok(())
+    }
+
+    /// Wrap `sink` for one channel section, restoring coefficient signs
+    /// from `signs` when the file has sign contexts
+    fn section_sink<'s>(
+        header: &IcfHeader,
+        channel: usize,
+        signs: &[u8],
+        sink: &'s mut dyn FnMut(CompressedBlock) -> Result<()>,
+    ) -> impl FnMut(CompressedBlock) -> Result<()> + 's {
+        let mut decoder = header.sign_contexts.then(|| {
+            let (blocks_x, blocks_y) = Self::block_grid(header, channel);
+            SignDecoder::new(signs.to_vec(), blocks_x, blocks_y)
+        });
+        move |mut block| {
+            if let Some(decoder) = &mut decoder {
+                decoder.restore(&mut block)?;
+            }
+            sink(block)
+        }
+    }
+
+    /// Block grid of one channel, as (columns, rows)
+    fn block_grid(header: &IcfHeader, channel: usize) -> (usize, usize) {
+        let (width, height) = Self::plane_dimensions(header.width, header.height, header.chroma_subsampling)[channel];
+        (width.div_ceil(8) as usize, height.div_ceil(8) as usize)
+    }
+
+    /// Feed `sink` the blocks of a possibly truncated payload, stopping at the
+    /// first parse error
+    ///
+    /// Blocks delivered before the error stay delivered. Channel sections
+    /// that run past the end of the data are parsed as far as they go.
+    fn stream_available(
+        header: &IcfHeader,
+        compressed_data: &[u8],
+        sink: &mut dyn FnMut(CompressedBlock) -> Result<()>,
+    ) -> Result<()> {
+        if header.channel_sections.is_empty() {
+            return Self::stream_blocks(&mut serde_json::Deserializer::from_slice(compressed_data), sink);
+        }
+
+        let mut offset = 0usize;
+        for (channel, section) in header.channel_sections.iter().enumerate() {
+            if offset >= compressed_data.len() {
+                anyhow::bail!("ICF data ends before channel {} section", channel);
+            }
+            let span = |length: u64, start: usize| usize::try_from(length).unwrap_or(usize::MAX)
+                .saturating_add(start)
+                .min(compressed_data.len());
+            let blocks_start = span(section.signs, offset);
+            let end = span(section.length, blocks_start);
+            let mut sink = Self::section_sink(header, channel, &compressed_data[offset..blocks_start], sink);
+            Self::stream_blocks(&mut serde_json::Deserializer::from_slice(&compressed_data[blocks_start..end]), &mut sink)
+                .with_context(|| format!("Channel {} section is incomplete", channel))?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    /// Fill blocks that were never decoded with the mean sample of their
+    /// decoded or already-filled 4-neighbors, one ring at a time
+    ///
+    /// A grid with no decoded blocks is left at zero, which is mid-gray
+    /// for luma and neutral for chroma.
+    fn fill_missing_blocks(plane: &mut Plane<f64>, filled: &[Vec<bool>]) {
+        let (blocks_x, blocks_y) = (filled.first().map_or(0, Vec::len), filled.len());
+        let mut known = filled.to_vec();
+        let neighbors = |x: usize, y: usize| {
+            [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+                .into_iter()
+                .filter(move |&(nx, ny)| nx < blocks_x && ny < blocks_y)
+        };
+
+        let mut ring: Vec<(usize, usize)> = (0..blocks_y)
+            .flat_map(|y| (0..blocks_x).map(move |x| (x, y)))
+            .filter(|&(x, y)| !known[y][x] && neighbors(x, y).any(|(nx, ny)| known[ny][nx]))
+            .collect();
+        while !ring.is_empty() {
+            let means: Vec<f64> = ring.iter()
+                .map(|&(x, y)| {
+                    let sources: Vec<f64> = neighbors(x, y)
+                        .filter(|&(nx, ny)| known[ny][nx])
+                        .map(|(nx, ny)| plane.block(nx, ny).to_array().iter().flatten().sum::<f64>() / 64.0)
+                        .collect();
+                    sources.iter().sum::<f64>() / sources.len() as f64
+                })
+                .collect();
+            for (&(x, y), mean) in ring.iter().zip(means) {
+                plane.write_block(x, y, &[[mean; 8]; 8]);
+                known[y][x] = true;
+            }
+
+            let mut next: Vec<(usize, usize)> = ring.iter()
+                .flat_map(|&(x, y)| neighbors(x, y))
+                .filter(|&(x, y)| !known[y][x])
+                .collect();
+            next.sort_unstable();
+            next.dedup();
+            ring = next;
+        }
+    }
+
+    /// Feed each block of a serialized block array to `sink` without collecting them
+    fn stream_blocks<'de, R>(
+        deserializer: &mut serde_json::Deserializer<R>,
+        sink: &mut dyn FnMut(CompressedBlock) -> Result<()>,
+    ) -> Result<()>
+    where
+        R: serde_json::de::Read<'de>,
+    {
+        use serde::Deserializer as _;
+
+        let mut sink_error = None;
+        let parsed = (&mut *deserializer).deserialize_seq(BlockVisitor { sink, sink_error: &mut sink_error });
+        if let Some(error) = sink_error {
+            return Err(error);
+        }
+        parsed.context("Failed to deserialize compressed blocks")?;
+        deserializer.end().context("Failed to deserialize compressed blocks")?;
+        Ok(())
+    }
+
+    fn check_tile_size(tile_size: Option<u32>) -> Result<()> {
+        match tile_size {
+            Some(size) if size == 0 || size % 16 != 0 => {
+                anyhow::bail!("ICF tile size must be a positive multiple of 16, got {}", size)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Width and height of the luma plane and the two chroma planes
+    fn plane_dimensions(width: u32, height: u32, subsampling: ChromaSubsampling) -> [(u32, u32); 3] {
+        let factor = subsampling.factor();
+        let chroma = (width.div_ceil(factor), height.div_ceil(factor));
+        [(width, height), chroma, chroma]
+    }
+
+    /// Tile edge in blocks for one channel's plane
+    fn tile_blocks(tile_size: Option<u32>, channel: usize, subsampling: ChromaSubsampling) -> Option<usize> {
+        let factor = if channel == 0 { 1 } else { subsampling.factor() };
+        tile_size.map(|size| (size / factor) as usize / Self::BLOCK_SIZE)
+    }
+
+    /// Sort key giving the coding order of a block: raster order of tiles,
+    /// then raster order of blocks within the tile
+    fn scan_key(x: usize, y: usize, tile_blocks: Option<usize>) -> (usize, usize, usize, usize) {
+        match tile_blocks {
+            Some(tile) => (y / tile, x / tile, y, x),
+            None => (0, 0, y, x),
+        }
+    }
+
+    /// Convert an RGB image to centered luma and chroma planes
+    fn rgb_to_frame(&self, rgb_img: &RgbImage, color_space: IcfColorSpace) -> Frame<f64> {
+        let (width, height) = (rgb_img.width() as usize, rgb_img.height() as usize);
+        let mut planes = [
+            Vec::with_capacity(width * height),
+            Vec::with_capacity(width * height),
+            Vec::with_capacity(width * height),
+        ];
+
+        for pixel in rgb_img.pixels() {
+            let (luma, c1, c2) = color_space.from_rgb(
+                pixel[0] as f64 / 255.0,
+                pixel[1] as f64 / 255.0,
+                pixel[2] as f64 / 255.0,
+            );
+
+            // Center around 0 for DCT
+            planes[0].push(luma * 255.0 - 128.0);
+            planes[1].push(c1 * 255.0);
+            planes[2].push(c2 * 255.0);
+        }
+
+        Frame {
+            planes: planes.into_iter().map(|data| Plane::from_vec(width, height, data)).collect(),
+            color_space,
+        }
+    }
+
+    /// 4:4:4 if enough blocks have chroma detail that 4:2:0 would average away, 4:2:0 otherwise
+    ///
+    /// A block is chroma-active when its chroma samples differ from the
+    /// means of their 2x2 cells, which is all `downsample_plane` keeps, by
+    /// more than `CHROMA_ACTIVE_ENERGY` in mean square. Colored text and UI
+    /// edges are active; smooth photographic color isn't.
+    fn choose_subsampling(frame: &Frame<f64>, trace: &mut ExplainTrace) -> ChromaSubsampling {
+        let chroma = &frame.planes[1..];
+        let (blocks_x, blocks_y) = chroma[0].block_grid();
+        let mut active = 0;
+        for block_y in 0..blocks_y {
+            for block_x in 0..blocks_x {
+                let mut energy = 0.0;
+                for plane in chroma {
+                    let block = plane.block(block_x, block_y);
+                    for (row, column) in (0..8).step_by(2).flat_map(|row| (0..8).step_by(2).map(move |column| (row, column))) {
+                        let cell = [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(dy, dx)| block.get(row + dy, column + dx));
+                        let mean = cell.iter().sum::<f64>() / 4.0;
+                        energy += cell.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>();
+                    }
+                }
+                if energy / (64.0 * chroma.len() as f64) > Self::CHROMA_ACTIVE_ENERGY {
+                    active += 1;
+                }
+            }
+        }
+
+        let fraction = active as f64 / (blocks_x * blocks_y).max(1) as f64;
+        let (subsampling, reason) = if trace.threshold("icf.subsampling", "chroma_active_fraction", fraction, Self::CHROMA_ACTIVE_FRACTION) {
+            (ChromaSubsampling::S444, "enough chroma detail to keep")
+        } else {
+            (ChromaSubsampling::S420, "too little chroma detail to keep")
+        };
+        trace.chosen("icf.subsampling", subsampling, reason);
+        trace_event!(active_fraction = fraction, subsampling = %subsampling, "chose chroma subsampling");
+        subsampling
+    }
+
+    /// Halve a plane in both directions by averaging 2x2 neighbourhoods
+    fn downsample_plane(plane: &Plane<f64>) -> Plane<f64> {
+        let (width, height) = (plane.width, plane.height);
+        let (half_width, half_height) = (width.div_ceil(2), height.div_ceil(2));
+        let mut output = Vec::with_capacity(half_width * half_height);
+
+        for y in 0..half_height {
+            for x in 0..half_width {
+                let mut sum = 0.0;
+                for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                    sum += plane.get((2 * x + dx).min(width - 1), (2 * y + dy).min(height - 1));
+                }
+                output.push(sum / 4.0);
+            }
+        }
+
+        Plane::from_vec(half_width, half_height, output)
+    }
+
+    /// Double a subsampled plane back to `width` x `height` by replication
+    fn upsample_plane<S: Copy>(plane: &Plane<S>, width: u32, height: u32) -> Plane<S> {
+        let (width, height) = (width as usize, height as usize);
+        let last_row = plane.height.saturating_sub(1);
+        let mut output = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let row = plane.row((y / 2).min(last_row));
+            for x in 0..width {
+                output.push(row[(x / 2).min(plane.width - 1)]);
+            }
+        }
+
+        Plane::from_vec(width, height, output)
+    }
+
+    /// Quantization table for one channel under the chosen table set
+    fn quantization_table(
+        &self,
+        kind: QuantTableKind,
+        quality: u8,
+        channel: usize,
+        plane: &Plane<f64>,
+    ) -> [[f64; 8]; 8] {
+        let is_luminance = channel == 0;
+        match kind {
+            // Encodes reject `Jpeg`, which only transcoding produces
+            QuantTableKind::Standard | QuantTableKind::Jpeg => Quantization::create_quantization_table(quality, is_luminance),
+            QuantTableKind::Perceptual if is_luminance => {
+                Quantization::perceptual_quantization_table(quality, 1.0)
+            }
+            QuantTableKind::Perceptual => Quantization::create_quantization_table(quality, false),
+            QuantTableKind::Optimized => {
+                // Adapt to the mean coefficient magnitudes of the whole channel
+                let mut mean = [[0.0; 8]; 8];
+                let (blocks_x, blocks_y) = plane.block_grid();
+                let block_count = (blocks_x * blocks_y).max(1) as f64;
+                let mut dct_block = [[0.0; 8]; 8];
+                for (_, block) in plane.blocks() {
+                    self.dct.forward_8x8_into(&block.to_array(), &mut dct_block);
+                    for (mean_row, dct_row) in mean.iter_mut().zip(dct_block.iter()) {
+                        for (m, coefficient) in mean_row.iter_mut().zip(dct_row.iter()) {
+                            *m += coefficient.abs() / block_count;
+                        }
+                    }
+                }
+                Quantization::adaptive_quantization_table(&mean, quality, is_luminance)
+            }
+        }
+    }
+
+    /// Compress blocks for a single channel in coding order
+    fn compress_channel_blocks(
+        &self,
+        plane: &Plane<f64>,
+        channel: u8,
+        quantization_table: &[[f64; 8]; 8],
+        tile_blocks: Option<usize>,
+    ) -> Vec<CompressedBlock> {
+        let (blocks_x, blocks_y) = plane.block_grid();
+        let mut positions: Vec<(usize, usize)> = (0..blocks_y)
+            .flat_map(|block_y| (0..blocks_x).map(move |block_x| (block_x, block_y)))
+            .collect();
+        positions.sort_by_key(|&(x, y)| Self::scan_key(x, y, tile_blocks));
+
+        let mut compressed_blocks = Vec::with_capacity(positions.len());
+        let mut prev_dc = 0i16; // For DC coefficient differential encoding
+        let mut current_tile = None;
+
+        for (block_x, block_y) in positions {
+            // DC prediction restarts at every tile
+            let (tile_y, tile_x, _, _) = Self::scan_key(block_x, block_y, tile_blocks);
+            if current_tile != Some((tile_y, tile_x)) {
+                current_tile = Some((tile_y, tile_x));
+                prev_dc = 0;
+            }
+
+            let block = plane.block(block_x, block_y).to_array();
+            
+            // DCT and quantize the level-shifted samples
+            let quantized_block = residual::quantize_residual(self.dct, &block, &ZERO_PREDICTION, quantization_table);
+            
+            // Extract DC coefficient (differential encoding)
+            let dc_coefficient = quantized_block[0][0].wrapping_sub(prev_dc);
+            prev_dc = quantized_block[0][0];
+            
+            // Convert to zigzag order and skip DC coefficient
+            let mut zigzag = Quantization::block_to_zigzag(&quantized_block);
+            zigzag.remove(0); // Remove DC coefficient (already stored separately)
+            
+            // Run-length encode AC coefficients
+            let ac_coefficients = Quantization::run_length_encode(&zigzag);
+            
+            compressed_blocks.push(CompressedBlock {
+                x: block_x as u16,
+                y: block_y as u16,
+                channel,
+                dc_coefficient,
+                ac_coefficients,
+            });
+        }
+
+        compressed_blocks
+    }
+
+    /// Serialize compressed blocks to binary data
+    fn serialize_blocks(&self, blocks: &[CompressedBlock]) -> Result<Vec<u8>> {
+        serde_json::to_vec(blocks)
+            .context("Failed to serialize compressed blocks")
+    }
+
+    /// Create ICF container, moving the metadata and quantization tables
+    /// into deflated sections if `compress_sections`
+    fn create_container(&self, mut header: IcfHeader, compressed_data: Vec<u8>, compress_sections: bool) -> Result<Vec<u8>> {
+        header.compressed_size = compressed_data.len() as u64;
+
+        header.sections.clear();
+        let mut section_data = Vec::new();
+        if compress_sections {
+            let mut jsons = Vec::new();
+            if let Some(metadata) = header.metadata.take() {
+                jsons.push((IcfSectionKind::Metadata, serde_json::to_vec(&metadata)?));
+            }
+            let tables = std::mem::take(&mut header.quantization_tables);
+            jsons.push((IcfSectionKind::QuantizationTables, serde_json::to_vec(&tables)?));
+            for (kind, json) in jsons {
+                let packed = header_sections::pack(&json)?;
+                header.sections.push(IcfSection {
+                    kind,
+                    length: packed.len() as u64,
+                    expanded_length: json.len() as u64,
+                });
+                section_data.extend_from_slice(&packed);
+            }
+        }
+        
+        let header_json = serde_json::to_vec(&header)
+            .context("Failed to serialize ICF header")?;
+        
+        let mut container = Vec::new();
+        container.extend_from_slice(Self::MAGIC.as_bytes());
+        container.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+        container.extend_from_slice(&header_json);
+        container.extend_from_slice(&section_data);
+        container.extend_from_slice(&compressed_data);
+
+        Ok(container)
+    }
+
+    /// Parse ICF container, returning the header and the block data
+    ///
+    /// A quantization table section is expanded into the header; a
+    /// metadata section is left for `metadata` to read.
+    pub fn parse_container<'a>(&self, icf_data: &'a [u8]) -> Result<(IcfHeader, &'a [u8])> {
+        let (mut header, rest) = Self::split_header(icf_data)?;
+        let sections_size = header.sections_size()?;
+        let compressed_data = usize::try_from(sections_size).ok()
+            .and_then(|size| rest.get(size..))
+            .context("Invalid ICF file: sections extend past the end")?;
+        if let Some(tables) = Self::read_section(&header, rest, IcfSectionKind::QuantizationTables)? {
+            header.quantization_tables = serde_json::from_slice(&tables)
+                .context("Failed to parse ICF quantization tables")?;
+        }
+
+        Ok((header, compressed_data))
+    }
+
+    /// The file's metadata, expanding its section if it has one
+    pub fn metadata(&self, icf_data: &[u8]) -> Result<Option<IcfMetadata>> {
+        let (header, rest) = Self::split_header(icf_data)?;
+        match Self::read_section(&header, rest, IcfSectionKind::Metadata)? {
+            Some(json) => Ok(Some(serde_json::from_slice(&json).context("Failed to parse ICF metadata")?)),
+            None => Ok(header.metadata),
+        }
+    }
+
+    /// JSON of the `kind` section, if the header lists one; `sections` is
+    /// the data following the header
+    fn read_section(header: &IcfHeader, sections: &[u8], kind: IcfSectionKind) -> Result<Option<Vec<u8>>> {
+        let mut offset = 0u64;
+        for section in &header.sections {
+            let end = offset.checked_add(section.length).context("ICF section sizes overflow")?;
+            if section.kind == kind {
+                let stored = sections.get(offset as usize..end as usize)
+                    .with_context(|| format!("Invalid ICF file: {} section extends past the end", kind.as_str()))?;
+                return header_sections::unpack(stored, section.expanded_length).map(Some);
+            }
+            offset = end;
+        }
+        Ok(None)
+    }
+
+    /// Parse the prefix and header JSON, returning the header and the data after it
+    fn split_header(icf_data: &[u8]) -> Result<(IcfHeader, &[u8])> {
+        if icf_data.len() < 8 {
+            anyhow::bail!("Invalid ICF file: too small");
+        }
+
+        let magic = std::str::from_utf8(&icf_data[0..4])
+            .context("Invalid ICF magic")?;
+        if magic != Self::MAGIC {
+            anyhow::bail!("Invalid ICF magic number");
+        }
+
+        let header_size = u32::from_le_bytes([
+            icf_data[4], icf_data[5], icf_data[6], icf_data[7]
+        ]) as usize;
+
+        if icf_data.len() < 8 + header_size {
+            anyhow::bail!("Invalid ICF file: header size mismatch");
+        }
+
+        let header_data = &icf_data[8..8 + header_size];
+        let header: IcfHeader = serde_json::from_slice(header_data)
+            .context("Failed to parse ICF header")?;
+
+        Ok((header, &icf_data[8 + header_size..]))
+    }
+
+    /// Read the dimensions, quality and channel count from the first bytes of a file
+    ///
+    /// Needs at most `PEEK_LIMIT` (4 KB) of prefix; a shorter prefix than
+    /// the header requires gives `NeedMoreData` with the length to fetch.
+    pub fn peek(prefix: &[u8]) -> Result<PeekResult<IcfPeek>> {
+        peek::peek_json_header(prefix, Self::MAGIC, "ICF")
+    }
+
+    /// Describe every region of an ICF file without decoding the blocks
+    pub fn parse_layout(&self, icf_data: &[u8]) -> Result<IcfLayout> {
+        let (header, _) = self.parse_container(icf_data)?;
+        let header_size = u32::from_le_bytes([
+            icf_data[4], icf_data[5], icf_data[6], icf_data[7]
+        ]) as u64;
+
+        let mut parts = vec![("magic", 4), ("header_length", 4), ("header", header_size)];
+        parts.extend(header.sections.iter().map(|section| (section.kind.as_str(), section.length)));
+        parts.push(("blocks", header.compressed_size));
+        let regions = layout::split_regions(icf_data, &parts).context("Invalid ICF layout")?;
+        let trailing_size = regions.iter()
+            .find(|region| region.name == "trailing")
+            .map_or(0, |region| region.length);
+
+        Ok(IcfLayout {
+            file_size: icf_data.len() as u64,
+            blocks_size: header.compressed_size,
+            trailing_size,
+            header,
+            regions,
+        })
+    }
+
+    /// Get compression statistics
+    pub fn get_stats(&self, original_path: &str, icf_data: &[u8]) -> Result<ImageCompressionStats> {
+        let original_img = input::decode_input(original_path)?;
+        let original_size = original_img.as_bytes().len();
+        let compressed_size = icf_data.len();
+        let compression_ratio = original_size as f64 / compressed_size as f64;
+        let savings_percent = ((original_size as f64 - compressed_size as f64) / original_size as f64) * 100.0;
+
+        Ok(ImageCompressionStats {
+            original_size,
+            compressed_size,
+            compression_ratio,
+            savings_percent,
+        })
+    }
+}
+
+/// Streams a JSON block array into a callback instead of collecting it
+struct BlockVisitor<'s> {
+    sink: &'s mut dyn FnMut(CompressedBlock) -> Result<()>,
+    /// Where the sink's error is kept, since serde can only carry its message
+    sink_error: &'s mut Option<anyhow::Error>,
+}
+
+impl<'de> serde::de::Visitor<'de> for BlockVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of compressed blocks")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<(), A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        use serde::de::Error;
+
+        while let Some(block) = seq.next_element::<CompressedBlock>()? {
+            if let Err(error) = (self.sink)(block) {
+                let message = format!("{:#}", error);
+                *self.sink_error = Some(error);
+                return Err(A::Error::custom(message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reconstructs spatial blocks as compressed blocks arrive
+///
+/// Blocks normally arrive in coding order and are decoded immediately.
+/// Anything ahead of the next expected position waits in `pending` until
+/// its predecessors show up, so DC prediction sees the same sequence as a
+/// sorted decode would.
+struct BlockAssembler<S, D> {
+    decode_block: D,
+    channels: Vec<ChannelAssembly<S>>,
+    /// Blocks of other channels are dropped without being decoded
+    mask: ChannelMask,
+}
+
+struct ChannelAssembly<S> {
+    /// Decoded samples, padded out to whole blocks
+    plane: Plane<S>,
+    /// Which blocks have been reconstructed
+    filled: Vec<Vec<bool>>,
+    tile_blocks: Option<usize>,
+    /// Tile edge in blocks used to walk the scan order
+    scan_tile: usize,
+    next: Option<(usize, usize)>,
+    prev_dc: i16,
+    current_tile: Option<(usize, usize)>,
+    pending: BTreeMap<(usize, usize, usize, usize), CompressedBlock>,
+}
+
+impl<S> ChannelAssembly<S> {
+    fn grid(&self) -> (usize, usize) {
+        (self.filled.first().map_or(0, Vec::len), self.filled.len())
+    }
+
+    /// Position following `(x, y)` in coding order, or `None` at the end
+    fn next_position(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        let (blocks_x, blocks_y) = self.grid();
+        let tile = self.scan_tile;
+        let (tile_x, tile_y) = (x / tile, y / tile);
+        let x_end = ((tile_x + 1) * tile).min(blocks_x);
+        let y_end = ((tile_y + 1) * tile).min(blocks_y);
+
+        if x + 1 < x_end {
+            Some((x + 1, y))
+        } else if y + 1 < y_end {
+            Some((tile_x * tile, y + 1))
+        } else if (tile_x + 1) * tile < blocks_x {
+            Some(((tile_x + 1) * tile, tile_y * tile))
+        } else if (tile_y + 1) * tile < blocks_y {
+            Some((0, (tile_y + 1) * tile))
+        } else {
+            None
+        }
+    }
+}
+
+impl<S, D> BlockAssembler<S, D>
+where
+    S: Copy + Default,
+    D: FnMut(usize, &[i16; 64]) -> [[S; 8]; 8],
+{
+    fn new(
+        decode_block: D,
+        dimensions: &[(u32, u32); 3],
+        tile_size: Option<u32>,
+        subsampling: ChromaSubsampling,
+        mask: ChannelMask,
+    ) -> Self {
+        let channels = dimensions.iter()
+            .enumerate()
+            .map(|(channel, &(width, height))| {
+                // Skipped channels get an empty grid and never hold blocks
+                let (blocks_x, blocks_y) = if mask.contains(channel) {
+                    (width.div_ceil(8) as usize, height.div_ceil(8) as usize)
+                } else {
+                    (0, 0)
+                };
+                let tile_blocks = IcfCodec::tile_blocks(tile_size, channel, subsampling);
+                ChannelAssembly {
+                    plane: Plane::new(blocks_x * 8, blocks_y * 8, S::default()),
+                    filled: vec![vec![false; blocks_x]; blocks_y],
+                    tile_blocks,
+                    scan_tile: tile_blocks.unwrap_or(blocks_x.max(blocks_y)).max(1),
+                    next: (blocks_x > 0 && blocks_y > 0).then_some((0, 0)),
+                    prev_dc: 0,
+                    current_tile: None,
+                    pending: BTreeMap::new(),
+                }
+            })
+            .collect();
+
+        Self { decode_block, channels, mask }
+    }
+
+    fn push(&mut self, block: CompressedBlock) -> Result<()> {
+        let channel_idx = block.channel as usize;
+        let Some(channel) = self.channels.get_mut(channel_idx) else {
+            anyhow::bail!("ICF block has invalid channel {}", block.channel);
+        };
+        if !self.mask.contains(channel_idx) {
+            return Ok(());
+        }
+
+        let (x, y) = (block.x as usize, block.y as usize);
+        let (blocks_x, blocks_y) = channel.grid();
+        if x >= blocks_x || y >= blocks_y {
+            anyhow::bail!("ICF block ({}, {}) lies outside the {}x{} block grid of channel {}",
+                x, y, blocks_x, blocks_y, channel_idx);
+        }
+
+        let key = IcfCodec::scan_key(x, y, channel.tile_blocks);
+        let expected = channel.next.map(|(nx, ny)| IcfCodec::scan_key(nx, ny, channel.tile_blocks));
+        if expected.is_none_or(|expected| key < expected) || channel.pending.contains_key(&key) {
+            anyhow::bail!("ICF block ({}, {}) of channel {} appears twice", x, y, channel_idx);
+        }
+
+        if Some(key) != expected {
+            channel.pending.insert(key, block);
+            return Ok(());
+        }
+
+        self.reconstruct(channel_idx, block);
+        loop {
+            let channel = &mut self.channels[channel_idx];
+            let Some((nx, ny)) = channel.next else { break };
+            let Some(block) = channel.pending.remove(&IcfCodec::scan_key(nx, ny, channel.tile_blocks)) else {
+                break;
+            };
+            self.reconstruct(channel_idx, block);
+        }
+        Ok(())
+    }
+
+    /// Decode one block in coding order and advance the expected position
+    fn reconstruct(&mut self, channel_idx: usize, block: CompressedBlock) {
+        let channel = &mut self.channels[channel_idx];
+        let (x, y) = (block.x as usize, block.y as usize);
+        let (tile_y, tile_x, _, _) = IcfCodec::scan_key(x, y, channel.tile_blocks);
+        if channel.current_tile != Some((tile_y, tile_x)) {
+            channel.current_tile = Some((tile_y, tile_x));
+            channel.prev_dc = 0;
+        }
+
+        // Reconstruct DC coefficient
+        let dc_coefficient = block.dc_coefficient.wrapping_add(channel.prev_dc);
+        channel.prev_dc = dc_coefficient;
+
+        // Combine DC and run-length coded AC coefficients in zigzag order
+        let mut zigzag = [0i16; 64];
+        icf_core::expand_runs(dc_coefficient, &block.ac_coefficients, &mut zigzag);
+
+        channel.plane.write_block(x, y, &(self.decode_block)(channel_idx, &zigzag));
+        channel.filled[y][x] = true;
+        if channel.next == Some((x, y)) {
+            channel.next = channel.next_position(x, y);
+        }
+    }
+
+    /// Decode whatever is still waiting on missing blocks and return the
+    /// block-padded planes
+    fn finish(self) -> Result<Vec<Plane<S>>> {
+        Ok(self.finish_with_coverage().into_iter().map(|(plane, _)| plane).collect())
+    }
+
+    /// Like `finish`, also returning which blocks of each plane were decoded
+    fn finish_with_coverage(mut self) -> Vec<(Plane<S>, Vec<Vec<bool>>)> {
+        for channel_idx in 0..self.channels.len() {
+            let pending = std::mem::take(&mut self.channels[channel_idx].pending);
+            for block in pending.into_values() {
+                self.reconstruct(channel_idx, block);
+            }
+        }
+
+        self.channels.into_iter().map(|channel| (channel.plane, channel.filled)).collect()
+    }
+}
+
+/// Reads an ICF file incrementally
+///
+/// Only the header is loaded up front; block data is streamed from the
+/// underlying reader, so the compressed payload is never held in memory.
+pub struct IcfReader<R> {
+    reader: R,
+    header: IcfHeader,
+    blocks_offset: u64,
+}
+
+impl IcfReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> IcfReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut prefix = [0u8; 8];
+        reader.read_exact(&mut prefix).context("Invalid ICF file: too small")?;
+        if &prefix[0..4] != IcfCodec::MAGIC.as_bytes() {
+            anyhow::bail!("Invalid ICF magic number");
+        }
+
+        let header_size = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as u64;
+        if stream_len < 8 + header_size {
+            anyhow::bail!("Invalid ICF file: header size mismatch");
+        }
+
+        let mut header: IcfHeader = serde_json::from_reader((&mut reader).take(header_size))
+            .context("Failed to parse ICF header")?;
+
+        // Sections are small; only the quantization tables are expanded
+        let sections_size = header.sections_size()?;
+        if stream_len - 8 - header_size < sections_size {
+            anyhow::bail!("Invalid ICF file: sections extend past the end");
+        }
+        let mut sections = Vec::new();
+        (&mut reader).take(sections_size).read_to_end(&mut sections)?;
+        if let Some(tables) = IcfCodec::read_section(&header, &sections, IcfSectionKind::QuantizationTables)? {
+            header.quantization_tables = serde_json::from_slice(&tables)
+                .context("Failed to parse ICF quantization tables")?;
+        }
+
+        Ok(Self { reader, header, blocks_offset: 8 + header_size + sections_size })
+    }
+
+    pub fn header(&self) -> &IcfHeader {
+        &self.header
+    }
+
+    /// Call `f` with each compressed block in file order
+    pub fn for_each_block<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(CompressedBlock) -> Result<()>,
+    {
+        self.stream_into(ChannelMask::ALL, &mut f)
+    }
+
+    /// Stream the blocks of the channels in `mask`, seeking past the
+    /// sections of other channels when the file has them
+    fn stream_into(&mut self, mask: ChannelMask, sink: &mut dyn FnMut(CompressedBlock) -> Result<()>) -> Result<()> {
+        if self.header.channel_sections.is_empty() {
+            self.reader.seek(SeekFrom::Start(self.blocks_offset))?;
+            let blocks = (&mut self.reader).take(self.header.compressed_size);
+            return IcfCodec::stream_blocks(&mut serde_json::Deserializer::from_reader(blocks), sink);
+        }
+
+        let mut offset = self.blocks_offset;
+        for (channel, section) in self.header.channel_sections.iter().enumerate() {
+            if mask.contains(channel) {
+                self.reader.seek(SeekFrom::Start(offset))?;
+                let mut signs = Vec::new();
+                (&mut self.reader).take(section.signs).read_to_end(&mut signs)?;
+                if signs.len() as u64 != section.signs {
+                    anyhow::bail!("ICF channel {} sign stream extends past end of file", channel);
+                }
+                let mut sink = IcfCodec::section_sink(&self.header, channel, &signs, sink);
+                let blocks = (&mut self.reader).take(section.length);
+                IcfCodec::stream_blocks(&mut serde_json::Deserializer::from_reader(blocks), &mut sink)?;
+            }
+            offset = section.signs.checked_add(section.length)
+                .and_then(|length| offset.checked_add(length))
+                .with_context(|| format!("ICF channel {} section is too long", channel))?;
+        }
+        Ok(())
+    }
+
+    pub fn decode(&mut self) -> Result<DynamicImage> {
+        Ok(self.decode_checked(&DecodeOptions::default())?.image)
+    }
+
+    /// Decode and verify as `IcfCodec::decode_checked` does, except that a
+    /// truncated payload fails even when tolerant
+    pub fn decode_checked(&mut self, options: &DecodeOptions) -> Result<DecodeOutcome> {
+        let codec = IcfCodec::new();
+        let header = self.header.clone();
+        codec.decode_streamed(header, options, |sink| self.stream_into(ChannelMask::ALL, sink))
+    }
+
+    /// Decode only the luma plane; see `IcfCodec::decode_luma`
+    pub fn decode_luma(&mut self) -> Result<GrayImage> {
+        let [luma, _, _] = self.decode_channels(ChannelMask::LUMA)?;
+        luma.context("ICF luma plane was not decoded")
+    }
+
+    /// Decode the channels in `mask`; see `IcfCodec::decode_channels`
+    pub fn decode_channels(&mut self, mask: ChannelMask) -> Result<[Option<GrayImage>; 3]> {
+        let codec = IcfCodec::new();
+        let header = self.header.clone();
+        codec.decode_channels_streamed(&header, mask, |sink| self.stream_into(mask, sink))
+    }
+}
+
+/// Image compression statistics
+#[derive(Debug, Clone)]
+pub struct ImageCompressionStats {
+    pub original_size: usize,
+    pub compressed_size: usize,
+    pub compression_ratio: f64,
+    pub savings_percent: f64,
+}
+
+impl std::fmt::Display for ImageCompressionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, 
+            "Original: {} bytes, Compressed: {} bytes, Ratio: {:.2}:1, Savings: {:.2}%",
+            self.original_size,
+            self.compressed_size,
+            self.compression_ratio,
+            self.savings_percent
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use image::{ImageBuffer, Rgb, Rgba, RgbaImage};
+    use crate::codecs::image::Anchor;
+    use crate::codecs::explain::Decision;
+
+    #[test]
+    fn test_icf_codec_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_image_path = temp_dir.path().join("test.png");
+        
+        // Create a simple test image
+        let img = ImageBuffer::from_fn(64, 64, |x, y| {
+            let intensity = ((x + y) % 256) as u8;
+            Rgb([intensity, intensity / 2, intensity / 4])
+        });
+        
+        img.save(&test_image_path).unwrap();
+        
+        let codec = IcfCodec::new();
+        
+        // Test different quality levels
+        for quality in [10, 50, 85, 95] {
+            let compressed = codec.encode(test_image_path.to_str().unwrap(), quality).unwrap();
+            let decompressed = codec.decode(&compressed).unwrap();
+            
+            // Check dimensions
+            assert_eq!(decompressed.width(), 64);
+            assert_eq!(decompressed.height(), 64);
+            
+            let stats = codec.get_stats(test_image_path.to_str().unwrap(), &compressed).unwrap();
+            println!("Quality {}: {}", quality, stats);
+            
+            // Higher quality should generally have larger file sizes
+            if quality > 10 {
+                // Basic sanity check that compression is working
+                assert!(compressed.len() < img.as_raw().len());
+            }
+        }
+    }
+
+    /// Encode a small gradient image and return the container bytes
+    fn encode_test_image(quality: u8) -> Vec<u8> {
+        let temp_dir = TempDir::new().unwrap();
+        let test_image_path = temp_dir.path().join("test.png");
+
+        let img = ImageBuffer::from_fn(32, 32, |x, y| {
+            let intensity = ((x * 5 + y * 3) % 256) as u8;
+            Rgb([intensity, 255 - intensity, intensity / 2])
+        });
+        img.save(&test_image_path).unwrap();
+
+        IcfCodec::new().encode(test_image_path.to_str().unwrap(), quality).unwrap()
+    }
+
+    /// Every compressed block of an ICF file, in file order
+    fn file_blocks(encoded: &[u8]) -> Vec<CompressedBlock> {
+        let mut blocks = Vec::new();
+        IcfReader::new(std::io::Cursor::new(encoded)).unwrap().for_each_block(|block| {
+            blocks.push(block);
+            Ok(())
+        }).unwrap();
+        blocks
+    }
+
+    fn gradient_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([(x * 3) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_reproducible_across_thread_pools() {
+        let img = gradient_image(72, 40);
+        let options = IcfEncodeOptions {
+            metadata: IcfMetadata {
+                tags: [("b", "2"), ("a", "1"), ("c", "3")]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            },
+            ..IcfEncodeOptions::with_quality(70)
+        };
+
+        let encodes: Vec<Vec<u8>> = [1, 4]
+            .into_iter()
+            .map(|threads| {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                pool.install(|| IcfCodec::new().encode_with_options(&img, &options).unwrap())
+            })
+            .collect();
+        assert_eq!(encodes[0], encodes[1]);
+
+        // Reproducible file encodes drop the source name and timestamp
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("secret-name.png");
+        img.save(&path).unwrap();
+        let from_file = IcfCodec::new()
+            .encode_file_with_options(path.to_str().unwrap(), &options)
+            .unwrap();
+        assert_eq!(from_file, encodes[0]);
+
+        let provenance = IcfEncodeOptions { reproducible: false, ..options.clone() };
+        let from_file = IcfCodec::new()
+            .encode_file_with_options(path.to_str().unwrap(), &provenance)
+            .unwrap();
+        let (header, _) = IcfCodec::new().parse_container(&from_file).unwrap();
+        let metadata = header.metadata.unwrap();
+        assert_eq!(metadata.source_name.as_deref(), Some("secret-name.png"));
+        assert!(metadata.created_at.is_some());
+    }
+
+    #[test]
+    fn test_strip_omits_metadata_section() {
+        let img = gradient_image(16, 16);
+        let mut options = IcfEncodeOptions {
+            reproducible: false,
+            ..IcfEncodeOptions::with_quality(80)
+        };
+        options.metadata.tags.insert("camera".to_string(), "test".to_string());
+
+        let codec = IcfCodec::new();
+        let with_metadata = codec.encode_with_options(&img, &options).unwrap();
+        let (header, _) = codec.parse_container(&with_metadata).unwrap();
+        assert_eq!(header.metadata.unwrap().tags["camera"], "test");
+
+        options.strip_metadata = true;
+        let stripped = codec.encode_with_options(&img, &options).unwrap();
+        let (header, _) = codec.parse_container(&stripped).unwrap();
+        assert!(header.metadata.is_none());
+        assert!(!String::from_utf8_lossy(&stripped).contains("metadata"));
+        assert_eq!(codec.decode(&stripped).unwrap().to_rgb8(), codec.decode(&with_metadata).unwrap().to_rgb8());
+    }
+
+    #[test]
+    fn test_profiles_recorded_in_header() {
+        let img = gradient_image(45, 37);
+        let codec = IcfCodec::new();
+
+        for profile in crate::codecs::image::PROFILES {
+            let encoded = codec.encode_with_options(&img, &IcfEncodeOptions::profile(profile)).unwrap();
+            let (header, _) = codec.parse_container(&encoded).unwrap();
+            assert_eq!(header.profile.as_deref(), Some(profile.name));
+            assert_eq!(header.quality, profile.quality);
+            assert_eq!(header.chroma_subsampling, profile.chroma_subsampling);
+            assert_eq!(header.color_space, profile.color_space);
+            assert_eq!(header.quantization, profile.quantization);
+            assert_eq!(header.tile_size, profile.tile_size);
+
+            let decoded = codec.decode(&encoded).unwrap().to_rgb8();
+            assert_eq!(decoded.dimensions(), (45, 37));
+            let original = img.to_rgb8();
+            let max_error = original.as_raw().iter()
+                .zip(decoded.as_raw())
+                .map(|(&a, &b)| (a as i32 - b as i32).abs())
+                .max()
+                .unwrap();
+            assert!(max_error < 64, "{}: max error {}", profile.name, max_error);
+        }
+    }
+
+    #[test]
+    fn test_override_wins_over_profile() {
+        let img = gradient_image(40, 24);
+        let codec = IcfCodec::new();
+        let options = IcfEncodeOptions {
+            quality: 30,
+            chroma_subsampling: ChromaSubsampling::S444.into(),
+            tile_size: Some(16),
+            ..IcfEncodeOptions::profile(&IcfProfile::THUMBNAIL)
+        };
+
+        let encoded = codec.encode_with_options(&img, &options).unwrap();
+        let (header, _) = codec.parse_container(&encoded).unwrap();
+        assert_eq!(header.profile.as_deref(), Some("thumbnail"));
+        assert_eq!(header.quality, 30);
+        assert_eq!(header.chroma_subsampling, ChromaSubsampling::S444);
+        assert_eq!(header.tile_size, Some(16));
+        // Untouched fields still come from the profile
+        assert_eq!(header.color_space, IcfProfile::THUMBNAIL.color_space);
+        assert_eq!(codec.decode(&encoded).unwrap().to_rgb8().dimensions(), (40, 24));
+
+        let invalid = IcfEncodeOptions { tile_size: Some(24), ..options };
+        assert!(codec.encode_with_options(&img, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_tiles_restart_dc_prediction() {
+        let img = gradient_image(48, 32);
+        let codec = IcfCodec::new();
+        let options = IcfEncodeOptions { tile_size: Some(16), ..IcfEncodeOptions::with_quality(90) };
+
+        let tiled = codec.encode_with_options(&img, &options).unwrap();
+        let untiled = codec.encode_image(&img, 90).unwrap();
+        assert_eq!(codec.decode(&tiled).unwrap().to_rgb8(), codec.decode(&untiled).unwrap().to_rgb8());
+
+        // The first luma block of every 2x2-block tile carries an absolute DC
+        let blocks = file_blocks(&tiled);
+        let untiled_blocks = file_blocks(&untiled);
+        let dc_at = |blocks: &[CompressedBlock], x: u16, y: u16| {
+            blocks.iter().find(|b| b.channel == 0 && b.x == x && b.y == y).unwrap().dc_coefficient
+        };
+        let absolute_dc = dc_at(&untiled_blocks, 0, 0);
+        assert_eq!(dc_at(&blocks, 0, 0), absolute_dc);
+        assert_ne!(dc_at(&blocks, 2, 0), dc_at(&untiled_blocks, 2, 0));
+    }
+
+    #[test]
+    fn test_decode_tolerates_block_order_but_not_duplicates() {
+        let img = gradient_image(48, 32);
+        let codec = IcfCodec::new();
+        let options = IcfEncodeOptions { tile_size: Some(16), ..IcfEncodeOptions::with_quality(85) };
+        let encoded = codec.encode_with_options(&img, &options).unwrap();
+
+        // Out-of-order blocks can only be written as one interleaved array
+        let mut header = codec.parse_container(&encoded).unwrap().0;
+        header.channel_sections.clear();
+        let mut blocks = file_blocks(&encoded);
+        blocks.reverse();
+        let reversed = codec.create_container(header.clone(), codec.serialize_blocks(&blocks).unwrap(), false).unwrap();
+        assert_eq!(codec.decode(&reversed).unwrap().to_rgb8(), codec.decode(&encoded).unwrap().to_rgb8());
+
+        blocks.push(blocks[0].clone());
+        let duplicated = codec.create_container(header, codec.serialize_blocks(&blocks).unwrap(), false).unwrap();
+        let error = codec.decode(&duplicated).unwrap_err();
+        assert!(format!("{:#}", error).contains("appears twice"));
+    }
+
+    #[test]
+    fn test_fixed_point_matches_float_decode() {
+        let codec = IcfCodec::new();
+        let noisy = DynamicImage::ImageRgb8(ImageBuffer::from_fn(37, 29, |x, y| {
+            let hash = (x * 7919 + y * 104729) ^ (x * y * 31);
+            Rgb([(hash % 256) as u8, (hash / 7 % 256) as u8, (x * 6 + y) as u8])
+        }));
+        let fixed_point = DecodeOptions { fixed_point: true, ..Default::default() };
+
+        for img in [gradient_image(48, 32), noisy] {
+            for (quality, profile) in [(95, IcfProfile::SCREENSHOT), (40, IcfProfile::PHOTO), (75, IcfProfile::THUMBNAIL)] {
+                let options = IcfEncodeOptions { quality, ..IcfEncodeOptions::profile(&profile) };
+                let encoded = codec.encode_with_options(&img, &options).unwrap();
+
+                let float = codec.decode(&encoded).unwrap().to_rgb8();
+                let fixed = codec.decode_checked(&encoded, &fixed_point).unwrap().image.to_rgb8();
+                assert_eq!(float.dimensions(), fixed.dimensions());
+                let max_error = float.as_raw().iter().zip(fixed.as_raw())
+                    .map(|(&a, &b)| a.abs_diff(b))
+                    .max()
+                    .unwrap();
+                assert!(max_error <= 1, "{} at quality {}: off by {}", profile.name, quality, max_error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_into_strided_buffer() {
+        let codec = IcfCodec::new();
+        let encoded = codec.encode_image(&gradient_image(21, 13), 85).unwrap();
+        let expected = codec.decode(&encoded).unwrap().to_rgb8();
+
+        // Rows padded to 80 bytes, with a sentinel in the padding
+        let stride = 80;
+        let mut buffer = vec![0xAB; stride * 14];
+        assert_eq!(codec.decode_into(&encoded, &mut buffer, stride).unwrap(), (21, 13));
+        for y in 0..13 {
+            let row = &buffer[y * stride..(y + 1) * stride];
+            assert_eq!(&row[..63], &expected.as_raw()[y * 63..(y + 1) * 63], "row {}", y);
+            assert!(row[63..].iter().all(|&byte| byte == 0xAB), "padding of row {} overwritten", y);
+        }
+        assert!(buffer[13 * stride..].iter().all(|&byte| byte == 0xAB));
+
+        // The last row needn't be padded, but nothing smaller will do
+        let mut exact = vec![0; stride * 12 + 63];
+        assert!(codec.decode_into(&encoded, &mut exact, stride).is_ok());
+        let error = codec.decode_into(&encoded, &mut exact[..stride * 12 + 62], stride).unwrap_err();
+        assert!(error.to_string().contains("Output buffer too small"), "{}", error);
+        assert!(codec.decode_into(&encoded, &mut buffer, 62).is_err());
+    }
+
+    /// Rebuild `encoded` with all blocks in one interleaved array and no channel sections
+    fn without_channel_sections(encoded: &[u8]) -> Vec<u8> {
+        let codec = IcfCodec::new();
+        let mut header = codec.parse_container(encoded).unwrap().0;
+        header.channel_sections.clear();
+        codec.create_container(header, codec.serialize_blocks(&file_blocks(encoded)).unwrap(), false).unwrap()
+    }
+
+    #[test]
+    fn test_decode_luma_matches_full_decode() {
+        let codec = IcfCodec::new();
+        for (color_space, subsampling) in [
+            (IcfColorSpace::YCoCg, ChromaSubsampling::S444),
+            (IcfColorSpace::YCbCr, ChromaSubsampling::S420),
+        ] {
+            let options = IcfEncodeOptions { color_space, chroma_subsampling: subsampling.into(), ..IcfEncodeOptions::with_quality(80) };
+            let encoded = codec.encode_with_options(&gradient_image(45, 30), &options).unwrap();
+            assert_eq!(codec.parse_container(&encoded).unwrap().0.channel_sections.len(), 3);
+
+            let luma = codec.decode_luma(&encoded).unwrap();
+            let full = codec.decode(&encoded).unwrap().to_rgb8();
+            assert_eq!(luma.dimensions(), full.dimensions());
+            for (gray, rgb) in luma.pixels().zip(full.pixels()) {
+                let (y, _, _) = color_space.from_rgb(rgb[0] as f64 / 255.0, rgb[1] as f64 / 255.0, rgb[2] as f64 / 255.0);
+                assert!((gray[0] as f64 - y * 255.0).abs() <= 2.0, "{:?}: {} vs {:.1}", color_space, gray[0], y * 255.0);
+            }
+
+            // Files written before channel sections decode the same, just slower
+            assert_eq!(codec.decode_luma(&without_channel_sections(&encoded)).unwrap(), luma);
+
+            let [all_luma, c1, c2] = codec.decode_channels(&encoded, ChannelMask::ALL).unwrap();
+            assert_eq!(all_luma.unwrap(), luma);
+            assert!(c1.is_some() && c2.is_some());
+            let [no_luma, c1, _] = codec.decode_channels(&encoded, ChannelMask::channel(1)).unwrap();
+            assert!(no_luma.is_none());
+            assert_eq!(c1.unwrap().dimensions(), (45, 30));
+        }
+    }
+
+    #[test]
+    fn test_coefficients_match_decoded_blocks() {
+        let codec = IcfCodec::new();
+        let encoded = codec.encode_with_options(&gradient_image(45, 30), &IcfEncodeOptions::with_quality(80)).unwrap();
+        let planes = codec.coefficients(&encoded).unwrap();
+        let luma_plane = &planes.channels[0];
+        assert_eq!((luma_plane.width(), luma_plane.height()), (48, 32));
+        assert_eq!(luma_plane.block(0, 0).unwrap()[0][0], luma_plane.quantized[0]);
+
+        // DC is 8x the block mean with an orthonormal DCT; absolute DCs show prediction was undone
+        let luma = codec.decode_luma(&encoded).unwrap();
+        for (x, y) in [(0, 0), (1, 0), (4, 2)] {
+            let dc = luma_plane.dequantized_block(x, y).unwrap()[0][0];
+            let sum: f64 = (0..8).flat_map(|row| (0..8).map(move |col| (x * 8 + col, y * 8 + row)))
+                .map(|(px, py)| luma.get_pixel(px as u32, py as u32)[0] as f64 - 128.0)
+                .sum();
+            assert!((dc / 8.0 - sum / 64.0).abs() <= 1.0, "block ({}, {}): DC {} vs mean {}", x, y, dc / 8.0, sum / 64.0);
+        }
+
+        // Minimal NPY reader: magic, version, header length, header dict, data
+        let npy = luma_plane.to_npy(false).unwrap();
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '<i2'") && header.contains("'fortran_order': False"));
+        assert!(header.contains("'shape': (32, 48)"), "{}", header);
+        let data = &npy[10 + header_len..];
+        assert_eq!(data.len(), 32 * 48 * 2);
+        let values: Vec<i16> = data.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(values, luma_plane.quantized);
+    }
+
+    /// Smooth shading, edges and sensor-like noise, standing in for a photograph
+    fn photo_like_image(width: u32, height: u32) -> DynamicImage {
+        let mut state = 0x9e37_79b9u32;
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let (fx, fy) = (x as f64, y as f64);
+            let shading = 110.0 + 60.0 * (fx / 37.0).sin() * (fy / 53.0).cos() + 0.3 * fy;
+            let edge = if (fx - 90.0).powi(2) + (fy - 70.0).powi(2) < 900.0 { 45.0 } else { 0.0 };
+            let noise = (state % 13) as f64 - 6.0;
+            let luma = shading + edge + noise;
+            Rgb([(luma + 20.0) as u8, luma as u8, (luma * 0.8 + 0.2 * fx) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_sign_contexts_shrink_file_without_changing_pixels() {
+        let codec = IcfCodec::new();
+        let img = photo_like_image(192, 144);
+        let plain = codec.encode_with_options(&img, &IcfEncodeOptions::with_quality(85)).unwrap();
+        let signed = codec.encode_with_options(&img, &IcfEncodeOptions { sign_contexts: true, ..IcfEncodeOptions::with_quality(85) }).unwrap();
 
-        for block_y in 0..blocks_y {
-            for block_x in 0..blocks_x {
-                for y in 0..8 {
-                    for x in 0..8 {
-                        let img_x = block_x * 8 + x;
-                        let img_y = block_y * 8 + y;
-                        
-                        if img_x < width as usize && img_y < height as usize {
-                            // Get YCoCg values and denormalize
-                            let yval = (ycocg_blocks[0][block_y][block_x][y][x] + 128.0) / 255.0;
-                            let co = ycocg_blocks[1][block_y][block_x][y][x] / 255.0;
-                            let cg = ycocg_blocks[2][block_y][block_x][y][x] / 255.0;
-                            
-                            // Convert back to RGB
-                            let (r, g, b) = ColorSpace::ycocg_to_rgb(yval, co, cg);
-                            
-                            // Clamp to valid range
-                            let r = (r * 255.0).round().max(0.0).min(255.0) as u8;
-                            let g = (g * 255.0).round().max(0.0).min(255.0) as u8;
-                            let b = (b * 255.0).round().max(0.0).min(255.0) as u8;
-                            
-                            rgb_img.put_pixel(img_x as u32, img_y as u32, Rgb([r, g, b]));
-                        }
-                    }
-                }
+        let reduction = 1.0 - signed.len() as f64 / plain.len() as f64;
+        assert!(reduction > 0.01, "{} -> {} bytes", plain.len(), signed.len());
+        assert!(codec.parse_container(&signed).unwrap().0.sign_contexts);
+        assert!(!String::from_utf8_lossy(&plain).contains("sign"));
+
+        let decoded = codec.decode(&signed).unwrap();
+        assert_eq!(decoded, codec.decode(&plain).unwrap());
+        assert_eq!(codec.coefficients(&signed).unwrap(), codec.coefficients(&plain).unwrap());
+        assert_eq!(IcfReader::new(std::io::Cursor::new(&signed)).unwrap().decode().unwrap(), decoded);
+        assert_eq!(codec.decode_partial(&signed).unwrap().image, decoded);
+    }
+
+    #[test]
+    fn test_auto_subsampling_follows_chroma_detail() {
+        // Red glyph-like strokes, one or two pixels wide, on white
+        let screenshot = DynamicImage::ImageRgb8(ImageBuffer::from_fn(160, 96, |x, y| {
+            let line = y % 16;
+            let stroke = (2..12).contains(&line) && (x % 7 < 2 || (line == 6 && x % 23 < 14));
+            if stroke && x % 60 < 50 { Rgb([210, 20, 30]) } else { Rgb([255, 255, 255]) }
+        }));
+        let codec = IcfCodec::new();
+        let options = IcfEncodeOptions { chroma_subsampling: SubsamplingMode::Auto, ..IcfEncodeOptions::with_quality(90) };
+        for (img, expected) in [(&screenshot, ChromaSubsampling::S444), (&photo_like_image(160, 96), ChromaSubsampling::S420)] {
+            let encoded = codec.encode_with_options(img, &options).unwrap();
+            assert_eq!(codec.parse_container(&encoded).unwrap().0.chroma_subsampling, expected);
+
+            let fixed = IcfEncodeOptions { chroma_subsampling: expected.into(), ..options.clone() };
+            assert_eq!(encoded, codec.encode_with_options(img, &fixed).unwrap());
+            let decoded = codec.decode(&encoded).unwrap().to_rgb8();
+            let max_error = decoded.as_raw().iter().zip(img.to_rgb8().as_raw()).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+            assert!(max_error < 64, "{}: max error {}", expected, max_error);
+        }
+    }
+
+    fn jpeg_bytes(img: &DynamicImage, quality: u8) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality).encode_image(img).unwrap();
+        jpeg
+    }
+
+    #[test]
+    fn test_transcode_from_jpeg_matches_jpeg_decode() {
+        let codec = IcfCodec::new();
+        let color = photo_like_image(61, 43);
+        let gray = DynamicImage::ImageLuma8(color.to_luma8());
+        for (img, quality) in [(&color, 80), (&color, 30), (&gray, 90)] {
+            let jpeg = jpeg_bytes(img, quality);
+            let icf = codec.transcode_from_jpeg(&jpeg).unwrap();
+            let (header, _) = codec.parse_container(&icf).unwrap();
+            assert_eq!(header.color_space, IcfColorSpace::YCbCr);
+            assert_eq!(header.quantization, QuantTableKind::Jpeg);
+            assert!(header.quality.abs_diff(quality) <= 2, "estimated quality {} for {}", header.quality, quality);
+
+            // The JPEG decoder's integer IDCT and color conversion round differently
+            let outcome = codec.decode_checked(&icf, &DecodeOptions { strict_checksum: true, ..Default::default() }).unwrap();
+            let expected = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+            let decoded = outcome.image.to_rgb8();
+            let differences: Vec<u8> = decoded.as_raw().iter().zip(expected.as_raw()).map(|(a, b)| a.abs_diff(*b)).collect();
+            let mean = differences.iter().map(|&d| d as f64).sum::<f64>() / differences.len() as f64;
+            assert!(differences.iter().all(|&d| d <= 2) && mean < 1.0, "max {:?}, mean {}", differences.iter().max(), mean);
+        }
+    }
+
+    #[test]
+    fn test_transcode_rejects_unsupported_jpegs() {
+        let codec = IcfCodec::new();
+        let mut jpeg = jpeg_bytes(&photo_like_image(16, 16), 75);
+        let sof = jpeg.windows(2).position(|marker| marker == [0xFF, 0xC0]).unwrap();
+        jpeg[sof + 1] = 0xC2;
+        let error = codec.transcode_from_jpeg(&jpeg).unwrap_err().to_string();
+        assert!(error.contains("Progressive"), "{}", error);
+
+        assert!(codec.transcode_from_jpeg(b"\x89PNG\r\n").is_err());
+        let truncated = jpeg_bytes(&photo_like_image(16, 16), 75);
+        assert!(codec.transcode_from_jpeg(&truncated[..truncated.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_plane_pipeline_matches_golden_output() {
+        // Hashes of what the nested-Vec block pipeline produced, updated when
+        // DCT and quantization tables were rounded to fixed precision; odd
+        // sizes exercise edge padding and chroma rounding
+        let codec = IcfCodec::new();
+        let img = photo_like_image(61, 43);
+        let hash = |bytes: &[u8]| format!("{:x}", Sha256::digest(bytes))[..16].to_string();
+        let cases = [
+            IcfEncodeOptions::with_quality(85),
+            IcfEncodeOptions {
+                chroma_subsampling: ChromaSubsampling::S420.into(),
+                color_space: IcfColorSpace::YCbCr,
+                quantization: QuantTableKind::Optimized,
+                tile_size: Some(16),
+                ..IcfEncodeOptions::with_quality(60)
+            },
+        ];
+        for (options, expected) in cases.iter().zip(GOLDEN) {
+            let encoded = codec.encode_with_options(&img, options).unwrap();
+            let mut truncated = encoded.clone();
+            truncated.truncate(encoded.len() * 3 / 4);
+            let actual = [
+                hash(&encoded),
+                hash(codec.decode(&encoded).unwrap().to_rgb8().as_raw()),
+                hash(codec.decode_luma(&encoded).unwrap().as_raw()),
+                hash(IcfReader::new(std::io::Cursor::new(&encoded)).unwrap().decode().unwrap().to_rgb8().as_raw()),
+                hash(codec.decode_partial(&truncated).unwrap().image.to_rgb8().as_raw()),
+            ];
+            assert_eq!(actual, expected.map(String::from), "{:?}", options);
+        }
+    }
+
+    /// File, decode, luma decode, reader decode and partial decode hashes
+    const GOLDEN: [[&str; 5]; 2] = [
+        ["47c8efe982813eeb", "c7c66afc9c464447", "f99f0a94da7d98a7", "c7c66afc9c464447", "c6daab01a9b912dc"],
+        ["cd3d5a32767097cd", "2480ca2ff3e3cc9f", "d561fdf3f5b1bb61", "2480ca2ff3e3cc9f", "4aa564680af8af4b"],
+    ];
+
+    #[test]
+    fn test_peek_reads_dimensions_from_prefix() {
+        let encoded = IcfCodec::new().encode_with_options(&gradient_image(45, 30), &IcfEncodeOptions::with_quality(70)).unwrap();
+        let header_end = 8 + u32::from_le_bytes(encoded[4..8].try_into().unwrap()) as usize;
+        let threshold = header_end.min(crate::codecs::peek::PEEK_LIMIT);
+
+        for length in 0..threshold {
+            match IcfCodec::peek(&encoded[..length]).unwrap() {
+                PeekResult::NeedMoreData(needed) => assert!(needed > length && needed <= threshold),
+                PeekResult::Ready(_) => panic!("peek succeeded with only {} bytes", length),
             }
         }
+        let peeked = IcfCodec::peek(&encoded[..threshold]).unwrap().ready().unwrap();
+        assert_eq!((peeked.width, peeked.height, peeked.channels, peeked.quality), (45, 30, 3, 70));
+        assert_eq!(peeked.checksum_kind(), ChecksumKind::Sha256);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_decode_matches_and_rejects_truncation() {
+        let codec = IcfCodec::new();
+        let planar = codec.encode_with_options(&gradient_image(45, 30), &IcfEncodeOptions::with_quality(80)).unwrap();
+        let dir = TempDir::new().unwrap();
+        for (name, encoded) in [("interleaved.icf", without_channel_sections(&planar)), ("planar.icf", planar)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, &encoded).unwrap();
+            assert_eq!(codec.decode_mmap(&path).unwrap(), codec.decode(&std::fs::read(&path).unwrap()).unwrap());
 
-        rgb_img
+            for cut in [0, 6, 20, encoded.len() - 1] {
+                std::fs::write(&path, &encoded[..cut]).unwrap();
+                let error = codec.decode_mmap(&path).unwrap_err();
+                assert!(
+                    matches!(error.downcast_ref::<IcfError>(), Some(IcfError::Truncated { available, .. }) if *available == cut as u64),
+                    "{} cut to {}: {:#}", name, cut, error
+                );
+            }
+        }
     }
 
-    /// Serialize compressed blocks to binary data
-    fn serialize_blocks(&self, blocks: &[CompressedBlock]) -> Result<Vec<u8>> {
-        serde_json::to_vec(blocks)
-            .context("Failed to serialize compressed blocks")
+    #[test]
+    fn test_partial_decode_of_truncated_file() {
+        let codec = IcfCodec::new();
+        let planar = codec.encode_with_options(&gradient_image(64, 48), &IcfEncodeOptions::with_quality(80)).unwrap();
+        let interleaved = without_channel_sections(&planar);
+
+        for encoded in [planar, interleaved] {
+            let intact = codec.decode_partial(&encoded).unwrap();
+            assert!(intact.is_complete());
+            assert_eq!(intact.coverage, 100.0);
+            assert!(intact.coverage_mask.pixels().all(|pixel| pixel[0] == 255));
+            assert_eq!(intact.image, codec.decode(&encoded).unwrap());
+
+            let payload_start = encoded.len() - codec.parse_container(&encoded).unwrap().1.len();
+            for fraction in [0.3, 0.8] {
+                let cut = (encoded.len() as f64 * fraction) as usize;
+                let partial = codec.decode_partial(&encoded[..cut]).unwrap();
+                assert_eq!((partial.image.width(), partial.image.height()), (64, 48));
+                assert!(partial.stop_reason.is_some());
+                assert!(codec.decode(&encoded[..cut]).is_err());
+
+                let payload_fraction = (cut - payload_start) as f64 * 100.0 / (encoded.len() - payload_start) as f64;
+                assert!((partial.coverage - payload_fraction).abs() < 20.0,
+                    "{:.1}% of blocks decoded from {:.1}% of the payload", partial.coverage, payload_fraction);
+                // Blocks are coded top to bottom, so the first row survives and the last doesn't
+                assert!(partial.coverage_mask.get_pixel(0, 0)[0] > 0);
+                assert!(partial.coverage_mask.get_pixel(63, 47)[0] < 255);
+            }
+        }
     }
 
-    /// Deserialize compressed blocks from binary data
-    fn deserialize_blocks(&self, data: &[u8]) -> Result<Vec<CompressedBlock>> {
-        serde_json::from_slice(data)
-            .context("Failed to deserialize compressed blocks")
+    #[test]
+    fn test_luma_decode_skips_chroma_sections() {
+        /// Counts the bytes read through it
+        struct CountingReader<R> {
+            inner: R,
+            read: std::rc::Rc<std::cell::Cell<u64>>,
+        }
+
+        impl<R: Read> Read for CountingReader<R> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.inner.read(buf)?;
+                self.read.set(self.read.get() + n as u64);
+                Ok(n)
+            }
+        }
+
+        impl<R: Seek> Seek for CountingReader<R> {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                self.inner.seek(pos)
+            }
+        }
+
+        let bytes_read = |encoded: &[u8], luma_only: bool| {
+            let read = std::rc::Rc::new(std::cell::Cell::new(0));
+            let mut reader = IcfReader::new(CountingReader { inner: std::io::Cursor::new(encoded), read: read.clone() }).unwrap();
+            if luma_only {
+                reader.decode_luma().unwrap();
+            } else {
+                reader.decode().unwrap();
+            }
+            read.get()
+        };
+
+        let codec = IcfCodec::new();
+        let encoded = codec.encode_image(&gradient_image(64, 64), 90).unwrap();
+        let (header, payload) = codec.parse_container(&encoded).unwrap();
+        let header_end = (encoded.len() - payload.len()) as u64;
+        let luma_section = header.channel_sections[0].length;
+
+        let luma_only = bytes_read(&encoded, true);
+        assert!(luma_only <= header_end + luma_section, "read {} of {} bytes", luma_only, encoded.len());
+        let full = bytes_read(&encoded, false);
+        assert_eq!(full, encoded.len() as u64);
+
+        // Without sections every block has to be scanned
+        let legacy = without_channel_sections(&encoded);
+        let legacy_luma = bytes_read(&legacy, true);
+        assert_eq!(legacy_luma, legacy.len() as u64);
     }
 
-    /// Create ICF container
-    fn create_container(&self, mut header: IcfHeader, compressed_data: Vec<u8>) -> Result<Vec<u8>> {
-        header.compressed_size = compressed_data.len() as u64;
-        
-        let header_json = serde_json::to_vec(&header)
-            .context("Failed to serialize ICF header")?;
-        
-        let mut container = Vec::new();
-        container.extend_from_slice(Self::MAGIC.as_bytes());
-        container.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
-        container.extend_from_slice(&header_json);
-        container.extend_from_slice(&compressed_data);
+    #[test]
+    fn test_parse_layout_covers_file() {
+        let codec = IcfCodec::new();
+        let encoded = encode_test_image(75);
+        let layout = codec.parse_layout(&encoded).unwrap();
 
-        Ok(container)
+        crate::codecs::layout::check_coverage(&layout.regions, encoded.len() as u64).unwrap();
+        let names: Vec<&str> = layout.regions.iter().map(|region| region.name.as_str()).collect();
+        assert_eq!(names, ["magic", "header_length", "header", "blocks"]);
+        assert_eq!(layout.trailing_size, 0);
+        assert_eq!(layout.blocks_size, layout.header.compressed_size);
+
+        let mut padded = encoded.clone();
+        padded.extend_from_slice(&[0; 3]);
+        let layout = codec.parse_layout(&padded).unwrap();
+        assert_eq!(layout.region("trailing").unwrap().length, 3);
+        crate::codecs::layout::check_coverage(&layout.regions, padded.len() as u64).unwrap();
+
+        assert!(codec.parse_layout(&encoded[..encoded.len() - 1]).is_err());
     }
 
-    /// Parse ICF container
-    pub fn parse_container(&self, icf_data: &[u8]) -> Result<(IcfHeader, Vec<u8>)> {
-        if icf_data.len() < 8 {
-            anyhow::bail!("Invalid ICF file: too small");
+    #[test]
+    fn test_planar_f32_matches_u8_path() {
+        let codec = IcfCodec::new();
+        let img = photo_like_image(40, 24);
+        let rgb = img.to_rgb8();
+        let options = IcfEncodeOptions::with_quality(90);
+        let from_u8 = codec.encode_with_options(&img, &options).unwrap();
+
+        let planar = PlanarF32 {
+            planes: [0, 1, 2].map(|channel| rgb.pixels().map(|pixel| pixel[channel] as f32 / 255.0).collect()),
+            width: 40,
+            height: 24,
+            layout: PlanarLayout::Rgb,
+        };
+        let from_f32 = codec.encode_planar_f32(&planar, &options).unwrap();
+        assert_eq!(codec.parse_container(&from_f32).unwrap().0.checksum, codec.parse_container(&from_u8).unwrap().0.checksum);
+        let decoded = codec.decode(&from_u8).unwrap().to_rgb8();
+        let max_diff = |a: &RgbImage, b: &RgbImage| a.as_raw().iter().zip(b.as_raw()).map(|(&x, &y)| x.abs_diff(y)).max().unwrap();
+        assert!(max_diff(&codec.decode(&from_f32).unwrap().to_rgb8(), &decoded) <= 1);
+
+        // The f32 decode is the u8 decode before rounding
+        let output = codec.decode_planar_f32(&from_u8, PlanarLayout::Rgb).unwrap();
+        for (i, pixel) in decoded.pixels().enumerate() {
+            for channel in 0..3 {
+                assert!((output.planes[channel][i] * 255.0 - pixel[channel] as f32).abs() <= 0.5 + 1e-3);
+            }
         }
 
-        let magic = std::str::from_utf8(&icf_data[0..4])
-            .context("Invalid ICF magic")?;
-        if magic != Self::MAGIC {
-            anyhow::bail!("Invalid ICF magic number");
+        // Luma/chroma planes survive a round trip through the encoder
+        let coded = codec.decode_planar_f32(&from_u8, PlanarLayout::LumaChroma(IcfColorSpace::YCoCg)).unwrap();
+        let again = codec.encode_planar_f32(&coded, &IcfEncodeOptions::with_quality(100)).unwrap();
+        let round_trip = codec.decode_planar_f32(&again, PlanarLayout::LumaChroma(IcfColorSpace::YCoCg)).unwrap();
+        for (before, after) in coded.planes.iter().zip(&round_trip.planes) {
+            assert!(before.iter().zip(after).all(|(a, b)| (a - b).abs() < 0.02));
         }
+    }
 
-        let header_size = u32::from_le_bytes([
-            icf_data[4], icf_data[5], icf_data[6], icf_data[7]
-        ]) as usize;
+    #[test]
+    fn test_planar_f32_clamps_out_of_range_input() {
+        let codec = IcfCodec::new();
+        let wild = [f32::NAN, -1.0, 2.0, f32::INFINITY, f32::NEG_INFINITY, 0.25];
+        let clamped = [0.0, 0.0, 1.0, 1.0, 0.0, 0.25];
+        let planes = |samples: &[f32]| [0, 1, 2].map(|shift| (0..64).map(|i| samples[(i + shift) % samples.len()]).collect());
+        let planar = |samples: &[f32], layout| PlanarF32 { planes: planes(samples), width: 8, height: 8, layout };
 
-        if icf_data.len() < 8 + header_size {
-            anyhow::bail!("Invalid ICF file: header size mismatch");
+        for layout in [PlanarLayout::Rgb, PlanarLayout::LumaChroma(IcfColorSpace::YCbCr)] {
+            let options = IcfEncodeOptions::default();
+            let encoded = codec.encode_planar_f32(&planar(&wild, layout), &options).unwrap();
+            assert_eq!(encoded, codec.encode_planar_f32(&planar(&clamped, layout), &options).unwrap());
+            let decoded = codec.decode_planar_f32(&encoded, layout).unwrap();
+            assert!(decoded.planes.iter().flatten().all(|sample| (0.0..=1.0).contains(sample)));
         }
 
-        let header_data = &icf_data[8..8 + header_size];
-        let header: IcfHeader = serde_json::from_slice(header_data)
-            .context("Failed to parse ICF header")?;
+        let mut short = planar(&clamped, PlanarLayout::Rgb);
+        short.planes[2].pop();
+        assert!(codec.encode_planar_f32(&short, &IcfEncodeOptions::default()).is_err());
+    }
 
-        let compressed_data = icf_data[8 + header_size..].to_vec();
+    #[test]
+    fn test_overlay_only_touches_covered_blocks() {
+        let codec = IcfCodec::new();
+        let img = gradient_image(64, 64);
+        let mut watermark = Overlay::new(RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255])), Anchor::BottomRight, 0.5);
+        watermark.name = "logo.png".to_string();
+        let options = IcfEncodeOptions { overlay: Some(watermark.clone()), ..IcfEncodeOptions::with_quality(90) };
+        let plain = codec.encode_with_options(&img, &IcfEncodeOptions::with_quality(90)).unwrap();
+        let marked = codec.encode_with_options(&img, &options).unwrap();
 
-        Ok((header, compressed_data))
+        let (before, after) = (codec.coefficients(&plain).unwrap(), codec.coefficients(&marked).unwrap());
+        for (before, after) in before.channels.iter().zip(&after.channels) {
+            for (block_y, block_x) in (0..8).flat_map(|y| (0..8).map(move |x| (y, x))) {
+                let block = |plane: &CoefficientPlane| -> Vec<i16> {
+                    (0..8).flat_map(|row| {
+                        let start = (block_y * 8 + row) * plane.width() + block_x * 8;
+                        plane.quantized[start..start + 8].to_vec()
+                    }).collect()
+                };
+                let covered = block_x >= 6 && block_y >= 6;
+                assert_eq!(block(before) != block(after), covered, "block {},{}", block_x, block_y);
+            }
+        }
+
+        // Covered pixels move halfway to red
+        let (plain, decoded) = (codec.decode(&plain).unwrap().to_rgb8(), codec.decode(&marked).unwrap().to_rgb8());
+        let (source, blended) = (plain.get_pixel(56, 56), decoded.get_pixel(56, 56));
+        assert!(blended[0] > source[0] + 40 && blended[1] < source[1], "{:?} -> {:?}", source, blended);
+
+        let recorded = codec.metadata(&marked).unwrap().unwrap().overlay.unwrap();
+        assert_eq!(recorded, watermark.record());
+        assert_eq!(recorded.name, "logo.png");
+        assert_eq!(recorded.sha256.len(), 64);
+        assert!(codec.encode_with_options(&img, &IcfEncodeOptions { strip_metadata: true, ..options.clone() })
+            .map(|encoded| codec.metadata(&encoded).unwrap().is_none()).unwrap());
+        watermark.opacity = 1.5;
+        assert!(codec.encode_with_options(&img, &IcfEncodeOptions { overlay: Some(watermark), ..options }).is_err());
     }
 
-    /// Get compression statistics
-    pub fn get_stats(&self, original_path: &str, icf_data: &[u8]) -> Result<ImageCompressionStats> {
-        let original_img = image::open(original_path)?;
-        let original_size = original_img.as_bytes().len();
-        let compressed_size = icf_data.len();
-        let compression_ratio = original_size as f64 / compressed_size as f64;
-        let savings_percent = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
+    #[test]
+    fn test_explain_trace_follows_auto_subsampling() {
+        let codec = IcfCodec::new();
+        let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(32, 32, Rgb([40, 120, 200])));
+        let options = IcfEncodeOptions { chroma_subsampling: SubsamplingMode::Auto, ..IcfEncodeOptions::profile(IcfProfile::by_name("photo").unwrap()) };
+        let (encoded, trace) = codec.encode_with_trace(&flat, &options).unwrap();
+        assert_eq!(encoded, codec.encode_with_options(&flat, &options).unwrap());
+        let steps: Vec<&str> = trace.decisions.iter().map(|decision| decision.step()).collect();
+        assert_eq!(steps, ["icf.profile", "icf.subsampling", "icf.subsampling", "icf.quantization"]);
+        assert!(matches!(trace.decisions[1], Decision::Threshold { value, exceeded: false, .. } if value == 0.0));
+        assert_eq!(trace.choices("icf.subsampling"), ["4:2:0"]);
 
-        Ok(ImageCompressionStats {
-            original_size,
-            compressed_size,
-            compression_ratio,
-            savings_percent,
-        })
+        let fixed = IcfEncodeOptions { chroma_subsampling: ChromaSubsampling::S444.into(), ..options };
+        let (_, trace) = codec.encode_with_trace(&flat, &fixed).unwrap();
+        assert_eq!(trace.decisions[1], Decision::Chosen { step: "icf.subsampling", option: "4:4:4".to_string(), reason: "requested".to_string() });
     }
-}
 
-/// Image compression statistics
-#[derive(Debug, Clone)]
-pub struct ImageCompressionStats {
-    pub original_size: usize,
-    pub compressed_size: usize,
-    pub compression_ratio: f64,
-    pub savings_percent: f64,
-}
+    #[test]
+    fn test_compressed_sections_shrink_metadata_and_stay_lazy() {
+        let codec = IcfCodec::new();
+        let img = gradient_image(16, 16);
+        let mut options = IcfEncodeOptions::default();
+        for i in 0..20 {
+            options.metadata.tags.insert(format!("exif.field{:02}", i), format!("origin upload batch {}", i % 3));
+        }
+        let plain = codec.encode_with_options(&img, &options).unwrap();
+        options.compress_sections = true;
+        let packed = codec.encode_with_options(&img, &options).unwrap();
+        assert!(packed.len() < plain.len() * 2 / 3, "{} vs {} bytes", packed.len(), plain.len());
 
-impl std::fmt::Display for ImageCompressionStats {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, 
-            "Original: {} bytes, Compressed: {} bytes, Ratio: {:.2}:1, Savings: {:.2}%",
-            self.original_size,
-            self.compressed_size,
-            self.compression_ratio,
-            self.savings_percent
-        )
+        let (header, _) = codec.parse_container(&packed).unwrap();
+        assert!(header.metadata_compressed() && header.metadata.is_none());
+        assert_eq!(header.quantization_tables, codec.parse_container(&plain).unwrap().0.quantization_tables);
+        assert_eq!(codec.metadata(&packed).unwrap(), codec.metadata(&plain).unwrap());
+        let decoded = codec.decode(&plain).unwrap().to_rgb8();
+        assert_eq!(codec.decode(&packed).unwrap().to_rgb8(), decoded);
+        assert_eq!(IcfReader::new(std::io::Cursor::new(&packed)).unwrap().decode().unwrap().to_rgb8(), decoded);
+
+        let layout = codec.parse_layout(&packed).unwrap();
+        let names: Vec<&str> = layout.regions.iter().map(|region| region.name.as_str()).collect();
+        assert_eq!(names, ["magic", "header_length", "header", "metadata", "quantization_tables", "blocks"]);
+        let metadata = layout.region("metadata").unwrap().clone();
+        assert!(matches!(codec.check_complete(&packed[..metadata.offset as usize + 1]).unwrap_err().downcast_ref(), Some(IcfError::Truncated { .. })));
+
+        // Pixels never touch the metadata section
+        let mut damaged = packed.clone();
+        damaged[(metadata.offset + metadata.length / 2) as usize] ^= 0xff;
+        assert_eq!(codec.decode(&damaged).unwrap().to_rgb8(), decoded);
+        assert!(codec.metadata(&damaged).is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
-    use image::{ImageBuffer, Rgb};
+    #[test]
+    fn test_files_without_sections_still_parse() {
+        let codec = IcfCodec::new();
+        let mut options = IcfEncodeOptions::default();
+        options.metadata.tags.insert("camera".to_string(), "test".to_string());
+        let encoded = codec.encode_with_options(&gradient_image(16, 16), &options).unwrap();
+        assert!(!String::from_utf8_lossy(&encoded).contains("\"sections\""));
+
+        let (header, _) = codec.parse_container(&encoded).unwrap();
+        assert!(!header.metadata_compressed());
+        assert_eq!(header.quantization_tables.len(), 3);
+        assert_eq!(codec.metadata(&encoded).unwrap(), header.metadata);
+        assert_eq!(header.metadata.unwrap().tags["camera"], "test");
+    }
 
     #[test]
-    fn test_icf_codec_roundtrip() {
-        let temp_dir = TempDir::new().unwrap();
-        let test_image_path = temp_dir.path().join("test.png");
-        
-        // Create a simple test image
-        let img = ImageBuffer::from_fn(64, 64, |x, y| {
-            let intensity = ((x + y) % 256) as u8;
-            Rgb([intensity, intensity / 2, intensity / 4])
+    fn test_decode_checked_flags_lossy_mismatch() {
+        let codec = IcfCodec::new();
+        let compressed = encode_test_image(50);
+
+        let outcome = codec.decode_checked(&compressed, &DecodeOptions::default()).unwrap();
+        assert!(!outcome.checksum_matched);
+        assert_ne!(outcome.expected, outcome.actual);
+
+        let (header, _) = codec.parse_container(&compressed).unwrap();
+        assert_eq!(outcome.expected, header.checksum);
+        assert_eq!(outcome.image.width(), 32);
+    }
+
+    #[test]
+    fn test_decode_checked_strict_rejects_mismatch() {
+        let codec = IcfCodec::new();
+        let compressed = encode_test_image(50);
+
+        let options = DecodeOptions { strict_checksum: true, ..Default::default() };
+        let error = codec.decode_checked(&compressed, &options).unwrap_err();
+        assert!(error.to_string().contains("checksum mismatch"));
+
+        // The plain decode stays lenient for lossy files
+        assert!(codec.decode(&compressed).is_ok());
+    }
+
+    #[test]
+    fn test_lossless_claim_makes_mismatch_fatal() {
+        let codec = IcfCodec::new();
+        let compressed = encode_test_image(50);
+
+        let (mut header, payload) = codec.parse_container(&compressed).unwrap();
+        header.compression_method = IcfCodec::LOSSLESS_METHOD.to_string();
+        let relabeled = codec.create_container(header, payload.to_vec(), false).unwrap();
+
+        assert!(codec.decode_checked(&relabeled, &DecodeOptions::default()).is_err());
+        assert!(codec.decode(&relabeled).is_err());
+
+        let [strict, normal, tolerant] = decode_at_each_level(&relabeled);
+        assert_eq!(anomaly_code(strict), Some("checksum_mismatch"));
+        assert_eq!(anomaly_code(normal), None);
+        assert_eq!(tolerant.unwrap().warnings[0].code(), "checksum_mismatch");
+    }
+
+    /// `decode_checked` of `data` in strict, normal and tolerant mode
+    fn decode_at_each_level(data: &[u8]) -> [Result<DecodeOutcome>; 3] {
+        [DecodeStrictness::Strict, DecodeStrictness::Normal, DecodeStrictness::Tolerant]
+            .map(|strictness| IcfCodec::new().decode_checked(data, &DecodeOptions { strictness, ..Default::default() }))
+    }
+
+    /// Code of the anomaly a decode failed on, `None` for any other error
+    fn anomaly_code(result: Result<DecodeOutcome>) -> Option<&'static str> {
+        match result.unwrap_err().downcast_ref() {
+            Some(IcfError::Anomaly(anomaly)) => Some(anomaly.code()),
+            _ => None,
+        }
+    }
+
+    /// `encode_test_image(50)` written again after `edit` changes its header and blocks
+    fn rewrite_test_image(edit: impl FnOnce(&mut IcfHeader, &mut Vec<CompressedBlock>)) -> Vec<u8> {
+        let codec = IcfCodec::new();
+        let compressed = encode_test_image(50);
+        let (mut header, payload) = codec.parse_container(&compressed).unwrap();
+        let mut blocks = Vec::new();
+        IcfCodec::stream_payload(&header, payload, ChannelMask::ALL, &mut |block| {
+            blocks.push(block);
+            Ok(())
+        }).unwrap();
+        header.channel_sections.clear();
+        edit(&mut header, &mut blocks);
+        codec.write_blocks(header, blocks, false).unwrap()
+    }
+
+    #[test]
+    fn test_strictness_on_unverifiable_checksum() {
+        let data = rewrite_test_image(|header, _| header.checksum = "crc32:0badf00d".to_string());
+
+        let [strict, normal, tolerant] = decode_at_each_level(&data);
+        assert_eq!(anomaly_code(strict), Some("unverifiable_checksum"));
+        let expected = vec![DecodeAnomaly::UnverifiableChecksum("crc32:0badf00d".to_string())];
+        assert_eq!(normal.unwrap().warnings, expected);
+        assert_eq!(tolerant.unwrap().warnings, expected);
+
+        // An intact lossy file decodes strictly with nothing to report
+        let outcome = IcfCodec::new().decode_checked(&encode_test_image(50), &DecodeOptions {
+            strictness: DecodeStrictness::Strict,
+            ..Default::default()
+        }).unwrap();
+        assert!(!outcome.checksum_matched);
+        assert!(outcome.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_strictness_on_missing_blocks() {
+        // The last luma block, so no later block's DC prediction depends on it
+        let data = rewrite_test_image(|_, blocks| {
+            let last = blocks.iter().rposition(|block| block.channel == 0).unwrap();
+            blocks.remove(last);
         });
-        
-        img.save(&test_image_path).unwrap();
-        
+
+        let [strict, normal, tolerant] = decode_at_each_level(&data);
+        assert_eq!(anomaly_code(strict), Some("missing_blocks"));
+        let (normal, tolerant) = (normal.unwrap(), tolerant.unwrap());
+        let expected = vec![DecodeAnomaly::MissingBlocks { channel: 0, count: 1 }];
+        assert_eq!(normal.warnings, expected);
+        assert_eq!(tolerant.warnings, expected);
+
+        // Normal leaves the block mid-gray; tolerant conceals it from its neighbors
+        let reference = IcfCodec::new().decode(&encode_test_image(50)).unwrap().to_rgb8();
+        let error = |image: &DynamicImage| {
+            let image = image.to_rgb8();
+            (24..32).flat_map(|y| (24..32).map(move |x| (x, y)))
+                .map(|(x, y)| (image.get_pixel(x, y)[1] as i32 - reference.get_pixel(x, y)[1] as i32).abs())
+                .sum::<i32>()
+        };
+        assert!(error(&tolerant.image) < error(&normal.image));
+        assert_eq!(normal.image.to_rgb8().get_pixel(0, 0), reference.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_strictness_on_unknown_section() {
         let codec = IcfCodec::new();
-        
-        // Test different quality levels
-        for quality in [10, 50, 85, 95] {
-            let compressed = codec.encode(test_image_path.to_str().unwrap(), quality).unwrap();
-            let decompressed = codec.decode(&compressed).unwrap();
-            
-            // Check dimensions
-            assert_eq!(decompressed.width(), 64);
-            assert_eq!(decompressed.height(), 64);
-            
-            let stats = codec.get_stats(test_image_path.to_str().unwrap(), &compressed).unwrap();
-            println!("Quality {}: {}", quality, stats);
-            
-            // Higher quality should generally have larger file sizes
-            if quality > 10 {
-                // Basic sanity check that compression is working
-                assert!(compressed.len() < img.as_raw().len());
+        let compressed = encode_test_image(50);
+        let (mut header, payload) = codec.parse_container(&compressed).unwrap();
+        header.sections = vec![IcfSection { kind: IcfSectionKind::Unknown, length: 4, expanded_length: 4 }];
+        let header_json = serde_json::to_string(&header).unwrap().replace("\"unknown\"", "\"thumbnail\"");
+        let mut data = IcfCodec::MAGIC.as_bytes().to_vec();
+        data.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+        data.extend_from_slice(header_json.as_bytes());
+        data.extend_from_slice(b"\0xyz");
+        data.extend_from_slice(payload);
+
+        let [strict, normal, tolerant] = decode_at_each_level(&data);
+        assert_eq!(anomaly_code(strict), Some("unknown_section"));
+        let expected = vec![DecodeAnomaly::UnknownSection { index: 0 }];
+        let (normal, tolerant) = (normal.unwrap(), tolerant.unwrap());
+        assert_eq!(normal.warnings, expected);
+        assert_eq!(tolerant.warnings, expected);
+        assert_eq!(normal.image, codec.decode(&compressed).unwrap());
+    }
+
+    #[test]
+    fn test_strictness_on_out_of_range_coefficients() {
+        let data = rewrite_test_image(|_, blocks| {
+            blocks[0].ac_coefficients.insert(0, (0, 5000));
+            blocks[1].ac_coefficients = vec![(70, 3)];
+        });
+
+        let [strict, normal, tolerant] = decode_at_each_level(&data);
+        assert_eq!(anomaly_code(strict), Some("coefficients_out_of_range"));
+        for outcome in [normal.unwrap(), tolerant.unwrap()] {
+            assert_eq!(outcome.warnings, vec![DecodeAnomaly::CoefficientsOutOfRange { channel: 0, x: 0, y: 0, blocks: 2 }]);
+        }
+    }
+
+    #[test]
+    fn test_strictness_on_truncated_payload() {
+        let compressed = encode_test_image(50);
+        let data = &compressed[..compressed.len() - 20];
+
+        let [strict, normal, tolerant] = decode_at_each_level(data);
+        assert!(strict.is_err());
+        assert_eq!(anomaly_code(normal), None);
+        let tolerant = tolerant.unwrap();
+        assert_eq!(tolerant.warnings[0].code(), "incomplete_payload");
+        assert!(tolerant.warnings.iter().any(|warning| warning.code() == "missing_blocks"));
+        assert_eq!(tolerant.image.width(), 32);
+    }
+
+    #[test]
+    fn test_decode_prints_nothing_to_stdout() {
+        const PROBE_ENV: &str = "ICF_STDOUT_PROBE";
+        const TEST_NAME: &str = "codecs::image::icf_codec::tests::test_decode_prints_nothing_to_stdout";
+
+        if std::env::var_os(PROBE_ENV).is_some() {
+            // Child process: encode, then decode a lossy file, whose checksum never matches
+            let compressed = encode_test_image(50);
+            IcfCodec::new().decode(&compressed).unwrap();
+            return;
+        }
+
+        // Re-run this test alone in a child process with stdout captured
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", TEST_NAME, "--nocapture", "--test-threads=1"])
+            .env(PROBE_ENV, "1")
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("1 passed"), "probe did not run: {}", stdout);
+        assert!(!stdout.to_lowercase().contains("checksum"), "library printed: {}", stdout);
+
+        // Anything besides the harness's own report came from the codec
+        let stray: Vec<&str> = stdout.lines()
+            .filter(|line| !line.is_empty() && !line.starts_with("running ") && !line.starts_with("test "))
+            .collect();
+        assert!(stray.is_empty(), "library printed: {:?}", stray);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_encode_and_decode_enter_phase_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::{Context, SubscriberExt};
+
+        /// Records the name of every span created
+        struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+        impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanNames {
+            fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, _: &tracing::span::Id, _: Context<'_, S>) {
+                self.0.lock().unwrap().push(attrs.metadata().name());
             }
         }
+
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(SpanNames(names.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let compressed = encode_test_image(50);
+            IcfCodec::new().decode(&compressed).unwrap();
+        });
+
+        let names = names.lock().unwrap();
+        for expected in [
+            "icf.read",
+            "icf.encode",
+            "icf.color_convert",
+            "icf.transform",
+            "icf.entropy",
+            "icf.container",
+            "icf.decode",
+            "icf.reconstruct",
+            "icf.verify",
+        ] {
+            assert!(names.contains(&expected), "missing span {}: {:?}", expected, names);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,136 @@
+//! Decoding of source images in other formats
+//!
+//! Builds without the `image-formats` feature read only PNG and JPEG. Every
+//! input goes through `decode_input`, which sniffs the format first, so a
+//! file this build can't read is reported as such rather than as a decode
+//! failure.
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use std::path::Path;
+use thiserror::Error;
+
+/// Crate feature that enables every format the `image` crate reads
+pub const FULL_FORMATS_FEATURE: &str = "image-formats";
+
+/// An encoded source image
+#[derive(Debug, Clone, Copy)]
+pub enum InputSource<'a> {
+    Path(&'a Path),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> From<&'a Path> for InputSource<'a> {
+    fn from(path: &'a Path) -> Self {
+        InputSource::Path(path)
+    }
+}
+
+impl<'a> From<&'a str> for InputSource<'a> {
+    fn from(path: &'a str) -> Self {
+        InputSource::Path(Path::new(path))
+    }
+}
+
+impl<'a> From<&'a [u8]> for InputSource<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        InputSource::Bytes(bytes)
+    }
+}
+
+/// Why a source image can't be decoded at all
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    #[error("{format} input needs the `{feature}` feature, which this build was compiled without")]
+    FormatDisabled { format: String, feature: &'static str },
+    #[error("{0} input can't be decoded")]
+    Unsupported(String),
+    #[error("Unrecognized image format")]
+    Unrecognized,
+}
+
+/// Decode a source image in its own pixel type
+pub fn decode_input<'a>(source: impl Into<InputSource<'a>>) -> Result<DynamicImage> {
+    match source.into() {
+        InputSource::Bytes(bytes) => decode_bytes(bytes, None),
+        InputSource::Path(path) => {
+            let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            decode_bytes(&bytes, Some(path)).with_context(|| format!("Failed to load {}", path.display()))
+        }
+    }
+}
+
+/// Decode a source image to 8-bit RGBA
+pub fn load_input<'a>(source: impl Into<InputSource<'a>>) -> Result<RgbaImage> {
+    decode_input(source).map(|img| img.to_rgba8())
+}
+
+/// Formats without a signature are only known by their file extension
+fn decode_bytes(bytes: &[u8], path: Option<&Path>) -> Result<DynamicImage> {
+    let format = image::guess_format(bytes).ok()
+        .or_else(|| path.and_then(|path| ImageFormat::from_path(path).ok()))
+        .ok_or(InputError::Unrecognized)?;
+    let name = format_name(format);
+    if !format.can_read() {
+        return Err(InputError::Unsupported(name).into());
+    }
+    if !format.reading_enabled() {
+        return Err(InputError::FormatDisabled { format: name, feature: FULL_FORMATS_FEATURE }.into());
+    }
+    image::load_from_memory_with_format(bytes, format).with_context(|| format!("Failed to decode {} image", name))
+}
+
+fn format_name(format: ImageFormat) -> String {
+    match format {
+        ImageFormat::Jpeg => "JPEG".to_string(),
+        ImageFormat::WebP => "WebP".to_string(),
+        ImageFormat::OpenExr => "OpenEXR".to_string(),
+        other => format!("{:?}", other).to_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_png_and_jpeg_always_load() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 8, |x, y| Rgb([x as u8 * 16, y as u8 * 32, 128])));
+        for format in [ImageOutputFormat::Png, ImageOutputFormat::Jpeg(90)] {
+            let mut encoded = Cursor::new(Vec::new());
+            img.write_to(&mut encoded, format).unwrap();
+            let loaded = load_input(&encoded.get_ref()[..]).unwrap();
+            assert_eq!(loaded.dimensions(), (16, 8));
+            assert_eq!(loaded.get_pixel(0, 0)[3], 255);
+        }
+
+        let error = load_input(&b"definitely not an image"[..]).unwrap_err();
+        assert_eq!(error.downcast_ref::<InputError>(), Some(&InputError::Unrecognized));
+    }
+
+    #[test]
+    fn test_bmp_needs_full_formats() {
+        // 1x1 24-bit BMP, one blue pixel
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&58u32.to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        for field in [40u32, 1, 1] {
+            bmp.extend_from_slice(&field.to_le_bytes());
+        }
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&24u16.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+        bmp.extend_from_slice(&[255, 0, 0, 0]);
+
+        let loaded = load_input(&bmp[..]);
+        if cfg!(feature = "image-formats") {
+            assert_eq!(loaded.unwrap().get_pixel(0, 0).0, [0, 0, 255, 255]);
+        } else {
+            let error = loaded.unwrap_err();
+            assert_eq!(error.to_string(), "BMP input needs the `image-formats` feature, which this build was compiled without");
+            assert!(matches!(error.downcast_ref::<InputError>(), Some(InputError::FormatDisabled { .. })));
+        }
+    }
+}
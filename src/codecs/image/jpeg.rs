@@ -0,0 +1,451 @@
+use anyhow::{bail, Context, Result};
+
+/// Quantized DCT coefficients and tables of a baseline JPEG, as stored
+///
+/// Nothing is dequantized or inverse transformed.
+pub(crate) struct JpegCoefficients {
+    pub width: u32,
+    pub height: u32,
+    pub components: Vec<JpegComponent>,
+    /// Adobe APP14 color transform, when the file has one; 0 means the
+    /// components are RGB rather than YCbCr
+    pub adobe_transform: Option<u8>,
+}
+
+pub(crate) struct JpegComponent {
+    /// Horizontal and vertical sampling factors
+    pub h: u8,
+    pub v: u8,
+    /// Quantization table in zigzag order
+    pub quantization_table: [u16; 64],
+    /// Columns of blocks in `blocks`, padded out to whole MCUs
+    pub blocks_x: usize,
+    /// Coefficients of each block in zigzag order, in raster order
+    pub blocks: Vec<[i16; 64]>,
+}
+
+/// Canonical Huffman table, decoded a bit at a time (ITU T.81 F.2.2.3)
+#[derive(Clone)]
+struct HuffmanTable {
+    /// Largest code of each length, or -1 if there are none
+    max_code: [i32; 17],
+    /// Index into `values` of the first code of each length, minus that code
+    offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8; 16], values: Vec<u8>) -> Result<Self> {
+        let mut max_code = [-1; 17];
+        let mut offset = [0; 17];
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1..=16 {
+            let count = counts[length - 1] as i32;
+            offset[length] = index - code;
+            if count > 0 {
+                code += count;
+                index += count;
+                max_code[length] = code - 1;
+            }
+            if code > 1 << length {
+                bail!("JPEG Huffman table has more codes than fit in {} bits", length);
+            }
+            code <<= 1;
+        }
+        if index as usize != values.len() {
+            bail!("JPEG Huffman table lists {} codes for {} values", index, values.len());
+        }
+        Ok(Self { max_code, offset, values })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = (code << 1) | bits.bit() as i32;
+            if code <= self.max_code[length] {
+                return Ok(self.values[(self.offset[length] + code) as usize]);
+            }
+        }
+        bail!("Corrupt JPEG data: invalid Huffman code")
+    }
+}
+
+/// Reads entropy-coded bits, unstuffing `FF 00` and stopping at markers
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+    /// A marker or the end of the data was reached; later bits read as
+    /// zero, and the caller's marker parsing reports any truncation
+    at_marker: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position, buffer: 0, count: 0, at_marker: false }
+    }
+
+    fn fill(&mut self) {
+        let byte = match self.data.get(self.position) {
+            Some(0xFF) if !self.at_marker && self.data.get(self.position + 1) == Some(&0) => {
+                self.position += 2;
+                0xFF
+            }
+            Some(0xFF) | None => {
+                self.at_marker = true;
+                0
+            }
+            Some(&byte) if !self.at_marker => {
+                self.position += 1;
+                byte
+            }
+            Some(_) => 0,
+        };
+        self.buffer = (self.buffer << 8) | byte as u32;
+        self.count += 8;
+    }
+
+    fn bit(&mut self) -> u32 {
+        self.bits(1)
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        while self.count < count {
+            self.fill();
+        }
+        self.count -= count;
+        (self.buffer >> self.count) & ((1 << count) - 1)
+    }
+
+    /// Value of a `size`-bit magnitude category (T.81 F.2.2.1)
+    fn receive_extend(&mut self, size: u8) -> Result<i16> {
+        if size > 15 {
+            bail!("Corrupt JPEG data: coefficient category {}", size);
+        }
+        let value = self.bits(size as u32) as i32;
+        let value = if size > 0 && value < 1 << (size - 1) { value - (1 << size) + 1 } else { value };
+        Ok(value as i16)
+    }
+
+    /// Skip the `RSTn` marker that should come next, dropping leftover bits
+    fn restart(&mut self) -> Result<()> {
+        self.buffer = 0;
+        self.count = 0;
+        self.at_marker = false;
+        match self.data.get(self.position..self.position + 2) {
+            Some([0xFF, 0xD0..=0xD7]) => {
+                self.position += 2;
+                Ok(())
+            }
+            _ => bail!("Corrupt JPEG data: missing restart marker"),
+        }
+    }
+}
+
+struct FrameComponent {
+    id: u8,
+    h: u8,
+    v: u8,
+    table: usize,
+}
+
+/// Parse a baseline sequential JPEG down to its quantized coefficients
+pub(crate) fn read_coefficients(data: &[u8]) -> Result<JpegCoefficients> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        bail!("Not a JPEG file (missing SOI marker)");
+    }
+
+    let mut quantization_tables: [Option<[u16; 64]>; 4] = [None; 4];
+    let mut dc_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut ac_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut restart_interval = 0usize;
+    let mut adobe_transform = None;
+    let mut frame: Option<(u32, u32, Vec<FrameComponent>)> = None;
+    let mut components: Vec<JpegComponent> = Vec::new();
+    let mut scanned: Vec<bool> = Vec::new();
+
+    let mut position = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        while data.get(position) == Some(&0xFF) && data.get(position + 1) == Some(&0xFF) {
+            position += 1;
+        }
+        let marker = match data.get(position..position + 2) {
+            Some(&[0xFF, marker]) => marker,
+            Some(_) => bail!("Corrupt JPEG data: expected a marker at byte {}", position),
+            None => bail!("JPEG data ends before the EOI marker"),
+        };
+        position += 2;
+        if marker == 0xD9 {
+            break;
+        }
+        if matches!(marker, 0x01 | 0xD0..=0xD7) {
+            continue;
+        }
+
+        let length = data.get(position..position + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+            .filter(|&length| length >= 2 && position + length <= data.len())
+            .with_context(|| format!("JPEG segment {:02X} runs past the end of the data", marker))?;
+        let segment = &data[position + 2..position + length];
+        position += length;
+
+        match marker {
+            0xC0 | 0xC1 => {
+                if frame.is_some() {
+                    bail!("JPEG has more than one frame");
+                }
+                let (width, height, frame_components) = read_frame(segment)?;
+                for component in &frame_components {
+                    let h_max = frame_components.iter().map(|c| c.h).max().unwrap_or(1) as usize;
+                    let v_max = frame_components.iter().map(|c| c.v).max().unwrap_or(1) as usize;
+                    let mcus_x = (width as usize).div_ceil(8 * h_max);
+                    let mcus_y = (height as usize).div_ceil(8 * v_max);
+                    let (blocks_x, blocks_y) = (mcus_x * component.h as usize, mcus_y * component.v as usize);
+                    components.push(JpegComponent {
+                        h: component.h,
+                        v: component.v,
+                        // Bound at the component's first scan; DQT may follow the frame header
+                        quantization_table: [0; 64],
+                        blocks_x,
+                        blocks: vec![[0; 64]; blocks_x * blocks_y],
+                    });
+                }
+                scanned = vec![false; frame_components.len()];
+                frame = Some((width, height, frame_components));
+            }
+            0xC2 | 0xC6 | 0xCA | 0xCE => bail!("Progressive JPEGs are not supported; only baseline sequential JPEGs can be transcoded"),
+            0xC3 | 0xC5 | 0xC7 | 0xC9 | 0xCB | 0xCD | 0xCF => {
+                bail!("Unsupported JPEG coding process (SOF{}); only baseline sequential JPEGs can be transcoded", marker - 0xC0)
+            }
+            0xC4 => read_huffman_tables(segment, &mut dc_tables, &mut ac_tables)?,
+            0xDB => read_quantization_tables(segment, &mut quantization_tables)?,
+            0xDD => {
+                let bytes = segment.get(..2).context("JPEG DRI segment is too short")?;
+                restart_interval = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+            }
+            0xEE if segment.starts_with(b"Adobe") => adobe_transform = segment.get(11).copied(),
+            0xDA => {
+                let (width, height, frame_components) = frame.as_ref().context("JPEG scan comes before the frame header")?;
+                let scan = read_scan_header(segment, frame_components)?;
+                let tables = scan.iter()
+                    .map(|&(index, dc, ac)| {
+                        let dc = dc_tables[dc].as_ref().with_context(|| format!("JPEG scan uses undefined DC table {}", dc))?;
+                        let ac = ac_tables[ac].as_ref().with_context(|| format!("JPEG scan uses undefined AC table {}", ac))?;
+                        Ok((index, dc, ac))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                for &(index, _, _) in &scan {
+                    let component = &frame_components[index];
+                    if !scanned[index] {
+                        components[index].quantization_table = quantization_tables[component.table]
+                            .with_context(|| format!("JPEG component {} uses undefined quantization table {}", component.id, component.table))?;
+                    }
+                }
+                let geometry = ScanGeometry::new(*width, *height, frame_components, &scan);
+                position = decode_scan(data, position, &tables, &geometry, restart_interval, &mut components)?;
+                for &(index, _, _) in &scan {
+                    scanned[index] = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (width, height, _) = frame.context("JPEG has no baseline frame header")?;
+    if let Some(missing) = scanned.iter().position(|&scanned| !scanned) {
+        bail!("JPEG component {} has no scan", missing);
+    }
+    Ok(JpegCoefficients { width, height, components, adobe_transform })
+}
+
+fn read_frame(segment: &[u8]) -> Result<(u32, u32, Vec<FrameComponent>)> {
+    let [precision, h1, h0, w1, w0, count, ref rest @ ..] = *segment else {
+        bail!("JPEG frame header is too short");
+    };
+    if precision != 8 {
+        bail!("JPEG sample precision {} is not supported", precision);
+    }
+    let (width, height) = (u16::from_be_bytes([w1, w0]) as u32, u16::from_be_bytes([h1, h0]) as u32);
+    if width == 0 || height == 0 {
+        bail!("JPEG frame is {}x{}; a height defined later (DNL) is not supported", width, height);
+    }
+    if rest.len() < count as usize * 3 || count == 0 {
+        bail!("JPEG frame header lists {} components in {} bytes", count, rest.len());
+    }
+    let components = rest.chunks_exact(3)
+        .take(count as usize)
+        .map(|bytes| {
+            let (h, v, table) = (bytes[1] >> 4, bytes[1] & 15, bytes[2] as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || table > 3 {
+                bail!("JPEG component {} has invalid sampling {}x{} or table {}", bytes[0], h, v, table);
+            }
+            Ok(FrameComponent { id: bytes[0], h, v, table })
+        })
+        .collect::<Result<_>>()?;
+    Ok((width, height, components))
+}
+
+fn read_quantization_tables(mut segment: &[u8], tables: &mut [Option<[u16; 64]>; 4]) -> Result<()> {
+    while let [info, ref rest @ ..] = *segment {
+        let (precision, id) = (info >> 4, (info & 15) as usize);
+        let size = if precision == 0 { 64 } else { 128 };
+        if id > 3 || precision > 1 || rest.len() < size {
+            bail!("Invalid JPEG quantization table {}", id);
+        }
+        tables[id] = Some(std::array::from_fn(|i| match precision {
+            0 => rest[i] as u16,
+            _ => u16::from_be_bytes([rest[2 * i], rest[2 * i + 1]]),
+        }));
+        segment = &rest[size..];
+    }
+    Ok(())
+}
+
+fn read_huffman_tables(
+    mut segment: &[u8],
+    dc_tables: &mut [Option<HuffmanTable>; 4],
+    ac_tables: &mut [Option<HuffmanTable>; 4],
+) -> Result<()> {
+    while let [info, ref rest @ ..] = *segment {
+        let (class, id) = (info >> 4, (info & 15) as usize);
+        let counts: &[u8; 16] = rest.get(..16)
+            .and_then(|counts| counts.try_into().ok())
+            .context("JPEG Huffman table is truncated")?;
+        let total: usize = counts.iter().map(|&count| count as usize).sum();
+        let values = rest.get(16..16 + total).context("JPEG Huffman table is truncated")?.to_vec();
+        if class > 1 || id > 3 {
+            bail!("Invalid JPEG Huffman table class {} id {}", class, id);
+        }
+        let table = Some(HuffmanTable::new(counts, values)?);
+        if class == 0 { dc_tables[id] = table } else { ac_tables[id] = table }
+        segment = &rest[16 + total..];
+    }
+    Ok(())
+}
+
+/// Components of a scan as (frame index, DC table, AC table)
+fn read_scan_header(segment: &[u8], frame_components: &[FrameComponent]) -> Result<Vec<(usize, usize, usize)>> {
+    let count = *segment.first().context("JPEG scan header is empty")? as usize;
+    let spectral = segment.get(1 + 2 * count..4 + 2 * count).context("JPEG scan header is too short")?;
+    if !(1..=4).contains(&count) {
+        bail!("JPEG scan has {} components", count);
+    }
+    if spectral != [0, 63, 0] {
+        bail!("JPEG scan covers coefficients {}..={} with approximation {:02X}; only sequential scans are supported",
+            spectral[0], spectral[1], spectral[2]);
+    }
+    segment[1..1 + 2 * count].chunks_exact(2)
+        .map(|bytes| {
+            let index = frame_components.iter().position(|component| component.id == bytes[0])
+                .with_context(|| format!("JPEG scan uses unknown component {}", bytes[0]))?;
+            let (dc, ac) = ((bytes[1] >> 4) as usize, (bytes[1] & 15) as usize);
+            if dc > 3 || ac > 3 {
+                bail!("JPEG scan uses invalid Huffman tables {}/{}", dc, ac);
+            }
+            Ok((index, dc, ac))
+        })
+        .collect()
+}
+
+/// Which blocks make up each MCU of a scan
+struct ScanGeometry {
+    mcus_x: usize,
+    mcus_y: usize,
+    /// Per scan component: blocks per MCU across and down
+    units: Vec<(usize, usize)>,
+}
+
+impl ScanGeometry {
+    fn new(width: u32, height: u32, frame_components: &[FrameComponent], scan: &[(usize, usize, usize)]) -> Self {
+        let h_max = frame_components.iter().map(|c| c.h).max().unwrap_or(1) as usize;
+        let v_max = frame_components.iter().map(|c| c.v).max().unwrap_or(1) as usize;
+        let (width, height) = (width as usize, height as usize);
+        if let [(index, _, _)] = *scan {
+            // A single-component scan codes exactly the component's own blocks, one per MCU
+            let component = &frame_components[index];
+            let component_width = (width * component.h as usize).div_ceil(h_max);
+            let component_height = (height * component.v as usize).div_ceil(v_max);
+            Self { mcus_x: component_width.div_ceil(8), mcus_y: component_height.div_ceil(8), units: vec![(1, 1)] }
+        } else {
+            Self {
+                mcus_x: width.div_ceil(8 * h_max),
+                mcus_y: height.div_ceil(8 * v_max),
+                units: scan.iter().map(|&(index, _, _)| {
+                    let component = &frame_components[index];
+                    (component.h as usize, component.v as usize)
+                }).collect(),
+            }
+        }
+    }
+}
+
+/// Decode the entropy-coded data of one scan starting at `position`,
+/// returning where the next marker starts
+fn decode_scan(
+    data: &[u8],
+    position: usize,
+    tables: &[(usize, &HuffmanTable, &HuffmanTable)],
+    geometry: &ScanGeometry,
+    restart_interval: usize,
+    components: &mut [JpegComponent],
+) -> Result<usize> {
+    let mut bits = BitReader::new(data, position);
+    let mut predictions = vec![0i16; tables.len()];
+    let mcu_count = geometry.mcus_x * geometry.mcus_y;
+
+    for mcu in 0..mcu_count {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            bits.restart()?;
+            predictions.fill(0);
+        }
+        let (mcu_x, mcu_y) = (mcu % geometry.mcus_x, mcu / geometry.mcus_x);
+        for ((&(index, dc, ac), &(units_x, units_y)), prediction) in tables.iter().zip(&geometry.units).zip(&mut predictions) {
+            let component = &mut components[index];
+            for unit in 0..units_x * units_y {
+                let block_x = mcu_x * units_x + unit % units_x;
+                let block_y = mcu_y * units_y + unit / units_x;
+                let block = &mut component.blocks[block_y * component.blocks_x + block_x];
+                decode_block(&mut bits, dc, ac, prediction, block)?;
+            }
+        }
+    }
+
+    // Resume after any padding bits, at the next marker
+    let mut end = bits.position;
+    while end + 1 < data.len() && !(data[end] == 0xFF && data[end + 1] != 0 && !(0xD0..=0xD7).contains(&data[end + 1])) {
+        end += 1;
+    }
+    Ok(end)
+}
+
+fn decode_block(bits: &mut BitReader, dc: &HuffmanTable, ac: &HuffmanTable, prediction: &mut i16, block: &mut [i16; 64]) -> Result<()> {
+    let category = dc.decode(bits)?;
+    *prediction = prediction.wrapping_add(bits.receive_extend(category)?);
+    block[0] = *prediction;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = ac.decode(bits)?;
+        let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            bail!("Corrupt JPEG data: AC run past the end of a block");
+        }
+        block[k] = bits.receive_extend(size)?;
+        k += 1;
+    }
+    Ok(())
+}
@@ -1,7 +1,32 @@
 pub mod icf_codec;
+pub mod coefficients;
 pub mod dct_transform;
 pub mod quantization;
+pub mod profile;
+pub mod input;
+pub mod overlay;
+pub mod phash;
+pub mod pipeline;
+pub mod placeholder;
+pub mod resample;
+pub mod source_analysis;
+pub mod transparency;
+pub(crate) mod header_sections;
+pub(crate) mod jpeg;
+#[cfg(test)]
+pub(crate) mod regression;
+pub(crate) mod sign_context;
 
 pub use icf_codec::*;
+pub use coefficients::*;
 pub use dct_transform::*;
-pub use quantization::*;
\ No newline at end of file
+pub use quantization::*;
+pub use profile::*;
+pub use input::*;
+pub use overlay::*;
+pub use phash::*;
+pub use pipeline::*;
+pub use placeholder::*;
+pub use resample::*;
+pub use source_analysis::*;
+pub use transparency::*;
\ No newline at end of file
@@ -0,0 +1,160 @@
+use anyhow::{bail, Result};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+use super::resample::{resize_image, ResampleOptions};
+
+/// Point of the image an overlay is placed against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl Anchor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Anchor::TopLeft => "top-left",
+            Anchor::TopRight => "top-right",
+            Anchor::BottomLeft => "bottom-left",
+            Anchor::BottomRight => "bottom-right",
+            Anchor::Center => "center",
+        }
+    }
+}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [Anchor::TopLeft, Anchor::TopRight, Anchor::BottomLeft, Anchor::BottomRight, Anchor::Center]
+            .into_iter()
+            .find(|anchor| anchor.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown anchor {:?}, expected top-left, top-right, bottom-left, bottom-right or center", s))
+    }
+}
+
+/// An image alpha-composited onto the source pixels before encoding, e.g.
+/// a watermark
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    pub image: RgbaImage,
+    pub position: Anchor,
+    /// Pixels in from the anchored edges; for `Center`, right and down
+    pub offset: (i32, i32),
+    /// Scales the overlay's own alpha, 0 to 1
+    pub opacity: f32,
+    /// Recorded in the metadata, e.g. the overlay's file name
+    pub name: String,
+}
+
+/// The metadata's note of an applied overlay, for auditing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OverlayRecord {
+    pub name: String,
+    /// SHA-256 of the overlay's dimensions and RGBA pixels as given
+    pub sha256: String,
+}
+
+impl Overlay {
+    pub fn new(image: RgbaImage, position: Anchor, opacity: f32) -> Self {
+        Self { image, position, offset: (0, 0), opacity, name: String::new() }
+    }
+
+    pub fn record(&self) -> OverlayRecord {
+        let mut hasher = Sha256::new();
+        hasher.update(self.image.width().to_le_bytes());
+        hasher.update(self.image.height().to_le_bytes());
+        hasher.update(self.image.as_raw());
+        OverlayRecord { name: self.name.clone(), sha256: format!("{:x}", hasher.finalize()) }
+    }
+
+    /// Blend the overlay into `img`
+    ///
+    /// An overlay larger than `img` along either axis is first scaled down
+    /// to fit, keeping its aspect ratio; parts pushed off the image by the
+    /// offset are clipped.
+    pub fn apply(&self, img: &mut RgbImage) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.opacity) {
+            bail!("Overlay opacity must be between 0 and 1, got {}", self.opacity);
+        }
+        let (width, height) = img.dimensions();
+        let (overlay_width, overlay_height) = self.image.dimensions();
+        let scaled;
+        let overlay = if overlay_width > width || overlay_height > height {
+            let scale = (width as f64 / overlay_width as f64).min(height as f64 / overlay_height as f64);
+            let size = |side: u32| ((side as f64 * scale).floor() as u32).max(1);
+            let resized = resize_image(
+                &DynamicImage::ImageRgba8(self.image.clone()), size(overlay_width), size(overlay_height), &ResampleOptions::default(),
+            );
+            scaled = resized.to_rgba8();
+            &scaled
+        } else {
+            &self.image
+        };
+
+        let (left, top) = self.origin((width, height), overlay.dimensions());
+        for (x, y, pixel) in overlay.enumerate_pixels() {
+            let (target_x, target_y) = (left + x as i64, top + y as i64);
+            if !(0..width as i64).contains(&target_x) || !(0..height as i64).contains(&target_y) {
+                continue;
+            }
+            let alpha = pixel[3] as f32 / 255.0 * self.opacity;
+            let target = img.get_pixel_mut(target_x as u32, target_y as u32);
+            for channel in 0..3 {
+                let source = target[channel] as f32;
+                target[channel] = (source + (pixel[channel] as f32 - source) * alpha).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Top-left corner of an overlay of `size` on an image of `canvas`
+    fn origin(&self, (width, height): (u32, u32), (overlay_width, overlay_height): (u32, u32)) -> (i64, i64) {
+        let (dx, dy) = (self.offset.0 as i64, self.offset.1 as i64);
+        let right = width as i64 - overlay_width as i64 - dx;
+        let bottom = height as i64 - overlay_height as i64 - dy;
+        match self.position {
+            Anchor::TopLeft => (dx, dy),
+            Anchor::TopRight => (right, dy),
+            Anchor::BottomLeft => (dx, bottom),
+            Anchor::BottomRight => (right, bottom),
+            Anchor::Center => (
+                (width as i64 - overlay_width as i64) / 2 + dx,
+                (height as i64 - overlay_height as i64) / 2 + dy,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oversized_overlay_is_scaled_to_fit() {
+        let mut img = RgbImage::from_pixel(20, 10, image::Rgb([0, 0, 0]));
+        let overlay = Overlay::new(RgbaImage::from_pixel(40, 40, image::Rgba([255, 255, 255, 255])), Anchor::Center, 1.0);
+        overlay.apply(&mut img).unwrap();
+        // Scaled to 10x10 and centred: columns 5..15 are covered
+        let covered: Vec<bool> = (0..20).map(|x| img.get_pixel(x, 5)[0] == 255).collect();
+        assert_eq!(covered, (0..20).map(|x| (5..15).contains(&x)).collect::<Vec<_>>());
+        assert!(img.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+
+        assert!(Overlay::new(RgbaImage::new(1, 1), Anchor::TopLeft, 1.5).apply(&mut img).is_err());
+        assert_eq!("bottom-right".parse::<Anchor>().unwrap(), Anchor::BottomRight);
+        assert!("middle".parse::<Anchor>().is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use image::RgbImage;
+
+/// Edge of the grid a hash compares, one bit per cell
+const HASH_SIZE: usize = 8;
+
+/// 64-bit perceptual hash of an image's pixels
+///
+/// The luma plane is averaged over 4x4 cells, edge pixels repeated to fill
+/// whole 8x8 blocks as the ICF encoder does, and the grid is then hashed
+/// like `IcfCodec::perceptual_hash` hashes its coefficient-domain grid.
+/// Hashes of different formats are therefore comparable.
+pub fn perceptual_hash_image(img: &RgbImage) -> u64 {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    if width == 0 || height == 0 {
+        return 0;
+    }
+
+    let (columns, rows) = (width.div_ceil(8) * 2, height.div_ceil(8) * 2);
+    let mut grid = vec![0.0; columns * rows];
+    for (index, cell) in grid.iter_mut().enumerate() {
+        let (cell_x, cell_y) = (index % columns * 4, index / columns * 4);
+        let sum: f64 = (cell_y..cell_y + 4)
+            .flat_map(|y| (cell_x..cell_x + 4).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let [r, g, b] = img.get_pixel(x.min(width - 1) as u32, y.min(height - 1) as u32).0;
+                0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+            })
+            .sum();
+        *cell = sum / 16.0;
+    }
+    hash_grid(&grid, columns, rows)
+}
+
+/// Number of bits two hashes differ in
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Whether two hashes are at most `threshold` bits apart
+///
+/// Re-encodes of one image typically land within a few bits; unrelated
+/// images differ in about half of the 64.
+pub fn similar(a: u64, b: u64, threshold: u32) -> bool {
+    hamming_distance(a, b) <= threshold
+}
+
+/// Means of the four 4x4 quadrants of a block, `[row][col]`, from the DC
+/// and three lowest AC coefficients of its orthonormal DCT
+pub(crate) fn quadrant_means(coefficients: &[[f64; 8]; 8]) -> [[f64; 2]; 2] {
+    // Mean of the first DCT basis function over the first half of the
+    // block; the second half has the opposite sign
+    let half: f64 = (0..4)
+        .map(|x| ((2 * x + 1) as f64 * std::f64::consts::PI / 16.0).cos())
+        .sum::<f64>() / 4.0 * 0.5;
+    let weights = [[0.125_f64.sqrt(), half], [0.125_f64.sqrt(), -half]];
+
+    std::array::from_fn(|quadrant_y| {
+        std::array::from_fn(|quadrant_x| {
+            (0..2)
+                .flat_map(|v| (0..2).map(move |u| (v, u)))
+                .map(|(v, u)| coefficients[v][u] * weights[quadrant_y][v] * weights[quadrant_x][u])
+                .sum()
+        })
+    })
+}
+
+/// Area-resize a `width` x `height` grid to 8x8 and set one bit per cell
+/// above the median, in raster order from the lowest bit
+pub(crate) fn hash_grid(values: &[f64], width: usize, height: usize) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+
+    let cells: Vec<f64> = (0..HASH_SIZE * HASH_SIZE)
+        .map(|index| {
+            let xs = cell_span(index % HASH_SIZE, width);
+            let ys = cell_span(index / HASH_SIZE, height);
+            let (mut sum, mut area) = (0.0, 0.0);
+            for y in ys.0.floor() as usize..(ys.1.ceil() as usize).min(height) {
+                let weight_y = ys.1.min(y as f64 + 1.0) - ys.0.max(y as f64);
+                for x in xs.0.floor() as usize..(xs.1.ceil() as usize).min(width) {
+                    let weight = weight_y * (xs.1.min(x as f64 + 1.0) - xs.0.max(x as f64));
+                    sum += values[y * width + x] * weight;
+                    area += weight;
+                }
+            }
+            sum / area
+        })
+        .collect();
+
+    let mut sorted = cells.clone();
+    sorted.sort_by(f64::total_cmp);
+    let median = (sorted[cells.len() / 2 - 1] + sorted[cells.len() / 2]) / 2.0;
+    cells.iter()
+        .enumerate()
+        .filter(|&(_, &value)| value > median)
+        .fold(0, |hash, (bit, _)| hash | 1 << bit)
+}
+
+/// Range of source cells, in fractional cell units, output cell `index` covers
+fn cell_span(index: usize, source_len: usize) -> (f64, f64) {
+    let scale = source_len as f64 / HASH_SIZE as f64;
+    (index as f64 * scale, (index + 1) as f64 * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::image::{IcfCodec, IcfEncodeOptions};
+    use image::{DynamicImage, Rgb};
+
+    fn waves(width: u32, height: u32, fx: f64, fy: f64) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let value = 128.0 + 90.0 * (x as f64 * fx).sin() * (y as f64 * fy).cos();
+            let value = value as u8;
+            Rgb([value, value / 2 + 60, 255 - value])
+        })
+    }
+
+    #[test]
+    fn test_reencodes_stay_close_and_different_images_do_not() {
+        let codec = IcfCodec::new();
+        let hash_at = |img: &RgbImage, quality| {
+            let encoded = codec.encode_with_options(&DynamicImage::ImageRgb8(img.clone()), &IcfEncodeOptions::with_quality(quality)).unwrap();
+            codec.perceptual_hash(&encoded).unwrap()
+        };
+
+        let first = waves(120, 80, 0.07, 0.05);
+        let second = waves(120, 80, 0.03, 0.11);
+        let (high, low) = (hash_at(&first, 90), hash_at(&first, 30));
+        assert!(hamming_distance(high, low) <= 4, "{} bits apart", hamming_distance(high, low));
+        assert!(similar(high, low, 4));
+
+        let other = hash_at(&second, 90);
+        assert!(hamming_distance(high, other) >= 20, "{} bits apart", hamming_distance(high, other));
+        assert!(!similar(high, other, 10));
+    }
+
+    #[test]
+    fn test_coefficient_hash_matches_pixel_hash() {
+        let codec = IcfCodec::new();
+        for img in [waves(120, 80, 0.07, 0.05), waves(45, 30, 0.2, 0.15)] {
+            let encoded = codec.encode_with_options(&DynamicImage::ImageRgb8(img.clone()), &IcfEncodeOptions::with_quality(90)).unwrap();
+            let distance = hamming_distance(codec.perceptual_hash(&encoded).unwrap(), perceptual_hash_image(&img));
+            assert!(distance <= 4, "{}x{}: {} bits apart", img.width(), img.height(), distance);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::codecs::image::icf_codec::{IcfCodec, IcfEncodeOptions};
+use crate::codecs::image::input::decode_input;
+use crate::codecs::image::profile::IcfProfile;
+use crate::codecs::image::resample::{fit_image, ResampleOptions};
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::prelude::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Settings for a `ThumbnailPipeline`
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    /// Bounding box edge of each variant, in pixels; images already inside
+    /// the box are encoded at their own size
+    pub sizes: Vec<u32>,
+    pub options: IcfEncodeOptions,
+    /// How images are scaled down to each size
+    pub resample: ResampleOptions,
+    /// Jobs that may wait for a worker before `submit` blocks
+    pub queue_capacity: usize,
+    /// Worker threads, 0 for one per core
+    pub threads: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![128, 256, 512],
+            options: IcfEncodeOptions::profile(&IcfProfile::THUMBNAIL),
+            resample: ResampleOptions::default(),
+            queue_capacity: 64,
+            threads: 0,
+        }
+    }
+}
+
+/// An image to make thumbnails of
+#[derive(Debug, Clone)]
+pub enum ThumbnailSource {
+    Path(PathBuf),
+    /// An encoded image in any format the `image` crate reads
+    Bytes(Vec<u8>),
+}
+
+impl From<PathBuf> for ThumbnailSource {
+    fn from(path: PathBuf) -> Self {
+        ThumbnailSource::Path(path)
+    }
+}
+
+impl From<&Path> for ThumbnailSource {
+    fn from(path: &Path) -> Self {
+        ThumbnailSource::Path(path.to_path_buf())
+    }
+}
+
+impl From<Vec<u8>> for ThumbnailSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        ThumbnailSource::Bytes(bytes)
+    }
+}
+
+/// Identifies a submitted job in its `JobResult`; assigned in submission order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(pub u64);
+
+/// Everything one job produced
+#[derive(Debug, Clone)]
+pub struct JobResult {
+    pub id: JobId,
+    /// ICF file for each size that encoded, in `PipelineConfig::sizes` order
+    pub variants: Vec<(u32, Vec<u8>)>,
+    /// Why the source or a size failed; a source that can't be decoded has
+    /// no variants
+    pub errors: Vec<String>,
+}
+
+/// Why `ThumbnailPipeline::submit` handed a source back
+#[derive(Debug, thiserror::Error)]
+pub enum SubmitError {
+    /// `try_submit` found the queue full
+    #[error("thumbnail queue is full")]
+    WouldBlock(ThumbnailSource),
+    /// The pipeline's dispatcher has stopped
+    #[error("thumbnail pipeline has stopped")]
+    Closed(ThumbnailSource),
+}
+
+/// Batch thumbnailer: decode, scale and ICF-encode every submitted image
+/// at several sizes on a shared thread pool
+///
+/// Jobs wait in a bounded queue; a dispatcher hands them to the pool, at
+/// most one per thread at a time, so a burst of submissions blocks in
+/// `submit` rather than piling up in memory. Each job's sizes, and the
+/// blocks of each encode, are spread over the pool's work-stealing
+/// threads. Results arrive on `results` in completion order.
+pub struct ThumbnailPipeline {
+    queue: SyncSender<(JobId, ThumbnailSource)>,
+    results: Receiver<JobResult>,
+    next_id: AtomicU64,
+    dispatcher: JoinHandle<()>,
+}
+
+impl ThumbnailPipeline {
+    pub fn new(config: PipelineConfig) -> Result<Self> {
+        if config.sizes.is_empty() || config.sizes.contains(&0) {
+            bail!("Thumbnail sizes must be non-empty and positive, got {:?}", config.sizes);
+        }
+        if config.queue_capacity == 0 {
+            bail!("Thumbnail queue capacity must be at least 1");
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .thread_name(|index| format!("icf-thumbnail-{}", index))
+            .build()
+            .context("Failed to start the thumbnail thread pool")?;
+
+        let (queue, jobs) = sync_channel(config.queue_capacity);
+        let (finished, results) = channel();
+        let config = Arc::new(config);
+        let dispatcher = std::thread::Builder::new()
+            .name("icf-thumbnail-dispatch".to_string())
+            .spawn(move || Self::dispatch(pool, config, jobs, finished))
+            .context("Failed to start the thumbnail dispatcher")?;
+
+        Ok(Self { queue, results, next_id: AtomicU64::new(0), dispatcher })
+    }
+
+    /// Queue `source`, blocking while the queue is full
+    pub fn submit(&self, source: impl Into<ThumbnailSource>) -> Result<JobId, SubmitError> {
+        let id = JobId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.queue.send((id, source.into())).map_err(|error| SubmitError::Closed(error.0 .1))?;
+        Ok(id)
+    }
+
+    /// Queue `source` unless the queue is full
+    pub fn try_submit(&self, source: impl Into<ThumbnailSource>) -> Result<JobId, SubmitError> {
+        let id = JobId(self.next_id.load(Ordering::Relaxed));
+        match self.queue.try_send((id, source.into())) {
+            Ok(()) => {
+                self.next_id.fetch_add(1, Ordering::Relaxed);
+                Ok(id)
+            }
+            Err(TrySendError::Full((_, source))) => Err(SubmitError::WouldBlock(source)),
+            Err(TrySendError::Disconnected((_, source))) => Err(SubmitError::Closed(source)),
+        }
+    }
+
+    /// Results of finished jobs, in the order they finished
+    pub fn results(&self) -> &Receiver<JobResult> {
+        &self.results
+    }
+
+    /// Stop taking jobs, finish the queued and running ones, and return
+    /// the results nobody has received yet
+    pub fn shutdown(self) -> Result<Vec<JobResult>> {
+        let Self { queue, results, dispatcher, .. } = self;
+        drop(queue);
+        dispatcher.join().map_err(|_| anyhow!("Thumbnail dispatcher panicked"))?;
+        Ok(results.try_iter().collect())
+    }
+
+    fn dispatch(
+        pool: rayon::ThreadPool,
+        config: Arc<PipelineConfig>,
+        jobs: Receiver<(JobId, ThumbnailSource)>,
+        finished: std::sync::mpsc::Sender<JobResult>,
+    ) {
+        // One slot per thread: a job only leaves the queue once a slot is free
+        let slots = pool.current_num_threads();
+        let (release, acquire) = sync_channel(slots);
+        for _ in 0..slots {
+            let _ = release.send(());
+        }
+
+        for (id, source) in jobs {
+            let _ = acquire.recv();
+            let (config, finished, release) = (Arc::clone(&config), finished.clone(), release.clone());
+            pool.spawn(move || {
+                // A panicking codec fails its job instead of leaking the slot
+                let result = catch_unwind(AssertUnwindSafe(|| Self::run(id, source, &config)))
+                    .unwrap_or_else(|_| JobResult { id, variants: Vec::new(), errors: vec!["thumbnailing panicked".to_string()] });
+                let _ = finished.send(result);
+                let _ = release.send(());
+            });
+        }
+
+        for _ in 0..slots {
+            let _ = acquire.recv();
+        }
+    }
+
+    fn run(id: JobId, source: ThumbnailSource, config: &PipelineConfig) -> JobResult {
+        let image = match &source {
+            ThumbnailSource::Path(path) => decode_input(path.as_path()),
+            ThumbnailSource::Bytes(bytes) => decode_input(&bytes[..]),
+        };
+        let image = match image {
+            Ok(image) => image,
+            Err(error) => return JobResult { id, variants: Vec::new(), errors: vec![format!("{:#}", error)] },
+        };
+
+        let codec = IcfCodec::new();
+        let encoded: Vec<(u32, Result<Vec<u8>>)> = config.sizes.par_iter()
+            .map(|&size| (size, codec.encode_with_options(&fit_image(&image, size, &config.resample), &config.options)))
+            .collect();
+
+        let mut result = JobResult { id, variants: Vec::new(), errors: Vec::new() };
+        for (size, data) in encoded {
+            match data {
+                Ok(data) => result.variants.push((size, data)),
+                Err(error) => result.errors.push(format!("{}px: {:#}", size, error)),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+    use std::collections::HashSet;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    fn png(width: u32, height: u32, seed: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([(x * 3 + seed) as u8, (y * 5 + seed * 7) as u8, ((x ^ y) + seed) as u8])
+        });
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image).write_to(&mut bytes, ImageOutputFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_pipeline_thumbnails_batch_and_isolates_corrupt_input() {
+        let config = PipelineConfig { sizes: vec![16, 48, 96], queue_capacity: 4, threads: 3, ..Default::default() };
+        let pipeline = ThumbnailPipeline::new(config).unwrap();
+        let poisoned = 17;
+
+        let mut ids = Vec::new();
+        for index in 0..50 {
+            let source = if index == poisoned { b"\x89PNG\r\n\x1a\nnot really".to_vec() } else { png(120, 80, index) };
+            ids.push(pipeline.submit(source).unwrap());
+        }
+
+        let mut seen = HashSet::new();
+        let codec = IcfCodec::new();
+        for _ in 0..50 {
+            let result = pipeline.results().recv_timeout(Duration::from_secs(60)).unwrap();
+            assert!(seen.insert(result.id));
+            if result.id == ids[poisoned as usize] {
+                assert!(result.variants.is_empty());
+                assert_eq!(result.errors.len(), 1);
+                continue;
+            }
+            assert!(result.errors.is_empty(), "{:?}", result.errors);
+            let sizes: Vec<(u32, u32, u32)> = result.variants.iter()
+                .map(|(size, data)| {
+                    let decoded = codec.decode(data).unwrap();
+                    (*size, decoded.width(), decoded.height())
+                })
+                .collect();
+            assert_eq!(sizes, [(16, 16, 11), (48, 48, 32), (96, 96, 64)]);
+        }
+        assert_eq!(seen, ids.into_iter().collect());
+        assert!(pipeline.shutdown().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_try_submit_reports_full_queue_and_shutdown_drains() {
+        let config = PipelineConfig { sizes: vec![256, 512], queue_capacity: 1, threads: 1, ..Default::default() };
+        let pipeline = ThumbnailPipeline::new(config).unwrap();
+        let source = png(640, 640, 3);
+
+        let mut accepted = 0;
+        let mut refused = 0;
+        for _ in 0..10 {
+            match pipeline.try_submit(source.clone()) {
+                Ok(id) => {
+                    assert_eq!(id, JobId(accepted));
+                    accepted += 1;
+                }
+                Err(SubmitError::WouldBlock(ThumbnailSource::Bytes(bytes))) => {
+                    assert_eq!(bytes, source);
+                    refused += 1;
+                }
+                Err(error) => panic!("{}", error),
+            }
+        }
+        assert!(refused > 0 && accepted <= 3, "{} accepted, {} refused", accepted, refused);
+
+        let results = pipeline.shutdown().unwrap();
+        assert_eq!(results.len() as u64, accepted);
+        assert!(results.iter().all(|result| result.variants.len() == 2));
+    }
+}
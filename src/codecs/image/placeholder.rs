@@ -0,0 +1,239 @@
+use anyhow::{bail, Result};
+use image::{Rgb, RgbImage};
+use std::f64::consts::PI;
+
+/// Edge of the square cells a placeholder is computed from, in pixels:
+/// one 4:2:0 chroma block, or 2x2 luma blocks
+pub(crate) const CELL_SIZE: usize = 16;
+
+/// Components `icf-cli info --placeholder` uses; 28 characters
+pub const DEFAULT_COMPONENTS: (u32, u32) = (4, 3);
+
+const BASE83: &[u8; 83] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Blurhash-style placeholder of an image's pixels
+///
+/// The pixels are averaged over 16x16 cells, and `components_x` by
+/// `components_y` cosine components of the cell grid are quantized and
+/// base83 encoded as blurhash does, so blurhash decoders render it.
+/// `IcfCodec::placeholder` takes the same cell means from DC coefficients,
+/// so both give the same string for an image and its ICF encoding.
+pub fn placeholder_image(img: &RgbImage, components_x: u32, components_y: u32) -> Result<String> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let (columns, rows) = (width.div_ceil(CELL_SIZE), height.div_ceil(CELL_SIZE));
+    let cells: Vec<[f64; 3]> = (0..columns * rows)
+        .map(|index| {
+            let (xs, ys) = cell_span(index % columns, width, index / columns, height);
+            let mut sum = [0.0; 3];
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let pixel = img.get_pixel(x as u32, y as u32).0;
+                    for (total, value) in sum.iter_mut().zip(pixel) {
+                        *total += value as f64 / 255.0;
+                    }
+                }
+            }
+            sum.map(|total| total / (xs.len() * ys.len()) as f64)
+        })
+        .collect();
+    encode_cells(&cells, width, height, components_x, components_y)
+}
+
+/// Pixel columns and rows of cell `(column, row)` inside the image
+pub(crate) fn cell_span(column: usize, width: usize, row: usize, height: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+    let span = |index: usize, len: usize| index * CELL_SIZE..((index + 1) * CELL_SIZE).min(len);
+    (span(column, width), span(row, height))
+}
+
+/// Mean of the first `columns` x `rows` samples of a block, from its
+/// dequantized orthonormal DCT coefficients
+///
+/// Over the whole block this is the DC alone; the AC terms only count for
+/// blocks the image edge cuts through.
+pub(crate) fn region_mean(coefficients: &[[f64; 8]; 8], columns: usize, rows: usize) -> f64 {
+    // Mean of each basis function over the first `n` samples
+    let weights = |n: usize| -> [f64; 8] {
+        std::array::from_fn(|k| {
+            let scale = if k == 0 { 0.125_f64.sqrt() } else { 0.5 };
+            let sum: f64 = (0..n).map(|x| ((2 * x + 1) as f64 * k as f64 * PI / 16.0).cos()).sum();
+            scale * sum / n as f64
+        })
+    };
+    let (across, down) = (weights(columns), weights(rows));
+    (0..8)
+        .flat_map(|v| (0..8).map(move |u| (v, u)))
+        .map(|(v, u)| coefficients[v][u] * down[v] * across[u])
+        .sum()
+}
+
+/// Encode the grid of mean unit-range sRGB colors of every cell of a
+/// `width` x `height` image, in raster order
+///
+/// Cells are weighted by the pixels they cover and sampled at the centre
+/// of those pixels.
+pub(crate) fn encode_cells(cells: &[[f64; 3]], width: usize, height: usize, components_x: u32, components_y: u32) -> Result<String> {
+    if !(1..=9).contains(&components_x) || !(1..=9).contains(&components_y) {
+        bail!("Placeholder components must be between 1 and 9, got {}x{}", components_x, components_y);
+    }
+    if width == 0 || height == 0 {
+        bail!("Can't make a placeholder of an empty image");
+    }
+
+    let columns = width.div_ceil(CELL_SIZE);
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalization = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for (index, cell) in cells.iter().enumerate() {
+                let (xs, ys) = cell_span(index % columns, width, index / columns, height);
+                let center_x = (xs.start + xs.end) as f64 / 2.0 / width as f64;
+                let center_y = (ys.start + ys.end) as f64 / 2.0 / height as f64;
+                let basis = (PI * i as f64 * center_x).cos() * (PI * j as f64 * center_y).cos();
+                let weight = (xs.len() * ys.len()) as f64 * basis * normalization / (width * height) as f64;
+                for (total, value) in factor.iter_mut().zip(cell) {
+                    *total += weight * srgb_to_linear(value.clamp(0.0, 1.0));
+                }
+            }
+            factors.push(factor);
+        }
+    }
+
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    base83(&mut hash, (components_x - 1) + (components_y - 1) * 9, 1);
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let maximum = ac.iter().flatten().fold(0.0_f64, |maximum, value| maximum.max(value.abs()));
+    let scale = if ac.is_empty() {
+        base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let quantized = (maximum * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        base83(&mut hash, quantized, 1);
+        (quantized + 1) as f64 / 166.0
+    };
+    let [r, g, b] = dc.map(|value| linear_to_srgb(value) as u32);
+    base83(&mut hash, (r << 16) | (g << 8) | b, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| {
+            let value = value / scale;
+            (value.signum() * value.abs().sqrt() * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+        });
+        base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    Ok(hash)
+}
+
+/// Render a placeholder from `placeholder_image` or `IcfCodec::placeholder`
+/// at `width` x `height`
+pub fn render_placeholder(hash: &str, width: u32, height: u32) -> Result<RgbImage> {
+    let digits = hash.bytes()
+        .map(|byte| BASE83.iter().position(|&digit| digit == byte).map(|digit| digit as u32))
+        .collect::<Option<Vec<u32>>>();
+    let Some(digits) = digits.filter(|digits| digits.len() >= 6) else {
+        bail!("Invalid placeholder {:?}", hash);
+    };
+    let number = |range: std::ops::Range<usize>| digits[range].iter().fold(0, |value, digit| value * 83 + digit);
+    let (components_x, components_y) = (digits[0] % 9 + 1, digits[0] / 9 + 1);
+    let count = (components_x * components_y) as usize;
+    if digits.len() != 4 + 2 * count {
+        bail!("Invalid placeholder {:?}: {}x{} components need {} characters", hash, components_x, components_y, 4 + 2 * count);
+    }
+
+    let scale = (digits[1] + 1) as f64 / 166.0;
+    let dc = number(2..6);
+    let mut factors = vec![[dc >> 16, (dc >> 8) & 255, dc & 255].map(|value| srgb_to_linear(value as f64 / 255.0))];
+    for component in 1..count {
+        let value = number(4 + 2 * component..6 + 2 * component);
+        factors.push([value / (19 * 19), value / 19 % 19, value % 19].map(|quantized| {
+            let value = (quantized as f64 - 9.0) / 9.0;
+            value.signum() * value * value * scale
+        }));
+    }
+
+    Ok(RgbImage::from_fn(width, height, |x, y| {
+        let (u, v) = ((x as f64 + 0.5) / width as f64, (y as f64 + 0.5) / height as f64);
+        let mut color = [0.0; 3];
+        for (index, factor) in factors.iter().enumerate() {
+            let (i, j) = (index as u32 % components_x, index as u32 / components_x);
+            let basis = (PI * i as f64 * u).cos() * (PI * j as f64 * v).cos();
+            for (total, value) in color.iter_mut().zip(factor) {
+                *total += basis * value;
+            }
+        }
+        Rgb(color.map(linear_to_srgb))
+    }))
+}
+
+fn base83(out: &mut String, value: u32, length: u32) {
+    for position in (0..length).rev() {
+        out.push(BASE83[(value / 83u32.pow(position) % 83) as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f64) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::image::{ChromaSubsampling, IcfCodec, IcfEncodeOptions, SubsamplingMode};
+    use image::DynamicImage;
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+            Rgb([(40.0 + 180.0 * u) as u8, (200.0 - 120.0 * v + 30.0 * u) as u8, (90.0 + 60.0 * (u * 5.0).sin() * v) as u8])
+        })
+    }
+
+    /// Mean color in linear light, as blurhash averages, in sRGB levels
+    fn mean_color(img: &RgbImage) -> [f64; 3] {
+        let mut sum = [0.0; 3];
+        for pixel in img.pixels() {
+            for (total, value) in sum.iter_mut().zip(pixel.0) {
+                *total += srgb_to_linear(value as f64 / 255.0);
+            }
+        }
+        sum.map(|total| linear_to_srgb(total / (img.width() * img.height()) as f64) as f64)
+    }
+
+    #[test]
+    fn test_icf_placeholder_matches_decoded_pixels() {
+        let codec = IcfCodec::new();
+        for (img, subsampling) in [(gradient(160, 96), ChromaSubsampling::S444), (gradient(120, 90), ChromaSubsampling::S420), (gradient(45, 30), ChromaSubsampling::S444)] {
+            let options = IcfEncodeOptions {
+                chroma_subsampling: SubsamplingMode::Fixed(subsampling),
+                ..IcfEncodeOptions::with_quality(90)
+            };
+            let encoded = codec.encode_with_options(&DynamicImage::ImageRgb8(img.clone()), &options).unwrap();
+            let decoded = codec.decode(&encoded).unwrap().to_rgb8();
+
+            let hash = codec.placeholder(&encoded, 4, 3).unwrap();
+            assert_eq!(hash.len(), 28);
+            assert_eq!(hash, placeholder_image(&decoded, 4, 3).unwrap(), "{}", subsampling);
+        }
+        assert!(placeholder_image(&gradient(8, 8), 10, 1).is_err());
+    }
+
+    #[test]
+    fn test_rendered_placeholder_keeps_average_color() {
+        let img = gradient(100, 75);
+        for (x, y) in [(1, 1), (4, 3), (9, 9)] {
+            let hash = placeholder_image(&img, x, y).unwrap();
+            let rendered = render_placeholder(&hash, 32, 24).unwrap();
+            let (original, blurred) = (mean_color(&img), mean_color(&rendered));
+            for channel in 0..3 {
+                assert!((original[channel] - blurred[channel]).abs() < 4.0, "{}x{}: {:?} vs {:?}", x, y, original, blurred);
+            }
+        }
+        assert!(render_placeholder("LKO2?U%2", 4, 4).is_err());
+        assert!(render_placeholder("not base83!", 4, 4).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::codecs::image::dct_transform::ColorSpace;
+
+/// Chroma plane resolution relative to luma
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromaSubsampling {
+    /// Full-resolution chroma
+    #[default]
+    #[serde(rename = "4:4:4")]
+    S444,
+    /// Chroma halved in both directions
+    #[serde(rename = "4:2:0")]
+    S420,
+}
+
+impl ChromaSubsampling {
+    /// Downsampling factor applied to each chroma dimension
+    pub fn factor(self) -> u32 {
+        match self {
+            ChromaSubsampling::S444 => 1,
+            ChromaSubsampling::S420 => 2,
+        }
+    }
+}
+
+impl fmt::Display for ChromaSubsampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChromaSubsampling::S444 => "4:4:4",
+            ChromaSubsampling::S420 => "4:2:0",
+        })
+    }
+}
+
+impl FromStr for ChromaSubsampling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "444" | "4:4:4" => Ok(ChromaSubsampling::S444),
+            "420" | "4:2:0" => Ok(ChromaSubsampling::S420),
+            _ => Err(anyhow!("Unknown chroma subsampling '{}' (expected 444 or 420)", s)),
+        }
+    }
+}
+
+/// Chroma subsampling an encode asks for
+///
+/// `Auto` picks 4:4:4 or 4:2:0 per image from how much fine chroma detail
+/// it has; the header records the choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubsamplingMode {
+    Fixed(ChromaSubsampling),
+    Auto,
+}
+
+impl From<ChromaSubsampling> for SubsamplingMode {
+    fn from(subsampling: ChromaSubsampling) -> Self {
+        SubsamplingMode::Fixed(subsampling)
+    }
+}
+
+impl fmt::Display for SubsamplingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubsamplingMode::Fixed(subsampling) => subsampling.fmt(f),
+            SubsamplingMode::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+impl FromStr for SubsamplingMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(SubsamplingMode::Auto),
+            _ => s.parse().map(SubsamplingMode::Fixed),
+        }
+    }
+}
+
+/// Color space the planes are coded in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IcfColorSpace {
+    /// Cheap integer-friendly transform, good for synthetic content
+    #[default]
+    YCoCg,
+    /// JFIF (BT.601 full-range) luma/chroma, good for photographs
+    YCbCr,
+}
+
+impl IcfColorSpace {
+    /// Convert unit-range RGB to (luma, chroma, chroma) with chroma centered on 0
+    pub fn from_rgb(self, r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+        match self {
+            IcfColorSpace::YCoCg => ColorSpace::rgb_to_ycocg(r, g, b),
+            IcfColorSpace::YCbCr => ColorSpace::rgb_to_ycbcr(r, g, b),
+        }
+    }
+
+    /// Inverse of `from_rgb`
+    pub fn to_rgb(self, luma: f64, c1: f64, c2: f64) -> (f64, f64, f64) {
+        match self {
+            IcfColorSpace::YCoCg => ColorSpace::ycocg_to_rgb(luma, c1, c2),
+            IcfColorSpace::YCbCr => ColorSpace::ycbcr_to_rgb(luma, c1, c2),
+        }
+    }
+}
+
+impl fmt::Display for IcfColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IcfColorSpace::YCoCg => "YCoCg",
+            IcfColorSpace::YCbCr => "YCbCr",
+        })
+    }
+}
+
+impl FromStr for IcfColorSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ycocg" => Ok(IcfColorSpace::YCoCg),
+            "ycbcr" => Ok(IcfColorSpace::YCbCr),
+            _ => Err(anyhow!("Unknown color space '{}' (expected ycocg or ycbcr)", s)),
+        }
+    }
+}
+
+/// How the quantization tables are derived
+///
+/// The tables themselves are always stored in the header, so this only
+/// records the encoder's choice; decoding doesn't depend on it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantTableKind {
+    /// JPEG Annex K tables scaled by quality
+    #[default]
+    Standard,
+    /// Contrast-sensitivity weighted luma table
+    Perceptual,
+    /// Tables adapted to the image's own DCT statistics
+    Optimized,
+    /// Tables copied from a transcoded JPEG; the encoder can't derive these
+    Jpeg,
+}
+
+impl fmt::Display for QuantTableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuantTableKind::Standard => "standard",
+            QuantTableKind::Perceptual => "perceptual",
+            QuantTableKind::Optimized => "optimized",
+            QuantTableKind::Jpeg => "jpeg",
+        })
+    }
+}
+
+impl FromStr for QuantTableKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standard" => Ok(QuantTableKind::Standard),
+            "perceptual" => Ok(QuantTableKind::Perceptual),
+            "optimized" => Ok(QuantTableKind::Optimized),
+            _ => Err(anyhow!("Unknown table set '{}' (expected standard, perceptual or optimized)", s)),
+        }
+    }
+}
+
+/// Named bundle of encoder parameters for a common use case
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcfProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub quality: u8,
+    pub chroma_subsampling: ChromaSubsampling,
+    pub color_space: IcfColorSpace,
+    pub quantization: QuantTableKind,
+    /// Tile edge in pixels, or `None` for a single untiled image
+    pub tile_size: Option<u32>,
+}
+
+impl IcfProfile {
+    pub const PHOTO: IcfProfile = IcfProfile {
+        name: "photo",
+        description: "Camera images: subsampled chroma, image-tuned tables",
+        quality: 80,
+        chroma_subsampling: ChromaSubsampling::S420,
+        color_space: IcfColorSpace::YCbCr,
+        quantization: QuantTableKind::Optimized,
+        tile_size: Some(512),
+    };
+
+    pub const SCREENSHOT: IcfProfile = IcfProfile {
+        name: "screenshot",
+        description: "UI and text: full chroma so colored edges stay sharp",
+        quality: 92,
+        chroma_subsampling: ChromaSubsampling::S444,
+        color_space: IcfColorSpace::YCoCg,
+        quantization: QuantTableKind::Standard,
+        tile_size: Some(256),
+    };
+
+    pub const ARCHIVAL: IcfProfile = IcfProfile {
+        name: "archival",
+        description: "Long-term storage: near-transparent quality",
+        quality: 95,
+        chroma_subsampling: ChromaSubsampling::S444,
+        color_space: IcfColorSpace::YCoCg,
+        quantization: QuantTableKind::Perceptual,
+        tile_size: Some(512),
+    };
+
+    pub const THUMBNAIL: IcfProfile = IcfProfile {
+        name: "thumbnail",
+        description: "Small previews: smallest files",
+        quality: 65,
+        chroma_subsampling: ChromaSubsampling::S420,
+        color_space: IcfColorSpace::YCbCr,
+        quantization: QuantTableKind::Standard,
+        tile_size: None,
+    };
+
+    /// Look up a profile in `PROFILES` by name
+    pub fn by_name(name: &str) -> Option<&'static IcfProfile> {
+        PROFILES.iter().find(|profile| profile.name == name)
+    }
+}
+
+/// Every built-in profile, in the order `icf-cli profiles` lists them
+pub const PROFILES: &[IcfProfile] = &[
+    IcfProfile::PHOTO,
+    IcfProfile::SCREENSHOT,
+    IcfProfile::ARCHIVAL,
+    IcfProfile::THUMBNAIL,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_are_valid_and_unique() {
+        for (i, profile) in PROFILES.iter().enumerate() {
+            assert!((1..=100).contains(&profile.quality));
+            assert!(profile.tile_size.is_none_or(|size| size > 0 && size % 16 == 0));
+            assert_eq!(IcfProfile::by_name(profile.name), Some(&PROFILES[i]));
+        }
+        assert!(IcfProfile::by_name("missing").is_none());
+    }
+
+    #[test]
+    fn test_parameter_names_roundtrip() {
+        for subsampling in [ChromaSubsampling::S444, ChromaSubsampling::S420] {
+            assert_eq!(subsampling.to_string().parse::<ChromaSubsampling>().unwrap(), subsampling);
+        }
+        for mode in [SubsamplingMode::Auto, ChromaSubsampling::S420.into()] {
+            assert_eq!(mode.to_string().parse::<SubsamplingMode>().unwrap(), mode);
+        }
+        for color_space in [IcfColorSpace::YCoCg, IcfColorSpace::YCbCr] {
+            assert_eq!(color_space.to_string().parse::<IcfColorSpace>().unwrap(), color_space);
+            // Header JSON keeps the historical spelling
+            assert_eq!(serde_json::to_string(&color_space).unwrap(), format!("\"{}\"", color_space));
+        }
+        for kind in [QuantTableKind::Standard, QuantTableKind::Perceptual, QuantTableKind::Optimized] {
+            assert_eq!(kind.to_string().parse::<QuantTableKind>().unwrap(), kind);
+        }
+    }
+
+    #[test]
+    fn test_ycbcr_roundtrip() {
+        let (y, cb, cr) = IcfColorSpace::YCbCr.from_rgb(0.5, 0.7, 0.3);
+        let (r, g, b) = IcfColorSpace::YCbCr.to_rgb(y, cb, cr);
+        assert!((r - 0.5).abs() < 1e-4 && (g - 0.7).abs() < 1e-4 && (b - 0.3).abs() < 1e-4);
+    }
+}
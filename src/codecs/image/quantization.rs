@@ -26,6 +26,18 @@ impl Quantization {
         [99.0, 99.0, 99.0, 99.0, 99.0, 99.0, 99.0, 99.0],
     ];
 
+    /// Quantization steps are multiples of 1/16
+    ///
+    /// Every table built here is rounded to this grid before use, so the
+    /// steps ICF headers store are exact, short decimals that don't depend
+    /// on the float operations they were derived with.
+    pub const STEP_DENOMINATOR: f64 = 16.0;
+
+    /// Round a step to the `STEP_DENOMINATOR` grid, and to at least 1
+    pub fn round_step(step: f64) -> f64 {
+        ((step * Self::STEP_DENOMINATOR).round() / Self::STEP_DENOMINATOR).max(1.0)
+    }
+
     /// Create scaled quantization table based on quality (1-100)
     pub fn create_quantization_table(quality: u8, is_luminance: bool) -> [[f64; 8]; 8] {
         let base_table = if is_luminance {
@@ -44,7 +56,7 @@ impl Quantization {
         for i in 0..8 {
             for j in 0..8 {
                 let scaled_value = (base_table[i][j] * scale_factor / 100.0).floor();
-                scaled_table[i][j] = scaled_value.max(1.0); // Minimum value of 1
+                scaled_table[i][j] = Self::round_step(scaled_value);
             }
         }
 
@@ -71,7 +83,7 @@ impl Quantization {
         for i in 0..8 {
             for j in 0..8 {
                 let frequency_weight = ((i + j) as f64 / 14.0).max(0.5);
-                adaptive_table[i][j] = (base_table[i][j] / (adaptation_factor * frequency_weight)).max(1.0);
+                adaptive_table[i][j] = Self::round_step(base_table[i][j] / (adaptation_factor * frequency_weight));
             }
         }
 
@@ -131,7 +143,7 @@ impl Quantization {
             for j in 0..8 {
                 // Apply CSF weighting and viewing distance adjustment
                 let csf_factor = csf_weights[i][j] * viewing_distance.sqrt();
-                table[i][j] = (base_quantizer / (100.0 * csf_factor)).max(1.0);
+                table[i][j] = Self::round_step(base_quantizer / (100.0 * csf_factor));
             }
         }
 
@@ -331,4 +343,22 @@ mod tests {
         // Higher frequency components should have larger quantization values
         assert!(perceptual_table[0][0] < perceptual_table[7][7]);
     }
+
+    #[test]
+    fn test_tables_are_on_step_grid() {
+        let mut block = [[0.0; 8]; 8];
+        block[0][0] = 300.0;
+        block[3][5] = -7.3;
+        for quality in [1, 33, 50, 77, 100] {
+            let tables = [
+                Quantization::create_quantization_table(quality, true),
+                Quantization::create_quantization_table(quality, false),
+                Quantization::perceptual_quantization_table(quality, 1.0),
+                Quantization::adaptive_quantization_table(&block, quality, true),
+            ];
+            for step in tables.iter().flatten().flatten() {
+                assert!(*step >= 1.0 && (step * Quantization::STEP_DENOMINATOR).fract() == 0.0, "step {}", step);
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+//! Encoder size and quality regression checks
+//!
+//! Deterministic corpora are encoded at a few quality levels and compared
+//! with `regression_baseline.json`: each size must land within the recorded
+//! band and the PSNR must stay above its floor. After an intended change,
+//! regenerate the baseline with
+//! `cargo test --lib regression::tests::bless -- --ignored`.
+use super::icf_codec::{IcfCodec, IcfEncodeOptions};
+use crate::codecs::video::WeightedPsnr;
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Quality levels every corpus is encoded at
+pub(crate) const QUALITIES: [u8; 3] = [30, 60, 90];
+/// Allowed relative deviation from a blessed size, either way
+const SIZE_TOLERANCE: f64 = 0.05;
+/// How far below a blessed PSNR the floor sits, in dB
+const PSNR_MARGIN: f64 = 0.5;
+
+const BASELINE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/codecs/image/regression_baseline.json");
+
+/// Accepted size range and PSNR floor for one corpus at one quality
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Expectation {
+    pub min_size: usize,
+    pub max_size: usize,
+    pub min_psnr: f64,
+}
+
+impl Expectation {
+    fn bless(size: usize, psnr: f64) -> Self {
+        Self {
+            min_size: (size as f64 * (1.0 - SIZE_TOLERANCE)).floor() as usize,
+            max_size: (size as f64 * (1.0 + SIZE_TOLERANCE)).ceil() as usize,
+            min_psnr: ((psnr - PSNR_MARGIN) * 10.0).floor() / 10.0,
+        }
+    }
+}
+
+/// Expectations by corpus name, then quality
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct Baseline {
+    pub corpora: BTreeMap<String, BTreeMap<u8, Expectation>>,
+}
+
+impl Baseline {
+    /// The committed baseline
+    pub fn committed() -> Result<Self> {
+        serde_json::from_str(include_str!("regression_baseline.json")).context("Invalid ICF regression baseline")
+    }
+
+    /// Fail if `size` or `psnr` falls outside what's recorded for `corpus` at `quality`
+    pub fn check(&self, corpus: &str, quality: u8, size: usize, psnr: f64) -> Result<()> {
+        let expected = self.corpora.get(corpus)
+            .and_then(|levels| levels.get(&quality))
+            .with_context(|| format!("No baseline for {} at quality {}", corpus, quality))?;
+        if !(expected.min_size..=expected.max_size).contains(&size) {
+            bail!("{} at quality {}: size {} outside {}..={}", corpus, quality, size, expected.min_size, expected.max_size);
+        }
+        if psnr < expected.min_psnr {
+            bail!("{} at quality {}: PSNR {:.2} dB below floor {:.1}", corpus, quality, psnr, expected.min_psnr);
+        }
+        Ok(())
+    }
+}
+
+/// One corpus encoded at one quality
+pub(crate) struct Measurement {
+    pub corpus: &'static str,
+    pub quality: u8,
+    pub encoded: Vec<u8>,
+    pub psnr: f64,
+}
+
+fn xorshift(seed: u32) -> impl FnMut() -> u32 {
+    let mut state = seed;
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
+
+/// Named test images built with integer arithmetic only, so they're the
+/// same on every platform
+pub(crate) fn corpora() -> Vec<(&'static str, RgbImage)> {
+    let mut next = xorshift(0x2545_f491);
+    let noise = RgbImage::from_fn(64, 48, |_, _| {
+        let value = next();
+        Rgb([value as u8, (value >> 8) as u8, (value >> 16) as u8])
+    });
+    let mut next = xorshift(0x0bad_cafe);
+    let photo = RgbImage::from_fn(72, 56, |x, y| {
+        let grain = (next() % 9) as i32 - 4;
+        let level = |base: i32| (base + grain).clamp(0, 255) as u8;
+        if (x as i32 - 40).pow(2) + (y as i32 - 24).pow(2) < 300 {
+            Rgb([level(210), level(170 - y as i32), level(50)])
+        } else {
+            Rgb([level(3 * x as i32), level(30 + 3 * y as i32), level(120)])
+        }
+    });
+    vec![
+        ("gradient", RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8]))),
+        ("checker", RgbImage::from_fn(64, 48, |x, y| if (x / 4 + y / 4) % 2 == 0 { Rgb([240, 240, 240]) } else { Rgb([20, 40, 160]) })),
+        ("noise", noise),
+        ("photo", photo),
+    ]
+}
+
+/// Encode every corpus at every quality in `QUALITIES`
+pub(crate) fn measure() -> Result<Vec<Measurement>> {
+    let codec = IcfCodec::new();
+    let mut measurements = Vec::new();
+    for (corpus, image) in corpora() {
+        let source = DynamicImage::ImageRgb8(image);
+        for quality in QUALITIES {
+            let encoded = codec.encode_with_options(&source, &IcfEncodeOptions::with_quality(quality))?;
+            let decoded = codec.decode(&encoded)?.to_rgb8();
+            let psnr = WeightedPsnr::default().compare(source.as_rgb8().context("Corpus isn't RGB8")?, &decoded)?.psnr;
+            measurements.push(Measurement { corpus, quality, encoded, psnr });
+        }
+    }
+    Ok(measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::image::{IcfReader, Quantization};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_encoder_matches_baseline() {
+        let baseline = Baseline::committed().unwrap();
+        let failures: Vec<String> = measure().unwrap().iter()
+            .filter_map(|m| baseline.check(m.corpus, m.quality, m.encoded.len(), m.psnr).err())
+            .map(|error| error.to_string())
+            .collect();
+        assert!(failures.is_empty(), "ICF encoder regressed; if intended, bless a new baseline:\n{}", failures.join("\n"));
+    }
+
+    /// File size if AC coefficients were stored one by one instead of run-length coded
+    fn size_without_rle(encoded: &[u8]) -> usize {
+        let mut reader = IcfReader::new(Cursor::new(encoded)).unwrap();
+        let payload = reader.header().compressed_size as usize;
+        let mut blocks = Vec::new();
+        reader.for_each_block(|mut block| {
+            let zigzag = Quantization::run_length_decode(&block.ac_coefficients);
+            block.ac_coefficients = zigzag[..63].iter().map(|&value| (0, value)).collect();
+            blocks.push(block);
+            Ok(())
+        }).unwrap();
+        encoded.len() - payload + serde_json::to_vec(&blocks).unwrap().len()
+    }
+
+    #[test]
+    fn test_disabling_rle_trips_size_band() {
+        let baseline = Baseline::committed().unwrap();
+        for m in measure().unwrap() {
+            let error = baseline.check(m.corpus, m.quality, size_without_rle(&m.encoded), m.psnr).err()
+                .unwrap_or_else(|| panic!("{} at quality {} stayed in band without RLE", m.corpus, m.quality));
+            assert!(error.to_string().contains("size"), "{}", error);
+        }
+    }
+
+    /// Regenerate `regression_baseline.json` from the current encoder
+    #[test]
+    #[ignore]
+    fn bless() {
+        let mut baseline = Baseline::default();
+        for m in measure().unwrap() {
+            baseline.corpora.entry(m.corpus.to_string()).or_default()
+                .insert(m.quality, Expectation::bless(m.encoded.len(), m.psnr));
+        }
+        let json = serde_json::to_string_pretty(&baseline).unwrap();
+        std::fs::write(BASELINE_PATH, json + "\n").unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use crate::codecs::plane::Plane;
+use crate::codecs::precision::round_table_value;
+use image::{DynamicImage, GenericImageView, RgbImage, RgbaImage};
+
+/// Downscales by at least this factor along an axis average whole source
+/// pixels instead of interpolating
+pub const AREA_MIN_FACTOR: f64 = 2.0;
+
+/// How samples along one axis are resampled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleFilter {
+    /// Mean of the source samples each output sample covers, weighted by
+    /// how much of each it covers; alias-free for large downscales
+    Area,
+    /// Catmull-Rom cubic, stretched to the scale factor when downscaling
+    CatmullRom,
+}
+
+impl ResampleFilter {
+    /// The filter for scaling `source` samples to `target`: area averaging
+    /// from `AREA_MIN_FACTOR` down, Catmull-Rom for anything milder and
+    /// for upscales
+    pub fn for_scale(source: usize, target: usize) -> Self {
+        if source as f64 >= target as f64 * AREA_MIN_FACTOR {
+            ResampleFilter::Area
+        } else {
+            ResampleFilter::CatmullRom
+        }
+    }
+}
+
+/// Options for `resize_image` and `fit_image`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResampleOptions {
+    /// Filter for both axes, or `None` to pick one per axis with
+    /// `ResampleFilter::for_scale`
+    pub filter: Option<ResampleFilter>,
+    /// Average in linear light rather than on sRGB levels, so fine
+    /// light/dark detail keeps its brightness; alpha is always linear
+    pub linear_light: bool,
+}
+
+/// Resample `plane` to `width` x `height`
+///
+/// Separable: rows are resampled, then columns. Samples past the edges
+/// repeat the edge sample.
+pub fn resample_plane(plane: &Plane<f32>, width: usize, height: usize, filter: Option<ResampleFilter>) -> Plane<f32> {
+    let columns = axis_weights(plane.width, width, filter.unwrap_or_else(|| ResampleFilter::for_scale(plane.width, width)));
+    let rows = axis_weights(plane.height, height, filter.unwrap_or_else(|| ResampleFilter::for_scale(plane.height, height)));
+
+    let mut across = Vec::with_capacity(width * plane.height);
+    for y in 0..plane.height {
+        let row = plane.row(y);
+        across.extend(columns.iter().map(|(start, weights)| {
+            weights.iter().zip(&row[*start..]).map(|(weight, sample)| weight * sample).sum::<f32>()
+        }));
+    }
+
+    let mut data = vec![0.0; width * height];
+    for (y, (start, weights)) in rows.iter().enumerate() {
+        let out = &mut data[y * width..(y + 1) * width];
+        for (offset, weight) in weights.iter().enumerate() {
+            let source = &across[(start + offset) * width..(start + offset + 1) * width];
+            for (value, sample) in out.iter_mut().zip(source) {
+                *value += weight * sample;
+            }
+        }
+    }
+    Plane::from_vec(width, height, data)
+}
+
+/// First source sample and the weights from there on of each of `target`
+/// samples resampled from `source`; the weights of each sum to one
+fn axis_weights(source: usize, target: usize, filter: ResampleFilter) -> Vec<(usize, Vec<f32>)> {
+    let scale = source as f64 / target as f64;
+    (0..target)
+        .map(|index| {
+            let mut weights = Vec::new();
+            let start = match filter {
+                ResampleFilter::Area => {
+                    let (from, to) = (index as f64 * scale, (index + 1) as f64 * scale);
+                    let start = from.floor() as usize;
+                    for sample in start..(to.ceil() as usize).min(source) {
+                        let covered = (to.min(sample as f64 + 1.0) - from.max(sample as f64)).max(0.0);
+                        weights.push(covered);
+                    }
+                    start
+                }
+                ResampleFilter::CatmullRom => {
+                    let stretch = scale.max(1.0);
+                    let center = (index as f64 + 0.5) * scale - 0.5;
+                    let first = (center - 2.0 * stretch).ceil() as isize;
+                    let last = (center + 2.0 * stretch).floor() as isize;
+                    // Taps past an edge add to the edge sample
+                    let start = first.clamp(0, source as isize - 1) as usize;
+                    let end = last.clamp(0, source as isize - 1) as usize;
+                    weights.resize(end - start + 1, 0.0);
+                    for tap in first..=last {
+                        let sample = tap.clamp(0, source as isize - 1) as usize;
+                        weights[sample - start] += catmull_rom((tap as f64 - center) / stretch);
+                    }
+                    start
+                }
+            };
+            let total: f64 = weights.iter().sum();
+            (start, weights.into_iter().map(|weight| (weight / total) as f32).collect())
+        })
+        .collect()
+}
+
+/// The Catmull-Rom kernel (cubic with B = 0, C = 1/2)
+fn catmull_rom(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        (1.5 * x - 2.5) * x * x + 1.0
+    } else if x < 2.0 {
+        ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+/// `img` resampled to `width` x `height`, as RGB, or RGBA if it has alpha
+///
+/// Rounding back to 8 bits carries each sample's error into the next one
+/// along the row, so gradients smoothed by a large downscale don't band.
+pub fn resize_image(img: &DynamicImage, width: u32, height: u32, options: &ResampleOptions) -> DynamicImage {
+    let (width, height) = (width.max(1), height.max(1));
+    let alpha = img.color().has_alpha();
+    let channels = if alpha { 4 } else { 3 };
+    let rgba = img.to_rgba8();
+    let levels = Levels::new(options.linear_light);
+
+    let planes: Vec<Plane<f32>> = (0..channels)
+        .map(|channel| {
+            let samples = rgba.pixels().map(|pixel| {
+                let value = pixel.0[channel];
+                if channel == 3 { value as f32 } else { levels.to_working[value as usize] }
+            });
+            let plane = Plane::from_vec(img.width() as usize, img.height() as usize, samples.collect());
+            resample_plane(&plane, width as usize, height as usize, options.filter)
+        })
+        .collect();
+
+    let mut data = vec![0u8; width as usize * height as usize * channels];
+    for (channel, plane) in planes.iter().enumerate() {
+        for y in 0..height as usize {
+            let mut carry = 0.0;
+            for (x, &value) in plane.row(y).iter().enumerate() {
+                let (level, error) = if channel == 3 { Levels::quantize_level(value + carry) } else { levels.quantize(value + carry) };
+                data[(y * width as usize + x) * channels + channel] = level;
+                carry = error;
+            }
+        }
+    }
+
+    if alpha {
+        DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).expect("sized above"))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data).expect("sized above"))
+    }
+}
+
+/// Largest size within a `max_dim` square with the aspect ratio of
+/// `width` x `height`; never larger than it already is
+pub fn fit_dimensions(width: u32, height: u32, max_dim: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_dim {
+        return (width, height);
+    }
+    let scale = |side: u32| ((side as u64 * max_dim as u64 + longest as u64 / 2) / longest as u64).max(1) as u32;
+    (scale(width), scale(height))
+}
+
+/// `img` scaled down to fit a `max_dim` square, keeping its aspect ratio;
+/// returned as is if it already fits
+pub fn fit_image(img: &DynamicImage, max_dim: u32, options: &ResampleOptions) -> DynamicImage {
+    let (width, height) = fit_dimensions(img.width(), img.height(), max_dim);
+    if (width, height) == img.dimensions() {
+        img.clone()
+    } else {
+        resize_image(img, width, height, options)
+    }
+}
+
+/// Conversions between 8-bit sRGB levels and the values resampled
+struct Levels {
+    /// Working value of each level
+    to_working: [f32; 256],
+    /// Working value halfway between each level and the next, ascending;
+    /// `None` when working values are levels, which just round
+    thresholds: Option<[f32; 255]>,
+}
+
+impl Levels {
+    fn new(linear_light: bool) -> Self {
+        if !linear_light {
+            return Self { to_working: std::array::from_fn(|level| level as f32), thresholds: None };
+        }
+        let linear = |srgb: f64| round_table_value(if srgb <= 0.04045 { srgb / 12.92 } else { ((srgb + 0.055) / 1.055).powf(2.4) }) as f32;
+        Self {
+            to_working: std::array::from_fn(|level| linear(level as f64 / 255.0)),
+            thresholds: Some(std::array::from_fn(|level| linear((level as f64 + 0.5) / 255.0))),
+        }
+    }
+
+    /// Nearest level to working value `value`, and what it misses by
+    fn quantize(&self, value: f32) -> (u8, f32) {
+        let Some(thresholds) = &self.thresholds else {
+            return Self::quantize_level(value);
+        };
+        let level = thresholds.partition_point(|&threshold| threshold < value);
+        (level as u8, value - self.to_working[level])
+    }
+
+    fn quantize_level(value: f32) -> (u8, f32) {
+        let level = value.round().clamp(0.0, 255.0);
+        (level as u8, value - level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::FilterType;
+    use image::Rgb;
+    use std::f64::consts::PI;
+
+    fn photo_like(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+            let detail = 40.0 * (u * 23.0).sin() * (v * 17.0).cos();
+            Rgb([(100.0 + 80.0 * u + detail) as u8, (60.0 + 120.0 * v - detail / 2.0) as u8, (180.0 - 90.0 * u * v + detail / 3.0) as u8])
+        }))
+    }
+
+    fn mean_error(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        let (a, b) = (a.to_rgb8(), b.to_rgb8());
+        assert_eq!(a.dimensions(), b.dimensions());
+        let total: u64 = a.as_raw().iter().zip(b.as_raw()).map(|(&x, &y)| x.abs_diff(y) as u64).sum();
+        total as f64 / a.as_raw().len() as f64
+    }
+
+    #[test]
+    fn test_resize_tracks_image_crate_filters() {
+        let img = photo_like(403, 301);
+        let options = ResampleOptions::default();
+        // Moderate scales interpolate, large downscales average
+        for ((width, height), reference) in [((270, 201), FilterType::CatmullRom), ((520, 390), FilterType::CatmullRom), ((67, 50), FilterType::Triangle)] {
+            let ours = resize_image(&img, width, height, &options);
+            let theirs = img.resize_exact(width, height, reference);
+            let error = mean_error(&ours, &theirs);
+            assert!(error < 1.0, "{}x{}: mean error {:.3}", width, height, error);
+        }
+        assert_eq!(ResampleFilter::for_scale(403, 201), ResampleFilter::Area);
+        assert_eq!(ResampleFilter::for_scale(403, 270), ResampleFilter::CatmullRom);
+        assert_eq!(fit_dimensions(120, 80, 16), (16, 11));
+        assert_eq!(fit_dimensions(80, 120, 400), (80, 120));
+
+        // Black and white pixels average to mid-grey light, not level 128
+        let checker = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| Rgb([if (x + y) % 2 == 0 { 0 } else { 255 }; 3])));
+        let linear = ResampleOptions { linear_light: true, ..Default::default() };
+        for (options, levels) in [(options, 127..=128), (linear, 187..=188)] {
+            let levels_used = resize_image(&checker, 16, 16, &options).to_rgb8().into_raw();
+            assert!(levels_used.iter().all(|level| levels.contains(level)), "{:?}: {:?}", options, &levels_used[..12]);
+        }
+    }
+
+    /// Energy of the nonzero frequencies below a quarter of the Nyquist
+    /// rate, as an RMS level
+    fn low_frequency_rms(img: &RgbImage) -> f64 {
+        let n = img.width() as usize;
+        let band = n / 8;
+        let samples: Vec<f64> = img.pixels().map(|pixel| pixel.0[0] as f64).collect();
+        let mut energy = 0.0;
+        for v in 0..=band {
+            for u in 0..=band {
+                if (u, v) == (0, 0) {
+                    continue;
+                }
+                let (mut re, mut im) = (0.0, 0.0);
+                for (index, sample) in samples.iter().enumerate() {
+                    let phase = 2.0 * PI * ((u * (index % n)) as f64 + (v * (index / n)) as f64) / n as f64;
+                    re += sample * phase.cos();
+                    im -= sample * phase.sin();
+                }
+                energy += (re * re + im * im) / (n * n) as f64;
+            }
+        }
+        (energy / (n * n) as f64).sqrt()
+    }
+
+    #[test]
+    fn test_area_downscale_does_not_alias_zone_plate() {
+        // Local frequency runs from 0.25 to 0.43 cycles per pixel, all of it
+        // above the Nyquist rate of the 8x smaller image, which should be flat
+        let (offset, k) = (700.0, 0.25 * PI / (700.0 * 2f64.sqrt()));
+        let plate = RgbImage::from_fn(512, 512, |x, y| {
+            let (x, y) = (x as f64 + offset, y as f64 + offset);
+            Rgb([(127.5 + 127.0 * (k * (x * x + y * y)).cos()) as u8; 3])
+        });
+        let plate = DynamicImage::ImageRgb8(plate);
+
+        let area = resize_image(&plate, 64, 64, &ResampleOptions::default()).to_rgb8();
+        let nearest = plate.resize_exact(64, 64, FilterType::Nearest).to_rgb8();
+        let (area_rms, nearest_rms) = (low_frequency_rms(&area), low_frequency_rms(&nearest));
+        assert!(area_rms < 1.0, "area averaging left {:.2} RMS of low-frequency aliasing", area_rms);
+        // Point sampling folds the same rings down into visible moiré
+        assert!(nearest_rms > 5.0, "nearest {:.2} vs area {:.2}", nearest_rms, area_rms);
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+
+use crate::codecs::image::icf_codec::CompressedBlock;
+use crate::codecs::text::arithmetic_coder::{ArithmeticCoder, ArithmeticDecoder};
+
+/// Zigzag positions where each frequency band starts, after the DC
+const BAND_STARTS: [usize; 5] = [1, 3, 6, 10, 21];
+/// Band, then the sign (-, 0, +) of the left and top neighbors' coefficient
+/// at the same frequency
+const CONTEXTS: usize = BAND_STARTS.len() * 9;
+/// Counts are halved past this total so the model keeps adapting
+const MAX_COUNT: u32 = 1024;
+
+/// Adaptive probability that an AC coefficient is negative
+#[derive(Clone, Copy)]
+struct BitModel {
+    counts: [u32; 2],
+}
+
+impl BitModel {
+    fn range(&self, negative: bool) -> (u64, u64, u64) {
+        let [positive, negatives] = self.counts.map(u64::from);
+        match negative {
+            false => (0, positive, positive + negatives),
+            true => (positive, positive + negatives, positive + negatives),
+        }
+    }
+
+    fn update(&mut self, negative: bool) {
+        self.counts[negative as usize] += 1;
+        if self.counts[0] + self.counts[1] > MAX_COUNT {
+            self.counts = self.counts.map(|count| count.div_ceil(2));
+        }
+    }
+}
+
+/// Sign statistics of one channel, shared by encoder and decoder
+///
+/// Blocks are visited in section order; a neighbor not visited yet
+/// counts as zero.
+struct SignContexts {
+    models: [BitModel; CONTEXTS],
+    /// Sign of every AC coefficient seen so far, per block in raster order
+    signs: Vec<[i8; 63]>,
+    blocks_x: usize,
+    blocks_y: usize,
+}
+
+impl SignContexts {
+    fn new(blocks_x: usize, blocks_y: usize) -> Self {
+        Self {
+            models: [BitModel { counts: [1, 1] }; CONTEXTS],
+            signs: vec![[0; 63]; blocks_x * blocks_y],
+            blocks_x,
+            blocks_y,
+        }
+    }
+
+    /// Index of `block` in `signs`, checking it lies in the grid
+    fn block_index(&self, block: &CompressedBlock) -> Result<usize> {
+        let (x, y) = (block.x as usize, block.y as usize);
+        if x >= self.blocks_x || y >= self.blocks_y {
+            anyhow::bail!("ICF block ({}, {}) lies outside the {}x{} sign context grid", x, y, self.blocks_x, self.blocks_y);
+        }
+        Ok(y * self.blocks_x + x)
+    }
+
+    fn context(&self, index: usize, position: usize) -> usize {
+        let band = BAND_STARTS.iter().rposition(|&start| position >= start).unwrap_or(0);
+        let left = if !index.is_multiple_of(self.blocks_x) { self.signs[index - 1][position - 1] } else { 0 };
+        let top = if index >= self.blocks_x { self.signs[index - self.blocks_x][position - 1] } else { 0 };
+        band * 9 + (left + 1) as usize * 3 + (top + 1) as usize
+    }
+
+    /// Visit the nonzero AC coefficients of `block`, letting `code` read or
+    /// set each one's sign, and record the results for later neighbors
+    fn visit(&mut self, block: &mut CompressedBlock, mut code: impl FnMut(&mut BitModel, &mut i16) -> Result<()>) -> Result<()> {
+        let index = self.block_index(block)?;
+        let mut signs = [0i8; 63];
+        let mut position = 1;
+        for (zeros, value) in &mut block.ac_coefficients {
+            position += *zeros as usize;
+            if *zeros == 0 && *value == 0 {
+                continue;
+            }
+            if position >= 64 {
+                break;
+            }
+            if *value != 0 {
+                let model = &mut self.models[self.context(index, position)];
+                code(model, value)?;
+                signs[position - 1] = value.signum() as i8;
+            }
+            position += 1;
+        }
+        self.signs[index] = signs;
+        Ok(())
+    }
+}
+
+/// Move the AC signs of one channel's `blocks` into a context-coded stream,
+/// leaving magnitudes behind
+pub(crate) fn encode_signs(blocks: &mut [CompressedBlock], blocks_x: usize, blocks_y: usize) -> Result<Vec<u8>> {
+    let mut contexts = SignContexts::new(blocks_x, blocks_y);
+    let mut coder = ArithmeticCoder::new();
+    for block in blocks.iter_mut() {
+        contexts.visit(block, |model, value| {
+            let negative = *value < 0;
+            let (low, high, total) = model.range(negative);
+            coder.encode_symbol(low, high, total)?;
+            model.update(negative);
+            Ok(())
+        })?;
+        // Strip signs only after the block's contexts have recorded them
+        for (_, value) in &mut block.ac_coefficients {
+            *value = value.checked_abs().ok_or_else(|| anyhow::anyhow!("ICF coefficient {} has no magnitude", value))?;
+        }
+    }
+    Ok(coder.finish())
+}
+
+/// Restores the signs `encode_signs` moved out of a channel's blocks
+pub(crate) struct SignDecoder {
+    contexts: SignContexts,
+    decoder: ArithmeticDecoder,
+}
+
+impl SignDecoder {
+    pub(crate) fn new(signs: Vec<u8>, blocks_x: usize, blocks_y: usize) -> Self {
+        Self { contexts: SignContexts::new(blocks_x, blocks_y), decoder: ArithmeticDecoder::new(signs) }
+    }
+
+    /// Give the next block in section order its signs back
+    pub(crate) fn restore(&mut self, block: &mut CompressedBlock) -> Result<()> {
+        let decoder = &mut self.decoder;
+        self.contexts.visit(block, |model, value| {
+            if *value < 0 {
+                anyhow::bail!("Corrupt ICF block: signed magnitude {} in a sign-coded section", value);
+            }
+            let (_, split, total) = model.range(false);
+            let negative = decoder.get_symbol_value(total)? >= split;
+            let (low, high, total) = model.range(negative);
+            decoder.decode_symbol(low, high, total)?;
+            model.update(negative);
+            if negative {
+                *value = -*value;
+            }
+            Ok(())
+        })
+    }
+}
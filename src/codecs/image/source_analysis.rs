@@ -0,0 +1,228 @@
+use crate::codecs::image::Quantization;
+use crate::codecs::text::CodecWarning;
+use image::{DynamicImage, RgbImage};
+use serde::Serialize;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// At most this many 8x8 blocks are sampled when estimating a source's
+/// quality, evenly spread over the image
+const MAX_BLOCKS: usize = 4096;
+
+/// Coefficients a step needs to line up before it counts as found
+const MIN_SAMPLES: usize = 16;
+
+/// Mean `cos(2πc/step)` over the coefficients above which they are taken
+/// to sit on multiples of `step`
+const LATTICE_SCORE: f64 = 0.5;
+
+/// ICF quality above a source's estimated quality that is still worth
+/// spending bits on
+pub const QUALITY_MARGIN: u8 = 10;
+
+/// Lossy compression an encode source was stored with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LossyFormat {
+    Jpeg,
+    /// WebP with a VP8 (rather than VP8L) bitstream
+    Webp,
+    Avif,
+}
+
+impl fmt::Display for LossyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LossyFormat::Jpeg => "JPEG",
+            LossyFormat::Webp => "lossy WebP",
+            LossyFormat::Avif => "AVIF",
+        })
+    }
+}
+
+/// The lossy format of an encoded image file, from its header
+///
+/// `None` for lossless formats, lossless WebP included, and for anything
+/// unrecognised.
+pub fn detect_lossy_format(data: &[u8]) -> Option<LossyFormat> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some(LossyFormat::Jpeg);
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        // Chunks follow the header; VP8X only announces features, so keep
+        // looking for the bitstream chunk after it
+        let mut chunk = &data[12..];
+        while chunk.len() >= 8 {
+            match &chunk[..4] {
+                b"VP8 " => return Some(LossyFormat::Webp),
+                b"VP8L" => return None,
+                _ => {}
+            }
+            let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as usize;
+            chunk = chunk.get(8 + size + size % 2..)?;
+        }
+        return None;
+    }
+    // ISO-BMFF: a `ftyp` box whose major brand is AVIF
+    if data.len() >= 12 && &data[4..8] == b"ftyp" && matches!(&data[8..12], b"avif" | b"avis") {
+        return Some(LossyFormat::Avif);
+    }
+    None
+}
+
+/// Estimated JPEG-scale quality a decoded image was last compressed at
+///
+/// Block-DCT quantization leaves the DCT coefficients of the 8x8 luma
+/// blocks on multiples of each frequency's step, and the same steps cause
+/// the source's blocking and ringing. The largest step the low-frequency
+/// coefficients line up on is found for each frequency and read back
+/// through the standard luminance table's quality scaling, which ICF
+/// quality shares. `None` when no steps are found: the source was
+/// compressed too lightly for them to survive rounding (about quality 90
+/// and up), or not on an 8x8 grid at all, as lossy WebP is not.
+pub fn estimate_prior_quality(img: &DynamicImage) -> Option<u8> {
+    let img = img.to_rgb8();
+    let (columns, rows) = (img.width() as usize / 8, img.height() as usize / 8);
+    let positions: Vec<(usize, usize)> = (0..4).flat_map(|v| (0..4 - v).map(move |u| (v, u))).collect();
+    let basis: [[f64; 8]; 4] = std::array::from_fn(|k| {
+        let scale = if k == 0 { 0.125_f64.sqrt() } else { 0.5 };
+        std::array::from_fn(|x| scale * ((2 * x + 1) as f64 * k as f64 * PI / 16.0).cos())
+    });
+
+    let mut samples = vec![Vec::new(); positions.len()];
+    let stride = (columns * rows).div_ceil(MAX_BLOCKS).max(1);
+    for block in (0..columns * rows).step_by(stride) {
+        let Some(luma) = block_luma(&img, block % columns * 8, block / columns * 8) else {
+            continue;
+        };
+        for (&(v, u), samples) in positions.iter().zip(&mut samples) {
+            let coefficient: f64 = (0..64).map(|i| luma[i / 8][i % 8] * basis[v][i / 8] * basis[u][i % 8]).sum();
+            samples.push(coefficient);
+        }
+    }
+
+    // libjpeg scale factor, in percent, implied by each step found
+    let mut scales: Vec<f64> = positions.iter().zip(&samples)
+        .filter_map(|(&(v, u), samples)| quantization_step(samples).map(|step| step * 100.0 / Quantization::LUMINANCE_TABLE[v][u]))
+        .collect();
+    if scales.len() < 2 {
+        return None;
+    }
+    scales.sort_by(f64::total_cmp);
+    let scale = scales[scales.len() / 2];
+    let quality = if scale > 100.0 { 5000.0 / scale } else { (200.0 - scale) / 2.0 };
+    Some(quality.round().clamp(1.0, 100.0) as u8)
+}
+
+/// Level-shifted luma of the 8x8 block at `(x, y)`, or `None` if any
+/// channel clips there, which moves its coefficients off the lattice
+fn block_luma(img: &RgbImage, x: usize, y: usize) -> Option<[[f64; 8]; 8]> {
+    let mut luma = [[0.0; 8]; 8];
+    for (row, line) in luma.iter_mut().enumerate() {
+        for (column, value) in line.iter_mut().enumerate() {
+            let [r, g, b] = img.get_pixel((x + column) as u32, (y + row) as u32).0;
+            if [r, g, b].iter().any(|&channel| channel == 0 || channel == 255) {
+                return None;
+            }
+            // JFIF luma, which the decoder's color conversion preserves
+            *value = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64 - 128.0;
+        }
+    }
+    Some(luma)
+}
+
+/// Largest quantization step `coefficients` sit on multiples of
+///
+/// Coefficients quantized to zero fit every step, so only those at least
+/// a quarter step out count, and they must land on two or more distinct
+/// multiples: a smooth gradient's near-constant coefficient fits too much.
+fn quantization_step(coefficients: &[f64]) -> Option<f64> {
+    (2..=255).rev().map(f64::from).find(|&step| {
+        let used: Vec<f64> = coefficients.iter().copied().filter(|c| c.abs() >= step / 4.0).collect();
+        if used.len() < MIN_SAMPLES {
+            return false;
+        }
+        let first = (used[0] / step).round();
+        let score = used.iter().map(|c| (2.0 * PI * c / step).cos()).sum::<f64>() / used.len() as f64;
+        score > LATTICE_SCORE && used.iter().any(|c| (c / step).round() != first)
+    })
+}
+
+/// Warn about encoding `data`, decoded as `img`, if it was already lossily
+/// compressed
+pub fn lossy_source_warning(data: &[u8], img: &DynamicImage) -> Option<CodecWarning> {
+    let format = detect_lossy_format(data)?;
+    Some(CodecWarning::LossySource { format, estimated_prior_quality: estimate_prior_quality(img) })
+}
+
+/// Lower quality to encode a source estimated at `estimated_prior_quality`
+/// with, if `quality` exceeds it by more than `QUALITY_MARGIN`
+///
+/// Detail the source lost can't be restored, so quality above that only
+/// spends bits reproducing its artifacts.
+pub fn suggested_quality(quality: u8, estimated_prior_quality: u8) -> Option<u8> {
+    let suggested = estimated_prior_quality.saturating_add(QUALITY_MARGIN).min(100);
+    (quality > suggested).then_some(suggested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ImageOutputFormat, Rgb};
+    use std::io::Cursor;
+
+    fn textured(width: u32, height: u32) -> DynamicImage {
+        let mut state = 0x1234_5678_u32;
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+            let noise = (state % 1000) as f64 / 1000.0 - 0.5;
+            let level = 128.0 + 60.0 * (u * 40.0).sin() * (v * 33.0).cos() + 30.0 * noise;
+            Rgb([level as u8, (level * 0.8 + 20.0) as u8, (255.0 - level) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_jpeg_source_warns_with_estimated_quality() {
+        let img = textured(256, 192);
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 40).encode_image(&img).unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+
+        let Some(CodecWarning::LossySource { format, estimated_prior_quality: Some(estimate) }) = lossy_source_warning(&jpeg, &decoded) else {
+            panic!("no estimate for a quality 40 JPEG");
+        };
+        assert_eq!(format, LossyFormat::Jpeg);
+        assert!((30..=50).contains(&estimate), "estimated quality {}", estimate);
+        assert_eq!(suggested_quality(85, estimate), Some(estimate + QUALITY_MARGIN));
+        assert_eq!(suggested_quality(estimate + 5, estimate), None);
+
+        // Nothing to find in pixels that were never quantized
+        assert_eq!(estimate_prior_quality(&img), None);
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        assert_eq!(lossy_source_warning(png.get_ref(), &img), None);
+    }
+
+    #[test]
+    fn test_detects_lossy_containers() {
+        let riff = |chunks: &[(&[u8; 4], usize)]| {
+            let mut data = b"RIFF\0\0\0\0WEBP".to_vec();
+            for (fourcc, size) in chunks {
+                data.extend_from_slice(*fourcc);
+                data.extend_from_slice(&(*size as u32).to_le_bytes());
+                data.resize(data.len() + size + size % 2, 0);
+            }
+            data
+        };
+        assert_eq!(detect_lossy_format(&riff(&[(b"VP8 ", 10)])), Some(LossyFormat::Webp));
+        assert_eq!(detect_lossy_format(&riff(&[(b"VP8L", 10)])), None);
+        assert_eq!(detect_lossy_format(&riff(&[(b"VP8X", 10), (b"ICCP", 3), (b"VP8 ", 10)])), Some(LossyFormat::Webp));
+        assert_eq!(detect_lossy_format(&riff(&[(b"VP8X", 10), (b"VP8L", 10)])), None);
+        assert_eq!(detect_lossy_format(b"\0\0\0\x1cftypavif\0\0\0\0"), Some(LossyFormat::Avif));
+        assert_eq!(detect_lossy_format(b"\x89PNG\r\n\x1a\n"), None);
+    }
+}
@@ -0,0 +1,110 @@
+use image::{Rgb, RgbImage, RgbaImage};
+
+/// Edge of the blocks transparency is judged over, matching ICF's DCT blocks
+const BLOCK: u32 = 8;
+
+/// Recolor the pixels `img` hides behind zero alpha, which ICF would
+/// otherwise code as if they were visible
+///
+/// Blocks with no visible pixel become black, so they code as a flat DC
+/// with no AC coefficients. In blocks with some visible pixels, each
+/// hidden one takes the color of the nearest visible pixel in the block,
+/// so the DCT doesn't ring across the edge into what's seen. Visible
+/// pixels keep their color; alpha itself is dropped, as ICF stores RGB.
+pub fn fill_transparent(img: &RgbaImage) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let mut rgb = RgbImage::new(width, height);
+    for block_y in (0..height).step_by(BLOCK as usize) {
+        for block_x in (0..width).step_by(BLOCK as usize) {
+            let (x_end, y_end) = ((block_x + BLOCK).min(width), (block_y + BLOCK).min(height));
+            let visible: Vec<(u32, u32)> = (block_y..y_end)
+                .flat_map(|y| (block_x..x_end).map(move |x| (x, y)))
+                .filter(|&(x, y)| img.get_pixel(x, y)[3] > 0)
+                .collect();
+            for y in block_y..y_end {
+                for x in block_x..x_end {
+                    let source = if img.get_pixel(x, y)[3] > 0 {
+                        Some((x, y))
+                    } else {
+                        visible.iter().copied().min_by_key(|&(vx, vy)| x.abs_diff(vx).pow(2) + y.abs_diff(vy).pow(2))
+                    };
+                    let color = source.map_or(Rgb([0, 0, 0]), |(sx, sy)| {
+                        let [r, g, b, _] = img.get_pixel(sx, sy).0;
+                        Rgb([r, g, b])
+                    });
+                    rgb.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+    rgb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::image::{IcfCodec, IcfEncodeOptions};
+    use image::{DynamicImage, Rgba};
+
+    /// A disc of smooth color on a transparent background full of noise,
+    /// as editors leave behind
+    fn sprite() -> RgbaImage {
+        let mut state = 0x1234_5678u32;
+        RgbaImage::from_fn(64, 64, |x, y| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if (x as i32 - 30).pow(2) + (y as i32 - 34).pow(2) < 18 * 18 {
+                Rgba([200, 60 + y as u8 * 2, 40 + x as u8 * 2, 255])
+            } else {
+                Rgba([state as u8, (state >> 8) as u8, (state >> 16) as u8, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_hidden_pixels_are_cheap_and_dont_ring() {
+        let sprite = sprite();
+        let codec = IcfCodec::new();
+        let encode = |fill_transparent| {
+            let options = IcfEncodeOptions { fill_transparent, ..IcfEncodeOptions::with_quality(80) };
+            let encoded = codec.encode_with_options(&DynamicImage::ImageRgba8(sprite.clone()), &options).unwrap();
+            (encoded.len(), codec.decode(&encoded).unwrap().to_rgb8())
+        };
+        let (naive_size, naive) = encode(false);
+        let (filled_size, filled) = encode(true);
+        assert!(filled_size * 3 < naive_size, "{} vs {} bytes", filled_size, naive_size);
+
+        let visible_error = |decoded: &RgbImage| sprite.enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > 0)
+            .flat_map(|(x, y, pixel)| (0..3).map(move |c| pixel[c].abs_diff(decoded.get_pixel(x, y)[c])))
+            .max()
+            .unwrap();
+        let (naive_error, filled_error) = (visible_error(&naive), visible_error(&filled));
+        assert!(filled_error < naive_error, "max visible error {} vs {}", filled_error, naive_error);
+        assert!(filled_error <= 8, "max visible error {}", filled_error);
+
+        // Blocks with nothing visible come back exactly black
+        for (x, y, pixel) in filled.enumerate_pixels() {
+            let (bx, by) = (x / BLOCK * BLOCK, y / BLOCK * BLOCK);
+            let hidden = (by..by + BLOCK).all(|y| (bx..bx + BLOCK).all(|x| sprite.get_pixel(x, y)[3] == 0));
+            if hidden {
+                assert_eq!(pixel.0, [0, 0, 0], "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_visible_pixels_keep_their_color() {
+        let sprite = sprite();
+        let filled = fill_transparent(&sprite);
+        for (x, y, pixel) in sprite.enumerate_pixels() {
+            if pixel[3] > 0 {
+                assert_eq!(&filled.get_pixel(x, y).0, &pixel.0[..3]);
+            }
+        }
+        // An opaque image passes through unchanged
+        let opaque = RgbaImage::from_pixel(9, 9, Rgba([1, 2, 3, 255]));
+        assert_eq!(fill_transparent(&opaque), RgbImage::from_pixel(9, 9, Rgb([1, 2, 3])));
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// A named byte range within a container file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LayoutRegion {
+    pub name: String,
+    pub offset: u64,
+    pub length: u64,
+    /// CRC-32 of the region's bytes, for comparing files region by region
+    pub crc32: u32,
+}
+
+impl LayoutRegion {
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset as usize..self.end() as usize
+    }
+}
+
+/// Split `data` into consecutive named regions of the given lengths
+///
+/// Any bytes after the last region become a `trailing` region, so the result
+/// always covers the whole file. Fails if the regions run past the end.
+pub fn split_regions(data: &[u8], parts: &[(&str, u64)]) -> Result<Vec<LayoutRegion>> {
+    let mut regions = Vec::with_capacity(parts.len() + 1);
+    let mut offset = 0u64;
+
+    for &(name, length) in parts {
+        let end = offset.checked_add(length).filter(|&end| end <= data.len() as u64);
+        let end = match end {
+            Some(end) => end,
+            None => bail!("Region '{}' ({} bytes at offset {}) extends past end of file ({} bytes)",
+                name, length, offset, data.len()),
+        };
+        regions.push(LayoutRegion {
+            name: name.to_string(),
+            offset,
+            length,
+            crc32: crc32fast::hash(&data[offset as usize..end as usize]),
+        });
+        offset = end;
+    }
+
+    if offset < data.len() as u64 {
+        regions.push(LayoutRegion {
+            name: "trailing".to_string(),
+            offset,
+            length: data.len() as u64 - offset,
+            crc32: crc32fast::hash(&data[offset as usize..]),
+        });
+    }
+
+    Ok(regions)
+}
+
+/// Check that regions are in order, contiguous, non-overlapping and cover exactly `file_size` bytes
+pub fn check_coverage(regions: &[LayoutRegion], file_size: u64) -> Result<()> {
+    let mut expected_offset = 0u64;
+    for region in regions {
+        if region.offset != expected_offset {
+            bail!("Region '{}' starts at {} but the previous region ends at {}",
+                region.name, region.offset, expected_offset);
+        }
+        expected_offset = region.end();
+    }
+    if expected_offset != file_size {
+        bail!("Regions cover {} bytes but the file is {} bytes", expected_offset, file_size);
+    }
+    Ok(())
+}
+
+/// Classic 16-bytes-per-line hexdump with offsets relative to `base_offset`
+pub fn hexdump(data: &[u8], base_offset: u64) -> String {
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x}  ", base_offset + line as u64 * 16);
+        for column in 0..16 {
+            match chunk.get(column) {
+                Some(byte) => { let _ = write!(out, "{:02x} ", byte); }
+                None => out.push_str("   "),
+            }
+            if column == 7 {
+                out.push(' ');
+            }
+        }
+        out.push_str(" |");
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_regions_adds_trailing() {
+        let data = b"MAGIhead0123456789extra";
+        let regions = split_regions(data, &[("magic", 4), ("header", 4), ("payload", 10)]).unwrap();
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions[3].name, "trailing");
+        assert_eq!(&data[regions[3].range()], b"extra");
+        check_coverage(&regions, data.len() as u64).unwrap();
+
+        assert!(split_regions(data, &[("magic", 4), ("payload", 100)]).is_err());
+        assert!(check_coverage(&regions[1..], data.len() as u64).is_err());
+        assert!(check_coverage(&regions[..3], data.len() as u64).is_err());
+    }
+
+    #[test]
+    fn test_hexdump_format() {
+        let dump = hexdump(b"TCF2\x00\x01abcdefghijklmnop", 0x10);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "00000010  54 43 46 32 00 01 61 62  63 64 65 66 67 68 69 6a  |TCF2..abcdefghij|");
+        assert!(lines[1].starts_with("00000020  6b 6c 6d 6e 6f 70 "));
+        assert!(lines[1].ends_with("|klmnop|"));
+    }
+}
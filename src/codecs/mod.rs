@@ -2,8 +2,30 @@ pub mod text;
 pub mod image;
 pub mod video;
 pub mod bencode;
+pub mod atomic;
+pub mod cli_common;
+pub mod doctor;
+pub mod explain;
+pub mod formats;
+pub mod layout;
+pub mod npy;
+pub mod plane;
+pub mod peek;
+pub mod progress;
+pub mod residual;
+pub(crate) mod precision;
+pub(crate) mod trace;
 
 pub use text::*;
 pub use image::*;
 pub use video::*;
-pub use bencode::*;
\ No newline at end of file
+pub use bencode::*;
+pub use atomic::*;
+pub use cli_common::*;
+pub use explain::*;
+pub use layout::*;
+pub use npy::*;
+pub use plane::*;
+pub use peek::*;
+pub use progress::*;
+pub use residual::*;
\ No newline at end of file
@@ -0,0 +1,86 @@
+use anyhow::{bail, Result};
+
+/// NumPy `.npy` magic string, followed by format version 1.0
+const NPY_MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+
+/// An element type `write_npy` can store
+pub trait NpyElement: Copy {
+    /// NumPy dtype string of the little-endian encoding
+    const DESCR: &'static str;
+
+    fn write_le(self, out: &mut Vec<u8>);
+}
+
+impl NpyElement for i16 {
+    const DESCR: &'static str = "<i2";
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn write_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+/// Serialize `data` as a C-order NumPy array of the given shape
+///
+/// Readable with `numpy.load`. The header is padded so the data starts on
+/// a 64-byte boundary, as the format asks.
+pub fn write_npy<T: NpyElement>(shape: &[usize], data: &[T]) -> Result<Vec<u8>> {
+    let elements: usize = shape.iter().product();
+    if elements != data.len() {
+        bail!("NPY shape {:?} holds {} elements, got {}", shape, elements, data.len());
+    }
+
+    // A one-element tuple needs its trailing comma
+    let dimensions: Vec<String> = shape.iter().map(usize::to_string).collect();
+    let shape = match dimensions.len() {
+        1 => format!("({},)", dimensions[0]),
+        _ => format!("({})", dimensions.join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", T::DESCR, shape);
+    // Magic and version, u16 header length, header, terminating newline
+    let unpadded = NPY_MAGIC.len() + 2 + header.len() + 1;
+    header.extend(std::iter::repeat_n(' ', unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+    if header.len() > u16::MAX as usize {
+        bail!("NPY header for shape {} is too long", shape);
+    }
+
+    let mut out = Vec::with_capacity(NPY_MAGIC.len() + 2 + header.len() + std::mem::size_of_val(data));
+    out.extend_from_slice(NPY_MAGIC);
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    for &value in data {
+        value.write_le(&mut out);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_layout() {
+        let npy = write_npy(&[2, 3], &[1i16, -2, 3, -4, 5, -6]).unwrap();
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+
+        assert_eq!(&npy[..8], NPY_MAGIC);
+        assert_eq!((10 + header_len) % 64, 0);
+        assert!(header.starts_with("{'descr': '<i2', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with(" \n"));
+        assert_eq!(&npy[10 + header_len..10 + header_len + 4], &[1, 0, 0xfe, 0xff]);
+        assert_eq!(npy.len(), 10 + header_len + 12);
+
+        let vector = write_npy(&[1], &[0.5f64]).unwrap();
+        assert!(vector.windows(4).any(|window| window == b"(1,)"));
+        assert!(write_npy(&[2, 2], &[0i16; 3]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+use serde::de::{DeserializeOwned, MapAccess, Visitor};
+use serde_json::{Map, Value};
+
+/// Longest prefix `TcfCodec::peek` or `IcfCodec::peek` ever asks for
+pub const PEEK_LIMIT: usize = 4096;
+
+/// Outcome of peeking at the start of a file
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeekResult<T> {
+    Ready(T),
+    /// The prefix must be at least this many bytes long; never more than `PEEK_LIMIT`
+    NeedMoreData(usize),
+}
+
+impl<T> PeekResult<T> {
+    pub fn ready(self) -> Option<T> {
+        match self {
+            PeekResult::Ready(value) => Some(value),
+            PeekResult::NeedMoreData(_) => None,
+        }
+    }
+}
+
+/// Which digest a header's `checksum` holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    Sha256,
+    /// Not a digest this crate writes
+    Unknown,
+}
+
+impl ChecksumKind {
+    pub fn of(checksum: &str) -> Self {
+        if checksum.len() == 64 && checksum.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            ChecksumKind::Sha256
+        } else {
+            ChecksumKind::Unknown
+        }
+    }
+}
+
+/// Read the leading header fields of a `magic`, u32 LE length, JSON header container
+///
+/// Needs the whole header or `PEEK_LIMIT` bytes, whichever is shorter. A
+/// header cut off at the limit is read up to its last complete entry, which
+/// works because encoders serialize the fields `T` needs before any large
+/// ones such as chunk indexes or quantization tables.
+pub(crate) fn peek_json_header<T: DeserializeOwned>(prefix: &[u8], magic: &str, format: &str) -> Result<PeekResult<T>> {
+    let magic_len = prefix.len().min(magic.len());
+    if prefix[..magic_len] != magic.as_bytes()[..magic_len] {
+        bail!("Invalid {} magic number", format);
+    }
+    if prefix.len() < 8 {
+        return Ok(PeekResult::NeedMoreData(8));
+    }
+
+    let header_size = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as usize;
+    let needed = header_size.saturating_add(8).min(PEEK_LIMIT);
+    if prefix.len() < needed {
+        return Ok(PeekResult::NeedMoreData(needed));
+    }
+
+    let mut entries = Map::new();
+    // A header cut off at the limit ends in an EOF error after its complete entries
+    let _ = serde::Deserializer::deserialize_map(
+        &mut serde_json::Deserializer::from_slice(&prefix[8..needed]),
+        HeaderEntries(&mut entries),
+    );
+    serde_json::from_value(Value::Object(entries))
+        .map(PeekResult::Ready)
+        .with_context(|| format!("{} header fields not found in the first {} bytes", format, needed))
+}
+
+/// Collects the entries of a JSON object, keeping each one only once the
+/// next key or the closing brace shows its value wasn't cut short
+struct HeaderEntries<'a>(&'a mut Map<String, Value>);
+
+impl<'de> Visitor<'de> for HeaderEntries<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a header object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut pending: Option<(String, Value)> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if let Some((key, value)) = pending.take() {
+                self.0.insert(key, value);
+            }
+            let value = map.next_value::<Value>()?;
+            pending = Some((key, value));
+        }
+        if let Some((key, value)) = pending {
+            self.0.insert(key, value);
+        }
+        Ok(())
+    }
+}
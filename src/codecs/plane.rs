@@ -0,0 +1,149 @@
+use crate::codecs::image::profile::IcfColorSpace;
+
+/// Single image plane stored row-major, rows `stride` samples apart
+///
+/// Every constructor here packs the rows (`stride == width`), so `data`
+/// holds exactly `width * height` samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plane<T = u8> {
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub data: Vec<T>,
+}
+
+impl<T: Copy> Plane<T> {
+    pub fn new(width: usize, height: usize, fill: T) -> Self {
+        Self {
+            width,
+            height,
+            stride: width,
+            data: vec![fill; width * height],
+        }
+    }
+
+    /// Plane over `data`, which must hold `width * height` samples
+    pub fn from_vec(width: usize, height: usize, data: Vec<T>) -> Self {
+        assert_eq!(data.len(), width * height, "plane data does not match {}x{}", width, height);
+        Self { width, height, stride: width, data }
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> T {
+        self.data[y * self.stride + x]
+    }
+
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, value: T) {
+        self.data[y * self.stride + x] = value;
+    }
+
+    #[inline]
+    pub fn row(&self, y: usize) -> &[T] {
+        &self.data[y * self.stride..y * self.stride + self.width]
+    }
+
+    /// Copy of this plane extended to `width`x`height` by replicating the last row and column
+    pub fn padded(&self, width: usize, height: usize) -> Plane<T> {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = self.row(y.min(self.height - 1));
+            data.extend((0..width).map(|x| row[x.min(self.width - 1)]));
+        }
+        Plane::from_vec(width, height, data)
+    }
+
+    /// Top-left `width`x`height` region of this plane
+    pub fn cropped(&self, width: usize, height: usize) -> Plane<T> {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            data.extend_from_slice(&self.row(y)[..width]);
+        }
+        Plane::from_vec(width, height, data)
+    }
+
+    /// Columns and rows of 8x8 blocks needed to cover the plane
+    pub fn block_grid(&self) -> (usize, usize) {
+        (self.width.div_ceil(8), self.height.div_ceil(8))
+    }
+
+    /// 8x8 block at block column `block_x`, row `block_y`
+    pub fn block(&self, block_x: usize, block_y: usize) -> BlockView<'_, T> {
+        BlockView { plane: self, x: block_x * 8, y: block_y * 8 }
+    }
+
+    /// Every 8x8 block in raster order, with its block column and row
+    pub fn blocks(&self) -> impl Iterator<Item = ((usize, usize), BlockView<'_, T>)> + '_ {
+        let (blocks_x, blocks_y) = self.block_grid();
+        (0..blocks_y).flat_map(move |block_y| {
+            (0..blocks_x).map(move |block_x| ((block_x, block_y), self.block(block_x, block_y)))
+        })
+    }
+
+    /// Write an 8x8 block, dropping samples that fall outside the plane
+    pub fn write_block(&mut self, block_x: usize, block_y: usize, block: &[[T; 8]; 8]) {
+        let (x0, y0) = (block_x * 8, block_y * 8);
+        let columns = self.width.saturating_sub(x0).min(8);
+        for (dy, block_row) in block.iter().enumerate().take(self.height.saturating_sub(y0)) {
+            let start = (y0 + dy) * self.stride + x0;
+            self.data[start..start + columns].copy_from_slice(&block_row[..columns]);
+        }
+    }
+}
+
+/// Read-only 8x8 window into a plane
+///
+/// Samples past the right or bottom edge repeat the last column or row,
+/// the padding block transforms expect.
+#[derive(Clone, Copy)]
+pub struct BlockView<'a, T> {
+    plane: &'a Plane<T>,
+    x: usize,
+    y: usize,
+}
+
+impl<T: Copy> BlockView<'_, T> {
+    #[inline]
+    pub fn get(&self, row: usize, column: usize) -> T {
+        let plane = self.plane;
+        plane.get((self.x + column).min(plane.width - 1), (self.y + row).min(plane.height - 1))
+    }
+
+    pub fn to_array(&self) -> [[T; 8]; 8] {
+        std::array::from_fn(|row| std::array::from_fn(|column| self.get(row, column)))
+    }
+}
+
+/// Planes of one picture in a luma/chroma color space
+///
+/// Chroma planes may be smaller than luma when subsampled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame<T> {
+    pub planes: Vec<Plane<T>>,
+    pub color_space: IcfColorSpace,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_replicate_edges_and_write_back() {
+        let plane = Plane::from_vec(10, 9, (0..90).collect::<Vec<i32>>());
+        assert_eq!(plane.block_grid(), (2, 2));
+        assert_eq!(plane.blocks().count(), 4);
+
+        let corner = plane.block(1, 1).to_array();
+        assert_eq!(corner[0][0], 88);
+        assert_eq!(corner[0][1], 89);
+        assert_eq!(corner[7][7], 89);
+        assert_eq!(corner[0][7], 89);
+
+        let mut copy = Plane::new(10, 9, 0);
+        for ((block_x, block_y), block) in plane.blocks() {
+            copy.write_block(block_x, block_y, &block.to_array());
+        }
+        assert_eq!(copy, plane);
+        assert_eq!(plane.padded(16, 16).cropped(10, 9), plane);
+    }
+}
@@ -0,0 +1,31 @@
+//! Keeping encoder output bit-identical across platforms
+//!
+//! Encoded files are content addressed, so an x86_64 and an aarch64 build
+//! must produce the same bytes. IEEE add, subtract, multiply, divide and
+//! sqrt are correctly rounded everywhere, and Rust never contracts them
+//! into fused multiply-adds on its own, so arithmetic on identical inputs
+//! in a fixed order gives identical results. Transcendental functions
+//! (`cos`, `log2`, `powf`, ...) are another matter: they come from the
+//! platform's libm, which may be off by an ulp and differently so per
+//! platform, or per CPU where glibc picks FMA code paths at runtime.
+//!
+//! The encode paths therefore call them only while building tables, and
+//! round every table entry with [`round_table_value`]. Everything
+//! downstream of the tables must stay plain arithmetic, with no
+//! `mul_add`, no float reductions in thread-count-dependent order, and no
+//! hash map iteration order leaking into output.
+
+/// Fractional bits kept in table entries computed with transcendental
+/// functions
+///
+/// Far coarser than an ulp of any entry's magnitude, so libm differences
+/// vanish, and far finer than anything the codecs can resolve.
+pub const TABLE_FRACTION_BITS: i32 = 40;
+
+/// Round a table entry to a multiple of 2^-`TABLE_FRACTION_BITS`
+///
+/// Scaling by a power of two is exact, so this rounds exactly once.
+pub fn round_table_value(value: f64) -> f64 {
+    let scale = (1u64 << TABLE_FRACTION_BITS) as f64;
+    (value * scale).round() / scale
+}
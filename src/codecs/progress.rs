@@ -0,0 +1,21 @@
+/// How far a long-running operation has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Units of work finished so far
+    pub done: u64,
+    /// Units of work in total; bytes unless the operation says otherwise
+    pub total: u64,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done as f64 / self.total as f64
+        }
+    }
+}
+
+/// Callback that long-running codec operations report `Progress` to
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(Progress);
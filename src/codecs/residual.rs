@@ -0,0 +1,28 @@
+//! DCT residual coding shared by ICF and VCF
+//!
+//! Every coded 8x8 block is a prediction plus a quantized DCT residual.
+//! ICF blocks are level-shifted samples predicted by zero; VCF I-frame
+//! blocks are predicted by flat grey and P-frame blocks by a motion
+//! compensated reference. Both codecs quantize and reconstruct through
+//! here, so a block coded by one reconstructs identically in the other.
+use crate::codecs::image::dct_transform::Dct8x8;
+use crate::codecs::image::quantization::Quantization;
+
+/// Prediction of level-shifted samples, which are centred on zero
+pub const ZERO_PREDICTION: [[f64; 8]; 8] = [[0.0; 8]; 8];
+/// Prediction of intra blocks of unshifted 8-bit samples
+pub const FLAT_PREDICTION: [[f64; 8]; 8] = [[128.0; 8]; 8];
+
+/// Quantized DCT of `samples - prediction`
+pub fn quantize_residual(dct: &Dct8x8, samples: &[[f64; 8]; 8], prediction: &[[f64; 8]; 8], table: &[[f64; 8]; 8]) -> [[i16; 8]; 8] {
+    let residual: [[f64; 8]; 8] = std::array::from_fn(|row| std::array::from_fn(|col| samples[row][col] - prediction[row][col]));
+    let mut coefficients = [[0.0; 8]; 8];
+    dct.forward_8x8_into(&residual, &mut coefficients);
+    Quantization::quantize_block(&coefficients, table)
+}
+
+/// `prediction` plus the dequantized residual, unrounded
+pub fn reconstruct_residual(dct: &Dct8x8, prediction: &[[f64; 8]; 8], quantized: &[[i16; 8]; 8], table: &[[f64; 8]; 8]) -> [[f64; 8]; 8] {
+    let residual = dct.inverse_8x8(&Quantization::dequantize_block(quantized, table));
+    std::array::from_fn(|row| std::array::from_fn(|col| prediction[row][col] + residual[row][col]))
+}
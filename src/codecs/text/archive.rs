@@ -0,0 +1,178 @@
+//! Solid archives of many small texts
+//!
+//! Files are concatenated and coded as shared chunks, so each chunk's model
+//! is fitted to many files instead of one. The file table rides along as a
+//! metadata chunk, and reading a file decodes only the chunks it overlaps.
+use super::chunking::ChunkStrategy;
+use super::tcf_codec::{ChunkDecoder, TcfCodec, TcfEncodeOptions, TcfEncoded, TcfFlags};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Where one file sits in an archive's concatenated text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TcfArchiveEntry {
+    pub name: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl TcfArchiveEntry {
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + self.length
+    }
+}
+
+/// An archive's file table, in the order files were added
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct FileTable {
+    files: Vec<TcfArchiveEntry>,
+}
+
+/// Builds a solid TCF archive
+pub struct TcfArchiveWriter {
+    options: TcfEncodeOptions,
+    text: String,
+    table: FileTable,
+}
+
+impl TcfArchiveWriter {
+    /// Chunk size when the options don't set a strategy
+    pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+    pub fn new(mut options: TcfEncodeOptions) -> Self {
+        options.chunking.get_or_insert(ChunkStrategy::FixedBytes(Self::DEFAULT_CHUNK_SIZE));
+        Self { options, text: String::new(), table: FileTable::default() }
+    }
+
+    /// Append a file; names must be unique
+    pub fn add(&mut self, name: impl Into<String>, text: &str) -> Result<()> {
+        let name = name.into();
+        if self.table.files.iter().any(|entry| entry.name == name) {
+            bail!("Archive already has a file named {:?}", name);
+        }
+        self.table.files.push(TcfArchiveEntry { name, offset: self.text.len() as u64, length: text.len() as u64 });
+        self.text.push_str(text);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.files.is_empty()
+    }
+
+    /// Encode the archive as a chunked TCF file
+    pub fn finish(self) -> Result<TcfEncoded> {
+        let table = serde_json::to_string(&self.table).context("Failed to serialize TCF archive table")?;
+        TcfCodec::encode_with_metadata(&self.text, &self.options, Some(&table)).map(|(encoded, _)| encoded)
+    }
+}
+
+/// Extracts files from a solid TCF archive
+///
+/// Decoded chunks are kept, so files sharing a chunk only decode it once.
+pub struct TcfArchiveReader<'a> {
+    chunks: ChunkDecoder<'a>,
+    entries: Vec<TcfArchiveEntry>,
+    by_name: HashMap<String, usize>,
+}
+
+impl<'a> TcfArchiveReader<'a> {
+    pub fn new(tcf_data: &'a [u8]) -> Result<Self> {
+        let header = TcfCodec::parse_header(tcf_data)?;
+        if header.flags & TcfFlags::CHUNKED == 0 {
+            bail!("TCF file isn't an archive: it isn't chunked");
+        }
+        let original_size = header.original_size;
+        let chunks = TcfCodec::chunk_decoder(tcf_data, header)?;
+        let table = chunks.metadata()?.context("TCF file isn't an archive: it has no file table")?;
+        let table: FileTable = serde_json::from_slice(&table).context("Invalid TCF archive table")?;
+
+        let mut by_name = HashMap::new();
+        for (index, entry) in table.files.iter().enumerate() {
+            if entry.offset.checked_add(entry.length).is_none_or(|end| end > original_size) {
+                bail!("Invalid TCF archive table: {:?} runs past the {} byte text", entry.name, original_size);
+            }
+            if by_name.insert(entry.name.clone(), index).is_some() {
+                bail!("Invalid TCF archive table: {:?} appears twice", entry.name);
+            }
+        }
+        Ok(Self { chunks, entries: table.files, by_name })
+    }
+
+    /// Every file, in the order they were added
+    pub fn entries(&self) -> &[TcfArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&TcfArchiveEntry> {
+        self.by_name.get(name).map(|&index| &self.entries[index])
+    }
+
+    /// The text of file `name`
+    pub fn read(&mut self, name: &str) -> Result<String> {
+        let range = self.entry(name)
+            .with_context(|| format!("TCF archive has no file named {:?}", name))?
+            .range();
+        String::from_utf8(self.chunks.read(range)?).with_context(|| format!("Invalid UTF-8 in archived file {:?}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::{TcfIndex, TcfMethod, TcfReader};
+
+    fn small_file(i: usize) -> String {
+        format!(
+            ".card-{i} {{\n  margin: {}px;\n  padding: {}px {}px;\n  color: #{:06x};\n  display: flex;\n}}\n",
+            i % 16, i % 8, i % 5, i * 2654435761 % 0xFFFFFF,
+        )
+    }
+
+    #[test]
+    fn test_archive_beats_separate_files() {
+        let options = TcfEncodeOptions { method: Some(TcfMethod::Gzip), ..Default::default() };
+        let mut writer = TcfArchiveWriter::new(options.clone());
+        let mut separate = 0;
+        for i in 0..500 {
+            let text = small_file(i);
+            writer.add(format!("css/card-{}.css", i), &text).unwrap();
+            separate += TcfCodec::encode_with_options(&text, &options).unwrap().data.len();
+        }
+        assert_eq!(writer.len(), 500);
+        assert!(writer.add("css/card-7.css", "again").is_err());
+        let archive = writer.finish().unwrap().data;
+        assert!(archive.len() * 4 < separate, "archive of {} bytes against {} separately", archive.len(), separate);
+
+        let mut reader = TcfArchiveReader::new(&archive).unwrap();
+        assert_eq!(reader.entries().len(), 500);
+        assert_eq!(reader.entries()[3].name, "css/card-3.css");
+        for i in [0, 1, 250, 499] {
+            assert_eq!(reader.read(&format!("css/card-{}.css", i)).unwrap(), small_file(i));
+        }
+        assert!(reader.read("css/missing.css").is_err());
+
+        // The table isn't part of the text
+        let text: String = (0..500).map(small_file).collect();
+        assert_eq!(TcfCodec::decode(&archive).unwrap(), text);
+        assert_eq!(TcfReader::new(&archive).unwrap().read_range(0..text.len() as u64).unwrap(), text.as_bytes());
+        let streamed: Vec<u8> = TcfCodec::decode_stream(&archive).unwrap().flat_map(Result::unwrap).collect();
+        assert_eq!(streamed, text.as_bytes());
+        let index = TcfIndex::from_tcf(&archive).unwrap();
+        assert_eq!(TcfIndex::from_bytes(&index.to_bytes().unwrap()).unwrap().chunks(), index.chunks());
+    }
+
+    #[test]
+    fn test_plain_files_are_not_archives() {
+        let chunked = TcfEncodeOptions { chunking: Some(ChunkStrategy::FixedBytes(64)), ..Default::default() };
+        let encoded = TcfCodec::encode_with_options(&small_file(1).repeat(4), &chunked).unwrap().data;
+        assert!(TcfArchiveReader::new(&encoded).err().unwrap().to_string().contains("no file table"));
+        let plain = TcfCodec::encode("not chunked").unwrap();
+        assert!(TcfArchiveReader::new(&plain).is_err());
+    }
+}
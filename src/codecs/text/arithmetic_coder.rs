@@ -1,7 +1,47 @@
-use std::collections::HashMap;
+use crate::codecs::precision::round_table_value;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Largest frequency total the coder accepts (2^24)
+///
+/// Precision contract: the coding registers are 32 bits wide and, after
+/// renormalization, the live range always exceeds a quarter of the register
+/// (2^30). With totals of at most 2^24 every symbol of frequency >= 1 is
+/// therefore mapped to a sub-range of at least 2^6 values, and the
+/// `range * cumulative` products stay below 2^56, well inside `u64`.
+pub const MAX_TOTAL: u64 = 1 << 24;
+
+/// Errors reported by the arithmetic coder
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CoderError {
+    #[error("Frequency total {total} exceeds the coder maximum of {max}")]
+    TotalTooLarge { total: u64, max: u64 },
+    #[error("Invalid symbol range [{low}, {high}) for frequency total {total}")]
+    InvalidRange { low: u64, high: u64, total: u64 },
+}
+
+/// Check a symbol range against the precision contract
+fn check_range(symbol_low: u64, symbol_high: u64, total: u64) -> Result<(), CoderError> {
+    check_total(total)?;
+    if symbol_low >= symbol_high || symbol_high > total {
+        return Err(CoderError::InvalidRange { low: symbol_low, high: symbol_high, total });
+    }
+    Ok(())
+}
+
+fn check_total(total: u64) -> Result<(), CoderError> {
+    if total > MAX_TOTAL {
+        return Err(CoderError::TotalTooLarge { total, max: MAX_TOTAL });
+    }
+    if total == 0 {
+        return Err(CoderError::InvalidRange { low: 0, high: 0, total });
+    }
+    Ok(())
+}
 
 /// High-precision arithmetic coder implementation
-/// Uses 64-bit precision for better compression than traditional 32-bit implementations
+/// Uses 32-bit coding registers held in 64-bit arithmetic; see [`MAX_TOTAL`]
+/// for the frequency totals this supports
 pub struct ArithmeticCoder {
     low: u64,
     high: u64,
@@ -12,7 +52,7 @@ pub struct ArithmeticCoder {
 }
 
 impl ArithmeticCoder {
-    const PRECISION: u64 = 62; // Use 62 bits to avoid overflow
+    const PRECISION: u64 = 32;
     const MAX_VALUE: u64 = (1u64 << Self::PRECISION) - 1;
     const QUARTER: u64 = 1u64 << (Self::PRECISION - 2);
     const HALF: u64 = 2 * Self::QUARTER;
@@ -29,8 +69,12 @@ impl ArithmeticCoder {
         }
     }
 
-    /// Encode a symbol with given probability range
-    pub fn encode_symbol(&mut self, symbol_low: u64, symbol_high: u64, total: u64) {
+    /// Encode a symbol occupying `[symbol_low, symbol_high)` of `total`
+    ///
+    /// Fails without changing the coder state if `total` exceeds [`MAX_TOTAL`]
+    /// or the range is empty or out of bounds.
+    pub fn encode_symbol(&mut self, symbol_low: u64, symbol_high: u64, total: u64) -> Result<(), CoderError> {
+        check_range(symbol_low, symbol_high, total)?;
         let range = self.high - self.low + 1;
         
         // Update bounds
@@ -59,6 +103,22 @@ impl ArithmeticCoder {
             self.low = (self.low - Self::QUARTER) << 1;
             self.high = ((self.high - Self::QUARTER) << 1) | 1;
         }
+
+        Ok(())
+    }
+
+    /// Bits emitted so far, counting pending underflow bits whose value is
+    /// not yet known
+    ///
+    /// `finish` adds at most two bits plus padding to the last byte.
+    pub fn bits_written(&self) -> u64 {
+        self.output.len() as u64 * 8 + self.bit_count as u64 + self.pending_bits
+    }
+
+    /// Underflow bits waiting for the next decided bit
+    #[cfg(test)]
+    pub(super) fn pending_bits(&self) -> u64 {
+        self.pending_bits
     }
 
     /// Finish encoding and return compressed data
@@ -73,9 +133,9 @@ impl ArithmeticCoder {
             self.output_pending_bits(0);
         }
 
-        // Flush remaining bits
+        // Flush remaining bits, left-aligned since the decoder reads MSB first
         if self.bit_count > 0 {
-            self.output.push(self.bit_buffer);
+            self.output.push(self.bit_buffer << (8 - self.bit_count));
         }
 
         self.output
@@ -111,7 +171,7 @@ pub struct ArithmeticDecoder {
 }
 
 impl ArithmeticDecoder {
-    const PRECISION: u64 = 62;
+    const PRECISION: u64 = 32;
     const MAX_VALUE: u64 = (1u64 << Self::PRECISION) - 1;
     const QUARTER: u64 = 1u64 << (Self::PRECISION - 2);
     const HALF: u64 = 2 * Self::QUARTER;
@@ -136,13 +196,15 @@ impl ArithmeticDecoder {
     }
 
     /// Get the current symbol value for decoding
-    pub fn get_symbol_value(&self, total: u64) -> u64 {
+    pub fn get_symbol_value(&self, total: u64) -> Result<u64, CoderError> {
+        check_total(total)?;
         let range = self.high - self.low + 1;
-        ((self.value - self.low + 1) * total - 1) / range
+        Ok(((self.value - self.low + 1) * total - 1) / range)
     }
 
-    /// Decode a symbol with given probability range
-    pub fn decode_symbol(&mut self, symbol_low: u64, symbol_high: u64, total: u64) {
+    /// Consume the symbol occupying `[symbol_low, symbol_high)` of `total`
+    pub fn decode_symbol(&mut self, symbol_low: u64, symbol_high: u64, total: u64) -> Result<(), CoderError> {
+        check_range(symbol_low, symbol_high, total)?;
         let range = self.high - self.low + 1;
         
         // Update bounds
@@ -170,6 +232,8 @@ impl ArithmeticDecoder {
             self.high = ((self.high - Self::QUARTER) << 1) | 1;
             self.value = ((self.value - Self::QUARTER) << 1) | self.input_bit() as u64;
         }
+
+        Ok(())
     }
 
     fn input_bit(&mut self) -> u8 {
@@ -191,89 +255,106 @@ impl ArithmeticDecoder {
 
 /// Adaptive frequency model for arithmetic coding
 pub struct FrequencyModel {
-    frequencies: HashMap<u8, u64>,
+    frequencies: BTreeMap<u8, u64>,
     total_frequency: u64,
     symbols: Vec<u8>,
+    /// Start of each symbol's range in `symbols` order, then the total
+    cumulative: Vec<u64>,
+    /// Index of each byte value in `symbols`
+    positions: [Option<u8>; 256],
 }
 
 impl FrequencyModel {
     pub fn new() -> Self {
         Self {
-            frequencies: HashMap::new(),
+            frequencies: BTreeMap::new(),
             total_frequency: 0,
             symbols: Vec::new(),
+            cumulative: vec![0],
+            positions: [None; 256],
         }
     }
 
     /// Build model from input data
     pub fn build_from_data(&mut self, data: &[u8]) {
+        let mut counts = [0u64; 256];
+        for &byte in data {
+            counts[byte as usize] += 1;
+        }
+        self.build_from_counts(&counts);
+    }
+
+    /// Build model from the number of times each byte value occurs
+    ///
+    /// Byte values that never occur are left out of the model.
+    pub fn build_from_counts(&mut self, counts: &[u64; 256]) {
         self.frequencies.clear();
         self.symbols.clear();
         self.total_frequency = 0;
 
-        // Count frequencies
-        for &byte in data {
-            *self.frequencies.entry(byte).or_insert(0) += 1;
-            self.total_frequency += 1;
+        // Sorted symbol list for consistent encoding/decoding
+        for (symbol, &count) in counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            self.frequencies.insert(symbol as u8, count);
+            self.symbols.push(symbol as u8);
+            self.total_frequency += count;
         }
 
-        // Create sorted symbol list for consistent encoding/decoding
-        self.symbols = self.frequencies.keys().copied().collect();
-        self.symbols.sort();
+        self.rescale(MAX_TOTAL);
+        self.index();
+    }
 
-        // Ensure minimum frequency for each symbol (Laplace smoothing)
-        for symbol in &self.symbols {
-            let freq = self.frequencies.get_mut(symbol).unwrap();
-            if *freq == 0 {
-                *freq = 1;
-                self.total_frequency += 1;
-            }
+    /// Rebuild the lookup tables from `symbols` and `frequencies`
+    fn index(&mut self) {
+        self.positions = [None; 256];
+        self.cumulative.clear();
+        let mut cumulative = 0;
+        for (position, &symbol) in self.symbols.iter().enumerate() {
+            self.positions[symbol as usize] = Some(position as u8);
+            self.cumulative.push(cumulative);
+            cumulative += self.frequencies.get(&symbol).copied().unwrap_or(0);
+        }
+        self.cumulative.push(cumulative);
+    }
+
+    /// Scale frequencies down proportionally so the total is at most `max_total`
+    ///
+    /// Every symbol keeps a frequency of at least 1, so nothing becomes
+    /// unencodable. Models already within the limit are left exact.
+    pub fn rescale(&mut self, max_total: u64) {
+        if self.total_frequency <= max_total {
+            return;
+        }
+
+        // Reserve one count per symbol for the minimum-frequency floor
+        let budget = max_total.saturating_sub(self.symbols.len() as u64).max(1);
+        let total = self.total_frequency;
+        self.total_frequency = 0;
+        for frequency in self.frequencies.values_mut() {
+            *frequency = ((*frequency as u128 * budget as u128 / total as u128) as u64).max(1);
+            self.total_frequency += *frequency;
         }
+        self.index();
     }
 
     /// Get probability range for a symbol
     pub fn get_symbol_range(&self, symbol: u8) -> Option<(u64, u64)> {
-        let mut cumulative = 0;
-        
-        for &s in &self.symbols {
-            let freq = *self.frequencies.get(&s)?;
-            if s == symbol {
-                return Some((cumulative, cumulative + freq));
-            }
-            cumulative += freq;
-        }
-        
-        None
+        let position = self.positions[symbol as usize]? as usize;
+        Some((self.cumulative[position], self.cumulative[position + 1]))
     }
 
     /// Get symbol from cumulative value
     pub fn get_symbol_from_value(&self, value: u64) -> Option<u8> {
-        let mut cumulative = 0;
-        
-        for &symbol in &self.symbols {
-            let freq = self.frequencies.get(&symbol)?;
-            if value >= cumulative && value < cumulative + freq {
-                return Some(symbol);
-            }
-            cumulative += freq;
-        }
-        
-        None
+        self.get_range_from_value(value).map(|(symbol, _, _)| symbol)
     }
 
     /// Get symbol range from cumulative value
     pub fn get_range_from_value(&self, value: u64) -> Option<(u8, u64, u64)> {
-        let mut cumulative = 0;
-        
-        for &symbol in &self.symbols {
-            let freq = self.frequencies.get(&symbol)?;
-            if value >= cumulative && value < cumulative + freq {
-                return Some((symbol, cumulative, cumulative + freq));
-            }
-            cumulative += freq;
+        if value >= *self.cumulative.last()? {
+            return None;
         }
-        
-        None
+        // The last range starting at or before `value`, which skips symbols of frequency 0
+        let position = self.cumulative.partition_point(|&start| start <= value) - 1;
+        Some((self.symbols[position], self.cumulative[position], self.cumulative[position + 1]))
     }
 
     pub fn total_frequency(&self) -> u64 {
@@ -285,14 +366,120 @@ impl FrequencyModel {
     }
 
     pub fn deserialize(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
-        let (frequencies, symbols): (HashMap<u8, u64>, Vec<u8>) = serde_json::from_slice(data)?;
-        let total_frequency = frequencies.values().sum();
-        
-        Ok(Self {
-            frequencies,
-            total_frequency,
-            symbols,
-        })
+        let (frequencies, symbols): (BTreeMap<u8, u64>, Vec<u8>) = serde_json::from_slice(data)?;
+        Self::from_parts(frequencies, symbols)
+    }
+
+    /// Serialize the model compactly: symbol count (u16 LE), then each symbol
+    /// byte followed by its frequency as a LEB128 varint
+    ///
+    /// This costs 2-4 bytes per symbol instead of ~10 for the JSON form, which
+    /// matters for large alphabets: UTF-8 text in non-Latin scripts touches
+    /// well over a hundred distinct byte values.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(2 + self.symbols.len() * 3);
+        output.extend_from_slice(&(self.symbols.len() as u16).to_le_bytes());
+
+        for &symbol in &self.symbols {
+            output.push(symbol);
+            let mut frequency = self.frequencies.get(&symbol).copied().unwrap_or(0);
+            loop {
+                let byte = (frequency & 0x7F) as u8;
+                frequency >>= 7;
+                if frequency == 0 {
+                    output.push(byte);
+                    break;
+                }
+                output.push(byte | 0x80);
+            }
+        }
+
+        output
+    }
+
+    /// Deserialize a model written by `to_compact_bytes`
+    pub fn from_compact_bytes(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if data.len() < 2 {
+            return Err("compact model too small".into());
+        }
+
+        let symbol_count = u16::from_le_bytes([data[0], data[1]]) as usize;
+        let mut position = 2;
+        let mut frequencies = BTreeMap::new();
+        let mut symbols = Vec::with_capacity(symbol_count);
+
+        for _ in 0..symbol_count {
+            let symbol = *data.get(position).ok_or("compact model truncated")?;
+            position += 1;
+
+            let mut frequency = 0u64;
+            let mut shift = 0;
+            loop {
+                let byte = *data.get(position).ok_or("compact model truncated")?;
+                position += 1;
+                if shift >= 64 {
+                    return Err("compact model frequency overflow".into());
+                }
+                frequency |= ((byte & 0x7F) as u64) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            if frequencies.insert(symbol, frequency).is_some() {
+                return Err(format!("duplicate symbol {} in compact model", symbol).into());
+            }
+            symbols.push(symbol);
+        }
+
+        if position != data.len() {
+            return Err("trailing bytes after compact model".into());
+        }
+
+        Self::from_parts(frequencies, symbols)
+    }
+
+    fn from_parts(frequencies: BTreeMap<u8, u64>, symbols: Vec<u8>) -> Result<Self, Box<dyn std::error::Error>> {
+        // Equal sizes and every symbol present: no duplicates, and no frequency left out
+        if symbols.len() != frequencies.len() || symbols.iter().any(|symbol| !frequencies.contains_key(symbol)) {
+            return Err("frequency model symbols don't match its frequencies".into());
+        }
+        let mut model = Self { total_frequency: frequencies.values().sum(), frequencies, symbols, ..Self::new() };
+        model.index();
+        Ok(model)
+    }
+}
+
+/// Dry-run cost of coding symbols with a [`FrequencyModel`]
+///
+/// Sums `-log2 p` per symbol instead of running the coder, which the real
+/// output tracks to within a few bits over long sequences.
+pub struct CostEstimator {
+    /// Cost of each byte value in bits, infinite for symbols the model lacks
+    bits: [f64; 256],
+}
+
+impl CostEstimator {
+    pub fn new(model: &FrequencyModel) -> Self {
+        let total = model.total_frequency() as f64;
+        let mut bits = [f64::INFINITY; 256];
+        for (&symbol, &frequency) in &model.frequencies {
+            if frequency > 0 {
+                bits[symbol as usize] = round_table_value((total / frequency as f64).log2());
+            }
+        }
+        Self { bits }
+    }
+
+    /// Cost of one symbol in bits, infinite if the model cannot code it
+    pub fn symbol_bits(&self, symbol: u8) -> f64 {
+        self.bits[symbol as usize]
+    }
+
+    /// Total cost of a symbol sequence in bits
+    pub fn estimate_bits(&self, symbols: impl IntoIterator<Item = u8>) -> f64 {
+        symbols.into_iter().map(|symbol| self.bits[symbol as usize]).sum()
     }
 }
 
@@ -312,7 +499,7 @@ mod tests {
         let mut encoder = ArithmeticCoder::new();
         for &byte in test_data {
             if let Some((low, high)) = model.get_symbol_range(byte) {
-                encoder.encode_symbol(low, high, model.total_frequency());
+                encoder.encode_symbol(low, high, model.total_frequency()).unwrap();
             }
         }
         let compressed = encoder.finish();
@@ -322,13 +509,125 @@ mod tests {
         let mut decoded = Vec::new();
         
         for _ in 0..test_data.len() {
-            let value = decoder.get_symbol_value(model.total_frequency());
+            let value = decoder.get_symbol_value(model.total_frequency()).unwrap();
             if let Some((symbol, low, high)) = model.get_range_from_value(value) {
                 decoded.push(symbol);
-                decoder.decode_symbol(low, high, model.total_frequency());
+                decoder.decode_symbol(low, high, model.total_frequency()).unwrap();
             }
         }
         
         assert_eq!(test_data, decoded.as_slice());
     }
-}
\ No newline at end of file
+
+    /// Three symbols: two of frequency 1 at either end of a total, one taking the rest
+    fn skewed_ranges(total: u64) -> [(u64, u64); 3] {
+        [(0, 1), (1, total - 1), (total - 1, total)]
+    }
+
+    fn roundtrip_with_total(total: u64) {
+        let ranges = skewed_ranges(total);
+        let symbols: Vec<usize> = (0..2000u64)
+            .map(|i| match i % 97 {
+                0 => 0,
+                50 => 2,
+                _ => 1,
+            })
+            .collect();
+
+        let mut encoder = ArithmeticCoder::new();
+        for &symbol in &symbols {
+            let (low, high) = ranges[symbol];
+            encoder.encode_symbol(low, high, total).unwrap();
+        }
+        let compressed = encoder.finish();
+
+        let mut decoder = ArithmeticDecoder::new(compressed);
+        for (position, &expected) in symbols.iter().enumerate() {
+            let value = decoder.get_symbol_value(total).unwrap();
+            let symbol = ranges.iter().position(|&(low, high)| value >= low && value < high)
+                .unwrap_or_else(|| panic!("value {} out of range at {} (total {})", value, position, total));
+            assert_eq!(symbol, expected, "position {} with total {}", position, total);
+            let (low, high) = ranges[symbol];
+            decoder.decode_symbol(low, high, total).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_large_totals_roundtrip() {
+        for total in [(1 << 16) - 1, 1 << 20, MAX_TOTAL - 1, MAX_TOTAL] {
+            roundtrip_with_total(total);
+        }
+    }
+
+    #[test]
+    fn test_total_above_max_is_rejected() {
+        let mut encoder = ArithmeticCoder::new();
+        assert_eq!(
+            encoder.encode_symbol(0, 1, MAX_TOTAL + 1),
+            Err(CoderError::TotalTooLarge { total: MAX_TOTAL + 1, max: MAX_TOTAL })
+        );
+        assert!(matches!(encoder.encode_symbol(5, 5, 10), Err(CoderError::InvalidRange { .. })));
+        assert!(matches!(encoder.encode_symbol(0, 11, 10), Err(CoderError::InvalidRange { .. })));
+
+        let decoder = ArithmeticDecoder::new(vec![0; 8]);
+        assert!(matches!(decoder.get_symbol_value(MAX_TOTAL + 1), Err(CoderError::TotalTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_rescale_keeps_every_symbol() {
+        let mut model = FrequencyModel::new();
+        model.build_from_data(b"aaaaaaaaaaaaaaaaaaaabbbbbc");
+        model.rescale(8);
+
+        assert!(model.total_frequency() <= 8);
+        for symbol in [b'a', b'b', b'c'] {
+            let (low, high) = model.get_symbol_range(symbol).unwrap();
+            assert!(high > low);
+        }
+        let (a_low, a_high) = model.get_symbol_range(b'a').unwrap();
+        assert!(a_high - a_low >= 3);
+    }
+
+    #[test]
+    fn test_lookups_skip_zero_frequency_symbols() {
+        // Symbols b'b' and b'd' have frequency 0, as a compact model may carry
+        let model = FrequencyModel::from_compact_bytes(&[5, 0, b'a', 3, b'b', 0, b'c', 2, b'd', 0, b'e', 1]).unwrap();
+        assert_eq!(model.total_frequency(), 6);
+        assert_eq!(model.get_symbol_range(b'c'), Some((3, 5)));
+        assert_eq!(model.get_symbol_range(b'b'), Some((3, 3)));
+        assert_eq!(model.get_symbol_range(b'z'), None);
+
+        let decoded: Vec<u8> = (0..6).map(|value| model.get_symbol_from_value(value).unwrap()).collect();
+        assert_eq!(decoded, b"aaacce");
+        assert_eq!(model.get_range_from_value(5), Some((b'e', 5, 6)));
+        assert_eq!(model.get_range_from_value(6), None);
+    }
+
+    #[test]
+    fn test_estimate_matches_bits_written() {
+        // Skewed pseudo-random source over a small alphabet
+        let mut state = 0x2545_f491_u32;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                b"eeeeetttaaoinshrdlu"[(state % 19) as usize]
+            })
+            .collect();
+        let mut model = FrequencyModel::new();
+        model.build_from_data(&data);
+
+        let mut encoder = ArithmeticCoder::new();
+        for &byte in &data {
+            let (low, high) = model.get_symbol_range(byte).unwrap();
+            encoder.encode_symbol(low, high, model.total_frequency()).unwrap();
+        }
+        let actual = encoder.bits_written() as f64;
+        let estimate = CostEstimator::new(&model).estimate_bits(data.iter().copied());
+
+        assert!((estimate - actual).abs() / actual < 0.005, "estimate {} vs actual {}", estimate, actual);
+        assert!(encoder.finish().len() as f64 * 8.0 >= actual);
+        assert_eq!(CostEstimator::new(&model).symbol_bits(b'z'), f64::INFINITY);
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::ops::Range;
+
+/// Where a chunked TCF file cuts its text into independently coded chunks
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStrategy {
+    /// Chunks of at most this many bytes, with no notion of records
+    FixedBytes(usize),
+    /// Records end with this ASCII byte; chunks end after the last record
+    /// that fits in the byte limit
+    OnDelimiter(u8, usize),
+    /// Records are paragraphs separated by blank lines; chunks end after the
+    /// last paragraph that fits in the byte limit
+    OnBlankLine(usize),
+}
+
+impl ChunkStrategy {
+    /// Bytes past `max_bytes` that `chunk_end` may look at: the byte after
+    /// the limit, and the rest of a character straddling it
+    pub const LOOKAHEAD: usize = 4;
+
+    pub fn max_bytes(&self) -> usize {
+        match *self {
+            ChunkStrategy::FixedBytes(max_bytes)
+            | ChunkStrategy::OnDelimiter(_, max_bytes)
+            | ChunkStrategy::OnBlankLine(max_bytes) => max_bytes,
+        }
+    }
+
+    /// Whether the text is split into records at all
+    pub fn has_records(&self) -> bool {
+        !matches!(self, ChunkStrategy::FixedBytes(_))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_bytes() == 0 {
+            anyhow::bail!("TCF chunk size must be at least 1 byte");
+        }
+        if let ChunkStrategy::OnDelimiter(delimiter, _) = self {
+            if !delimiter.is_ascii() {
+                anyhow::bail!("TCF chunk delimiter must be an ASCII byte, got 0x{:02x}", delimiter);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a record starts at byte `position` of `text`
+    ///
+    /// A record starts at the beginning of the text, after each delimiter,
+    /// and after each run of two or more newlines. Only the two bytes before
+    /// `position` and the byte at it are looked at.
+    pub fn is_record_start(&self, text: &[u8], position: usize) -> bool {
+        if position == 0 {
+            return self.has_records();
+        }
+        match *self {
+            ChunkStrategy::FixedBytes(_) => false,
+            ChunkStrategy::OnDelimiter(delimiter, _) => text.get(position - 1) == Some(&delimiter),
+            ChunkStrategy::OnBlankLine(_) => {
+                position >= 2
+                    && text.get(position - 2..position) == Some(b"\n\n".as_slice())
+                    && text.get(position) != Some(&b'\n')
+            }
+        }
+    }
+
+    /// Byte ranges of the chunks of `text`, in order and covering it exactly
+    ///
+    /// Every chunk ends on a character boundary. Record strategies end a
+    /// chunk at the last record start that keeps it within `max_bytes`,
+    /// falling back to a plain `max_bytes` cut for records that don't fit.
+    pub fn split(&self, text: &str) -> Vec<Range<usize>> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < text.len() {
+            let end = self.chunk_end(text, start);
+            chunks.push(start..end);
+            start = end;
+        }
+
+        chunks
+    }
+
+    /// End of the chunk of `text` starting at `start`, as `split` cuts it
+    ///
+    /// Looks at the two bytes before `start` and at most `max_bytes` +
+    /// `LOOKAHEAD` bytes after it, so a window of the text holding those
+    /// gives the same end as the whole text.
+    pub fn chunk_end(&self, text: &str, start: usize) -> usize {
+        let bytes = text.as_bytes();
+        let limit = (start + self.max_bytes().max(1)).min(bytes.len());
+        if limit == bytes.len() {
+            return limit;
+        }
+        (start + 1..=limit).rev()
+            .find(|&position| self.is_record_start(bytes, position))
+            .unwrap_or_else(|| Self::char_boundary_before(text, start, limit))
+    }
+
+    /// Offset within `chunk` of the first record starting in it, if any
+    pub fn first_record(&self, text: &[u8], chunk: Range<usize>) -> Option<usize> {
+        chunk.clone()
+            .find(|&position| self.is_record_start(text, position))
+            .map(|position| position - chunk.start)
+    }
+
+    /// The last character boundary at or before `limit`, or the first one
+    /// after it when a single character straddles `start..limit`
+    fn char_boundary_before(text: &str, start: usize, limit: usize) -> usize {
+        (start + 1..=limit).rev()
+            .find(|&position| text.is_char_boundary(position))
+            .unwrap_or_else(|| (limit..=text.len()).find(|&position| text.is_char_boundary(position)).unwrap_or(text.len()))
+    }
+}
+
+/// Cuts text read from a source into the chunks `ChunkStrategy::split`
+/// would cut the whole text into, without seeking
+///
+/// Holds one chunk, `LOOKAHEAD` bytes past it and the characters covering
+/// the two bytes before it that record starts depend on.
+pub struct ChunkReader<R> {
+    source: R,
+    strategy: ChunkStrategy,
+    /// Kept history, then unread text, then bytes read ahead
+    buffer: Vec<u8>,
+    /// Where the next chunk starts in `buffer`
+    start: usize,
+    /// Offset in the text of `buffer[start]`
+    offset: u64,
+    /// Length of the chunk last returned, dropped on the next call
+    returned: usize,
+    at_end: bool,
+}
+
+/// One chunk from `ChunkReader::next_chunk`
+#[derive(Debug)]
+pub struct TextChunk<'a> {
+    pub text: &'a str,
+    /// Where the chunk starts in the text
+    pub text_offset: u64,
+    /// Offset within the chunk of the first record starting in it
+    pub first_record: Option<u64>,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(source: R, strategy: ChunkStrategy) -> Result<Self> {
+        strategy.validate()?;
+        Ok(Self { source, strategy, buffer: Vec::new(), start: 0, offset: 0, returned: 0, at_end: false })
+    }
+
+    /// Read up to the end of the next chunk, or `None` at the end of the text
+    ///
+    /// Fails if the source does, or isn't UTF-8.
+    pub fn next_chunk(&mut self) -> Result<Option<TextChunk<'_>>> {
+        self.consume();
+        let wanted = self.start + self.strategy.max_bytes() + ChunkStrategy::LOOKAHEAD;
+        if !self.at_end && self.buffer.len() < wanted {
+            let missing = (wanted - self.buffer.len()) as u64;
+            self.buffer.reserve(missing as usize);
+            let read = (&mut self.source).take(missing).read_to_end(&mut self.buffer)
+                .context("Failed to read text to chunk")?;
+            self.at_end = (read as u64) < missing;
+        }
+
+        // Only a character the read ahead cut short may be incomplete
+        let valid = match std::str::from_utf8(&self.buffer) {
+            Ok(text) => text.len(),
+            Err(error) if error.error_len().is_none() && !self.at_end => error.valid_up_to(),
+            Err(error) => anyhow::bail!("Invalid UTF-8 at byte {} of the text", self.offset - self.start as u64 + error.valid_up_to() as u64),
+        };
+        if self.start == valid {
+            return Ok(None);
+        }
+        let text = std::str::from_utf8(&self.buffer[..valid]).expect("validated above");
+        let end = self.strategy.chunk_end(text, self.start);
+        self.returned = end - self.start;
+        Ok(Some(TextChunk {
+            text: &text[self.start..end],
+            text_offset: self.offset,
+            first_record: self.strategy.first_record(text.as_bytes(), self.start..end).map(|offset| offset as u64),
+        }))
+    }
+
+    /// Drop the chunk last returned, keeping the characters covering its
+    /// last two bytes
+    fn consume(&mut self) {
+        let end = self.start + std::mem::take(&mut self.returned);
+        self.offset += (end - self.start) as u64;
+        // History starts on a character boundary, so the buffer stays UTF-8
+        let keep = (0..=end.saturating_sub(2)).rev()
+            .find(|&position| self.buffer.get(position).is_none_or(|byte| byte & 0xC0 != 0x80))
+            .unwrap_or(0);
+        self.buffer.drain(..keep);
+        self.start = end - keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_prefers_record_boundaries() {
+        let text = "one\ntwo\nthree\n";
+        let strategy = ChunkStrategy::OnDelimiter(b'\n', 9);
+        let chunks: Vec<&str> = strategy.split(text).into_iter().map(|range| &text[range]).collect();
+        assert_eq!(chunks, ["one\ntwo\n", "three\n"]);
+
+        // A record longer than the limit is cut, leaving chunks with no record start
+        let text = "a very long record\nshort\n";
+        let strategy = ChunkStrategy::OnDelimiter(b'\n', 8);
+        let chunks = strategy.split(text);
+        assert_eq!(chunks, [0..8, 8..16, 16..19, 19..25]);
+        let first_records: Vec<Option<usize>> = chunks.into_iter()
+            .map(|chunk| strategy.first_record(text.as_bytes(), chunk))
+            .collect();
+        assert_eq!(first_records, [Some(0), None, None, Some(0)]);
+    }
+
+    #[test]
+    fn test_blank_line_records_and_char_boundaries() {
+        let text = "para one\n\n\npara two\nline\n\nlast";
+        let strategy = ChunkStrategy::OnBlankLine(15);
+        let chunks: Vec<&str> = strategy.split(text).into_iter().map(|range| &text[range]).collect();
+        assert_eq!(chunks, ["para one\n\n\n", "para two\nline\n\n", "last"]);
+
+        let text = "ééééé";
+        let chunks = ChunkStrategy::FixedBytes(3).split(text);
+        assert!(chunks.iter().all(|range| text.is_char_boundary(range.start) && range.len() == 2));
+        assert_eq!(ChunkStrategy::FixedBytes(1).split(text).len(), 5);
+    }
+}
@@ -0,0 +1,196 @@
+//! Adaptive context model for the arithmetic coder
+//!
+//! PPM-style: each byte is coded in the longest context of up to
+//! `max_order` preceding bytes that has been seen before, escaping to the
+//! next shorter one when the byte is new to it, and to a flat model over
+//! all 256 values below order 0. A context's escape is weighted by the
+//! number of distinct bytes it has seen. Encoder and decoder update the
+//! same counts after every byte, so nothing about the model is stored.
+use super::arithmetic_coder::{ArithmeticCoder, ArithmeticDecoder};
+use super::model_stats::MAX_MODEL_ORDER;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// Context total at which counts are halved, so the model keeps adapting
+const RESCALE_TOTAL: u32 = 1 << 16;
+
+/// Most bytes one byte of coded output can hold
+///
+/// A context's counts stay under `RESCALE_TOTAL` and its escape counts
+/// one more, so no byte is likelier than `1 - 1 / RESCALE_TOTAL` and each
+/// costs over `1 / (RESCALE_TOTAL * ln 2)` bits. This allows twice the
+/// resulting bytes per bit, for the coder's rounding.
+pub const MAX_BYTES_PER_CODED_BYTE: u64 = 2 * 8 * RESCALE_TOTAL as u64;
+
+/// What one context has seen, in the order it first saw each byte
+#[derive(Default)]
+struct Counts {
+    symbols: Vec<(u8, u32)>,
+    total: u32,
+}
+
+impl Counts {
+    /// Total the coder divides this context's range by: the counts, then
+    /// the escape
+    fn coding_total(&self) -> u64 {
+        self.total as u64 + self.symbols.len() as u64
+    }
+
+    fn range(&self, byte: u8) -> Option<(u64, u64)> {
+        let mut low = 0;
+        for &(symbol, count) in &self.symbols {
+            if symbol == byte {
+                return Some((low, low + count as u64));
+            }
+            low += count as u64;
+        }
+        None
+    }
+
+    /// The byte whose range holds `value`, `None` for the escape
+    fn symbol_at(&self, value: u64) -> Option<(u8, u64, u64)> {
+        let mut low = 0;
+        for &(symbol, count) in &self.symbols {
+            let high = low + count as u64;
+            if value < high {
+                return Some((symbol, low, high));
+            }
+            low = high;
+        }
+        None
+    }
+
+    fn add(&mut self, byte: u8) {
+        match self.symbols.iter_mut().find(|(symbol, _)| *symbol == byte) {
+            Some((_, count)) => *count += 1,
+            None => self.symbols.push((byte, 1)),
+        }
+        self.total += 1;
+        if self.total >= RESCALE_TOTAL {
+            self.total = 0;
+            for (_, count) in &mut self.symbols {
+                *count = (*count / 2).max(1);
+                self.total += *count;
+            }
+        }
+    }
+}
+
+/// Byte model conditioned on up to `max_order` preceding bytes
+pub struct ContextModel {
+    max_order: usize,
+    /// Counts of each context seen so far, keyed by order and its bytes
+    contexts: HashMap<(usize, u64), Counts>,
+    /// The last eight bytes, most recent lowest
+    history: u64,
+    /// Bytes of history, up to `max_order`
+    seen: usize,
+}
+
+impl ContextModel {
+    pub fn new(max_order: usize) -> Result<Self> {
+        if max_order > MAX_MODEL_ORDER {
+            bail!("Model order {} is above the maximum of {}", max_order, MAX_MODEL_ORDER);
+        }
+        Ok(Self { max_order, contexts: HashMap::new(), history: 0, seen: 0 })
+    }
+
+    /// Code `byte` and learn from it
+    pub fn encode(&mut self, coder: &mut ArithmeticCoder, byte: u8) -> Result<()> {
+        for order in (0..=self.seen).rev() {
+            let Some(counts) = self.contexts.get(&self.key(order)) else {
+                continue;
+            };
+            let total = counts.coding_total();
+            match counts.range(byte) {
+                Some((low, high)) => {
+                    coder.encode_symbol(low, high, total)?;
+                    self.update(byte);
+                    return Ok(());
+                }
+                None => coder.encode_symbol(counts.total as u64, total, total)?,
+            }
+        }
+        coder.encode_symbol(byte as u64, byte as u64 + 1, 256)?;
+        self.update(byte);
+        Ok(())
+    }
+
+    /// Decode the next byte and learn from it
+    pub fn decode(&mut self, decoder: &mut ArithmeticDecoder) -> Result<u8> {
+        for order in (0..=self.seen).rev() {
+            let Some(counts) = self.contexts.get(&self.key(order)) else {
+                continue;
+            };
+            let total = counts.coding_total();
+            let value = decoder.get_symbol_value(total)?;
+            match counts.symbol_at(value) {
+                Some((symbol, low, high)) => {
+                    decoder.decode_symbol(low, high, total)?;
+                    self.update(symbol);
+                    return Ok(symbol);
+                }
+                None => decoder.decode_symbol(counts.total as u64, total, total)?,
+            }
+        }
+        let value = decoder.get_symbol_value(256)?;
+        let byte = u8::try_from(value).context("Context model decoded a value outside its byte range")?;
+        decoder.decode_symbol(value, value + 1, 256)?;
+        self.update(byte);
+        Ok(byte)
+    }
+
+    fn key(&self, order: usize) -> (usize, u64) {
+        let mask = u64::MAX.checked_shr(64 - 8 * order as u32).unwrap_or(0);
+        (order, self.history & mask)
+    }
+
+    fn update(&mut self, byte: u8) {
+        for order in 0..=self.seen {
+            let key = self.key(order);
+            self.contexts.entry(key).or_default().add(byte);
+        }
+        self.history = (self.history << 8) | byte as u64;
+        self.seen = (self.seen + 1).min(self.max_order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8], max_order: usize) -> usize {
+        let mut model = ContextModel::new(max_order).unwrap();
+        let mut coder = ArithmeticCoder::new();
+        for &byte in data {
+            model.encode(&mut coder, byte).unwrap();
+        }
+        let coded = coder.finish();
+
+        let mut model = ContextModel::new(max_order).unwrap();
+        let mut decoder = ArithmeticDecoder::new(coded.clone());
+        let decoded: Vec<u8> = (0..data.len()).map(|_| model.decode(&mut decoder).unwrap()).collect();
+        assert!(decoded == data, "order {} didn't round-trip", max_order);
+        coded.len()
+    }
+
+    #[test]
+    fn test_every_order_roundtrips() {
+        // Every byte value, long repeats to force rescaling, then text
+        let mut data: Vec<u8> = (0..=255).collect();
+        data.resize(data.len() + 3 * RESCALE_TOTAL as usize, b'z');
+        data.extend_from_slice("context models predict the next byte from the last few 🦀".repeat(50).as_bytes());
+        for order in 0..=MAX_MODEL_ORDER {
+            roundtrip(&data, order);
+        }
+        assert_eq!(roundtrip(b"", 3), 1);
+        assert!(ContextModel::new(MAX_MODEL_ORDER + 1).is_err());
+    }
+
+    #[test]
+    fn test_long_runs_stay_within_the_expansion_bound() {
+        let run = vec![b'z'; 1 << 21];
+        let coded = roundtrip(&run, 2);
+        assert!((run.len() as u64) < coded as u64 * MAX_BYTES_PER_CODED_BYTE / 2, "{} bytes coded to {}", run.len(), coded);
+    }
+}
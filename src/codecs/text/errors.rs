@@ -0,0 +1,112 @@
+use std::fmt;
+
+/// Stable category of a TCF decode failure, for callers deciding what to
+/// do next
+///
+/// `ChecksumMismatch` and `InvalidFormat` mean this copy of the file is
+/// damaged, so another copy may decode; the `Unsupported*` codes mean the
+/// file is fine but this build can't read it; `Truncated` means the data
+/// stops short of what the header declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TcfErrorCode {
+    /// Not a TCF file, or a header or index that contradicts itself
+    InvalidFormat,
+    /// Written by a newer version of the format
+    UnsupportedVersion,
+    /// A compression method or tokenizer this build doesn't know or wasn't
+    /// built with, or a dictionary the caller didn't supply
+    UnsupportedMethod,
+    /// A checksum that isn't a digest this build can verify
+    UnsupportedChecksum,
+    Truncated,
+    /// The payload didn't decode, or decoded to text that doesn't match its checksum
+    ChecksumMismatch,
+}
+
+impl TcfErrorCode {
+    /// Code of the first `TcfError` in `error`'s chain; errors that carry
+    /// none are `InvalidFormat`
+    pub fn of(error: &anyhow::Error) -> Self {
+        error.chain()
+            .find_map(|cause| cause.downcast_ref::<TcfError>())
+            .map_or(TcfErrorCode::InvalidFormat, TcfError::code)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TcfErrorCode::InvalidFormat => "invalid_format",
+            TcfErrorCode::UnsupportedVersion => "unsupported_version",
+            TcfErrorCode::UnsupportedMethod => "unsupported_method",
+            TcfErrorCode::UnsupportedChecksum => "unsupported_checksum",
+            TcfErrorCode::Truncated => "truncated",
+            TcfErrorCode::ChecksumMismatch => "checksum_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for TcfErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// TCF failures callers may want to tell apart; see `TcfErrorCode`
+#[derive(Debug, thiserror::Error)]
+pub enum TcfError {
+    #[error("Invalid TCF magic number")]
+    InvalidMagic,
+    #[error("Unsupported TCF version: {0}")]
+    UnsupportedVersion(u16),
+    #[error("Legacy TCF1 file; convert it with `tcf-cli migrate`")]
+    LegacyFormat,
+    #[error("Unsupported TCF compression method: {method}{}", if *.missing_feature { format!(" (built without the '{}' feature)", .method) } else { String::new() })]
+    UnsupportedMethod { method: String, missing_feature: bool },
+    #[error("Unknown TCF tokenizer: {0}")]
+    UnknownTokenizer(String),
+    #[error("Unsupported TCF checksum {0:?}: not a SHA-256 digest")]
+    UnsupportedChecksum(String),
+    /// The data ends inside `section`
+    #[error("TCF file is truncated: {section} needs {needed} bytes, has {available}")]
+    Truncated { section: &'static str, needed: u64, available: u64 },
+    #[error("TCF checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("TCF chunk {0} checksum mismatch")]
+    ChunkChecksumMismatch(usize),
+    /// Chunk `index`'s data doesn't start where the chunks before it end;
+    /// an `index` past the last chunk means they don't end at the
+    /// header's `compressed_size`
+    #[error("TCF chunk {index} data starts at {actual}, expected {expected}")]
+    ChunkOffsetMismatch { index: usize, expected: u64, actual: u64 },
+    /// Coded with a dictionary that neither the file nor the caller supplied
+    #[error("TCF file was coded with dictionary {0}, which it doesn't carry; decode it with that dictionary")]
+    MissingDictionary(String),
+    #[error("TCF file was coded with dictionary {expected}, not {actual}")]
+    DictionaryMismatch { expected: String, actual: String },
+    /// A model or payload that failed to decode although its sizes check out
+    #[error("Corrupt TCF {method} payload: {cause:#}")]
+    CorruptPayload { method: String, cause: anyhow::Error },
+}
+
+impl TcfError {
+    pub fn code(&self) -> TcfErrorCode {
+        match self {
+            TcfError::InvalidMagic | TcfError::ChunkOffsetMismatch { .. } => TcfErrorCode::InvalidFormat,
+            TcfError::UnsupportedVersion(_) | TcfError::LegacyFormat => TcfErrorCode::UnsupportedVersion,
+            TcfError::UnsupportedMethod { .. } | TcfError::UnknownTokenizer(_) => TcfErrorCode::UnsupportedMethod,
+            TcfError::MissingDictionary(_) | TcfError::DictionaryMismatch { .. } => TcfErrorCode::UnsupportedMethod,
+            TcfError::UnsupportedChecksum(_) => TcfErrorCode::UnsupportedChecksum,
+            TcfError::Truncated { .. } => TcfErrorCode::Truncated,
+            TcfError::ChecksumMismatch { .. } | TcfError::ChunkChecksumMismatch(_) | TcfError::CorruptPayload { .. } => {
+                TcfErrorCode::ChecksumMismatch
+            }
+        }
+    }
+
+    /// `Truncated` unless `data` holds `needed` bytes
+    pub(super) fn check_length(section: &'static str, needed: u64, data: &[u8]) -> Result<(), TcfError> {
+        if (data.len() as u64) < needed {
+            return Err(TcfError::Truncated { section, needed, available: data.len() as u64 });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{bail, Context, Result};
+
+/// Whether the lines of `text` are in byte order
+///
+/// The empty piece after a trailing newline doesn't count as a line.
+pub fn is_sorted_lines(text: &str) -> bool {
+    text.strip_suffix('\n').unwrap_or(text).split('\n').is_sorted()
+}
+
+/// Front code sorted lines
+///
+/// Each line becomes a varint of the bytes it shares with the previous
+/// line, then the rest of the line and a newline. Fails if the lines are
+/// not sorted, where the shared prefixes would be too short to pay off.
+pub fn front_encode(text: &str) -> Result<Vec<u8>> {
+    if !is_sorted_lines(text) {
+        bail!("Front coding needs lines in sorted order");
+    }
+    let mut coded = Vec::with_capacity(text.len() / 2);
+    let mut previous: &[u8] = &[];
+    for line in text.split('\n').map(str::as_bytes) {
+        let shared = previous.iter().zip(line).take_while(|(a, b)| a == b).count();
+        write_varint(&mut coded, shared as u64);
+        coded.extend_from_slice(&line[shared..]);
+        coded.push(b'\n');
+        previous = line;
+    }
+    Ok(coded)
+}
+
+/// Rebuild the text `front_encode` coded, failing once it passes `limit` bytes
+pub fn front_decode(coded: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut text = Vec::with_capacity(coded.len().min(limit));
+    let mut previous = 0..0;
+    let mut position = 0;
+    while position < coded.len() {
+        if position > 0 {
+            text.push(b'\n');
+        }
+        let shared = usize::try_from(read_varint(coded, &mut position).context("Corrupt front coded line")?)?;
+        let end = coded[position..].iter().position(|&byte| byte == b'\n')
+            .map(|length| position + length)
+            .context("Front coded line has no terminator")?;
+        if shared > previous.len() {
+            bail!("Front coded line shares {} bytes with a {} byte line", shared, previous.len());
+        }
+        let start = text.len();
+        if start + shared + (end - position) > limit {
+            bail!("Front coded text runs past {} bytes", limit);
+        }
+        text.extend_from_within(previous.start..previous.start + shared);
+        text.extend_from_slice(&coded[position..end]);
+        previous = start..text.len();
+        position = end + 1;
+    }
+    Ok(text)
+}
+
+pub(super) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub(super) fn read_varint(data: &[u8], position: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*position).context("Truncated varint")?;
+        *position += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Corrupt varint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_front_coding_roundtrip() {
+        for text in ["", "\n", "a", "a\n", "a\na\nab\nb", "a\nab\nabc\n", "\n\na\nb", "é\nêtre\n"] {
+            assert!(is_sorted_lines(text), "{:?}", text);
+            let coded = front_encode(text).unwrap();
+            assert_eq!(front_decode(&coded, text.len()).unwrap(), text.as_bytes(), "{:?}", text);
+        }
+        let coded = front_encode("abcdef\nabcdefg").unwrap();
+        assert!(front_decode(&coded, 13).is_err());
+        assert!(front_encode("b\na").is_err());
+        assert!(is_sorted_lines("a\nb\n"));
+        assert!(!is_sorted_lines("a\nb\n\n"));
+    }
+}
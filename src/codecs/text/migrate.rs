@@ -0,0 +1,289 @@
+//! Converting legacy TCF1 files to the current format
+//!
+//! TCF1 is the TypeScript codec's container: `TCF1`, a u32 LE header
+//! length, a camelCase JSON header, then a run-length coded payload in
+//! which `0xFF, byte, count` is a run and any other byte is itself. It has
+//! no model parameters, so migrated files use the default encode options.
+use crate::codecs::atomic::write_atomic;
+use crate::codecs::formats;
+use super::tcf_codec::{TcfCodec, TcfEncodeOptions};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Header note of the legacy file a TCF file was converted from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MigrationRecord {
+    /// Magic of the legacy container, e.g. `TCF1`
+    pub format: String,
+    pub version: u16,
+    /// The legacy header's checksum
+    pub checksum: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Tcf1Header {
+    version: u16,
+    original_size: u64,
+    checksum: String,
+}
+
+/// Whether `data` starts like a TCF1 file
+pub fn is_tcf1(data: &[u8]) -> bool {
+    data.starts_with(formats::TCF1.magic.as_bytes())
+}
+
+/// Decode a TCF1 file, checking its size and checksum
+pub fn decode_tcf1(data: &[u8]) -> Result<String> {
+    Ok(parse_tcf1(data)?.1)
+}
+
+fn parse_tcf1(data: &[u8]) -> Result<(Tcf1Header, String)> {
+    if !is_tcf1(data) || data.len() < 8 {
+        bail!("Not a TCF1 file");
+    }
+    let header_size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let payload_start = 8usize.checked_add(header_size)
+        .filter(|&end| end <= data.len())
+        .context("Invalid TCF1 file: header size mismatch")?;
+    let header: Tcf1Header = serde_json::from_slice(&data[8..payload_start]).context("Failed to parse TCF1 header")?;
+    if !formats::TCF1.reads(header.version) {
+        bail!("Unsupported TCF1 version: {}", header.version);
+    }
+
+    // Mirrors the TypeScript decoder, which takes an 0xFF as a run only
+    // when two more bytes follow it
+    let payload = &data[payload_start..];
+    let mut bytes = Vec::with_capacity(header.original_size.min(1 << 30) as usize);
+    let mut i = 0;
+    while i < payload.len() {
+        if payload[i] == 0xFF && i + 2 < payload.len() {
+            bytes.extend(std::iter::repeat_n(payload[i + 1], payload[i + 2] as usize));
+            i += 3;
+        } else {
+            bytes.push(payload[i]);
+            i += 1;
+        }
+    }
+
+    if bytes.len() as u64 != header.original_size {
+        bail!("TCF1 payload decodes to {} bytes, header says {}", bytes.len(), header.original_size);
+    }
+    let actual = format!("{:x}", Sha256::digest(&bytes));
+    if actual != header.checksum {
+        bail!("TCF1 checksum mismatch: expected {}, got {}", header.checksum, actual);
+    }
+    let text = String::from_utf8(bytes).context("Invalid UTF-8 in decoded TCF1 data")?;
+    Ok((header, text))
+}
+
+/// What `migrate` did with a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationOutcome {
+    Migrated { legacy_size: u64, migrated_size: u64 },
+    /// Already in the current format; nothing was written
+    AlreadyCanonical,
+}
+
+/// Convert the TCF1 file `reader` yields into the current format
+///
+/// The new header records the legacy checksum in `migrated_from`.
+pub fn migrate<R: Read, W: Write>(mut reader: R, mut writer: W) -> Result<MigrationOutcome> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).context("Failed to read TCF file")?;
+    match migrate_bytes(&data)? {
+        Some(migrated) => {
+            writer.write_all(&migrated)?;
+            writer.flush()?;
+            Ok(MigrationOutcome::Migrated { legacy_size: data.len() as u64, migrated_size: migrated.len() as u64 })
+        }
+        None => Ok(MigrationOutcome::AlreadyCanonical),
+    }
+}
+
+/// The migrated file, or `None` if `data` is already current
+fn migrate_bytes(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    if data.starts_with(formats::TCF.magic.as_bytes()) {
+        return Ok(None);
+    }
+    let (legacy, text) = parse_tcf1(data)?;
+    let encoded = TcfCodec::encode_with_options(&text, &TcfEncodeOptions::default())?.data;
+
+    // Payload offsets are relative to the header's end, so it can be rewritten
+    let mut header = TcfCodec::parse_header(&encoded)?;
+    let payload_start = 8 + u32::from_le_bytes([encoded[4], encoded[5], encoded[6], encoded[7]]) as usize;
+    header.migrated_from = Some(MigrationRecord {
+        format: formats::TCF1.magic.to_string(),
+        version: legacy.version,
+        checksum: legacy.checksum,
+    });
+    let header_json = serde_json::to_vec(&header).context("Failed to serialize TCF header")?;
+    let mut migrated = Vec::with_capacity(encoded.len() + 128);
+    migrated.extend_from_slice(formats::TCF.magic.as_bytes());
+    migrated.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+    migrated.extend_from_slice(&header_json);
+    migrated.extend_from_slice(&encoded[payload_start..]);
+    Ok(Some(migrated))
+}
+
+/// Files `migrate_tree` looked at, by what happened to them
+#[derive(Debug, Clone, Default)]
+pub struct MigrationSummary {
+    pub migrated: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for MigrationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} migrated, {} already current, {} failed", self.migrated.len(), self.skipped.len(), self.failed.len())
+    }
+}
+
+/// Migrate every TCF1 file under `root` in place, replacing each atomically
+///
+/// Files that aren't TCF at all are left out of the summary; a file that
+/// fails is recorded and the rest carry on.
+pub fn migrate_tree(root: &Path) -> Result<MigrationSummary> {
+    let mut summary = MigrationSummary::default();
+    let mut pending = vec![root.to_path_buf()];
+    let mut files = Vec::new();
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read directory {}", dir.display()))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            } else if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+
+    for path in files {
+        let result = fs::read(&path).map_err(anyhow::Error::from).and_then(|data| {
+            if !is_tcf1(&data) && !data.starts_with(formats::TCF.magic.as_bytes()) {
+                return Ok(None);
+            }
+            Ok(Some(migrate_bytes(&data)?))
+        });
+        match result {
+            Ok(None) => {}
+            Ok(Some(None)) => summary.skipped.push(path),
+            Ok(Some(Some(migrated))) => match write_atomic(&path, &migrated) {
+                Ok(()) => summary.migrated.push(path),
+                Err(error) => summary.failed.push((path, format!("{:#}", error))),
+            },
+            Err(error) => summary.failed.push((path, format!("{:#}", error))),
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A file as the TypeScript codec writes it
+    fn tcf1_fixture(text: &str) -> Vec<u8> {
+        tcf1_with_runs(text, 255)
+    }
+
+    /// A TCF1 file whose runs are at most `longest` bytes, each written with
+    /// its length truncated to a byte
+    fn tcf1_with_runs(text: &str, longest: usize) -> Vec<u8> {
+        let data = text.as_bytes();
+        let mut payload = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let run = data[i..].iter().take(longest).take_while(|&&byte| byte == data[i]).count();
+            if run > 3 {
+                payload.extend_from_slice(&[0xFF, data[i], run as u8]);
+            } else {
+                payload.extend_from_slice(&data[i..i + run]);
+            }
+            i += run;
+        }
+        let header = serde_json::json!({
+            "magic": "TCF1",
+            "version": 1,
+            "flags": 0,
+            "originalSize": data.len(),
+            "compressedSize": payload.len(),
+            "checksum": format!("{:x}", Sha256::digest(data)),
+        });
+        let header = serde_json::to_vec(&header).unwrap();
+        let mut file = b"TCF1".to_vec();
+        file.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        file.extend_from_slice(&header);
+        file.extend_from_slice(&payload);
+        file
+    }
+
+    #[test]
+    fn test_long_runs_and_multibyte_text() {
+        // Runs longer than a length byte are split into several escapes
+        let text = format!("café 北京 {} é北\n{}", "=".repeat(600), "北".repeat(90));
+        assert_eq!(decode_tcf1(&tcf1_fixture(&text)).unwrap(), text);
+        let mut migrated = Vec::new();
+        migrate(&tcf1_fixture(&text)[..], &mut migrated).unwrap();
+        assert_eq!(TcfCodec::decode(&migrated).unwrap(), text);
+
+        // Earlier TypeScript encoders wrote a 600-byte run as 600 % 256;
+        // such files fail their size check rather than decode wrongly
+        let error = decode_tcf1(&tcf1_with_runs(&text, usize::MAX)).unwrap_err().to_string();
+        assert!(error.contains("header says"), "{}", error);
+    }
+
+    #[test]
+    fn test_migrate_tcf1_file() {
+        let text = "Legacy text with a long run: ==========, and some unicode: café.\n".repeat(20);
+        let legacy = tcf1_fixture(&text);
+        assert_eq!(decode_tcf1(&legacy).unwrap(), text);
+        assert!(TcfCodec::decode(&legacy).unwrap_err().to_string().contains("tcf-cli migrate"));
+
+        let mut migrated = Vec::new();
+        let outcome = migrate(&legacy[..], &mut migrated).unwrap();
+        assert_eq!(outcome, MigrationOutcome::Migrated { legacy_size: legacy.len() as u64, migrated_size: migrated.len() as u64 });
+        assert_eq!(TcfCodec::decode(&migrated).unwrap(), text);
+        let record = TcfCodec::parse_header(&migrated).unwrap().migrated_from.unwrap();
+        assert_eq!((record.format.as_str(), record.version), ("TCF1", 1));
+        assert_eq!(record.checksum, format!("{:x}", Sha256::digest(text.as_bytes())));
+
+        let mut untouched = Vec::new();
+        assert_eq!(migrate(&migrated[..], &mut untouched).unwrap(), MigrationOutcome::AlreadyCanonical);
+        assert!(untouched.is_empty());
+
+        let mut corrupt = legacy.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(migrate(&corrupt[..], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_migrate_tree_in_place() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("a.tcf"), tcf1_fixture("first file")).unwrap();
+        fs::write(dir.path().join("nested/b.tcf"), tcf1_fixture("second file")).unwrap();
+        fs::write(dir.path().join("current.tcf"), TcfCodec::encode("already current").unwrap()).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a TCF file").unwrap();
+        fs::write(dir.path().join("broken.tcf"), b"TCF1\xFF\xFF\xFF\xFF").unwrap();
+
+        let summary = migrate_tree(dir.path()).unwrap();
+        assert_eq!(summary.migrated, [dir.path().join("a.tcf"), dir.path().join("nested/b.tcf")]);
+        assert_eq!(summary.skipped, [dir.path().join("current.tcf")]);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.to_string(), "2 migrated, 1 already current, 1 failed");
+        assert_eq!(TcfCodec::decode(&fs::read(dir.path().join("nested/b.tcf")).unwrap()).unwrap(), "second file");
+
+        // A second run finds nothing left to do
+        let again = migrate_tree(dir.path()).unwrap();
+        assert_eq!((again.migrated.len(), again.skipped.len()), (0, 3));
+    }
+}
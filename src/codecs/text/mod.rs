@@ -1,9 +1,42 @@
 pub mod tcf_codec;
 pub mod arithmetic_coder;
+pub mod context_model;
 pub mod simple_coder;
 pub mod simple_tcf;
+pub mod tokenizer;
+pub mod sniff;
+pub mod chunking;
+pub mod front_coding;
+pub mod newlines;
+pub mod warnings;
+pub mod errors;
+pub mod seek_index;
+pub mod tcf_stream;
+pub mod archive;
+pub mod migrate;
+pub mod padding;
+pub mod model_stats;
+pub mod vectors;
+pub mod static_dictionary;
+pub mod source_encoding;
 
 pub use tcf_codec::*;
 pub use arithmetic_coder::*;
+pub use context_model::*;
 pub use simple_coder::*;
-pub use simple_tcf::*;
\ No newline at end of file
+pub use simple_tcf::*;
+pub use tokenizer::*;
+pub use sniff::*;
+pub use chunking::*;
+pub use front_coding::*;
+pub use warnings::*;
+pub use errors::*;
+pub use seek_index::*;
+pub use tcf_stream::*;
+pub use archive::*;
+pub use migrate::*;
+pub use padding::*;
+pub use model_stats::*;
+pub use vectors::*;
+pub use static_dictionary::*;
+pub use source_encoding::*;
\ No newline at end of file
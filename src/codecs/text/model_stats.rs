@@ -0,0 +1,203 @@
+//! Context statistics of a TCF file's text
+//!
+//! TCF's arithmetic coder stores order-0 models, so there are no learned
+//! contexts to read back. Instead the decoded text is replayed through a
+//! PPM-style context model up to `max_order` bytes, counting what each
+//! context saw and how often a symbol was new to it (an escape).
+use super::seek_index::TcfReader;
+use super::tcf_codec::TcfCodec;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Highest order the replay supports
+pub const MAX_MODEL_ORDER: usize = 8;
+/// How many contexts `ModelStats::top_contexts` keeps
+const TOP_CONTEXTS: usize = 20;
+/// Contexts seen fewer times than this aren't ranked as predictive
+const MIN_OCCURRENCES: u64 = 4;
+
+/// What the contexts of one order saw
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderStats {
+    pub order: usize,
+    /// Distinct contexts of this length
+    pub contexts: usize,
+    /// Symbols coded in a context of this length
+    pub symbols: u64,
+    /// Mean number of distinct symbols following a context
+    pub average_branching: f64,
+    /// Symbols that were new to their context
+    pub escapes: u64,
+    pub escape_rate: f64,
+}
+
+/// A context whose next symbol is nearly certain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PredictiveContext {
+    pub order: usize,
+    /// The context bytes, with anything but printable ASCII escaped
+    pub context: String,
+    pub occurrences: u64,
+    /// The most frequent next symbol, escaped like `context`
+    pub prediction: String,
+    /// Share of occurrences followed by `prediction`
+    pub probability: f64,
+}
+
+/// Context statistics replayed from a text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelStats {
+    pub max_order: usize,
+    pub text_size: u64,
+    pub orders: Vec<OrderStats>,
+    pub top_contexts: Vec<PredictiveContext>,
+}
+
+impl ModelStats {
+    /// Replay `text` through contexts of orders `0..=max_order`
+    pub fn replay(text: &[u8], max_order: usize) -> Result<Self> {
+        if max_order > MAX_MODEL_ORDER {
+            bail!("Model order {} is above the maximum of {}", max_order, MAX_MODEL_ORDER);
+        }
+        let mut orders = Vec::new();
+        let mut top_contexts = Vec::new();
+        for order in 0..=max_order {
+            let mut successors: HashMap<&[u8], HashMap<u8, u64>> = HashMap::new();
+            let mut escapes = 0;
+            for position in order..text.len() {
+                let counts = successors.entry(&text[position - order..position]).or_default();
+                let count = counts.entry(text[position]).or_default();
+                if *count == 0 {
+                    escapes += 1;
+                }
+                *count += 1;
+            }
+            let symbols = text.len().saturating_sub(order) as u64;
+            let branches: usize = successors.values().map(HashMap::len).sum();
+            orders.push(OrderStats {
+                order,
+                contexts: successors.len(),
+                symbols,
+                average_branching: if successors.is_empty() { 0.0 } else { branches as f64 / successors.len() as f64 },
+                escapes,
+                escape_rate: if symbols == 0 { 0.0 } else { escapes as f64 / symbols as f64 },
+            });
+            for (context, counts) in successors.iter().filter(|_| order > 0) {
+                let occurrences: u64 = counts.values().sum();
+                if occurrences < MIN_OCCURRENCES {
+                    continue;
+                }
+                let (&prediction, &hits) = counts.iter().max_by_key(|&(&symbol, &hits)| (hits, std::cmp::Reverse(symbol))).unwrap();
+                top_contexts.push(PredictiveContext {
+                    order,
+                    context: escape(context),
+                    occurrences,
+                    prediction: escape(&[prediction]),
+                    probability: hits as f64 / occurrences as f64,
+                });
+            }
+        }
+        // Most certain first, then most used, then by name so ties are stable
+        top_contexts.sort_by(|a, b| b.probability.total_cmp(&a.probability)
+            .then(b.occurrences.cmp(&a.occurrences))
+            .then(a.order.cmp(&b.order))
+            .then_with(|| a.context.cmp(&b.context)));
+        top_contexts.truncate(TOP_CONTEXTS);
+        Ok(Self { max_order, text_size: text.len() as u64, orders, top_contexts })
+    }
+}
+
+fn escape(bytes: &[u8]) -> String {
+    bytes.iter().flat_map(|&byte| std::ascii::escape_default(byte)).map(char::from).collect()
+}
+
+impl fmt::Display for ModelStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Context model replay: {} bytes, orders 0..={}", self.text_size, self.max_order)?;
+        writeln!(f, "{:>5} {:>10} {:>12} {:>10} {:>10} {:>8}", "Order", "Contexts", "Symbols", "Branching", "Escapes", "Escape%")?;
+        for stats in &self.orders {
+            writeln!(f, "{:>5} {:>10} {:>12} {:>10.2} {:>10} {:>7.2}%", stats.order, stats.contexts, stats.symbols,
+                stats.average_branching, stats.escapes, stats.escape_rate * 100.0)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Top {} predictive contexts:", self.top_contexts.len())?;
+        writeln!(f, "{:>5} {:<20} {:>8} {:<8} {:>7}", "Order", "Context", "Seen", "Next", "P")?;
+        for context in &self.top_contexts {
+            writeln!(f, "{:>5} {:<20} {:>8} {:<8} {:>6.1}%", context.order, format!("\"{}\"", context.context),
+                context.occurrences, format!("\"{}\"", context.prediction), context.probability * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl TcfCodec {
+    /// Context statistics of the text in `tcf_data`, replayed up to `max_order`
+    pub fn model_stats(tcf_data: &[u8], max_order: usize) -> Result<ModelStats> {
+        ModelStats::replay(Self::decode(tcf_data)?.as_bytes(), max_order)
+    }
+}
+
+impl TcfReader<'_> {
+    /// Context statistics of the whole text, replayed up to `max_order`
+    pub fn model_stats(&mut self, max_order: usize) -> Result<ModelStats> {
+        let text = self.read_range(0..self.original_size())?;
+        ModelStats::replay(&text, max_order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::{ChunkStrategy, TcfEncodeOptions};
+
+    const ENGLISH: &str = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+        it was the age of foolishness, it was the epoch of belief, it was the epoch of incredulity, \
+        it was the season of Light, it was the season of Darkness, it was the spring of hope, \
+        it was the winter of despair, we had everything before us, we had nothing before us.\n";
+
+    #[test]
+    fn test_replay_reports_every_order() {
+        let encoded = TcfCodec::encode(ENGLISH).unwrap();
+        let stats = TcfCodec::model_stats(&encoded, 4).unwrap();
+        assert_eq!(stats.orders.iter().map(|o| o.order).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(stats.orders[0].contexts, 1);
+        assert!(stats.orders.iter().all(|o| o.contexts > 0 && o.escapes > 0));
+        // Longer contexts are more numerous and each more certain
+        assert!(stats.orders.windows(2).all(|pair| pair[0].contexts < pair[1].contexts));
+        assert!(stats.orders[4].average_branching < stats.orders[1].average_branching);
+        assert_eq!(stats.top_contexts.len(), TOP_CONTEXTS);
+        assert!(stats.top_contexts.iter().all(|c| c.order > 0 && c.occurrences >= MIN_OCCURRENCES));
+        assert_eq!(stats.top_contexts[0], PredictiveContext {
+            order: 1, context: ",".to_string(), occurrences: 11, prediction: " ".to_string(), probability: 1.0,
+        });
+        assert!(stats.top_contexts.iter().any(|c| c.context == " wa" && c.prediction == "s"));
+        assert!(TcfCodec::model_stats(&encoded, MAX_MODEL_ORDER + 1).is_err());
+
+        // The range reader replays the same text
+        let options = TcfEncodeOptions { chunking: Some(ChunkStrategy::OnDelimiter(b',', 64)), ..Default::default() };
+        let chunked = TcfCodec::encode_with_options(ENGLISH, &options).unwrap().data;
+        assert_eq!(TcfReader::new(&chunked).unwrap().model_stats(4).unwrap(), stats);
+    }
+
+    #[test]
+    fn test_escapes_fall_with_more_data() {
+        let short = ModelStats::replay(&ENGLISH.as_bytes()[..80], 3).unwrap();
+        let long = ModelStats::replay(ENGLISH.repeat(8).as_bytes(), 3).unwrap();
+        for (short, long) in short.orders.iter().zip(&long.orders) {
+            assert!(long.escape_rate < short.escape_rate, "order {}: {} vs {}", long.order, long.escape_rate, short.escape_rate);
+        }
+    }
+
+    #[test]
+    fn test_json_schema_is_stable() {
+        let stats = ModelStats::replay(ENGLISH.as_bytes(), 2).unwrap();
+        let json = serde_json::to_value(&stats).unwrap();
+        let keys = |value: &serde_json::Value| value.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&json), ["max_order", "orders", "text_size", "top_contexts"]);
+        assert_eq!(keys(&json["orders"][0]), ["average_branching", "contexts", "escape_rate", "escapes", "order", "symbols"]);
+        assert_eq!(keys(&json["top_contexts"][0]), ["context", "occurrences", "order", "prediction", "probability"]);
+        assert_eq!(serde_json::from_value::<ModelStats>(json).unwrap(), stats);
+    }
+}
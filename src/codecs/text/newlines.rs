@@ -0,0 +1,141 @@
+use super::front_coding::{read_varint, write_varint};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// How `TcfCodec` treats line endings before coding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NewlinePolicy {
+    /// Code the text as it is
+    #[default]
+    Preserve,
+    /// Code CRLFs as LFs, recording which LFs had a CR so decoding puts
+    /// them back
+    NormalizeLfRecordPositions,
+}
+
+impl NewlinePolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NewlinePolicy::Preserve => "preserve",
+            NewlinePolicy::NormalizeLfRecordPositions => "normalize",
+        }
+    }
+}
+
+impl std::fmt::Display for NewlinePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for NewlinePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "preserve" => Ok(NewlinePolicy::Preserve),
+            "normalize" => Ok(NewlinePolicy::NormalizeLfRecordPositions),
+            _ => bail!("Unknown newline policy: {}", s),
+        }
+    }
+}
+
+/// Where a normalized file's CRs go back, from `TcfHeader::newlines`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NewlineRecord {
+    /// Size of the text as coded, with CRLFs turned into LFs
+    pub coded_size: u64,
+    /// Size of the record, stored after the payload
+    pub record_size: u64,
+}
+
+/// Turn every CRLF in `text` into LF, returning the text and a record of
+/// which LFs had a CR
+///
+/// The record is varints counting runs of LFs, alternately without and
+/// with a CR, starting without. Files with one line ending throughout
+/// record one or two runs.
+pub fn normalize_crlf(text: &str) -> (String, Vec<u8>) {
+    let mut normalized = String::with_capacity(text.len());
+    let mut record = Vec::new();
+    let (mut converting, mut run) = (false, 0u64);
+    let mut rest = text;
+    while let Some(end) = rest.find('\n') {
+        let line = &rest[..end];
+        let crlf = line.ends_with('\r');
+        normalized.push_str(line.strip_suffix('\r').unwrap_or(line));
+        normalized.push('\n');
+        if crlf != converting {
+            write_varint(&mut record, run);
+            (converting, run) = (crlf, 0);
+        }
+        run += 1;
+        rest = &rest[end + 1..];
+    }
+    normalized.push_str(rest);
+    if run > 0 {
+        write_varint(&mut record, run);
+    }
+    (normalized, record)
+}
+
+/// Put back the CRs `normalize_crlf` removed, failing if the result would
+/// not be `original_size` bytes
+pub fn restore_crlf(text: &[u8], record: &[u8], original_size: u64) -> Result<Vec<u8>> {
+    let size = usize::try_from(original_size)?;
+    if size < text.len() {
+        bail!("Newline record shrinks {} bytes of text to {}", text.len(), size);
+    }
+    let mut restored = Vec::with_capacity(size);
+    let (mut converting, mut run) = (true, 0u64);
+    let mut position = 0;
+    for line in text.split_inclusive(|&byte| byte == b'\n') {
+        if line.ends_with(b"\n") {
+            while run == 0 {
+                if position == record.len() {
+                    bail!("Newline record ends before the text's last line");
+                }
+                run = read_varint(record, &mut position)?;
+                converting = !converting;
+            }
+            run -= 1;
+            if converting {
+                if restored.len() + line.len() >= size {
+                    bail!("Newline record adds more than {} bytes", size - text.len());
+                }
+                restored.extend_from_slice(&line[..line.len() - 1]);
+                restored.extend_from_slice(b"\r\n");
+                continue;
+            }
+        }
+        restored.extend_from_slice(line);
+    }
+    if run > 0 || position < record.len() || restored.len() != size {
+        bail!("Newline record doesn't match the {} byte text", size);
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crlf_roundtrip() {
+        for text in ["", "a", "\n", "\r\n", "a\r\nb\nc\r\n\r\nd", "\r\r\n\r", "x\n\n\r\n\r\n\n", "é\r\nê"] {
+            let (normalized, record) = normalize_crlf(text);
+            assert!(!normalized.contains("\r\n") || text.contains("\r\r\n"), "{:?}", text);
+            assert_eq!(restore_crlf(normalized.as_bytes(), &record, text.len() as u64).unwrap(), text.as_bytes(), "{:?}", text);
+        }
+
+        let log = "GET /index.html 200\r\n".repeat(1000);
+        let (normalized, record) = normalize_crlf(&log);
+        assert_eq!(normalized.len(), log.len() - 1000);
+        assert_eq!(record, [0, 0xE8, 0x07]);
+
+        let (normalized, record) = normalize_crlf("a\r\nb\nc");
+        assert!(restore_crlf(normalized.as_bytes(), &record, 8).is_err());
+        assert!(restore_crlf(normalized.as_bytes(), &[0, 5], 7).is_err());
+        assert!(restore_crlf(normalized.as_bytes(), &[], 7).is_err());
+    }
+}
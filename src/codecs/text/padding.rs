@@ -0,0 +1,99 @@
+//! Padding TCF files to fixed sizes, so their length says less about the text
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Sizes `TcfCodec` pads whole files up to
+///
+/// Padding is zero bytes after everything else in the file, so encoding
+/// stays deterministic; its length is recorded in `TcfHeader::padding`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    #[default]
+    None,
+    /// The next power of two
+    PowerOfTwo,
+    /// The smallest bucket that fits, or past the largest, the next
+    /// multiple of it; sizes are ascending and nonzero
+    Bucket(Vec<u64>),
+}
+
+impl PaddingPolicy {
+    /// Buckets from a list of sizes, sorted and deduplicated
+    pub fn buckets(mut sizes: Vec<u64>) -> Result<Self> {
+        if sizes.is_empty() || sizes.contains(&0) {
+            bail!("Padding buckets must be nonempty and nonzero");
+        }
+        sizes.sort_unstable();
+        sizes.dedup();
+        Ok(PaddingPolicy::Bucket(sizes))
+    }
+
+    /// Size a file of `size` bytes is padded to
+    pub fn padded_size(&self, size: u64) -> Result<u64> {
+        let padded = match self {
+            PaddingPolicy::None => Some(size),
+            PaddingPolicy::PowerOfTwo => size.checked_next_power_of_two(),
+            PaddingPolicy::Bucket(sizes) => match sizes.iter().find(|&&bucket| bucket >= size) {
+                Some(&bucket) => Some(bucket),
+                None => {
+                    let largest = *sizes.last().context("No padding buckets")?;
+                    size.div_ceil(largest).checked_mul(largest)
+                }
+            },
+        };
+        padded.with_context(|| format!("Can't pad {} bytes under {}", size, self))
+    }
+}
+
+impl fmt::Display for PaddingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaddingPolicy::None => f.write_str("none"),
+            PaddingPolicy::PowerOfTwo => f.write_str("pow2"),
+            PaddingPolicy::Bucket(sizes) => {
+                let sizes: Vec<String> = sizes.iter().map(u64::to_string).collect();
+                f.write_str(&sizes.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for PaddingPolicy {
+    type Err = anyhow::Error;
+
+    /// `none`, `pow2`, or comma-separated bucket sizes in bytes
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(PaddingPolicy::None),
+            "pow2" => Ok(PaddingPolicy::PowerOfTwo),
+            _ => {
+                let sizes = s.split(',')
+                    .map(|size| size.trim().parse::<u64>().with_context(|| format!("Invalid padding policy: {}", s)))
+                    .collect::<Result<Vec<_>>>()?;
+                Self::buckets(sizes)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_sizes() {
+        let buckets: PaddingPolicy = "4096, 1024,1024".parse().unwrap();
+        assert_eq!(buckets, PaddingPolicy::Bucket(vec![1024, 4096]));
+        assert_eq!(buckets.to_string(), "1024,4096");
+        let sizes: Vec<u64> = [1, 1024, 1025, 4097, 9000].iter().map(|&size| buckets.padded_size(size).unwrap()).collect();
+        assert_eq!(sizes, [1024, 1024, 4096, 8192, 12288]);
+
+        let pow2: PaddingPolicy = "pow2".parse().unwrap();
+        assert_eq!((pow2.padded_size(1000).unwrap(), pow2.padded_size(1024).unwrap()), (1024, 1024));
+        assert!(pow2.padded_size(u64::MAX).is_err());
+        assert_eq!(PaddingPolicy::None.padded_size(777).unwrap(), 777);
+        assert!("0,512".parse::<PaddingPolicy>().is_err());
+        assert!("sometimes".parse::<PaddingPolicy>().is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::codecs::atomic::write_atomic;
+use crate::codecs::formats;
+use super::front_coding::{read_varint, write_varint};
+use super::tcf_codec::{ChunkDecoder, ChunkType, TcfChunk, TcfCodec, TcfFlags, TcfHeader};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Seek table of a chunked TCF file, kept in a `.tcfx` sidecar
+///
+/// Lets a reader find any chunk without parsing the archive's own chunk
+/// table, so the archive can stay append-only and cacheable. The sidecar
+/// records the archive's length and a SHA-256 of its header, and is
+/// rejected once either changes.
+///
+/// Layout: `TCFX`, a version byte, the archive length and payload start
+/// as varints, the header digest, a length-prefixed JSON header with the
+/// chunk table left out, the method names, then one compact row per chunk.
+#[derive(Debug, Clone)]
+pub struct TcfIndex {
+    /// Length of the indexed archive
+    pub file_size: u64,
+    /// SHA-256 of the archive's magic, header length and header
+    pub header_sha256: [u8; 32],
+    /// Where the archive's chunk data starts
+    payload_start: u64,
+    header: TcfHeader,
+}
+
+impl TcfIndex {
+    const MAGIC: &'static [u8] = formats::TCF_INDEX.magic.as_bytes();
+    const VERSION: u8 = formats::TCF_INDEX.version as u8;
+
+    /// Index the TCF file at `tcf_path`, reading only its header
+    pub fn build<P: AsRef<Path>>(tcf_path: P) -> Result<Self> {
+        let path = tcf_path.as_ref();
+        let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let file_size = file.metadata()?.len();
+        let mut prefix = vec![0u8; 8];
+        file.read_exact(&mut prefix).context("Invalid TCF file: too small")?;
+        let header_size = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as u64;
+        file.take(header_size).read_to_end(&mut prefix)?;
+        let header = TcfCodec::parse_header(&prefix)?;
+        Self::with_header(header, &prefix, file_size)
+    }
+
+    /// Index a TCF file already in memory
+    pub fn from_tcf(tcf_data: &[u8]) -> Result<Self> {
+        let header = TcfCodec::parse_header(tcf_data)?;
+        let payload_start = 8 + u32::from_le_bytes([tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]]) as usize;
+        Self::with_header(header, &tcf_data[..payload_start], tcf_data.len() as u64)
+    }
+
+    /// Where the sidecar of `tcf_path` lives: `big.tcf` gets `big.tcfx`
+    pub fn sidecar_path<P: AsRef<Path>>(tcf_path: P) -> PathBuf {
+        tcf_path.as_ref().with_extension("tcfx")
+    }
+
+    /// Metadata chunks aren't needed to seek the text, so they're left out
+    fn with_header(mut header: TcfHeader, prefix: &[u8], file_size: u64) -> Result<Self> {
+        if header.flags & TcfFlags::CHUNKED == 0 {
+            bail!("TCF file isn't chunked, so there is nothing to index");
+        }
+        header.chunks.retain(|chunk| chunk.chunk_type != ChunkType::Metadata);
+        Ok(Self {
+            file_size,
+            header_sha256: Sha256::digest(prefix).into(),
+            payload_start: prefix.len() as u64,
+            header,
+        })
+    }
+
+    pub fn chunks(&self) -> &[TcfChunk] {
+        &self.header.chunks
+    }
+
+    /// Fail unless `tcf_data` is the archive this index was built from
+    pub fn check(&self, tcf_data: &[u8]) -> Result<()> {
+        if tcf_data.len() as u64 != self.file_size {
+            bail!("Stale TCF index: built for a {} byte file, this one is {} bytes", self.file_size, tcf_data.len());
+        }
+        let prefix = &tcf_data[..self.payload_start as usize];
+        if Sha256::digest(prefix).as_slice() != self.header_sha256 {
+            bail!("Stale TCF index: the file's header has changed since it was indexed");
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut methods: Vec<&str> = Vec::new();
+        for chunk in self.chunks() {
+            if !methods.contains(&chunk.compression_method.as_str()) {
+                methods.push(&chunk.compression_method);
+            }
+        }
+        let template = serde_json::to_vec(&TcfHeader { chunks: Vec::new(), ..self.header.clone() })?;
+
+        let mut out = Vec::with_capacity(64 + template.len() + self.chunks().len() * 16);
+        out.extend_from_slice(Self::MAGIC);
+        out.push(Self::VERSION);
+        write_varint(&mut out, self.file_size);
+        write_varint(&mut out, self.payload_start);
+        out.extend_from_slice(&self.header_sha256);
+        write_varint(&mut out, template.len() as u64);
+        out.extend_from_slice(&template);
+        write_varint(&mut out, methods.len() as u64);
+        for method in &methods {
+            write_varint(&mut out, method.len() as u64);
+            out.extend_from_slice(method.as_bytes());
+        }
+
+        // Chunks tile the text in order, so text offsets are left implicit
+        write_varint(&mut out, self.chunks().len() as u64);
+        for chunk in self.chunks() {
+            write_varint(&mut out, chunk.original_size);
+            write_varint(&mut out, chunk.data_offset);
+            write_varint(&mut out, chunk.model_size as u64);
+            write_varint(&mut out, chunk.compressed_size);
+            let method = methods.iter().position(|&method| method == chunk.compression_method).unwrap_or_default();
+            write_varint(&mut out, method as u64);
+            write_varint(&mut out, chunk.first_record.map_or(0, |offset| offset + 1));
+            write_varint(&mut out, match chunk.chunk_type {
+                ChunkType::Data => 0,
+                ChunkType::Reference(target) => target + 1,
+                ChunkType::Metadata | ChunkType::Dictionary => bail!("TCF index can't hold metadata or dictionary chunks"),
+            });
+            out.extend_from_slice(&chunk.crc32.to_le_bytes());
+        }
+        Ok(out)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < 5 || &data[..4] != Self::MAGIC {
+            bail!("Invalid TCF index magic number");
+        }
+        if !formats::TCF_INDEX.reads(data[4] as u16) {
+            bail!("Unsupported TCF index version: {}", data[4]);
+        }
+        let mut position = 5;
+        let varint = |position: &mut usize| read_varint(data, position).context("Invalid TCF index: truncated");
+        let bytes = |position: &mut usize, len: u64| -> Result<&[u8]> {
+            let end = usize::try_from(len).ok()
+                .and_then(|len| position.checked_add(len))
+                .filter(|&end| end <= data.len())
+                .context("Invalid TCF index: truncated")?;
+            let slice = &data[*position..end];
+            *position = end;
+            Ok(slice)
+        };
+
+        let file_size = varint(&mut position)?;
+        let payload_start = varint(&mut position)?;
+        if payload_start > file_size {
+            bail!("Invalid TCF index: payload starts past the end of the file");
+        }
+        let header_sha256 = bytes(&mut position, 32)?.try_into()?;
+        let template_len = varint(&mut position)?;
+        let mut header: TcfHeader = serde_json::from_slice(bytes(&mut position, template_len)?)
+            .context("Failed to parse TCF index header")?;
+        let methods = (0..varint(&mut position)?)
+            .map(|_| {
+                let len = varint(&mut position)?;
+                Ok(std::str::from_utf8(bytes(&mut position, len)?)?.to_string())
+            })
+            .collect::<Result<Vec<String>>>()?;
+
+        let count = varint(&mut position)?;
+        let mut text_offset = 0u64;
+        for _ in 0..count {
+            let original_size = varint(&mut position)?;
+            let data_offset = varint(&mut position)?;
+            let model_size = u32::try_from(varint(&mut position)?)?;
+            let compressed_size = varint(&mut position)?;
+            let compression_method = methods.get(varint(&mut position)? as usize)
+                .context("Invalid TCF index: unknown method")?
+                .clone();
+            let first_record = varint(&mut position)?.checked_sub(1);
+            let chunk_type = match varint(&mut position)? {
+                0 => ChunkType::Data,
+                target => ChunkType::Reference(target - 1),
+            };
+            let crc32 = u32::from_le_bytes(bytes(&mut position, 4)?.try_into()?);
+            header.chunks.push(TcfChunk {
+                text_offset,
+                original_size,
+                data_offset,
+                model_size,
+                compressed_size,
+                compression_method,
+                first_record,
+                crc32,
+                chunk_type,
+            });
+            text_offset = text_offset.checked_add(original_size).context("Invalid TCF index: chunk sizes overflow")?;
+        }
+        if text_offset != header.original_size {
+            bail!("Invalid TCF index: chunks cover {} of {} bytes", text_offset, header.original_size);
+        }
+        if position != data.len() {
+            bail!("Invalid TCF index: trailing data");
+        }
+        Ok(Self { file_size, header_sha256, payload_start, header })
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        write_atomic(path, &self.to_bytes()?)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        Self::from_bytes(&std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?)
+    }
+}
+
+/// Range decoder over a chunked TCF file
+///
+/// Decoded chunks are kept, so neighbouring ranges only decode each chunk
+/// once.
+pub struct TcfReader<'a> {
+    chunks: ChunkDecoder<'a>,
+    original_size: u64,
+}
+
+impl<'a> TcfReader<'a> {
+    /// Read the chunk table from the file's own header
+    pub fn new(tcf_data: &'a [u8]) -> Result<Self> {
+        let header = TcfCodec::parse_header(tcf_data)?;
+        if header.flags & TcfFlags::CHUNKED == 0 {
+            bail!("TCF file isn't chunked; decode it whole");
+        }
+        let original_size = header.original_size;
+        Ok(Self { chunks: TcfCodec::chunk_decoder(tcf_data, header)?, original_size })
+    }
+
+    /// Take the chunk table from `index` instead, after checking it still
+    /// matches `tcf_data`
+    pub fn with_index(tcf_data: &'a [u8], index: &TcfIndex) -> Result<Self> {
+        index.check(tcf_data)?;
+        let original_size = index.header.original_size;
+        Ok(Self { chunks: TcfCodec::chunk_decoder(tcf_data, index.header.clone())?, original_size })
+    }
+
+    /// Length of the original text
+    pub fn original_size(&self) -> u64 {
+        self.original_size
+    }
+
+    /// Bytes `range` of the original text, which may split a character
+    pub fn read_range(&mut self, range: Range<u64>) -> Result<Vec<u8>> {
+        if range.start > range.end || range.end > self.original_size {
+            bail!("TCF range {}..{} is outside the {} byte text", range.start, range.end, self.original_size);
+        }
+        self.chunks.read(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::{ChunkStrategy, TcfEncodeOptions};
+    use tempfile::TempDir;
+
+    fn chunked_file(text: &str) -> Vec<u8> {
+        let options = TcfEncodeOptions {
+            chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 1024)),
+            ..Default::default()
+        };
+        TcfCodec::encode_with_options(text, &options).unwrap().data
+    }
+
+    #[test]
+    fn test_sidecar_range_decode() {
+        let text: String = (0..2000).map(|i| format!("line {} of the archive, value {}\n", i, i * 37 % 101)).collect();
+        let encoded = chunked_file(&text);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("big.tcf");
+        std::fs::write(&path, &encoded).unwrap();
+
+        let index = TcfIndex::build(&path).unwrap();
+        assert_eq!(index.to_bytes().unwrap(), TcfIndex::from_tcf(&encoded).unwrap().to_bytes().unwrap());
+        assert!(index.chunks().len() > 10);
+        let sidecar = TcfIndex::sidecar_path(&path);
+        assert_eq!(sidecar, dir.path().join("big.tcfx"));
+        index.write(&sidecar).unwrap();
+        let index = TcfIndex::read(&sidecar).unwrap();
+        assert_eq!(index.chunks(), TcfCodec::parse_header(&encoded).unwrap().chunks);
+
+        // Much smaller than the JSON chunk table it replaces
+        let header_size = u32::from_le_bytes(encoded[4..8].try_into().unwrap()) as usize;
+        assert!(index.to_bytes().unwrap().len() * 3 < header_size);
+
+        let mut reader = TcfReader::with_index(&encoded, &index).unwrap();
+        for range in [0..10, 5000..9000, 20_000..text.len() as u64] {
+            let expected = &text.as_bytes()[range.start as usize..range.end as usize];
+            assert_eq!(reader.read_range(range.clone()).unwrap(), expected);
+            assert_eq!(TcfReader::new(&encoded).unwrap().read_range(range).unwrap(), expected);
+        }
+        assert!(reader.read_range(0..text.len() as u64 + 1).is_err());
+
+        let bytes = index.to_bytes().unwrap();
+        assert!(TcfIndex::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(TcfIndex::from_tcf(&TcfCodec::encode(&text).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_stale_sidecar_is_rejected() {
+        let text = "alpha\n".repeat(500);
+        let encoded = chunked_file(&text);
+        let index = TcfIndex::from_tcf(&encoded).unwrap();
+        assert!(TcfReader::with_index(&encoded, &index).is_ok());
+
+        // Appended to
+        let mut appended = encoded.clone();
+        appended.extend_from_slice(b"more");
+        assert!(TcfReader::with_index(&appended, &index).is_err());
+
+        // Same length, different header
+        let checksum = TcfCodec::parse_header(&encoded).unwrap().checksum;
+        let at = encoded.windows(checksum.len()).position(|window| window == checksum.as_bytes()).unwrap();
+        let mut rewritten = encoded.clone();
+        rewritten[at] = if rewritten[at] == b'0' { b'1' } else { b'0' };
+        let error = TcfReader::with_index(&rewritten, &index).err().unwrap();
+        assert!(error.to_string().contains("Stale TCF index"), "{}", error);
+    }
+}
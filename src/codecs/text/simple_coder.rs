@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 use flate2::{Compression, write::GzEncoder, read::GzDecoder};
 use std::io::prelude::*;
@@ -6,14 +6,14 @@ use std::io::prelude::*;
 /// Simplified frequency model for demonstration
 #[derive(Serialize, Deserialize)]
 pub struct SimpleFrequencyModel {
-    frequencies: HashMap<u8, u32>,
+    frequencies: BTreeMap<u8, u32>,
     total: u32,
 }
 
 impl SimpleFrequencyModel {
     pub fn new() -> Self {
         Self {
-            frequencies: HashMap::new(),
+            frequencies: BTreeMap::new(),
             total: 0,
         }
     }
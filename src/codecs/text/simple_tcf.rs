@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use crate::codecs::formats;
 use crate::codecs::text::simple_coder::{SimpleArithmeticCoder, SimpleFrequencyModel};
 use anyhow::{Result, Context};
 
@@ -17,8 +18,8 @@ pub struct SimpleTcfHeader {
 pub struct SimpleTcfCodec;
 
 impl SimpleTcfCodec {
-    const MAGIC: &'static str = "TCF2";
-    const VERSION: u16 = 2;
+    const MAGIC: &'static str = formats::TCF.magic;
+    const VERSION: u16 = formats::TCF.version;
 
     pub fn encode(text: &str) -> Result<Vec<u8>> {
         let original_data = text.as_bytes();
@@ -196,4 +197,16 @@ impl std::fmt::Display for TextCompressionStats {
             self.savings_percent
         )
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_characters_roundtrip() {
+        for text in ["\0", "\x07", "\x7f", "\r", "line\0with\x07bell\x7f\rand\r\nmore\0"] {
+            let encoded = SimpleTcfCodec::encode(text).unwrap();
+            assert_eq!(SimpleTcfCodec::decode(&encoded).unwrap(), text, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::codecs::formats;
+use crate::codecs::precision::round_table_value;
+use serde::Serialize;
+use std::fmt;
+
+/// How much of the input the sniffer looks at
+pub const SNIFF_WINDOW: usize = 64 * 1024;
+
+/// Order-0 entropy, in bits per byte, above which coding can't gain anything
+pub(crate) const HIGH_ENTROPY_BITS: f64 = 7.5;
+
+/// Inputs shorter than this are too small for a meaningful entropy estimate
+const MIN_ENTROPY_SAMPLE: usize = 1024;
+
+/// Signatures of formats that are already compressed
+const SIGNATURES: &[(&str, usize, &[u8])] = &[
+    ("gzip", 0, &[0x1f, 0x8b]),
+    ("zip", 0, b"PK\x03\x04"),
+    ("zstd", 0, &[0x28, 0xb5, 0x2f, 0xfd]),
+    ("bzip2", 0, b"BZh"),
+    ("xz", 0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+    ("7z", 0, &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c]),
+    ("png", 0, b"\x89PNG\r\n\x1a\n"),
+    ("jpeg", 0, &[0xff, 0xd8, 0xff]),
+    ("gif", 0, b"GIF8"),
+    ("webp", 8, b"WEBP"),
+    ("mp4", 4, b"ftyp"),
+    ("tcf", 0, formats::TCF.magic.as_bytes()),
+    ("icf", 0, formats::ICF.magic.as_bytes()),
+    ("vcf", 0, formats::VCF.magic.as_bytes()),
+];
+
+/// What a quick look at the input suggests about compressing it as text
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressibilityHint {
+    /// Looks like text worth coding
+    Compressible,
+    /// Starts with the signature of a compressed format
+    AlreadyCompressed(&'static str),
+    /// Bytes are close to uniformly distributed
+    HighEntropy { bits_per_byte: f64 },
+    /// Not text: invalid UTF-8 or NUL bytes, but not obviously incompressible
+    Binary,
+}
+
+impl CompressibilityHint {
+    /// Whether coding is expected to make the input larger
+    pub fn is_incompressible(&self) -> bool {
+        matches!(self, CompressibilityHint::AlreadyCompressed(_) | CompressibilityHint::HighEntropy { .. })
+    }
+}
+
+impl fmt::Display for CompressibilityHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressibilityHint::Compressible => f.write_str("compressible text"),
+            CompressibilityHint::AlreadyCompressed(format) => write!(f, "already compressed ({})", format),
+            CompressibilityHint::HighEntropy { bits_per_byte } => {
+                write!(f, "high entropy ({:.2} bits/byte)", bits_per_byte)
+            }
+            CompressibilityHint::Binary => f.write_str("binary data"),
+        }
+    }
+}
+
+/// Classify `data` from its magic bytes and the entropy of its first `SNIFF_WINDOW` bytes
+pub fn estimate_compressibility(data: &[u8]) -> CompressibilityHint {
+    if let Some(&(format, _, _)) = SIGNATURES.iter()
+        .find(|(_, offset, magic)| data.get(*offset..offset + magic.len()) == Some(*magic))
+    {
+        return CompressibilityHint::AlreadyCompressed(format);
+    }
+
+    let sample = &data[..data.len().min(SNIFF_WINDOW)];
+    if sample.len() >= MIN_ENTROPY_SAMPLE {
+        let bits_per_byte = entropy(sample);
+        if bits_per_byte > HIGH_ENTROPY_BITS {
+            return CompressibilityHint::HighEntropy { bits_per_byte };
+        }
+    }
+
+    // A character cut off by the window isn't evidence of binary data
+    let invalid_utf8 = std::str::from_utf8(sample).is_err_and(|error| error.error_len().is_some());
+    if invalid_utf8 || sample.contains(&0) {
+        return CompressibilityHint::Binary;
+    }
+
+    CompressibilityHint::Compressible
+}
+
+/// Order-0 Shannon entropy in bits per byte
+fn entropy(sample: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in sample {
+        counts[byte as usize] += 1;
+    }
+
+    let total = sample.len() as f64;
+    counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            round_table_value(-p * p.log2())
+        })
+        .sum()
+}
@@ -0,0 +1,311 @@
+//! Text that wasn't UTF-8 before it was coded
+//!
+//! TCF models UTF-8, so UTF-16 and Latin-1 sources are converted on the way
+//! in and the header's `source_encoding` records what they were, byte order
+//! mark included, so decoding can give back the exact original bytes.
+use super::padding::PaddingPolicy;
+use super::tcf_codec::TcfCodec;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Encoding of the bytes handed to `TcfCodec::encode_with_source_encoding`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO 8859-1: each byte is the code point of the same value
+    Latin1,
+    /// Whichever of the others `detect` picks
+    Detect,
+}
+
+impl SourceEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceEncoding::Utf8 => "utf8",
+            SourceEncoding::Utf16Le => "utf16le",
+            SourceEncoding::Utf16Be => "utf16be",
+            SourceEncoding::Latin1 => "latin1",
+            SourceEncoding::Detect => "detect",
+        }
+    }
+
+    /// The encoding `bytes` are most likely in
+    ///
+    /// A byte order mark decides it if there is one. Otherwise text is
+    /// UTF-8 if it's valid, so UTF-8 with NULs in it still comes back
+    /// byte for byte; UTF-16 if it decodes as such and at least half its
+    /// code units are ASCII; and Latin-1 if it has no control bytes besides
+    /// whitespace. Anything else isn't taken for text.
+    pub fn detect(bytes: &[u8]) -> Result<SourceEncoding> {
+        if bytes.starts_with(UTF8_BOM) {
+            Ok(SourceEncoding::Utf8)
+        } else if bytes.starts_with(UTF16LE_BOM) {
+            Ok(SourceEncoding::Utf16Le)
+        } else if bytes.starts_with(UTF16BE_BOM) {
+            Ok(SourceEncoding::Utf16Be)
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Ok(SourceEncoding::Utf8)
+        } else if mostly_ascii_utf16(bytes, false) {
+            Ok(SourceEncoding::Utf16Le)
+        } else if mostly_ascii_utf16(bytes, true) {
+            Ok(SourceEncoding::Utf16Be)
+        } else if !bytes.iter().any(|&byte| matches!(byte, 0x00..=0x08 | 0x0B | 0x0E..=0x1F | 0x7F)) {
+            Ok(SourceEncoding::Latin1)
+        } else {
+            bail!("Can't tell the encoding: not UTF-8, UTF-16 or Latin-1 text")
+        }
+    }
+
+    /// `bytes` as text, and the record that turns it back into them
+    ///
+    /// UTF-8 bytes are already the text, so a byte order mark stays in it
+    /// as U+FEFF and there's nothing to record. Converted text has its
+    /// byte order mark dropped and noted in the record instead; Latin-1 has
+    /// none, so its bytes are all text.
+    pub fn decode(self, bytes: &[u8]) -> Result<(String, SourceRecord)> {
+        let encoding = match self {
+            SourceEncoding::Detect => SourceEncoding::detect(bytes)?,
+            encoding => encoding,
+        };
+        let bom_len = encoding.bom()
+            .filter(|bom| encoding != SourceEncoding::Utf8 && bytes.starts_with(bom))
+            .map_or(0, <[u8]>::len);
+        let body = &bytes[bom_len..];
+        let text = match encoding {
+            SourceEncoding::Utf8 => std::str::from_utf8(body).context("Invalid UTF-8 in source text")?.to_string(),
+            SourceEncoding::Utf16Le => decode_utf16(body, false)?,
+            SourceEncoding::Utf16Be => decode_utf16(body, true)?,
+            SourceEncoding::Latin1 => body.iter().map(|&byte| byte as char).collect(),
+            SourceEncoding::Detect => unreachable!("detect picks a concrete encoding"),
+        };
+        Ok((text, SourceRecord { encoding, bom: bom_len > 0 }))
+    }
+
+    fn bom(self) -> Option<&'static [u8]> {
+        match self {
+            SourceEncoding::Utf8 => Some(UTF8_BOM),
+            SourceEncoding::Utf16Le => Some(UTF16LE_BOM),
+            SourceEncoding::Utf16Be => Some(UTF16BE_BOM),
+            SourceEncoding::Latin1 | SourceEncoding::Detect => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SourceEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "utf8" => Ok(SourceEncoding::Utf8),
+            "utf16le" => Ok(SourceEncoding::Utf16Le),
+            "utf16be" => Ok(SourceEncoding::Utf16Be),
+            "latin1" => Ok(SourceEncoding::Latin1),
+            "detect" => Ok(SourceEncoding::Detect),
+            _ => bail!("Unknown source encoding: {}", s),
+        }
+    }
+}
+
+/// What a file's text was before it was converted to UTF-8, from
+/// `TcfHeader::source_encoding`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceRecord {
+    pub encoding: SourceEncoding,
+    /// Whether the source started with a byte order mark
+    pub bom: bool,
+}
+
+impl SourceRecord {
+    /// Whether the text is its own source, so there's nothing to record
+    pub fn is_plain_utf8(&self) -> bool {
+        self.encoding == SourceEncoding::Utf8 && !self.bom
+    }
+
+    /// `text` in the source's encoding, with its byte order mark
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if self.bom {
+            bytes.extend_from_slice(self.encoding.bom().context("Source record has a byte order mark its encoding lacks")?);
+        }
+        match self.encoding {
+            SourceEncoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+            SourceEncoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            SourceEncoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            SourceEncoding::Latin1 => {
+                for c in text.chars() {
+                    let byte = u8::try_from(c).ok().with_context(|| format!("{:?} has no Latin-1 byte", c))?;
+                    bytes.push(byte);
+                }
+            }
+            SourceEncoding::Detect => bail!("Source record names no encoding"),
+        }
+        Ok(bytes)
+    }
+}
+
+/// Whether `bytes` are UTF-16 in the given byte order with at least half
+/// their code units ASCII, as text without a byte order mark usually is
+fn mostly_ascii_utf16(bytes: &[u8], big_endian: bool) -> bool {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(2) {
+        return false;
+    }
+    let (high, low) = if big_endian { (0, 1) } else { (1, 0) };
+    let ascii = bytes.chunks_exact(2).filter(|unit| unit[high] == 0 && unit[low] != 0 && unit[low] < 0x80).count();
+    ascii * 2 >= bytes.len() / 2 && decode_utf16(bytes, big_endian).is_ok()
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> Result<String> {
+    if !bytes.len().is_multiple_of(2) {
+        bail!("UTF-16 source has an odd length of {} bytes", bytes.len());
+    }
+    let units = bytes.chunks_exact(2).map(|unit| {
+        if big_endian { u16::from_be_bytes([unit[0], unit[1]]) } else { u16::from_le_bytes([unit[0], unit[1]]) }
+    });
+    char::decode_utf16(units)
+        .collect::<std::result::Result<String, _>>()
+        .context("Invalid UTF-16 in source text")
+}
+
+impl TcfCodec {
+    /// Encode text stored as `bytes` in `encoding`, recording the encoding
+    /// and any byte order mark so `decode_to_source` gives `bytes` back
+    ///
+    /// The text is coded as UTF-8, as `encode` codes it; `decode` returns
+    /// it so. Plain UTF-8 sources record nothing and code exactly as
+    /// `encode` would.
+    pub fn encode_with_source_encoding(bytes: &[u8], encoding: SourceEncoding) -> Result<Vec<u8>> {
+        let (text, source) = encoding.decode(bytes)?;
+        Self::record_source(&Self::encode(&text)?, source, &PaddingPolicy::None)
+    }
+
+    /// `encoded` with its header recording `source`, padded by `policy` as
+    /// it was encoded
+    ///
+    /// Payload offsets are relative to the header's end, so only the header
+    /// and padding change.
+    pub fn record_source(encoded: &[u8], source: SourceRecord, policy: &PaddingPolicy) -> Result<Vec<u8>> {
+        if source.is_plain_utf8() {
+            return Ok(encoded.to_vec());
+        }
+        let mut header = Self::parse_header(encoded)?;
+        let payload_start = 8 + u32::from_le_bytes([encoded[4], encoded[5], encoded[6], encoded[7]]) as usize;
+        let body_end = encoded.len().checked_sub(header.padding as usize)
+            .filter(|&end| end >= payload_start)
+            .context("Invalid TCF file: padding overruns the payload")?;
+        let body = &encoded[payload_start..body_end];
+        header.source_encoding = Some(source);
+        let header_json = Self::padded_header(&mut header, body.len() as u64, policy)?;
+        let mut recorded = Vec::with_capacity(8 + header_json.len() + body.len() + header.padding as usize);
+        recorded.extend_from_slice(Self::MAGIC.as_bytes());
+        recorded.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+        recorded.extend_from_slice(&header_json);
+        recorded.extend_from_slice(body);
+        recorded.resize(recorded.len() + header.padding as usize, 0);
+        Ok(recorded)
+    }
+
+    /// Decode TCF data to the bytes it was encoded from, in the encoding
+    /// its header records; UTF-8 for files that record none
+    pub fn decode_to_source(tcf_data: &[u8]) -> Result<Vec<u8>> {
+        let text = Self::decode(tcf_data)?;
+        match Self::parse_header(tcf_data)?.source_encoding {
+            Some(source) => source.encode(&text),
+            None => Ok(text.into_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, big_endian: bool, bom: bool) -> Vec<u8> {
+        let record = SourceRecord { encoding: if big_endian { SourceEncoding::Utf16Be } else { SourceEncoding::Utf16Le }, bom };
+        record.encode(text).unwrap()
+    }
+
+    #[test]
+    fn test_utf16le_with_bom_roundtrips_to_identical_bytes() {
+        let text = "Grüße aus Köln ☕ 𝄞\r\nzweite Zeile\r\n".repeat(20);
+        let source = utf16(&text, false, true);
+        assert!(source.starts_with(UTF16LE_BOM));
+
+        let tcf = TcfCodec::encode_with_source_encoding(&source, SourceEncoding::Detect).unwrap();
+        let header = TcfCodec::parse_header(&tcf).unwrap();
+        assert_eq!(header.source_encoding, Some(SourceRecord { encoding: SourceEncoding::Utf16Le, bom: true }));
+        assert_eq!(TcfCodec::decode(&tcf).unwrap(), text);
+        assert_eq!(TcfCodec::decode_to_source(&tcf).unwrap(), source);
+    }
+
+    #[test]
+    fn test_latin1_high_bytes_roundtrip() {
+        let source = b"caf\xe9 cr\xe8me br\xfbl\xe9e, \xa35 \xd7 2\n\xbfqu\xe9?\t\xff\n".to_vec();
+        let tcf = TcfCodec::encode_with_source_encoding(&source, SourceEncoding::Latin1).unwrap();
+        assert_eq!(TcfCodec::decode(&tcf).unwrap(), "café crème brûlée, £5 × 2\n¿qué?\tÿ\n");
+        assert_eq!(TcfCodec::decode_to_source(&tcf).unwrap(), source);
+
+        let record = SourceRecord { encoding: SourceEncoding::Latin1, bom: false };
+        assert!(record.encode("no Latin-1 for ☕").is_err());
+    }
+
+    #[test]
+    fn test_detect_picks_each_fixture_encoding() {
+        let text = "naïve café, 12 €\n";
+        let fixtures = [
+            ("utf8", text.as_bytes().to_vec(), SourceEncoding::Utf8, false),
+            ("utf8 bom", [UTF8_BOM, text.as_bytes()].concat(), SourceEncoding::Utf8, false),
+            ("utf16le", utf16(text, false, false), SourceEncoding::Utf16Le, false),
+            ("utf16le bom", utf16(text, false, true), SourceEncoding::Utf16Le, true),
+            ("utf16be", utf16(text, true, false), SourceEncoding::Utf16Be, false),
+            ("utf16be bom", utf16(text, true, true), SourceEncoding::Utf16Be, true),
+            ("latin1", b"na\xefve caf\xe9\r\n".to_vec(), SourceEncoding::Latin1, false),
+        ];
+        for (name, bytes, encoding, bom) in fixtures {
+            assert_eq!(SourceEncoding::detect(&bytes).unwrap(), encoding, "{}", name);
+            let (decoded, record) = SourceEncoding::Detect.decode(&bytes).unwrap();
+            assert_eq!(record, SourceRecord { encoding, bom }, "{}", name);
+            assert_eq!(record.encode(&decoded).unwrap(), bytes, "{}", name);
+        }
+        assert!(SourceEncoding::detect(b"\x00\x01\x02\x03\xff").is_err());
+    }
+
+    #[test]
+    fn test_utf8_with_nuls_is_not_taken_for_utf16() {
+        // Valid as UTF-16LE too, and all its code units are ASCII
+        let source = b"a\0b\0c\0d\0".to_vec();
+        assert_eq!(SourceEncoding::detect(&source).unwrap(), SourceEncoding::Utf8);
+
+        let tcf = TcfCodec::encode_with_source_encoding(&source, SourceEncoding::Detect).unwrap();
+        assert_eq!(TcfCodec::parse_header(&tcf).unwrap().source_encoding, None);
+        assert_eq!(TcfCodec::decode(&tcf).unwrap().as_bytes(), source);
+        assert_eq!(TcfCodec::decode_to_source(&tcf).unwrap(), source);
+    }
+
+    #[test]
+    fn test_plain_utf8_records_nothing() {
+        let text = "plain text stays as encode codes it\n";
+        let tcf = TcfCodec::encode_with_source_encoding(text.as_bytes(), SourceEncoding::Detect).unwrap();
+        assert_eq!(tcf, TcfCodec::encode(text).unwrap());
+        assert_eq!(TcfCodec::decode_to_source(&tcf).unwrap(), text.as_bytes());
+
+        // A UTF-8 byte order mark is text like any other
+        let with_bom = format!("\u{feff}{}", text);
+        let tcf = TcfCodec::encode_with_source_encoding(with_bom.as_bytes(), SourceEncoding::Detect).unwrap();
+        assert_eq!(tcf, TcfCodec::encode(&with_bom).unwrap());
+        assert_eq!(TcfCodec::decode(&tcf).unwrap(), with_bom);
+        assert!(TcfCodec::decode(&tcf).unwrap().as_bytes().starts_with(UTF8_BOM));
+    }
+}
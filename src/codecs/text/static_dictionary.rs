@@ -0,0 +1,363 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Common substrings shared by a family of small documents, substituted
+/// before entropy coding so even a document too short to repeat itself
+/// codes them cheaply
+///
+/// Entry `i` is coded as the private use character U+E000 + `i`, three
+/// UTF-8 bytes, so the coded text is still text for every method and
+/// tokenizer. Private use characters already in the text are escaped with
+/// `ESCAPE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    entries: Vec<String>,
+}
+
+/// Where a `DICTIONARY_COMPRESSED` file's payload stands relative to its text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryRecord {
+    /// Length of the text after substitution, which is what the payload codes
+    pub coded_size: u64,
+    /// `Dictionary::short_hash` of the dictionary the text was substituted with
+    pub hash: String,
+}
+
+/// Supplies the dictionaries of files coded without theirs, by the
+/// `Dictionary::short_hash` their headers record
+///
+/// Short hashes are the first 64 bits of the SHA-256, to keep headers of
+/// small files small; resolvers check what they return has the hash.
+pub trait DictionaryResolver {
+    /// The dictionary with short hash `hash`; `None` if there is none
+    fn resolve(&self, hash: &str) -> Result<Option<Dictionary>>;
+}
+
+/// A dictionary resolves itself
+impl DictionaryResolver for Dictionary {
+    fn resolve(&self, hash: &str) -> Result<Option<Dictionary>> {
+        Ok((self.short_hash() == hash).then(|| self.clone()))
+    }
+}
+
+/// A directory of dictionaries, each saved as its `to_bytes` in a file
+/// named by its `short_hash`
+#[derive(Debug, Clone)]
+pub struct DictionaryDir {
+    path: PathBuf,
+}
+
+impl DictionaryDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Write `dictionary` where `resolve` looks for it, failing if a
+    /// different dictionary with the same short hash is already there
+    pub fn save(&self, dictionary: &Dictionary) -> Result<PathBuf> {
+        let path = self.path.join(dictionary.short_hash());
+        if fs::read(&path).is_ok_and(|bytes| bytes != dictionary.to_bytes()) {
+            bail!("Dictionary {} holds a different dictionary with the same short hash", path.display());
+        }
+        fs::write(&path, dictionary.to_bytes()).with_context(|| format!("Failed to write dictionary {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+impl DictionaryResolver for DictionaryDir {
+    /// Fails on a file whose dictionary doesn't have the hash it's named
+    /// by, so a stray or damaged one is never used in place of the one
+    /// asked for
+    fn resolve(&self, hash: &str) -> Result<Option<Dictionary>> {
+        if !Dictionary::is_short_hash(hash) {
+            bail!("Dictionary hash {:?} isn't {} lowercase hex digits", hash, Dictionary::SHORT_HASH_LEN);
+        }
+        let path = self.path.join(hash);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read dictionary {}", path.display())),
+        };
+        let dictionary = Dictionary::from_bytes(&bytes).with_context(|| format!("Invalid dictionary {}", path.display()))?;
+        if dictionary.short_hash() != hash {
+            bail!("Dictionary {} has hash {}, not the one it's named by", path.display(), dictionary.short_hash());
+        }
+        Ok(Some(dictionary))
+    }
+}
+
+impl Dictionary {
+    /// First character standing for an entry
+    const FIRST_CODE: u32 = 0xE000;
+    /// Precedes a private use character that is part of the text
+    pub const ESCAPE: char = '\u{F8FF}';
+    /// Every other private use character of the Basic Multilingual Plane
+    pub const MAX_ENTRIES: usize = (Self::ESCAPE as u32 - Self::FIRST_CODE) as usize;
+    /// Longest entry `to_bytes` can store
+    pub const MAX_ENTRY_BYTES: usize = u8::MAX as usize;
+    /// Shortest substring `train` considers; a code takes three bytes
+    const MIN_TRAINED_BYTES: usize = 4;
+    /// Longest substring `train` considers
+    const MAX_TRAINED_BYTES: usize = 32;
+    /// Hex digits of `short_hash`
+    pub const SHORT_HASH_LEN: usize = 16;
+
+    /// Dictionary of `entries`, in code order; each must be non-empty and
+    /// at most `MAX_ENTRY_BYTES` long
+    pub fn new(entries: Vec<String>) -> Result<Self> {
+        if entries.len() > Self::MAX_ENTRIES {
+            bail!("Dictionary has {} entries; at most {} fit", entries.len(), Self::MAX_ENTRIES);
+        }
+        if let Some((index, entry)) = entries.iter().enumerate().find(|(_, entry)| entry.is_empty() || entry.len() > Self::MAX_ENTRY_BYTES) {
+            bail!("Dictionary entry {} is {} bytes; entries take 1 to {}", index, entry.len(), Self::MAX_ENTRY_BYTES);
+        }
+        Ok(Self { entries })
+    }
+
+    /// Pick the substrings of `samples` that would save the most if each
+    /// cost three bytes, while `to_bytes` stays within `max_size`
+    ///
+    /// Candidates are ranked by bytes saved across all samples. One that
+    /// shares half its length with an entry already picked is passed over,
+    /// so the budget isn't spent on the same run shifted by a byte. Every
+    /// substring of up to 32 bytes is counted, so samples are best kept to
+    /// a few hundred KB.
+    pub fn train(samples: &[&str], max_size: usize) -> Self {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for sample in samples {
+            let boundaries: Vec<usize> = sample.char_indices().map(|(i, _)| i).chain([sample.len()]).collect();
+            for (n, &start) in boundaries.iter().enumerate() {
+                let ends = boundaries[n + 1..].iter()
+                    .skip_while(|&&end| end - start < Self::MIN_TRAINED_BYTES)
+                    .take_while(|&&end| end - start <= Self::MAX_TRAINED_BYTES);
+                for &end in ends {
+                    *counts.entry(&sample[start..end]).or_default() += 1;
+                }
+            }
+        }
+
+        let saving = |candidate: &str, count: u64| (count * (candidate.len() as u64 - 3)).saturating_sub(candidate.len() as u64);
+        let mut candidates: Vec<(u64, &str)> = counts.into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(candidate, count)| (saving(candidate, count), candidate))
+            .filter(|&(saving, _)| saving > 0)
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+
+        // Every substring of the entries picked so far
+        let mut covered: HashSet<&str> = HashSet::new();
+        let mut entries = Vec::new();
+        let mut size = 2;
+        for (_, candidate) in candidates {
+            if entries.len() == Self::MAX_ENTRIES {
+                break;
+            }
+            if size + 1 + candidate.len() > max_size {
+                continue;
+            }
+            let (mut low, mut high) = (candidate.len() / 2, candidate.len().div_ceil(2));
+            while !candidate.is_char_boundary(low) {
+                low -= 1;
+            }
+            while !candidate.is_char_boundary(high) {
+                high += 1;
+            }
+            if covered.contains(&candidate[..high]) || covered.contains(&candidate[low..]) {
+                continue;
+            }
+            let boundaries: Vec<usize> = candidate.char_indices().map(|(i, _)| i).chain([candidate.len()]).collect();
+            for (n, &start) in boundaries.iter().enumerate() {
+                covered.extend(boundaries[n + 1..].iter().map(|&end| &candidate[start..end]));
+            }
+            size += 1 + candidate.len();
+            entries.push(candidate.to_string());
+        }
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// First 64 bits of `content_hash`, which files coded without their
+    /// dictionary record so the right one is used to decode them
+    pub fn short_hash(&self) -> String {
+        let mut hash = self.content_hash();
+        hash.truncate(Self::SHORT_HASH_LEN);
+        hash
+    }
+
+    /// Whether `hash` has the form of a `short_hash`, and so names no path
+    pub fn is_short_hash(hash: &str) -> bool {
+        hash.len() == Self::SHORT_HASH_LEN && hash.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    /// Lowercase hex SHA-256 of `to_bytes`, which is also the `ContentId`
+    /// an `ObjectStore` keeps the dictionary under
+    pub fn content_hash(&self) -> String {
+        format!("{:x}", Sha256::digest(self.to_bytes()))
+    }
+
+    /// Entry count as a u16 LE, then each entry as a length byte and its bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = (self.entries.len() as u16).to_le_bytes().to_vec();
+        for entry in &self.entries {
+            out.push(entry.len() as u8);
+            out.extend_from_slice(entry.as_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let count = data.get(..2).context("Dictionary truncated")?;
+        let count = u16::from_le_bytes([count[0], count[1]]) as usize;
+        let mut entries = Vec::with_capacity(count);
+        let mut position = 2;
+        for index in 0..count {
+            let length = *data.get(position).context("Dictionary truncated")? as usize;
+            let bytes = data.get(position + 1..position + 1 + length).context("Dictionary truncated")?;
+            let entry = std::str::from_utf8(bytes).with_context(|| format!("Dictionary entry {} is not UTF-8", index))?;
+            entries.push(entry.to_string());
+            position += 1 + length;
+        }
+        if position != data.len() {
+            bail!("{} bytes after the last dictionary entry", data.len() - position);
+        }
+        Self::new(entries)
+    }
+
+    /// `text` with the longest entry matching at each point replaced by its code
+    pub fn substitute(&self, text: &str) -> String {
+        let mut by_first: HashMap<char, Vec<usize>> = HashMap::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            by_first.entry(entry.chars().next().unwrap()).or_default().push(index);
+        }
+        for candidates in by_first.values_mut() {
+            candidates.sort_by_key(|&index| std::cmp::Reverse(self.entries[index].len()));
+        }
+
+        let mut out = String::with_capacity(text.len());
+        let mut position = 0;
+        while let Some(c) = text[position..].chars().next() {
+            let rest = &text[position..];
+            let matched = by_first.get(&c)
+                .and_then(|candidates| candidates.iter().find(|&&index| rest.starts_with(self.entries[index].as_str())));
+            if let Some(&index) = matched {
+                out.push(char::from_u32(Self::FIRST_CODE + index as u32).unwrap());
+                position += self.entries[index].len();
+                continue;
+            }
+            if Self::is_code(c) || c == Self::ESCAPE {
+                out.push(Self::ESCAPE);
+            }
+            out.push(c);
+            position += c.len_utf8();
+        }
+        out
+    }
+
+    /// Undo `substitute`, failing on codes past the last entry or if the
+    /// text doesn't come out `text_size` bytes long
+    pub fn expand(&self, coded: &[u8], text_size: u64) -> Result<Vec<u8>> {
+        let coded = std::str::from_utf8(coded).context("Dictionary coded text is not UTF-8")?;
+        let mut out = String::with_capacity(usize::try_from(text_size).unwrap_or(0));
+        let mut chars = coded.chars();
+        while let Some(c) = chars.next() {
+            if c == Self::ESCAPE {
+                out.push(chars.next().context("Dictionary escape at end of text")?);
+            } else if Self::is_code(c) {
+                let index = (c as u32 - Self::FIRST_CODE) as usize;
+                let entry = self.entries.get(index)
+                    .with_context(|| format!("Dictionary code {} is past the last of {} entries", index, self.entries.len()))?;
+                out.push_str(entry);
+            } else {
+                out.push(c);
+            }
+            if out.len() as u64 > text_size {
+                bail!("Dictionary expansion runs past {} bytes", text_size);
+            }
+        }
+        if (out.len() as u64) < text_size {
+            bail!("Dictionary expansion gives {} bytes, expected {}", out.len(), text_size);
+        }
+        Ok(out.into_bytes())
+    }
+
+    fn is_code(c: char) -> bool {
+        (Self::FIRST_CODE..Self::ESCAPE as u32).contains(&(c as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responses() -> Vec<String> {
+        (0..40).map(|i| format!(
+            r#"{{"id":{},"status":"active","created_at":"2024-03-{:02}T10:00:00Z","owner":{{"login":"user{}","type":"User"}}}}"#,
+            i, i % 28 + 1, i * 7,
+        )).collect()
+    }
+
+    #[test]
+    fn test_train_picks_repeated_keys_within_budget() {
+        let samples = responses();
+        let samples: Vec<&str> = samples.iter().map(String::as_str).collect();
+        let dictionary = Dictionary::train(&samples, 256);
+        assert!(dictionary.to_bytes().len() <= 256);
+        assert!(dictionary.entries().iter().any(|entry| entry.contains("\"login\":\"user")), "{:?}", dictionary.entries());
+        assert!(Dictionary::train(&samples, 2).entries().is_empty());
+        assert_eq!(Dictionary::train(&samples, 256), dictionary);
+    }
+
+    #[test]
+    fn test_substitute_roundtrips_text_using_private_use_characters() {
+        let dictionary = Dictionary::new(vec!["\"status\":".to_string(), "\"stat".to_string(), "ünï".to_string()]).unwrap();
+        let text = "{\"status\":1,\"stats\":\u{E000}\u{E001}\u{F8FF}} ünïcode \u{E005}";
+        let coded = dictionary.substitute(text);
+        assert!(coded.starts_with("{\u{E000}1,\u{E001}s"));
+        assert!(coded.len() < text.len() + 12);
+        assert_eq!(dictionary.expand(coded.as_bytes(), text.len() as u64).unwrap(), text.as_bytes());
+        assert!(dictionary.expand(coded.as_bytes(), text.len() as u64 - 1).is_err());
+        assert!(dictionary.expand("\u{E003}".as_bytes(), 3).is_err());
+    }
+
+    #[test]
+    fn test_bytes_roundtrip_and_validation() {
+        let dictionary = Dictionary::new(vec!["abcd".to_string(), "→→".to_string()]).unwrap();
+        let bytes = dictionary.to_bytes();
+        assert_eq!(Dictionary::from_bytes(&bytes).unwrap(), dictionary);
+        assert!(Dictionary::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Dictionary::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(Dictionary::new(vec![String::new()]).is_err());
+        assert!(Dictionary::new(vec!["x".repeat(256)]).is_err());
+    }
+
+    #[test]
+    fn test_dictionary_dir_resolves_by_hash() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dictionaries = DictionaryDir::new(dir.path());
+        let dictionary = Dictionary::new(vec!["abcd".to_string()]).unwrap();
+        let hash = dictionary.short_hash();
+        assert!(dictionary.content_hash().starts_with(&hash));
+        assert_eq!(dictionaries.resolve(&hash).unwrap(), None);
+        assert_eq!(dictionary.resolve(&hash).unwrap().as_ref(), Some(&dictionary));
+
+        let path = dictionaries.save(&dictionary).unwrap();
+        assert_eq!(path.file_name().unwrap().to_str().unwrap(), hash);
+        assert_eq!(dictionaries.resolve(&hash).unwrap(), Some(dictionary.clone()));
+        dictionaries.save(&dictionary).unwrap();
+        let other = Dictionary::new(vec!["efgh".to_string()]).unwrap();
+        assert_eq!(dictionary.resolve(&other.short_hash()).unwrap(), None);
+        fs::write(&path, other.to_bytes()).unwrap();
+        assert!(dictionaries.resolve(&hash).is_err());
+        assert!(dictionaries.save(&dictionary).is_err());
+        // Headers are untrusted, so a hash can't name a path
+        assert!(dictionaries.resolve("../../etc/passwd").is_err());
+    }
+}
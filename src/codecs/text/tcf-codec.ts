@@ -99,8 +99,8 @@ export class TextCodec {
       const currentByte = data[i];
       let runLength = 1;
       
-      // Count consecutive identical bytes
-      while (i + runLength < data.length && data[i + runLength] === currentByte) {
+      // Count consecutive identical bytes, up to what the run's length byte holds
+      while (i + runLength < data.length && runLength < 255 && data[i + runLength] === currentByte) {
         runLength++;
       }
       
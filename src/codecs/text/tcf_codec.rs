@@ -1,7 +1,33 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use crate::codecs::text::arithmetic_coder::{ArithmeticCoder, ArithmeticDecoder, FrequencyModel};
+use crate::codecs::cli_common::RuntimeConfig;
+use crate::codecs::formats;
+use crate::codecs::text::arithmetic_coder::{ArithmeticCoder, ArithmeticDecoder, CostEstimator, FrequencyModel};
+use crate::codecs::text::chunking::ChunkStrategy;
+use crate::codecs::text::context_model::{ContextModel, MAX_BYTES_PER_CODED_BYTE};
+use crate::codecs::text::front_coding::{front_decode, front_encode, is_sorted_lines};
+use crate::codecs::text::migrate::MigrationRecord;
+use crate::codecs::text::model_stats::MAX_MODEL_ORDER;
+use crate::codecs::text::newlines::{normalize_crlf, restore_crlf, NewlinePolicy, NewlineRecord};
+use crate::codecs::text::padding::PaddingPolicy;
+use crate::codecs::text::sniff::{self, CompressibilityHint};
+use crate::codecs::text::source_encoding::SourceRecord;
+use crate::codecs::text::static_dictionary::{Dictionary, DictionaryRecord, DictionaryResolver};
+use crate::codecs::text::tokenizer::{tokenizer_by_id, ByteTokenizer, JsonAwareTokenizer, Token, TokenClass, Tokenizer};
+use crate::codecs::text::errors::TcfError;
+use crate::codecs::text::warnings::CodecWarning;
+use crate::codecs::explain::ExplainTrace;
+use crate::codecs::layout::{self, LayoutRegion};
+use crate::codecs::peek::{self, ChecksumKind, PeekResult};
+use crate::codecs::trace::{phase, trace_event};
 use anyhow::{Result, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::ops::Range;
 
 /// TCF (Text Codec Format) header structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -14,6 +40,147 @@ pub struct TcfHeader {
     pub checksum: String,
     pub model_size: u32,
     pub compression_method: String,
+    /// Parameters of the arithmetic model; absent in files using the byte model
+    #[serde(default, skip_serializing_if = "ModelParams::is_default")]
+    pub model_params: ModelParams,
+    /// How the text was cut into chunks; absent in single-chunk files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<ChunkStrategy>,
+    /// Index of the chunks of a `CHUNKED` file, in text order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<TcfChunk>,
+    /// Where the CRs of a `NEWLINES_NORMALIZED` file go back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newlines: Option<NewlineRecord>,
+    /// What a `DICTIONARY_COMPRESSED` file's text was substituted with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dictionary: Option<DictionaryRecord>,
+    /// The legacy file this one was converted from by `migrate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migrated_from: Option<MigrationRecord>,
+    /// Encoding and byte order mark of a source that wasn't plain UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_encoding: Option<SourceRecord>,
+    /// Zero bytes at the end of the file, from `TcfEncodeOptions::pad_to`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub padding: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Index entry for one independently decodable chunk
+///
+/// A chunk's data is its model followed by its payload, both coded as a
+/// single-chunk file with `compression_method` would code them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TcfChunk {
+    /// Where the chunk's text starts in the original
+    pub text_offset: u64,
+    pub original_size: u64,
+    /// Where the chunk's data starts, relative to the end of the header
+    pub data_offset: u64,
+    pub model_size: u32,
+    pub compressed_size: u64,
+    pub compression_method: String,
+    /// Offset within the chunk's text of the first record that starts in it
+    pub first_record: Option<u64>,
+    /// CRC-32 of the chunk's text, so ranges can be checked without the whole file
+    pub crc32: u32,
+    /// Whether the chunk has data of its own; absent for data chunks
+    #[serde(default, skip_serializing_if = "ChunkType::is_data")]
+    pub chunk_type: ChunkType,
+}
+
+impl TcfChunk {
+    /// Length of the chunk's model and payload together
+    pub fn data_size(&self) -> Option<usize> {
+        (self.model_size as usize).checked_add(usize::try_from(self.compressed_size).ok()?)
+    }
+
+    /// Text bytes per stored byte; `None` for references, which store nothing
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let stored = self.model_size as u64 + self.compressed_size;
+        (self.chunk_type.is_data() && stored > 0).then(|| self.original_size as f64 / stored as f64)
+    }
+}
+
+/// What a `TcfChunk` entry points at
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkType {
+    /// The chunk's own model and payload
+    #[default]
+    Data,
+    /// Same text as the earlier data chunk with this index, which holds the
+    /// data; the entry's data fields are zero
+    Reference(u64),
+    /// Data that isn't part of the text, e.g. an archive's file table; such
+    /// chunks follow every text chunk and start at the end of the text
+    Metadata,
+    /// The serialized `Dictionary` of a `DICTIONARY_COMPRESSED` file, which
+    /// is never chunked, so this is the only entry; starts at the end of
+    /// the text
+    Dictionary,
+}
+
+impl ChunkType {
+    fn is_data(&self) -> bool {
+        *self == ChunkType::Data
+    }
+}
+
+/// Header fields `TcfCodec::peek` reads from the start of a file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TcfPeek {
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub compression_method: String,
+    pub flags: u32,
+    pub checksum: String,
+}
+
+impl TcfPeek {
+    pub fn checksum_kind(&self) -> ChecksumKind {
+        ChecksumKind::of(&self.checksum)
+    }
+
+    /// Original size over payload size
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_size > 0 {
+            self.original_size as f64 / self.compressed_size as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// How the arithmetic coder models the text
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModelParams {
+    /// Id of the `Tokenizer` whose token classes each get their own model
+    pub tokenizer_id: String,
+    /// Longest context of the adaptive `ContextModel` the bytes are coded
+    /// with; zero for the stored order-0 model
+    #[serde(default, skip_serializing_if = "is_zero_order")]
+    pub max_order: u8,
+}
+
+fn is_zero_order(order: &u8) -> bool {
+    *order == 0
+}
+
+impl Default for ModelParams {
+    fn default() -> Self {
+        Self { tokenizer_id: ByteTokenizer::ID.to_string(), max_order: 0 }
+    }
+}
+
+impl ModelParams {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 /// TCF compression flags
@@ -21,138 +188,721 @@ pub struct TcfFlags;
 impl TcfFlags {
     pub const NONE: u32 = 0;
     pub const UNICODE_NORMALIZED: u32 = 1;
+    /// Text substituted with a `Dictionary` before coding; see `TcfHeader::dictionary`
     pub const DICTIONARY_COMPRESSED: u32 = 2;
     pub const ADAPTIVE_MODEL: u32 = 4;
+    /// Model stored with `FrequencyModel::to_compact_bytes` instead of JSON
+    pub const COMPACT_MODEL: u32 = 8;
+    /// Text coded as independent chunks listed in `TcfHeader::chunks`
+    pub const CHUNKED: u32 = 16;
+    /// Some payload holds front coded lines, to be rebuilt after decompressing
+    pub const FRONT_CODED: u32 = 32;
+    /// CRLFs coded as LFs, with a newline record after the payload
+    pub const NEWLINES_NORMALIZED: u32 = 64;
+}
+
+/// Payload coding recorded in `TcfHeader::compression_method`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcfMethod {
+    /// Order-0 arithmetic coding with a stored frequency model
+    Arithmetic,
+    /// DEFLATE payload in gzip framing (flate2)
+    Gzip,
+    /// Zstandard payload; needs the `zstd` feature to encode or decode
+    Zstd,
+    /// Raw bytes, for input that no method would shrink
+    Stored,
+    /// Sorted lines with each one's prefix shared with the previous line
+    /// replaced by its length, then gzipped
+    FrontCoding,
+}
+
+impl TcfMethod {
+    /// Every general-purpose method this build can encode and decode
+    ///
+    /// Front coding only takes sorted lines, so it is left out.
+    pub fn available() -> Vec<TcfMethod> {
+        [TcfMethod::Arithmetic, TcfMethod::Gzip, TcfMethod::Zstd, TcfMethod::Stored]
+            .into_iter()
+            .filter(|method| method.is_supported())
+            .collect()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TcfMethod::Arithmetic => "arithmetic",
+            TcfMethod::Gzip => "gzip",
+            TcfMethod::Zstd => "zstd",
+            TcfMethod::Stored => "stored",
+            TcfMethod::FrontCoding => "front-coding",
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        self != TcfMethod::Zstd || cfg!(feature = "zstd")
+    }
+}
+
+impl std::fmt::Display for TcfMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for TcfMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "arithmetic" => Ok(TcfMethod::Arithmetic),
+            "gzip" => Ok(TcfMethod::Gzip),
+            "zstd" => Ok(TcfMethod::Zstd),
+            "stored" => Ok(TcfMethod::Stored),
+            "front-coding" => Ok(TcfMethod::FrontCoding),
+            _ => Err(TcfError::UnsupportedMethod { method: s.to_string(), missing_feature: false }.into()),
+        }
+    }
+}
+
+/// Options for `TcfCodec::encode_with_options`
+#[derive(Debug, Clone)]
+pub struct TcfEncodeOptions {
+    /// Payload coding, or `None` to try every available method and keep the
+    /// smallest; input the sniffer calls incompressible is stored directly
+    pub method: Option<TcfMethod>,
+    /// Tokenizer for the arithmetic method; see `TOKENIZER_IDS`
+    pub tokenizer_id: String,
+    /// Code the text as independent chunks cut this way, so ranges can be
+    /// decoded without the rest; `None`, the default, codes it as one
+    /// payload however long it is, unlike `TcfCodec::encode`
+    pub chunking: Option<ChunkStrategy>,
+    /// Line ending handling; normalizing can't be combined with chunking
+    pub newline: NewlinePolicy,
+    /// Pad the file up to a fixed size, so its length reveals less of the text
+    pub pad_to: PaddingPolicy,
+    /// Substitute this dictionary's entries before coding; can't be
+    /// combined with chunking
+    pub dictionary: Option<Dictionary>,
+    /// Store `dictionary` in the file; without it, the header records only
+    /// its id and decoding needs `TcfCodec::decode_with_dictionary`
+    /// or `TcfCodec::decode_with_resolver`
+    pub embed_dictionary: bool,
+    /// Code the arithmetic method's bytes with an adaptive context model
+    /// of up to this many bytes instead of a stored order-0 model; needs
+    /// the byte tokenizer
+    pub max_order: u8,
+}
+
+impl Default for TcfEncodeOptions {
+    fn default() -> Self {
+        Self {
+            method: Some(TcfMethod::Arithmetic),
+            tokenizer_id: ByteTokenizer::ID.to_string(),
+            chunking: None,
+            newline: NewlinePolicy::Preserve,
+            pad_to: PaddingPolicy::None,
+            dictionary: None,
+            embed_dictionary: true,
+            max_order: 0,
+        }
+    }
+}
+
+/// A file from `TcfCodec::encode_with_options`, with anything about how it
+/// was coded that the caller may want to act on
+#[derive(Debug, Clone)]
+pub struct TcfEncoded {
+    pub data: Vec<u8>,
+    pub warnings: Vec<CodecWarning>,
+}
+
+/// Byte-level map of a TCF file, for tooling and debugging
+#[derive(Serialize, Debug, Clone)]
+pub struct TcfLayout {
+    pub file_size: u64,
+    pub header: TcfHeader,
+    /// Consecutive regions covering the whole file: `magic`, `header_length`,
+    /// `header`, `model`, `payload`, `newlines` for files with normalized
+    /// line endings, `dictionary` for files carrying their dictionary,
+    /// `padding` for padded files and, if present, `trailing`
+    pub regions: Vec<LayoutRegion>,
+    pub model_size: u64,
+    pub payload_size: u64,
+    /// Bytes after the payload that the decoder doesn't account for
+    pub trailing_size: u64,
+}
+
+impl TcfLayout {
+    pub fn region(&self, name: &str) -> Option<&LayoutRegion> {
+        self.regions.iter().find(|region| region.name == name)
+    }
 }
 
 /// High-performance Text Codec implementation
 pub struct TcfCodec;
 
 impl TcfCodec {
-    const MAGIC: &'static str = "TCF2"; // Version 2 with proper arithmetic coding
-    const VERSION: u16 = 2;
+    pub(super) const MAGIC: &'static str = formats::TCF.magic;
+    pub(super) const VERSION: u16 = formats::TCF.version;
+    /// Ends each token in the tokenized arithmetic stream
+    const TOKEN_END: u8 = 0xFF;
+    /// Bytes of text the arithmetic coder tokenizes at a time
+    const TOKEN_WINDOW: usize = 64 * 1024;
+    /// Share of JSON string bytes in escapes above which encoding warns
+    const ESCAPE_HEAVY_FRACTION: f64 = 0.25;
 
     /// Encode text to TCF format with advanced compression
+    ///
+    /// Text over `STREAM_CHUNK_BYTES` is cut into chunks that long, each
+    /// coded and checked on its own, so a damaged byte loses only its chunk
+    /// and ranges decode without the rest. Other chunk sizes are set with
+    /// `encode_with_options`, which doesn't chunk on its own: default
+    /// options code long text as one payload.
     pub fn encode(text: &str) -> Result<Vec<u8>> {
+        let chunking = (text.len() > Self::STREAM_CHUNK_BYTES).then_some(ChunkStrategy::FixedBytes(Self::STREAM_CHUNK_BYTES));
+        let options = TcfEncodeOptions { chunking, ..Default::default() };
+        Self::encode_with_options(text, &options).map(|encoded| encoded.data)
+    }
+
+    /// Encode text with an explicit payload method, reporting anything
+    /// the caller may not expect as warnings
+    ///
+    /// Text is chunked only as `options.chunking` says, so unlike `encode`,
+    /// default options code text over `STREAM_CHUNK_BYTES` as one payload.
+    pub fn encode_with_options(text: &str, options: &TcfEncodeOptions) -> Result<TcfEncoded> {
+        Self::encode_with_trace(text, options).map(|(encoded, _)| encoded)
+    }
+
+    /// Like `encode_with_options`, also returning the method and chunk
+    /// decisions the encode made
+    pub fn encode_with_trace(text: &str, options: &TcfEncodeOptions) -> Result<(TcfEncoded, ExplainTrace)> {
+        Self::encode_with_metadata(text, options, None)
+    }
+
+    /// Encode text, appending `metadata` as a metadata chunk after the text
+    /// chunks; needs a chunking strategy when given
+    pub(super) fn encode_with_metadata(text: &str, options: &TcfEncodeOptions, metadata: Option<&str>) -> Result<(TcfEncoded, ExplainTrace)> {
+        phase!("tcf.encode", bytes_in = text.len(), tokenizer = %options.tokenizer_id);
+
         // Normalize Unicode text (NFC normalization)
-        let normalized_text = text.chars()
-            .collect::<String>()
-            .chars()
-            .nfc()
-            .collect::<String>();
+        let normalized_text = text.chars().nfc().collect::<String>();
         
+        let mut warnings = Vec::new();
+        let mut trace = ExplainTrace::default();
+        let chars_changed = changed_chars(text, &normalized_text);
+        if chars_changed > 0 {
+            warnings.push(CodecWarning::NormalizedInput { chars_changed });
+        }
+
         let original_data = normalized_text.as_bytes();
         let original_size = original_data.len() as u64;
-
-        // Build adaptive frequency model
-        let mut model = FrequencyModel::new();
-        model.build_from_data(original_data);
-        
-        // Serialize the model
-        let model_data = model.serialize();
-        let model_size = model_data.len() as u32;
-
-        // Encode using arithmetic coding
-        let mut encoder = ArithmeticCoder::new();
-        for &byte in original_data {
-            if let Some((low, high)) = model.get_symbol_range(byte) {
-                encoder.encode_symbol(low, high, model.total_frequency());
-            }
-        }
-        let compressed_data = encoder.finish();
+        let tokenizer = Self::tokenizer(&options.tokenizer_id)?;
+        Self::check_max_order(options.max_order, tokenizer.as_ref())?;
 
         // Calculate checksum
         let mut hasher = Sha256::new();
         hasher.update(original_data);
         let checksum = format!("{:x}", hasher.finalize());
 
+        // The checksum and original size stay those of the text as given
+        let (coded_text, newline_record) = match options.newline {
+            NewlinePolicy::Preserve => (Cow::Borrowed(normalized_text.as_str()), Vec::new()),
+            NewlinePolicy::NormalizeLfRecordPositions if options.chunking.is_some() => {
+                anyhow::bail!("Newline normalization can't be combined with chunking")
+            }
+            NewlinePolicy::NormalizeLfRecordPositions => {
+                let (text, record) = normalize_crlf(&normalized_text);
+                (Cow::Owned(text), record)
+            }
+        };
+        let newline_coded_size = coded_text.len() as u64;
+        let (coded_text, dictionary) = match &options.dictionary {
+            None => (coded_text, None),
+            Some(_) if options.chunking.is_some() => anyhow::bail!("Dictionary substitution can't be combined with chunking"),
+            Some(dictionary) => {
+                let substituted = dictionary.substitute(&coded_text);
+                let record = DictionaryRecord {
+                    coded_size: substituted.len() as u64,
+                    hash: dictionary.short_hash(),
+                };
+                (Cow::Owned(substituted), Some((dictionary, record)))
+            }
+        };
+
+        if metadata.is_some() && options.chunking.is_none() {
+            anyhow::bail!("TCF metadata chunks need a chunking strategy");
+        }
+        let (method, model_data, compressed_data, mut chunks) = match options.chunking {
+            None => {
+                let (method, model_data, compressed_data) = Self::code_text(&coded_text, options.method, tokenizer.as_ref(), options.max_order, &mut warnings, &mut trace)?;
+                (Some(method), model_data, compressed_data, Vec::new())
+            }
+            Some(strategy) => {
+                strategy.validate()?;
+                let (mut chunks, mut data) = Self::code_chunks(&coded_text, strategy, options.method, tokenizer.as_ref(), options.max_order, &mut warnings, &mut trace)?;
+                if let Some(metadata) = metadata {
+                    let (method, model_data, compressed_data) = Self::code_text(metadata, options.method, tokenizer.as_ref(), options.max_order, &mut warnings, &mut trace)?;
+                    chunks.push(TcfChunk {
+                        text_offset: coded_text.len() as u64,
+                        original_size: metadata.len() as u64,
+                        data_offset: data.len() as u64,
+                        model_size: model_data.len() as u32,
+                        compressed_size: compressed_data.len() as u64,
+                        compression_method: method.as_str().to_string(),
+                        first_record: None,
+                        crc32: crc32fast::hash(metadata.as_bytes()),
+                        chunk_type: ChunkType::Metadata,
+                    });
+                    data.extend_from_slice(&model_data);
+                    data.extend_from_slice(&compressed_data);
+                }
+                (Self::chunked_method(&chunks, options.method)?, Vec::new(), data, chunks)
+            }
+        };
+
+        let (mut flags, model_params) = Self::coding_flags(method, options.chunking.map(|_| chunks.as_slice()), tokenizer.as_ref(), options.max_order);
+        if flags & TcfFlags::ADAPTIVE_MODEL != 0 && tokenizer.id() == JsonAwareTokenizer::ID {
+            let (escaped, total) = escape_counts(&coded_text);
+            Self::warn_escape_heavy(escaped, total, &mut warnings);
+        }
+        let newlines = (options.newline == NewlinePolicy::NormalizeLfRecordPositions).then(|| {
+            flags |= TcfFlags::NEWLINES_NORMALIZED;
+            NewlineRecord { coded_size: newline_coded_size, record_size: newline_record.len() as u64 }
+        });
+        let mut dictionary_data = Vec::new();
+        if let Some((dictionary, _)) = &dictionary {
+            flags |= TcfFlags::DICTIONARY_COMPRESSED;
+            if options.embed_dictionary {
+                let bytes = dictionary.to_bytes();
+                dictionary_data = Self::gzip(&bytes)?;
+                chunks.push(TcfChunk {
+                    text_offset: original_size,
+                    original_size: bytes.len() as u64,
+                    data_offset: (model_data.len() + compressed_data.len() + newline_record.len()) as u64,
+                    model_size: 0,
+                    compressed_size: dictionary_data.len() as u64,
+                    compression_method: TcfMethod::Gzip.as_str().to_string(),
+                    first_record: None,
+                    crc32: crc32fast::hash(&bytes),
+                    chunk_type: ChunkType::Dictionary,
+                });
+            }
+        }
+
         // Create header
-        let header = TcfHeader {
+        let mut header = TcfHeader {
             magic: Self::MAGIC.to_string(),
             version: Self::VERSION,
-            flags: TcfFlags::UNICODE_NORMALIZED | TcfFlags::ADAPTIVE_MODEL,
+            flags,
             original_size,
             compressed_size: compressed_data.len() as u64,
             checksum,
-            model_size,
-            compression_method: "arithmetic".to_string(),
+            model_size: model_data.len() as u32,
+            // Chunks coded with different methods each record their own
+            compression_method: method.map_or("mixed", TcfMethod::as_str).to_string(),
+            model_params,
+            chunking: options.chunking,
+            chunks,
+            newlines,
+            dictionary: dictionary.map(|(_, record)| record),
+            migrated_from: None,
+            source_encoding: None,
+            padding: 0,
         };
 
         // Serialize header
-        let header_json = serde_json::to_vec(&header)
-            .context("Failed to serialize TCF header")?;
+        let body_size = (model_data.len() + compressed_data.len() + newline_record.len() + dictionary_data.len()) as u64;
+        let header_json = Self::padded_header(&mut header, body_size, &options.pad_to)?;
         
-        // Create container: magic(4) + header_size(4) + header + model + compressed_data
+        // Create container: magic(4) + header_size(4) + header + model + compressed_data + newline record + dictionary + padding
         let mut container = Vec::new();
         container.extend_from_slice(Self::MAGIC.as_bytes());
         container.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
         container.extend_from_slice(&header_json);
         container.extend_from_slice(&model_data);
         container.extend_from_slice(&compressed_data);
+        container.extend_from_slice(&newline_record);
+        container.extend_from_slice(&dictionary_data);
+        container.resize(container.len() + header.padding as usize, 0);
+        trace_event!(
+            method = %header.compression_method,
+            chunks = header.chunks.len(),
+            model_bytes = model_data.len(),
+            bytes_out = container.len(),
+            "encoded TCF",
+        );
 
-        Ok(container)
+        Ok((TcfEncoded { data: container, warnings }, trace))
     }
 
-    /// Decode TCF format to text
-    pub fn decode(tcf_data: &[u8]) -> Result<String> {
-        if tcf_data.len() < 8 {
-            anyhow::bail!("Invalid TCF file: too small");
+    /// Serialize `header` for a file with `body_size` bytes after it, setting
+    /// its padding so the whole file lands on a size `policy` allows
+    ///
+    /// Recording the padding lengthens the header by an amount that depends
+    /// on its digit count, so each count is tried in turn; if none lands
+    /// exactly, the next allowed size up is.
+    pub(super) fn padded_header(header: &mut TcfHeader, body_size: u64, policy: &PaddingPolicy) -> Result<Vec<u8>> {
+        let file_size = |header: &mut TcfHeader, padding: u64| -> Result<(Vec<u8>, u64)> {
+            header.padding = padding;
+            let header_json = serde_json::to_vec(&*header).context("Failed to serialize TCF header")?;
+            let size = 8 + header_json.len() as u64 + body_size;
+            Ok((header_json, size))
+        };
+        let (header_json, unpadded) = file_size(header, 0)?;
+        let mut target = policy.padded_size(unpadded)?;
+        if target == unpadded {
+            return Ok(header_json);
+        }
+        loop {
+            for digits in 1..=20 {
+                let (_, size) = file_size(header, 10u64.pow(digits - 1))?;
+                let Some(padding) = target.checked_sub(size) else { break };
+                if padding > 0 && padding.ilog10() + 1 == digits {
+                    return Ok(file_size(header, padding)?.0);
+                }
+            }
+            target = policy.padded_size(target + 1)?;
         }
+    }
+
+    /// The method every chunk was coded with, `None` when they differ
+    pub(super) fn chunked_method(chunks: &[TcfChunk], requested: Option<TcfMethod>) -> Result<Option<TcfMethod>> {
+        let mut methods = chunks.iter().map(|chunk| chunk.compression_method.as_str());
+        Ok(match methods.next() {
+            Some(first) if methods.all(|method| method == first) => Some(first.parse()?),
+            Some(_) => None,
+            None => Some(requested.unwrap_or(TcfMethod::Stored)),
+        })
+    }
 
-        // Read magic
-        let magic = std::str::from_utf8(&tcf_data[0..4])
-            .context("Invalid TCF magic")?;
-        if magic != Self::MAGIC {
-            anyhow::bail!("Invalid TCF magic number: expected {}, got {}", Self::MAGIC, magic);
+    /// Header flags and model parameters for text coded as one payload with
+    /// `method`, or as `chunks`
+    pub(super) fn coding_flags(method: Option<TcfMethod>, chunks: Option<&[TcfChunk]>, tokenizer: &dyn Tokenizer, max_order: u8) -> (u32, ModelParams) {
+        let uses = |wanted: TcfMethod| match chunks {
+            None => method == Some(wanted),
+            Some(chunks) => chunks.iter().any(|chunk| chunk.compression_method == wanted.as_str()),
+        };
+        // Only the arithmetic coder carries a model
+        let arithmetic = uses(TcfMethod::Arithmetic);
+        let model_params = if arithmetic {
+            ModelParams { tokenizer_id: tokenizer.id().to_string(), max_order }
+        } else {
+            ModelParams::default()
+        };
+        let mut flags = TcfFlags::UNICODE_NORMALIZED;
+        if arithmetic {
+            flags |= TcfFlags::ADAPTIVE_MODEL | TcfFlags::COMPACT_MODEL;
+        }
+        if chunks.is_some() {
+            flags |= TcfFlags::CHUNKED;
         }
+        if method == Some(TcfMethod::FrontCoding) || uses(TcfMethod::FrontCoding) {
+            flags |= TcfFlags::FRONT_CODED;
+        }
+        (flags, model_params)
+    }
 
-        // Read header size
-        let header_size = u32::from_le_bytes([
-            tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]
-        ]) as usize;
+    /// Reject a context model order the coder can't use
+    pub(super) fn check_max_order(max_order: u8, tokenizer: &dyn Tokenizer) -> Result<()> {
+        if max_order as usize > MAX_MODEL_ORDER {
+            anyhow::bail!("Model order {} is above the maximum of {}", max_order, MAX_MODEL_ORDER);
+        }
+        if max_order > 0 && tokenizer.id() != ByteTokenizer::ID {
+            anyhow::bail!("Context modeling needs the byte tokenizer, not {}", tokenizer.id());
+        }
+        Ok(())
+    }
 
-        if tcf_data.len() < 8 + header_size {
-            anyhow::bail!("Invalid TCF file: header size mismatch");
+    /// Warn if `escaped` of the `total` bytes in JSON strings are escapes
+    pub(super) fn warn_escape_heavy(escaped: usize, total: usize, warnings: &mut Vec<CodecWarning>) {
+        let fraction = if total == 0 { 0.0 } else { escaped as f64 / total as f64 };
+        if fraction > Self::ESCAPE_HEAVY_FRACTION {
+            warnings.push(CodecWarning::EscapeHeavy { fraction });
         }
+    }
 
-        // Parse header
-        let header_data = &tcf_data[8..8 + header_size];
-        let header: TcfHeader = serde_json::from_slice(header_data)
-            .context("Failed to parse TCF header")?;
+    /// Guess from magic bytes and byte entropy whether `data` is worth coding
+    pub fn estimate_compressibility(data: &[u8]) -> CompressibilityHint {
+        sniff::estimate_compressibility(data)
+    }
+
+    /// Code the payload with the requested method, or keep the smallest candidate
+    ///
+    /// Warnings from coding candidates that lose are dropped.
+    pub(super) fn code_text(
+        text: &str,
+        method: Option<TcfMethod>,
+        tokenizer: &dyn Tokenizer,
+        max_order: u8,
+        warnings: &mut Vec<CodecWarning>,
+        trace: &mut ExplainTrace,
+    ) -> Result<(TcfMethod, Vec<u8>, Vec<u8>)> {
+        let hint = || Self::estimate_compressibility(text.as_bytes());
+        match method {
+            Some(method) => {
+                trace.chosen("tcf.method", method, "requested");
+                let (model_data, compressed_data) = Self::encode_payload(method, tokenizer, max_order, text, warnings)?;
+                Ok((method, model_data, compressed_data))
+            }
+            None if hint().is_incompressible() => {
+                let reason = hint();
+                if let CompressibilityHint::HighEntropy { bits_per_byte } = reason {
+                    trace.threshold("tcf.sniff", "bits_per_byte", bits_per_byte, sniff::HIGH_ENTROPY_BITS);
+                }
+                trace.chosen("tcf.method", TcfMethod::Stored, format!("input looks {}", reason));
+                CodecWarning::add(warnings, CodecWarning::StoredFallback { reason });
+                Ok((TcfMethod::Stored, Vec::new(), text.as_bytes().to_vec()))
+            }
+            None => {
+                // Methods with a cheap size estimate are only coded once they
+                // could still win; estimates round down so they undercut the real size
+                type Coded = (Vec<u8>, Vec<u8>, Vec<CodecWarning>);
+                let code = |method| -> Result<Coded> {
+                    let mut warnings = Vec::new();
+                    let (model_data, compressed_data) = Self::encode_payload(method, tokenizer, max_order, text, &mut warnings)?;
+                    Ok((model_data, compressed_data, warnings))
+                };
+                let mut candidates = Vec::new();
+                let mut methods = TcfMethod::available();
+                if is_sorted_lines(text) {
+                    methods.push(TcfMethod::FrontCoding);
+                }
+                for method in methods {
+                    match Self::estimate_payload_size(method, tokenizer, max_order, text) {
+                        Some(size) => {
+                            trace.candidate("tcf.method", method, "estimated_bytes", size as f64);
+                            candidates.push((size, method, None));
+                        }
+                        None => {
+                            let coded = code(method)?;
+                            let size = coded.0.len() + coded.1.len();
+                            trace.candidate("tcf.method", method, "coded_bytes", size as f64);
+                            candidates.push((size, method, Some(coded)));
+                        }
+                    }
+                }
+                let considered = candidates.len();
+                candidates.sort_by_key(|&(size, _, _)| size);
 
-        // Validate version
-        if header.version != Self::VERSION {
-            anyhow::bail!("Unsupported TCF version: {}", header.version);
+                let mut best: Option<(TcfMethod, Coded)> = None;
+                for (size, method, coded) in candidates {
+                    if best.as_ref().is_some_and(|(_, (m, c, _))| m.len() + c.len() <= size) {
+                        break;
+                    }
+                    let coded = match coded {
+                        Some(coded) => coded,
+                        None => {
+                            let coded = code(method)?;
+                            trace.candidate("tcf.method", method, "coded_bytes", (coded.0.len() + coded.1.len()) as f64);
+                            coded
+                        }
+                    };
+                    let size = coded.0.len() + coded.1.len();
+                    if best.as_ref().is_none_or(|(_, (m, c, _))| size < m.len() + c.len()) {
+                        best = Some((method, coded));
+                    }
+                }
+                let (method, (model_data, compressed_data, coded_warnings)) = best.context("No TCF compression method available")?;
+                trace.chosen("tcf.method", method, format!("smallest of {} candidates", considered));
+                for warning in coded_warnings {
+                    CodecWarning::add(warnings, warning);
+                }
+                Ok((method, model_data, compressed_data))
+            }
         }
+    }
 
-        // Read model and compressed data
-        let model_start = 8 + header_size;
-        let model_end = model_start + header.model_size as usize;
-        let compressed_start = model_end;
+    /// Code each chunk of `text` on its own, returning the index and the
+    /// concatenated chunk data
+    ///
+    /// A chunk whose text matches an earlier chunk's becomes a reference to
+    /// it and stores no data.
+    fn code_chunks(
+        text: &str,
+        strategy: ChunkStrategy,
+        method: Option<TcfMethod>,
+        tokenizer: &dyn Tokenizer,
+        max_order: u8,
+        warnings: &mut Vec<CodecWarning>,
+        trace: &mut ExplainTrace,
+    ) -> Result<(Vec<TcfChunk>, Vec<u8>)> {
+        phase!("tcf.chunk", strategy = ?strategy);
+        let mut chunks: Vec<TcfChunk> = Vec::new();
+        let mut data = Vec::new();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+
+        for range in strategy.split(text) {
+            let chunk_text = &text[range.clone()];
+            let first_record = strategy.first_record(text.as_bytes(), range.clone()).map(|offset| offset as u64);
+            if let Some(&original) = seen.get(chunk_text) {
+                trace.chosen("tcf.chunk", "reference", format!("chunk {} repeats chunk {}", chunks.len(), original));
+                chunks.push(TcfChunk {
+                    text_offset: range.start as u64,
+                    data_offset: 0,
+                    model_size: 0,
+                    compressed_size: 0,
+                    first_record,
+                    chunk_type: ChunkType::Reference(original as u64),
+                    ..chunks[original].clone()
+                });
+                continue;
+            }
+            seen.insert(chunk_text, chunks.len());
 
-        if tcf_data.len() < compressed_start {
-            anyhow::bail!("Invalid TCF file: insufficient data");
+            trace.chosen("tcf.chunk", "data", format!("chunk {} is bytes {}..{}", chunks.len(), range.start, range.end));
+            let (method, model_data, compressed_data) = Self::code_text(chunk_text, method, tokenizer, max_order, warnings, trace)?;
+            chunks.push(TcfChunk {
+                text_offset: range.start as u64,
+                original_size: range.len() as u64,
+                data_offset: data.len() as u64,
+                model_size: model_data.len() as u32,
+                compressed_size: compressed_data.len() as u64,
+                compression_method: method.as_str().to_string(),
+                first_record,
+                crc32: crc32fast::hash(chunk_text.as_bytes()),
+                chunk_type: ChunkType::Data,
+            });
+            data.extend_from_slice(&model_data);
+            data.extend_from_slice(&compressed_data);
         }
 
-        // Deserialize frequency model
-        let model_data = &tcf_data[model_start..model_end];
-        let model = FrequencyModel::deserialize(model_data)
-            .map_err(|e| anyhow::anyhow!("Failed to deserialize frequency model: {}", e))?;
+        trace_event!(
+            chunks = chunks.len(),
+            references = chunks.iter().filter(|chunk| !chunk.chunk_type.is_data()).count(),
+            "coded chunks"
+        );
+        Ok((chunks, data))
+    }
 
-        // Decode compressed data
-        let compressed_data = tcf_data[compressed_start..].to_vec();
-        let mut decoder = ArithmeticDecoder::new(compressed_data);
-        let mut decoded_bytes = Vec::new();
+    pub(super) fn tokenizer(id: &str) -> Result<Box<dyn Tokenizer>> {
+        tokenizer_by_id(id).ok_or_else(|| TcfError::UnknownTokenizer(id.to_string()).into())
+    }
 
-        for _ in 0..header.original_size {
-            let value = decoder.get_symbol_value(model.total_frequency());
-            if let Some((symbol, low, high)) = model.get_range_from_value(value) {
-                decoded_bytes.push(symbol);
-                decoder.decode_symbol(low, high, model.total_frequency());
-            } else {
-                anyhow::bail!("Failed to decode symbol at position {}", decoded_bytes.len());
+    /// Code `text` with one method, returning the model section and the payload
+    fn encode_payload(
+        method: TcfMethod,
+        tokenizer: &dyn Tokenizer,
+        max_order: u8,
+        text: &str,
+        warnings: &mut Vec<CodecWarning>,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        phase!("tcf.entropy", method = %method);
+        let data = text.as_bytes();
+        match method {
+            TcfMethod::Arithmetic if tokenizer.id() != ByteTokenizer::ID => Self::encode_tokens(tokenizer, text, warnings),
+            TcfMethod::Arithmetic if max_order > 0 => {
+                // The model adapts as it goes, so there is none to store
+                let mut model = ContextModel::new(max_order as usize)?;
+                let mut encoder = ArithmeticCoder::new();
+                for &byte in data {
+                    model.encode(&mut encoder, byte)?;
+                }
+                Ok((Vec::new(), encoder.finish()))
+            }
+            TcfMethod::Arithmetic => {
+                // Build adaptive frequency model
+                let mut model = FrequencyModel::new();
+                model.build_from_data(data);
+                if model.total_frequency() < data.len() as u64 {
+                    CodecWarning::add(warnings, CodecWarning::ModelRescaled { models: 1 });
+                }
+
+                // Encode using arithmetic coding
+                let mut encoder = ArithmeticCoder::new();
+                for &byte in data {
+                    if let Some((low, high)) = model.get_symbol_range(byte) {
+                        encoder.encode_symbol(low, high, model.total_frequency())?;
+                    }
+                }
+                Ok((model.to_compact_bytes(), encoder.finish()))
             }
+            TcfMethod::Gzip => Ok((Vec::new(), Self::gzip(data)?)),
+            TcfMethod::FrontCoding => Ok((Vec::new(), Self::gzip(&front_encode(text)?)?)),
+            #[cfg(feature = "zstd")]
+            TcfMethod::Zstd => {
+                let payload = zstd::stream::encode_all(data, 19).context("Failed to zstd-compress TCF payload")?;
+                Ok((Vec::new(), payload))
+            }
+            TcfMethod::Stored => Ok((Vec::new(), data.to_vec())),
+            #[cfg(not(feature = "zstd"))]
+            TcfMethod::Zstd => anyhow::bail!("Unsupported TCF compression method: zstd (built without the 'zstd' feature)"),
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data)?;
+        encoder.finish().context("Failed to gzip TCF payload")
+    }
+
+    /// Lower bound on what `encode_payload` would produce, where one is
+    /// cheaper than coding
+    fn estimate_payload_size(method: TcfMethod, tokenizer: &dyn Tokenizer, max_order: u8, text: &str) -> Option<usize> {
+        match method {
+            TcfMethod::Arithmetic if tokenizer.id() == ByteTokenizer::ID && max_order == 0 => {
+                let mut model = FrequencyModel::new();
+                model.build_from_data(text.as_bytes());
+                let bits = CostEstimator::new(&model).estimate_bits(text.bytes());
+                Some(model.to_compact_bytes().len() + (bits / 8.0) as usize)
+            }
+            TcfMethod::Stored => Some(text.len()),
+            _ => None,
+        }
+    }
+
+    /// Decode TCF format to text
+    pub fn decode(tcf_data: &[u8]) -> Result<String> {
+        Self::decode_with(tcf_data, None)
+    }
+
+    /// Like `decode`, for files coded with `dictionary` that don't carry it
+    pub fn decode_with_dictionary(tcf_data: &[u8], dictionary: &Dictionary) -> Result<String> {
+        Self::decode_with(tcf_data, Some(dictionary))
+    }
+
+    /// Like `decode`, fetching the dictionary of a file that doesn't carry
+    /// it from `resolver` by the hash its header records
+    pub fn decode_with_resolver(tcf_data: &[u8], resolver: &dyn DictionaryResolver) -> Result<String> {
+        let header = Self::parse_header(tcf_data)?;
+        match Self::resolve_dictionary(&header, resolver)? {
+            Some(dictionary) => Self::decode_with(tcf_data, Some(&dictionary)),
+            None => Self::decode(tcf_data),
         }
+    }
+
+    /// The dictionary a file coded without its own needs, from `resolver`;
+    /// `None` for files that need none or carry theirs
+    ///
+    /// Fails with `TcfError::MissingDictionary` if `resolver` doesn't have it.
+    pub fn resolve_dictionary(header: &TcfHeader, resolver: &dyn DictionaryResolver) -> Result<Option<Dictionary>> {
+        let Some(record) = &header.dictionary else {
+            return Ok(None);
+        };
+        if header.chunks.iter().any(|chunk| chunk.chunk_type == ChunkType::Dictionary) {
+            return Ok(None);
+        }
+        let dictionary = resolver.resolve(&record.hash)
+            .with_context(|| format!("Failed to resolve TCF dictionary {}", record.hash))?
+            .ok_or_else(|| TcfError::MissingDictionary(record.hash.clone()))?;
+        Ok(Some(dictionary))
+    }
+
+    fn decode_with(tcf_data: &[u8], dictionary: Option<&Dictionary>) -> Result<String> {
+        phase!("tcf.decode", bytes_in = tcf_data.len());
+        let header = Self::parse_header(tcf_data)?;
+        Self::check_readable(&header)?;
+
+        // Read model and compressed data
+        let header_size = u32::from_le_bytes([
+            tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]
+        ]) as usize;
+        let model_start = 8 + header_size;
+        let decoded_bytes = if header.flags & TcfFlags::CHUNKED != 0 {
+            let original_size = header.original_size;
+            Self::chunk_decoder(tcf_data, header.clone())?.read(0..original_size)?
+        } else {
+            Self::decode_unchunked(&header, &tcf_data[model_start..], dictionary)?
+        };
 
         // Verify checksum
         let mut hasher = Sha256::new();
@@ -160,10 +910,11 @@ impl TcfCodec {
         let actual_checksum = format!("{:x}", hasher.finalize());
         
         if actual_checksum != header.checksum {
-            anyhow::bail!("TCF checksum mismatch: expected {}, got {}", 
-                header.checksum, actual_checksum);
+            return Err(TcfError::ChecksumMismatch { expected: header.checksum, actual: actual_checksum }.into());
         }
 
+        trace_event!(method = %header.compression_method, bytes_out = decoded_bytes.len(), "decoded TCF");
+
         // Convert to string
         let decoded_text = String::from_utf8(decoded_bytes)
             .context("Invalid UTF-8 in decoded data")?;
@@ -171,73 +922,929 @@ impl TcfCodec {
         Ok(decoded_text)
     }
 
-    /// Get compression statistics
-    pub fn get_stats(original_text: &str, tcf_data: &[u8]) -> TextCompressionStats {
-        let original_size = original_text.as_bytes().len();
-        let compressed_size = tcf_data.len();
-        let compression_ratio = original_size as f64 / compressed_size as f64;
-        let savings_percent = ((original_size - compressed_size) as f64 / original_size as f64) * 100.0;
+    /// Like `decode`, but refusing files whose text is over `runtime`'s
+    /// memory limit before decoding any of it
+    pub fn decode_with_runtime(tcf_data: &[u8], runtime: &RuntimeConfig) -> Result<String> {
+        let header = Self::parse_header(tcf_data)?;
+        runtime.check_memory("TCF decode", header.original_size)?;
+        Self::decode(tcf_data)
+    }
 
-        TextCompressionStats {
-            original_size,
-            compressed_size,
-            compression_ratio,
-            savings_percent,
+    /// Decode TCF format one chunk at a time
+    ///
+    /// Yields the text of each chunk in order, or the whole text at once for
+    /// unchunked files, so only one chunk is held at a time. Each piece is
+    /// checked before it is yielded, and the file's SHA-256 after the last
+    /// one, a mismatch being yielded as a final error; see
+    /// `TcfDecodeStream::verify`. Pieces may end mid-character, so they are
+    /// bytes.
+    pub fn decode_stream(tcf_data: &[u8]) -> Result<TcfDecodeStream<'_>> {
+        Self::stream_with(tcf_data, None)
+    }
+
+    /// Like `decode_stream`, for files coded with `dictionary` that don't
+    /// carry it
+    pub fn decode_stream_with_dictionary<'a>(tcf_data: &'a [u8], dictionary: &'a Dictionary) -> Result<TcfDecodeStream<'a>> {
+        Self::stream_with(tcf_data, Some(dictionary))
+    }
+
+    fn stream_with<'a>(tcf_data: &'a [u8], dictionary: Option<&'a Dictionary>) -> Result<TcfDecodeStream<'a>> {
+        let header = Self::parse_header(tcf_data)?;
+        let expected = header.checksum.clone();
+        let source = if header.flags & TcfFlags::CHUNKED != 0 {
+            StreamSource::Chunked(Self::chunk_decoder(tcf_data, header)?, 0)
+        } else {
+            Self::check_readable(&header)?;
+            let header_size = u32::from_le_bytes([tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]]) as usize;
+            StreamSource::Unchunked(Some((header, &tcf_data[8 + header_size..], dictionary)))
+        };
+        Ok(TcfDecodeStream { source, verifier: StreamVerifier::new(expected), finished: false })
+    }
+
+    /// Decode the model and payload following the header of an unchunked
+    /// file, expanding any dictionary codes and putting back any CRs its
+    /// newline record lists
+    ///
+    /// `dictionary` is only used if the file doesn't carry its own.
+    pub(super) fn decode_unchunked(header: &TcfHeader, data: &[u8], dictionary: Option<&Dictionary>) -> Result<Vec<u8>> {
+        let method = Self::supported_method(&header.compression_method)?;
+        let model_end = header.model_size as u64;
+        TcfError::check_length("model", model_end, data)?;
+        let payload_end = model_end.saturating_add(header.compressed_size);
+        TcfError::check_length("payload", payload_end, data)?;
+        let (model_end, payload_end) = (model_end as usize, payload_end as usize);
+
+        // Each step undoes one encode step, so each has its own size
+        let text_size = header.newlines.as_ref().map_or(header.original_size, |newlines| newlines.coded_size);
+        let payload_size = header.dictionary.as_ref().map_or(text_size, |record| record.coded_size);
+        let coded_header = TcfHeader { original_size: payload_size, newlines: None, ..header.clone() };
+        let mut text = Self::decode_payload(method, &coded_header, &data[..model_end], &data[model_end..payload_end])?;
+        if let Some(record) = &header.dictionary {
+            let dictionary = Self::file_dictionary(header, record, data, dictionary)?;
+            text = dictionary.expand(&text, text_size).context("Invalid TCF dictionary coding")?;
         }
+        let Some(newlines) = &header.newlines else {
+            return Ok(text);
+        };
+
+        let record_end = (payload_end as u64).saturating_add(newlines.record_size);
+        TcfError::check_length("newline record", record_end, data)?;
+        let record = &data[payload_end..record_end as usize];
+        restore_crlf(&text, record, header.original_size).context("Invalid TCF newline record")
     }
 
-    /// Parse TCF header without full decoding
-    pub fn parse_header(tcf_data: &[u8]) -> Result<TcfHeader> {
-        if tcf_data.len() < 8 {
-            anyhow::bail!("Invalid TCF file: too small");
+    /// The dictionary an unchunked file's text was substituted with: the
+    /// one in its dictionary chunk, or else `external` if that is the one
+    /// its `record` names
+    fn file_dictionary<'d>(header: &TcfHeader, record: &DictionaryRecord, data: &[u8], external: Option<&'d Dictionary>) -> Result<Cow<'d, Dictionary>> {
+        let dictionary = match header.chunks.iter().position(|chunk| chunk.chunk_type == ChunkType::Dictionary) {
+            Some(index) => {
+                let chunk = &header.chunks[index];
+                let start = usize::try_from(chunk.data_offset)?;
+                let end = chunk.data_size()
+                    .and_then(|size| start.checked_add(size))
+                    .context("TCF dictionary chunk is too large")?;
+                TcfError::check_length("dictionary", end as u64, data)?;
+                let method = Self::supported_method(&chunk.compression_method)?;
+                let chunk_header = TcfHeader {
+                    original_size: chunk.original_size,
+                    compressed_size: chunk.compressed_size,
+                    model_size: chunk.model_size,
+                    ..header.clone()
+                };
+                let model_end = start + chunk.model_size as usize;
+                let bytes = Self::decode_payload(method, &chunk_header, &data[start..model_end], &data[model_end..end])?;
+                if crc32fast::hash(&bytes) != chunk.crc32 {
+                    return Err(TcfError::ChunkChecksumMismatch(index).into());
+                }
+                Cow::Owned(Dictionary::from_bytes(&bytes).context("Invalid TCF dictionary chunk")?)
+            }
+            None => Cow::Borrowed(external.ok_or_else(|| TcfError::MissingDictionary(record.hash.clone()))?),
+        };
+        if dictionary.short_hash() != record.hash {
+            return Err(TcfError::DictionaryMismatch { expected: record.hash.clone(), actual: dictionary.short_hash() }.into());
+        }
+        Ok(dictionary)
+    }
+
+    /// Decode bytes `range` of the original text
+    ///
+    /// Chunked files only decode the chunks the range overlaps, each checked
+    /// against its CRC-32; other files are decoded whole. The range may split
+    /// a UTF-8 character, so the result is bytes.
+    pub fn decode_range(tcf_data: &[u8], range: Range<u64>) -> Result<Vec<u8>> {
+        let header = Self::parse_header(tcf_data)?;
+        if range.start > range.end || range.end > header.original_size {
+            anyhow::bail!("TCF range {}..{} is outside the {} byte text", range.start, range.end, header.original_size);
+        }
+        if header.flags & TcfFlags::CHUNKED == 0 {
+            let text = Self::decode(tcf_data)?;
+            return Ok(text.as_bytes()[range.start as usize..range.end as usize].to_vec());
         }
 
-        let magic = std::str::from_utf8(&tcf_data[0..4])
-            .context("Invalid TCF magic")?;
-        if magic != Self::MAGIC {
-            anyhow::bail!("Invalid TCF magic number");
+        Self::chunk_decoder(tcf_data, header)?.read(range)
+    }
+
+    /// Decode the whole records that start within `range` of the original text
+    ///
+    /// Needs a file chunked on records. The result runs from the first record
+    /// start at or after `range.start` to the first one at or after
+    /// `range.end`, so a record straddling either end is included only if it
+    /// starts inside the range.
+    pub fn decode_records(tcf_data: &[u8], range: Range<u64>) -> Result<String> {
+        let header = Self::parse_header(tcf_data)?;
+        if header.flags & TcfFlags::CHUNKED == 0 || !header.chunking.is_some_and(|strategy| strategy.has_records()) {
+            anyhow::bail!("TCF file has no record index; encode it with a delimiter or blank-line chunking strategy");
         }
+        if range.start > range.end || range.end > header.original_size {
+            anyhow::bail!("TCF range {}..{} is outside the {} byte text", range.start, range.end, header.original_size);
+        }
+
+        let mut chunks = Self::chunk_decoder(tcf_data, header)?;
+        let start = chunks.next_record_start(range.start)?;
+        let end = chunks.next_record_start(range.end)?.max(start);
+        String::from_utf8(chunks.read(start..end)?)
+            .context("Invalid UTF-8 in decoded TCF records")
+    }
 
+    pub(super) fn chunk_decoder(tcf_data: &[u8], header: TcfHeader) -> Result<ChunkDecoder<'_>> {
+        Self::check_readable(&header)?;
+        Self::check_chunk_offsets(&header)?;
         let header_size = u32::from_le_bytes([
             tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]
         ]) as usize;
+        Ok(ChunkDecoder::new(header, &tcf_data[8 + header_size..]))
+    }
 
-        if tcf_data.len() < 8 + header_size {
-            anyhow::bail!("Invalid TCF file: header size mismatch");
+    /// Reject a chunk index whose data offsets don't match the layout
+    /// encoders write
+    ///
+    /// Chunks holding data sit back to back in index order from the end of
+    /// the header, filling the `compressed_size` bytes it declares exactly;
+    /// references hold none and have offset zero.
+    pub(super) fn check_chunk_offsets(header: &TcfHeader) -> Result<(), TcfError> {
+        let mut expected = 0u64;
+        for (index, chunk) in header.chunks.iter().enumerate() {
+            if let ChunkType::Reference(_) = chunk.chunk_type {
+                if chunk.data_offset != 0 {
+                    return Err(TcfError::ChunkOffsetMismatch { index, expected: 0, actual: chunk.data_offset });
+                }
+                continue;
+            }
+            if chunk.data_offset != expected {
+                return Err(TcfError::ChunkOffsetMismatch { index, expected, actual: chunk.data_offset });
+            }
+            expected = expected.saturating_add(chunk.model_size as u64).saturating_add(chunk.compressed_size);
         }
+        if expected != header.compressed_size {
+            return Err(TcfError::ChunkOffsetMismatch { index: header.chunks.len(), expected: header.compressed_size, actual: expected });
+        }
+        Ok(())
+    }
 
-        let header_data = &tcf_data[8..8 + header_size];
-        let header: TcfHeader = serde_json::from_slice(header_data)
-            .context("Failed to parse TCF header")?;
+    /// Parse a method name, rejecting ones this build can't decode
+    pub(super) fn supported_method(name: &str) -> Result<TcfMethod> {
+        let method: TcfMethod = name.parse()?;
+        if !method.is_supported() {
+            return Err(TcfError::UnsupportedMethod { method: name.to_string(), missing_feature: true }.into());
+        }
+        Ok(method)
+    }
 
-        Ok(header)
+    /// Reject headers of a version or checksum kind this build can't read
+    pub(super) fn check_readable(header: &TcfHeader) -> Result<()> {
+        if !formats::TCF.reads(header.version) {
+            return Err(TcfError::UnsupportedVersion(header.version).into());
+        }
+        if ChecksumKind::of(&header.checksum) != ChecksumKind::Sha256 {
+            return Err(TcfError::UnsupportedChecksum(header.checksum.clone()).into());
+        }
+        Ok(())
     }
-}
 
-/// Text compression statistics
-#[derive(Debug, Clone)]
-pub struct TextCompressionStats {
-    pub original_size: usize,
-    pub compressed_size: usize,
-    pub compression_ratio: f64,
-    pub savings_percent: f64,
-}
+    /// Decode one model and payload; `header` supplies the sizes, flags and tokenizer
+    ///
+    /// Failures of the data itself come back as `TcfError::CorruptPayload`.
+    fn decode_payload(method: TcfMethod, header: &TcfHeader, model_data: &[u8], compressed_data: &[u8]) -> Result<Vec<u8>> {
+        Self::decode_method(method, header, model_data, compressed_data).map_err(|error| match error.downcast::<TcfError>() {
+            Ok(typed) => typed.into(),
+            Err(cause) => TcfError::CorruptPayload { method: method.as_str().to_string(), cause }.into(),
+        })
+    }
 
-impl std::fmt::Display for TextCompressionStats {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, 
-            "Original: {} bytes, Compressed: {} bytes, Ratio: {:.2}:1, Savings: {:.2}%",
-            self.original_size,
-            self.compressed_size,
-            self.compression_ratio,
-            self.savings_percent
-        )
+    fn decode_method(method: TcfMethod, header: &TcfHeader, model_data: &[u8], compressed_data: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            TcfMethod::Arithmetic if header.model_params.max_order > 0 => Self::decode_context(header, compressed_data),
+            TcfMethod::Arithmetic if header.model_params.tokenizer_id != ByteTokenizer::ID => {
+                let tokenizer = Self::tokenizer(&header.model_params.tokenizer_id)?;
+                Self::decode_tokens(tokenizer.as_ref(), header, model_data, compressed_data)
+            }
+            TcfMethod::Arithmetic => Self::decode_arithmetic(header, model_data, compressed_data),
+            TcfMethod::Gzip | TcfMethod::Zstd | TcfMethod::Stored | TcfMethod::FrontCoding => {
+                Self::decode_foreign(method, header, compressed_data)
+            }
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn decode_arithmetic(header: &TcfHeader, model_data: &[u8], compressed_data: &[u8]) -> Result<Vec<u8>> {
+        // Deserialize frequency model
+        let model = if header.flags & TcfFlags::COMPACT_MODEL != 0 {
+            FrequencyModel::from_compact_bytes(model_data)
+        } else {
+            FrequencyModel::deserialize(model_data)
+        }
+        .map_err(|e| anyhow::anyhow!("Failed to deserialize frequency model: {}", e))?;
+
+        // Decode compressed data
+        let mut decoder = ArithmeticDecoder::new(compressed_data.to_vec());
+        let mut decoded_bytes = Vec::new();
+
+        for _ in 0..header.original_size {
+            let value = decoder.get_symbol_value(model.total_frequency())?;
+            if let Some((symbol, low, high)) = model.get_range_from_value(value) {
+                decoded_bytes.push(symbol);
+                decoder.decode_symbol(low, high, model.total_frequency())?;
+            } else {
+                anyhow::bail!("Failed to decode symbol at position {}", decoded_bytes.len());
+            }
+        }
+
+        Ok(decoded_bytes)
+    }
+
+    /// Decode bytes coded with an adaptive `ContextModel`, replaying its
+    /// updates symbol by symbol as the encoder made them
+    fn decode_context(header: &TcfHeader, compressed_data: &[u8]) -> Result<Vec<u8>> {
+        if header.model_params.tokenizer_id != ByteTokenizer::ID {
+            anyhow::bail!("TCF context model needs the byte tokenizer, not {}", header.model_params.tokenizer_id);
+        }
+        // The header's size is untrusted, and a run of likely bytes reads no
+        // more payload, so the payload's length is what bounds the loop
+        let most = (compressed_data.len() as u64).saturating_mul(MAX_BYTES_PER_CODED_BYTE);
+        if header.original_size > most {
+            anyhow::bail!("TCF header claims {} bytes, more than a {} byte context-coded payload can hold", header.original_size, compressed_data.len());
+        }
+        let mut model = ContextModel::new(header.model_params.max_order as usize)?;
+        let mut decoder = ArithmeticDecoder::new(compressed_data.to_vec());
+        // Only reserve what typical text codes to, so a forged size costs
+        // memory as it's decoded rather than all up front
+        let reserve = header.original_size.min((compressed_data.len() as u64).saturating_mul(8));
+        let mut decoded_bytes = Vec::with_capacity(reserve as usize);
+        for _ in 0..header.original_size {
+            decoded_bytes.push(model.decode(&mut decoder)?);
+        }
+        Ok(decoded_bytes)
+    }
+
+    /// Call `f` with each token of `text`, in order
+    ///
+    /// Token lists take several times the memory of their text, so a
+    /// single-line input is never tokenized whole. Each window of about
+    /// `TOKEN_WINDOW` bytes drops its last token, which the window edge may
+    /// have cut short, and the next window starts where it did; the tokens
+    /// are those of the whole text. A window grows until it holds a whole
+    /// token, so memory follows the window or the longest token.
+    fn for_each_token<'a>(tokenizer: &dyn Tokenizer, text: &'a str, mut f: impl FnMut(Token<'a>) -> Result<()>) -> Result<()> {
+        let mut start = 0;
+        let mut window = Self::TOKEN_WINDOW;
+        while start < text.len() {
+            let mut end = (start + window).min(text.len());
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            let mut tokens = tokenizer.tokenize(&text[start..end]);
+            if end < text.len() {
+                if tokens.len() < 2 {
+                    window *= 2;
+                    continue;
+                }
+                tokens.pop();
+            }
+            for token in tokens {
+                start += token.text.len();
+                f(token)?;
+            }
+            window = Self::TOKEN_WINDOW;
+        }
+        Ok(())
+    }
+
+    /// Arithmetic-code a token stream
+    ///
+    /// Each token is its class, coded with a model conditioned on the previous
+    /// token's class, followed by its bytes and a 0xFF terminator (a byte UTF-8
+    /// never uses) coded with that class's own model. The model section holds
+    /// the class-transition models and then the per-class byte models, each as
+    /// a u32 length followed by its compact form.
+    fn encode_tokens(tokenizer: &dyn Tokenizer, text: &str, warnings: &mut Vec<CodecWarning>) -> Result<(Vec<u8>, Vec<u8>)> {
+        let classes = tokenizer.classes();
+
+        // Count the symbols each model will code, then build the models
+        let mut transition_counts = vec![[0u64; 256]; classes.len() + 1];
+        let mut class_counts = vec![[0u64; 256]; classes.len()];
+        let class_index = |token: &Token| {
+            classes.iter().position(|&class| class == token.class)
+                .with_context(|| format!("{} tokenizer emitted undeclared class {:?}", tokenizer.id(), token.class))
+        };
+        let mut previous = classes.len();
+        Self::for_each_token(tokenizer, text, |token| {
+            let (index, bytes) = (class_index(&token)?, token.text.as_bytes());
+            transition_counts[previous][index] += 1;
+            for &byte in bytes {
+                class_counts[index][byte as usize] += 1;
+            }
+            class_counts[index][Self::TOKEN_END as usize] += 1;
+            previous = index;
+            Ok(())
+        })?;
+        let build = |counts: &[u64; 256]| {
+            let mut model = FrequencyModel::new();
+            model.build_from_counts(counts);
+            model
+        };
+        let transition_models: Vec<FrequencyModel> = transition_counts.iter().map(build).collect();
+        let class_models: Vec<FrequencyModel> = class_counts.iter().map(build).collect();
+        let rescaled = transition_counts.iter().chain(&class_counts).zip(transition_models.iter().chain(&class_models))
+            .filter(|(counts, model)| model.total_frequency() < counts.iter().sum())
+            .count();
+        if rescaled > 0 {
+            CodecWarning::add(warnings, CodecWarning::ModelRescaled { models: rescaled });
+        }
+
+        let mut model_data = Vec::new();
+        for model in transition_models.iter().chain(&class_models) {
+            let bytes = model.to_compact_bytes();
+            model_data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            model_data.extend_from_slice(&bytes);
+        }
+
+        let mut encoder = ArithmeticCoder::new();
+        let mut encode = |model: &FrequencyModel, symbol: u8| -> Result<()> {
+            let (low, high) = model.get_symbol_range(symbol)
+                .context("TCF token model is missing a symbol")?;
+            encoder.encode_symbol(low, high, model.total_frequency())?;
+            Ok(())
+        };
+        let mut previous = classes.len();
+        Self::for_each_token(tokenizer, text, |token| {
+            let (index, bytes) = (class_index(&token)?, token.text.as_bytes());
+            encode(&transition_models[previous], index as u8)?;
+            for &byte in bytes.iter().chain([Self::TOKEN_END].iter()) {
+                encode(&class_models[index], byte)?;
+            }
+            previous = index;
+            Ok(())
+        })?;
+
+        Ok((model_data, encoder.finish()))
+    }
+
+    fn decode_tokens(tokenizer: &dyn Tokenizer, header: &TcfHeader, model_data: &[u8], compressed_data: &[u8]) -> Result<Vec<u8>> {
+        let classes = tokenizer.classes();
+        let mut models = Vec::with_capacity(classes.len() * 2 + 1);
+        let mut position = 0;
+        for _ in 0..classes.len() * 2 + 1 {
+            let length = model_data.get(position..position + 4)
+                .context("TCF token models truncated")?;
+            let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+            let bytes = model_data.get(position + 4..position + 4 + length)
+                .context("TCF token models truncated")?;
+            models.push(FrequencyModel::from_compact_bytes(bytes)
+                .map_err(|e| anyhow::anyhow!("Failed to deserialize frequency model: {}", e))?);
+            position += 4 + length;
+        }
+        let (transition_models, class_models) = models.split_at(classes.len() + 1);
+
+        let mut decoder = ArithmeticDecoder::new(compressed_data.to_vec());
+        let mut decode = |model: &FrequencyModel| -> Result<u8> {
+            let value = decoder.get_symbol_value(model.total_frequency())?;
+            let (symbol, low, high) = model.get_range_from_value(value)
+                .context("Failed to decode TCF token symbol")?;
+            decoder.decode_symbol(low, high, model.total_frequency())?;
+            Ok(symbol)
+        };
+
+        let mut decoded_bytes = Vec::new();
+        let mut previous = classes.len();
+        while (decoded_bytes.len() as u64) < header.original_size {
+            let index = decode(&transition_models[previous])? as usize;
+            let class_model = class_models.get(index)
+                .with_context(|| format!("Invalid TCF token class {}", index))?;
+            loop {
+                let byte = decode(class_model)?;
+                if byte == Self::TOKEN_END {
+                    break;
+                }
+                if decoded_bytes.len() as u64 >= header.original_size {
+                    anyhow::bail!("TCF token stream runs past original size {}", header.original_size);
+                }
+                decoded_bytes.push(byte);
+            }
+            previous = index;
+        }
+
+        Ok(decoded_bytes)
+    }
+
+    /// Decompress a gzip, zstd, stored or front coded payload, producing at
+    /// most `original_size + 1` bytes
+    fn decode_foreign(method: TcfMethod, header: &TcfHeader, compressed_data: &[u8]) -> Result<Vec<u8>> {
+        TcfError::check_length("payload", header.compressed_size, compressed_data)?;
+        let payload = &compressed_data[..header.compressed_size as usize];
+        let limit = header.original_size.saturating_add(1);
+        let mut decoded_bytes = Vec::new();
+
+        match method {
+            TcfMethod::Gzip => {
+                GzDecoder::new(payload).take(limit).read_to_end(&mut decoded_bytes)
+                    .context("Failed to gunzip TCF payload")?;
+            }
+            TcfMethod::FrontCoding => {
+                // Front coding adds at most two bytes per line, and a line takes at least one
+                let mut coded = Vec::new();
+                GzDecoder::new(payload).take(limit.saturating_mul(3)).read_to_end(&mut coded)
+                    .context("Failed to gunzip TCF payload")?;
+                decoded_bytes = front_decode(&coded, usize::try_from(limit)?)?;
+            }
+            #[cfg(feature = "zstd")]
+            TcfMethod::Zstd => {
+                zstd::stream::read::Decoder::new(payload)?.take(limit).read_to_end(&mut decoded_bytes)
+                    .context("Failed to decompress zstd TCF payload")?;
+            }
+            TcfMethod::Stored => decoded_bytes.extend_from_slice(payload),
+            _ => anyhow::bail!("Unsupported TCF compression method: {}", method),
+        }
+
+        if decoded_bytes.len() as u64 != header.original_size {
+            anyhow::bail!("TCF payload decompressed to {} bytes, header says {}",
+                decoded_bytes.len(), header.original_size);
+        }
+        Ok(decoded_bytes)
+    }
+
+    /// Get compression statistics
+    pub fn get_stats(original_text: &str, tcf_data: &[u8]) -> TextCompressionStats {
+        let original_size = original_text.as_bytes().len();
+        let compressed_size = tcf_data.len();
+        let compression_ratio = if compressed_size > 0 {
+            original_size as f64 / compressed_size as f64
+        } else {
+            0.0
+        };
+        // Small inputs can grow, so compute savings in floating point
+        let savings_percent = if original_size > 0 {
+            ((original_size as f64 - compressed_size as f64) / original_size as f64) * 100.0
+        } else {
+            0.0
+        };
+
+        let padding_size = Self::parse_header(tcf_data).map_or(0, |header| header.padding as usize).min(compressed_size);
+
+        TextCompressionStats {
+            original_size,
+            compressed_size,
+            padding_size,
+            compression_ratio,
+            savings_percent,
+        }
+    }
+
+    /// Parse TCF header without full decoding
+    pub fn parse_header(tcf_data: &[u8]) -> Result<TcfHeader> {
+        if tcf_data.starts_with(formats::TCF1.magic.as_bytes()) {
+            return Err(TcfError::LegacyFormat.into());
+        }
+        let magic_len = tcf_data.len().min(4);
+        if tcf_data[..magic_len] != Self::MAGIC.as_bytes()[..magic_len] {
+            return Err(TcfError::InvalidMagic.into());
+        }
+        TcfError::check_length("header length", 8, tcf_data)?;
+
+        let header_size = u32::from_le_bytes([
+            tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]
+        ]) as usize;
+        TcfError::check_length("header", 8 + header_size as u64, tcf_data)?;
+
+        let header_data = &tcf_data[8..8 + header_size];
+        let header: TcfHeader = serde_json::from_slice(header_data)
+            .context("Failed to parse TCF header")?;
+
+        Ok(header)
+    }
+
+    /// Read the size, method, flags and checksum from the first bytes of a file
+    ///
+    /// Needs at most `PEEK_LIMIT` (4 KB) of prefix; a shorter prefix than
+    /// the header requires gives `NeedMoreData` with the length to fetch.
+    /// The model and payload are never looked at.
+    pub fn peek(prefix: &[u8]) -> Result<PeekResult<TcfPeek>> {
+        peek::peek_json_header(prefix, Self::MAGIC, "TCF")
+    }
+
+    /// Describe every region of a TCF file without decoding the payload
+    pub fn parse_layout(tcf_data: &[u8]) -> Result<TcfLayout> {
+        let header = Self::parse_header(tcf_data)?;
+        let header_size = u32::from_le_bytes([
+            tcf_data[4], tcf_data[5], tcf_data[6], tcf_data[7]
+        ]) as u64;
+
+        let mut sections = vec![
+            ("magic", 4),
+            ("header_length", 4),
+            ("header", header_size),
+            ("model", header.model_size as u64),
+            ("payload", header.compressed_size),
+        ];
+        if let Some(newlines) = &header.newlines {
+            sections.push(("newlines", newlines.record_size));
+        }
+        if let Some(chunk) = header.chunks.iter().find(|chunk| chunk.chunk_type == ChunkType::Dictionary) {
+            sections.push(("dictionary", chunk.model_size as u64 + chunk.compressed_size));
+        }
+        if header.padding > 0 {
+            sections.push(("padding", header.padding));
+        }
+        let regions = layout::split_regions(tcf_data, &sections).context("Invalid TCF layout")?;
+        let trailing_size = regions.iter()
+            .find(|region| region.name == "trailing")
+            .map_or(0, |region| region.length);
+
+        Ok(TcfLayout {
+            file_size: tcf_data.len() as u64,
+            model_size: header.model_size as u64,
+            payload_size: header.compressed_size,
+            trailing_size,
+            header,
+            regions,
+        })
+    }
+}
+
+/// What a streaming decoder checks, and when
+///
+/// A stream can't take back what it has yielded, so a check that fails
+/// after the last piece comes too late for a consumer that already acted
+/// on the earlier ones. `PerChunk` checks every piece before yielding it:
+/// chunks against the CRC-32 in the chunk table, and an unchunked file,
+/// which is a single piece, against its SHA-256. The first failure ends
+/// the stream with `TcfError::ChunkChecksumMismatch` naming the chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyMode {
+    PerChunk,
+    /// `PerChunk`, and also the SHA-256 of the whole text once the last
+    /// piece is out
+    #[default]
+    Full,
+    /// Nothing; corrupt data that still decodes is yielded as-is
+    Off,
+}
+
+/// Checks the pieces of a streamed decode as they go by under a `VerifyMode`
+pub(super) struct StreamVerifier {
+    mode: VerifyMode,
+    hasher: Sha256,
+    expected: String,
+}
+
+impl StreamVerifier {
+    pub(super) fn new(expected: String) -> Self {
+        Self { mode: VerifyMode::default(), hasher: Sha256::new(), expected }
+    }
+
+    pub(super) fn set_mode(&mut self, mode: VerifyMode) {
+        self.mode = mode;
+    }
+
+    /// Check a piece before it is yielded; `whole` when it is the entire
+    /// text of an unchunked file
+    pub(super) fn check(&mut self, bytes: &[u8], whole: bool) -> Result<()> {
+        if self.mode == VerifyMode::Off {
+            return Ok(());
+        }
+        if whole {
+            let actual = format!("{:x}", Sha256::digest(bytes));
+            if actual != self.expected {
+                return Err(TcfError::ChecksumMismatch { expected: self.expected.clone(), actual }.into());
+            }
+        }
+        if self.mode == VerifyMode::Full {
+            self.hasher.update(bytes);
+        }
+        Ok(())
+    }
+
+    /// The whole-text check after the last piece, under `Full`
+    pub(super) fn finish(&mut self) -> Option<Result<Vec<u8>>> {
+        if self.mode != VerifyMode::Full {
+            return None;
+        }
+        let actual = format!("{:x}", std::mem::take(&mut self.hasher).finalize());
+        (actual != self.expected).then(|| Err(TcfError::ChecksumMismatch {
+            expected: self.expected.clone(),
+            actual,
+        }.into()))
+    }
+}
+
+/// Iterator over the decoded pieces of a TCF file, from `TcfCodec::decode_stream`
+pub struct TcfDecodeStream<'a> {
+    source: StreamSource<'a>,
+    verifier: StreamVerifier,
+    finished: bool,
+}
+
+enum StreamSource<'a> {
+    /// Header and everything after it, until the payload is decoded
+    Unchunked(Option<(TcfHeader, &'a [u8], Option<&'a Dictionary>)>),
+    /// Chunk decoder and index of the next chunk
+    Chunked(ChunkDecoder<'a>, usize),
+}
+
+impl TcfDecodeStream<'_> {
+    /// Check pieces under `mode` instead of `VerifyMode::Full`
+    pub fn verify(mut self, mode: VerifyMode) -> Self {
+        self.verifier.set_mode(mode);
+        if let StreamSource::Chunked(chunks, _) = &mut self.source {
+            chunks.check_chunks = mode != VerifyMode::Off;
+        }
+        self
+    }
+}
+
+impl Iterator for TcfDecodeStream<'_> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>>> {
+        if self.finished {
+            return None;
+        }
+        let whole = matches!(self.source, StreamSource::Unchunked(_));
+        let piece = match &mut self.source {
+            StreamSource::Unchunked(pending) => pending.take()
+                .map(|(header, data, dictionary)| TcfCodec::decode_unchunked(&header, data, dictionary)),
+            StreamSource::Chunked(chunks, next) if *next < chunks.text_chunks().len() => {
+                *next += 1;
+                Some(chunks.decode_chunk(*next - 1))
+            }
+            StreamSource::Chunked(..) => None,
+        };
+
+        match piece.map(|piece| piece.and_then(|bytes| self.verifier.check(&bytes, whole).map(|_| bytes))) {
+            Some(Ok(bytes)) => Some(Ok(bytes)),
+            Some(Err(error)) => {
+                self.finished = true;
+                Some(Err(error))
+            }
+            None => {
+                self.finished = true;
+                self.verifier.finish()
+            }
+        }
+    }
+}
+
+/// Decodes the chunks of a chunked TCF file on demand, keeping each one
+/// it has decoded
+pub(super) struct ChunkDecoder<'a> {
+    /// Header fields every chunk shares, with the index moved out
+    template: TcfHeader,
+    chunks: Vec<TcfChunk>,
+    /// Everything after the container header
+    data: &'a [u8],
+    decoded: HashMap<usize, Vec<u8>>,
+    /// Check each decoded chunk against its CRC-32
+    pub(super) check_chunks: bool,
+}
+
+impl<'a> ChunkDecoder<'a> {
+    /// Decoder of a file's chunks, where `data` is everything after its
+    /// header; empty when the data is passed to `decode_data` instead
+    pub(super) fn new(mut header: TcfHeader, data: &'a [u8]) -> Self {
+        let chunks = std::mem::take(&mut header.chunks);
+        Self { template: header, chunks, data, decoded: HashMap::new(), check_chunks: true }
+    }
+
+    pub(super) fn chunks(&self) -> &[TcfChunk] {
+        &self.chunks
+    }
+
+    /// The chunks holding text, which come before any metadata chunks
+    pub(super) fn text_chunks(&self) -> &[TcfChunk] {
+        let end = self.chunks.iter().position(|chunk| chunk.chunk_type == ChunkType::Metadata).unwrap_or(self.chunks.len());
+        &self.chunks[..end]
+    }
+
+    /// Decoded bytes of the first metadata chunk, if the file has one
+    pub(super) fn metadata(&self) -> Result<Option<Vec<u8>>> {
+        let Some(index) = self.chunks.iter().position(|chunk| chunk.chunk_type == ChunkType::Metadata) else {
+            return Ok(None);
+        };
+        self.decode_chunk(index).map(Some)
+    }
+
+    /// Index of the chunk holding text byte `offset`, or the chunk count past the end
+    fn chunk_at(&self, offset: u64) -> usize {
+        self.chunks.partition_point(|chunk| chunk.text_offset.saturating_add(chunk.original_size) <= offset)
+    }
+
+    fn chunk(&mut self, index: usize) -> Result<&[u8]> {
+        let index = self.data_index(index)?;
+        if !self.decoded.contains_key(&index) {
+            let bytes = self.decode_chunk(index)?;
+            self.decoded.insert(index, bytes);
+        }
+        Ok(&self.decoded[&index])
+    }
+
+    /// Index of the data chunk holding chunk `index`'s text
+    ///
+    /// A reference must point back at a data chunk with the same text.
+    pub(super) fn data_index(&self, index: usize) -> Result<usize> {
+        let chunk = &self.chunks[index];
+        let ChunkType::Reference(target) = chunk.chunk_type else {
+            return Ok(index);
+        };
+        let target = usize::try_from(target).ok()
+            .filter(|&target| target < index)
+            .with_context(|| format!("TCF chunk {} refers forward to chunk {}", index, target))?;
+        let original = &self.chunks[target];
+        if !original.chunk_type.is_data() || original.original_size != chunk.original_size || original.crc32 != chunk.crc32 {
+            anyhow::bail!("TCF chunk {} refers to chunk {}, which doesn't hold its text", index, target);
+        }
+        Ok(target)
+    }
+
+    fn decode_chunk(&self, index: usize) -> Result<Vec<u8>> {
+        let index = self.data_index(index)?;
+        let chunk = &self.chunks[index];
+        let start = usize::try_from(chunk.data_offset)?;
+        let end = chunk.data_size()
+            .and_then(|size| start.checked_add(size))
+            .with_context(|| format!("TCF chunk {} is too large", index))?;
+        TcfError::check_length("chunk data", end as u64, self.data)?;
+        self.decode_data(index, &self.data[start..end])
+    }
+
+    /// Decode data chunk `index` from `data`, its model followed by its payload
+    pub(super) fn decode_data(&self, index: usize, data: &[u8]) -> Result<Vec<u8>> {
+        phase!("tcf.decode_chunk", index);
+        let chunk = &self.chunks[index];
+        let method = TcfCodec::supported_method(&chunk.compression_method)?;
+        let model_end = (chunk.model_size as usize).min(data.len());
+
+        let header = TcfHeader {
+            original_size: chunk.original_size,
+            compressed_size: chunk.compressed_size,
+            model_size: chunk.model_size,
+            compression_method: chunk.compression_method.clone(),
+            ..self.template.clone()
+        };
+        let bytes = TcfCodec::decode_payload(method, &header, &data[..model_end], &data[model_end..])?;
+        if self.check_chunks && (bytes.len() as u64 != chunk.original_size || crc32fast::hash(&bytes) != chunk.crc32) {
+            return Err(TcfError::ChunkChecksumMismatch(index).into());
+        }
+        Ok(bytes)
+    }
+
+    /// Bytes `range` of the text, decoding only the chunks it overlaps
+    pub(super) fn read(&mut self, range: Range<u64>) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut index = self.chunk_at(range.start);
+        let mut position = range.start;
+
+        while position < range.end {
+            let text_offset = self.chunks.get(index)
+                .context("TCF chunk index doesn't cover the text")?
+                .text_offset;
+            let bytes = self.chunk(index)?;
+            let local_start = position.checked_sub(text_offset).context("TCF chunk index has a gap")? as usize;
+            let local_end = (range.end - text_offset).min(bytes.len() as u64) as usize;
+            let slice = bytes.get(local_start..local_end)
+                .filter(|slice| !slice.is_empty())
+                .context("TCF chunk index is inconsistent")?;
+            out.extend_from_slice(slice);
+            position += slice.len() as u64;
+            index += 1;
+        }
+
+        Ok(out)
+    }
+
+    /// Offset of the first record start at or after `position`, or the end of the text
+    fn next_record_start(&mut self, position: u64) -> Result<u64> {
+        let strategy = self.template.chunking.context("TCF file has no chunking strategy")?;
+
+        for index in self.chunk_at(position)..self.chunks.len() {
+            let chunk = &self.chunks[index];
+            let Some(first_record) = chunk.first_record else {
+                continue;
+            };
+            let text_offset = chunk.text_offset;
+            if text_offset + first_record >= position {
+                return Ok(text_offset + first_record);
+            }
+
+            // `position` is past this chunk's first record; look for a later one in its text.
+            // Starts within a chunk only depend on its own bytes, except at its
+            // first record and its end, which the index covers.
+            let bytes = self.chunk(index)?;
+            let from = (position - text_offset) as usize;
+            if let Some(local) = (from..bytes.len()).find(|&local| strategy.is_record_start(bytes, local)) {
+                return Ok(text_offset + local as u64);
+            }
+        }
+
+        Ok(self.template.original_size)
+    }
+}
+
+/// Text compression statistics
+#[derive(Debug, Clone)]
+pub struct TextCompressionStats {
+    pub original_size: usize,
+    /// Size of the file, padding included
+    pub compressed_size: usize,
+    /// Padding counted in `compressed_size`
+    pub padding_size: usize,
+    pub compression_ratio: f64,
+    pub savings_percent: f64,
+}
+
+impl TextCompressionStats {
+    /// Size of the file without its padding
+    pub fn logical_size(&self) -> usize {
+        self.compressed_size - self.padding_size
+    }
+}
+
+impl std::fmt::Display for TextCompressionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, 
+            "Original: {} bytes, Compressed: {} bytes, Ratio: {:.2}:1, Savings: {:.2}%",
+            self.original_size,
+            self.compressed_size,
+            self.compression_ratio,
+            self.savings_percent
+        )?;
+        if self.padding_size > 0 {
+            write!(f, ", Padded from: {} bytes", self.logical_size())?;
+        }
+        Ok(())
+    }
+}
+
+/// Characters of `text` outside the prefix and suffix it shares with `normalized`
+fn changed_chars(text: &str, normalized: &str) -> usize {
+    if text == normalized {
+        return 0;
+    }
+    let (length, normalized_length) = (text.chars().count(), normalized.chars().count());
+    let same = |(a, b): &(char, char)| a == b;
+    let prefix = text.chars().zip(normalized.chars()).take_while(same).count();
+    let suffix = text.chars().rev().zip(normalized.chars().rev())
+        .take(length.min(normalized_length) - prefix)
+        .take_while(same)
+        .count();
+    length - prefix - suffix
+}
+
+/// Bytes in JSON string escapes, and in JSON strings overall
+pub(super) fn escape_counts(text: &str) -> (usize, usize) {
+    let (mut escaped, mut total) = (0, 0);
+    let _ = TcfCodec::for_each_token(&JsonAwareTokenizer, text, |token| {
+        if token.class != TokenClass::String {
+            return Ok(());
+        }
+        let bytes = token.text.as_bytes();
+        total += bytes.len();
+        let mut position = 0;
+        while position < bytes.len() {
+            if bytes[position] == b'\\' {
+                let length = if bytes.get(position + 1) == Some(&b'u') { 6 } else { 2 };
+                let length = length.min(bytes.len() - position);
+                escaped += length;
+                position += length;
+            } else {
+                position += 1;
+            }
+        }
+        Ok(())
+    });
+    (escaped, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::tokenizer::TOKENIZER_IDS;
+    use crate::codecs::text::sniff::CompressibilityHint;
+    use crate::codecs::text::chunking::ChunkStrategy;
+    use crate::codecs::peek::PEEK_LIMIT;
+    use crate::codecs::text::newlines::NewlinePolicy;
+    use crate::codecs::text::arithmetic_coder::MAX_TOTAL;
+    use crate::codecs::text::errors::TcfErrorCode;
+    use crate::codecs::text::static_dictionary::{Dictionary, DictionaryDir};
+    use crate::codecs::explain::Decision;
 
     #[test]
     fn test_tcf_roundtrip() {
@@ -262,6 +1869,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_control_characters_roundtrip() {
+        let log = "ts=1\0level=warn\x07\r\nrow\x7f\x7f\0\0\rlast\x1b[0m\0".repeat(40);
+        let texts = ["\0", "\x07", "\x7f", "\r", "a\rb", "\0\0\0", log.as_str()];
+        let mut options = vec![TcfEncodeOptions { method: None, ..Default::default() }];
+        options.extend(TcfMethod::available().into_iter().map(|method| TcfEncodeOptions { method: Some(method), ..Default::default() }));
+        options.extend(TOKENIZER_IDS.iter().map(|id| TcfEncodeOptions { tokenizer_id: id.to_string(), ..Default::default() }));
+        options.push(TcfEncodeOptions { chunking: Some(ChunkStrategy::OnDelimiter(b'\0', 64)), ..Default::default() });
+
+        for options in &options {
+            for text in texts {
+                let encoded = TcfCodec::encode_with_options(text, options).unwrap().data;
+                assert_eq!(TcfCodec::decode(&encoded).unwrap(), text, "{:?} with {:?}/{}", text, options.method, options.tokenizer_id);
+            }
+        }
+    }
+
     #[test]
     fn test_tcf_header_parsing() {
         let text = "Test text for header parsing";
@@ -273,6 +1897,877 @@ mod tests {
         assert_eq!(header.original_size, text.len() as u64);
     }
 
+    const ENGLISH_CORPUS: &str = "It was a bright cold day in April, and the clocks were striking thirteen. \
+The hallway smelt of boiled cabbage and old rag mats. At one end of it a coloured poster, too large \
+for indoor display, had been tacked to the wall. It depicted simply an enormous face, more than a \
+metre wide: the face of a man of about forty-five, with a heavy black moustache and ruggedly handsome features.\n";
+
+    const RUSSIAN_CORPUS: &str = "Все счастливые семьи похожи друг на друга, каждая несчастливая семья \
+несчастлива по-своему. Все смешалось в доме Облонских. Жена узнала, что муж был в связи с бывшею в их \
+доме француженкою-гувернанткой, и объявила мужу, что не может жить с ним в одном доме.\n";
+
+    const CHINESE_CORPUS: &str = "天下大势，分久必合，合久必分。周末七国分争，并入于秦。及秦灭之后，楚、汉分争，\
+又并入于汉。汉朝自高祖斩白蛇而起义，一统天下，后来光武中兴，传至献帝，遂分为三国。推其致乱之由，\
+殆始于桓、灵二帝。桓帝禁锢善类，崇信宦官。及桓帝崩，灵帝即位，大将军窦武、太傅陈蕃共相辅佐。\n";
+
+    const EMOJI_CORPUS: &str = "🚀 Launch day! 🎉🎉 The team shipped 🛠️ fixes for 🐛 bugs and added ✨ sparkle. \
+Coffee ☕☕☕ count: high 📈. Next stop 🌕 then 🪐. Thanks 🙏 everyone 💖 — see you at the 🍕 party!\n";
+
+    fn corpora() -> Vec<(&'static str, String)> {
+        // Repeat each paragraph so the corpus resembles a document rather than a snippet
+        vec![
+            ("english", ENGLISH_CORPUS.repeat(8)),
+            ("russian", RUSSIAN_CORPUS.repeat(8)),
+            ("chinese", CHINESE_CORPUS.repeat(8)),
+            ("emoji", EMOJI_CORPUS.repeat(8)),
+        ]
+    }
+
+    #[test]
+    fn test_multilingual_corpus_roundtrip() {
+        for (name, text) in corpora() {
+            let compressed = TcfCodec::encode(&text).unwrap();
+            let decompressed = TcfCodec::decode(&compressed).unwrap();
+            assert_eq!(text, decompressed, "{} corpus did not round-trip", name);
+
+            let stats = TcfCodec::get_stats(&text, &compressed);
+            assert!(
+                stats.compressed_size < stats.original_size,
+                "{} corpus did not compress: {}", name, stats
+            );
+            println!("{} corpus: {}", name, stats);
+        }
+    }
+
+    #[test]
+    fn test_context_orders_beat_order_zero_on_english() {
+        let text = ENGLISH_CORPUS.repeat(8);
+        let encode = |max_order| {
+            let options = TcfEncodeOptions { max_order, ..Default::default() };
+            TcfCodec::encode_with_options(&text, &options).unwrap().data
+        };
+        let sizes: Vec<usize> = (0..=3).map(|order| {
+            let encoded = encode(order);
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), text, "order {}", order);
+            assert_eq!(TcfCodec::parse_header(&encoded).unwrap().model_params.max_order, order);
+            encoded.len()
+        }).collect();
+        assert!(sizes.windows(2).all(|pair| pair[1] < pair[0]), "sizes by order {:?}", sizes);
+        assert!(sizes[3] * 2 < sizes[0], "order 3 {} bytes, order 0 {} bytes", sizes[3], sizes[0]);
+        assert!(sizes[3] < sizes[1], "{:?}", sizes);
+
+        // A forged size the payload can't hold fails before decoding any of it
+        let forged = with_header(&encode(3), |header| header.original_size = 1 << 40);
+        let error = TcfCodec::decode(&forged).unwrap_err();
+        assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::CorruptPayload { .. })), "{:#}", error);
+
+        // Chunks and streams carry the order to every chunk
+        let chunked = TcfEncodeOptions { max_order: 3, chunking: Some(ChunkStrategy::FixedBytes(500)), ..Default::default() };
+        let encoded = TcfCodec::encode_with_options(&text, &chunked).unwrap().data;
+        assert_eq!(TcfCodec::decode(&encoded).unwrap(), text);
+        let mut streamed = Vec::new();
+        TcfCodec::encode_stream(text.as_bytes(), &mut streamed, &chunked).unwrap();
+        assert!(streamed == encoded);
+
+        for options in [
+            TcfEncodeOptions { max_order: MAX_MODEL_ORDER as u8 + 1, ..Default::default() },
+            TcfEncodeOptions { max_order: 2, tokenizer_id: JsonAwareTokenizer::ID.to_string(), ..Default::default() },
+        ] {
+            assert!(TcfCodec::encode_with_options(&text, &options).is_err(), "order {} with {}", options.max_order, options.tokenizer_id);
+        }
+    }
+
+    #[test]
+    fn test_multilingual_text_roundtrips_with_every_option() {
+        // The models are over bytes and travel in the file, so nothing
+        // outside printable ASCII needs an alphabet of its own
+        let mixed = format!("line one\nline two\tafter a tab\r\n{}{}", EMOJI_CORPUS, CHINESE_CORPUS);
+        let texts = ["\n", "\t\t\n", "🦀", "漢字", "a\n🦀\n漢\n", mixed.as_str()];
+        let mut options = vec![TcfEncodeOptions { method: None, ..Default::default() }];
+        options.extend(TcfMethod::available().into_iter().map(|method| TcfEncodeOptions { method: Some(method), ..Default::default() }));
+        options.extend(TOKENIZER_IDS.iter().map(|id| TcfEncodeOptions { tokenizer_id: id.to_string(), ..Default::default() }));
+        options.push(TcfEncodeOptions { chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 64)), ..Default::default() });
+
+        for options in &options {
+            for text in texts {
+                let encoded = TcfCodec::encode_with_options(text, options).unwrap().data;
+                assert_eq!(TcfCodec::decode(&encoded).unwrap(), text, "{:?} with {:?}/{}", text, options.method, options.tokenizer_id);
+                let streamed: Vec<u8> = TcfCodec::decode_stream(&encoded).unwrap().collect::<Result<Vec<_>>>().unwrap().concat();
+                assert_eq!(streamed, text.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_model_improves_large_alphabets() {
+        let text = CHINESE_CORPUS.repeat(8);
+        let compressed = TcfCodec::encode(&text).unwrap();
+        let header = TcfCodec::parse_header(&compressed).unwrap();
+        assert!(header.flags & TcfFlags::COMPACT_MODEL != 0);
+
+        let mut model = FrequencyModel::new();
+        model.build_from_data(text.as_bytes());
+        let json_model_size = model.serialize().len();
+        assert_eq!(header.model_size as usize, model.to_compact_bytes().len());
+        assert!(header.model_size as usize * 3 < json_model_size);
+
+        // Bits per character with the compact model versus the JSON model it replaces
+        let chars = text.chars().count() as f64;
+        let compact_bpc = compressed.len() as f64 * 8.0 / chars;
+        let json_bpc = (compressed.len() - header.model_size as usize + json_model_size) as f64 * 8.0 / chars;
+        assert!(compact_bpc < json_bpc);
+        // Clearly below the ~24 bits per character of raw UTF-8 CJK
+        let raw_bpc = text.len() as f64 * 8.0 / chars;
+        assert!(compact_bpc < raw_bpc * 0.85, "chinese corpus at {:.2} bits/char", compact_bpc);
+    }
+
+    #[test]
+    fn test_json_model_files_still_decode() {
+        // Files written before COMPACT_MODEL existed store the model as JSON
+        let text = RUSSIAN_CORPUS;
+        let compressed = TcfCodec::encode(text).unwrap();
+        let mut header = TcfCodec::parse_header(&compressed).unwrap();
+        let header_size = u32::from_le_bytes([compressed[4], compressed[5], compressed[6], compressed[7]]) as usize;
+        let payload = &compressed[8 + header_size + header.model_size as usize..];
+
+        let mut model = FrequencyModel::new();
+        model.build_from_data(text.as_bytes());
+        let json_model = model.serialize();
+        header.flags &= !TcfFlags::COMPACT_MODEL;
+        header.model_size = json_model.len() as u32;
+
+        let header_json = serde_json::to_vec(&header).unwrap();
+        let mut legacy = Vec::from(&b"TCF2"[..]);
+        legacy.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+        legacy.extend_from_slice(&header_json);
+        legacy.extend_from_slice(&json_model);
+        legacy.extend_from_slice(payload);
+
+        assert_eq!(TcfCodec::decode(&legacy).unwrap(), text);
+    }
+
+    #[test]
+    fn test_parse_layout_covers_file() {
+        for (name, text) in corpora() {
+            let encoded = TcfCodec::encode(&text).unwrap();
+            let layout = TcfCodec::parse_layout(&encoded).unwrap();
+
+            assert_eq!(layout.file_size, encoded.len() as u64);
+            crate::codecs::layout::check_coverage(&layout.regions, layout.file_size).unwrap();
+            let names: Vec<&str> = layout.regions.iter().map(|region| region.name.as_str()).collect();
+            assert_eq!(names, ["magic", "header_length", "header", "model", "payload"], "{}", name);
+            assert_eq!(layout.trailing_size, 0);
+            assert_eq!(&encoded[layout.region("magic").unwrap().range()], b"TCF2");
+        }
+
+        // Unaccounted bytes show up as a trailing region
+        let mut encoded = TcfCodec::encode("layout test").unwrap();
+        encoded.extend_from_slice(b"junk");
+        let layout = TcfCodec::parse_layout(&encoded).unwrap();
+        assert_eq!(layout.trailing_size, 4);
+        crate::codecs::layout::check_coverage(&layout.regions, encoded.len() as u64).unwrap();
+
+        // A payload running past the end of the file is reported, not clamped
+        encoded.truncate(encoded.len() - 6);
+        assert!(TcfCodec::parse_layout(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_foreign_methods_roundtrip() {
+        let text = ENGLISH_CORPUS.repeat(20);
+        for method in TcfMethod::available() {
+            let options = TcfEncodeOptions { method: Some(method), ..Default::default() };
+            let compressed = TcfCodec::encode_with_options(&text, &options).unwrap().data;
+            let header = TcfCodec::parse_header(&compressed).unwrap();
+            assert_eq!(header.compression_method, method.as_str());
+            assert_eq!(TcfCodec::decode(&compressed).unwrap(), text, "{}", method);
+
+            let layout = TcfCodec::parse_layout(&compressed).unwrap();
+            crate::codecs::layout::check_coverage(&layout.regions, layout.file_size).unwrap();
+            if method != TcfMethod::Arithmetic {
+                assert_eq!(header.model_size, 0);
+            }
+            if method != TcfMethod::Arithmetic && method != TcfMethod::Stored {
+                assert!(compressed.len() < text.len() / 4, "{} barely compressed", method);
+            }
+        }
+        assert_eq!(TcfCodec::decode(&TcfCodec::encode_with_options("", &TcfEncodeOptions { method: Some(TcfMethod::Gzip), ..Default::default() }).unwrap().data).unwrap(), "");
+    }
+
+    #[test]
+    fn test_auto_method_picks_smallest() {
+        // Highly repetitive text favours a dictionary coder over order-0 arithmetic
+        let text = ENGLISH_CORPUS.repeat(20);
+        let auto = TcfCodec::encode_with_options(&text, &TcfEncodeOptions { method: None, ..Default::default() }).unwrap().data;
+        for method in TcfMethod::available() {
+            let fixed = TcfCodec::encode_with_options(&text, &TcfEncodeOptions { method: Some(method), ..Default::default() }).unwrap().data;
+            assert!(auto.len() <= fixed.len(), "auto larger than {}", method);
+        }
+        assert_ne!(TcfCodec::parse_header(&auto).unwrap().compression_method, "arithmetic");
+        assert_eq!(TcfCodec::decode(&auto).unwrap(), text);
+    }
+
+    /// Sorted URL manifest, `lines` long
+    fn sorted_urls(lines: usize) -> String {
+        let mut urls: Vec<String> = (0..lines)
+            .map(|i| format!("https://cdn.example.com/assets/{}/v{}/{:05}.{}", ["css", "img", "js"][i % 3], i % 7, i * 7919 % 100_000, ["css", "webp", "js"][i % 3]))
+            .collect();
+        urls.sort();
+        urls.join("\n")
+    }
+
+    #[test]
+    fn test_large_text_is_chunked_by_default() {
+        let text: String = (0..100_000).map(|i| format!("request {} served in {} ms from edge {}\n", i, i * 37 % 1000, i % 13)).collect();
+        let chunk_bytes = TcfCodec::STREAM_CHUNK_BYTES;
+        assert!(text.len() > 3 * chunk_bytes);
+        let mut encoded = TcfCodec::encode(&text).unwrap();
+        let header = TcfCodec::parse_header(&encoded).unwrap();
+        assert_eq!(header.chunking, Some(ChunkStrategy::FixedBytes(chunk_bytes)));
+        assert_eq!(header.chunks.len(), text.len().div_ceil(chunk_bytes));
+        assert!(header.chunks.iter().all(|chunk| chunk.chunk_type == ChunkType::Data && chunk.original_size <= chunk_bytes as u64));
+        assert_eq!(TcfCodec::decode(&encoded).unwrap(), text);
+
+        // Damage to one chunk leaves the others decodable
+        let header_end = 8 + u32::from_le_bytes(encoded[4..8].try_into().unwrap()) as usize;
+        let chunk = &header.chunks[1];
+        encoded[header_end + chunk.data_offset as usize + chunk.model_size as usize + chunk.compressed_size as usize / 2] ^= 0x5A;
+        assert!(TcfCodec::decode(&encoded).is_err());
+        assert_eq!(TcfCodec::decode_range(&encoded, 0..chunk_bytes as u64).unwrap(), &text.as_bytes()[..chunk_bytes]);
+        let last = &header.chunks[header.chunks.len() - 1];
+        assert_eq!(TcfCodec::decode_range(&encoded, last.text_offset..text.len() as u64).unwrap(), &text.as_bytes()[last.text_offset as usize..]);
+
+        assert!(TcfCodec::parse_header(&TcfCodec::encode("short").unwrap()).unwrap().chunks.is_empty());
+    }
+
+    #[test]
+    fn test_front_coding_sorted_lines() {
+        let urls = sorted_urls(100_000);
+        let front = TcfEncodeOptions { method: Some(TcfMethod::FrontCoding), ..Default::default() };
+        let coded = TcfCodec::encode_with_options(&urls, &front).unwrap().data;
+        let default = TcfCodec::encode(&urls).unwrap();
+        assert!(coded.len() * 4 < default.len(), "front coding {} bytes, default {}", coded.len(), default.len());
+        let header = TcfCodec::parse_header(&coded).unwrap();
+        assert_ne!(header.flags & TcfFlags::FRONT_CODED, 0);
+        assert_eq!(TcfCodec::decode(&coded).unwrap(), urls);
+
+        // Auto selection spots the sorted lines, with or without a final newline
+        let auto = TcfEncodeOptions { method: None, ..Default::default() };
+        let urls = sorted_urls(5_000);
+        let with_newline = format!("{}\n", urls);
+        for text in [&urls, &with_newline] {
+            let encoded = TcfCodec::encode_with_options(text, &auto).unwrap().data;
+            assert_eq!(TcfCodec::parse_header(&encoded).unwrap().compression_method, "front-coding");
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), *text);
+        }
+    }
+
+    #[test]
+    fn test_front_coding_rejects_unsorted_lines() {
+        let mut lines: Vec<&str> = ENGLISH_CORPUS.split(' ').collect();
+        lines.reverse();
+        let unsorted = lines.join("\n");
+        let front = TcfEncodeOptions { method: Some(TcfMethod::FrontCoding), ..Default::default() };
+        assert!(TcfCodec::encode_with_options(&unsorted, &front).is_err());
+
+        let auto = TcfCodec::encode_with_options(&unsorted, &TcfEncodeOptions { method: None, ..Default::default() }).unwrap().data;
+        let header = TcfCodec::parse_header(&auto).unwrap();
+        assert_ne!(header.compression_method, "front-coding");
+        assert_eq!(header.flags & TcfFlags::FRONT_CODED, 0);
+    }
+
+    /// Rewrite the header of an encoded file, keeping model and payload
+    fn with_header(encoded: &[u8], edit: impl FnOnce(&mut TcfHeader)) -> Vec<u8> {
+        let mut header = TcfCodec::parse_header(encoded).unwrap();
+        let header_size = u32::from_le_bytes([encoded[4], encoded[5], encoded[6], encoded[7]]) as usize;
+        edit(&mut header);
+
+        let header_json = serde_json::to_vec(&header).unwrap();
+        let mut output = Vec::from(&b"TCF2"[..]);
+        output.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+        output.extend_from_slice(&header_json);
+        output.extend_from_slice(&encoded[8 + header_size..]);
+        output
+    }
+
+    #[test]
+    fn test_unsupported_method_is_an_error() {
+        let gzip = TcfCodec::encode_with_options("method test", &TcfEncodeOptions { method: Some(TcfMethod::Gzip), ..Default::default() }).unwrap().data;
+
+        let unknown = with_header(&gzip, |header| header.compression_method = "brotli".to_string());
+        let error = TcfCodec::decode(&unknown).unwrap_err();
+        assert!(error.to_string().contains("Unsupported TCF compression method"), "{}", error);
+
+        // Without the zstd feature, zstd files are rejected rather than misread
+        let zstd = with_header(&gzip, |header| header.compression_method = "zstd".to_string());
+        let result = TcfCodec::decode(&zstd);
+        if TcfMethod::Zstd.is_supported() {
+            assert!(result.is_err(), "gzip bytes decoded as zstd");
+        } else {
+            assert!(result.unwrap_err().to_string().contains("Unsupported TCF compression method: zstd"));
+            assert!(TcfCodec::encode_with_options("x", &TcfEncodeOptions { method: Some(TcfMethod::Zstd), ..Default::default() }).is_err());
+        }
+
+        // A payload that inflates past original_size is refused
+        let lying = with_header(&gzip, |header| header.original_size = 3);
+        assert!(TcfCodec::decode(&lying).is_err());
+    }
+
+    fn json_logs() -> String {
+        let levels = ["info", "warn", "debug", "error"];
+        let paths = ["/api/users", "/api/orders/\\u00e9t\\u00e9", "/static/app.js", "/health"];
+        (0..300).map(|i| {
+            format!(
+                "{{\"ts\":{},\"level\":\"{}\",\"path\":\"{}\",\"status\":{},\"latency_ms\":{}.{},\"user\":\"caf\\u00e9 \\\"{}\\\" ☕\"}}\n",
+                1_700_000_000 + i * 37, levels[i % 4], paths[i * 7 % 4], [200, 201, 404, 500][i * 3 % 4],
+                i * 13 % 900, i % 10, i % 17,
+            )
+        }).collect()
+    }
+
+    #[test]
+    fn test_json_tokenizer_beats_byte_model_on_logs() {
+        let text = json_logs();
+        let encode = |tokenizer: &str| {
+            let options = TcfEncodeOptions { tokenizer_id: tokenizer.to_string(), ..Default::default() };
+            TcfCodec::encode_with_options(&text, &options).unwrap().data
+        };
+
+        let byte = encode("byte");
+        assert!(!String::from_utf8_lossy(&byte).contains("tokenizer_id"), "byte files keep the old header");
+        for id in TOKENIZER_IDS {
+            let encoded = encode(id);
+            assert_eq!(TcfCodec::parse_header(&encoded).unwrap().model_params.tokenizer_id, *id);
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), text, "{} tokenizer", id);
+        }
+
+        let json = encode("json");
+        assert!(json.len() < byte.len(), "json {} bytes vs byte {} bytes", json.len(), byte.len());
+    }
+
+    #[test]
+    fn test_unknown_tokenizer_is_an_error() {
+        let options = TcfEncodeOptions { tokenizer_id: "bpe".to_string(), ..Default::default() };
+        assert!(TcfCodec::encode_with_options("x", &options).is_err());
+
+        let options = TcfEncodeOptions { tokenizer_id: "word".to_string(), ..Default::default() };
+        let encoded = TcfCodec::encode_with_options("tokenizer test", &options).unwrap().data;
+        let unknown = with_header(&encoded, |header| header.model_params.tokenizer_id = "bpe".to_string());
+        let error = TcfCodec::decode(&unknown).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown TCF tokenizer: bpe");
+    }
+
+    #[test]
+    fn test_compressibility_hints_drive_auto_method() {
+        let english = ENGLISH_CORPUS.repeat(20);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(english.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut png = Vec::from(&b"\x89PNG\r\n\x1a\n"[..]);
+        png.extend_from_slice(&[0, 0, 0, 13]);
+        let mut state = 0x9e37_79b9u32;
+        let random: Vec<u8> = (0..8192).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect();
+
+        assert_eq!(TcfCodec::estimate_compressibility(&gzip), CompressibilityHint::AlreadyCompressed("gzip"));
+        assert_eq!(TcfCodec::estimate_compressibility(&png), CompressibilityHint::AlreadyCompressed("png"));
+        assert!(matches!(TcfCodec::estimate_compressibility(&random), CompressibilityHint::HighEntropy { .. }));
+        assert_eq!(TcfCodec::estimate_compressibility(english.as_bytes()), CompressibilityHint::Compressible);
+        assert_eq!(TcfCodec::estimate_compressibility(&[b'a', 0, b'b']), CompressibilityHint::Binary);
+
+        // Auto stores text the sniffer flags without trying any coder; valid
+        // UTF-8 never reaches the entropy limit, so use a signature
+        let auto = TcfEncodeOptions { method: None, ..Default::default() };
+        let noise: String = "PK\x03\x04".chars().chain(random.iter().map(|&byte| char::from(byte % 95 + 32))).collect();
+        let stored = TcfCodec::encode_with_options(&noise, &auto).unwrap().data;
+        assert_eq!(TcfCodec::parse_header(&stored).unwrap().compression_method, "stored");
+        assert_eq!(TcfCodec::decode(&stored).unwrap(), noise);
+        let coded = TcfCodec::encode_with_options(&english, &auto).unwrap().data;
+        assert_ne!(TcfCodec::parse_header(&coded).unwrap().compression_method, "stored");
+    }
+
+    #[test]
+    fn test_explain_trace_records_decisions_without_changing_output() {
+        let auto = TcfEncodeOptions { method: None, ..Default::default() };
+        let signed = format!("PK\x03\x04{}", ENGLISH_CORPUS);
+        let (stored, trace) = TcfCodec::encode_with_trace(&signed, &auto).unwrap();
+        assert_eq!(trace.decisions, [Decision::Chosen {
+            step: "tcf.method",
+            option: "stored".to_string(),
+            reason: "input looks already compressed (zip)".to_string(),
+        }]);
+        assert_eq!(stored.data, TcfCodec::encode_with_options(&signed, &auto).unwrap().data);
+
+        // Every available method is weighed before the smallest is chosen
+        let english = ENGLISH_CORPUS.repeat(4);
+        let (coded, trace) = TcfCodec::encode_with_trace(&english, &auto).unwrap();
+        let method = TcfCodec::parse_header(&coded.data).unwrap().compression_method;
+        let (last, weighed) = trace.decisions.split_last().unwrap();
+        assert!(matches!(last, Decision::Chosen { option, .. } if *option == method), "{:?}", last);
+        for available in TcfMethod::available() {
+            assert!(weighed.iter().any(|decision| matches!(decision, Decision::Candidate { option, .. } if *option == available.as_str())), "{}", available);
+        }
+        assert_eq!(coded.data, TcfCodec::encode_with_options(&english, &auto).unwrap().data);
+
+        // Chunks are traced in order, repeats as references
+        let options = TcfEncodeOptions { chunking: Some(ChunkStrategy::FixedBytes(64)), ..Default::default() };
+        let (chunked, trace) = TcfCodec::encode_with_trace(&"a".repeat(192), &options).unwrap();
+        assert_eq!(trace.choices("tcf.chunk"), ["data", "reference", "reference"]);
+        assert_eq!(trace.choices("tcf.method"), ["arithmetic"]);
+        assert_eq!(chunked.data, TcfCodec::encode_with_options(&"a".repeat(192), &options).unwrap().data);
+    }
+
+    #[test]
+    fn test_peek_reads_header_from_prefix() {
+        let text = "peek at me without decoding anything ".repeat(20);
+        let small_chunks = TcfEncodeOptions {
+            chunking: Some(ChunkStrategy::FixedBytes(16)),
+            ..Default::default()
+        };
+        for encoded in [
+            TcfCodec::encode(&text).unwrap(),
+            // The chunk index pushes this header past the peek limit
+            TcfCodec::encode_with_options(&text, &small_chunks).unwrap().data,
+        ] {
+            let header = TcfCodec::parse_header(&encoded).unwrap();
+            let header_end = 8 + u32::from_le_bytes(encoded[4..8].try_into().unwrap()) as usize;
+            let threshold = header_end.min(PEEK_LIMIT);
+
+            for length in 0..threshold {
+                match TcfCodec::peek(&encoded[..length]).unwrap() {
+                    PeekResult::NeedMoreData(needed) => assert!(needed > length && needed <= threshold),
+                    PeekResult::Ready(_) => panic!("peek succeeded with only {} bytes", length),
+                }
+            }
+            let peeked = TcfCodec::peek(&encoded[..threshold]).unwrap().ready().unwrap();
+            assert_eq!(peeked.original_size, text.len() as u64);
+            assert_eq!(peeked.compressed_size, header.compressed_size);
+            assert_eq!(peeked.compression_method, header.compression_method);
+            assert_eq!(peeked.flags, header.flags);
+            assert_eq!(peeked.checksum_kind(), ChecksumKind::Sha256);
+        }
+        assert!(TcfCodec::peek(b"ICF2").is_err());
+    }
+
+    #[test]
+    fn test_chunked_ndjson_is_record_aligned() {
+        let logs = json_logs();
+        let options = TcfEncodeOptions {
+            chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 2048)),
+            ..Default::default()
+        };
+        let encoded = TcfCodec::encode_with_options(&logs, &options).unwrap().data;
+        assert_eq!(TcfCodec::decode(&encoded).unwrap(), logs);
+
+        let header = TcfCodec::parse_header(&encoded).unwrap();
+        assert!(header.chunks.len() > 1);
+        for chunk in &header.chunks[..header.chunks.len() - 1] {
+            let end = (chunk.text_offset + chunk.original_size) as usize;
+            assert_eq!(logs.as_bytes()[end - 1], b'\n');
+            assert_eq!(chunk.first_record, Some(0));
+        }
+
+        // Byte ranges cut records; record ranges return whole lines
+        let middle = logs.len() / 2;
+        let range = middle as u64..middle as u64 + 500;
+        assert_eq!(TcfCodec::decode_range(&encoded, range.clone()).unwrap(), &logs.as_bytes()[middle..middle + 500]);
+        let records = TcfCodec::decode_records(&encoded, range.clone()).unwrap();
+        assert!(records.ends_with('\n'));
+        for line in records.lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+        let offset = logs.find(&records).unwrap();
+        assert!(offset >= middle && logs.as_bytes()[offset - 1] == b'\n');
+        assert!(offset + records.len() >= middle + 500);
+
+        assert_eq!(TcfCodec::decode_records(&encoded, 0..logs.len() as u64).unwrap(), logs);
+        assert!(TcfCodec::decode_range(&encoded, 0..logs.len() as u64 + 1).is_err());
+        assert!(TcfCodec::decode_records(&TcfCodec::encode(&logs).unwrap(), range).is_err());
+    }
+
+    #[test]
+    fn test_repeated_chunks_become_references() {
+        // Noisy enough that only deduplication can shrink the repeats
+        let mut state = 0x2545F491u32;
+        let block: String = (0..64 * 1024).map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            b"abcdefghijklmnopqrstuvwxyz .,:-\n"[(state >> 27) as usize] as char
+        }).collect();
+        let text = block.repeat(20);
+        let options = TcfEncodeOptions { chunking: Some(ChunkStrategy::FixedBytes(64 * 1024)), ..Default::default() };
+        let single = TcfCodec::encode_with_options(&block, &options).unwrap().data;
+        let encoded = TcfCodec::encode_with_options(&text, &options).unwrap().data;
+        assert!(encoded.len() < single.len() + 8 * 1024, "{} vs one copy {}", encoded.len(), single.len());
+        assert_eq!(TcfCodec::decode(&encoded).unwrap(), text);
+
+        let layout = TcfCodec::parse_layout(&encoded).unwrap();
+        crate::codecs::layout::check_coverage(&layout.regions, layout.file_size).unwrap();
+        let chunks = &layout.header.chunks;
+        assert_eq!(chunks.len(), 20);
+        assert_eq!(chunks[0].chunk_type, ChunkType::Data);
+        assert!(chunks[1..].iter().all(|chunk| chunk.chunk_type == ChunkType::Reference(0) && chunk.compressed_size == 0));
+        assert_eq!(chunks[7].text_offset, 7 * 64 * 1024);
+
+        let range = 5 * 64 * 1024 - 100..6 * 64 * 1024 + 100;
+        assert_eq!(TcfCodec::decode_range(&encoded, range.start as u64..range.end as u64).unwrap(), &text.as_bytes()[range]);
+        let streamed: Vec<u8> = TcfCodec::decode_stream(&encoded).unwrap().flat_map(Result::unwrap).collect();
+        assert_eq!(streamed, text.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_offsets_match_file_layout() {
+        let logs = json_logs();
+        let shouted = logs.replace("info", "INFO");
+        let text = format!("{}{}{}", logs, shouted, logs);
+        let options = TcfEncodeOptions {
+            method: None,
+            chunking: Some(ChunkStrategy::FixedBytes(logs.len())),
+            pad_to: PaddingPolicy::PowerOfTwo,
+            ..Default::default()
+        };
+        let encoded = TcfCodec::encode_with_options(&text, &options).unwrap().data;
+        let header = TcfCodec::parse_header(&encoded).unwrap();
+        let types: Vec<ChunkType> = header.chunks.iter().map(|chunk| chunk.chunk_type).collect();
+        assert_eq!(types, [ChunkType::Data, ChunkType::Data, ChunkType::Reference(0)]);
+
+        // Each data chunk's bytes, read at its offset, are what coding its text alone gives
+        let body_start = 8 + u32::from_le_bytes(encoded[4..8].try_into().unwrap()) as usize;
+        let mut end = body_start;
+        for chunk in header.chunks.iter().filter(|chunk| chunk.chunk_type.is_data()) {
+            let start = body_start + chunk.data_offset as usize;
+            assert_eq!(start, end);
+            end = start + chunk.data_size().unwrap();
+            let chunk_text = &text[chunk.text_offset as usize..(chunk.text_offset + chunk.original_size) as usize];
+            let method = chunk.compression_method.parse().unwrap();
+            let (_, model_data, compressed_data) = TcfCodec::code_text(chunk_text, Some(method), &ByteTokenizer, 0, &mut Vec::new(), &mut ExplainTrace::default()).unwrap();
+            assert!(encoded[start..end] == [model_data, compressed_data].concat(), "chunk at {}", chunk.text_offset);
+        }
+        assert_eq!(end as u64, body_start as u64 + header.compressed_size);
+        assert_eq!(end as u64 + header.padding, encoded.len() as u64);
+
+        // An index pointing a chunk anywhere else is refused before any data is decoded
+        let moved = with_header(&encoded, |header| header.chunks[1].data_offset += 1);
+        for error in [TcfCodec::decode(&moved).unwrap_err(), TcfCodec::decode_reader(&moved[..]).err().unwrap()] {
+            assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::ChunkOffsetMismatch { index: 1, .. })), "{:#}", error);
+            assert_eq!(TcfErrorCode::of(&error), TcfErrorCode::InvalidFormat);
+        }
+        let overstated = with_header(&encoded, |header| header.compressed_size += 1);
+        assert!(TcfCodec::decode_range(&overstated, 0..10).is_err());
+    }
+
+    #[test]
+    fn test_decode_stream_yields_chunks_then_checks_digest() {
+        let logs = json_logs();
+        let options = TcfEncodeOptions {
+            chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 2048)),
+            ..Default::default()
+        };
+        for encoded in [TcfCodec::encode_with_options(&logs, &options).unwrap().data, TcfCodec::encode(&logs).unwrap()] {
+            let pieces: Vec<Vec<u8>> = TcfCodec::decode_stream(&encoded).unwrap().collect::<Result<_>>().unwrap();
+            assert_eq!(pieces.len(), TcfCodec::parse_header(&encoded).unwrap().chunks.len().max(1));
+            assert_eq!(pieces.concat(), logs.as_bytes());
+
+            // Same-length digest so the header keeps its size
+            let mut corrupted = encoded.clone();
+            let checksum = TcfCodec::parse_header(&encoded).unwrap().checksum;
+            let at = corrupted.windows(64).position(|window| window == checksum.as_bytes()).unwrap();
+            corrupted[at] = if corrupted[at] == b'0' { b'1' } else { b'0' };
+            let results: Vec<Result<Vec<u8>>> = TcfCodec::decode_stream(&corrupted).unwrap().collect();
+            // An unchunked file is one piece, checked before it's yielded
+            let chunked = TcfCodec::parse_header(&encoded).unwrap().flags & TcfFlags::CHUNKED != 0;
+            assert_eq!(results.len(), if chunked { pieces.len() + 1 } else { 1 });
+            assert!(results.last().unwrap().as_ref().unwrap_err().to_string().contains("checksum mismatch"));
+
+            // Per-chunk checks alone don't look at the whole-file digest
+            let streamed = TcfCodec::decode_stream(&corrupted).unwrap().verify(VerifyMode::PerChunk).collect::<Result<Vec<_>>>();
+            assert_eq!(streamed.is_ok(), chunked);
+            let streamed = TcfCodec::decode_stream(&corrupted).unwrap().verify(VerifyMode::Off).collect::<Result<Vec<_>>>();
+            assert_eq!(streamed.unwrap().concat(), logs.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_per_chunk_verify_stops_at_corrupt_chunk() {
+        let logs = json_logs();
+        let options = TcfEncodeOptions {
+            method: Some(TcfMethod::Stored),
+            chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 1024)),
+            ..Default::default()
+        };
+        let encoded = TcfCodec::encode_with_options(&logs, &options).unwrap().data;
+        let header = TcfCodec::parse_header(&encoded).unwrap();
+        assert!(header.chunks.len() > 3);
+        let header_size = u32::from_le_bytes(encoded[4..8].try_into().unwrap()) as usize;
+        let mut corrupted = encoded.clone();
+        corrupted[8 + header_size + header.chunks[2].data_offset as usize + 10] ^= 0x20;
+
+        let mut stream = TcfCodec::decode_stream(&corrupted).unwrap().verify(VerifyMode::PerChunk);
+        let yielded: Vec<Vec<u8>> = stream.by_ref().map_while(Result::ok).collect();
+        assert_eq!(yielded.len(), 2);
+        assert_eq!(yielded.concat(), logs.as_bytes()[..header.chunks[2].text_offset as usize]);
+        assert!(stream.next().is_none());
+        let error = TcfCodec::decode_stream(&corrupted).unwrap().verify(VerifyMode::PerChunk).nth(2).unwrap().unwrap_err();
+        assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::ChunkChecksumMismatch(2))), "{:#}", error);
+
+        // The reader-based stream stops at the same place
+        let mut stream = TcfCodec::decode_reader(&corrupted[..]).unwrap().verify(VerifyMode::PerChunk);
+        assert_eq!(stream.by_ref().take(2).filter(Result::is_ok).count(), 2);
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+
+        // Unverified, the bad chunk goes out with the rest
+        let unchecked = TcfCodec::decode_stream(&corrupted).unwrap().verify(VerifyMode::Off).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(unchecked.len(), header.chunks.len());
+        assert_ne!(unchecked.concat(), logs.as_bytes());
+    }
+
+    #[test]
+    fn test_record_ranges_match_reference() {
+        let markdown: String = (0..40)
+            .map(|i| format!("## Section {}\n\nParagraph {} with ünïcode text{}\n\n\n", i, i, " and more".repeat(i % 7)))
+            .collect();
+        let cases = [
+            (json_logs(), ChunkStrategy::OnDelimiter(b'\n', 4096)),
+            // Smaller than a record, so chunks start mid-record
+            (json_logs(), ChunkStrategy::OnDelimiter(b'\n', 50)),
+            (markdown.clone(), ChunkStrategy::OnBlankLine(300)),
+            (markdown, ChunkStrategy::OnBlankLine(7)),
+        ];
+
+        for (text, strategy) in cases {
+            let options = TcfEncodeOptions { method: None, chunking: Some(strategy), ..Default::default() };
+            let encoded = TcfCodec::encode_with_options(&text, &options).unwrap().data;
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), text);
+
+            let bytes = text.as_bytes();
+            let next_start = |position: usize| {
+                (position..bytes.len()).find(|&p| strategy.is_record_start(bytes, p)).unwrap_or(bytes.len())
+            };
+            for start in (0..text.len()).step_by(97) {
+                let end = (start + 211).min(text.len());
+                let expected = &text[next_start(start)..next_start(end).max(next_start(start))];
+                let records = TcfCodec::decode_records(&encoded, start as u64..end as u64).unwrap();
+                assert_eq!(records, expected, "{:?} at {}..{}", strategy, start, end);
+            }
+        }
+    }
+
+    #[test]
+    fn test_newline_policies_roundtrip_mixed_endings() {
+        let mixed = "unix line\nwindows line\r\n\r\nlone \r stays\r\r\nlast\n".repeat(30) + "no newline at end";
+        for newline in [NewlinePolicy::Preserve, NewlinePolicy::NormalizeLfRecordPositions] {
+            for method in [TcfMethod::Arithmetic, TcfMethod::Gzip, TcfMethod::Stored] {
+                let options = TcfEncodeOptions { method: Some(method), newline, ..Default::default() };
+                let encoded = TcfCodec::encode_with_options(&mixed, &options).unwrap().data;
+                assert_eq!(TcfCodec::decode(&encoded).unwrap(), mixed, "{} with {}", newline, method);
+                let streamed: Vec<u8> = TcfCodec::decode_stream(&encoded).unwrap().flat_map(Result::unwrap).collect();
+                assert_eq!(streamed, mixed.as_bytes());
+                assert_eq!(TcfCodec::decode_range(&encoded, 10..40).unwrap(), &mixed.as_bytes()[10..40]);
+
+                let header = TcfCodec::parse_header(&encoded).unwrap();
+                assert_eq!(header.original_size, mixed.len() as u64);
+                assert_eq!(header.flags & TcfFlags::NEWLINES_NORMALIZED != 0, newline != NewlinePolicy::Preserve);
+                let layout = TcfCodec::parse_layout(&encoded).unwrap();
+                assert_eq!(layout.trailing_size, 0);
+                assert_eq!(layout.region("newlines").is_some(), header.newlines.is_some());
+            }
+        }
+
+        let chunked = TcfEncodeOptions {
+            newline: NewlinePolicy::NormalizeLfRecordPositions,
+            chunking: Some(ChunkStrategy::FixedBytes(64)),
+            ..Default::default()
+        };
+        assert!(TcfCodec::encode_with_options(&mixed, &chunked).is_err());
+    }
+
+    #[test]
+    fn test_normalizing_crlf_log_improves_ratio() {
+        let log: String = (0..500)
+            .map(|i| format!("2024-03-{:02} 10:{:02}:{:02} INFO service[{}] handled request {}\r\n", i % 28 + 1, i % 60, i * 7 % 60, i % 4, i))
+            .collect();
+        let size = |newline| {
+            let options = TcfEncodeOptions { newline, ..Default::default() };
+            let encoded = TcfCodec::encode_with_options(&log, &options).unwrap().data;
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), log);
+            encoded.len()
+        };
+        let (preserved, normalized) = (size(NewlinePolicy::Preserve), size(NewlinePolicy::NormalizeLfRecordPositions));
+        // Every line saves its CR, at better than half a byte each
+        assert!(normalized + 250 < preserved, "normalized {} vs preserved {}", normalized, preserved);
+    }
+
+    #[test]
+    fn test_padding_lands_on_buckets() {
+        let buckets = PaddingPolicy::buckets(vec![512, 1024, 4096]).unwrap();
+        let padded = |text: &str, pad_to: &PaddingPolicy, chunking| {
+            let options = TcfEncodeOptions { pad_to: pad_to.clone(), chunking, ..Default::default() };
+            let encoded = TcfCodec::encode_with_options(text, &options).unwrap().data;
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), text);
+            encoded
+        };
+
+        let short = padded("a short note", &buckets, None);
+        let longer = padded(&"A longer message about something else entirely. ".repeat(4), &buckets, None);
+        assert_eq!((short.len(), longer.len()), (512, 512));
+        let header = TcfCodec::parse_header(&short).unwrap();
+        assert!(short[short.len() - header.padding as usize..].iter().all(|&byte| byte == 0));
+        assert_eq!(TcfCodec::parse_layout(&short).unwrap().trailing_size, 0);
+        let stats = TcfCodec::get_stats("a short note", &short);
+        assert_eq!(stats.logical_size(), short.len() - header.padding as usize);
+
+        let text = json_logs();
+        let chunked = padded(&text, &PaddingPolicy::PowerOfTwo, Some(ChunkStrategy::FixedBytes(1024)));
+        assert!(chunked.len().is_power_of_two());
+        assert_eq!(TcfCodec::decode_range(&chunked, 100..200).unwrap(), &text.as_bytes()[100..200]);
+        let mut streamed = Vec::new();
+        let options = TcfEncodeOptions { pad_to: PaddingPolicy::PowerOfTwo, chunking: Some(ChunkStrategy::FixedBytes(1024)), ..Default::default() };
+        TcfCodec::encode_stream(text.as_bytes(), &mut streamed, &options).unwrap();
+        assert_eq!(streamed, chunked);
+
+        // Past the largest bucket, files round up to multiples of it
+        let large = padded(&text, &buckets, None);
+        assert_eq!(large.len() % 4096, 0);
+        assert_eq!(padded(&text, &PaddingPolicy::None, None).len(), TcfCodec::encode(&text).unwrap().len());
+    }
+
+    fn api_response(i: usize) -> String {
+        format!(
+            r#"{{"id":{},"node_id":"MDQ6VXNlcj{}","status":"{}","created_at":"2024-03-{:02}T{:02}:00:00Z","owner":{{"login":"user{}","type":"User","site_admin":false}},"labels":[{}]}}"#,
+            i * 7919 % 100000, i * 31, ["active", "pending", "archived"][i % 3], i % 28 + 1, i % 24, i * 13,
+            (0..i % 3).map(|l| format!(r#"{{"name":"label-{}","color":"ededed"}}"#, l)).collect::<Vec<_>>().join(","),
+        )
+    }
+
+    #[test]
+    fn test_trained_dictionary_shrinks_small_json_responses() {
+        let samples: Vec<String> = (0..200).map(api_response).collect();
+        let samples: Vec<&str> = samples.iter().map(String::as_str).collect();
+        let dictionary = Dictionary::train(&samples, 2048);
+        let plain = TcfEncodeOptions { method: None, ..Default::default() };
+        let with_dictionary = TcfEncodeOptions { dictionary: Some(dictionary.clone()), embed_dictionary: false, ..plain.clone() };
+
+        // The header is the same either way and dwarfs payloads this small
+        let payload_size = |encoded: &[u8]| {
+            let header = TcfCodec::parse_header(encoded).unwrap();
+            header.model_size as usize + header.compressed_size as usize
+        };
+        let (mut plain_size, mut dictionary_size, mut file_sizes) = (0, 0, (0, 0));
+        for response in (1000..1050).map(api_response) {
+            let plain_encoded = TcfCodec::encode_with_options(&response, &plain).unwrap().data;
+            let encoded = TcfCodec::encode_with_options(&response, &with_dictionary).unwrap().data;
+            plain_size += payload_size(&plain_encoded);
+            dictionary_size += payload_size(&encoded);
+            file_sizes = (file_sizes.0 + plain_encoded.len(), file_sizes.1 + encoded.len());
+            assert_eq!(TcfCodec::decode_with_dictionary(&encoded, &dictionary).unwrap(), response);
+            let error = TcfCodec::decode(&encoded).unwrap_err();
+            assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::MissingDictionary(hash)) if *hash == dictionary.short_hash()), "{:#}", error);
+        }
+        assert!(dictionary_size * 2 < plain_size, "payloads with dictionary {} vs without {}", dictionary_size, plain_size);
+        assert!(file_sizes.1 < file_sizes.0, "files with dictionary {} vs without {}", file_sizes.1, file_sizes.0);
+    }
+
+    #[test]
+    fn test_dictionary_chunk_roundtrips_with_every_option() {
+        let text = format!("{}\r\n{}\r\nprivate use \u{E000}\u{F8FF} stays\n", api_response(1), api_response(2));
+        let dictionary = Dictionary::new(vec!["\"created_at\":\"2024-03-".to_string(), "\"login\":\"user".to_string(), "\r\n".to_string()]).unwrap();
+        let mut options: Vec<TcfEncodeOptions> = TcfMethod::available().into_iter()
+            .map(|method| TcfEncodeOptions { method: Some(method), ..Default::default() })
+            .chain(TOKENIZER_IDS.iter().map(|id| TcfEncodeOptions { tokenizer_id: id.to_string(), ..Default::default() }))
+            .chain([TcfEncodeOptions { method: None, newline: NewlinePolicy::NormalizeLfRecordPositions, ..Default::default() }])
+            .collect();
+        for options in &mut options {
+            options.dictionary = Some(dictionary.clone());
+            let encoded = TcfCodec::encode_with_options(&text, options).unwrap().data;
+            assert_eq!(TcfCodec::decode(&encoded).unwrap(), text, "{:?}", options);
+            let header = TcfCodec::parse_header(&encoded).unwrap();
+            assert_ne!(header.flags & TcfFlags::DICTIONARY_COMPRESSED, 0);
+            assert_eq!(header.chunks.iter().map(|chunk| chunk.chunk_type).collect::<Vec<_>>(), [ChunkType::Dictionary]);
+            let layout = TcfCodec::parse_layout(&encoded).unwrap();
+            assert!(layout.region("dictionary").is_some());
+            assert_eq!(layout.trailing_size, 0);
+        }
+
+        // A file without its chunk needs the same dictionary back
+        let detached = TcfEncodeOptions { dictionary: Some(dictionary), embed_dictionary: false, ..Default::default() };
+        let encoded = TcfCodec::encode_with_options(&text, &detached).unwrap().data;
+        let other = Dictionary::new(vec!["\"login\":\"user".to_string()]).unwrap();
+        let error = TcfCodec::decode_with_dictionary(&encoded, &other).unwrap_err();
+        assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::DictionaryMismatch { .. })), "{:#}", error);
+        assert_eq!(TcfErrorCode::of(&error), TcfErrorCode::UnsupportedMethod);
+        let chunked = TcfEncodeOptions { chunking: Some(ChunkStrategy::FixedBytes(64)), ..detached };
+        assert!(TcfCodec::encode_with_options(&text, &chunked).is_err());
+    }
+
+    #[test]
+    fn test_detached_dictionaries_resolve_by_hash() {
+        let samples: Vec<String> = (0..100).map(api_response).collect();
+        let samples: Vec<&str> = samples.iter().map(String::as_str).collect();
+        let dictionary = Dictionary::train(&samples, 1024);
+        let detached = TcfEncodeOptions { dictionary: Some(dictionary.clone()), embed_dictionary: false, ..Default::default() };
+        let texts = [api_response(500), api_response(501)];
+        let encoded: Vec<Vec<u8>> = texts.iter().map(|text| TcfCodec::encode_with_options(text, &detached).unwrap().data).collect();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dictionaries = DictionaryDir::new(dir.path());
+        let path = dictionaries.save(&dictionary).unwrap();
+        for (text, encoded) in texts.iter().zip(&encoded) {
+            assert_eq!(TcfCodec::parse_header(encoded).unwrap().dictionary.unwrap().hash, dictionary.short_hash());
+            assert_eq!(TcfCodec::decode_with_resolver(encoded, &dictionaries).unwrap(), *text);
+            assert_eq!(TcfCodec::decode_with_resolver(encoded, &dictionary).unwrap(), *text);
+            let streamed: Vec<u8> = TcfCodec::decode_stream_with_dictionary(encoded, &dictionary).unwrap().flat_map(Result::unwrap).collect();
+            assert_eq!(streamed, text.as_bytes());
+        }
+
+        // A file carrying its dictionary needs no resolver
+        let embedded = TcfEncodeOptions { embed_dictionary: true, ..detached };
+        let embedded = TcfCodec::encode_with_options(&texts[0], &embedded).unwrap().data;
+
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(TcfCodec::decode_with_resolver(&embedded, &dictionaries).unwrap(), texts[0]);
+        for encoded in &encoded {
+            for error in [TcfCodec::decode_with_resolver(encoded, &dictionaries).unwrap_err(), TcfCodec::decode(encoded).unwrap_err()] {
+                assert!(matches!(error.downcast_ref::<TcfError>(), Some(TcfError::MissingDictionary(hash)) if *hash == dictionary.short_hash()), "{:#}", error);
+                assert!(error.to_string().contains(&dictionary.short_hash()), "{}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_warnings() {
+        let warnings = |text: &str, options: &TcfEncodeOptions| TcfCodec::encode_with_options(text, options).unwrap().warnings;
+        let plain = "Plain ASCII text with nothing unusual in it.\n".repeat(50);
+        assert_eq!(warnings(&plain, &TcfEncodeOptions::default()), []);
+
+        let auto = TcfEncodeOptions { method: None, ..Default::default() };
+        assert_eq!(warnings(&format!("PK\x03\x04{}", plain), &auto), [CodecWarning::StoredFallback {
+            reason: CompressibilityHint::AlreadyCompressed("zip"),
+        }]);
+
+        let json = TcfEncodeOptions { tokenizer_id: JsonAwareTokenizer::ID.to_string(), ..Default::default() };
+        assert_eq!(warnings(r#"["\u00e9\u00e8", 1]"#, &json), [CodecWarning::EscapeHeavy { fraction: 12.0 / 14.0 }]);
+        assert_eq!(warnings(r#"["caf\u00e9 au lait, s'il vous plait", 1]"#, &json), []);
+
+        // Past the coder's total, counts are scaled down
+        let flat = "a".repeat(MAX_TOTAL as usize + 1);
+        assert_eq!(warnings(&flat, &TcfEncodeOptions::default()), [CodecWarning::ModelRescaled { models: 1 }]);
+
+        // The NFC step passes text through for now, so exercise the count directly
+        assert_eq!(changed_chars("cafe\u{301} au lait", "caf\u{e9} au lait"), 2);
+        assert_eq!(changed_chars("same", "same"), 0);
+        assert_eq!(changed_chars("aa", "a"), 1);
+
+        let mut merged = Vec::new();
+        for models in [1, 2] {
+            CodecWarning::add(&mut merged, CodecWarning::ModelRescaled { models });
+        }
+        assert_eq!(merged, [CodecWarning::ModelRescaled { models: 3 }]);
+    }
+
     #[test]
     fn test_tcf_error_cases() {
         // Too small data
@@ -287,19 +2782,132 @@ mod tests {
         data.extend_from_slice(b"corrupted"); // Only 9 bytes
         assert!(TcfCodec::decode(&data).is_err());
     }
+
+    #[test]
+    fn test_error_codes_tell_failures_apart() {
+        let text = "error code matrix\r\n".repeat(40);
+        let code = |data: &[u8]| TcfErrorCode::of(&TcfCodec::decode(data).unwrap_err());
+        let encode = |method, newline| {
+            let options = TcfEncodeOptions { method: Some(method), newline, ..Default::default() };
+            TcfCodec::encode_with_options(&text, &options).unwrap().data
+        };
+        let arithmetic = encode(TcfMethod::Arithmetic, NewlinePolicy::NormalizeLfRecordPositions);
+
+        assert_eq!(code(b"GIF89a\0\0\0\0"), TcfErrorCode::InvalidFormat);
+        assert_eq!(code(&with_header(&arithmetic, |header| header.version = TcfCodec::VERSION + 1)), TcfErrorCode::UnsupportedVersion);
+        assert_eq!(code(&with_header(&arithmetic, |header| header.compression_method = "brotli".to_string())), TcfErrorCode::UnsupportedMethod);
+        assert_eq!(code(&with_header(&arithmetic, |header| header.model_params.tokenizer_id = "bpe".to_string())), TcfErrorCode::UnsupportedMethod);
+        let blake3 = with_header(&arithmetic, |header| header.checksum = format!("blake3:{}", header.checksum));
+        assert_eq!(code(&blake3), TcfErrorCode::UnsupportedChecksum);
+
+        // Cut at and just inside every section: magic, header length, header, model, payload, newlines
+        let layout = TcfCodec::parse_layout(&arithmetic).unwrap();
+        assert_eq!(layout.regions.len(), 6);
+        for region in &layout.regions {
+            for cut in [region.offset, region.offset + region.length / 2] {
+                assert_eq!(code(&arithmetic[..cut as usize]), TcfErrorCode::Truncated, "cut at {} in {}", cut, region.name);
+            }
+        }
+
+        // A flipped payload bit either fails to decode or fails the checksum
+        let chunked = TcfCodec::encode_with_options(&text, &TcfEncodeOptions {
+            chunking: Some(ChunkStrategy::FixedBytes(64)),
+            ..Default::default()
+        }).unwrap().data;
+        for method in [TcfMethod::Arithmetic, TcfMethod::Gzip, TcfMethod::Stored] {
+            let mut flipped = encode(method, NewlinePolicy::Preserve);
+            let payload = TcfCodec::parse_layout(&flipped).unwrap().region("payload").unwrap().clone();
+            flipped[(payload.offset + payload.length / 2) as usize] ^= 0x10;
+            assert_eq!(code(&flipped), TcfErrorCode::ChecksumMismatch, "{}", method);
+        }
+        let mut flipped = chunked.clone();
+        let last = flipped.len() - 3;
+        flipped[last] ^= 0x10;
+        assert_eq!(code(&flipped), TcfErrorCode::ChecksumMismatch);
+        let error = TcfCodec::decode_reader(&chunked[..chunked.len() - 3]).unwrap()
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(TcfErrorCode::of(&error), TcfErrorCode::Truncated);
+    }
+
+    #[test]
+    fn test_chunks_pick_their_own_methods() {
+        const CHUNK: usize = 1024;
+        let prose = "It was the best of times, it was the worst of times, it was the age of wisdom. ";
+        // ASCII and two to four byte characters in proportions that spread
+        // their bytes evenly: about 7.7 bits per byte
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |bound: u32| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) % bound as u64) as u32
+        };
+        let mut text = String::new();
+        for i in 0..3 {
+            let chapter = format!("Chapter {}. {}", i, prose.repeat(CHUNK / prose.len() + 1));
+            text.push_str(&chapter[..CHUNK]);
+            let noise_end = text.len() + CHUNK;
+            loop {
+                let c = match next(146) {
+                    0..=94 => char::from_u32(0x20 + next(95)),
+                    95..=124 => char::from_u32(0x80 + next(0x780)),
+                    125..=140 => char::from_u32(0x800 + next(0xf800)),
+                    _ => char::from_u32(0x10000 + next(0x100000)),
+                };
+                let Some(c) = c else { continue };
+                if text.len() + c.len_utf8() > noise_end {
+                    break;
+                }
+                text.push(c);
+            }
+            while text.len() < noise_end {
+                text.push('=');
+            }
+        }
+
+        let mixed = TcfEncodeOptions { method: None, chunking: Some(ChunkStrategy::FixedBytes(CHUNK)), ..Default::default() };
+        let encoded = TcfCodec::encode_with_options(&text, &mixed).unwrap().data;
+        let header = TcfCodec::parse_header(&encoded).unwrap();
+        let methods: Vec<&str> = header.chunks.iter().map(|chunk| chunk.compression_method.as_str()).collect();
+        assert_eq!(methods, ["gzip", "stored", "gzip", "stored", "gzip", "stored"]);
+        assert_eq!(header.compression_method, "mixed");
+        assert_eq!(TcfCodec::decode(&encoded).unwrap(), text);
+
+        for method in TcfMethod::available() {
+            let single = TcfEncodeOptions { method: Some(method), ..mixed.clone() };
+            let single = TcfCodec::encode_with_options(&text, &single).unwrap().data;
+            assert!(encoded.len() < single.len(), "mixed {} bytes, {} {} bytes", encoded.len(), method, single.len());
+        }
+    }
+
+    #[test]
+    fn test_windowed_tokens_match_whole_text() {
+        // Short tokens across many windows, then one string longer than a window
+        let records = r#"{"id":12,"name":"caf\u00e9 \"x\"","tags":["a-b",-3.5e2]},"#.repeat(3000);
+        let text = format!("[{}\"{}\",\"ünïcode\"]", records, "0123456789abcdef".repeat(TcfCodec::TOKEN_WINDOW / 8));
+        assert!(text.len() > 3 * TcfCodec::TOKEN_WINDOW);
+
+        for id in TOKENIZER_IDS {
+            let tokenizer = tokenizer_by_id(id).unwrap();
+            let mut windowed = Vec::new();
+            TcfCodec::for_each_token(tokenizer.as_ref(), &text, |token| {
+                windowed.push(token);
+                Ok(())
+            }).unwrap();
+            assert!(windowed == tokenizer.tokenize(&text), "{} tokens differ", id);
+        }
+    }
 }
 
 // Add missing unicode normalization trait
 trait UnicodeNormalization {
-    fn nfc(self) -> std::str::Chars<'static>;
+    fn nfc(self) -> Self;
 }
 
 impl UnicodeNormalization for std::str::Chars<'_> {
-    fn nfc(self) -> std::str::Chars<'static> {
+    fn nfc(self) -> Self {
         // For simplicity, we'll just return the chars as-is
         // In a real implementation, you'd use the unicode-normalization crate
         // This is a placeholder to make the code compile
-        let s: String = self.collect();
-        Box::leak(s.into_boxed_str()).chars()
+        self
     }
 }
\ No newline at end of file
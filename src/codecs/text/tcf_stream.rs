@@ -0,0 +1,578 @@
+use super::chunking::{ChunkReader, ChunkStrategy};
+use super::errors::TcfError;
+use super::newlines::NewlinePolicy;
+use super::tcf_codec::{escape_counts, ChunkDecoder, ChunkType, StreamVerifier, TcfChunk, TcfCodec, TcfEncodeOptions, TcfFlags, TcfHeader, TcfMethod, VerifyMode};
+use super::tokenizer::JsonAwareTokenizer;
+use super::warnings::CodecWarning;
+use crate::codecs::explain::ExplainTrace;
+use crate::codecs::trace::{phase, trace_event};
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Coded chunk data `encode_stream` keeps in memory before spilling it to
+/// a temporary file
+const SPOOL_MEMORY: usize = 1 << 20;
+
+static SPOOL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Header bytes `encode_stream_seekable` leaves ahead of the chunk data,
+/// enough for the index of a few hundred chunks
+pub const SEEKABLE_HEADER_RESERVE: u64 = 16 * 1024;
+
+impl TcfCodec {
+    /// Chunk size `encode_stream` cuts at when the options don't chunk, and
+    /// `transcode_gzip_to_tcf` cuts lines into
+    pub const STREAM_CHUNK_BYTES: usize = 1 << 20;
+
+    /// Encode text read from `source` into `sink` as a chunked TCF file
+    ///
+    /// The source is read a chunk at a time and never seeked, so it can be
+    /// a decompressor or a request body. The header leads the file and
+    /// indexes every chunk, so the coded chunks wait for the end of the
+    /// text: in memory up to 1 MiB, in a temporary file past that; a sink
+    /// that can seek is spared that by `encode_stream_seekable`. Options
+    /// without chunking get `FixedBytes(STREAM_CHUNK_BYTES)`; with it, the
+    /// file is the one `encode_with_options` makes from the whole text,
+    /// though the escape-heavy warning is judged chunk by chunk.
+    pub fn encode_stream<R: Read, W: Write>(source: R, mut sink: W, options: &TcfEncodeOptions) -> Result<Vec<CodecWarning>> {
+        phase!("tcf.encode_stream", tokenizer = %options.tokenizer_id);
+        let mut spool = Spool::default();
+        let (mut header, warnings) = Self::code_stream(source, options, |data| spool.write(data))?;
+
+        let header_json = Self::padded_header(&mut header, spool.len, &options.pad_to)?;
+        sink.write_all(Self::MAGIC.as_bytes())?;
+        sink.write_all(&(header_json.len() as u32).to_le_bytes())?;
+        sink.write_all(&header_json)?;
+        spool.copy_to(&mut sink)?;
+        std::io::copy(&mut std::io::repeat(0).take(header.padding), &mut sink)?;
+        sink.flush()?;
+        trace_event!(chunks = header.chunks.len(), bytes_in = header.original_size, "streamed TCF");
+        Ok(warnings)
+    }
+
+    /// Like `encode_stream`, writing the coded chunks straight to `sink`
+    /// and the header over the space left for it once they are all out
+    ///
+    /// The file starts at the sink's current position. The header gets
+    /// `SEEKABLE_HEADER_RESERVE` bytes, its JSON followed by spaces; one
+    /// that outgrows them has the chunk data moved up to make room, which
+    /// is why the sink must also read. Apart from those spaces the file is
+    /// the one `encode_stream` writes.
+    pub fn encode_stream_seekable<R: Read, W: Read + Write + Seek>(source: R, mut sink: W, options: &TcfEncodeOptions) -> Result<Vec<CodecWarning>> {
+        phase!("tcf.encode_stream", tokenizer = %options.tokenizer_id, seekable = true);
+        let start = sink.stream_position()?;
+        let data_start = start + 8 + SEEKABLE_HEADER_RESERVE;
+        sink.seek(SeekFrom::Start(data_start))?;
+        let (mut header, warnings) = Self::code_stream(source, options, |data| {
+            sink.write_all(data).context("Failed to write TCF chunk data")
+        })?;
+
+        // The reserve absorbs the header's length, so padding only needs
+        // working out again if the header outgrows it
+        let mut reserve = SEEKABLE_HEADER_RESERVE;
+        let header_json = loop {
+            let unpadded = 8 + reserve + header.compressed_size;
+            header.padding = options.pad_to.padded_size(unpadded)? - unpadded;
+            let header_json = serde_json::to_vec(&header).context("Failed to serialize TCF header")?;
+            if header_json.len() as u64 <= reserve {
+                break header_json;
+            }
+            reserve = header_json.len() as u64;
+        };
+        let body_start = start + 8 + reserve;
+        if body_start != data_start {
+            shift_forward(&mut sink, data_start, body_start, header.compressed_size)?;
+        }
+
+        sink.seek(SeekFrom::Start(body_start + header.compressed_size))?;
+        std::io::copy(&mut std::io::repeat(0).take(header.padding), &mut sink)?;
+        sink.seek(SeekFrom::Start(start))?;
+        sink.write_all(Self::MAGIC.as_bytes())?;
+        sink.write_all(&(reserve as u32).to_le_bytes())?;
+        sink.write_all(&header_json)?;
+        std::io::copy(&mut std::io::repeat(b' ').take(reserve - header_json.len() as u64), &mut sink)?;
+        sink.seek(SeekFrom::Start(body_start + header.compressed_size + header.padding))?;
+        sink.flush()?;
+        trace_event!(chunks = header.chunks.len(), bytes_in = header.original_size, header_bytes = reserve, "streamed TCF");
+        Ok(warnings)
+    }
+
+    /// Code the chunks of the text read from `source`, handing each one's
+    /// data to `emit` in order, and return the header indexing them,
+    /// without padding
+    fn code_stream<R: Read>(source: R, options: &TcfEncodeOptions, mut emit: impl FnMut(&[u8]) -> Result<()>) -> Result<(TcfHeader, Vec<CodecWarning>)> {
+        if options.newline != NewlinePolicy::Preserve {
+            bail!("Newline normalization can't be combined with chunking");
+        }
+        if options.dictionary.is_some() {
+            bail!("Dictionary substitution can't be combined with chunking");
+        }
+        let strategy = options.chunking.unwrap_or(ChunkStrategy::FixedBytes(Self::STREAM_CHUNK_BYTES));
+        let tokenizer = Self::tokenizer(&options.tokenizer_id)?;
+        Self::check_max_order(options.max_order, tokenizer.as_ref())?;
+        let json = tokenizer.id() == JsonAwareTokenizer::ID;
+
+        let mut reader = ChunkReader::new(source, strategy)?;
+        let mut warnings = Vec::new();
+        let mut chunks: Vec<TcfChunk> = Vec::new();
+        // Chunks are matched on a digest of their text, which isn't kept
+        let mut seen: HashMap<[u8; 32], usize> = HashMap::new();
+        let mut hasher = Sha256::new();
+        let (mut original_size, mut data_size, mut escaped, mut total) = (0, 0, 0, 0);
+
+        while let Some(chunk) = reader.next_chunk()? {
+            let bytes = chunk.text.as_bytes();
+            hasher.update(bytes);
+            original_size += bytes.len() as u64;
+            if json {
+                let (chunk_escaped, chunk_total) = escape_counts(chunk.text);
+                escaped += chunk_escaped;
+                total += chunk_total;
+            }
+
+            let digest: [u8; 32] = Sha256::digest(bytes).into();
+            if let Some(&original) = seen.get(&digest) {
+                chunks.push(TcfChunk {
+                    text_offset: chunk.text_offset,
+                    data_offset: 0,
+                    model_size: 0,
+                    compressed_size: 0,
+                    first_record: chunk.first_record,
+                    chunk_type: ChunkType::Reference(original as u64),
+                    ..chunks[original].clone()
+                });
+                continue;
+            }
+            seen.insert(digest, chunks.len());
+
+            let (method, model_data, compressed_data) = Self::code_text(chunk.text, options.method, tokenizer.as_ref(), options.max_order, &mut warnings, &mut ExplainTrace::default())?;
+            chunks.push(TcfChunk {
+                text_offset: chunk.text_offset,
+                original_size: bytes.len() as u64,
+                data_offset: data_size,
+                model_size: model_data.len() as u32,
+                compressed_size: compressed_data.len() as u64,
+                compression_method: method.as_str().to_string(),
+                first_record: chunk.first_record,
+                crc32: crc32fast::hash(bytes),
+                chunk_type: ChunkType::Data,
+            });
+            emit(&model_data)?;
+            emit(&compressed_data)?;
+            data_size += (model_data.len() + compressed_data.len()) as u64;
+        }
+
+        let method = Self::chunked_method(&chunks, options.method)?;
+        let (flags, model_params) = Self::coding_flags(method, Some(&chunks), tokenizer.as_ref(), options.max_order);
+        if flags & TcfFlags::ADAPTIVE_MODEL != 0 && json {
+            Self::warn_escape_heavy(escaped, total, &mut warnings);
+        }
+        let header = TcfHeader {
+            magic: Self::MAGIC.to_string(),
+            version: Self::VERSION,
+            flags,
+            original_size,
+            compressed_size: data_size,
+            checksum: format!("{:x}", hasher.finalize()),
+            model_size: 0,
+            compression_method: method.map_or("mixed", TcfMethod::as_str).to_string(),
+            model_params,
+            chunking: Some(strategy),
+            chunks,
+            newlines: None,
+            dictionary: None,
+            migrated_from: None,
+            source_encoding: None,
+            padding: 0,
+        };
+        Ok((header, warnings))
+    }
+
+    /// Decode a TCF file read from `source` one chunk at a time
+    ///
+    /// Like `decode_stream`, but never holds more of a chunked file than one
+    /// chunk's data, plus that of chunks later ones refer back to. Chunk
+    /// data must be in index order, as encoders write it; unchunked files
+    /// are read whole.
+    pub fn decode_reader<R: Read>(mut source: R) -> Result<TcfReadStream<R>> {
+        let mut prefix = Vec::with_capacity(8);
+        (&mut source).take(8).read_to_end(&mut prefix)?;
+        if prefix.len() == 8 {
+            let header_size = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as u64;
+            (&mut source).take(header_size).read_to_end(&mut prefix)?;
+        }
+        let header = Self::parse_header(&prefix)?;
+        Self::check_readable(&header)?;
+
+        let expected = header.checksum.clone();
+        let body = if header.flags & TcfFlags::CHUNKED != 0 {
+            Self::check_chunk_offsets(&header)?;
+            // Where each chunk that others refer to is needed for the last time
+            let mut last_use = HashMap::new();
+            for (index, chunk) in header.chunks.iter().enumerate() {
+                if let ChunkType::Reference(target) = chunk.chunk_type {
+                    last_use.insert(target as usize, index);
+                }
+            }
+            ReadBody::Chunked { chunks: Box::new(ChunkDecoder::new(header, &[])), next: 0, position: 0, kept: HashMap::new(), last_use }
+        } else {
+            ReadBody::Unchunked(Some(header))
+        };
+        Ok(TcfReadStream { source, body, verifier: StreamVerifier::new(expected), finished: false })
+    }
+
+    /// Decode a TCF file read from `source` into `sink`, returning the
+    /// length of the text
+    ///
+    /// The checksum is only known once the last chunk is written, so on a
+    /// mismatch `sink` has already seen the text.
+    pub fn decode_into<R: Read, W: Write>(source: R, mut sink: W) -> Result<u64> {
+        let mut written = 0;
+        for piece in Self::decode_reader(source)? {
+            let piece = piece?;
+            sink.write_all(&piece)?;
+            written += piece.len() as u64;
+        }
+        sink.flush()?;
+        Ok(written)
+    }
+}
+
+/// Recode a gzip stream of UTF-8 text as a TCF file chunked on lines
+///
+/// Neither side is held whole; see `TcfCodec::encode_stream`. Each chunk
+/// keeps whichever method codes it smallest, gzip included, so the file
+/// is rarely larger than the gzip stream was.
+pub fn transcode_gzip_to_tcf<R: Read, W: Write>(gzip: R, tcf: W) -> Result<Vec<CodecWarning>> {
+    let options = TcfEncodeOptions {
+        method: None,
+        chunking: Some(ChunkStrategy::OnDelimiter(b'\n', TcfCodec::STREAM_CHUNK_BYTES)),
+        ..Default::default()
+    };
+    TcfCodec::encode_stream(MultiGzDecoder::new(gzip), tcf, &options)
+}
+
+/// Decode a TCF file into a gzip stream a chunk at a time, returning the
+/// length of the text
+pub fn transcode_tcf_to_gzip<R: Read, W: Write>(tcf: R, gzip: W) -> Result<u64> {
+    let mut encoder = GzEncoder::new(gzip, Compression::default());
+    let written = TcfCodec::decode_into(tcf, &mut encoder)?;
+    encoder.finish().context("Failed to finish gzip stream")?;
+    Ok(written)
+}
+
+/// Iterator over the decoded pieces of a TCF file, from `TcfCodec::decode_reader`
+pub struct TcfReadStream<R> {
+    source: R,
+    body: ReadBody,
+    verifier: StreamVerifier,
+    finished: bool,
+}
+
+enum ReadBody {
+    /// Header, until the payload is read and decoded
+    Unchunked(Option<TcfHeader>),
+    Chunked {
+        chunks: Box<ChunkDecoder<'static>>,
+        /// Index of the next chunk
+        next: usize,
+        /// Bytes of chunk data read so far
+        position: u64,
+        /// Data of chunks that later chunks refer to
+        kept: HashMap<usize, Vec<u8>>,
+        last_use: HashMap<usize, usize>,
+    },
+}
+
+impl<R: Read> TcfReadStream<R> {
+    /// Check pieces under `mode` instead of `VerifyMode::Full`
+    pub fn verify(mut self, mode: VerifyMode) -> Self {
+        self.verifier.set_mode(mode);
+        if let ReadBody::Chunked { chunks, .. } = &mut self.body {
+            chunks.check_chunks = mode != VerifyMode::Off;
+        }
+        self
+    }
+
+    fn next_piece(&mut self) -> Option<Result<Vec<u8>>> {
+        match &mut self.body {
+            ReadBody::Unchunked(header) => {
+                let header = header.take()?;
+                let mut data = Vec::new();
+                Some(self.source.read_to_end(&mut data)
+                    .context("Failed to read TCF data")
+                    .and_then(|_| TcfCodec::decode_unchunked(&header, &data, None)))
+            }
+            ReadBody::Chunked { chunks, next, .. } if *next == chunks.text_chunks().len() => None,
+            ReadBody::Chunked { chunks, next, position, kept, last_use } => {
+                let index = *next;
+                *next += 1;
+                Some(Self::read_chunk(&mut self.source, chunks, index, position, kept, last_use))
+            }
+        }
+    }
+
+    fn read_chunk(
+        source: &mut R,
+        chunks: &ChunkDecoder<'static>,
+        index: usize,
+        position: &mut u64,
+        kept: &mut HashMap<usize, Vec<u8>>,
+        last_use: &HashMap<usize, usize>,
+    ) -> Result<Vec<u8>> {
+        let data_index = chunks.data_index(index)?;
+        if data_index != index {
+            let data = kept.get(&data_index).context("TCF chunk refers to data that wasn't kept")?;
+            let bytes = chunks.decode_data(data_index, data)?;
+            if last_use.get(&data_index) == Some(&index) {
+                kept.remove(&data_index);
+            }
+            return Ok(bytes);
+        }
+
+        let chunk = &chunks.chunks()[index];
+        let gap = chunk.data_offset.checked_sub(*position)
+            .with_context(|| format!("TCF chunk {} data is out of order; decode the file from memory instead", index))?;
+        let size = chunk.data_size().with_context(|| format!("TCF chunk {} is too large", index))?;
+        io::copy(&mut source.take(gap), &mut io::sink())?;
+        let mut data = Vec::with_capacity(size);
+        source.take(size as u64).read_to_end(&mut data)?;
+        TcfError::check_length("chunk data", size as u64, &data)?;
+        *position = chunk.data_offset + size as u64;
+
+        let bytes = chunks.decode_data(index, &data)?;
+        if last_use.contains_key(&index) {
+            kept.insert(index, data);
+        }
+        Ok(bytes)
+    }
+}
+
+impl<R: Read> Iterator for TcfReadStream<R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>>> {
+        if self.finished {
+            return None;
+        }
+        let whole = matches!(self.body, ReadBody::Unchunked(_));
+        match self.next_piece().map(|piece| piece.and_then(|bytes| self.verifier.check(&bytes, whole).map(|_| bytes))) {
+            Some(Ok(bytes)) => Some(Ok(bytes)),
+            Some(Err(error)) => {
+                self.finished = true;
+                Some(Err(error))
+            }
+            None => {
+                self.finished = true;
+                self.verifier.finish()
+            }
+        }
+    }
+}
+
+/// Move the `len` bytes at `from` in `file` up to `to`, last block first
+/// so none is overwritten before it is read
+fn shift_forward<F: Read + Write + Seek>(file: &mut F, from: u64, to: u64, len: u64) -> Result<()> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let block = remaining.min(buffer.len() as u64);
+        remaining -= block;
+        let block = &mut buffer[..block as usize];
+        file.seek(SeekFrom::Start(from + remaining))?;
+        file.read_exact(block).context("Failed to read back TCF chunk data")?;
+        file.seek(SeekFrom::Start(to + remaining))?;
+        file.write_all(block).context("Failed to move TCF chunk data")?;
+    }
+    Ok(())
+}
+
+/// Coded chunks waiting for the header, in memory until they outgrow
+/// `SPOOL_MEMORY` and in a temporary file after that
+#[derive(Default)]
+struct Spool {
+    memory: Vec<u8>,
+    file: Option<(File, PathBuf)>,
+    len: u64,
+}
+
+impl Spool {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.len += data.len() as u64;
+        if self.file.is_none() && self.memory.len() + data.len() <= SPOOL_MEMORY {
+            self.memory.extend_from_slice(data);
+            return Ok(());
+        }
+        if self.file.is_none() {
+            let path = std::env::temp_dir().join(format!(
+                "tcf-spool-{}-{}", std::process::id(), SPOOL_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            file.write_all(&std::mem::take(&mut self.memory)).context("Failed to spool TCF chunk data")?;
+            self.file = Some((file, path));
+        }
+        let (file, _) = self.file.as_mut().expect("created above");
+        file.write_all(data).context("Failed to spool TCF chunk data")
+    }
+
+    fn copy_to<W: Write>(&mut self, sink: &mut W) -> Result<()> {
+        sink.write_all(&self.memory)?;
+        if let Some((file, _)) = &mut self.file {
+            file.seek(SeekFrom::Start(0))?;
+            io::copy(file, sink)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Some((file, path)) = self.file.take() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::padding::PaddingPolicy;
+    use flate2::read::GzDecoder;
+
+    /// Hands out at most `step` bytes per read, as sockets and decompressors may
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.step).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn mixed_text() -> String {
+        let mut text = String::new();
+        for i in 0..3000 {
+            match i % 7 {
+                0 => text.push_str("\n\n"),
+                3 => text.push_str(&format!("ünïcødé → {} ✓\n", i)),
+                _ => text.push_str(&format!("{{\"seq\":{},\"msg\":\"line {}\"}}\n", i, i % 50)),
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn test_stream_encode_matches_whole_text_encode() {
+        let text = mixed_text();
+        let strategies = [
+            ChunkStrategy::OnDelimiter(b'\n', 4096),
+            ChunkStrategy::OnBlankLine(3000),
+            ChunkStrategy::FixedBytes(1001),
+        ];
+        for strategy in strategies {
+            for method in [Some(TcfMethod::Arithmetic), None] {
+                let options = TcfEncodeOptions { method, chunking: Some(strategy), ..Default::default() };
+                let whole = TcfCodec::encode_with_options(&text, &options).unwrap().data;
+                let mut streamed = Vec::new();
+                TcfCodec::encode_stream(Trickle { data: text.as_bytes(), step: 777 }, &mut streamed, &options).unwrap();
+                assert!(streamed == whole, "{:?} {:?}", strategy, method);
+
+                let decoded: Vec<u8> = TcfCodec::decode_reader(Trickle { data: &streamed, step: 333 }).unwrap()
+                    .flat_map(Result::unwrap)
+                    .collect();
+                assert!(decoded == text.as_bytes(), "{:?} {:?}", strategy, method);
+            }
+        }
+
+        let invalid = [b"valid start ".as_slice(), &[0xC3, 0x28], b" and more"].concat();
+        let error = TcfCodec::encode_stream(invalid.as_slice(), io::sink(), &TcfEncodeOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("byte 12"), "{}", error);
+    }
+
+    #[test]
+    fn test_seekable_encode_rewrites_header_over_reserve() {
+        let text = mixed_text();
+        // A few chunks fit the reserve; hundreds outgrow it
+        for (strategy, pad_to) in [
+            (ChunkStrategy::OnDelimiter(b'\n', 16 * 1024), PaddingPolicy::None),
+            (ChunkStrategy::FixedBytes(501), PaddingPolicy::None),
+            (ChunkStrategy::FixedBytes(501), PaddingPolicy::PowerOfTwo),
+        ] {
+            let options = TcfEncodeOptions { chunking: Some(strategy), pad_to: pad_to.clone(), ..Default::default() };
+            let mut streamed = Vec::new();
+            TcfCodec::encode_stream(text.as_bytes(), &mut streamed, &options).unwrap();
+            let mut sink = io::Cursor::new(b"prefix".to_vec());
+            sink.seek(SeekFrom::End(0)).unwrap();
+            TcfCodec::encode_stream_seekable(Trickle { data: text.as_bytes(), step: 777 }, &mut sink, &options).unwrap();
+            assert_eq!(sink.position(), sink.get_ref().len() as u64);
+            let file = &sink.get_ref()[6..];
+
+            // Read the header straight from the bytes rather than through the codec
+            let header_size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+            assert!(header_size as u64 >= SEEKABLE_HEADER_RESERVE);
+            let header: TcfHeader = serde_json::from_slice(&file[8..8 + header_size]).unwrap();
+            let expected = TcfCodec::parse_header(&streamed).unwrap();
+            assert!(header.chunks.len() > 3);
+            assert_eq!(header.chunks, expected.chunks);
+            assert_eq!((header.compressed_size, header.checksum.as_str()), (expected.compressed_size, expected.checksum.as_str()));
+            assert!(header.chunks[1..].iter().all(|chunk| chunk.data_offset > 0));
+
+            let body = &file[8 + header_size..];
+            let expected_body = &streamed[streamed.len() - expected.padding as usize - expected.compressed_size as usize..];
+            assert!(body[..header.compressed_size as usize] == expected_body[..expected.compressed_size as usize], "{:?}", strategy);
+            assert_eq!(body.len() as u64, header.compressed_size + header.padding);
+            if pad_to == PaddingPolicy::PowerOfTwo {
+                assert!(file.len().is_power_of_two(), "{} bytes", file.len());
+            }
+            assert_eq!(TcfCodec::decode(file).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_gzip_transcoding_keeps_references_and_detects_corruption() {
+        let text = mixed_text().repeat(3);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut tcf = Vec::new();
+        transcode_gzip_to_tcf(gzip.as_slice(), &mut tcf).unwrap();
+        assert_eq!(TcfCodec::decode(&tcf).unwrap(), text);
+
+        // Repeats of the text come out as references, which the reader must keep data for
+        let options = TcfEncodeOptions { chunking: Some(ChunkStrategy::FixedBytes(mixed_text().len())), ..Default::default() };
+        let mut repeated = Vec::new();
+        TcfCodec::encode_stream(text.as_bytes(), &mut repeated, &options).unwrap();
+        let header = TcfCodec::parse_header(&repeated).unwrap();
+        assert_eq!(header.chunks.iter().filter(|chunk| chunk.chunk_type == ChunkType::Reference(0)).count(), 2);
+
+        for tcf in [&tcf, &repeated] {
+            let mut regzipped = Vec::new();
+            assert_eq!(transcode_tcf_to_gzip(tcf.as_slice(), &mut regzipped).unwrap(), text.len() as u64);
+            let mut decoded = String::new();
+            GzDecoder::new(regzipped.as_slice()).read_to_string(&mut decoded).unwrap();
+            assert!(decoded == text);
+        }
+
+        let mut corrupt = tcf.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0x55;
+        assert!(TcfCodec::decode_into(corrupt.as_slice(), io::sink()).is_err());
+        assert!(TcfCodec::decode_into(&tcf[..tcf.len() - 10], io::sink()).is_err());
+    }
+}
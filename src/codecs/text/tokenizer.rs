@@ -0,0 +1,273 @@
+/// Kind of text a token holds; each class gets its own frequency model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    /// Raw text with no further structure
+    Byte,
+    /// Letters, digits and underscores
+    Word,
+    Whitespace,
+    /// Anything that isn't a word, number, string or whitespace
+    Punctuation,
+    /// A quoted JSON string, quotes and escapes included
+    String,
+    Number,
+}
+
+/// A slice of the input tagged with its class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub class: TokenClass,
+    pub text: &'a str,
+}
+
+impl<'a> Token<'a> {
+    fn new(class: TokenClass, text: &'a str) -> Self {
+        Self { class, text }
+    }
+}
+
+/// Splits text into classed tokens for the TCF arithmetic coder
+///
+/// Tokens must cover the input exactly, in order, so that `detokenize`
+/// reproduces it byte for byte.
+pub trait Tokenizer: Send + Sync {
+    /// Identifier stored in `ModelParams::tokenizer_id`
+    fn id(&self) -> &'static str;
+
+    /// Every class this tokenizer emits; a class's position is its coded index
+    fn classes(&self) -> &'static [TokenClass];
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>>;
+
+    fn detokenize(&self, tokens: &[Token<'_>]) -> String {
+        tokens.iter().map(|token| token.text).collect()
+    }
+}
+
+/// The whole input as a single token: plain order-0 byte coding
+pub struct ByteTokenizer;
+
+impl ByteTokenizer {
+    pub const ID: &'static str = "byte";
+}
+
+impl Tokenizer for ByteTokenizer {
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn classes(&self) -> &'static [TokenClass] {
+        &[TokenClass::Byte]
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>> {
+        if text.is_empty() {
+            Vec::new()
+        } else {
+            vec![Token::new(TokenClass::Byte, text)]
+        }
+    }
+}
+
+/// Words, whitespace runs and punctuation runs
+pub struct WordTokenizer;
+
+impl WordTokenizer {
+    pub const ID: &'static str = "word";
+}
+
+impl Tokenizer for WordTokenizer {
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn classes(&self) -> &'static [TokenClass] {
+        &[TokenClass::Word, TokenClass::Whitespace, TokenClass::Punctuation]
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>> {
+        split_runs(text, |c| {
+            if is_word_char(c) {
+                TokenClass::Word
+            } else if c.is_whitespace() {
+                TokenClass::Whitespace
+            } else {
+                TokenClass::Punctuation
+            }
+        })
+    }
+}
+
+/// JSON strings, numbers, bare words, whitespace and punctuation
+///
+/// Works on any text; input that isn't JSON just produces less useful
+/// tokens. An unterminated string runs to the end of the input.
+pub struct JsonAwareTokenizer;
+
+impl JsonAwareTokenizer {
+    pub const ID: &'static str = "json";
+}
+
+impl Tokenizer for JsonAwareTokenizer {
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn classes(&self) -> &'static [TokenClass] {
+        &[
+            TokenClass::String,
+            TokenClass::Number,
+            TokenClass::Word,
+            TokenClass::Whitespace,
+            TokenClass::Punctuation,
+        ]
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<Token<'a>> {
+        let bytes = text.as_bytes();
+        let mut tokens = Vec::new();
+        let mut start = 0;
+
+        while start < bytes.len() {
+            let first = bytes[start];
+            let next_is_digit = bytes.get(start + 1).is_some_and(u8::is_ascii_digit);
+            let (class, end) = if first == b'"' {
+                (TokenClass::String, string_end(bytes, start))
+            } else if first.is_ascii_digit() || (first == b'-' && next_is_digit) {
+                let end = run_end(bytes, start + 1, |b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'));
+                (TokenClass::Number, end)
+            } else if is_ascii_word_byte(first) {
+                (TokenClass::Word, run_end(bytes, start, is_ascii_word_byte))
+            } else if first.is_ascii_whitespace() {
+                (TokenClass::Whitespace, run_end(bytes, start, |b| b.is_ascii_whitespace()))
+            } else {
+                // Everything else, including non-ASCII outside strings, up to the next token start
+                let end = run_end(bytes, start + 1, |b| {
+                    !(b == b'"' || b == b'-' || is_ascii_word_byte(b) || b.is_ascii_whitespace())
+                });
+                (TokenClass::Punctuation, end)
+            };
+
+            tokens.push(Token::new(class, &text[start..end]));
+            start = end;
+        }
+
+        tokens
+    }
+}
+
+/// Built-in tokenizer ids, in the order `tcf-cli` lists them
+pub const TOKENIZER_IDS: &[&str] = &[ByteTokenizer::ID, WordTokenizer::ID, JsonAwareTokenizer::ID];
+
+/// Look up a built-in tokenizer by its stored id
+pub fn tokenizer_by_id(id: &str) -> Option<Box<dyn Tokenizer>> {
+    match id {
+        ByteTokenizer::ID => Some(Box::new(ByteTokenizer)),
+        WordTokenizer::ID => Some(Box::new(WordTokenizer)),
+        JsonAwareTokenizer::ID => Some(Box::new(JsonAwareTokenizer)),
+        _ => None,
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_ascii_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Split `text` wherever the class of consecutive characters changes
+fn split_runs(text: &str, classify: impl Fn(char) -> TokenClass) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut current: Option<(TokenClass, usize)> = None;
+
+    for (index, c) in text.char_indices() {
+        let class = classify(c);
+        match current {
+            Some((current_class, _)) if current_class == class => {}
+            Some((current_class, start)) => {
+                tokens.push(Token::new(current_class, &text[start..index]));
+                current = Some((class, index));
+            }
+            None => current = Some((class, index)),
+        }
+    }
+    if let Some((class, start)) = current {
+        tokens.push(Token::new(class, &text[start..]));
+    }
+
+    tokens
+}
+
+/// End of the run of bytes matching `matches` starting at `start`
+///
+/// `matches` only ever accepts ASCII or only rejects ASCII, so runs end on
+/// character boundaries.
+fn run_end(bytes: &[u8], start: usize, matches: impl Fn(u8) -> bool) -> usize {
+    bytes[start..].iter()
+        .position(|&b| !matches(b))
+        .map_or(bytes.len(), |offset| start + offset)
+}
+
+/// Index just past the closing quote of the string opening at `start`
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut index = start + 1;
+    while index < bytes.len() {
+        match bytes[index] {
+            // Skip the escaped byte; it's ASCII in valid JSON, and any
+            // multi-byte character still ends at a later boundary
+            b'\\' => index += 2,
+            b'"' => return index + 1,
+            _ => index += 1,
+        }
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: &[&str] = &[
+        "",
+        "plain words, and punctuation!",
+        r#"{"msg":"café \"quoted\" \\","n":-12.5e3,"ok":true,"list":[1,2]}"#,
+        "unterminated \"string with é and \\",
+        "naïve -x 3-4 ✨ trailing\\",
+    ];
+
+    #[test]
+    fn test_tokenizers_are_lossless() {
+        for id in TOKENIZER_IDS {
+            let tokenizer = tokenizer_by_id(id).unwrap();
+            assert_eq!(tokenizer.id(), *id);
+            for sample in SAMPLES {
+                let tokens = tokenizer.tokenize(sample);
+                assert!(tokens.iter().all(|token| !token.text.is_empty()));
+                assert!(tokens.iter().all(|token| tokenizer.classes().contains(&token.class)));
+                assert_eq!(tokenizer.detokenize(&tokens), *sample, "{} tokenizer", id);
+            }
+        }
+        assert!(tokenizer_by_id("bpe").is_none());
+    }
+
+    #[test]
+    fn test_json_tokens() {
+        let tokens = JsonAwareTokenizer.tokenize(r#"{"a\"b": -1.5e2, "c":null}"#);
+        let classes: Vec<(TokenClass, &str)> = tokens.iter().map(|token| (token.class, token.text)).collect();
+        assert_eq!(classes, [
+            (TokenClass::Punctuation, "{"),
+            (TokenClass::String, r#""a\"b""#),
+            (TokenClass::Punctuation, ":"),
+            (TokenClass::Whitespace, " "),
+            (TokenClass::Number, "-1.5e2"),
+            (TokenClass::Punctuation, ","),
+            (TokenClass::Whitespace, " "),
+            (TokenClass::String, r#""c""#),
+            (TokenClass::Punctuation, ":"),
+            (TokenClass::Word, "null"),
+            (TokenClass::Punctuation, "}"),
+        ]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Golden vectors for the arithmetic coder bitstream
+//!
+//! Other decoders (the wasm/JS client, edge workers) have to match
+//! `ArithmeticCoder` bit for bit, so the bitstream is specified here and
+//! pinned by `CODER_VECTORS`. A change to the coder that alters any vector
+//! is a format change: update the vectors on purpose, never to make the
+//! tests pass.
+//!
+//! # Bitstream
+//!
+//! The coder keeps 32-bit registers `low = 0` and `high = 2^32 - 1`, a
+//! count of pending bits starting at 0, and writes bits MSB first.
+//! `QUARTER = 2^30`, `HALF = 2^31`. A symbol is a half-open range
+//! `[sym_low, sym_high)` of a frequency total `1 <= total <= 2^24`; all
+//! arithmetic is exact integer arithmetic with truncating division.
+//!
+//! Encoding a symbol:
+//!
+//! 1. `range = high - low + 1`, then `high = low + range * sym_high / total - 1`
+//!    and `low = low + range * sym_low / total`, both from the old `low`.
+//! 2. While `high < HALF` or `low >= HALF`: if `high < HALF` write 0 and
+//!    then one 1 per pending bit; otherwise write 1 and then one 0 per
+//!    pending bit, and subtract `HALF` from `low` and `high`. Either way
+//!    clear the pending count, then `low = 2 * low` and `high = 2 * high + 1`.
+//! 3. While `low >= QUARTER` and `high < 3 * QUARTER`: add one pending bit,
+//!    then `low = 2 * (low - QUARTER)` and `high = 2 * (high - QUARTER) + 1`.
+//!
+//! Step 3 runs only after step 2 has finished, and never hands back to it
+//! for the same symbol. Pending bits carry an undecided bit forward: their
+//! value is the opposite of the next bit step 2 writes.
+//!
+//! Flushing after the last symbol: add one pending bit; if `low < QUARTER`
+//! write 0 and the pending bits as 1s, otherwise write 1 and the pending
+//! bits as 0s. Pad the last byte with 0 bits. There is no end-of-stream
+//! symbol: the symbol count travels out of band (a TCF header's
+//! `original_size`), and a stream of no symbols is still flushed.
+//!
+//! Decoding keeps the same `low` and `high` plus a 32-bit `value` read
+//! from the first 32 bits of input; bits past the end of the input read as
+//! 0. The next symbol is the one whose range holds
+//! `((value - low + 1) * total - 1) / range`. Consuming it repeats steps
+//! 1-3 without writing: step 2 subtracts `HALF` from `value` as well and
+//! shifts the next input bit into it, and step 3 maps `value` like `low`
+//! and shifts in the next bit.
+
+/// One encode case: a cumulative frequency table, the symbols coded with
+/// it, and the exact bytes `ArithmeticCoder` produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoderVector {
+    pub name: &'static str,
+    /// Symbol `i` occupies `[cumulative[i], cumulative[i + 1])`; the last
+    /// entry is the total
+    pub cumulative: &'static [u64],
+    pub symbols: &'static [usize],
+    pub encoded: &'static [u8],
+}
+
+impl CoderVector {
+    pub fn total(&self) -> u64 {
+        *self.cumulative.last().unwrap_or(&0)
+    }
+
+    /// The range of `symbol` as `(low, high)`
+    pub fn range(&self, symbol: usize) -> (u64, u64) {
+        (self.cumulative[symbol], self.cumulative[symbol + 1])
+    }
+}
+
+pub const CODER_VECTORS: &[CoderVector] = &[
+    // Flushing an empty stream
+    CoderVector { name: "empty", cumulative: &[0, 1, 2], symbols: &[], encoded: &[0x40] },
+    // A total of 1: coding never narrows the interval, so only the flush
+    // writes anything
+    CoderVector { name: "minimum-total", cumulative: &[0, 1], symbols: &[0, 0, 0, 0], encoded: &[0x40] },
+    CoderVector { name: "uniform", cumulative: &[0, 1, 2, 3, 4], symbols: &[0, 1, 2, 3, 3, 2, 1, 0], encoded: &[0x1b, 0xe4, 0x40] },
+    CoderVector { name: "skewed", cumulative: &[0, 60, 63, 64], symbols: &[0, 0, 1, 0, 0, 0, 2, 0, 0, 1], encoded: &[0xdb, 0x9b] },
+    // The middle half of the interval, over and over: every symbol leaves
+    // it straddling HALF inside the middle quarters, so pending bits pile
+    // up until the last symbol settles them
+    CoderVector { name: "pending-bits", cumulative: &[0, 1, 3, 4], symbols: &[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2], encoded: &[0x80, 0x05] },
+    // The largest total the coder takes, with a one-count symbol
+    CoderVector { name: "maximum-total", cumulative: &[0, 1, 1 << 24], symbols: &[1, 0, 1, 1, 0], encoded: &[0x00, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00] },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::text::{ArithmeticCoder, ArithmeticDecoder};
+
+    fn encode(vector: &CoderVector) -> (Vec<u8>, u64) {
+        let mut coder = ArithmeticCoder::new();
+        let mut most_pending = 0;
+        for &symbol in vector.symbols {
+            let (low, high) = vector.range(symbol);
+            coder.encode_symbol(low, high, vector.total()).unwrap();
+            most_pending = most_pending.max(coder.pending_bits());
+        }
+        (coder.finish(), most_pending)
+    }
+
+    #[test]
+    fn test_encoder_reproduces_vectors() {
+        for vector in CODER_VECTORS {
+            assert_eq!(encode(vector).0, vector.encoded, "{}", vector.name);
+        }
+        let most_pending = |name| encode(CODER_VECTORS.iter().find(|v| v.name == name).unwrap()).1;
+        assert!(most_pending("pending-bits") >= 12);
+        assert_eq!(most_pending("minimum-total"), 0);
+    }
+
+    #[test]
+    fn test_decoder_reads_vectors() {
+        for vector in CODER_VECTORS {
+            let mut decoder = ArithmeticDecoder::new(vector.encoded.to_vec());
+            for (position, &expected) in vector.symbols.iter().enumerate() {
+                let value = decoder.get_symbol_value(vector.total()).unwrap();
+                let symbol = vector.cumulative.windows(2).position(|range| (range[0]..range[1]).contains(&value)).unwrap();
+                assert_eq!(symbol, expected, "{} symbol {}", vector.name, position);
+                let (low, high) = vector.range(symbol);
+                decoder.decode_symbol(low, high, vector.total()).unwrap();
+            }
+        }
+    }
+}
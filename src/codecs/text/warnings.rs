@@ -0,0 +1,71 @@
+use crate::codecs::image::source_analysis::LossyFormat;
+use crate::codecs::text::sniff::CompressibilityHint;
+use serde::Serialize;
+use std::fmt;
+
+/// Something `TcfCodec::encode_with_options` did that the caller may not
+/// expect, returned alongside the encoded file, or something about an
+/// ICF encode's source from `lossy_source_warning`
+///
+/// The library never prints these; the CLIs show them as notes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CodecWarning {
+    /// Unicode normalization changed the text, so decoding returns
+    /// different bytes than were encoded
+    NormalizedInput { chars_changed: usize },
+    /// Automatic method selection stored the text uncompressed
+    StoredFallback { reason: CompressibilityHint },
+    /// Arithmetic models whose counts were scaled down to fit the coder,
+    /// approximating the statistics they were built from
+    ModelRescaled { models: usize },
+    /// Fraction of JSON string bytes that are backslash escapes, which the
+    /// json tokenizer codes literally
+    EscapeHeavy { fraction: f64 },
+    /// The image being encoded was decoded from a lossy format, so its
+    /// artifacts are coded on top of ICF's own
+    LossySource { format: LossyFormat, estimated_prior_quality: Option<u8> },
+}
+
+impl CodecWarning {
+    /// Add `warning`, folding it into an earlier warning of the same kind
+    ///
+    /// Chunked files would otherwise repeat a warning once per chunk.
+    /// Rescaled model counts add up; other kinds keep the first report.
+    pub(crate) fn add(warnings: &mut Vec<CodecWarning>, warning: CodecWarning) {
+        let earlier = warnings.iter_mut()
+            .find(|earlier| std::mem::discriminant(*earlier) == std::mem::discriminant(&warning));
+        match (earlier, warning) {
+            (Some(CodecWarning::ModelRescaled { models }), CodecWarning::ModelRescaled { models: more }) => *models += more,
+            (Some(_), _) => {}
+            (None, warning) => warnings.push(warning),
+        }
+    }
+}
+
+impl fmt::Display for CodecWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecWarning::NormalizedInput { chars_changed } => {
+                write!(f, "Unicode normalization changed {} characters; decoding returns the normalized text", chars_changed)
+            }
+            CodecWarning::StoredFallback { reason } => write!(f, "stored uncompressed: input looks {}", reason),
+            CodecWarning::ModelRescaled { models } => {
+                write!(f, "{} arithmetic models were scaled down to fit the coder, costing some compression", models)
+            }
+            CodecWarning::EscapeHeavy { fraction } => write!(
+                f,
+                "{:.0}% of JSON string bytes are escapes, coded literally by the json tokenizer",
+                fraction * 100.0
+            ),
+            CodecWarning::LossySource { format, estimated_prior_quality: Some(quality) } => write!(
+                f,
+                "source is {}, last compressed at about quality {}; its losses add to this encode's",
+                format, quality
+            ),
+            CodecWarning::LossySource { format, estimated_prior_quality: None } => {
+                write!(f, "source is {}; its losses add to this encode's", format)
+            }
+        }
+    }
+}
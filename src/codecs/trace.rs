@@ -0,0 +1,39 @@
+//! Optional `tracing` instrumentation for the codec pipelines
+//!
+//! Built with the `tracing` feature, each encode and decode phase runs in a
+//! debug-level span and reports its sizes as debug events. Without it these
+//! macros expand to nothing, so field expressions aren't even evaluated.
+
+/// Enter a debug span until the end of the enclosing block
+///
+/// Takes the same arguments as `tracing::debug_span!`.
+macro_rules! phase {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _phase = tracing::debug_span!($($args)*).entered();
+    };
+}
+
+/// Record a debug event in the current span
+///
+/// Takes the same arguments as `tracing::debug!`.
+macro_rules! trace_event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($args)*);
+    };
+}
+
+/// Report a diagnostic through `tracing` when enabled, otherwise through `log`
+///
+/// Only plain format arguments, since `log` has no structured fields.
+macro_rules! diagnostic {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($args)*);
+        #[cfg(not(feature = "tracing"))]
+        log::$level!($($args)*);
+    };
+}
+
+pub(crate) use {diagnostic, phase, trace_event};
@@ -0,0 +1,512 @@
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+
+use crate::codecs::video::frame::{Plane, VideoFrame};
+
+/// Preprocessing step applied to frames before they reach the encoder
+///
+/// A filter may drop frames, emit several frames per input, or hold frames
+/// back until `finish`. Frame size and rate changes must be reported through
+/// `output_size` and `output_fps` so later filters and the container header
+/// see the right values.
+pub trait FrameFilter {
+    /// Short description recorded in the container, e.g. `scale 320x240 bilinear`
+    fn describe(&self) -> String;
+
+    /// Frame rate of the filter's output for a given input rate
+    fn output_fps(&self, input_fps: f64) -> f64 {
+        input_fps
+    }
+
+    /// Frame size of the filter's output for a given input size
+    fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        (width, height)
+    }
+
+    /// Called once, before the first frame, with the incoming size and rate
+    fn configure(&mut self, _width: u32, _height: u32, _fps: f64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Process one input frame
+    fn push(&mut self, frame: VideoFrame) -> Result<Vec<VideoFrame>>;
+
+    /// Flush any frames still held at end of stream
+    fn finish(&mut self) -> Result<Vec<VideoFrame>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Ordered list of filters; frames pass through them first to last
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn FrameFilter>>,
+    configured: bool,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a filter to the end of the chain
+    pub fn with(mut self, filter: impl FrameFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Descriptions of every filter, in application order
+    pub fn describe(&self) -> Vec<String> {
+        self.filters.iter().map(|filter| filter.describe()).collect()
+    }
+
+    /// Frame rate after the whole chain
+    pub fn output_fps(&self, input_fps: f64) -> f64 {
+        self.filters.iter().fold(input_fps, |fps, filter| filter.output_fps(fps))
+    }
+
+    /// Run one frame through the chain; `fps` is the source frame rate
+    pub fn push(&mut self, frame: VideoFrame, fps: f64) -> Result<Vec<VideoFrame>> {
+        if !self.configured {
+            let (mut width, mut height, mut fps) = (frame.width, frame.height, fps);
+            for filter in &mut self.filters {
+                filter.configure(width, height, fps)?;
+                (width, height) = filter.output_size(width, height);
+                fps = filter.output_fps(fps);
+            }
+            self.configured = true;
+        }
+
+        let mut frames = vec![frame];
+        for filter in &mut self.filters {
+            let mut output = Vec::new();
+            for frame in frames {
+                output.extend(filter.push(frame)?);
+            }
+            frames = output;
+        }
+        Ok(frames)
+    }
+
+    /// Flush every filter, passing held frames through the rest of the chain
+    pub fn finish(&mut self) -> Result<Vec<VideoFrame>> {
+        let mut frames = Vec::new();
+        for filter in &mut self.filters {
+            let mut output = Vec::new();
+            for frame in frames {
+                output.extend(filter.push(frame)?);
+            }
+            output.extend(filter.finish()?);
+            frames = output;
+        }
+        Ok(frames)
+    }
+
+    /// Wrap a frame source so it yields filtered frames
+    pub fn apply<I>(self, frames: I, fps: f64) -> FilteredFrames<I::IntoIter>
+    where
+        I: IntoIterator<Item = Result<VideoFrame>>,
+    {
+        FilteredFrames {
+            source: frames.into_iter(),
+            chain: self,
+            fps,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+/// Iterator returned by `FilterChain::apply`
+pub struct FilteredFrames<I> {
+    source: I,
+    chain: FilterChain,
+    fps: f64,
+    pending: VecDeque<VideoFrame>,
+    finished: bool,
+}
+
+impl<I> Iterator for FilteredFrames<I>
+where
+    I: Iterator<Item = Result<VideoFrame>>,
+{
+    type Item = Result<VideoFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Some(Ok(frame));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let output = match self.source.next() {
+                Some(Ok(frame)) => self.chain.push(frame, self.fps),
+                Some(Err(error)) => Err(error),
+                None => {
+                    self.finished = true;
+                    self.chain.finish()
+                }
+            };
+            match output {
+                Ok(frames) => self.pending.extend(frames),
+                Err(error) => {
+                    self.finished = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+/// Changes the frame rate by dropping or repeating frames
+///
+/// Output frame `n` shows the input frame on screen at time `n / target_fps`,
+/// so `N` input frames become `ceil(N * target / source)` output frames.
+pub struct FpsConverter {
+    target_fps: f64,
+    ratio: f64,
+    input_index: u64,
+    emitted: u64,
+}
+
+impl FpsConverter {
+    pub fn new(target_fps: f64) -> Self {
+        Self { target_fps, ratio: 1.0, input_index: 0, emitted: 0 }
+    }
+}
+
+impl FrameFilter for FpsConverter {
+    fn describe(&self) -> String {
+        format!("fps {}", self.target_fps)
+    }
+
+    fn output_fps(&self, _input_fps: f64) -> f64 {
+        self.target_fps
+    }
+
+    fn configure(&mut self, _width: u32, _height: u32, fps: f64) -> Result<()> {
+        if !(self.target_fps > 0.0 && self.target_fps.is_finite()) {
+            bail!("Target frame rate must be positive, got {}", self.target_fps);
+        }
+        self.ratio = self.target_fps / fps;
+        Ok(())
+    }
+
+    fn push(&mut self, frame: VideoFrame) -> Result<Vec<VideoFrame>> {
+        self.input_index += 1;
+        // Small epsilon so exact ratios like 30 -> 15 don't round up a frame early
+        let due = (self.input_index as f64 * self.ratio - 1e-9).ceil() as u64;
+        let copies = due.saturating_sub(self.emitted);
+        self.emitted += copies;
+        Ok(vec![frame; copies as usize])
+    }
+}
+
+/// Blends each frame with the previous output where the two barely differ
+///
+/// Works per 8x8 luma block (and the matching 4x4 chroma blocks). The blend
+/// weight falls linearly from `strength` at zero difference to nothing once
+/// the mean absolute difference reaches `threshold`, so moving content is
+/// left alone.
+pub struct TemporalDenoise {
+    strength: f64,
+    threshold: f64,
+    previous: Option<VideoFrame>,
+}
+
+impl TemporalDenoise {
+    pub const DEFAULT_THRESHOLD: f64 = 12.0;
+
+    /// `strength` is the weight given to the previous frame for static blocks (0 to 1)
+    pub fn new(strength: f64) -> Self {
+        Self {
+            strength: strength.clamp(0.0, 1.0),
+            threshold: Self::DEFAULT_THRESHOLD,
+            previous: None,
+        }
+    }
+
+    fn blend_block(current: &mut Plane, previous: &Plane, x0: usize, y0: usize, size: usize, weight: f64) {
+        for y in y0..(y0 + size).min(current.height) {
+            for x in x0..(x0 + size).min(current.width) {
+                let blended = current.get(x, y) as f64 * (1.0 - weight) + previous.get(x, y) as f64 * weight;
+                current.set(x, y, blended.round() as u8);
+            }
+        }
+    }
+
+    fn mean_abs_difference(current: &Plane, previous: &Plane, x0: usize, y0: usize, size: usize) -> f64 {
+        let mut sum = 0u64;
+        let mut count = 0u64;
+        for y in y0..(y0 + size).min(current.height) {
+            for x in x0..(x0 + size).min(current.width) {
+                sum += (current.get(x, y) as i32 - previous.get(x, y) as i32).unsigned_abs() as u64;
+                count += 1;
+            }
+        }
+        sum as f64 / count.max(1) as f64
+    }
+}
+
+impl FrameFilter for TemporalDenoise {
+    fn describe(&self) -> String {
+        format!("denoise strength={:.2}", self.strength)
+    }
+
+    fn push(&mut self, mut frame: VideoFrame) -> Result<Vec<VideoFrame>> {
+        if let Some(previous) = &self.previous {
+            for block_y in (0..frame.height as usize).step_by(8) {
+                for block_x in (0..frame.width as usize).step_by(8) {
+                    let difference = Self::mean_abs_difference(&frame.planes[0], &previous.planes[0], block_x, block_y, 8);
+                    let weight = self.strength * (1.0 - difference / self.threshold).max(0.0);
+                    if weight <= 0.0 {
+                        continue;
+                    }
+                    Self::blend_block(&mut frame.planes[0], &previous.planes[0], block_x, block_y, 8, weight);
+                    for plane in 1..3 {
+                        Self::blend_block(&mut frame.planes[plane], &previous.planes[plane], block_x / 2, block_y / 2, 4, weight);
+                    }
+                }
+            }
+        }
+        self.previous = Some(frame.clone());
+        Ok(vec![frame])
+    }
+}
+
+/// Keeps a rectangle of each frame
+///
+/// `x` and `y` must be even so the chroma planes stay aligned with luma.
+pub struct Crop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Crop {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    fn crop_plane(plane: &Plane, x: usize, y: usize, width: usize, height: usize) -> Plane {
+        let mut output = Plane::new(width, height, 0);
+        for row in 0..height {
+            let source = (y + row) * plane.width + x;
+            output.data[row * width..(row + 1) * width].copy_from_slice(&plane.data[source..source + width]);
+        }
+        output
+    }
+}
+
+impl FrameFilter for Crop {
+    fn describe(&self) -> String {
+        format!("crop {}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+
+    fn output_size(&self, _width: u32, _height: u32) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn configure(&mut self, width: u32, height: u32, _fps: f64) -> Result<()> {
+        if !self.x.is_multiple_of(2) || !self.y.is_multiple_of(2) {
+            bail!("Crop offset {},{} must be even for 4:2:0 video", self.x, self.y);
+        }
+        if self.width == 0 || self.height == 0
+            || self.x + self.width > width || self.y + self.height > height
+        {
+            bail!("Crop {} does not fit in a {}x{} frame", self.describe(), width, height);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, frame: VideoFrame) -> Result<Vec<VideoFrame>> {
+        let (chroma_width, chroma_height) = VideoFrame::chroma_size(self.width, self.height);
+        let (x, y) = (self.x as usize, self.y as usize);
+        let [luma, u, v] = &frame.planes;
+        Ok(vec![VideoFrame {
+            width: self.width,
+            height: self.height,
+            planes: [
+                Self::crop_plane(luma, x, y, self.width as usize, self.height as usize),
+                Self::crop_plane(u, x / 2, y / 2, chroma_width, chroma_height),
+                Self::crop_plane(v, x / 2, y / 2, chroma_width, chroma_height),
+            ],
+        }])
+    }
+}
+
+/// Resampling kernel used by `Scale`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMethod {
+    Nearest,
+    Bilinear,
+}
+
+/// Resizes every frame to a fixed size
+pub struct Scale {
+    width: u32,
+    height: u32,
+    method: ScaleMethod,
+}
+
+impl Scale {
+    pub fn new(width: u32, height: u32, method: ScaleMethod) -> Self {
+        Self { width, height, method }
+    }
+
+    fn scale_plane(plane: &Plane, width: usize, height: usize, method: ScaleMethod) -> Plane {
+        let mut output = Plane::new(width, height, 0);
+        let scale_x = plane.width as f64 / width as f64;
+        let scale_y = plane.height as f64 / height as f64;
+
+        for y in 0..height {
+            // Sample at pixel centres so both edges map symmetrically
+            let source_y = ((y as f64 + 0.5) * scale_y - 0.5).clamp(0.0, (plane.height - 1) as f64);
+            for x in 0..width {
+                let source_x = ((x as f64 + 0.5) * scale_x - 0.5).clamp(0.0, (plane.width - 1) as f64);
+                let value = match method {
+                    ScaleMethod::Nearest => plane.get(source_x.round() as usize, source_y.round() as usize),
+                    ScaleMethod::Bilinear => {
+                        let (x0, y0) = (source_x.floor() as usize, source_y.floor() as usize);
+                        let (x1, y1) = ((x0 + 1).min(plane.width - 1), (y0 + 1).min(plane.height - 1));
+                        let (fx, fy) = (source_x - x0 as f64, source_y - y0 as f64);
+                        let top = plane.get(x0, y0) as f64 * (1.0 - fx) + plane.get(x1, y0) as f64 * fx;
+                        let bottom = plane.get(x0, y1) as f64 * (1.0 - fx) + plane.get(x1, y1) as f64 * fx;
+                        (top * (1.0 - fy) + bottom * fy).round() as u8
+                    }
+                };
+                output.set(x, y, value);
+            }
+        }
+        output
+    }
+}
+
+impl FrameFilter for Scale {
+    fn describe(&self) -> String {
+        let method = match self.method {
+            ScaleMethod::Nearest => "nearest",
+            ScaleMethod::Bilinear => "bilinear",
+        };
+        format!("scale {}x{} {}", self.width, self.height, method)
+    }
+
+    fn output_size(&self, _width: u32, _height: u32) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn configure(&mut self, _width: u32, _height: u32, _fps: f64) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            bail!("Scale target {}x{} must be non-empty", self.width, self.height);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, frame: VideoFrame) -> Result<Vec<VideoFrame>> {
+        let (chroma_width, chroma_height) = VideoFrame::chroma_size(self.width, self.height);
+        let [luma, u, v] = &frame.planes;
+        Ok(vec![VideoFrame {
+            width: self.width,
+            height: self.height,
+            planes: [
+                Self::scale_plane(luma, self.width as usize, self.height as usize, self.method),
+                Self::scale_plane(u, chroma_width, chroma_height, self.method),
+                Self::scale_plane(v, chroma_width, chroma_height, self.method),
+            ],
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_frames(count: usize) -> Vec<Result<VideoFrame>> {
+        (0..count)
+            .map(|index| {
+                let mut frame = VideoFrame::new(32, 24);
+                frame.planes[0].data.fill(index as u8);
+                Ok(frame)
+            })
+            .collect()
+    }
+
+    fn run(chain: FilterChain, count: usize, fps: f64) -> Vec<VideoFrame> {
+        chain.apply(numbered_frames(count), fps).collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn test_fps_conversion_frame_counts() {
+        for (source, target, inputs, expected) in [(30.0, 15.0, 30, 15), (24.0, 30.0, 24, 30), (25.0, 25.0, 10, 10), (30.0, 24.0, 60, 48)] {
+            let frames = run(FilterChain::new().with(FpsConverter::new(target)), inputs, source);
+            assert_eq!(frames.len(), expected, "{} -> {}", source, target);
+        }
+
+        // Decimation keeps evenly spaced frames; duplication repeats in order
+        let halved = run(FilterChain::new().with(FpsConverter::new(15.0)), 6, 30.0);
+        let shown: Vec<u8> = halved.iter().map(|frame| frame.y().data[0]).collect();
+        assert_eq!(shown, [0, 2, 4]);
+        let doubled = run(FilterChain::new().with(FpsConverter::new(60.0)), 3, 30.0);
+        let shown: Vec<u8> = doubled.iter().map(|frame| frame.y().data[0]).collect();
+        assert_eq!(shown, [0, 0, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn test_scale_and_crop_dimensions() {
+        for method in [ScaleMethod::Nearest, ScaleMethod::Bilinear] {
+            let frames = run(FilterChain::new().with(Scale::new(17, 9, method)), 2, 30.0);
+            let frame = &frames[1];
+            assert_eq!((frame.width, frame.height), (17, 9));
+            assert_eq!((frame.u().width, frame.u().height), (9, 5));
+            // A flat frame stays flat
+            assert!(frame.y().data.iter().all(|&value| value == 1));
+        }
+
+        let frames = run(FilterChain::new().with(Crop::new(2, 4, 20, 11)).with(Scale::new(10, 6, ScaleMethod::Bilinear)), 1, 30.0);
+        assert_eq!((frames[0].width, frames[0].height), (10, 6));
+
+        let odd = FilterChain::new().with(Crop::new(1, 0, 8, 8)).apply(numbered_frames(1), 30.0);
+        assert!(odd.collect::<Result<Vec<_>>>().is_err());
+        let too_big = FilterChain::new().with(Crop::new(0, 0, 40, 8)).apply(numbered_frames(1), 30.0);
+        assert!(too_big.collect::<Result<Vec<_>>>().is_err());
+    }
+
+    #[test]
+    fn test_denoise_blends_static_blocks_only() {
+        let mut first = VideoFrame::new(16, 8);
+        first.planes[0].data.fill(100);
+        let mut second = first.clone();
+        // Left block: small flicker; right block: large change (motion)
+        for y in 0..8 {
+            for x in 0..8 {
+                second.planes[0].set(x, y, 104);
+                second.planes[0].set(x + 8, y, 200);
+            }
+        }
+
+        let mut denoise = TemporalDenoise::new(0.5);
+        denoise.push(first).unwrap();
+        let output = denoise.push(second).unwrap().remove(0);
+        let left = output.y().get(0, 0);
+        assert!(left > 100 && left < 104, "static block not smoothed: {}", left);
+        assert_eq!(output.y().get(8, 0), 200);
+    }
+
+    #[test]
+    fn test_chain_description_and_fps() {
+        let chain = FilterChain::new()
+            .with(FpsConverter::new(24.0))
+            .with(TemporalDenoise::new(0.25))
+            .with(Scale::new(320, 240, ScaleMethod::Nearest));
+        assert_eq!(chain.describe(), ["fps 24", "denoise strength=0.25", "scale 320x240 nearest"]);
+        assert_eq!(chain.output_fps(30.0), 24.0);
+        assert!(FilterChain::new().is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+use image::RgbImage;
+
+use crate::codecs::image::dct_transform::ColorSpace;
+pub use crate::codecs::plane::Plane;
+
+/// Planar YUV 4:2:0 video frame (Y, then U and V at half resolution, rounded up)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoFrame {
+    pub width: u32,
+    pub height: u32,
+    pub planes: [Plane; 3],
+}
+
+impl VideoFrame {
+    /// Mid-grey frame of the given size
+    pub fn new(width: u32, height: u32) -> Self {
+        let (chroma_width, chroma_height) = Self::chroma_size(width, height);
+        Self {
+            width,
+            height,
+            planes: [
+                Plane::new(width as usize, height as usize, 128),
+                Plane::new(chroma_width, chroma_height, 128),
+                Plane::new(chroma_width, chroma_height, 128),
+            ],
+        }
+    }
+
+    /// Dimensions of the U and V planes for a frame of the given luma size
+    pub fn chroma_size(width: u32, height: u32) -> (usize, usize) {
+        (width.div_ceil(2) as usize, height.div_ceil(2) as usize)
+    }
+
+    /// Size in bytes of one frame of raw 4:2:0 samples
+    pub fn raw_size(width: u32, height: u32) -> usize {
+        let (chroma_width, chroma_height) = Self::chroma_size(width, height);
+        width as usize * height as usize + 2 * chroma_width * chroma_height
+    }
+
+    pub fn y(&self) -> &Plane {
+        &self.planes[0]
+    }
+
+    pub fn u(&self) -> &Plane {
+        &self.planes[1]
+    }
+
+    pub fn v(&self) -> &Plane {
+        &self.planes[2]
+    }
+
+    /// Convert from RGB as full-range BT.601 like JFIF, averaging chroma
+    /// over each 2x2 block of pixels
+    pub fn from_rgb(image: &RgbImage) -> Self {
+        let (width, height) = image.dimensions();
+        let mut frame = Self::new(width, height);
+        let mut chroma = vec![(0.0, 0.0, 0.0); frame.planes[1].data.len()];
+        let chroma_width = frame.planes[1].width;
+        let to_u8 = |value: f64| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let [r, g, b] = pixel.0.map(|channel| channel as f64 / 255.0);
+            let (luma, cb, cr) = ColorSpace::rgb_to_ycbcr(r, g, b);
+            frame.planes[0].set(x as usize, y as usize, to_u8(luma));
+            let sum = &mut chroma[y as usize / 2 * chroma_width + x as usize / 2];
+            *sum = (sum.0 + cb, sum.1 + cr, sum.2 + 1.0);
+        }
+        for (i, (cb, cr, count)) in chroma.into_iter().enumerate() {
+            frame.planes[1].data[i] = to_u8(cb / count + 128.0 / 255.0);
+            frame.planes[2].data[i] = to_u8(cr / count + 128.0 / 255.0);
+        }
+        frame
+    }
+
+    /// Convert to RGB, reading the samples as full-range BT.601 like JFIF
+    /// and replicating each chroma sample over its 2x2 pixels
+    pub fn to_rgb(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let (x, y) = (x as usize, y as usize);
+            let luma = self.planes[0].get(x, y) as f64 / 255.0;
+            let cb = (self.planes[1].get(x / 2, y / 2) as f64 - 128.0) / 255.0;
+            let cr = (self.planes[2].get(x / 2, y / 2) as f64 - 128.0) / 255.0;
+            let (r, g, b) = ColorSpace::ycbcr_to_rgb(luma, cb, cr);
+            let to_u8 = |value: f64| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+            image::Rgb([to_u8(r), to_u8(g), to_u8(b)])
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::{bail, Result};
+
+/// Edge of the square area one importance sample covers at macroblock resolution
+const MACROBLOCK_SIZE: u32 = 16;
+
+/// How much each part of a frame matters, 0 (background) to 255 (focus)
+///
+/// Either one sample per pixel of the frame, or one per 16x16 macroblock
+/// (the frame size rounded up to whole macroblocks).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportanceMap {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<u8>,
+}
+
+impl ImportanceMap {
+    pub fn new(width: u32, height: u32, values: Vec<u8>) -> Result<Self> {
+        if values.len() != width as usize * height as usize {
+            bail!("Importance map of {}x{} needs {} values, got {}", width, height, width as usize * height as usize, values.len());
+        }
+        Ok(Self { width, height, values })
+    }
+
+    /// Macroblock-resolution map for a `width`x`height` frame, highest at
+    /// the center and falling off linearly to 0 at the corners
+    pub fn center_weighted(width: u32, height: u32) -> Self {
+        let (columns, rows) = (width.div_ceil(MACROBLOCK_SIZE), height.div_ceil(MACROBLOCK_SIZE));
+        let values = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                // Distance from the center with both axes scaled to [-1, 1]
+                let dx = ((column as f64 + 0.5) / columns as f64) * 2.0 - 1.0;
+                let dy = ((row as f64 + 0.5) / rows as f64) * 2.0 - 1.0;
+                let distance = ((dx * dx + dy * dy) / 2.0).sqrt();
+                ((1.0 - distance) * 255.0).round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        Self { width: columns, height: rows, values }
+    }
+
+    /// Quantizer offset of each macroblock of a `width`x`height` frame, in
+    /// raster order, within `±max_delta`
+    ///
+    /// Offsets are taken relative to the map's mean so the frame's overall
+    /// rate stays near what its quality gives: macroblocks more important
+    /// than average get negative (finer) offsets, the rest positive.
+    pub fn qp_deltas(&self, width: u32, height: u32, max_delta: u8) -> Result<Vec<i8>> {
+        let importance = self.macroblock_importance(width, height)?;
+        let mean = importance.iter().sum::<f64>() / importance.len().max(1) as f64;
+        let max_delta = max_delta as f64;
+        Ok(importance.iter()
+            .map(|&value| ((mean - value) * 2.0 * max_delta).round().clamp(-max_delta, max_delta) as i8)
+            .collect())
+    }
+
+    /// Mean importance of each macroblock in 0.0..=1.0, in raster order
+    fn macroblock_importance(&self, width: u32, height: u32) -> Result<Vec<f64>> {
+        let (columns, rows) = (width.div_ceil(MACROBLOCK_SIZE), height.div_ceil(MACROBLOCK_SIZE));
+        if (self.width, self.height) == (columns, rows) {
+            return Ok(self.values.iter().map(|&value| value as f64 / 255.0).collect());
+        }
+        if (self.width, self.height) != (width, height) {
+            bail!("Importance map is {}x{}; expected {}x{} (frame) or {}x{} (macroblocks)",
+                self.width, self.height, width, height, columns, rows);
+        }
+
+        // Padding macroblocks still overlap the frame, so every one has samples
+        let mut importance = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                let xs = column * MACROBLOCK_SIZE..((column + 1) * MACROBLOCK_SIZE).min(width);
+                let ys = row * MACROBLOCK_SIZE..((row + 1) * MACROBLOCK_SIZE).min(height);
+                let count = xs.len() * ys.len();
+                let sum: u64 = ys
+                    .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                    .map(|(x, y)| self.values[(y * width + x) as usize] as u64)
+                    .sum();
+                importance.push(sum as f64 / (count as f64 * 255.0));
+            }
+        }
+        Ok(importance)
+    }
+}
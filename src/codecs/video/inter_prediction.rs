@@ -1,8 +1,42 @@
 // Inter-frame prediction module
+use crate::codecs::video::frame::Plane;
+use crate::codecs::video::motion_estimation::MotionVector;
+
+/// Motion-compensated prediction from a reference plane
 pub struct InterPredictor;
 
 impl InterPredictor {
     pub fn new() -> Self {
         Self
     }
-}
\ No newline at end of file
+
+    /// Predicted 8x8 block at (`x`, `y`) displaced by `mv`
+    ///
+    /// The vector must keep the block inside `reference`; the encoder's motion
+    /// search guarantees this for every vector it emits.
+    pub fn predict_8x8(&self, reference: &Plane, x: usize, y: usize, mv: MotionVector) -> [[f64; 8]; 8] {
+        let ref_x = (x as i32 + mv.x) as usize;
+        let ref_y = (y as i32 + mv.y) as usize;
+        let mut block = [[0.0; 8]; 8];
+        for (row, line) in block.iter_mut().enumerate() {
+            for (col, value) in line.iter_mut().enumerate() {
+                *value = reference.get(ref_x + col, ref_y + row) as f64;
+            }
+        }
+        block
+    }
+
+    /// Chroma vector for a luma vector in 4:2:0 content (halved, rounding toward zero)
+    pub fn chroma_vector(mv: MotionVector) -> MotionVector {
+        MotionVector { x: mv.x / 2, y: mv.y / 2 }
+    }
+
+    /// Whether a vector keeps a `size` block at (`x`, `y`) inside `plane`
+    pub fn in_bounds(plane: &Plane, x: usize, y: usize, mv: MotionVector, size: usize) -> bool {
+        let ref_x = x as i64 + mv.x as i64;
+        let ref_y = y as i64 + mv.y as i64;
+        ref_x >= 0 && ref_y >= 0
+            && ref_x as usize + size <= plane.width
+            && ref_y as usize + size <= plane.height
+    }
+}
@@ -1,7 +1,17 @@
 pub mod vcf_codec;
 pub mod motion_estimation;
 pub mod inter_prediction;
+pub mod frame;
+pub mod y4m;
+pub mod quality;
+pub mod filter;
+pub mod importance;
 
 pub use vcf_codec::*;
 pub use motion_estimation::*;
-pub use inter_prediction::*;
\ No newline at end of file
+pub use inter_prediction::*;
+pub use frame::*;
+pub use y4m::*;
+pub use quality::*;
+pub use filter::*;
+pub use importance::*;
@@ -1,8 +1,66 @@
 // Motion estimation module for video compression
-pub struct MotionEstimator;
+use serde::{Deserialize, Serialize};
+
+use crate::codecs::video::frame::Plane;
+
+/// Displacement of a block in the reference frame, in whole pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MotionVector {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Exhaustive block-matching motion search using sum of absolute differences
+pub struct MotionEstimator {
+    search_range: i32,
+}
 
 impl MotionEstimator {
+    pub const DEFAULT_SEARCH_RANGE: i32 = 8;
+
     pub fn new() -> Self {
-        Self
+        Self::with_search_range(Self::DEFAULT_SEARCH_RANGE)
+    }
+
+    pub fn with_search_range(search_range: i32) -> Self {
+        Self { search_range: search_range.max(0) }
+    }
+
+    /// Find the vector minimising SAD for the `size`x`size` block at (`x`, `y`)
+    ///
+    /// Candidates are restricted so the displaced block lies entirely inside
+    /// `reference`; ties prefer the shorter vector, so static content gets
+    /// zero motion. Returns the vector and its SAD.
+    pub fn estimate(&self, current: &Plane, reference: &Plane, x: usize, y: usize, size: usize) -> (MotionVector, u32) {
+        let min_dx = -(x.min(self.search_range as usize) as i32);
+        let min_dy = -(y.min(self.search_range as usize) as i32);
+        let max_dx = (reference.width - size - x).min(self.search_range as usize) as i32;
+        let max_dy = (reference.height - size - y).min(self.search_range as usize) as i32;
+
+        let mut best = (MotionVector::default(), Self::sad(current, reference, x, y, MotionVector::default(), size));
+        for dy in min_dy..=max_dy {
+            for dx in min_dx..=max_dx {
+                let mv = MotionVector { x: dx, y: dy };
+                let sad = Self::sad(current, reference, x, y, mv, size);
+                let shorter = dx.abs() + dy.abs() < best.0.x.abs() + best.0.y.abs();
+                if sad < best.1 || (sad == best.1 && shorter) {
+                    best = (mv, sad);
+                }
+            }
+        }
+        best
+    }
+
+    /// Sum of absolute differences between a block and its displaced reference
+    pub fn sad(current: &Plane, reference: &Plane, x: usize, y: usize, mv: MotionVector, size: usize) -> u32 {
+        let ref_x = (x as i32 + mv.x) as usize;
+        let ref_y = (y as i32 + mv.y) as usize;
+        let mut sad = 0u32;
+        for row in 0..size {
+            let cur = &current.data[(y + row) * current.width + x..][..size];
+            let refr = &reference.data[(ref_y + row) * reference.width + ref_x..][..size];
+            sad += cur.iter().zip(refr).map(|(&a, &b)| a.abs_diff(b) as u32).sum::<u32>();
+        }
+        sad
     }
-}
\ No newline at end of file
+}
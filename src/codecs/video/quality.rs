@@ -0,0 +1,381 @@
+use anyhow::{bail, Result};
+use image::RgbImage;
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::codecs::image::ColorSpace;
+use crate::codecs::video::frame::VideoFrame;
+use crate::codecs::video::vcf_codec::FrameType;
+
+/// PSNR reported for identical planes (and the cap for near-identical ones)
+pub const MAX_PSNR: f64 = 100.0;
+
+/// Objective quality of one decoded frame against its source
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameQuality {
+    pub index: usize,
+    pub frame_type: FrameType,
+    pub psnr_y: f64,
+    pub psnr_u: f64,
+    pub psnr_v: f64,
+    /// Combined PSNR from the 6:1:1 weighted Y/U/V mean squared error
+    pub psnr: f64,
+}
+
+impl FrameQuality {
+    pub fn compare(index: usize, frame_type: FrameType, original: &VideoFrame, decoded: &VideoFrame) -> Result<Self> {
+        if (original.width, original.height) != (decoded.width, decoded.height) {
+            bail!("Frame {} is {}x{} in the original but {}x{} decoded",
+                index, original.width, original.height, decoded.width, decoded.height);
+        }
+
+        let mse: Vec<f64> = original.planes.iter()
+            .zip(decoded.planes.iter())
+            .map(|(a, b)| mean_squared_error(&a.data, &b.data))
+            .collect();
+
+        Ok(Self {
+            index,
+            frame_type,
+            psnr_y: psnr_from_mse(mse[0]),
+            psnr_u: psnr_from_mse(mse[1]),
+            psnr_v: psnr_from_mse(mse[2]),
+            psnr: WeightedPsnr::default().combine([mse[0], mse[1], mse[2]]),
+        })
+    }
+}
+
+/// Per-channel weights of a combined luma/chroma PSNR
+///
+/// The default 6:1:1 counts luma errors six times as much as each chroma
+/// channel's, roughly as the eye does.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct WeightedPsnr {
+    pub y_weight: f64,
+    pub co_weight: f64,
+    pub cg_weight: f64,
+}
+
+impl Default for WeightedPsnr {
+    fn default() -> Self {
+        Self { y_weight: 6.0, co_weight: 1.0, cg_weight: 1.0 }
+    }
+}
+
+impl FromStr for WeightedPsnr {
+    type Err = anyhow::Error;
+
+    /// Weights written `Y:CO:CG`, e.g. `6:1:1`
+    fn from_str(s: &str) -> Result<Self> {
+        let weights: Vec<f64> = s.split(':').map(|weight| weight.trim().parse::<f64>()).collect::<Result<_, _>>()
+            .map_err(|_| anyhow::anyhow!("Weights {:?} must be three numbers written Y:CO:CG", s))?;
+        let [y_weight, co_weight, cg_weight] = weights[..] else {
+            bail!("Weights {:?} must be three numbers written Y:CO:CG", s);
+        };
+        if weights.iter().any(|&weight| !weight.is_finite() || weight < 0.0) || weights.iter().sum::<f64>() <= 0.0 {
+            bail!("Weights {:?} must be non-negative and not all zero", s);
+        }
+        Ok(Self { y_weight, co_weight, cg_weight })
+    }
+}
+
+impl WeightedPsnr {
+    /// Every channel counted the same
+    pub const UNWEIGHTED: Self = Self { y_weight: 1.0, co_weight: 1.0, cg_weight: 1.0 };
+
+    /// PSNR of the weighted mean of per-channel squared errors
+    pub fn combine(&self, mse: [f64; 3]) -> f64 {
+        let total = self.y_weight + self.co_weight + self.cg_weight;
+        psnr_from_mse((self.y_weight * mse[0] + self.co_weight * mse[1] + self.cg_weight * mse[2]) / total)
+    }
+
+    /// Compare two images of the same size in YCoCg
+    ///
+    /// Co and Cg span twice Y's range, so they're halved first and all three
+    /// channels are measured against a peak of 255.
+    pub fn compare(&self, original: &RgbImage, decoded: &RgbImage) -> Result<ImageQuality> {
+        if original.dimensions() != decoded.dimensions() {
+            bail!("Images differ in size: {}x{} and {}x{}",
+                original.width(), original.height(), decoded.width(), decoded.height());
+        }
+        let mut sums = [0.0f64; 3];
+        for (a, b) in original.pixels().zip(decoded.pixels()) {
+            let a = ColorSpace::rgb_to_ycocg(a[0] as f64, a[1] as f64, a[2] as f64);
+            let b = ColorSpace::rgb_to_ycocg(b[0] as f64, b[1] as f64, b[2] as f64);
+            sums[0] += (a.0 - b.0).powi(2);
+            sums[1] += ((a.1 - b.1) / 2.0).powi(2);
+            sums[2] += ((a.2 - b.2) / 2.0).powi(2);
+        }
+        let count = (original.width() as f64 * original.height() as f64).max(1.0);
+        let mse = sums.map(|sum| sum / count);
+        Ok(ImageQuality {
+            psnr_y: psnr_from_mse(mse[0]),
+            psnr_co: psnr_from_mse(mse[1]),
+            psnr_cg: psnr_from_mse(mse[2]),
+            psnr: self.combine(mse),
+            weights: *self,
+        })
+    }
+}
+
+/// Objective quality of a decoded image against its source
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageQuality {
+    pub psnr_y: f64,
+    pub psnr_co: f64,
+    pub psnr_cg: f64,
+    /// Combined PSNR under `weights`
+    pub psnr: f64,
+    pub weights: WeightedPsnr,
+}
+
+impl fmt::Display for ImageQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PSNR: {:.2} dB ({}:{}:{}; Y {:.2} dB, Co {:.2} dB, Cg {:.2} dB)",
+            self.psnr, self.weights.y_weight, self.weights.co_weight, self.weights.cg_weight,
+            self.psnr_y, self.psnr_co, self.psnr_cg)
+    }
+}
+
+/// Color vision deficiency simulated by `simulate_cvd`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CvdKind {
+    /// No working red cones
+    Protan,
+    /// No working green cones, the most common deficiency
+    Deutan,
+    /// No working blue cones
+    Tritan,
+}
+
+impl CvdKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CvdKind::Protan => "protan",
+            CvdKind::Deutan => "deutan",
+            CvdKind::Tritan => "tritan",
+        }
+    }
+
+    /// Machado et al. (2009) matrices at full severity, in linear RGB
+    fn matrix(self) -> [[f64; 3]; 3] {
+        match self {
+            CvdKind::Protan => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            CvdKind::Deutan => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            CvdKind::Tritan => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+}
+
+impl fmt::Display for CvdKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CvdKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [CvdKind::Protan, CvdKind::Deutan, CvdKind::Tritan]
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown color vision deficiency {:?}, expected protan, deutan or tritan", s))
+    }
+}
+
+/// How `image` looks to a viewer with a `kind` deficiency
+///
+/// Each matrix row sums to one, so greys are left as they are.
+pub fn simulate_cvd(image: &RgbImage, kind: CvdKind) -> RgbImage {
+    let to_linear: Vec<f64> = (0..=255u8).map(|value| srgb_to_linear(value as f64 / 255.0)).collect();
+    let matrix = kind.matrix();
+    let mut out = image.clone();
+    for pixel in out.pixels_mut() {
+        let linear = [to_linear[pixel[0] as usize], to_linear[pixel[1] as usize], to_linear[pixel[2] as usize]];
+        for (channel, row) in matrix.iter().enumerate() {
+            let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+            pixel[channel] = (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8;
+        }
+    }
+    out
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Summary statistics over a per-frame quality report
+#[derive(Debug, Clone, Serialize)]
+pub struct QualitySummary {
+    pub frame_count: usize,
+    pub min_psnr: f64,
+    pub mean_psnr: f64,
+    /// 5th percentile (nearest rank) of combined PSNR
+    pub p5_psnr: f64,
+    /// Indices of the lowest-PSNR frames, worst first
+    pub worst_frames: Vec<usize>,
+}
+
+impl QualitySummary {
+    /// Summarise a report, keeping the `worst_count` lowest-quality frames; `None` if empty
+    pub fn from_frames(frames: &[FrameQuality], worst_count: usize) -> Option<Self> {
+        if frames.is_empty() {
+            return None;
+        }
+
+        let mut ranked: Vec<&FrameQuality> = frames.iter().collect();
+        ranked.sort_by(|a, b| a.psnr.total_cmp(&b.psnr).then(a.index.cmp(&b.index)));
+
+        let p5_rank = (frames.len() as f64 * 0.05).ceil().max(1.0) as usize;
+
+        Some(Self {
+            frame_count: frames.len(),
+            min_psnr: ranked[0].psnr,
+            mean_psnr: frames.iter().map(|frame| frame.psnr).sum::<f64>() / frames.len() as f64,
+            p5_psnr: ranked[p5_rank - 1].psnr,
+            worst_frames: ranked.iter().take(worst_count).map(|frame| frame.index).collect(),
+        })
+    }
+}
+
+impl std::fmt::Display for QualitySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,
+            "Frames: {}, PSNR min: {:.2} dB, mean: {:.2} dB, p5: {:.2} dB, worst frames: {:?}",
+            self.frame_count,
+            self.min_psnr,
+            self.mean_psnr,
+            self.p5_psnr,
+            self.worst_frames
+        )
+    }
+}
+
+/// Write a per-frame report as CSV (one row per frame, with a header row)
+pub fn write_quality_csv<W: Write>(mut writer: W, frames: &[FrameQuality]) -> Result<()> {
+    writeln!(writer, "frame,type,psnr_y,psnr_u,psnr_v,psnr")?;
+    for frame in frames {
+        writeln!(writer, "{},{:?},{:.4},{:.4},{:.4},{:.4}",
+            frame.index, frame.frame_type, frame.psnr_y, frame.psnr_u, frame.psnr_v, frame.psnr)?;
+    }
+    Ok(())
+}
+
+pub fn mean_squared_error(a: &[u8], b: &[u8]) -> f64 {
+    if a.is_empty() {
+        return 0.0;
+    }
+    let sum: u64 = a.iter()
+        .zip(b)
+        .map(|(&x, &y)| {
+            let diff = x.abs_diff(y) as u64;
+            diff * diff
+        })
+        .sum();
+    sum as f64 / a.len() as f64
+}
+
+pub fn psnr_from_mse(mse: f64) -> f64 {
+    if mse <= 0.0 {
+        MAX_PSNR
+    } else {
+        (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quality(index: usize, psnr: f64) -> FrameQuality {
+        FrameQuality { index, frame_type: FrameType::P, psnr_y: psnr, psnr_u: psnr, psnr_v: psnr, psnr }
+    }
+
+    #[test]
+    fn test_summary_statistics() {
+        let frames: Vec<FrameQuality> = (0..40)
+            .map(|index| quality(index, 40.0 + (index % 7) as f64))
+            .chain([quality(40, 21.0), quality(41, 25.0)])
+            .collect();
+
+        let summary = QualitySummary::from_frames(&frames, 3).unwrap();
+        assert_eq!(summary.frame_count, 42);
+        assert_eq!(summary.min_psnr, 21.0);
+        assert_eq!(summary.worst_frames, vec![40, 41, 0]);
+        // ceil(42 * 0.05) = 3rd lowest
+        assert_eq!(summary.p5_psnr, 40.0);
+        assert!(QualitySummary::from_frames(&[], 3).is_none());
+
+        let mut csv = Vec::new();
+        write_quality_csv(&mut csv, &frames[..2]).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.starts_with("frame,type,psnr_y"));
+    }
+
+    #[test]
+    fn test_weights_can_reverse_the_ranking() {
+        let gray = RgbImage::from_pixel(8, 8, image::Rgb([128, 128, 128]));
+        // Red up and blue down leaves Y alone but moves Co by 12
+        let chroma_error = RgbImage::from_pixel(8, 8, image::Rgb([134, 128, 122]));
+        let luma_error = RgbImage::from_pixel(8, 8, image::Rgb([132, 132, 132]));
+
+        let plain = WeightedPsnr::UNWEIGHTED;
+        assert!(plain.compare(&gray, &chroma_error).unwrap().psnr < plain.compare(&gray, &luma_error).unwrap().psnr);
+        let weighted = WeightedPsnr::default();
+        let chroma = weighted.compare(&gray, &chroma_error).unwrap();
+        assert!(chroma.psnr > weighted.compare(&gray, &luma_error).unwrap().psnr);
+        assert_eq!((chroma.psnr_y, chroma.psnr_cg), (MAX_PSNR, MAX_PSNR));
+
+        assert_eq!("6:1:1".parse::<WeightedPsnr>().unwrap(), weighted);
+        assert!("6:1".parse::<WeightedPsnr>().is_err());
+        assert!("0:0:0".parse::<WeightedPsnr>().is_err());
+        assert!(weighted.compare(&gray, &RgbImage::new(4, 4)).is_err());
+    }
+
+    #[test]
+    fn test_cvd_simulation_leaves_grays_alone() {
+        let grays = RgbImage::from_fn(16, 16, |x, y| {
+            let value = (y * 16 + x) as u8;
+            image::Rgb([value, value, value])
+        });
+        for kind in [CvdKind::Protan, CvdKind::Deutan, CvdKind::Tritan] {
+            assert_eq!(simulate_cvd(&grays, kind), grays, "{}", kind);
+        }
+
+        // Red and green collapse towards each other for a deutan viewer
+        let red_green = RgbImage::from_fn(2, 1, |x, _| if x == 0 { image::Rgb([200, 40, 40]) } else { image::Rgb([40, 160, 40]) });
+        let simulated = simulate_cvd(&red_green, "deutan".parse().unwrap());
+        let distance = |image: &RgbImage| {
+            let (a, b) = (image.get_pixel(0, 0), image.get_pixel(1, 0));
+            (0..3).map(|c| (a[c] as f64 - b[c] as f64).powi(2)).sum::<f64>()
+        };
+        assert!(distance(&simulated) < distance(&red_green) / 2.0);
+    }
+
+    #[test]
+    fn test_psnr_of_identical_planes_is_capped() {
+        assert_eq!(psnr_from_mse(mean_squared_error(&[7, 8, 9], &[7, 8, 9])), MAX_PSNR);
+        let psnr = psnr_from_mse(mean_squared_error(&[0, 0], &[255, 255]));
+        assert!(psnr.abs() < 1e-9);
+    }
+}
@@ -1,6 +1,130 @@
-// Video Codec Format (VCF) implementation placeholder
+// Video Codec Format (VCF) implementation
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use image::DynamicImage;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::codecs::atomic::write_atomic;
+use crate::codecs::cli_common::RuntimeConfig;
+use crate::codecs::formats;
+use crate::codecs::residual::{self, FLAT_PREDICTION};
+use crate::codecs::image::{
+    dct_transform::Dct8x8, quantization::Quantization, ChromaSubsampling, CoefficientPlane, CoefficientPlanes,
+    IcfCodec, IcfColorSpace, IcfEncodeOptions,
+};
+use crate::codecs::video::filter::FilterChain;
+use crate::codecs::video::frame::{Plane, VideoFrame};
+use crate::codecs::video::importance::ImportanceMap;
+use crate::codecs::video::inter_prediction::InterPredictor;
+use crate::codecs::video::motion_estimation::{MotionEstimator, MotionVector};
+use crate::codecs::video::quality::FrameQuality;
+use crate::codecs::video::y4m::{fps_to_rational, Y4mReader, Y4mWriter};
+use crate::codecs::trace::{diagnostic, phase, trace_event};
+
+/// Frame coding type
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    /// Intra frame, decodable on its own
+    I,
+    /// Predicted from recently decoded frames
+    P,
+}
+
+/// How the encoder picks each frame's quantizer
+///
+/// Both currently hold every frame at one QP; they differ in what the
+/// caller fixes. `ConstantQp` pins the quantizer, so encodes are
+/// reproducible and comparable, while `ConstantQuality` names a quality
+/// target the encoder is free to meet by adapting the QP per frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateControl {
+    /// The same quantizer for every frame, 0 (finest) to `VcfCodec::MAX_QP`
+    ConstantQp(u8),
+    /// Quality 1-100, on the same scale as the image codecs
+    ConstantQuality(u8),
+}
+
+impl RateControl {
+    /// Quantizer for the next frame
+    fn frame_qp(self) -> u8 {
+        match self {
+            RateControl::ConstantQp(qp) => qp.min(VcfCodec::MAX_QP),
+            RateControl::ConstantQuality(quality) => VcfCodec::qp_for_quality(quality),
+        }
+    }
+}
+
+impl std::fmt::Display for RateControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateControl::ConstantQp(qp) => write!(f, "constant QP {}", qp),
+            RateControl::ConstantQuality(quality) => write!(f, "constant quality {}", quality),
+        }
+    }
+}
+
+/// Location of one coded frame within the VCF payload
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VcfFrameEntry {
+    pub frame_type: FrameType,
+    pub offset: u64,
+    pub size: u64,
+    /// The frame data starts with a quantizer offset per macroblock
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub qp_deltas: bool,
+    /// Buffered frames a P-frame's macroblocks choose from, newest first;
+    /// 0 means just the newest, otherwise each coded macroblock carries
+    /// its reference index
+    #[serde(default, skip_serializing_if = "single_reference")]
+    pub references: u8,
+    /// Frame quantizer; version 1 files have none and use the tables of
+    /// the header's quality
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qp: Option<u8>,
+    /// Presentation time in `timebase` ticks; files before version 3 have
+    /// none and frame `n` is shown at tick `n`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pts: Option<u64>,
+}
+
+/// Length of one timestamp tick: `num / den` seconds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timebase {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl Timebase {
+    /// One tick per frame at `fps`
+    pub fn from_fps(fps: f64) -> Self {
+        let (fps_num, fps_den) = fps_to_rational(fps);
+        Self { num: fps_den, den: fps_num }
+    }
+
+    pub fn seconds(self, ticks: u64) -> f64 {
+        ticks as f64 * self.num as f64 / self.den as f64
+    }
+
+    /// The last tick at or before `seconds`, which must not be negative
+    fn ticks_at(self, seconds: f64) -> u64 {
+        // The margin keeps a time printed from a tick on that tick
+        (seconds * self.den as f64 / self.num as f64 + 1e-9).floor() as u64
+    }
+
+    /// Ticks between frames at `fps`, at least one
+    fn ticks_per_frame(self, fps: f64) -> u64 {
+        (self.den as f64 / (self.num as f64 * fps)).round().max(1.0) as u64
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VcfHeader {
@@ -8,27 +132,1580 @@ pub struct VcfHeader {
     pub version: u16,
     pub width: u32,
     pub height: u32,
+    /// Nominal frame rate; variable-rate files time frames by their `pts`
     pub fps: f64,
     pub frame_count: u32,
     pub duration: f64,
+    /// Quality of a constant-quality encode, 0 for constant QP
     pub quality: u8,
+    /// The QP of a constant-QP encode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constant_qp: Option<u8>,
+    pub gop_size: u32,
+    /// Reconstructed frames kept for prediction; I-frames empty the buffer
+    #[serde(default = "one_reference", skip_serializing_if = "single_reference")]
+    pub reference_count: u8,
+    pub block_size: u8,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    /// SHA-256 of the source 4:2:0 samples
+    pub checksum: String,
+    pub frames: Vec<VcfFrameEntry>,
+    /// Preprocessing filters applied before encoding, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<String>,
+    /// Number of frames coded at each QP
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub qp_histogram: BTreeMap<u8, u32>,
+    /// Tick length of the frames' timestamps, from version 3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timebase: Option<Timebase>,
+}
+
+impl VcfHeader {
+    pub fn rate_control(&self) -> RateControl {
+        match self.constant_qp {
+            Some(qp) => RateControl::ConstantQp(qp),
+            None => RateControl::ConstantQuality(self.quality),
+        }
+    }
+
+    /// Tick length of the frame timestamps; older files tick once per frame
+    pub fn timebase(&self) -> Timebase {
+        self.timebase.unwrap_or_else(|| Timebase::from_fps(self.fps))
+    }
+
+    /// Presentation time of frame `index` in `timebase` ticks
+    pub fn frame_pts(&self, index: usize) -> Option<u64> {
+        self.frames.get(index).map(|entry| entry.pts.unwrap_or(index as u64))
+    }
+
+    /// Presentation time of frame `index` in seconds
+    pub fn frame_time(&self, index: usize) -> Option<f64> {
+        self.frame_pts(index).map(|pts| self.timebase().seconds(pts))
+    }
+
+    /// Ticks frame `index` stays on screen: up to the next frame, or one
+    /// nominal frame for the last
+    pub fn frame_duration(&self, index: usize) -> Option<u64> {
+        let pts = self.frame_pts(index)?;
+        Some(match self.frame_pts(index + 1) {
+            Some(next) => next - pts,
+            None => self.timebase().ticks_per_frame(self.fps),
+        })
+    }
+
+    /// Frame indices of each GOP, in order; every GOP but a damaged first
+    /// one starts at an I-frame
+    pub fn gops(&self) -> Vec<Range<usize>> {
+        let mut starts: Vec<usize> = self.frames.iter().enumerate()
+            .filter(|&(index, entry)| index == 0 || entry.frame_type == FrameType::I)
+            .map(|(index, _)| index)
+            .collect();
+        starts.push(self.frames.len());
+        starts.windows(2).map(|pair| pair[0]..pair[1]).filter(|gop| !gop.is_empty()).collect()
+    }
+
+    /// The frame on screen at `seconds`: the last one presented at or
+    /// before it, or the first frame for earlier times
+    pub fn frame_at_time(&self, seconds: f64) -> Option<usize> {
+        if self.frames.is_empty() || !seconds.is_finite() || seconds < 0.0 {
+            return None;
+        }
+        let ticks = self.timebase().ticks_at(seconds);
+        let shown = self.frames.iter().enumerate()
+            .take_while(|&(index, entry)| entry.pts.unwrap_or(index as u64) <= ticks)
+            .count();
+        Some(shown.saturating_sub(1))
+    }
+
+    /// Write one line per frame: index, type, coded size, QP, timestamp and
+    /// the frame rate implied by its duration
+    ///
+    /// I-frames are marked with `*` as keyframes.
+    pub fn write_frame_table<W: Write>(&self, mut out: W) -> Result<()> {
+        writeln!(out, "{:>7} {:<4} {:>10} {:>4} {:>10} {:>8}", "frame", "type", "bytes", "qp", "time (s)", "fps")?;
+        let timebase = self.timebase();
+        for (index, entry) in self.frames.iter().enumerate() {
+            let (frame_type, key) = match entry.frame_type {
+                FrameType::I => ("I", "*"),
+                FrameType::P => ("P", ""),
+            };
+            let qp = entry.qp.map_or_else(|| "-".to_string(), |qp| qp.to_string());
+            let time = self.frame_time(index).unwrap_or_default();
+            let fps = self.frame_duration(index).map_or(0.0, |ticks| 1.0 / timebase.seconds(ticks));
+            writeln!(out, "{:>7} {:<4} {:>10} {:>4} {:>10.3} {:>8.2}",
+                index, format!("{}{}", frame_type, key), entry.size, qp, time, fps)?;
+        }
+        Ok(())
+    }
 }
 
-pub struct VcfCodec;
+/// Block-based video codec: DCT-coded I-frames and motion-compensated P-frames
+///
+/// Frames are 8-bit YUV 4:2:0. Each frame is coded in 16x16 macroblocks
+/// (four 8x8 luma blocks plus one 8x8 block per chroma plane); frame edges are
+/// padded by replication to a macroblock multiple and cropped on decode.
+/// P-frames predict from *reconstructed* frames, so encoder and decoder
+/// never drift apart. Both keep the last `reference_count` of them in a
+/// sliding window that each I-frame flushes, and each P-frame macroblock
+/// picks the reference it matches best, which helps with occlusions.
+///
+/// Each frame is coded at a QP, recorded in the frame index; each QP step
+/// scales the quantization tables by 2^(1/6), so six steps double or halve
+/// the step size. Frames pushed with an importance map also carry a
+/// quantizer offset per macroblock on the same scale.
+pub struct VcfCodec {
+    dct: &'static Dct8x8,
+    estimator: MotionEstimator,
+    predictor: InterPredictor,
+    gop_size: u32,
+    max_qp_delta: u8,
+    reference_count: u8,
+    runtime: RuntimeConfig,
+}
 
 impl VcfCodec {
+    const MAGIC: &'static str = formats::VCF.magic;
+    const VERSION: u16 = formats::VCF.version;
+    const MACROBLOCK_SIZE: usize = 16;
+    /// Mean absolute luma difference per sample up to which a block and
+    /// the keyframe moved back to it are taken to show the same picture
+    const BLEND_MATCH_SAD: u64 = 12;
+    pub const DEFAULT_GOP_SIZE: u32 = 30;
+    pub const DEFAULT_MAX_QP_DELTA: u8 = 6;
+    /// Largest quantizer offset the format allows
+    pub const QP_DELTA_LIMIT: u8 = 24;
+    /// Largest reference buffer the format allows
+    pub const MAX_REFERENCES: u8 = 4;
+    /// Coarsest frame quantizer, about quality 2
+    pub const MAX_QP: u8 = 63;
+    /// QP whose tables are the standard JPEG tables, i.e. quality 50
+    const STANDARD_QP: i32 = 36;
+    /// 2^(k/6) for k in 0..6, as every 6 QP steps double the step sizes
+    ///
+    /// Literals rather than `powf`, whose last bit differs between math
+    /// libraries. Decoders rebuild the tables from the QP, so they must come
+    /// out identical everywhere; whole octaves are exact powers of two.
+    const SIXTH_OCTAVES: [f64; 6] = [1.0, 1.122462048309373, 1.2599210498948732, std::f64::consts::SQRT_2, 1.5874010519681994, 1.7817974362806785];
+
+    const MB_SKIP: u8 = 0;
+    const MB_CODED: u8 = 1;
+    const END_OF_BLOCK: u8 = 0xFF;
+
     pub fn new() -> Self {
-        Self
+        Self {
+            dct: Dct8x8::shared(),
+            estimator: MotionEstimator::new(),
+            predictor: InterPredictor::new(),
+            gop_size: Self::DEFAULT_GOP_SIZE,
+            max_qp_delta: Self::DEFAULT_MAX_QP_DELTA,
+            reference_count: 1,
+            runtime: RuntimeConfig::default(),
+        }
+    }
+
+    /// Decode GOPs on `runtime`'s pool and refuse files whose buffered
+    /// frames are over its memory limit
+    pub fn with_runtime(mut self, runtime: RuntimeConfig) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Set how many quantizer steps an importance map may move a macroblock
+    /// away from the frame quantizer, up to `QP_DELTA_LIMIT`
+    pub fn with_max_qp_delta(mut self, max_qp_delta: u8) -> Self {
+        self.max_qp_delta = max_qp_delta.min(Self::QP_DELTA_LIMIT);
+        self
+    }
+
+    /// Start a constant-quality encode that takes frames one at a time
+    pub fn encoder(&self, fps: f64, quality: u8) -> VcfEncoder<'_> {
+        self.encoder_with_rate_control(fps, RateControl::ConstantQuality(quality))
+    }
+
+    /// Start an encode that takes frames one at a time
+    pub fn encoder_with_rate_control(&self, fps: f64, rate_control: RateControl) -> VcfEncoder<'_> {
+        let rate_control = match rate_control {
+            RateControl::ConstantQp(qp) => RateControl::ConstantQp(qp.min(Self::MAX_QP)),
+            RateControl::ConstantQuality(quality) => RateControl::ConstantQuality(quality.clamp(1, 100)),
+        };
+        VcfEncoder {
+            codec: self,
+            fps,
+            timebase: Timebase::from_fps(fps),
+            last_pts: None,
+            rate_control,
+            filters: Vec::new(),
+            payload: Vec::new(),
+            entries: Vec::new(),
+            hasher: Sha256::new(),
+            dimensions: None,
+            references: ReferenceBuffer::new(self.reference_count),
+        }
+    }
+
+    /// Set the I-frame interval (1 makes every frame an I-frame)
+    pub fn with_gop_size(mut self, gop_size: u32) -> Self {
+        self.gop_size = gop_size.max(1);
+        self
+    }
+
+    /// Set how many reconstructed frames P-frames may predict from, 1 to
+    /// `MAX_REFERENCES`
+    pub fn with_reference_count(mut self, reference_count: u8) -> Self {
+        self.reference_count = reference_count.clamp(1, Self::MAX_REFERENCES);
+        self
+    }
+
+    /// Encode a .y4m file into a .vcf file
+    pub fn encode(&self, input_path: &str, output_path: &str, quality: u8) -> Result<()> {
+        self.encode_filtered(input_path, output_path, RateControl::ConstantQuality(quality), FilterChain::new())
+    }
+
+    /// Encode a .y4m file into a .vcf file, preprocessing frames with `filters`
+    pub fn encode_filtered(&self, input_path: &str, output_path: &str, rate_control: RateControl, filters: FilterChain) -> Result<()> {
+        let reader = Y4mReader::open(input_path)?;
+        let fps = reader.fps();
+        let vcf_data = self.encode_frames_filtered(reader, fps, rate_control, filters)?;
+        write_atomic(output_path, &vcf_data)
+    }
+
+    /// Encode a stream of frames; only the current and reference frames are held in memory
+    pub fn encode_frames<I>(&self, frames: I, fps: f64, quality: u8) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = Result<VideoFrame>>,
+    {
+        self.encode_stream(frames, fps, RateControl::ConstantQuality(quality), Vec::new())
+    }
+
+    /// Encode a stream of frames after passing them through `filters`
+    ///
+    /// The chain's descriptions are recorded in the header for provenance,
+    /// and the header's frame rate and size are those of the filtered frames.
+    pub fn encode_frames_filtered<I>(&self, frames: I, fps: f64, rate_control: RateControl, filters: FilterChain) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = Result<VideoFrame>>,
+    {
+        let output_fps = filters.output_fps(fps);
+        let descriptions = filters.describe();
+        self.encode_stream(filters.apply(frames, fps), output_fps, rate_control, descriptions)
+    }
+
+    fn encode_stream<I>(&self, frames: I, fps: f64, rate_control: RateControl, filters: Vec<String>) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = Result<VideoFrame>>,
+    {
+        phase!("vcf.encode", rate_control = %rate_control, gop_size = self.gop_size);
+        let mut encoder = self.encoder_with_rate_control(fps, rate_control);
+        encoder.filters = filters;
+        for frame in frames {
+            encoder.push_frame(&frame?)?;
+        }
+        encoder.finish()
+    }
+
+    /// Decode a VCF file to a .y4m byte stream
+    ///
+    /// Y4M frames are evenly spaced at the header's frame rate, so frames
+    /// of a variable-rate file are repeated or dropped to fill each output
+    /// frame with the frame on screen at its time.
+    ///
+    /// GOPs are decoded in parallel with `decode_parallel_ordered`.
+    pub fn decode(&self, vcf_data: &[u8]) -> Result<Vec<u8>> {
+        let (header, _) = self.parse_container(vcf_data)?;
+        let mut writer = Y4mWriter::new(Vec::new(), header.width, header.height, header.fps)?;
+        let timebase = header.timebase();
+        let (fps_num, fps_den) = fps_to_rational(header.fps);
+        let start = header.frame_pts(0).unwrap_or_default();
+        let mut written = 0u128;
+        let mut failed = None;
+        self.decode_parallel_ordered(vcf_data, |index, frame| {
+            let index = index as usize;
+            // Output frames starting before this frame ends
+            let end = (header.frame_pts(index).unwrap_or_default() + header.frame_duration(index).unwrap_or(1) - start) as u128;
+            let slots = (end * timebase.num as u128 * fps_num as u128).div_ceil(timebase.den as u128 * fps_den as u128);
+            while failed.is_none() && written < slots {
+                failed = writer.write_frame(&frame).err();
+                written += 1;
+            }
+        })?;
+        match failed {
+            Some(error) => Err(error),
+            None => Ok(writer.into_inner()),
+        }
+    }
+
+    /// Iterate over decoded frames in display order
+    pub fn frames<'a>(&'a self, vcf_data: &'a [u8]) -> Result<VcfFrames<'a>> {
+        let (header, payload) = self.parse_container(vcf_data)?;
+        Ok(self.frames_from(Cow::Owned(header), payload, 0))
+    }
+
+    /// Decode every frame in order, concealing those that fail to decode
+    /// instead of stopping at the first
+    ///
+    /// A lost frame is the one before it moved on by the motion of the
+    /// last frame decoded. Frames after a lost one predict from its
+    /// stand-in, so they drift until the next keyframe; when that decodes,
+    /// the stand-in and every frame up to the keyframe are blended towards
+    /// it, moved back along each block's motion, by a weight rising evenly
+    /// over the rest of the GOP, so the picture lands on the keyframe
+    /// instead of jumping to it. All frames are held at once.
+    pub fn decode_tolerant(&self, vcf_data: &[u8]) -> Result<DecodeReport> {
+        let mut frames = self.frames(vcf_data)?;
+        let count = frames.header.frames.len();
+        self.check_decode_memory(&frames.header, count + frames.header.reference_count as usize + 1)?;
+        let mut report = DecodeReport { frames: Vec::with_capacity(count), concealed: Vec::new() };
+        for index in 0..count {
+            let frame = match frames.decode_next(index) {
+                Ok(frame) => frame,
+                Err(error) => {
+                    diagnostic!(debug, "concealing VCF frame {}: {:#}", index, error);
+                    let frame_type = frames.header.frames[index].frame_type;
+                    report.concealed.push(ConcealedFrame { index, frame_type, error: format!("{:#}", error) });
+                    frames.conceal(index)
+                }
+            };
+            report.frames.push(frame);
+        }
+        Ok(report)
+    }
+
+    /// Frames from `start`, which must be an I-frame or the first frame
+    fn frames_from<'a>(&'a self, header: Cow<'a, VcfHeader>, payload: &'a [u8], start: usize) -> VcfFrames<'a> {
+        VcfFrames {
+            codec: self,
+            tables: Self::quantization_tables(header.quality.clamp(1, 100)),
+            references: ReferenceBuffer::new(header.reference_count),
+            header,
+            payload,
+            next_index: start,
+            motion: Vec::new(),
+            convergence: None,
+        }
+    }
+
+    /// Decode every frame, one GOP per task on the runtime's pool,
+    /// handing each frame to `sink` with its index as soon as it's decoded
+    ///
+    /// GOPs are independent, so they decode at once; frames of one GOP
+    /// arrive in order, but GOPs interleave. A task holds only its GOP's
+    /// reference frames. Stops at the first error, though frames of other
+    /// GOPs may still reach `sink`.
+    pub fn decode_parallel<F>(&self, vcf_data: &[u8], sink: F) -> Result<()>
+    where
+        F: Fn(u32, VideoFrame) + Sync,
+    {
+        let (header, payload) = self.parse_container(vcf_data)?;
+        let gops = header.gops();
+        self.check_decode_memory(&header, self.runtime.threads().min(gops.len()) * (header.reference_count as usize + 1))?;
+        self.runtime.install(|| gops.into_par_iter().try_for_each(|gop| {
+            self.decode_gop(&header, payload, gop, &sink)
+        }))
+    }
+
+    /// Like `decode_parallel`, but delivering frames in index order
+    ///
+    /// GOPs are decoded in waves of one per pool thread, and each wave is
+    /// delivered once it's done, so at most threads × the longest GOP
+    /// frames are buffered at a time.
+    pub fn decode_parallel_ordered<F>(&self, vcf_data: &[u8], mut sink: F) -> Result<ParallelDecodeStats>
+    where
+        F: FnMut(u32, VideoFrame),
+    {
+        let (header, payload) = self.parse_container(vcf_data)?;
+        let gops = header.gops();
+        let threads = self.runtime.threads().max(1);
+        let longest = gops.iter().map(|gop| gop.len()).max().unwrap_or(0);
+        self.check_decode_memory(&header, threads.min(gops.len()) * (longest + header.reference_count as usize))?;
+        let buffered = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        for wave in gops.chunks(threads) {
+            let decoded = self.runtime.install(|| wave.par_iter()
+                .map(|gop| {
+                    let mut frames = Vec::with_capacity(gop.len());
+                    self.decode_gop(&header, payload, gop.clone(), |index, frame| {
+                        frames.push((index, frame));
+                        let now = buffered.fetch_add(1, Ordering::Relaxed) + 1;
+                        peak.fetch_max(now, Ordering::Relaxed);
+                    })?;
+                    Ok(frames)
+                })
+                .collect::<Result<Vec<_>>>())?;
+            for (index, frame) in decoded.into_iter().flatten() {
+                buffered.fetch_sub(1, Ordering::Relaxed);
+                sink(index, frame);
+            }
+        }
+
+        Ok(ParallelDecodeStats {
+            gops: gops.len(),
+            buffer_bound: threads * longest,
+            peak_buffered: peak.into_inner(),
+        })
+    }
+
+    /// Fail if `frames` 4:2:0 frames of `header`'s size are over the memory limit
+    fn check_decode_memory(&self, header: &VcfHeader, frames: usize) -> Result<()> {
+        let frame_bytes = header.width as u64 * header.height as u64 * 3 / 2;
+        let what = format!("VCF decode of {} {}x{} frames", frames, header.width, header.height);
+        Ok(self.runtime.check_memory(&what, frame_bytes.saturating_mul(frames as u64))?)
+    }
+
+    fn decode_gop(&self, header: &VcfHeader, payload: &[u8], gop: Range<usize>, mut sink: impl FnMut(u32, VideoFrame)) -> Result<()> {
+        let mut frames = self.frames_from(Cow::Borrowed(header), payload, gop.start);
+        for index in gop {
+            let frame = frames.next().ok_or_else(|| anyhow!("VCF frame {} is missing", index))??;
+            sink(index as u32, frame);
+        }
+        Ok(())
+    }
+
+    /// Decode the frame on screen at `seconds`, starting from the keyframe
+    /// before it; returns its index and the frame
+    pub fn seek_to_time(&self, vcf_data: &[u8], seconds: f64) -> Result<(usize, VideoFrame)> {
+        let mut frames = self.frames(vcf_data)?;
+        let index = frames.header.frame_at_time(seconds)
+            .ok_or_else(|| anyhow!("No VCF frame at {}s", seconds))?;
+        frames.next_index = (0..=index).rev()
+            .find(|&keyframe| frames.header.frames[keyframe].frame_type == FrameType::I)
+            .unwrap_or(0);
+        let skip = index - frames.next_index;
+        let frame = frames.nth(skip).ok_or_else(|| anyhow!("VCF frame {} is missing", index))??;
+        Ok((index, frame))
+    }
+
+    /// Per-frame PSNR of `vcf_data` against the frames it was encoded from
+    ///
+    /// Both sides are streamed, so memory use doesn't grow with clip length.
+    pub fn quality_report<I>(&self, original_frames: I, vcf_data: &[u8]) -> Result<Vec<FrameQuality>>
+    where
+        I: IntoIterator<Item = Result<VideoFrame>>,
+    {
+        let decoded_frames = self.frames(vcf_data)?;
+        let frame_types: Vec<FrameType> = decoded_frames.header().frames.iter()
+            .map(|entry| entry.frame_type)
+            .collect();
+
+        let mut originals = original_frames.into_iter();
+        let mut report = Vec::with_capacity(frame_types.len());
+        for (index, decoded) in decoded_frames.enumerate() {
+            let decoded = decoded?;
+            let original = originals.next()
+                .ok_or_else(|| anyhow!("Original has {} frames but the VCF has {}", index, frame_types.len()))??;
+            report.push(FrameQuality::compare(index, frame_types[index], &original, &decoded)?);
+        }
+
+        if originals.next().is_some() {
+            bail!("Original has more frames than the VCF ({})", frame_types.len());
+        }
+        Ok(report)
+    }
+
+    /// Parse the container into its header and frame payload
+    pub fn parse_container<'a>(&self, vcf_data: &'a [u8]) -> Result<(VcfHeader, &'a [u8])> {
+        if vcf_data.len() < 8 {
+            bail!("Invalid VCF file: too small");
+        }
+        if &vcf_data[0..4] != Self::MAGIC.as_bytes() {
+            bail!("Invalid VCF magic number");
+        }
+
+        let header_size = u32::from_le_bytes([vcf_data[4], vcf_data[5], vcf_data[6], vcf_data[7]]) as usize;
+        let payload_start = 8usize.checked_add(header_size)
+            .filter(|&end| end <= vcf_data.len())
+            .ok_or_else(|| anyhow!("Invalid VCF file: header size mismatch"))?;
+
+        let header: VcfHeader = serde_json::from_slice(&vcf_data[8..payload_start])
+            .context("Failed to parse VCF header")?;
+        if !formats::VCF.reads(header.version) {
+            bail!("Unsupported VCF version: {}", header.version);
+        }
+        if header.frames.len() != header.frame_count as usize {
+            bail!("VCF frame index has {} entries, header says {}", header.frames.len(), header.frame_count);
+        }
+        if !(1..=Self::MAX_REFERENCES).contains(&header.reference_count) {
+            bail!("Unsupported VCF reference count: {}", header.reference_count);
+        }
+        if let Some(timebase) = header.timebase {
+            if timebase.num == 0 || timebase.den == 0 {
+                bail!("Invalid VCF timebase {}/{}", timebase.num, timebase.den);
+            }
+            let mut previous = None;
+            for (index, entry) in header.frames.iter().enumerate() {
+                let pts = entry.pts.ok_or_else(|| anyhow!("VCF frame {} has no timestamp", index))?;
+                if previous.is_some_and(|previous| pts <= previous) {
+                    bail!("VCF frame {} timestamp {} doesn't follow {}", index, pts, previous.unwrap_or_default());
+                }
+                previous = Some(pts);
+            }
+        }
+
+        Ok((header, &vcf_data[payload_start..]))
+    }
+
+    fn create_container(&self, header: &VcfHeader, payload: &[u8]) -> Result<Vec<u8>> {
+        let header_json = serde_json::to_vec(header)?;
+        let mut container = Vec::with_capacity(8 + header_json.len() + payload.len());
+        container.extend_from_slice(Self::MAGIC.as_bytes());
+        container.extend_from_slice(&(header_json.len() as u32).to_le_bytes());
+        container.extend_from_slice(&header_json);
+        container.extend_from_slice(payload);
+        Ok(container)
+    }
+
+    fn quantization_tables(quality: u8) -> [[[f64; 8]; 8]; 2] {
+        [
+            Quantization::create_quantization_table(quality, true),
+            Quantization::create_quantization_table(quality, false),
+        ]
+    }
+
+    /// Step scale `offset` QP steps away from the standard tables
+    fn qp_scale(offset: i32) -> f64 {
+        let octaves = offset.div_euclid(6);
+        let octave = if octaves >= 0 { (1u64 << octaves) as f64 } else { 1.0 / (1u64 << -octaves) as f64 };
+        octave * Self::SIXTH_OCTAVES[offset.rem_euclid(6) as usize]
+    }
+
+    /// Tables for a frame coded at `qp`
+    fn qp_tables(qp: u8) -> [[[f64; 8]; 8]; 2] {
+        let scale = Self::qp_scale(qp as i32 - Self::STANDARD_QP);
+        Self::quantization_tables(50).map(|table| table.map(|row| row.map(|step| (step * scale).max(1.0))))
+    }
+
+    /// The QP whose step sizes are closest to the tables of `quality`
+    pub fn qp_for_quality(quality: u8) -> u8 {
+        let quality = quality.clamp(1, 100) as f64;
+        let scale = if quality < 50.0 { 50.0 / quality } else { 2.0 - quality / 50.0 };
+        if scale <= 0.0 {
+            return 0;
+        }
+        // Nearest in octaves: count the geometric midpoints between
+        // neighbouring QPs' scales that `scale` lies above
+        (-Self::STANDARD_QP..Self::MAX_QP as i32 - Self::STANDARD_QP)
+            .filter(|&offset| scale * scale > Self::qp_scale(offset) * Self::qp_scale(offset + 1))
+            .count() as u8
+    }
+
+    /// Pad all planes to whole macroblocks
+    fn pad_frame(frame: &VideoFrame) -> [Plane; 3] {
+        let width = (frame.width as usize).next_multiple_of(Self::MACROBLOCK_SIZE);
+        let height = (frame.height as usize).next_multiple_of(Self::MACROBLOCK_SIZE);
+        [
+            frame.planes[0].padded(width, height),
+            frame.planes[1].padded(width / 2, height / 2),
+            frame.planes[2].padded(width / 2, height / 2),
+        ]
+    }
+
+    fn crop_frame(planes: &[Plane; 3], width: u32, height: u32) -> VideoFrame {
+        let (chroma_width, chroma_height) = VideoFrame::chroma_size(width, height);
+        VideoFrame {
+            width,
+            height,
+            planes: [
+                planes[0].cropped(width as usize, height as usize),
+                planes[1].cropped(chroma_width, chroma_height),
+                planes[2].cropped(chroma_width, chroma_height),
+            ],
+        }
+    }
+
+    /// Code every 8x8 block of every plane independently (DC coded differentially)
+    fn encode_intra(&self, current: &[Plane; 3], tables: &[[[f64; 8]; 8]; 2], qp: Option<&QpMap>, out: &mut Vec<u8>) -> [Plane; 3] {
+        let mut reconstructed = current.clone();
+
+        for (index, plane) in current.iter().enumerate() {
+            let mut previous_dc = 0i16;
+            for by in (0..plane.height).step_by(8) {
+                for bx in (0..plane.width).step_by(8) {
+                    let table = Self::block_table(tables, qp, index, bx, by);
+                    let quantized = self.quantize_residual(plane, bx, by, &FLAT_PREDICTION, &table);
+                    let zigzag = Quantization::block_to_zigzag(&quantized);
+                    write_block(out, zigzag[0].wrapping_sub(previous_dc), &zigzag[1..]);
+                    previous_dc = zigzag[0];
+                    self.reconstruct_block(&mut reconstructed[index], bx, by, &FLAT_PREDICTION, &quantized, &table);
+                }
+            }
+        }
+
+        reconstructed
+    }
+
+    fn decode_intra(&self, data: &mut ByteReader, planes: &mut [Plane; 3], tables: &[[[f64; 8]; 8]; 2], qp: Option<&QpMap>) -> Result<()> {
+
+        for (index, plane) in planes.iter_mut().enumerate() {
+            let blocks_x = plane.width / 8;
+            for (block, quantized) in Self::read_intra_plane(data, blocks_x, plane.height / 8)?.into_iter().enumerate() {
+                let (bx, by) = (block % blocks_x * 8, block / blocks_x * 8);
+                let table = Self::block_table(tables, qp, index, bx, by);
+                self.reconstruct_block(plane, bx, by, &FLAT_PREDICTION, &quantized, &table);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the quantized blocks of one I-frame plane in raster order, with
+    /// the DC prediction undone
+    fn read_intra_plane(data: &mut ByteReader, blocks_x: usize, blocks_y: usize) -> Result<Vec<[[i16; 8]; 8]>> {
+        let mut previous_dc = 0i16;
+        (0..blocks_x * blocks_y)
+            .map(|_| {
+                let mut zigzag = read_block(data)?;
+                zigzag[0] = zigzag[0].wrapping_add(previous_dc);
+                previous_dc = zigzag[0];
+                Ok(Quantization::zigzag_to_block(&zigzag))
+            })
+            .collect()
+    }
+
+    /// Copy frame `index` out as a standalone ICF image
+    ///
+    /// An I-frame coded at a single quantizer is remuxed: its quantized
+    /// coefficients and tables are stored as they are, so the image's
+    /// channels decode to exactly the frame's samples. Other frames have no
+    /// coefficients of their own to copy; they are decoded and encoded
+    /// afresh at the ICF quality closest to the frame's tables, and a
+    /// warning is logged.
+    pub fn extract_icf(&self, vcf_data: &[u8], index: usize) -> Result<Vec<u8>> {
+        let (header, payload) = self.parse_container(vcf_data)?;
+        let entry = header.frames.get(index)
+            .ok_or_else(|| anyhow!("VCF has {} frames, no frame {}", header.frames.len(), index))?;
+        phase!("vcf.extract_icf", index, frame_type = ?entry.frame_type);
+        let tables = match entry.qp {
+            Some(qp) if qp > Self::MAX_QP => bail!("Corrupt VCF frame {} quantizer {}", index, qp),
+            Some(qp) => Self::qp_tables(qp),
+            None => Self::quantization_tables(header.quality.clamp(1, 100)),
+        };
+        let icf = IcfCodec::new();
+
+        if entry.frame_type == FrameType::I && !entry.qp_deltas {
+            let coded = Self::inflate_frame(&header, payload, index)?;
+            let mut data = ByteReader::new(&coded);
+            let padded = Self::pad_frame(&VideoFrame::new(header.width, header.height));
+            let mut channels = Vec::with_capacity(3);
+            for (plane_index, plane) in padded.iter().enumerate() {
+                let (blocks_x, blocks_y) = (plane.width / 8, plane.height / 8);
+                let mut quantized = vec![0i16; plane.width * plane.height];
+                for (index, block) in Self::read_intra_plane(&mut data, blocks_x, blocks_y)?.into_iter().enumerate() {
+                    let (bx, by) = (index % blocks_x * 8, index / blocks_x * 8);
+                    for (row, values) in block.iter().enumerate() {
+                        let start = (by + row) * plane.width + bx;
+                        quantized[start..start + 8].copy_from_slice(values);
+                    }
+                }
+                channels.push(CoefficientPlane {
+                    blocks_x,
+                    blocks_y,
+                    quantized,
+                    quantization_table: tables[(plane_index > 0) as usize],
+                });
+            }
+            if !data.is_empty() {
+                bail!("VCF frame {} has trailing data", index);
+            }
+            return icf.transcode_from_coefficients(
+                header.width, header.height, ChromaSubsampling::S420, &CoefficientPlanes { channels },
+            );
+        }
+
+        diagnostic!(warn, "VCF frame {} is not a single-quantizer I-frame, so it is re-encoded rather than remuxed", index);
+        let frame = self.frames(vcf_data)?
+            .nth(index)
+            .ok_or_else(|| anyhow!("VCF frame {} is missing", index))??;
+        let options = IcfEncodeOptions {
+            quality: IcfCodec::estimate_quality(&tables[0]),
+            chroma_subsampling: ChromaSubsampling::S420.into(),
+            color_space: IcfColorSpace::YCbCr,
+            ..Default::default()
+        };
+        icf.encode_with_options(&DynamicImage::ImageRgb8(frame.to_rgb()), &options)
+    }
+
+    /// Build a VCF from ICF stills, one per frame, shown at `fps`
+    ///
+    /// Stills like those `extract_icf` remuxes (YCbCr 4:2:0, whole
+    /// macroblocks, one QP's tables) are remuxed back as I-frames, their
+    /// coefficients stored as they are, so each frame decodes to exactly
+    /// the still's samples. Other stills are decoded and coded afresh at
+    /// the QP closest to their quality. With `delta`, every frame that may
+    /// be predicted is also tried as a P-frame against the frames before
+    /// it, keeping whichever coding is smaller.
+    pub fn from_icf_frames<'a, I>(&self, stills: I, fps: f64, delta: bool) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        phase!("vcf.from_icf_frames", delta);
+        let icf = IcfCodec::new();
+        let mut encoder = None;
+        for (index, still) in stills.into_iter().enumerate() {
+            let (frame, intra, qp) = match self.remux_intra(&icf, still).with_context(|| format!("ICF frame {}", index))? {
+                Some((frame, coded, qp)) => (frame, Some(coded), qp),
+                None => {
+                    diagnostic!(warn, "ICF frame {} can't be remuxed, so it is re-encoded", index);
+                    let header = icf.parse_container(still)?.0;
+                    let image = icf.decode(still).with_context(|| format!("ICF frame {}", index))?;
+                    (VideoFrame::from_rgb(&image.to_rgb8()), None, Self::qp_for_quality(header.quality))
+                }
+            };
+            encoder.get_or_insert_with(|| self.encoder_with_rate_control(fps, RateControl::ConstantQp(qp)))
+                .push_promoted(&frame, intra, qp, delta)?;
+        }
+        encoder.ok_or_else(|| anyhow!("Cannot encode a video with no frames"))?.finish()
+    }
+
+    /// The frame an ICF still decodes to, its I-frame coding and QP, if its
+    /// coefficients can be stored as they are
+    fn remux_intra(&self, icf: &IcfCodec, icf_data: &[u8]) -> Result<Option<(VideoFrame, Vec<u8>, u8)>> {
+        let (header, _) = icf.parse_container(icf_data)?;
+        let mb = Self::MACROBLOCK_SIZE as u32;
+        if header.is_lossless()
+            || header.color_space != IcfColorSpace::YCbCr
+            || header.chroma_subsampling != ChromaSubsampling::S420
+            || !header.width.is_multiple_of(mb)
+            || !header.height.is_multiple_of(mb)
+        {
+            return Ok(None);
+        }
+        let coefficients = icf.coefficients(icf_data)?;
+        let same_tables = |tables: [[[f64; 8]; 8]; 2]| coefficients.channels.iter()
+            .enumerate()
+            .all(|(channel, plane)| plane.quantization_table == tables[(channel > 0) as usize]);
+        let Some(qp) = (0..=Self::MAX_QP).find(|&qp| same_tables(Self::qp_tables(qp))) else {
+            return Ok(None);
+        };
+
+        let mut coded = Vec::new();
+        for plane in &coefficients.channels {
+            let mut previous_dc = 0i16;
+            for by in 0..plane.blocks_y {
+                for bx in 0..plane.blocks_x {
+                    let block = plane.block(bx, by).context("Coefficient block out of range")?;
+                    let zigzag = Quantization::block_to_zigzag(&block);
+                    write_block(&mut coded, zigzag[0].wrapping_sub(previous_dc), &zigzag[1..]);
+                    previous_dc = zigzag[0];
+                }
+            }
+        }
+        let mut planes = Self::pad_frame(&VideoFrame::new(header.width, header.height));
+        self.decode_intra(&mut ByteReader::new(&coded), &mut planes, &Self::qp_tables(qp), None)?;
+        Ok(Some((Self::crop_frame(&planes, header.width, header.height), coded, qp)))
+    }
+
+    /// Code each macroblock as a reference index, motion vector and DCT
+    /// residual, or skip it
+    ///
+    /// `references` is newest first; the index is only coded when there is
+    /// more than one to choose from.
+    fn encode_inter(
+        &self,
+        current: &[Plane; 3],
+        references: &[[Plane; 3]],
+        tables: &[[[f64; 8]; 8]; 2],
+        qp: Option<&QpMap>,
+        out: &mut Vec<u8>,
+    ) -> [Plane; 3] {
+        let mut reconstructed = current.clone();
+        let mb = Self::MACROBLOCK_SIZE;
+
+        for mby in (0..current[0].height).step_by(mb) {
+            for mbx in (0..current[0].width).step_by(mb) {
+                // Ties keep the newer reference
+                let mut best = (0, MotionVector::default(), u32::MAX);
+                for (index, reference) in references.iter().enumerate() {
+                    let (mv, sad) = self.estimator.estimate(&current[0], &reference[0], mbx, mby, mb);
+                    if sad < best.2 {
+                        best = (index, mv, sad);
+                    }
+                }
+                let (reference_index, mv, _) = best;
+                let reference = &references[reference_index];
+
+                let blocks: Vec<InterBlock> = Self::macroblock_blocks(mbx, mby, mv)
+                    .into_iter()
+                    .map(|(plane, x, y, block_mv)| {
+                        let prediction = self.predictor.predict_8x8(&reference[plane], x, y, block_mv);
+                        let table = Self::block_table(tables, qp, plane, x, y);
+                        let quantized = self.quantize_residual(&current[plane], x, y, &prediction, &table);
+                        InterBlock { plane, x, y, prediction, quantized }
+                    })
+                    .collect();
+
+                let residual_is_zero = blocks.iter()
+                    .all(|block| block.quantized.iter().flatten().all(|&c| c == 0));
+                if reference_index == 0 && mv == MotionVector::default() && residual_is_zero {
+                    out.push(Self::MB_SKIP);
+                } else {
+                    out.push(Self::MB_CODED);
+                    if references.len() > 1 {
+                        out.push(reference_index as u8);
+                    }
+                    write_svarint(out, mv.x as i64);
+                    write_svarint(out, mv.y as i64);
+                    for block in &blocks {
+                        let zigzag = Quantization::block_to_zigzag(&block.quantized);
+                        write_block(out, zigzag[0], &zigzag[1..]);
+                    }
+                }
+
+                for block in &blocks {
+                    let table = Self::block_table(tables, qp, block.plane, block.x, block.y);
+                    self.reconstruct_block(
+                        &mut reconstructed[block.plane], block.x, block.y, &block.prediction, &block.quantized, &table,
+                    );
+                }
+            }
+        }
+
+        reconstructed
+    }
+
+    /// Decode a P-frame into `planes`, returning each macroblock's vector
+    /// over one frame interval, in raster order
+    fn decode_inter(
+        &self,
+        data: &mut ByteReader,
+        planes: &mut [Plane; 3],
+        references: &[[Plane; 3]],
+        tables: &[[[f64; 8]; 8]; 2],
+        qp: Option<&QpMap>,
+    ) -> Result<Vec<MotionVector>> {
+        let mb = Self::MACROBLOCK_SIZE;
+        let mut motion = Vec::with_capacity((planes[0].width / mb) * (planes[0].height / mb));
+
+        for mby in (0..planes[0].height).step_by(mb) {
+            for mbx in (0..planes[0].width).step_by(mb) {
+                let (reference_index, mv, coded) = match data.read_u8()? {
+                    Self::MB_SKIP => (0, MotionVector::default(), false),
+                    Self::MB_CODED => {
+                        let reference_index = if references.len() > 1 { data.read_u8()? as usize } else { 0 };
+                        let x = i32::try_from(data.read_svarint()?).context("Corrupt VCF motion vector")?;
+                        let y = i32::try_from(data.read_svarint()?).context("Corrupt VCF motion vector")?;
+                        (reference_index, MotionVector { x, y }, true)
+                    }
+                    mode => bail!("Corrupt VCF macroblock mode {}", mode),
+                };
+                let reference = references.get(reference_index)
+                    .ok_or_else(|| anyhow!("Corrupt VCF reference index {} at macroblock ({}, {})", reference_index, mbx, mby))?;
+                if !InterPredictor::in_bounds(&reference[0], mbx, mby, mv, mb) {
+                    bail!("Corrupt VCF motion vector ({}, {}) at macroblock ({}, {})", mv.x, mv.y, mbx, mby);
+                }
+                // Reference `i` is `i + 1` frames back
+                let frames = reference_index as i32 + 1;
+                motion.push(MotionVector { x: mv.x / frames, y: mv.y / frames });
+
+                for (plane, bx, by, block_mv) in Self::macroblock_blocks(mbx, mby, mv) {
+                    let prediction = self.predictor.predict_8x8(&reference[plane], bx, by, block_mv);
+                    let quantized = if coded {
+                        Quantization::zigzag_to_block(&read_block(data)?)
+                    } else {
+                        [[0; 8]; 8]
+                    };
+                    let table = Self::block_table(tables, qp, plane, bx, by);
+                    self.reconstruct_block(&mut planes[plane], bx, by, &prediction, &quantized, &table);
+                }
+            }
+        }
+
+        Ok(motion)
+    }
+
+    /// `planes` with each macroblock fetched from where its vector in
+    /// `motion` points, reading past the edges from the nearest edge
+    /// sample; macroblocks without a vector stay put
+    fn motion_compensate(planes: &[Plane; 3], motion: &[MotionVector]) -> [Plane; 3] {
+        let columns = planes[0].width / Self::MACROBLOCK_SIZE;
+        std::array::from_fn(|index| {
+            let source = &planes[index];
+            let size = if index == 0 { Self::MACROBLOCK_SIZE } else { Self::MACROBLOCK_SIZE / 2 };
+            let mut out = source.clone();
+            for y in 0..source.height {
+                for x in 0..source.width {
+                    let mv = motion.get((y / size) * columns + x / size).copied().unwrap_or_default();
+                    let mv = if index == 0 { mv } else { InterPredictor::chroma_vector(mv) };
+                    let from_x = (x as i64 + mv.x as i64).clamp(0, source.width as i64 - 1) as usize;
+                    let from_y = (y as i64 + mv.y as i64).clamp(0, source.height as i64 - 1) as usize;
+                    out.set(x, y, source.get(from_x, from_y));
+                }
+            }
+            out
+        })
+    }
+
+    /// `current` moved `weight` of the way to `keyframe`, `frames_ahead`
+    /// frames later, first moved back to where `current` has its picture
+    ///
+    /// Each block of the keyframe is fetched from where its `motion` over
+    /// one frame interval, kept up, takes it. The vectors are decoded, not
+    /// searched for, so the drift being corrected isn't matched and kept.
+    /// Blocks whose picture has left the frame by then, or that don't look
+    /// like what's fetched for them, are left as they are.
+    fn blend_towards(current: &[Plane; 3], keyframe: &[Plane; 3], motion: &[MotionVector], frames_ahead: usize, weight: f64) -> [Plane; 3] {
+        let mb = Self::MACROBLOCK_SIZE;
+        let (columns, rows) = (current[0].width / mb, current[0].height / mb);
+        // current(p) matches previous(p + step), so it matches the keyframe
+        // at p - step for every frame between them
+        let ahead = frames_ahead as i32;
+        let motion: Vec<MotionVector> = (0..motion.len())
+            .map(|block| {
+                let step = Self::median_vector(motion, columns, rows, block);
+                MotionVector { x: -step.x * ahead, y: -step.y * ahead }
+            })
+            .collect();
+        let aligned = Self::motion_compensate(keyframe, &motion);
+        let (width, height) = (current[0].width as i64, current[0].height as i64);
+        let blended: Vec<bool> = motion
+            .iter()
+            .enumerate()
+            .map(|(block, mv)| {
+                let (mbx, mby) = ((block % columns) * mb, (block / columns) * mb);
+                let (from_x, from_y) = (mbx as i64 + mv.x as i64, mby as i64 + mv.y as i64);
+                let inside = from_x >= 0 && from_y >= 0 && from_x + mb as i64 <= width && from_y + mb as i64 <= height;
+                let sad: u64 = (mby..mby + mb)
+                    .flat_map(|y| (mbx..mbx + mb).map(move |x| (x, y)))
+                    .map(|(x, y)| current[0].get(x, y).abs_diff(aligned[0].get(x, y)) as u64)
+                    .sum();
+                inside && sad <= Self::BLEND_MATCH_SAD * (mb * mb) as u64
+            })
+            .collect();
+        std::array::from_fn(|index| {
+            let size = if index == 0 { mb } else { mb / 2 };
+            let mut plane = current[index].clone();
+            for y in 0..plane.height {
+                for x in 0..plane.width {
+                    if blended.get((y / size) * columns + x / size).copied().unwrap_or(false) {
+                        let (sample, target) = (plane.get(x, y) as f64, aligned[index].get(x, y) as f64);
+                        plane.set(x, y, (sample + (target - sample) * weight).round() as u8);
+                    }
+                }
+            }
+            plane
+        })
+    }
+
+    /// Vector median of `block`'s vector and its neighbours': the one
+    /// nearest all the others, so a stray vector doesn't stand for its area
+    ///
+    /// Ties go to the longer vector. Searches stop at the frame edge, so
+    /// blocks there fall short of their motion but never overshoot it.
+    fn median_vector(motion: &[MotionVector], columns: usize, rows: usize, block: usize) -> MotionVector {
+        let (column, row) = (block % columns, block / columns);
+        let neighbours: Vec<MotionVector> = (row.saturating_sub(1)..(row + 2).min(rows))
+            .flat_map(|y| (column.saturating_sub(1)..(column + 2).min(columns)).map(move |x| y * columns + x))
+            .filter_map(|index| motion.get(index).copied())
+            .collect();
+        let distance = |a: &MotionVector| -> f64 {
+            neighbours.iter().map(|b| (((a.x - b.x) as f64).powi(2) + ((a.y - b.y) as f64).powi(2)).sqrt()).sum()
+        };
+        let length = |a: &MotionVector| a.x.abs() + a.y.abs();
+        neighbours.iter().copied()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)).then(length(b).cmp(&length(a))))
+            .unwrap_or_default()
+    }
+
+    /// Quantization table for block (bx, by) of `plane`, scaled by its macroblock's offset
+    fn block_table(tables: &[[[f64; 8]; 8]; 2], qp: Option<&QpMap>, plane: usize, bx: usize, by: usize) -> [[f64; 8]; 8] {
+        let table = tables[(plane > 0) as usize];
+        match qp.map(|qp| qp.delta_at(plane, bx, by)) {
+            None | Some(0) => table,
+            Some(delta) => {
+                let scale = Self::qp_scale(delta as i32);
+                table.map(|row| row.map(|step| (step * scale).max(1.0)))
+            }
+        }
+    }
+
+    /// The six 8x8 blocks of a macroblock as (plane, x, y, vector), in coding order
+    fn macroblock_blocks(mbx: usize, mby: usize, mv: MotionVector) -> [(usize, usize, usize, MotionVector); 6] {
+        let chroma_mv = InterPredictor::chroma_vector(mv);
+        [
+            (0, mbx, mby, mv),
+            (0, mbx + 8, mby, mv),
+            (0, mbx, mby + 8, mv),
+            (0, mbx + 8, mby + 8, mv),
+            (1, mbx / 2, mby / 2, chroma_mv),
+            (2, mbx / 2, mby / 2, chroma_mv),
+        ]
+    }
+
+    fn quantize_residual(
+        &self,
+        plane: &Plane,
+        bx: usize,
+        by: usize,
+        prediction: &[[f64; 8]; 8],
+        table: &[[f64; 8]; 8],
+    ) -> [[i16; 8]; 8] {
+        let block = plane.block(bx / 8, by / 8);
+        let samples = std::array::from_fn(|row| std::array::from_fn(|col| block.get(row, col) as f64));
+        residual::quantize_residual(self.dct, &samples, prediction, table)
+    }
+
+    /// Write prediction + dequantized residual into `plane`; shared by encoder and decoder
+    fn reconstruct_block(
+        &self,
+        plane: &mut Plane,
+        bx: usize,
+        by: usize,
+        prediction: &[[f64; 8]; 8],
+        quantized: &[[i16; 8]; 8],
+        table: &[[f64; 8]; 8],
+    ) {
+        let reconstructed = residual::reconstruct_residual(self.dct, prediction, quantized, table);
+        let block = reconstructed.map(|row| row.map(|sample| sample.round().clamp(0.0, 255.0) as u8));
+        plane.write_block(bx / 8, by / 8, &block);
+    }
+
+    /// The coefficient stream of frame `index`
+    fn inflate_frame(header: &VcfHeader, payload: &[u8], index: usize) -> Result<Vec<u8>> {
+        let entry = &header.frames[index];
+        let start = usize::try_from(entry.offset)?;
+        let end = start.checked_add(usize::try_from(entry.size)?)
+            .filter(|&end| end <= payload.len())
+            .ok_or_else(|| anyhow!("VCF frame {} extends past end of file", index))?;
+
+        let mut coded = Vec::new();
+        DeflateDecoder::new(&payload[start..end])
+            .read_to_end(&mut coded)
+            .with_context(|| format!("Failed to inflate VCF frame {}", index))?;
+        Ok(coded)
+    }
+
+    fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+/// Incremental encoder returned by [`VcfCodec::encoder`]
+///
+/// Holds only the reference frames and the coded output between frames.
+pub struct VcfEncoder<'a> {
+    codec: &'a VcfCodec,
+    fps: f64,
+    timebase: Timebase,
+    last_pts: Option<u64>,
+    rate_control: RateControl,
+    filters: Vec<String>,
+    payload: Vec<u8>,
+    entries: Vec<VcfFrameEntry>,
+    hasher: Sha256,
+    dimensions: Option<(u32, u32)>,
+    references: ReferenceBuffer,
+}
+
+impl VcfEncoder<'_> {
+    /// Count timestamps in ticks of `timebase` instead of one per frame;
+    /// set it before pushing frames
+    pub fn with_timebase(mut self, timebase: Timebase) -> Self {
+        self.timebase = timebase;
+        self
+    }
+
+    /// Code the next frame at the frame quantizer, one nominal frame after
+    /// the previous one
+    pub fn push_frame(&mut self, frame: &VideoFrame) -> Result<()> {
+        self.push(frame, None, self.next_pts())
+    }
+
+    /// Code the next frame, presented at `pts` ticks of the timebase
+    ///
+    /// Timestamps must be strictly increasing; gaps between them may vary,
+    /// as in variable-frame-rate recordings.
+    pub fn push_frame_at(&mut self, frame: &VideoFrame, pts: u64) -> Result<()> {
+        if let Some(last) = self.last_pts.filter(|&last| pts <= last) {
+            bail!("Frame {} timestamp {} doesn't follow {}", self.entries.len(), pts, last);
+        }
+        self.push(frame, None, pts)
+    }
+
+    /// Code the next frame, spending more bits where `map` is high
+    ///
+    /// Each macroblock's quantizer moves at most the codec's
+    /// `max_qp_delta` steps from the frame quantizer; see
+    /// [`ImportanceMap::qp_deltas`].
+    pub fn push_frame_with_map(&mut self, frame: &VideoFrame, map: &ImportanceMap) -> Result<()> {
+        let deltas = map.qp_deltas(frame.width, frame.height, self.codec.max_qp_delta)?;
+        let columns = frame.width.div_ceil(VcfCodec::MACROBLOCK_SIZE as u32) as usize;
+        self.push(frame, Some(QpMap { columns, deltas }), self.next_pts())
     }
 
-    pub fn encode(&self, _input_path: &str, _output_path: &str, _quality: u8) -> Result<()> {
-        // Placeholder implementation
-        todo!("VCF encoding implementation")
+    /// Write the container
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let (width, height) = self.dimensions.ok_or_else(|| anyhow!("Cannot encode a video with no frames"))?;
+        if self.timebase.num == 0 || self.timebase.den == 0 {
+            bail!("Invalid timebase {}/{}", self.timebase.num, self.timebase.den);
+        }
+        let frame_count = self.entries.len() as u32;
+        let first_pts = self.entries.first().and_then(|entry| entry.pts).unwrap_or_default();
+        let end_pts = self.last_pts.unwrap_or_default() + self.timebase.ticks_per_frame(self.fps);
+        let mut qp_histogram = BTreeMap::new();
+        for qp in self.entries.iter().filter_map(|entry| entry.qp) {
+            *qp_histogram.entry(qp).or_insert(0) += 1;
+        }
+        let (quality, constant_qp) = match self.rate_control {
+            RateControl::ConstantQp(qp) => (0, Some(qp)),
+            RateControl::ConstantQuality(quality) => (quality, None),
+        };
+        let header = VcfHeader {
+            magic: VcfCodec::MAGIC.to_string(),
+            version: VcfCodec::VERSION,
+            width,
+            height,
+            fps: self.fps,
+            frame_count,
+            duration: self.timebase.seconds(end_pts - first_pts),
+            quality,
+            constant_qp,
+            gop_size: self.codec.gop_size,
+            reference_count: self.references.capacity,
+            block_size: VcfCodec::MACROBLOCK_SIZE as u8,
+            original_size: (VideoFrame::raw_size(width, height) * frame_count as usize) as u64,
+            compressed_size: self.payload.len() as u64,
+            checksum: format!("{:x}", self.hasher.finalize()),
+            frames: self.entries,
+            filters: self.filters,
+            qp_histogram,
+            timebase: Some(self.timebase),
+        };
+        trace_event!(frames = frame_count, bytes_in = header.original_size, bytes_out = self.payload.len(), "encoded VCF");
+
+        self.codec.create_container(&header, &self.payload)
+    }
+
+    fn next_pts(&self) -> u64 {
+        self.last_pts.map_or(0, |last| last + self.timebase.ticks_per_frame(self.fps))
+    }
+
+    /// Check `frame` against the video's size and add it to the checksum,
+    /// returning its index
+    fn begin_frame(&mut self, frame: &VideoFrame) -> Result<usize> {
+        let index = self.entries.len();
+        match self.dimensions {
+            None => self.dimensions = Some((frame.width, frame.height)),
+            Some(dims) if dims != (frame.width, frame.height) => {
+                bail!("Frame {} is {}x{}, expected {}x{}", index, frame.width, frame.height, dims.0, dims.1);
+            }
+            Some(_) => {}
+        }
+        for plane in &frame.planes {
+            self.hasher.update(&plane.data);
+        }
+        Ok(index)
+    }
+
+    /// Whether frame `index` may be a P-frame
+    fn may_predict(&self, index: usize) -> bool {
+        !self.references.frames().is_empty() && !(index as u32).is_multiple_of(self.codec.gop_size)
+    }
+
+    /// Record a coded frame and make its reconstruction a reference
+    fn append(&mut self, frame_type: FrameType, compressed: &[u8], qp_deltas: bool, qp: u8, pts: u64, reconstructed: [Plane; 3]) {
+        let references = self.references.frames().len();
+        self.entries.push(VcfFrameEntry {
+            frame_type,
+            offset: self.payload.len() as u64,
+            size: compressed.len() as u64,
+            qp_deltas,
+            references: if frame_type == FrameType::P && references > 1 { references as u8 } else { 0 },
+            qp: Some(qp),
+            pts: Some(pts),
+        });
+        self.last_pts = Some(pts);
+        self.payload.extend_from_slice(compressed);
+        self.references.update(frame_type, reconstructed);
+    }
+
+    /// Add a frame from `from_icf_frames` at `qp`: `intra` is its I-frame
+    /// coding when that is already known, as for remuxed stills
+    ///
+    /// With `delta`, a frame that may be predicted is also coded as a
+    /// P-frame, which is kept if it comes out smaller.
+    fn push_promoted(&mut self, frame: &VideoFrame, intra: Option<Vec<u8>>, qp: u8, delta: bool) -> Result<()> {
+        let index = self.begin_frame(frame)?;
+        phase!("vcf.promote_frame", index);
+        let codec = self.codec;
+        let tables = VcfCodec::qp_tables(qp);
+        let current = VcfCodec::pad_frame(frame);
+        let (coded, reconstructed) = match intra {
+            // The frame is what these coefficients decode to, so it is its own reconstruction
+            Some(coded) => (coded, current.clone()),
+            None => {
+                let mut coded = Vec::new();
+                let reconstructed = codec.encode_intra(&current, &tables, None, &mut coded);
+                (coded, reconstructed)
+            }
+        };
+        let mut chosen = (FrameType::I, VcfCodec::deflate(&coded)?, reconstructed);
+
+        if delta && self.may_predict(index) {
+            let mut coded = Vec::new();
+            let reconstructed = codec.encode_inter(&current, self.references.frames(), &tables, None, &mut coded);
+            let compressed = VcfCodec::deflate(&coded)?;
+            if compressed.len() < chosen.1.len() {
+                chosen = (FrameType::P, compressed, reconstructed);
+            }
+        }
+        let (frame_type, compressed, reconstructed) = chosen;
+        trace_event!(frame_type = ?frame_type, qp, bytes_out = compressed.len(), "promoted frame");
+        self.append(frame_type, &compressed, false, qp, self.next_pts(), reconstructed);
+        Ok(())
+    }
+
+    fn push(&mut self, frame: &VideoFrame, qp: Option<QpMap>, pts: u64) -> Result<()> {
+        let index = self.begin_frame(frame)?;
+
+        phase!("vcf.frame", index);
+        let codec = self.codec;
+        let current = VcfCodec::pad_frame(frame);
+        let mut coded = Vec::new();
+        if let Some(qp) = &qp {
+            for &delta in &qp.deltas {
+                write_svarint(&mut coded, delta as i64);
+            }
+        }
+        let frame_qp = self.rate_control.frame_qp();
+        let tables = VcfCodec::qp_tables(frame_qp);
+        let (frame_type, reconstructed) = if self.may_predict(index) {
+            (FrameType::P, codec.encode_inter(&current, self.references.frames(), &tables, qp.as_ref(), &mut coded))
+        } else {
+            (FrameType::I, codec.encode_intra(&current, &tables, qp.as_ref(), &mut coded))
+        };
+
+        let compressed = VcfCodec::deflate(&coded)?;
+        trace_event!(frame_type = ?frame_type, qp = frame_qp, coded_bytes = coded.len(), bytes_out = compressed.len(), "coded frame");
+        self.append(frame_type, &compressed, qp.is_some(), frame_qp, pts, reconstructed);
+        Ok(())
+    }
+}
+
+/// Reconstructed frames P-frames predict from, newest first
+///
+/// Both sides apply the same update after every frame: an I-frame empties
+/// the buffer, and once `capacity` frames are held the oldest drops out.
+struct ReferenceBuffer {
+    capacity: u8,
+    frames: Vec<[Plane; 3]>,
+}
+
+impl ReferenceBuffer {
+    fn new(capacity: u8) -> Self {
+        Self { capacity, frames: Vec::with_capacity(capacity as usize) }
+    }
+
+    fn frames(&self) -> &[[Plane; 3]] {
+        &self.frames
+    }
+
+    fn update(&mut self, frame_type: FrameType, reconstructed: [Plane; 3]) {
+        if frame_type == FrameType::I {
+            self.frames.clear();
+        }
+        self.frames.insert(0, reconstructed);
+        self.frames.truncate(self.capacity as usize);
+    }
+}
+
+fn one_reference() -> u8 {
+    1
+}
+
+fn single_reference(count: &u8) -> bool {
+    *count <= 1
+}
+
+/// Quantizer offsets of a frame's macroblocks, in raster order
+struct QpMap {
+    columns: usize,
+    deltas: Vec<i8>,
+}
+
+impl QpMap {
+    fn read(data: &mut ByteReader, columns: usize, rows: usize) -> Result<Self> {
+        let limit = VcfCodec::QP_DELTA_LIMIT as i64;
+        let deltas = (0..columns * rows)
+            .map(|_| match data.read_svarint()? {
+                delta if (-limit..=limit).contains(&delta) => Ok(delta as i8),
+                delta => bail!("Corrupt VCF quantizer offset {}", delta),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { columns, deltas })
+    }
+
+    /// Offset of the macroblock holding block (bx, by) of `plane`
+    fn delta_at(&self, plane: usize, bx: usize, by: usize) -> i8 {
+        let size = if plane == 0 { VcfCodec::MACROBLOCK_SIZE } else { VcfCodec::MACROBLOCK_SIZE / 2 };
+        self.deltas[(by / size) * self.columns + bx / size]
+    }
+}
+
+/// One 8x8 block of a P-frame macroblock during encoding
+struct InterBlock {
+    plane: usize,
+    x: usize,
+    y: usize,
+    prediction: [[f64; 8]; 8],
+    quantized: [[i16; 8]; 8],
+}
+
+/// How a `VcfCodec::decode_parallel_ordered` run went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelDecodeStats {
+    pub gops: usize,
+    /// Most frames that could be buffered: pool threads × the longest GOP
+    pub buffer_bound: usize,
+    /// Most frames that were buffered at once
+    pub peak_buffered: usize,
+}
+
+/// Frames from `VcfCodec::decode_tolerant`, and which of them are stand-ins
+#[derive(Debug, Clone)]
+pub struct DecodeReport {
+    pub frames: Vec<VideoFrame>,
+    /// Frames that failed to decode and were concealed, in order
+    pub concealed: Vec<ConcealedFrame>,
+}
+
+/// A frame `VcfCodec::decode_tolerant` couldn't decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcealedFrame {
+    pub index: usize,
+    pub frame_type: FrameType,
+    /// Why it didn't decode
+    pub error: String,
+}
+
+/// Streaming decoder returned by [`VcfCodec::frames`]
+///
+/// Holds only the reference frames between iterations.
+pub struct VcfFrames<'a> {
+    codec: &'a VcfCodec,
+    header: Cow<'a, VcfHeader>,
+    payload: &'a [u8],
+    /// Tables for frames without a QP, from version 1 files
+    tables: [[[f64; 8]; 8]; 2],
+    next_index: usize,
+    references: ReferenceBuffer,
+    /// Vector of each macroblock of the last frame decoded over one frame
+    /// interval; empty after an I-frame
+    motion: Vec<MotionVector>,
+    /// Where frames after a concealed one are steered, until its GOP ends
+    convergence: Option<Convergence>,
+}
+
+/// The keyframe ending the GOP a frame was lost in, which the frames from
+/// the loss up to it are blended towards
+struct Convergence {
+    /// First frame of the GOP that was lost
+    lost: usize,
+    /// Index of the keyframe
+    index: usize,
+    keyframe: [Plane; 3],
+}
+
+impl Convergence {
+    /// Weight of the keyframe in frame `index`, rising evenly from the loss
+    /// so the frame before the keyframe is nearly all keyframe
+    fn weight(&self, index: usize) -> f64 {
+        (index + 1 - self.lost) as f64 / (self.index + 1 - self.lost) as f64
+    }
+}
+
+impl<'a> VcfFrames<'a> {
+    pub fn header(&self) -> &VcfHeader {
+        &self.header
+    }
+
+    /// Frames paired with their presentation time in header timebase ticks
+    pub fn timed(mut self) -> impl Iterator<Item = Result<(u64, VideoFrame)>> + 'a {
+        std::iter::from_fn(move || {
+            let pts = self.header.frame_pts(self.next_index)?;
+            self.next().map(|frame| frame.map(|frame| (pts, frame)))
+        })
+    }
+
+    fn decode_next(&mut self, index: usize) -> Result<VideoFrame> {
+        phase!("vcf.decode_frame", index);
+        let entry = &self.header.frames[index];
+        let coded = VcfCodec::inflate_frame(&self.header, self.payload, index)?;
+        let mut data = ByteReader::new(&coded);
+
+        let tables = match entry.qp {
+            Some(qp) if qp > VcfCodec::MAX_QP => bail!("Corrupt VCF frame {} quantizer {}", index, qp),
+            Some(qp) => VcfCodec::qp_tables(qp),
+            None => self.tables,
+        };
+        let blank = VcfCodec::pad_frame(&VideoFrame::new(self.header.width, self.header.height));
+        let mut planes = blank;
+        let mb = VcfCodec::MACROBLOCK_SIZE;
+        let qp = if entry.qp_deltas {
+            Some(QpMap::read(&mut data, planes[0].width / mb, planes[0].height / mb)?)
+        } else {
+            None
+        };
+        let motion = match entry.frame_type {
+            FrameType::I => {
+                self.codec.decode_intra(&mut data, &mut planes, &tables, qp.as_ref())?;
+                Vec::new()
+            }
+            FrameType::P => {
+                let references = self.references.frames()
+                    .get(..(entry.references as usize).max(1))
+                    .ok_or_else(|| anyhow!("VCF frame {} predicts from {} references, {} decoded",
+                        index, entry.references.max(1), self.references.frames().len()))?;
+                self.codec.decode_inter(&mut data, &mut planes, references, &tables, qp.as_ref())?
+            }
+        };
+        if !data.is_empty() {
+            bail!("VCF frame {} has trailing data", index);
+        }
+
+        self.motion = motion;
+        let planes = self.converge(index, planes);
+        let frame = VcfCodec::crop_frame(&planes, self.header.width, self.header.height);
+        self.references.update(self.header.frames[index].frame_type, planes);
+        Ok(frame)
+    }
+
+    /// Stand-in for frame `index`, which failed to decode, buffered for
+    /// later frames to predict from; see `VcfCodec::decode_tolerant`
+    fn conceal(&mut self, index: usize) -> VideoFrame {
+        let (width, height) = (self.header.width, self.header.height);
+        if self.convergence.as_ref().is_none_or(|convergence| convergence.index <= index) {
+            self.convergence = self.header.frames.iter()
+                .skip(index + 1)
+                .position(|entry| entry.frame_type == FrameType::I)
+                .map(|offset| index + 1 + offset)
+                .and_then(|next| {
+                    let keyframe = self.codec.frames_from(Cow::Borrowed(&*self.header), self.payload, next).next()?.ok()?;
+                    Some(Convergence { lost: index, index: next, keyframe: VcfCodec::pad_frame(&keyframe) })
+                });
+        }
+        let planes = match (self.references.frames().first(), &self.convergence) {
+            (Some(previous), _) => VcfCodec::motion_compensate(previous, &self.motion),
+            (None, Some(convergence)) => convergence.keyframe.clone(),
+            (None, None) => VcfCodec::pad_frame(&VideoFrame::new(width, height)),
+        };
+        let planes = self.converge(index, planes);
+        let frame = VcfCodec::crop_frame(&planes, width, height);
+        self.references.update(self.header.frames[index].frame_type, planes);
+        frame
+    }
+
+    /// Frame `index` blended towards the keyframe ending its GOP, if a
+    /// frame before it in the GOP was concealed
+    fn converge(&mut self, index: usize, planes: [Plane; 3]) -> [Plane; 3] {
+        match &self.convergence {
+            Some(convergence) if index < convergence.index => {
+                let ahead = convergence.index - index;
+                VcfCodec::blend_towards(&planes, &convergence.keyframe, &self.motion, ahead, convergence.weight(index))
+            }
+            Some(_) => {
+                self.convergence = None;
+                planes
+            }
+            None => planes,
+        }
+    }
+}
+
+impl Iterator for VcfFrames<'_> {
+    type Item = Result<VideoFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next_index;
+        if index >= self.header.frames.len() {
+            return None;
+        }
+
+        let result = self.decode_next(index);
+        // Later frames depend on this one, so stop after the first error
+        self.next_index = if result.is_ok() { index + 1 } else { self.header.frames.len() };
+        Some(result)
+    }
+}
+
+/// Coefficients of one block: DC, then (run of zeros, level) pairs for the AC terms
+fn write_block(out: &mut Vec<u8>, dc: i16, ac: &[i16]) {
+    write_svarint(out, dc as i64);
+    let mut run = 0u8;
+    for &coefficient in ac {
+        if coefficient == 0 {
+            run += 1;
+        } else {
+            out.push(run);
+            write_svarint(out, coefficient as i64);
+            run = 0;
+        }
+    }
+    out.push(VcfCodec::END_OF_BLOCK);
+}
+
+fn read_block(data: &mut ByteReader) -> Result<Vec<i16>> {
+    let mut zigzag = vec![0i16; 64];
+    zigzag[0] = i16::try_from(data.read_svarint()?).context("Corrupt VCF coefficient")?;
+
+    let mut position = 1usize;
+    loop {
+        let run = data.read_u8()?;
+        if run == VcfCodec::END_OF_BLOCK {
+            break;
+        }
+        position += run as usize;
+        if position >= 64 {
+            bail!("Corrupt VCF block: coefficient index out of range");
+        }
+        zigzag[position] = i16::try_from(data.read_svarint()?).context("Corrupt VCF coefficient")?;
+        position += 1;
+    }
+    Ok(zigzag)
+}
+
+fn write_svarint(out: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push((zigzag as u8) | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+/// Cursor over a decoded frame's coefficient stream
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let byte = *self.data.get(self.position).ok_or_else(|| anyhow!("Truncated VCF frame data"))?;
+        self.position += 1;
+        Ok(byte)
     }
 
-    pub fn decode(&self, _vcf_data: &[u8]) -> Result<Vec<u8>> {
-        // Placeholder implementation
-        todo!("VCF decoding implementation")
+    fn read_svarint(&mut self) -> Result<i64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(((value >> 1) as i64) ^ -((value & 1) as i64));
+            }
+        }
+        bail!("Corrupt VCF varint")
     }
 }
 
@@ -43,7 +1720,7 @@ pub struct VideoCompressionStats {
 
 impl std::fmt::Display for VideoCompressionStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, 
+        write!(f,
             "Original: {} bytes, Compressed: {} bytes, Ratio: {:.2}:1, Savings: {:.2}%",
             self.original_size,
             self.compressed_size,
@@ -51,4 +1728,499 @@ impl std::fmt::Display for VideoCompressionStats {
             self.savings_percent
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::video::filter::{FpsConverter, Scale, ScaleMethod, TemporalDenoise};
+    use crate::codecs::video::quality::{mean_squared_error, psnr_from_mse, QualitySummary};
+
+    /// Smooth pattern drifting right by `speed` pixels per frame
+    fn moving_frame(width: u32, height: u32, t: u32, speed: u32) -> VideoFrame {
+        let mut frame = VideoFrame::new(width, height);
+        for (index, plane) in frame.planes.iter_mut().enumerate() {
+            let scale = if index == 0 { 1.0 } else { 2.0 };
+            for y in 0..plane.height {
+                for x in 0..plane.width {
+                    let fx = (x as f64 * scale - (t * speed) as f64) / 7.0;
+                    let fy = y as f64 * scale / 9.0;
+                    let amplitude = if index == 0 { 70.0 } else { 20.0 };
+                    plane.set(x, y, (128.0 + amplitude * fx.sin() * fy.cos()).round() as u8);
+                }
+            }
+        }
+        frame
+    }
+
+    fn add_noise(frame: &mut VideoFrame, seed: u64) {
+        let mut state = seed;
+        for plane in frame.planes.iter_mut() {
+            for sample in plane.data.iter_mut() {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let noise = ((state >> 33) % 121) as i32 - 60;
+                *sample = (*sample as i32 + noise).clamp(0, 255) as u8;
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_decode_matches_sequential() {
+        let frames: Vec<VideoFrame> = (0..30).map(|t| moving_frame(32, 24, t, 2)).collect();
+        let codec = VcfCodec::new().with_gop_size(3);
+        let vcf_data = codec.encode_frames(frames.into_iter().map(Ok), 25.0, 80).unwrap();
+        let sequential: Vec<VideoFrame> = codec.frames(&vcf_data).unwrap().collect::<Result<_>>().unwrap();
+        let (header, _) = codec.parse_container(&vcf_data).unwrap();
+        assert_eq!(header.gops().len(), 10);
+        assert!(header.gops().iter().all(|gop| header.frames[gop.start].frame_type == FrameType::I && gop.len() == 3));
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let unordered = std::sync::Mutex::new(Vec::new());
+        pool.install(|| codec.decode_parallel(&vcf_data, |index, frame| unordered.lock().unwrap().push((index, frame))))
+            .unwrap();
+        let mut unordered = unordered.into_inner().unwrap();
+        unordered.sort_by_key(|&(index, _)| index);
+        assert!(unordered.into_iter().map(|(_, frame)| frame).eq(sequential.iter().cloned()));
+
+        let mut ordered = Vec::new();
+        let stats = pool.install(|| codec.decode_parallel_ordered(&vcf_data, |index, frame| ordered.push((index, frame))))
+            .unwrap();
+        assert_eq!(ordered.iter().map(|&(index, _)| index).collect::<Vec<_>>(), (0..30).collect::<Vec<_>>());
+        assert!(ordered.into_iter().map(|(_, frame)| frame).eq(sequential));
+        assert_eq!((stats.gops, stats.buffer_bound), (10, 12));
+        assert!(stats.peak_buffered > 0 && stats.peak_buffered <= stats.buffer_bound, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_icf_stills_promote_to_vcf() {
+        // A still background with a small square moving across it
+        let frames: Vec<VideoFrame> = (0..10).map(|t| {
+            let mut frame = moving_frame(48, 32, 0, 0);
+            for y in 8..16 {
+                for x in 0..8 {
+                    frame.planes[0].set(x + 3 * t as usize, y, 240);
+                }
+            }
+            frame
+        }).collect();
+        let intra_only = VcfCodec::new().with_gop_size(1);
+        let source = intra_only.encode_frames(frames.into_iter().map(Ok), 10.0, 75).unwrap();
+        let expected: Vec<VideoFrame> = intra_only.frames(&source).unwrap().collect::<Result<_>>().unwrap();
+        let indices: Vec<usize> = (0..10).collect();
+        let stills = IcfCodec::new().from_vcf_frames(&source, &indices).unwrap();
+
+        let codec = VcfCodec::new();
+        let remuxed = codec.from_icf_frames(stills.iter().map(Vec::as_slice), 10.0, false).unwrap();
+        let (header, _) = codec.parse_container(&remuxed).unwrap();
+        assert!(header.frames.iter().all(|entry| entry.frame_type == FrameType::I));
+        assert!(codec.frames(&remuxed).unwrap().map(Result::unwrap).eq(expected.iter().cloned()));
+        assert_eq!(IcfCodec::new().from_vcf_frames(&remuxed, &indices).unwrap(), stills);
+
+        let delta = codec.from_icf_frames(stills.iter().map(Vec::as_slice), 10.0, true).unwrap();
+        let (header, _) = codec.parse_container(&delta).unwrap();
+        assert!(header.frames[1..].iter().all(|entry| entry.frame_type == FrameType::P));
+        assert!(delta.len() * 2 < remuxed.len(), "delta {} bytes vs {} remuxed", delta.len(), remuxed.len());
+        for (index, (decoded, original)) in codec.frames(&delta).unwrap().zip(&expected).enumerate() {
+            let quality = FrameQuality::compare(index, FrameType::P, original, &decoded.unwrap()).unwrap();
+            assert!(quality.psnr > 35.0, "frame {}: {:.1} dB", index, quality.psnr);
+        }
+
+        // Stills that can't be remuxed are re-encoded
+        let still = IcfCodec::new().encode_image(&DynamicImage::ImageRgb8(expected[0].to_rgb()), 75).unwrap();
+        let reencoded = codec.from_icf_frames([still.as_slice()], 10.0, false).unwrap();
+        assert_eq!(codec.parse_container(&reencoded).unwrap().0.frame_count, 1);
+    }
+
+    #[test]
+    fn test_tolerant_decode_conceals_lost_p_frames() {
+        let frames: Vec<VideoFrame> = (0..16).map(|t| moving_frame(96, 48, t, 2)).collect();
+        let codec = VcfCodec::new().with_gop_size(8);
+        let vcf_data = codec.encode_frames(frames.iter().cloned().map(Ok), 25.0, 85).unwrap();
+        let clean: Vec<VideoFrame> = codec.frames(&vcf_data).unwrap().collect::<Result<_>>().unwrap();
+        let (header, payload) = codec.parse_container(&vcf_data).unwrap();
+        let payload_start = vcf_data.len() - payload.len();
+        assert!(codec.decode_tolerant(&vcf_data).unwrap().concealed.is_empty());
+
+        // Frame 3 has four frames to converge over; frame 7 is followed by
+        // the keyframe, so it's half way there at once
+        for lost in [3, 7] {
+            let mut corrupted = vcf_data.clone();
+            // A reserved deflate block type, so the frame can't inflate
+            corrupted[payload_start + header.frames[lost].offset as usize] = 0xFF;
+            assert!(codec.frames(&corrupted).unwrap().nth(lost).unwrap().is_err());
+
+            let report = codec.decode_tolerant(&corrupted).unwrap();
+            let concealed: Vec<(usize, FrameType)> = report.concealed.iter().map(|frame| (frame.index, frame.frame_type)).collect();
+            assert_eq!(concealed, [(lost, FrameType::P)]);
+            assert_eq!(report.frames.len(), frames.len());
+            let psnr = |decoded: &VideoFrame| FrameQuality::compare(lost, FrameType::P, &frames[lost], decoded).unwrap().psnr;
+            let (concealed, repeated) = (psnr(&report.frames[lost]), psnr(&clean[lost - 1]));
+            assert!(concealed > repeated + 3.0, "frame {}: concealed {:.1} dB, repeated {:.1} dB", lost, concealed, repeated);
+
+            // Exact up to the loss and again from the next keyframe
+            assert_eq!(report.frames[..lost], clean[..lost]);
+            assert_eq!(report.frames[8..], clean[8..]);
+        }
+    }
+
+    #[test]
+    fn test_concealment_converges_on_the_next_keyframe() {
+        let frames: Vec<VideoFrame> = (0..40).map(|t| moving_frame(96, 48, t, 2)).collect();
+        let codec = VcfCodec::new().with_gop_size(32);
+        let vcf_data = codec.encode_frames(frames.iter().cloned().map(Ok), 25.0, 85).unwrap();
+        let clean: Vec<VideoFrame> = codec.frames(&vcf_data).unwrap().collect::<Result<_>>().unwrap();
+        let (header, payload) = codec.parse_container(&vcf_data).unwrap();
+        let mut corrupted = vcf_data.clone();
+        let lost = 2;
+        corrupted[vcf_data.len() - payload.len() + header.frames[lost].offset as usize] = 0xFF;
+        let report = codec.decode_tolerant(&corrupted).unwrap();
+        assert_eq!(report.concealed.iter().map(|frame| frame.index).collect::<Vec<_>>(), [lost]);
+
+        // The stand-in's error mustn't be carried along the GOP, but shrink
+        // until the keyframe, after which the decode is clean again
+        let psnr: Vec<f64> = (lost..32)
+            .map(|index| FrameQuality::compare(index, FrameType::P, &clean[index], &report.frames[index]).unwrap().psnr)
+            .collect();
+        for (offset, value) in psnr.iter().enumerate() {
+            assert!(*value >= psnr[0] - 0.5, "frame {} psnr {:.2} fell below the stand-in's {:.2}", lost + offset, value, psnr[0]);
+        }
+        assert!(psnr[psnr.len() - 1] > psnr[0] + 3.0, "psnr {:.2} before the keyframe, {:.2} when lost", psnr[psnr.len() - 1], psnr[0]);
+        assert_eq!(report.frames[32..], clean[32..]);
+    }
+
+    #[test]
+    fn test_vcf_roundtrip() {
+        let (width, height) = (56, 40); // not macroblock aligned
+        let frames: Vec<VideoFrame> = (0..10).map(|t| moving_frame(width, height, t, 2)).collect();
+
+        let codec = VcfCodec::new().with_gop_size(4);
+        let vcf_data = codec.encode_frames(frames.iter().cloned().map(Ok), 25.0, 85).unwrap();
+
+        let (header, _) = codec.parse_container(&vcf_data).unwrap();
+        assert_eq!(header.frame_count, 10);
+        assert_eq!((header.width, header.height), (width, height));
+        let types: Vec<FrameType> = header.frames.iter().map(|entry| entry.frame_type).collect();
+        assert_eq!(types[0], FrameType::I);
+        assert_eq!(types[4], FrameType::I);
+        assert_eq!(types[5], FrameType::P);
+
+        // Motion compensation should make P-frames much cheaper than I-frames
+        assert!(header.frames[5].size * 2 < header.frames[4].size);
+
+        let decoded: Vec<VideoFrame> = codec.frames(&vcf_data).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(decoded.len(), frames.len());
+        for (index, (original, decoded)) in frames.iter().zip(&decoded).enumerate() {
+            let quality = FrameQuality::compare(index, types[index], original, decoded).unwrap();
+            assert!(quality.psnr > 30.0, "frame {} psnr {:.2}", index, quality.psnr);
+        }
+
+        // y4m output re-reads to the same frames
+        let y4m = codec.decode(&vcf_data).unwrap();
+        let reread: Vec<VideoFrame> = Y4mReader::new(&y4m[..]).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(reread, decoded);
+    }
+
+    #[test]
+    fn test_quality_report_finds_noisy_frame() {
+        let noisy_index = 6;
+        let original = |t: u32| {
+            let mut frame = moving_frame(64, 48, t, 1);
+            if t == noisy_index {
+                add_noise(&mut frame, 42);
+            }
+            Ok(frame)
+        };
+
+        let codec = VcfCodec::new().with_gop_size(8);
+        let vcf_data = codec.encode_frames((0..12).map(original), 30.0, 75).unwrap();
+        let report = codec.quality_report((0..12).map(original), &vcf_data).unwrap();
+        assert_eq!(report.len(), 12);
+
+        let summary = QualitySummary::from_frames(&report, 3).unwrap();
+        assert_eq!(summary.worst_frames[0], noisy_index as usize);
+        assert_eq!(summary.min_psnr, report[noisy_index as usize].psnr);
+        assert!(summary.mean_psnr > 35.0 && summary.mean_psnr < 50.0, "mean psnr {:.2}", summary.mean_psnr);
+
+        // Frame count mismatches are reported rather than silently truncated
+        assert!(codec.quality_report((0..11).map(original), &vcf_data).is_err());
+        assert!(codec.quality_report((0..13).map(original), &vcf_data).is_err());
+    }
+
+    #[test]
+    fn test_filter_chain_recorded_in_header() {
+        let source: Vec<Result<VideoFrame>> = (0..10).map(|t| Ok(moving_frame(48, 32, t, 2))).collect();
+        let filters = FilterChain::new()
+            .with(FpsConverter::new(15.0))
+            .with(TemporalDenoise::new(0.5))
+            .with(Scale::new(24, 16, ScaleMethod::Bilinear));
+
+        let codec = VcfCodec::new();
+        let encoded = codec.encode_frames_filtered(source, 30.0, RateControl::ConstantQuality(90), filters).unwrap();
+        let (header, _) = codec.parse_container(&encoded).unwrap();
+        assert_eq!(header.filters, ["fps 15", "denoise strength=0.50", "scale 24x16 bilinear"]);
+        assert_eq!((header.width, header.height, header.fps), (24, 16, 15.0));
+        assert_eq!(header.frame_count, 5);
+
+        let decoded: Vec<VideoFrame> = codec.frames(&encoded).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(decoded.len(), 5);
+        assert_eq!((decoded[0].width, decoded[0].height), (24, 16));
+
+        // Unfiltered encodes don't mention filters at all
+        let plain = codec.encode_frames(vec![Ok(moving_frame(16, 16, 0, 1))], 30.0, 90).unwrap();
+        assert!(codec.parse_container(&plain).unwrap().0.filters.is_empty());
+        assert!(!String::from_utf8_lossy(&plain).contains("filters"));
+    }
+
+    /// PSNR of the luma samples of `original` and `decoded` inside `region` (x, y, width, height)
+    fn region_psnr(original: &VideoFrame, decoded: &VideoFrame, (x0, y0, width, height): (usize, usize, usize, usize)) -> f64 {
+        let samples = |frame: &VideoFrame| -> Vec<u8> {
+            (y0..y0 + height).flat_map(|y| (x0..x0 + width).map(move |x| (x, y))).map(|(x, y)| frame.y().get(x, y)).collect()
+        };
+        psnr_from_mse(mean_squared_error(&samples(original), &samples(decoded)))
+    }
+
+    #[test]
+    fn test_importance_map_shifts_quality_to_center() {
+        let (width, height) = (128, 96);
+        let frames: Vec<VideoFrame> = (0..3)
+            .map(|t| {
+                let mut frame = moving_frame(width, height, t, 1);
+                add_noise(&mut frame, 7);
+                frame
+            })
+            .collect();
+        let codec = VcfCodec::new().with_gop_size(2);
+        let map = ImportanceMap::center_weighted(width, height);
+
+        let mut plain = codec.encoder(25.0, 60);
+        let mut weighted = codec.encoder(25.0, 60);
+        let mut reconstructions = Vec::new();
+        for frame in &frames {
+            plain.push_frame(frame).unwrap();
+            weighted.push_frame_with_map(frame, &map).unwrap();
+            let reference = &weighted.references.frames()[0];
+            reconstructions.push(VcfCodec::crop_frame(reference, width, height));
+        }
+        let plain = plain.finish().unwrap();
+        let weighted = weighted.finish().unwrap();
+
+        // Offsets are centered on the map's mean, so the rate barely moves
+        let ratio = weighted.len() as f64 / plain.len() as f64;
+        assert!((0.85..1.15).contains(&ratio), "size ratio {:.3}", ratio);
+
+        let decoded: Vec<VideoFrame> = codec.frames(&weighted).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(decoded, reconstructions);
+        assert!(codec.parse_container(&weighted).unwrap().0.frames.iter().all(|entry| entry.qp_deltas));
+
+        let center = (48, 32, 32, 32);
+        let corner = (0, 0, 32, 32);
+        for (original, decoded) in frames.iter().zip(&decoded) {
+            let (center_psnr, corner_psnr) = (region_psnr(original, decoded, center), region_psnr(original, decoded, corner));
+            assert!(center_psnr > corner_psnr + 3.0, "center {:.2} dB, corner {:.2} dB", center_psnr, corner_psnr);
+        }
+    }
+
+    /// Static noisy background with a flat bar covering part of it on every third frame
+    fn occluded_frame(t: u32) -> VideoFrame {
+        let mut frame = VideoFrame::new(64, 64);
+        add_noise(&mut frame, 11);
+        if t % 3 == 1 {
+            for y in 16..48 {
+                for x in 8..56 {
+                    frame.planes[0].set(x, y, 40);
+                }
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn test_second_reference_recovers_occluded_background() {
+        let frames: Vec<VideoFrame> = (0..12).map(occluded_frame).collect();
+        let encode = |references| {
+            let codec = VcfCodec::new().with_gop_size(12).with_reference_count(references);
+            let vcf_data = codec.encode_frames(frames.iter().cloned().map(Ok), 25.0, 80).unwrap();
+            let report = codec.quality_report(frames.iter().cloned().map(Ok), &vcf_data).unwrap();
+            let psnr = QualitySummary::from_frames(&report, 1).unwrap().mean_psnr;
+            (vcf_data, psnr)
+        };
+
+        let (single, single_psnr) = encode(1);
+        let (double, double_psnr) = encode(2);
+        assert!((double_psnr - single_psnr).abs() < 0.5, "{:.2} dB vs {:.2} dB", double_psnr, single_psnr);
+        assert!(double.len() * 10 < single.len() * 8, "{} bytes with 2 refs, {} with 1", double.len(), single.len());
+
+        let (header, _) = VcfCodec::new().parse_container(&double).unwrap();
+        assert_eq!(header.reference_count, 2);
+        let references: Vec<u8> = header.frames.iter().take(4).map(|entry| entry.references).collect();
+        assert_eq!(references, [0, 0, 2, 2]);
+
+        // Single-reference files keep the original header layout
+        assert!(!String::from_utf8_lossy(&single).contains("reference"));
+    }
+
+    #[test]
+    fn test_multi_reference_decode_stays_in_sync() {
+        let (width, height) = (48, 40);
+        let codec = VcfCodec::new().with_gop_size(50).with_reference_count(3);
+        let mut encoder = codec.encoder(25.0, 70);
+        let mut reconstructions = Vec::new();
+        for t in 0..120 {
+            let mut frame = moving_frame(width, height, t, 1);
+            if t % 5 == 2 {
+                add_noise(&mut frame, t as u64);
+            }
+            encoder.push_frame(&frame).unwrap();
+            reconstructions.push(VcfCodec::crop_frame(&encoder.references.frames()[0], width, height));
+        }
+        let vcf_data = encoder.finish().unwrap();
+
+        let decoded: Vec<VideoFrame> = codec.frames(&vcf_data).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(decoded, reconstructions);
+
+        // I-frames flush the buffer, so the next frame has one reference again
+        let (header, _) = codec.parse_container(&vcf_data).unwrap();
+        assert_eq!(header.frames[50].frame_type, FrameType::I);
+        assert_eq!(header.frames[51].references, 0);
+        assert_eq!(header.frames[52].references, 2);
+        assert_eq!(header.frames[53].references, 3);
+    }
+
+    #[test]
+    fn test_constant_qp_recorded_per_frame() {
+        let frames: Vec<VideoFrame> = (0..6).map(|t| moving_frame(48, 32, t, 2)).collect();
+        let codec = VcfCodec::new().with_gop_size(3);
+        let encode = |rate_control| {
+            let mut encoder = codec.encoder_with_rate_control(25.0, rate_control);
+            for frame in &frames {
+                encoder.push_frame(frame).unwrap();
+            }
+            encoder.finish().unwrap()
+        };
+
+        let fine = encode(RateControl::ConstantQp(20));
+        let coarse = encode(RateControl::ConstantQp(40));
+        assert!(coarse.len() < fine.len());
+
+        let (header, _) = codec.parse_container(&fine).unwrap();
+        assert_eq!(header.rate_control(), RateControl::ConstantQp(20));
+        assert!(header.frames.iter().all(|entry| entry.qp == Some(20)));
+        assert_eq!(header.qp_histogram, BTreeMap::from([(20, 6)]));
+        let decoded: Vec<VideoFrame> = codec.frames(&fine).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(decoded.len(), 6);
+
+        // Constant quality maps to the QP with the nearest step sizes
+        let (header, _) = codec.parse_container(&encode(RateControl::ConstantQuality(85))).unwrap();
+        assert_eq!(header.rate_control(), RateControl::ConstantQuality(85));
+        assert!(header.frames.iter().all(|entry| entry.qp == Some(VcfCodec::qp_for_quality(85))));
+        assert_eq!(VcfCodec::qp_for_quality(50), 36);
+        assert_eq!(VcfCodec::qp_for_quality(100), 0);
+        assert!(VcfCodec::qp_for_quality(10) > VcfCodec::qp_for_quality(85));
+
+        let mut table = Vec::new();
+        header.write_frame_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        let rows: Vec<&str> = table.lines().skip(1).collect();
+        assert_eq!(rows.len(), 6);
+        let keyframes: Vec<usize> = rows.iter().enumerate()
+            .filter(|(_, row)| row.contains("I*"))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(keyframes, [0, 3]);
+        assert!(rows[4].split_whitespace().eq(["4", "P", &header.frames[4].size.to_string(), "26", "0.160", "25.00"]));
+    }
+
+    #[test]
+    fn test_variable_frame_rate_timestamps() {
+        let pts = [0u64, 33, 50, 200, 210, 1000];
+        let codec = VcfCodec::new().with_gop_size(3);
+        let mut encoder = codec.encoder(30.0, 80).with_timebase(Timebase { num: 1, den: 1000 });
+        for (t, &pts) in pts.iter().enumerate() {
+            encoder.push_frame_at(&moving_frame(32, 32, t as u32, 3), pts).unwrap();
+        }
+        assert!(encoder.push_frame_at(&moving_frame(32, 32, 0, 3), 1000).is_err());
+        assert!(encoder.push_frame_at(&moving_frame(32, 32, 0, 3), 999).is_err());
+        let vcf_data = encoder.finish().unwrap();
+
+        let (header, _) = codec.parse_container(&vcf_data).unwrap();
+        assert_eq!(header.frame_count, 6);
+        assert!((header.duration - 1.033).abs() < 1e-9, "{}", header.duration);
+        let timed: Vec<(u64, VideoFrame)> = codec.frames(&vcf_data).unwrap().timed().collect::<Result<_>>().unwrap();
+        assert!(timed.iter().map(|(pts, _)| *pts).eq(pts));
+
+        // Times between samples show the frame presented last
+        for (seconds, expected) in [(0.0, 0), (0.1, 2), (0.2, 3), (0.205, 3), (0.5, 4), (5.0, 5)] {
+            assert_eq!(header.frame_at_time(seconds), Some(expected), "{}s", seconds);
+            let (index, frame) = codec.seek_to_time(&vcf_data, seconds).unwrap();
+            assert_eq!((index, &frame), (expected, &timed[expected].1), "{}s", seconds);
+        }
+        assert!(codec.seek_to_time(&vcf_data, f64::NAN).is_err());
+
+        // Y4M output holds each frame for its real duration at the nominal rate
+        let y4m = codec.decode(&vcf_data).unwrap();
+        let reread: Vec<VideoFrame> = Y4mReader::new(&y4m[..]).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(reread.len(), 31);
+        for (n, frame) in reread.iter().enumerate() {
+            assert_eq!(frame, &timed[header.frame_at_time(n as f64 / 30.0).unwrap()].1, "output frame {}", n);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_frames_are_rejected() {
+        let codec = VcfCodec::new();
+        let vcf_data = codec.encode_frames((0..3).map(|t| Ok(moving_frame(32, 32, t, 1))), 30.0, 85).unwrap();
+
+        let mut truncated = vcf_data.clone();
+        truncated.truncate(vcf_data.len() - 4);
+        let results: Vec<Result<VideoFrame>> = codec.frames(&truncated).unwrap().collect();
+        assert!(results.last().unwrap().is_err());
+
+        assert!(codec.encode_frames(Vec::new(), 30.0, 85).is_err());
+    }
+
+    #[test]
+    fn test_keyframe_extracts_to_identical_icf() {
+        let (width, height) = (56, 40); // not macroblock aligned
+        let codec = VcfCodec::new().with_gop_size(4);
+        let vcf_data = codec.encode_frames((0..6).map(|t| Ok(moving_frame(width, height, t, 2))), 25.0, 85).unwrap();
+        let decoded: Vec<VideoFrame> = codec.frames(&vcf_data).unwrap().collect::<Result<_>>().unwrap();
+
+        let icf_data = codec.extract_icf(&vcf_data, 4).unwrap();
+        let icf = IcfCodec::new();
+        let (header, _) = icf.parse_container(&icf_data).unwrap();
+        assert_eq!((header.width, header.height), (width, height));
+        assert_eq!(header.chroma_subsampling, ChromaSubsampling::S420);
+
+        let frame = &decoded[4];
+        let channels = icf.decode_channels(&icf_data, crate::codecs::image::ChannelMask::ALL).unwrap();
+        for (index, channel) in channels.iter().enumerate() {
+            let channel = channel.as_ref().unwrap();
+            let shift = (index > 0) as u32;
+            for (x, y, pixel) in channel.enumerate_pixels() {
+                let sample = frame.planes[index].get((x >> shift) as usize, (y >> shift) as usize);
+                assert_eq!(pixel.0[0], sample, "channel {} at ({}, {})", index, x, y);
+            }
+        }
+        // RGB goes through the same conversion, short of rounding the planes first
+        let rgb = icf.decode(&icf_data).unwrap().to_rgb8();
+        let expected = frame.to_rgb();
+        assert!(rgb.as_raw().iter().zip(expected.as_raw()).all(|(&a, &b)| a.abs_diff(b) <= 2));
+    }
+
+    #[test]
+    fn test_predicted_frame_extracts_by_reencoding() {
+        let codec = VcfCodec::new().with_gop_size(4);
+        let vcf_data = codec.encode_frames((0..3).map(|t| Ok(moving_frame(48, 32, t, 2))), 25.0, 85).unwrap();
+        let decoded = codec.frames(&vcf_data).unwrap().nth(2).unwrap().unwrap();
+
+        let icf_data = codec.extract_icf(&vcf_data, 2).unwrap();
+        let image = IcfCodec::new().decode(&icf_data).unwrap().to_rgb8();
+        let expected = decoded.to_rgb();
+        let mse = image.as_raw().iter().zip(expected.as_raw())
+            .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+            .sum::<f64>() / image.as_raw().len() as f64;
+        assert!(psnr_from_mse(mse) > 30.0, "psnr {:.2}", psnr_from_mse(mse));
+
+        assert!(codec.extract_icf(&vcf_data, 3).is_err());
+    }
+}
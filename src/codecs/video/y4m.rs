@@ -0,0 +1,194 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+use crate::codecs::video::frame::VideoFrame;
+
+const STREAM_MAGIC: &str = "YUV4MPEG2";
+const FRAME_MAGIC: &str = "FRAME";
+
+/// Streaming reader for YUV4MPEG2 (.y4m) files with 4:2:0 8-bit content
+///
+/// Frames are yielded one at a time, so memory use is independent of clip length.
+pub struct Y4mReader<R: BufRead> {
+    reader: R,
+    width: u32,
+    height: u32,
+    fps_num: u32,
+    fps_den: u32,
+}
+
+impl Y4mReader<BufReader<File>> {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    /// Parse the stream header
+    pub fn new(mut reader: R) -> Result<Self> {
+        let line = read_header_line(&mut reader)?
+            .ok_or_else(|| anyhow!("Empty Y4M stream"))?;
+        let mut tokens = line.split(' ');
+        if tokens.next() != Some(STREAM_MAGIC) {
+            bail!("Invalid Y4M magic");
+        }
+
+        let (mut width, mut height) = (None, None);
+        let (mut fps_num, mut fps_den) = (30, 1);
+        for token in tokens.filter(|token| !token.is_empty()) {
+            let (tag, value) = token.split_at(1);
+            match tag {
+                "W" => width = Some(value.parse::<u32>().context("Invalid Y4M width")?),
+                "H" => height = Some(value.parse::<u32>().context("Invalid Y4M height")?),
+                "F" => {
+                    let (num, den) = value.split_once(':').ok_or_else(|| anyhow!("Invalid Y4M frame rate"))?;
+                    fps_num = num.parse().context("Invalid Y4M frame rate")?;
+                    fps_den = den.parse().context("Invalid Y4M frame rate")?;
+                }
+                "C" if !value.starts_with("420") || value.contains("p1") => {
+                    bail!("Unsupported Y4M colorspace C{} (only 8-bit 4:2:0 is supported)", value);
+                }
+                // Interlacing, aspect ratio and extensions don't affect sample layout
+                _ => {}
+            }
+        }
+
+        let width = width.ok_or_else(|| anyhow!("Y4M header missing width"))?;
+        let height = height.ok_or_else(|| anyhow!("Y4M header missing height"))?;
+        if width == 0 || height == 0 || fps_num == 0 || fps_den == 0 {
+            bail!("Invalid Y4M dimensions or frame rate");
+        }
+
+        Ok(Self { reader, width, height, fps_num, fps_den })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps_num as f64 / self.fps_den as f64
+    }
+
+    /// Read the next frame, or `None` at end of stream
+    pub fn read_frame(&mut self) -> Result<Option<VideoFrame>> {
+        let line = match read_header_line(&mut self.reader)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if line.split(' ').next() != Some(FRAME_MAGIC) {
+            bail!("Invalid Y4M frame header");
+        }
+
+        let mut frame = VideoFrame::new(self.width, self.height);
+        for plane in frame.planes.iter_mut() {
+            self.reader.read_exact(&mut plane.data).context("Truncated Y4M frame")?;
+        }
+        Ok(Some(frame))
+    }
+}
+
+impl<R: BufRead> Iterator for Y4mReader<R> {
+    type Item = Result<VideoFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Writer for YUV4MPEG2 streams
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Write the stream header
+    pub fn new(mut writer: W, width: u32, height: u32, fps: f64) -> Result<Self> {
+        let (fps_num, fps_den) = fps_to_rational(fps);
+        writeln!(writer, "{} W{} H{} F{}:{} Ip A1:1 C420jpeg", STREAM_MAGIC, width, height, fps_num, fps_den)?;
+        Ok(Self { writer, width, height })
+    }
+
+    pub fn write_frame(&mut self, frame: &VideoFrame) -> Result<()> {
+        if frame.width != self.width || frame.height != self.height {
+            bail!("Frame is {}x{}, stream is {}x{}", frame.width, frame.height, self.width, self.height);
+        }
+        writeln!(self.writer, "{}", FRAME_MAGIC)?;
+        for plane in &frame.planes {
+            self.writer.write_all(&plane.data)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Express a frame rate as the integer ratio Y4M headers use
+pub(crate) fn fps_to_rational(fps: f64) -> (u32, u32) {
+    if (fps - fps.round()).abs() < 1e-6 {
+        (fps.round() as u32, 1)
+    } else if (fps * 1.001 - (fps * 1.001).round()).abs() < 1e-3 {
+        // NTSC-style rates such as 29.97 (30000:1001)
+        ((fps * 1.001).round() as u32 * 1000, 1001)
+    } else {
+        ((fps * 1000.0).round() as u32, 1000)
+    }
+}
+
+/// Read one newline-terminated header line; `None` at a clean end of stream
+fn read_header_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        bail!("Truncated Y4M header");
+    }
+    Ok(Some(String::from_utf8(line).context("Y4M header is not valid UTF-8")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_y4m_roundtrip() {
+        let mut frames = vec![VideoFrame::new(5, 3), VideoFrame::new(5, 3)];
+        frames[1].planes[0].data.iter_mut().enumerate().for_each(|(i, v)| *v = i as u8);
+        frames[1].planes[2].data[5] = 7;
+
+        let mut writer = Y4mWriter::new(Vec::new(), 5, 3, 30000.0 / 1001.0).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        let bytes = writer.into_inner();
+        assert!(bytes.starts_with(b"YUV4MPEG2 W5 H3 F30000:1001 "));
+
+        let reader = Y4mReader::new(&bytes[..]).unwrap();
+        assert_eq!((reader.width(), reader.height()), (5, 3));
+        assert!((reader.fps() - 29.97).abs() < 0.01);
+        let read: Vec<VideoFrame> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(read, frames);
+        assert_eq!(read[0].u().width, 3);
+    }
+
+    #[test]
+    fn test_y4m_rejects_unsupported_and_truncated_input() {
+        assert!(Y4mReader::new(&b"YUV4MPEG2 W4 H4 F25:1 C444\n"[..]).is_err());
+        assert!(Y4mReader::new(&b"YUV4MPEG2 H4 F25:1\n"[..]).is_err());
+
+        let truncated = b"YUV4MPEG2 W4 H4 F25:1\nFRAME\n\x10\x10";
+        let mut reader = Y4mReader::new(&truncated[..]).unwrap();
+        assert!(reader.read_frame().is_err());
+    }
+}
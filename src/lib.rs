@@ -1,3 +1,4 @@
 pub mod codecs;
+pub mod cdn;
 
 pub use codecs::*;
\ No newline at end of file
@@ -0,0 +1,108 @@
+//! Walking a bencode document must allocate for its depth, not its size.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use codec_cdn_rust::codecs::bencode::{BencodeCodec, BencodeStats, BencodeWriter, WalkLimits};
+
+/// Counts allocations and allocated bytes while tracking is enabled
+struct CountingAllocator;
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+    static COUNT: Cell<usize> = const { Cell::new(0) };
+    static BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record(size: usize) {
+    // try_with: the thread-locals may already be gone during thread teardown
+    let _ = TRACKING.try_with(|tracking| {
+        if tracking.get() {
+            let _ = COUNT.try_with(|count| count.set(count.get() + 1));
+            let _ = BYTES.try_with(|bytes| bytes.set(bytes.get() + size));
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations `f` makes on this thread and their total size
+fn allocations(f: impl FnOnce()) -> (usize, usize) {
+    COUNT.with(|count| count.set(0));
+    BYTES.with(|bytes| bytes.set(0));
+    TRACKING.with(|tracking| tracking.set(true));
+    f();
+    TRACKING.with(|tracking| tracking.set(false));
+    (COUNT.with(Cell::get), BYTES.with(Cell::get))
+}
+
+/// `records` dictionaries, each holding a list of `depth` nested lists
+fn synthetic_document(records: usize, depth: usize) -> Vec<u8> {
+    let mut writer = BencodeWriter::new(Vec::new());
+    writer.begin_list().unwrap();
+    for record in 0..records {
+        let name = format!("record-{}", record);
+        writer.begin_dictionary().unwrap();
+        writer.write_bytes_from(2, &b"id"[..]).unwrap();
+        writer.write_bytes_from(name.len() as u64, name.as_bytes()).unwrap();
+        writer.write_bytes_from(4, &b"path"[..]).unwrap();
+        for _ in 0..depth {
+            writer.begin_list().unwrap();
+        }
+        writer.write_bytes_from(256, &[record as u8; 256][..]).unwrap();
+        for _ in 0..depth {
+            writer.end().unwrap();
+        }
+        writer.end().unwrap();
+    }
+    writer.end().unwrap();
+    writer.finish().unwrap()
+}
+
+#[test]
+fn test_walk_allocates_for_depth_not_size() {
+    let walk = |document: &[u8]| {
+        let mut stats = BencodeStats::default();
+        let counts = allocations(|| BencodeCodec::walk(document, &mut stats, &WalkLimits::default()).unwrap());
+        (stats, counts)
+    };
+
+    let small = synthetic_document(10, 8);
+    let large = synthetic_document(20_000, 8);
+    assert!(large.len() > 5_000_000, "document too small to prove anything");
+
+    let (small_stats, small_counts) = walk(&small);
+    let (large_stats, large_counts) = walk(&large);
+    assert_eq!(large_stats.dictionaries, 20_000);
+    assert_eq!(large_stats.lists, 1 + 20_000 * 8);
+    assert_eq!(large_stats.strings, 20_000 * 2);
+    assert_eq!(large_stats.max_depth, 10);
+    assert_eq!(small_stats.max_depth, large_stats.max_depth);
+
+    // Same depth, 2000 times the nodes: the same allocations
+    assert_eq!(large_counts, small_counts);
+    assert!(large_counts.1 < 16 * 1024, "walk allocated {} bytes", large_counts.1);
+
+    // Deeper nesting costs more stack, but still nowhere near the size
+    let (_, deep_counts) = walk(&synthetic_document(10, 200));
+    assert!(deep_counts.1 < 32 * 1024, "walk allocated {} bytes", deep_counts.1);
+}
@@ -0,0 +1,90 @@
+//! `POST /upload` end to end: encode on upload, then read back through the
+//! routes that serve and transcode stored objects.
+
+use std::sync::Arc;
+
+use codec_cdn_rust::cdn::{routes, CachePolicy, ObjectStore, UploadPolicy, UploadReceipt};
+use codec_cdn_rust::codecs::image::IcfCodec;
+use codec_cdn_rust::codecs::video::WeightedPsnr;
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+use tempfile::TempDir;
+use warp::http::StatusCode;
+
+fn test_image() -> RgbImage {
+    ImageBuffer::from_fn(96, 64, |x, y| Rgb([(x * 2) as u8, (y * 3) as u8, ((x + y) * 2) as u8]))
+}
+
+fn png(img: &RgbImage) -> Vec<u8> {
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(img.clone()).write_to(&mut bytes, image::ImageOutputFormat::Png).unwrap();
+    bytes.into_inner()
+}
+
+#[tokio::test]
+async fn test_uploads_are_encoded_and_read_back() {
+    let temp_dir = TempDir::new().unwrap();
+    let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+    let api = routes(store.clone(), CachePolicy::default(), UploadPolicy::default());
+    let upload = |content_type: &str, body: Vec<u8>| {
+        warp::test::request().method("POST").path("/upload").header("content-type", content_type).body(body).reply(&api)
+    };
+
+    // Text goes to TCF and comes back decoded to a client that doesn't accept tcf
+    let text: String = (0..500).map(|i| format!("line {} of the upload test, value {}\n", i, i * 7 % 13)).collect();
+    let response = upload("text/plain; charset=utf-8", text.clone().into_bytes()).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let json: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    for field in ["content_type", "format", "original", "encoded", "ratio"] {
+        assert!(json.get(field).is_some(), "missing {}", field);
+    }
+    let receipt: UploadReceipt = serde_json::from_value(json).unwrap();
+    assert_eq!((receipt.format.as_str(), receipt.content_type.as_str()), ("tcf", "text/plain; charset=utf-8"));
+    assert_eq!(receipt.original.size, text.len() as u64);
+    assert!(receipt.ratio > 1.0);
+    assert_eq!(receipt.ratio, receipt.original.size as f64 / receipt.encoded.size as f64);
+    assert_eq!(store.get(&receipt.original.id).unwrap().unwrap(), text.as_bytes());
+    assert_eq!(store.info(&receipt.encoded.id).unwrap().unwrap().format.as_deref(), Some("tcf"));
+    let response = warp::test::request().path(&format!("/o/{}", receipt.encoded.id)).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), text.as_bytes());
+
+    // Images go to ICF; variants of the encoded copy stay close to the source
+    let img = test_image();
+    let response = upload("image/png", png(&img)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let receipt: UploadReceipt = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(receipt.format, "icf");
+    assert_eq!(store.info(&receipt.encoded.id).unwrap().unwrap().format.as_deref(), Some("icf"));
+    let response = warp::test::request().path(&format!("/o/{}/variant?q=90", receipt.encoded.id)).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let decoded = IcfCodec::new().decode(response.body()).unwrap().to_rgb8();
+    assert_eq!(decoded.dimensions(), img.dimensions());
+    let psnr = WeightedPsnr::default().compare(&img, &decoded).unwrap().psnr;
+    assert!(psnr > 30.0, "PSNR {:.2}", psnr);
+}
+
+#[tokio::test]
+async fn test_refused_uploads_carry_error_codes() {
+    let temp_dir = TempDir::new().unwrap();
+    let store = Arc::new(ObjectStore::open(temp_dir.path()).unwrap());
+    let policy = UploadPolicy { max_text_size: 100, max_image_size: 1000, ..Default::default() };
+    let api = routes(store.clone(), CachePolicy::default(), policy);
+    let upload = |content_type: &str, body: Vec<u8>| {
+        warp::test::request().method("POST").path("/upload").header("content-type", content_type).body(body).reply(&api)
+    };
+
+    for (content_type, body, status, code) in [
+        ("text/plain", vec![b'a'; 101], StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+        ("image/png", vec![0; 1001], StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+        ("text/plain", vec![0xFF, 0xFE], StatusCode::UNPROCESSABLE_ENTITY, "undecodable_payload"),
+        ("image/png", b"not a png".to_vec(), StatusCode::UNPROCESSABLE_ENTITY, "undecodable_payload"),
+        ("application/octet-stream", vec![1, 2, 3], StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type"),
+    ] {
+        let response = upload(content_type, body).await;
+        assert_eq!(response.status(), status, "{}", content_type);
+        let json: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(json["code"], code);
+        assert!(json["error"].is_string());
+    }
+    assert_eq!(store.stats().unwrap().bases.objects, 0);
+}
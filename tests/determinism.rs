@@ -0,0 +1,226 @@
+//! Encoder output must be bit-identical on every platform and every run,
+//! since files are content addressed.
+//!
+//! Each case encodes a fixed corpus and compares the SHA-256 of the result
+//! with the digest recorded here. The corpora are built with integer
+//! arithmetic only, so they are identical everywhere too. A change that
+//! alters a format on purpose must update the digests in the same commit;
+//! anything else that moves them is a determinism bug.
+
+use codec_cdn_rust::codecs::bencode::{BencodeCodec, BencodeDict, BencodeValue};
+use codec_cdn_rust::codecs::image::{
+    ChromaSubsampling, DecodeOptions, DecodeStrictness, IcfCodec, IcfColorSpace, IcfEncodeOptions, QuantTableKind,
+    SubsamplingMode, PROFILES,
+};
+use codec_cdn_rust::codecs::text::{ChunkStrategy, SimpleTcfCodec, TcfCodec, TcfEncodeOptions, TcfMethod, TOKENIZER_IDS};
+use codec_cdn_rust::codecs::video::{RateControl, VcfCodec, VideoFrame};
+use codec_cdn_rust::codecs::FilterChain;
+use image::{DynamicImage, Rgb, RgbImage};
+use sha2::{Digest, Sha256};
+
+fn xorshift(seed: u32) -> impl FnMut() -> u32 {
+    let mut state = seed;
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
+
+/// Compare each case's digest with `expected`, reporting every mismatch
+/// in the form `expected` is written in
+fn check_digests(expected: &[(&str, &str)], cases: Vec<(String, Vec<u8>)>) {
+    let actual: Vec<(String, String)> = cases.into_iter()
+        .map(|(label, data)| {
+            let digest = Sha256::digest(&data).iter().map(|byte| format!("{:02x}", byte)).collect();
+            (label, digest)
+        })
+        .collect();
+    let labels: Vec<&str> = actual.iter().map(|(label, _)| label.as_str()).collect();
+    let recorded: Vec<&str> = expected.iter().map(|(label, _)| *label).collect();
+    let matches = labels == recorded && actual.iter().zip(expected).all(|((_, digest), (_, expected))| digest == expected);
+    if !matches {
+        let listing: String = actual.iter().map(|(label, digest)| format!("    (\"{}\", \"{}\"),\n", label, digest)).collect();
+        panic!("encoder output changed; if the format change is intended, record:\n{}", listing);
+    }
+}
+
+/// Photo-like test image: gradients, hard edges and noise, with sizes that
+/// leave partial blocks and macroblocks at the edges
+fn test_image() -> DynamicImage {
+    let mut next = xorshift(0x1234_5678);
+    DynamicImage::ImageRgb8(RgbImage::from_fn(100, 75, |x, y| {
+        let noise = (next() % 17) as i32 - 8;
+        let disc = (x as i32 - 60).pow(2) + (y as i32 - 35).pow(2) < 400;
+        let level = |base: i32| (base + noise).clamp(0, 255) as u8;
+        if disc {
+            Rgb([level(220), level(180 - y as i32), level(40)])
+        } else {
+            Rgb([level(2 * x as i32), level(40 + 2 * y as i32), level(((x ^ y) & 0x3F) as i32 + 100)])
+        }
+    }))
+}
+
+/// Log lines and JSON records, so every tokenizer has structure to find
+fn test_text() -> String {
+    let mut next = xorshift(0x9e37_79b9);
+    let words = ["alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel"];
+    let mut text = String::new();
+    for line in 0..400 {
+        let word = words[next() as usize % words.len()];
+        if line % 3 == 0 {
+            text.push_str(&format!("{{\"id\":{},\"name\":\"{}\",\"score\":{},\"tags\":[\"{}\"]}}\n", line, word, next() % 1000, words[line % words.len()]));
+        } else {
+            text.push_str(&format!("2024-01-{:02} INFO {} request took {}ms (ünïcödé {})\n", line % 28 + 1, word, next() % 500, line));
+        }
+    }
+    text
+}
+
+/// A textured pattern moving two pixels right and one down per frame
+fn test_frames() -> Vec<VideoFrame> {
+    (0..6u32)
+        .map(|t| {
+            let mut frame = VideoFrame::new(52, 36);
+            for (index, plane) in frame.planes.iter_mut().enumerate() {
+                let shift = if index == 0 { 1 } else { 2 };
+                for y in 0..plane.height {
+                    for x in 0..plane.width {
+                        let (u, v) = ((x * shift) as u32 + 2 * t, (y * shift) as u32 + t);
+                        let value = ((u * 7) ^ (v * 5)) & 0x7F;
+                        plane.set(x, y, (value + if index == 0 { 60 } else { 100 }) as u8);
+                    }
+                }
+            }
+            frame
+        })
+        .collect()
+}
+
+#[test]
+fn test_icf_output_digests() {
+    let img = test_image();
+    let mut cases: Vec<(String, IcfEncodeOptions)> = vec![
+        ("default".to_string(), IcfEncodeOptions::default()),
+        ("quality-20-420".to_string(), IcfEncodeOptions {
+            chroma_subsampling: SubsamplingMode::Fixed(ChromaSubsampling::S420),
+            ..IcfEncodeOptions::with_quality(20)
+        }),
+        ("quality-95".to_string(), IcfEncodeOptions::with_quality(95)),
+        ("ycbcr".to_string(), IcfEncodeOptions { color_space: IcfColorSpace::YCbCr, ..Default::default() }),
+        ("perceptual".to_string(), IcfEncodeOptions { quantization: QuantTableKind::Perceptual, ..Default::default() }),
+        ("optimized".to_string(), IcfEncodeOptions { quantization: QuantTableKind::Optimized, ..Default::default() }),
+        ("tiled".to_string(), IcfEncodeOptions { tile_size: Some(32), ..Default::default() }),
+        ("sign-contexts".to_string(), IcfEncodeOptions { sign_contexts: true, ..Default::default() }),
+    ];
+    cases.extend(PROFILES.iter().map(|profile| (format!("profile-{}", profile.name), IcfEncodeOptions::profile(profile))));
+
+    let codec = IcfCodec::new();
+    let encoded: Vec<(String, Vec<u8>)> = cases.into_iter()
+        .map(|(label, options)| (format!("icf/{}", label), codec.encode_with_options(&img, &options).unwrap()))
+        .collect();
+    // A strict decode fails on anything the encoder writes that the format
+    // doesn't allow, which the digests alone would record as intended
+    let strict = DecodeOptions { strictness: DecodeStrictness::Strict, ..Default::default() };
+    for (label, data) in &encoded {
+        if let Err(error) = codec.decode_checked(data, &strict) {
+            panic!("{} fails a strict decode: {:#}", label, error);
+        }
+    }
+    check_digests(&[
+        ("icf/default", "d3d524df3825798fb8a0c1b18b8e3c40649c4065bc0816eac7f32934aedcfee6"),
+        ("icf/quality-20-420", "1586afbf995a9b9e22767a7417423e716a4a2948768c51cbe6e5b36d3127b71c"),
+        ("icf/quality-95", "2500e819f2398c82ec096938c7ec2bf57bec4b32281dcec8a79588f937d9ee27"),
+        ("icf/ycbcr", "ccd87e81ed05fb8756684aa1dafaa1e913d65ddf14cb06a9eb708df16b23aac3"),
+        ("icf/perceptual", "76668b1b3f4bbca524a1ef82cf8f59deb51e2dc5cb461b3c723a87b7fb567b7c"),
+        ("icf/optimized", "9bbb7a8bfecaccc7afcf4f269bcee1d37ad47562ec5ba7435dcb47b642405061"),
+        ("icf/tiled", "7129175308566ea60ac80fc9d4e7d614ccb5b24ee0c80788806ac38e988df53f"),
+        ("icf/sign-contexts", "8029f3de0a5f9d8d4e3af1d62da281ec14ca0558ed42b7b2a3365ab7a0f89bab"),
+        ("icf/profile-photo", "256a884d030a4672240fba247a97513db47502da0154621ad1358807156342cf"),
+        ("icf/profile-screenshot", "c27a39c0d7ae06d7ee7a145bcd56624438a9abca11b4f08b2d4644f37d69e557"),
+        ("icf/profile-archival", "d836456f4855e00bb84403dac68768aeb8882c28a9be5b5778f6366cb1129f4d"),
+        ("icf/profile-thumbnail", "98ebaec189cbd5277021cd8435bae870d4bd93c4792043b6e348de669681960a"),
+    ], encoded);
+}
+
+#[test]
+fn test_tcf_output_digests() {
+    let text = test_text();
+    let mut cases = Vec::new();
+    for tokenizer_id in TOKENIZER_IDS {
+        let options = TcfEncodeOptions { tokenizer_id: tokenizer_id.to_string(), ..Default::default() };
+        cases.push((format!("tcf/arithmetic-{}", tokenizer_id), options));
+    }
+    for method in [TcfMethod::Gzip, TcfMethod::Stored] {
+        cases.push((format!("tcf/{}", method.as_str()), TcfEncodeOptions { method: Some(method), ..Default::default() }));
+    }
+    // Automatic selection would consider zstd, whose output belongs to the
+    // libzstd it links; its digests are only recorded for default features
+    if !cfg!(feature = "zstd") {
+        cases.push(("tcf/auto".to_string(), TcfEncodeOptions { method: None, ..Default::default() }));
+        cases.push((
+            "tcf/auto-chunked".to_string(),
+            TcfEncodeOptions { method: None, chunking: Some(ChunkStrategy::OnDelimiter(b'\n', 4096)), ..Default::default() },
+        ));
+    }
+    let mut encoded: Vec<(String, Vec<u8>)> = cases.into_iter()
+        .map(|(label, options)| (label, TcfCodec::encode_with_options(&text, &options).unwrap().data))
+        .collect();
+
+    let mut sorted: Vec<&str> = text.lines().collect();
+    sorted.sort_unstable();
+    let sorted = sorted.join("\n");
+    let options = TcfEncodeOptions { method: Some(TcfMethod::FrontCoding), ..Default::default() };
+    encoded.push(("tcf/front-coding".to_string(), TcfCodec::encode_with_options(&sorted, &options).unwrap().data));
+    encoded.push(("tcf/simple".to_string(), SimpleTcfCodec::encode(&text).unwrap()));
+    check_digests(&[
+        ("tcf/arithmetic-byte", "50f323188140046bb61720447fca5281e3bab6c611ca56ad5d4a96e8394de4f9"),
+        ("tcf/arithmetic-word", "22ee0fe9a41635f4a79c404ab2fdf7b075fa57211bbe835450ff0e1ae7c0391e"),
+        ("tcf/arithmetic-json", "0448b229f6f4e0b94eb32c12011280d18b5f8d9b0c388d7ffc393d8eceb7ee6f"),
+        ("tcf/gzip", "aa31c789818bf4478db4134348e00d62a8708adcdb79ffd45201aff4fb182ef6"),
+        ("tcf/stored", "d767cc31a8e43dfe29cf1075345d07c540c32ec4a466282944f16e567cb8c2fc"),
+        ("tcf/auto", "aa31c789818bf4478db4134348e00d62a8708adcdb79ffd45201aff4fb182ef6"),
+        ("tcf/auto-chunked", "ca7073ad48a5f433ff5d7cd1e8ac070ef07bdde707013a872a2bcbb2df05d74c"),
+        ("tcf/front-coding", "0b0be20f290758e839f0df580c0bdd21aa34c11426eb33fea209cd9815a959c5"),
+        ("tcf/simple", "ce7ddff7160a8e84ec146ddf61c817530aec5c4d40f7da2e7a7d1d3dfa1f1e19"),
+    ], encoded);
+}
+
+#[test]
+fn test_vcf_output_digests() {
+    let frames = test_frames();
+    let encode = |codec: VcfCodec, rate_control| {
+        codec.encode_frames_filtered(frames.iter().cloned().map(Ok), 25.0, rate_control, FilterChain::new()).unwrap()
+    };
+    check_digests(&[
+        ("vcf/quality-60", "a7a6cf956b7e8cd97fdd6895c643794512af21f17c63c0de9f25b16925cfcdc1"),
+        ("vcf/qp-44-gop-4", "4986e3f499a8914b8d4443ddbe6c98387754722f26239a9437f0768390990df9"),
+        ("vcf/two-references", "612cbc0a15baecf80b80551b12173c9de165955e91a057c8a928d2060bd92599"),
+    ], vec![
+        ("vcf/quality-60".to_string(), encode(VcfCodec::new(), RateControl::ConstantQuality(60))),
+        ("vcf/qp-44-gop-4".to_string(), encode(VcfCodec::new().with_gop_size(4), RateControl::ConstantQp(44))),
+        ("vcf/two-references".to_string(), encode(VcfCodec::new().with_reference_count(2), RateControl::ConstantQuality(80))),
+    ]);
+}
+
+#[test]
+fn test_bencode_output_digests() {
+    let mut next = xorshift(0x2545_f491);
+    let pieces: Vec<u8> = (0..200).map(|_| next() as u8).collect();
+    let dictionary = |entries: Vec<(&str, BencodeValue)>| {
+        BencodeValue::dictionary(entries.into_iter().map(|(key, value)| (key.as_bytes().to_vec(), value)).collect::<BencodeDict>())
+    };
+    let value = dictionary(vec![
+        ("announce", BencodeValue::string("http://tracker.example/announce")),
+        ("info", dictionary(vec![
+            ("name", BencodeValue::string("corpus.bin")),
+            ("length", BencodeValue::integer(123_456_789)),
+            ("pieces", BencodeValue::byte_string(pieces)),
+        ])),
+        ("url-list", BencodeValue::list(vec![BencodeValue::string("a"), BencodeValue::string("b")])),
+    ]);
+    check_digests(&[
+        ("bencode/torrent", "7d1ab8b64d98a6cc6d9225219eb41e6a25ee7100239875cdfaf96abca0486ac7"),
+    ], vec![("bencode/torrent".to_string(), BencodeCodec::encode(&value).unwrap())]);
+}
@@ -0,0 +1,39 @@
+// Generated by `bencode-cli codegen` from 2 sample(s)
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Torrent {
+    pub announce: String,
+    #[serde(rename = "announce-list", default, skip_serializing_if = "Option::is_none")]
+    pub announce_list: Option<Vec<Vec<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(rename = "created by", default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    #[serde(rename = "creation date", default, skip_serializing_if = "Option::is_none")]
+    pub creation_date: Option<i64>,
+    pub info: Info,
+    #[serde(rename = "url-list", default, skip_serializing_if = "Option::is_none")]
+    pub url_list: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Info {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<File>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<i64>,
+    pub name: String,
+    #[serde(rename = "piece length")]
+    pub piece_length: i64,
+    pub pieces: serde_bytes::ByteBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct File {
+    pub length: i64,
+    pub path: Vec<String>,
+}
@@ -0,0 +1,209 @@
+//! Every ICF decode path must agree with a slow reference decoder.
+//!
+//! The reference below rebuilds pixels from the file's quantized
+//! coefficients with textbook formulas: f64 throughout, a direct 2D IDCT
+//! sum, nearest-neighbor chroma upsampling and the color transforms written
+//! out longhand. Random images are encoded with random options and each
+//! backend in `BACKENDS` must land within ±1 of the reference on every
+//! channel of every pixel. A new decode path is covered by adding it there.
+
+use std::f64::consts::PI;
+use std::io::Cursor;
+
+use anyhow::Result;
+use codec_cdn_rust::codecs::cli_common::RuntimeConfig;
+use codec_cdn_rust::codecs::image::{
+    ChromaSubsampling, DecodeOptions, IcfCodec, IcfColorSpace, IcfEncodeOptions, IcfReader, QuantTableKind,
+};
+use image::{DynamicImage, Rgb, RgbImage};
+
+const CASES: u64 = 300;
+/// Largest difference from the reference a backend may show
+const TOLERANCE: u8 = 1;
+
+type Backend = fn(&[u8]) -> Result<RgbImage>;
+
+/// Every decode path under test, by name
+const BACKENDS: &[(&str, Backend)] = &[
+    ("scalar", |data| Ok(IcfCodec::new().decode(data)?.to_rgb8())),
+    ("fixed-point", |data| {
+        let options = DecodeOptions { fixed_point: true, ..Default::default() };
+        Ok(IcfCodec::new().decode_checked(data, &options)?.image.to_rgb8())
+    }),
+    ("strided", decode_strided),
+    ("reader", |data| Ok(IcfReader::new(Cursor::new(data))?.decode()?.to_rgb8())),
+    ("pooled", |data| {
+        let runtime = RuntimeConfig::new(Some(4), None)?;
+        let codec = IcfCodec::new().with_runtime(runtime.clone());
+        Ok(runtime.install(|| codec.decode(data))?.to_rgb8())
+    }),
+    #[cfg(feature = "mmap")]
+    ("mmap", |data| {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), data)?;
+        Ok(IcfCodec::new().decode_mmap(file.path())?.to_rgb8())
+    }),
+];
+
+/// `decode_into` a buffer with padding between rows
+fn decode_strided(data: &[u8]) -> Result<RgbImage> {
+    let header = IcfCodec::new().parse_container(data)?.0;
+    let (width, height) = (header.width as usize, header.height as usize);
+    let stride = width * 3 + 5;
+    let mut out = vec![0xAA; stride * height];
+    IcfCodec::new().decode_into(data, &mut out, stride)?;
+    let rows = out.chunks(stride).flat_map(|row| row[..width * 3].to_vec()).collect();
+    Ok(RgbImage::from_raw(width as u32, height as u32, rows).unwrap())
+}
+
+/// The reference decode of `data`
+fn reference_decode(data: &[u8]) -> RgbImage {
+    let codec = IcfCodec::new();
+    let header = codec.parse_container(data).unwrap().0;
+    let coefficients = codec.coefficients(data).unwrap();
+    let planes: Vec<Vec<f64>> = coefficients.channels.iter().map(|plane| {
+        let mut samples = vec![0.0; plane.width() * plane.height()];
+        for block_y in 0..plane.blocks_y {
+            for block_x in 0..plane.blocks_x {
+                let block = plane.dequantized_block(block_x, block_y).unwrap();
+                for y in 0..8 {
+                    for x in 0..8 {
+                        samples[(block_y * 8 + y) * plane.width() + block_x * 8 + x] = idct_sample(&block, x, y);
+                    }
+                }
+            }
+        }
+        samples
+    }).collect();
+
+    let factor = if header.chroma_subsampling == ChromaSubsampling::S420 { 2 } else { 1 };
+    RgbImage::from_fn(header.width, header.height, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let sample = |channel: usize, x: usize, y: usize| planes[channel][y * coefficients.channels[channel].width() + x];
+        let luma = (sample(0, x, y) + 128.0) / 255.0;
+        let c1 = sample(1, x / factor, y / factor) / 255.0;
+        let c2 = sample(2, x / factor, y / factor) / 255.0;
+        let (r, g, b) = match header.color_space {
+            IcfColorSpace::YCoCg => (luma + c1 / 2.0 - c2 / 2.0, luma + c2 / 2.0, luma - c1 / 2.0 - c2 / 2.0),
+            IcfColorSpace::YCbCr => (luma + 1.402 * c2, luma - 0.344136 * c1 - 0.714136 * c2, luma + 1.772 * c1),
+        };
+        let to_u8 = |value: f64| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        Rgb([to_u8(r), to_u8(g), to_u8(b)])
+    })
+}
+
+/// Sample `(x, y)` of the inverse DCT of `block`, indexed `[v][u]`
+fn idct_sample(block: &[[f64; 8]; 8], x: usize, y: usize) -> f64 {
+    let scale = |k: usize| if k == 0 { (1.0f64 / 8.0).sqrt() } else { 0.5 };
+    let mut sum = 0.0;
+    for (v, row) in block.iter().enumerate() {
+        for (u, &coefficient) in row.iter().enumerate() {
+            sum += scale(u) * scale(v) * coefficient
+                * ((2 * x + 1) as f64 * u as f64 * PI / 16.0).cos()
+                * ((2 * y + 1) as f64 * v as f64 * PI / 16.0).cos();
+        }
+    }
+    sum
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// A random image of gradients, flat patches, edges and noise, with
+/// random encode options; the same `seed` always gives the same case
+fn random_case(seed: u64) -> (RgbImage, IcfEncodeOptions) {
+    let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1);
+    let (width, height) = (1 + rng.below(48) as u32, 1 + rng.below(48) as u32);
+    let base: [i64; 3] = std::array::from_fn(|_| rng.below(256) as i64);
+    let slope: [i64; 6] = std::array::from_fn(|_| rng.below(17) as i64 - 8);
+    let noise = 1 + rng.below(64) as i64;
+    let edge = (rng.below(width as u64 + 1) as u32, rng.below(256) as i64);
+    let img = RgbImage::from_fn(width, height, |x, y| {
+        let mut pixel = [0u8; 3];
+        for (c, value) in pixel.iter_mut().enumerate() {
+            let mut level = base[c] + slope[2 * c] * x as i64 + slope[2 * c + 1] * y as i64;
+            if x >= edge.0 {
+                level += edge.1 - 128;
+            }
+            level += rng.below(noise as u64) as i64 - noise / 2;
+            *value = level.clamp(0, 255) as u8;
+        }
+        Rgb(pixel)
+    });
+    let options = IcfEncodeOptions {
+        chroma_subsampling: [ChromaSubsampling::S444, ChromaSubsampling::S420][rng.below(2) as usize].into(),
+        color_space: [IcfColorSpace::YCoCg, IcfColorSpace::YCbCr][rng.below(2) as usize],
+        quantization: [QuantTableKind::Standard, QuantTableKind::Perceptual][rng.below(2) as usize],
+        tile_size: [None, Some(16), Some(32)][rng.below(3) as usize],
+        sign_contexts: rng.below(2) == 1,
+        compress_sections: rng.below(2) == 1,
+        ..IcfEncodeOptions::with_quality(1 + rng.below(100) as u8)
+    };
+    (img, options)
+}
+
+/// The first pixel where `decoded` is further than `TOLERANCE` from `reference`
+fn first_mismatch(reference: &RgbImage, decoded: &RgbImage) -> Option<String> {
+    if reference.dimensions() != decoded.dimensions() {
+        return Some(format!("size {:?}, expected {:?}", decoded.dimensions(), reference.dimensions()));
+    }
+    reference.enumerate_pixels()
+        .find(|(x, y, pixel)| (0..3).any(|c| pixel[c].abs_diff(decoded.get_pixel(*x, *y)[c]) > TOLERANCE))
+        .map(|(x, y, pixel)| format!("pixel ({}, {}) is {:?}, expected {:?}", x, y, decoded.get_pixel(x, y).0, pixel.0))
+}
+
+/// Run `backends` over every case, returning a line per disagreement
+fn run(backends: &[(&str, Backend)]) -> Vec<String> {
+    let codec = IcfCodec::new();
+    let mut failures = Vec::new();
+    for seed in 0..CASES {
+        let (img, options) = random_case(seed);
+        let encoded = codec.encode_with_options(&DynamicImage::ImageRgb8(img), &options).unwrap();
+        let reference = reference_decode(&encoded);
+        for (name, backend) in backends {
+            let mismatch = match backend(&encoded) {
+                Ok(decoded) => first_mismatch(&reference, &decoded),
+                Err(e) => Some(format!("error: {:#}", e)),
+            };
+            if let Some(mismatch) = mismatch {
+                failures.push(format!("{} on case {} ({:?}): {}", name, seed, options, mismatch));
+            }
+        }
+    }
+    failures
+}
+
+#[test]
+fn test_backends_match_reference() {
+    let failures = run(BACKENDS);
+    assert!(failures.is_empty(), "{} disagreements:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn test_harness_catches_an_off_by_one() {
+    // Chroma taken one column over, as an upsampler indexing from the
+    // wrong edge would
+    let shifted: Backend = |data| {
+        let img = IcfCodec::new().decode(data)?.to_rgb8();
+        let (width, height) = img.dimensions();
+        Ok(RgbImage::from_fn(width, height, |x, y| {
+            let neighbor = img.get_pixel((x + 1).min(width - 1), y);
+            let pixel = img.get_pixel(x, y);
+            Rgb([pixel[0], neighbor[1], pixel[2]])
+        }))
+    };
+    let failures = run(&[("shifted", shifted)]);
+    assert!(failures.len() as u64 > CASES / 2, "only {} cases caught", failures.len());
+}
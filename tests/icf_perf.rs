@@ -0,0 +1,95 @@
+//! Decode latency smoke test for a 1080p ICF.
+//!
+//! Decodes a fixed synthetic 1920×1080 container `RUNS` times on one thread
+//! and fails if the median is over budget. The budget is deliberately loose:
+//! this catches gross regressions such as a quadratic block lookup, not a
+//! few percent. `cargo bench -- image_1080p` gives the detailed numbers.
+//!
+//! Run with `cargo test --release --features perf-tests --test icf_perf`.
+//! `ICF_DECODE_BUDGET_MS` overrides the budget for release builds; debug
+//! builds get `DEBUG_SLOWDOWN` times as long.
+#![cfg(feature = "perf-tests")]
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use codec_cdn_rust::codecs::cli_common::RuntimeConfig;
+use codec_cdn_rust::codecs::image::IcfCodec;
+use image::{DynamicImage, Rgb, RgbImage};
+
+const RUNS: usize = 20;
+const BUDGET_ENV: &str = "ICF_DECODE_BUDGET_MS";
+/// Median decode budget for a release build, in milliseconds
+const DEFAULT_BUDGET_MS: u64 = 500;
+/// How much slower an unoptimized build decodes
+const DEBUG_SLOWDOWN: u32 = 20;
+
+/// The container every run decodes: gradients and an XOR pattern at Q75
+fn container_1080p() -> Vec<u8> {
+    let img = RgbImage::from_fn(1920, 1080, |x, y| Rgb([(x / 8) as u8, (y / 5) as u8, ((x ^ y) % 256) as u8]));
+    IcfCodec::new().encode_image(&DynamicImage::ImageRgb8(img), 75).unwrap()
+}
+
+fn budget() -> Duration {
+    let ms = match std::env::var(BUDGET_ENV) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("{} must be whole milliseconds, got {:?}", BUDGET_ENV, value)),
+        Err(_) => DEFAULT_BUDGET_MS,
+    };
+    let budget = Duration::from_millis(ms);
+    if cfg!(debug_assertions) { budget * DEBUG_SLOWDOWN } else { budget }
+}
+
+/// Median wall time of `runs` calls to `decode` on a single worker thread
+fn median_decode_time(data: &[u8], runs: usize, decode: impl Fn(&IcfCodec, &[u8]) + Sync) -> Duration {
+    let runtime = RuntimeConfig::new(Some(1), None).unwrap();
+    let codec = IcfCodec::new().with_runtime(runtime.clone());
+    let mut times: Vec<Duration> = (0..runs).map(|_| {
+        let start = Instant::now();
+        runtime.install(|| decode(&codec, data));
+        start.elapsed()
+    }).collect();
+    times.sort();
+    times[times.len() / 2]
+}
+
+/// `Err` describing the overrun if `median` is over `budget`
+fn check_budget(median: Duration, budget: Duration) -> Result<(), String> {
+    if median > budget {
+        Err(format!("median 1080p decode took {:?}, budget is {:?} (set {} to adjust)", median, budget, BUDGET_ENV))
+    } else {
+        Ok(())
+    }
+}
+
+fn decode(codec: &IcfCodec, data: &[u8]) {
+    black_box(codec.decode(data).unwrap());
+}
+
+#[test]
+fn test_1080p_decode_within_budget() {
+    let data = container_1080p();
+    let median = median_decode_time(&data, RUNS, decode);
+    if let Err(overrun) = check_budget(median, budget()) {
+        panic!("{}", overrun);
+    }
+}
+
+#[test]
+fn test_budget_catches_a_quadratic_block_lookup() {
+    // Each block found by a linear scan of every block, as a lookup that
+    // lost its index would
+    let quadratic = |codec: &IcfCodec, data: &[u8]| {
+        decode(codec, data);
+        let blocks: Vec<(u32, u32, u32)> = (0..3)
+            .flat_map(|channel| (0..1080 / 8).flat_map(move |y| (0..1920 / 8).map(move |x| (channel, x, y))))
+            .collect();
+        for block in &blocks {
+            black_box(blocks.iter().position(|candidate| candidate == black_box(block)));
+        }
+    };
+    let data = container_1080p();
+    // The default budget leaves this much headroom over current release numbers
+    let budget = median_decode_time(&data, 3, decode) * 3;
+    assert!(check_budget(median_decode_time(&data, 3, decode), budget).is_ok());
+    assert!(check_budget(median_decode_time(&data, 1, quadratic), budget).is_err());
+}
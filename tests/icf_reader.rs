@@ -0,0 +1,101 @@
+//! Streaming ICF reads must not buffer the compressed payload.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use codec_cdn_rust::codecs::image::{IcfCodec, IcfReader};
+use image::{DynamicImage, ImageBuffer, Rgb};
+
+/// Records the largest single allocation made while tracking is enabled
+struct CountingAllocator;
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+    static LARGEST: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record(size: usize) {
+    // try_with: the thread-locals may already be gone during thread teardown
+    let _ = TRACKING.try_with(|tracking| {
+        if tracking.get() {
+            let _ = LARGEST.try_with(|largest| largest.set(largest.get().max(size)));
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Largest single allocation `f` makes on this thread
+fn largest_allocation(f: impl FnOnce()) -> usize {
+    LARGEST.with(|largest| largest.set(0));
+    TRACKING.with(|tracking| tracking.set(true));
+    f();
+    TRACKING.with(|tracking| tracking.set(false));
+    LARGEST.with(Cell::get)
+}
+
+fn noisy_image(width: u32, height: u32) -> DynamicImage {
+    let mut state = 0x2545_f491u32;
+    let img = ImageBuffer::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        Rgb([r, g, b])
+    });
+    DynamicImage::ImageRgb8(img)
+}
+
+#[test]
+fn test_reader_streams_blocks_without_buffering_payload() {
+    const THRESHOLD: usize = 64 * 1024;
+
+    let codec = IcfCodec::new();
+    let encoded = codec.encode_image(&noisy_image(256, 256), 90).unwrap();
+    let (header, payload) = codec.parse_container(&encoded).unwrap();
+    assert!(payload.len() > 16 * THRESHOLD, "payload too small to prove anything");
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("noisy.icf");
+    std::fs::write(&path, &encoded).unwrap();
+
+    let mut block_count = 0;
+    let largest = largest_allocation(|| {
+        let mut reader = IcfReader::open(&path).unwrap();
+        reader.for_each_block(|_| {
+            block_count += 1;
+            Ok(())
+        }).unwrap();
+    });
+    assert_eq!(block_count, 3 * 32 * 32);
+    assert!(largest < THRESHOLD, "parse path allocated {} bytes at once", largest);
+
+    // Borrowing the payload doesn't copy it either
+    let largest = largest_allocation(|| {
+        codec.parse_container(&encoded).unwrap();
+    });
+    assert!(largest < THRESHOLD, "parse_container allocated {} bytes at once", largest);
+
+    let mut reader = IcfReader::open(&path).unwrap();
+    assert_eq!(reader.header().checksum, header.checksum);
+    assert_eq!(reader.decode().unwrap().to_rgb8(), codec.decode(&encoded).unwrap().to_rgb8());
+}
@@ -0,0 +1,188 @@
+//! Piping a gzip body through TCF and back without holding either whole.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use codec_cdn_rust::codecs::text::{transcode_gzip_to_tcf, transcode_tcf_to_gzip, TcfCodec};
+use flate2::read::GzEncoder;
+use flate2::write::GzDecoder;
+use flate2::Compression;
+
+/// Tracks the peak of live allocated bytes while tracking is enabled
+struct PeakAllocator;
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(size: usize) {
+    if TRACKING.load(Ordering::Relaxed) {
+        let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(live, Ordering::Relaxed);
+    }
+}
+
+fn shrink(size: usize) {
+    if TRACKING.load(Ordering::Relaxed) {
+        let _ = LIVE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| Some(live.saturating_sub(size)));
+    }
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        grow(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        shrink(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        grow(new_size);
+        shrink(layout.size());
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Peak bytes `f` held live at once
+///
+/// The counters are global, so only one test in this binary may use them.
+fn peak_usage<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    LIVE.store(0, Ordering::Relaxed);
+    PEAK.store(0, Ordering::Relaxed);
+    TRACKING.store(true, Ordering::Relaxed);
+    let result = f();
+    TRACKING.store(false, Ordering::Relaxed);
+    (result, PEAK.load(Ordering::Relaxed))
+}
+
+/// Bytes of the log-like text `LogSource` produces
+const TEXT_BYTES: u64 = 20 << 20;
+
+/// Generates `TEXT_BYTES` of access-log lines on demand, the same every time
+struct LogSource {
+    state: u32,
+    line: Vec<u8>,
+    remaining: u64,
+}
+
+impl LogSource {
+    fn new() -> Self {
+        Self { state: 0x9e37_79b9, line: Vec::new(), remaining: TEXT_BYTES }
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
+
+impl Read for LogSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.line.is_empty() && self.remaining > 0 {
+            let paths = ["/", "/index.html", "/api/v1/items", "/static/app.js", "/img/logo.png", "/ünïcødé/päth"];
+            let (a, b, c, d) = (self.next(), self.next(), self.next(), self.next());
+            self.line = format!(
+                "10.0.{}.{} - - [12/Mar/2025:{:02}:{:02}:{:02} +0000] \"GET {} HTTP/1.1\" {} {}\n",
+                a % 256, b % 256, c % 24, c / 24 % 60, d % 60,
+                paths[(a >> 8) as usize % paths.len()],
+                [200, 200, 200, 304, 404][(b >> 8) as usize % 5],
+                d >> 12,
+            ).into_bytes();
+            self.line.truncate(self.remaining.min(self.line.len() as u64) as usize);
+        }
+        let n = buf.len().min(self.line.len());
+        buf[..n].copy_from_slice(&self.line[..n]);
+        self.line.drain(..n);
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Counts the bytes read through it and the largest single read
+struct CountingReader<R> {
+    inner: R,
+    total: u64,
+    largest: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.total += n as u64;
+        self.largest = self.largest.max(n);
+        Ok(n)
+    }
+}
+
+/// Checks what is written to it against a fresh `LogSource`
+struct Expect {
+    expected: LogSource,
+    checked: u64,
+}
+
+impl Write for Expect {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut expected = vec![0; buf.len()];
+        self.expected.read_exact(&mut expected)?;
+        if expected != buf {
+            return Err(io::Error::other(format!("text differs within bytes {}..{}", self.checked, self.checked + buf.len() as u64)));
+        }
+        self.checked += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A few MB: a chunk of text, its coded candidates and the spool's memory share
+const PEAK_LIMIT: usize = 6 << 20;
+
+#[test]
+fn test_gzip_body_round_trips_through_tcf_in_bounded_memory() {
+    let mut tcf = tempfile::tempfile().unwrap();
+    let mut gzip = CountingReader { inner: GzEncoder::new(LogSource::new(), Compression::default()), total: 0, largest: 0 };
+    let (warnings, peak) = peak_usage(|| transcode_gzip_to_tcf(&mut gzip, &mut tcf).unwrap());
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert!(peak < PEAK_LIMIT, "gzip to TCF peaked at {} bytes", peak);
+    // The whole gzip stream went through, a buffer at a time
+    assert!(gzip.total > 0 && gzip.largest <= 64 * 1024, "{} bytes, largest read {}", gzip.total, gzip.largest);
+    assert!(gzip.inner.get_ref().remaining == 0);
+
+    let header_prefix = {
+        use std::io::Seek;
+        tcf.rewind().unwrap();
+        let mut prefix = vec![0; 64 * 1024];
+        let n = tcf.read(&mut prefix).unwrap();
+        prefix.truncate(n);
+        tcf.rewind().unwrap();
+        prefix
+    };
+    let header = TcfCodec::parse_header(&header_prefix).unwrap();
+    assert_eq!(header.original_size, TEXT_BYTES);
+    assert!(header.chunks.len() >= 20, "{} chunks", header.chunks.len());
+    assert!(header.compressed_size < gzip.total, "TCF payload {} vs gzip {}", header.compressed_size, gzip.total);
+
+    let mut check = GzDecoder::new(Expect { expected: LogSource::new(), checked: 0 });
+    let file_size = tcf.metadata().unwrap().len();
+    let mut source = CountingReader { inner: &mut tcf, total: 0, largest: 0 };
+    let (written, peak) = peak_usage(|| transcode_tcf_to_gzip(&mut source, &mut check).unwrap());
+    let check = check.finish().unwrap();
+    assert_eq!(written, TEXT_BYTES);
+    assert_eq!(check.checked, TEXT_BYTES);
+    assert!(peak < PEAK_LIMIT, "TCF to gzip peaked at {} bytes", peak);
+    // Read once, front to back
+    assert_eq!(source.total, file_size);
+}
@@ -0,0 +1,184 @@
+//! Text shapes that stress the TCF coders: one enormous line, and an
+//! alphabet with no skew to exploit.
+//!
+//! Lives in its own test binary because it installs a global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use base64::Engine;
+use codec_cdn_rust::codecs::text::{ChunkStrategy, SimpleTcfCodec, TcfCodec, TcfEncodeOptions, TcfMethod, TOKENIZER_IDS};
+
+/// Tracks the peak of live allocated bytes while tracking is enabled
+struct PeakAllocator;
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(size: usize) {
+    if TRACKING.load(Ordering::Relaxed) {
+        let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(live, Ordering::Relaxed);
+    }
+}
+
+fn shrink(size: usize) {
+    if TRACKING.load(Ordering::Relaxed) {
+        // Frees of memory allocated before tracking started must not wrap
+        let _ = LIVE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| Some(live.saturating_sub(size)));
+    }
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        grow(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        shrink(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        grow(new_size);
+        shrink(layout.size());
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Peak bytes `f` held live at once, on top of what was live before it ran
+///
+/// The counters are global, so only one test in this binary may use them.
+fn peak_usage<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    LIVE.store(0, Ordering::Relaxed);
+    PEAK.store(0, Ordering::Relaxed);
+    TRACKING.store(true, Ordering::Relaxed);
+    let result = f();
+    TRACKING.store(false, Ordering::Relaxed);
+    (result, PEAK.load(Ordering::Relaxed))
+}
+
+fn xorshift(seed: u32) -> impl FnMut() -> u32 {
+    let mut state = seed;
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
+
+/// Minified JSON: one line of records, no whitespace between tokens
+fn minified_json(target: usize) -> String {
+    let mut next = xorshift(0x9e37_79b9);
+    let words = ["alpha", "Bravo", "charlie_2", "DELTA-x", "echo.io", "fox~trot", "golf/7", "hotel#q", "India+", "juliet%20"];
+    let mut text = String::from("[");
+    let mut id = 0;
+    while text.len() < target {
+        if id > 0 {
+            text.push(',');
+        }
+        let word = |n: u32| words[n as usize % words.len()];
+        text.push_str(&format!(
+            r#"{{"id":{},"user":"{}@{}.example","score":{}.{:03},"active":{},"tags":["{}","{}"],"meta":{{"ref":"{:08X}","note":"{} (v{}) & <{}>"}}}}"#,
+            id,
+            word(next()),
+            word(next()),
+            next() % 1000,
+            next() % 1000,
+            next().is_multiple_of(2),
+            word(next()),
+            word(next()),
+            next(),
+            word(next()),
+            next() % 10,
+            word(next()),
+        ));
+        id += 1;
+    }
+    text.push(']');
+    text
+}
+
+/// Base64 of random bytes: 64 symbols, near uniform, one line
+fn base64_blob(target: usize) -> String {
+    let mut next = xorshift(0x2545_f491);
+    let bytes: Vec<u8> = (0..target * 3 / 4).map(|_| next() as u8).collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// Chunk size of the chunked encoding; a delimiter that never occurs must
+/// still cut the text at this size
+const CHUNK_BYTES: usize = 256 * 1024;
+
+/// Every way the text codecs can be asked to code `text`
+fn encodings() -> Vec<(String, TcfEncodeOptions)> {
+    let mut encodings = Vec::new();
+    for method in TcfMethod::available() {
+        if method == TcfMethod::Arithmetic {
+            for tokenizer_id in TOKENIZER_IDS {
+                let options = TcfEncodeOptions { method: Some(method), tokenizer_id: tokenizer_id.to_string(), ..Default::default() };
+                encodings.push((format!("{}/{}", method.as_str(), tokenizer_id), options));
+            }
+        } else {
+            encodings.push((method.as_str().to_string(), TcfEncodeOptions { method: Some(method), ..Default::default() }));
+        }
+    }
+    encodings.push(("auto".to_string(), TcfEncodeOptions { method: None, ..Default::default() }));
+    encodings.push((
+        "chunked".to_string(),
+        TcfEncodeOptions { method: None, chunking: Some(ChunkStrategy::OnDelimiter(b'\n', CHUNK_BYTES)), ..Default::default() },
+    ));
+    encodings
+}
+
+/// Round trip `text` through every encoding and the simple codec,
+/// returning the bits per byte of each encoding
+fn check_corpus(name: &str, text: &str) -> BTreeMap<String, f64> {
+    assert!(!text.contains('\n'), "{} must be a single line", name);
+    let mut bits_per_byte = BTreeMap::new();
+    for (label, options) in encodings() {
+        let (encoded, peak) = peak_usage(|| TcfCodec::encode_with_options(text, &options).unwrap().data);
+        // The text, its normalized copy, the output and candidates being compared
+        assert!(peak < 8 * text.len(), "{} {}: peak {} bytes for {} bytes of text", name, label, peak, text.len());
+        assert!(TcfCodec::decode(&encoded).unwrap() == text, "{} did not round trip with {}", name, label);
+        if options.chunking.is_some() {
+            assert_eq!(TcfCodec::parse_header(&encoded).unwrap().chunks.len(), text.len().div_ceil(CHUNK_BYTES), "{}", name);
+        }
+        bits_per_byte.insert(label, encoded.len() as f64 * 8.0 / text.len() as f64);
+    }
+    let simple = SimpleTcfCodec::encode(text).unwrap();
+    assert!(SimpleTcfCodec::decode(&simple).unwrap() == text, "{} did not round trip with the simple codec", name);
+    bits_per_byte
+}
+
+#[test]
+fn test_single_line_json_and_base64() {
+    let json = minified_json(1 << 20);
+    let blob = base64_blob(1 << 20);
+    // A string token far longer than the arithmetic coder tokenizes at once
+    let embedded = format!(r#"{{"name":"blob.bin","data":"{}","records":{}}}"#, base64_blob(300 * 1024), minified_json(4096));
+
+    let json_bits = check_corpus("json", &json);
+    let blob_bits = check_corpus("base64", &blob);
+    check_corpus("embedded", &embedded);
+
+    // 64 equally likely symbols cost 6 bits each, whatever the coder
+    for (label, bits) in &blob_bits {
+        let expected = if label == "stored" { 8.0..8.01 } else { 5.99..6.1 };
+        assert!(expected.contains(bits), "base64 {}: {:.3} bits per byte", label, bits);
+    }
+    // JSON is skewed and repetitive, so every coder does better on it
+    for (label, bits) in &json_bits {
+        if label != "stored" {
+            assert!(*bits < blob_bits[label] - 0.5, "json {}: {:.3} bits per byte", label, bits);
+        }
+    }
+    assert!(json_bits["auto"] < 2.0, "json auto: {:.3} bits per byte", json_bits["auto"]);
+}